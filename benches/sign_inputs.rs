@@ -0,0 +1,141 @@
+//! How long signing and checking the inputs of a claim of many bets takes.
+//!
+//! Run with `cargo bench --bench sign_inputs [-- <n-inputs>...]`. Each bet input used to be signed
+//! by a wallet of its own which looks at every input of the transaction and works out the sighash
+//! midstate again for each one. That is compared with `psbt_ext::sign_inputs` which only signs the
+//! inputs it's told to with the midstate worked out once per thread.
+use bdk::{
+    bitcoin::{
+        blockdata::{opcodes, script::Builder},
+        secp256k1::{self, All, Secp256k1, SecretKey},
+        util::psbt::PartiallySignedTransaction as Psbt,
+        Network, OutPoint, PrivateKey, PublicKey, Script, Transaction, TxIn, TxOut,
+    },
+    database::MemoryDatabase,
+    signer::SignerOrdering,
+    KeychainKind, SignOptions, Wallet,
+};
+use gun_wallet::{external_signer::verify_signed_psbt, parallel, psbt_ext::sign_inputs};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_INPUTS: [usize; 3] = [10, 100, 400];
+
+fn main() {
+    let n_inputs = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse::<usize>().ok())
+        .collect::<Vec<_>>();
+    let n_inputs = match n_inputs.is_empty() {
+        true => DEFAULT_INPUTS.to_vec(),
+        false => n_inputs,
+    };
+    let secp = Secp256k1::new();
+    println!(
+        "{:>7} {:>8} {:>14} {:>14} {:>8} {:>14}",
+        "inputs", "threads", "wallet-per-bet", "sign_inputs", "speedup", "verify"
+    );
+    for n in n_inputs {
+        let (psbt, keys) = claim_psbt(n, &secp);
+        let to_sign = keys.iter().cloned().enumerate().collect::<Vec<_>>();
+
+        let old = time(|| {
+            let mut psbt = psbt.clone();
+            for secret_key in &keys {
+                wallet_sign(&mut psbt, secret_key, &secp);
+            }
+            psbt
+        });
+        let mut signed = psbt.clone();
+        let new = time(|| {
+            signed = psbt.clone();
+            sign_inputs(&mut signed, &to_sign, &secp).unwrap();
+        });
+        let verify = time(|| verify_signed_psbt(&psbt, &signed, &secp).unwrap());
+
+        println!(
+            "{:>7} {:>8} {:>14?} {:>14?} {:>7.1}x {:>14?}",
+            n,
+            parallel::workers(n),
+            old,
+            new,
+            old.as_secs_f64() / new.as_secs_f64(),
+            verify
+        );
+    }
+}
+
+fn time<R>(mut f: impl FnMut() -> R) -> Duration {
+    let start = Instant::now();
+    drop(f());
+    start.elapsed()
+}
+
+/// How claims were signed before: a wallet for each bet's descriptor with its key.
+fn wallet_sign(psbt: &mut Psbt, secret_key: &SecretKey, secp: &Secp256k1<All>) {
+    let public_key = public_key(secret_key, secp);
+    let mut wallet = Wallet::new_offline(
+        &format!("wsh(pk({}))", public_key)[..],
+        None,
+        Network::Regtest,
+        MemoryDatabase::default(),
+    )
+    .unwrap();
+    wallet.add_signer(
+        KeychainKind::External,
+        SignerOrdering::default(),
+        Arc::new(PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: *secret_key,
+        }),
+    );
+    wallet.sign(psbt, SignOptions::default()).unwrap();
+}
+
+fn public_key(secret_key: &SecretKey, secp: &Secp256k1<All>) -> PublicKey {
+    PublicKey {
+        compressed: true,
+        key: secp256k1::PublicKey::from_secret_key(secp, secret_key),
+    }
+}
+
+/// A transaction spending `n` bet outputs, each with a key of its own.
+fn claim_psbt(n: usize, secp: &Secp256k1<All>) -> (Psbt, Vec<SecretKey>) {
+    let keys = (0..n)
+        .map(|i| {
+            let mut bytes = [1u8; 32];
+            bytes[..8].copy_from_slice(&(i as u64 + 1).to_be_bytes());
+            SecretKey::from_slice(&bytes).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let tx = Transaction {
+        version: 2,
+        lock_time: 0,
+        input: (0..n)
+            .map(|i| TxIn {
+                previous_output: OutPoint::new(Default::default(), i as u32),
+                ..Default::default()
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: n as u64 * 9_000,
+            script_pubkey: Script::from(vec![0x51]),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+    for (input, secret_key) in psbt.inputs.iter_mut().zip(&keys) {
+        let witness_script = Builder::new()
+            .push_key(&public_key(secret_key, secp))
+            .push_opcode(opcodes::all::OP_CHECKSIG)
+            .into_script();
+        input.witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: witness_script.to_v0_p2wsh(),
+        });
+        input.witness_script = Some(witness_script);
+    }
+    (psbt, keys)
+}
@@ -0,0 +1,99 @@
+//! Checking the addresses coins are sent to.
+//!
+//! Every address given to gun to pay (`gun send`, `--change-to`, scheduled payments, cold storage
+//! and where bet winnings are claimed to) goes through [`check`] so they're all held to the same
+//! rules:
+//!
+//! - It has to be for the wallet's network. Testnet addresses are also accepted on signet and
+//!   testnet base58 addresses on regtest since those networks share the encodings.
+//! - Segwit addresses of a version nothing can spend from yet (v2 and up, or v1 that isn't a 32
+//!   byte taproot key) are refused since the coins could be lost or taken by anyone once the
+//!   version means something.
+//!
+//! Parsing enforces the rest of BIP350: version 0 must be bech32 and the later versions bech32m.
+use crate::exit_code::ErrorKind;
+use bdk::bitcoin::{util::address::Payload, Address, Network};
+use std::str::FromStr;
+
+/// Parses `string` as `what` (e.g. "the change address") for a wallet on `network`.
+pub fn parse(string: &str, network: Network, what: &str) -> anyhow::Result<Address> {
+    let address = Address::from_str(string.trim()).map_err(|e| {
+        ErrorKind::BadInput.error(format!(
+            "{} {} isn't a valid bitcoin address ({}). Check that it was copied in full.",
+            what, string, e
+        ))
+    })?;
+    check(&address, network, what)
+}
+
+/// Checks `address` can be paid from a wallet on `network`. It's returned with the wallet's
+/// network so it compares equal to the wallet's own addresses.
+pub fn check(address: &Address, network: Network, what: &str) -> anyhow::Result<Address> {
+    if !on_network(address, network) {
+        return Err(ErrorKind::BadInput.error(format!(
+            "{} {} is a {} address but this wallet is on {}. Use a {} address instead.",
+            what, address, address.network, network, network
+        )));
+    }
+    if let Payload::WitnessProgram { version, program } = &address.payload {
+        let version = version.to_u8();
+        if version > 1 || (version == 1 && program.len() != 32) {
+            return Err(ErrorKind::BadInput.error(format!(
+                "{} {} is a segwit version {} address which nothing can spend from yet so the coins could be lost. Ask for a different kind of address.",
+                what, address, version
+            )));
+        }
+    }
+    let mut address = address.clone();
+    address.network = network;
+    Ok(address)
+}
+
+/// Whether `address` is for `network` taking into account that the test networks share
+/// encodings.
+pub fn on_network(address: &Address, network: Network) -> bool {
+    let is_base58 = !matches!(address.payload, Payload::WitnessProgram { .. });
+    match (address.network, network) {
+        (a, b) if a == b => true,
+        // signet bech32 addresses start with tb1 like testnet ones
+        (Network::Testnet, Network::Signet) => true,
+        // regtest has its own bech32 prefix but not base58 ones
+        (Network::Testnet, Network::Regtest) => is_base58,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn networks_and_versions() {
+        let testnet = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let testnet_p2pkh = "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn";
+        let regtest = "bcrt1qs758ursh4q9z627kt3pp5yysm78ddny6txaqgw";
+        let mainnet = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+        assert!(parse(testnet, Network::Testnet, "the address").is_ok());
+        assert!(parse(testnet, Network::Signet, "the address").is_ok());
+        assert!(parse(testnet, Network::Regtest, "the address").is_err());
+        assert!(parse(testnet_p2pkh, Network::Regtest, "the address").is_ok());
+        assert!(parse(regtest, Network::Regtest, "the address").is_ok());
+        assert!(parse(mainnet, Network::Testnet, "the address").is_err());
+        assert!(parse(testnet, Network::Bitcoin, "the address").is_err());
+        assert_eq!(
+            parse(testnet, Network::Signet, "the address")
+                .unwrap()
+                .network,
+            Network::Signet
+        );
+
+        // BIP350 test vectors
+        let taproot = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+        let v16 = "BC1SW50QGDZ25J";
+        let v0_as_bech32m = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh";
+        assert!(parse(taproot, Network::Bitcoin, "the address").is_ok());
+        assert!(parse(v16, Network::Bitcoin, "the address").is_err());
+        assert!(parse(v0_as_bech32m, Network::Bitcoin, "the address").is_err());
+    }
+}
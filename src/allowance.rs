@@ -0,0 +1,164 @@
+//! Spending allowances: small amounts kept under a key that can be handed to another device (e.g.
+//! a phone) while the wallet can take back whatever is left once the allowance expires.
+//!
+//! Each allowance has its own descriptor
+//!
+//! ```text
+//! wsh(or_d(pk(<device>/0/*),and_v(v:pk(<reclaim>/0/*),after(<expiry height>))))
+//! ```
+//!
+//! Both keys come from the seed words. The device key is at `m/84'/<coin>'/<1000 + index>'/0'` and
+//! the reclaim key next to it at `.../1'` so the device's xprv can't be used to derive the reclaim
+//! key. The device can spend at any time. It can't be revoked so the wallet can only race it
+//! after the expiry.
+use crate::keychain::Keychain;
+use bdk::bitcoin::{
+    secp256k1::Secp256k1,
+    util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+    Network,
+};
+use olivia_core::chrono::NaiveDateTime;
+
+/// Allowance accounts start here so they stay clear of accounts wallets usually use.
+const FIRST_ACCOUNT: u32 = 1000;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Allowance {
+    /// The block height after which the wallet can reclaim the coins
+    pub expiry_height: u32,
+    pub created_at: NaiveDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Which keys of an allowance's descriptor are private.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AllowanceKeys {
+    /// Neither (for watching it)
+    Public,
+    /// The device's key for handing over to the device
+    Device,
+    /// The reclaim key for the wallet to take the coins back
+    Reclaim,
+}
+
+fn account_path(network: Network, index: u32, role: u32) -> DerivationPath {
+    let coin_type = match network {
+        Network::Bitcoin => 0,
+        _ => 1,
+    };
+    DerivationPath::from(vec![
+        ChildNumber::Hardened { index: 84 },
+        ChildNumber::Hardened { index: coin_type },
+        ChildNumber::Hardened {
+            index: FIRST_ACCOUNT + index,
+        },
+        ChildNumber::Hardened { index: role },
+    ])
+}
+
+/// The key for the device or the reclaim key written out with its origin.
+fn descriptor_key(
+    keychain: &Keychain,
+    network: Network,
+    index: u32,
+    role: u32,
+    private: bool,
+) -> String {
+    let secp = Secp256k1::new();
+    let master = keychain.main_wallet_xprv(network);
+    let path = account_path(network, index, role);
+    let xprv: ExtendedPrivKey = master
+        .derive_priv(&secp, &path)
+        .expect("derivation can't fail");
+    let origin = format!(
+        "[{}{}]",
+        master.fingerprint(&secp),
+        path.to_string().trim_start_matches('m')
+    );
+    match private {
+        true => format!("{}{}/0/*", origin, xprv),
+        false => format!(
+            "{}{}/0/*",
+            origin,
+            ExtendedPubKey::from_private(&secp, &xprv)
+        ),
+    }
+}
+
+/// The descriptor of allowance `index` with the private key `keys` says.
+pub fn descriptor(
+    keychain: &Keychain,
+    network: Network,
+    index: u32,
+    allowance: &Allowance,
+    keys: AllowanceKeys,
+) -> String {
+    let device = descriptor_key(keychain, network, index, 0, keys == AllowanceKeys::Device);
+    let reclaim = descriptor_key(keychain, network, index, 1, keys == AllowanceKeys::Reclaim);
+    format!(
+        "wsh(or_d(pk({}),and_v(v:pk({}),after({}))))",
+        device, reclaim, allowance.expiry_height
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::{database::MemoryDatabase, wallet::AddressIndex, Wallet};
+
+    #[test]
+    fn every_descriptor_of_an_allowance_has_the_same_addresses() {
+        let keychain = Keychain::new([42u8; 64]);
+        let allowance = Allowance {
+            expiry_height: 800_000,
+            created_at: NaiveDateTime::from_timestamp(0, 0),
+            label: None,
+        };
+        let address = |keys| {
+            let descriptor = descriptor(&keychain, Network::Regtest, 3, &allowance, keys);
+            Wallet::new_offline(
+                descriptor.as_str(),
+                None,
+                Network::Regtest,
+                MemoryDatabase::default(),
+            )
+            .unwrap()
+            .get_address(AddressIndex::Peek(0))
+            .unwrap()
+            .address
+        };
+        let public = address(AllowanceKeys::Public);
+        assert_eq!(address(AllowanceKeys::Device), public);
+        assert_eq!(address(AllowanceKeys::Reclaim), public);
+
+        let device = descriptor(
+            &keychain,
+            Network::Regtest,
+            3,
+            &allowance,
+            AllowanceKeys::Device,
+        );
+        assert!(device.contains("/84'/1'/1003'/0']tprv"));
+        assert!(device.contains("/84'/1'/1003'/1']tpub"));
+        assert!(device.ends_with("after(800000))))"));
+        let other = descriptor(
+            &keychain,
+            Network::Regtest,
+            4,
+            &allowance,
+            AllowanceKeys::Public,
+        );
+        assert_ne!(
+            other,
+            descriptor(
+                &keychain,
+                Network::Regtest,
+                3,
+                &allowance,
+                AllowanceKeys::Public
+            )
+        );
+    }
+}
@@ -1,29 +1,164 @@
-use std::str::FromStr;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
 use anyhow::anyhow;
 use bdk::bitcoin::{Amount, Denomination};
 
+/// All the bitcoin there will ever be in sats
+const MAX_MONEY_SATS: u64 = 21_000_000 * 100_000_000;
+
 pub trait FromCliStr: Sized {
     fn from_cli_str(string: &str) -> anyhow::Result<Self>;
 }
 
 impl FromCliStr for Amount {
+    /// Parses an amount with its unit e.g. `0.01BTC`, `10 mBTC`, `2_500bits` or `5000sat`. The unit
+    /// isn't optional so `1` can't mean a bitcoin when you meant a sat.
     fn from_cli_str(string: &str) -> anyhow::Result<Self> {
         match string.rfind(char::is_numeric) {
             Some(i) => {
-                let denom = Denomination::from_str(&string[(i + 1)..])?;
+                let unit_str = string[(i + 1)..].trim();
+                if unit_str.is_empty() {
+                    return Err(anyhow!(
+                        "{} needs a unit e.g. {}sat or {}BTC",
+                        string,
+                        string,
+                        string
+                    ));
+                }
+                let unit = AmountUnit::from_str(unit_str)?;
                 let value: String = string[..=i]
                     .chars()
-                    .filter(|c| !c.is_whitespace())
+                    .filter(|c| !c.is_whitespace() && *c != '_')
                     .collect();
 
-                Ok(Amount::from_str_in(&value, denom)?)
+                let amount = Amount::from_str_in(&value, unit.denomination())?;
+                if amount > Amount::from_sat(MAX_MONEY_SATS) {
+                    return Err(anyhow!(
+                        "{} is more than the 21 million bitcoin there will ever be",
+                        string
+                    ));
+                }
+                Ok(amount)
+            }
+            None => Err(anyhow!("{} is not a Bitcoin amount", string)),
+        }
+    }
+}
+
+/// A unit amounts can be written and shown in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmountUnit {
+    Btc,
+    /// 100,000 sats
+    Mbtc,
+    /// 100 sats
+    Bits,
+    Sat,
+}
+
+impl Default for AmountUnit {
+    fn default() -> Self {
+        AmountUnit::Btc
+    }
+}
+
+impl AmountUnit {
+    pub fn denomination(self) -> Denomination {
+        match self {
+            AmountUnit::Btc => Denomination::Bitcoin,
+            AmountUnit::Mbtc => Denomination::MilliBitcoin,
+            AmountUnit::Bits => Denomination::MicroBitcoin,
+            AmountUnit::Sat => Denomination::Satoshi,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AmountUnit::Btc => "BTC",
+            AmountUnit::Mbtc => "mBTC",
+            AmountUnit::Bits => "bits",
+            AmountUnit::Sat => "sat",
+        }
+    }
+
+    /// Formats `amount` in this unit for people to read. Amounts in BTC have their digits grouped
+    /// (e.g. `0.01 000 000`) and the unit left off since it's the usual one. The other units
+    /// always say what they are.
+    pub fn format(self, amount: Amount) -> String {
+        if amount == Amount::ZERO {
+            return "0".to_string();
+        }
+        match self {
+            AmountUnit::Btc => {
+                let mut string = amount.to_string();
+                string.insert(string.len() - 7, ' ');
+                string.insert(string.len() - 11, ' ');
+                string.trim_end_matches(" BTC").to_string()
             }
-            None => Err(anyhow!("{} is not a Bitcoin amount")),
+            AmountUnit::Sat => format!("{} sat", group_thousands(amount.as_sat())),
+            unit => format!(
+                "{} {}",
+                amount.to_string_in(unit.denomination()),
+                unit.name()
+            ),
         }
     }
 }
 
+/// e.g. `1 234 567`
+fn group_thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(' ');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+impl FromStr for AmountUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // mBTC and MBTC would be a thousand million times apart and nobody means megabitcoin
+        Ok(match s.to_lowercase().as_str() {
+            "btc" | "bitcoin" => AmountUnit::Btc,
+            "mbtc" => AmountUnit::Mbtc,
+            "bits" | "bit" | "ubtc" | "µbtc" => AmountUnit::Bits,
+            "sat" | "sats" | "satoshi" | "satoshis" => AmountUnit::Sat,
+            _ => return Err(anyhow!("{} isn't a unit. Use BTC, mBTC, bits or sat.", s)),
+        })
+    }
+}
+
+impl std::fmt::Display for AmountUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+static DISPLAY_UNIT: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the unit amounts are shown in from now on (the `display-unit` config setting or `--unit`).
+pub fn set_display_unit(unit: AmountUnit) {
+    DISPLAY_UNIT.store(unit as u8, Ordering::Relaxed)
+}
+
+pub fn display_unit() -> AmountUnit {
+    match DISPLAY_UNIT.load(Ordering::Relaxed) {
+        1 => AmountUnit::Mbtc,
+        2 => AmountUnit::Bits,
+        3 => AmountUnit::Sat,
+        _ => AmountUnit::Btc,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -33,5 +168,35 @@ mod test {
             Amount::from_cli_str("0.01BTC").unwrap(),
             Amount::from_sat(1_000_000)
         );
+        assert_eq!(
+            Amount::from_cli_str("0.01btc").unwrap(),
+            Amount::from_sat(1_000_000)
+        );
+        assert_eq!(
+            Amount::from_cli_str("10 mBTC").unwrap(),
+            Amount::from_sat(1_000_000)
+        );
+        assert_eq!(
+            Amount::from_cli_str("2_500bits").unwrap(),
+            Amount::from_sat(250_000)
+        );
+        assert_eq!(
+            Amount::from_cli_str("5000 sats").unwrap(),
+            Amount::from_sat(5_000)
+        );
+        assert!(Amount::from_cli_str("5000").is_err());
+        assert!(Amount::from_cli_str("5000 sets").is_err());
+        assert!(Amount::from_cli_str("21000000BTC").is_ok());
+        assert!(Amount::from_cli_str("21000001BTC").is_err());
+    }
+
+    #[test]
+    fn format_in_each_unit() {
+        let amount = Amount::from_sat(1_234_567);
+        assert_eq!(AmountUnit::Btc.format(amount), "0.01 234 567");
+        assert_eq!(AmountUnit::Mbtc.format(amount), "12.34567 mBTC");
+        assert_eq!(AmountUnit::Bits.format(amount), "12345.67 bits");
+        assert_eq!(AmountUnit::Sat.format(amount), "1 234 567 sat");
+        assert_eq!(AmountUnit::Sat.format(Amount::ZERO), "0");
     }
 }
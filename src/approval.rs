@@ -0,0 +1,277 @@
+//! Sends of more than a threshold can be made to need two people: one to make the send and a
+//! different one to approve it before it is signed.
+//!
+//! Approvers are identified by a passphrase. Only a salted PBKDF2 hash of it is kept in the config.
+//!
+//! The commands that make sends ask for approval themselves but the policy is enforced where
+//! signing happens: the wallet is loaded with an [`ApprovalSigner`] that goes before the real
+//! signers and claims are checked before they're signed (see [`check`]). So PSBTs from elsewhere
+//! (`gun psbt sign`, `gun fund-psbt`), bets and claims are held to it too.
+use crate::{
+    backup::{check_passphrase, hash_passphrase},
+    chrono::NaiveDateTime,
+    exit_code::ErrorKind,
+};
+use bdk::{
+    bitcoin::{
+        hashes::{hash160, Hash},
+        secp256k1::{All, Secp256k1},
+        util::psbt::PartiallySignedTransaction as Psbt,
+        Amount, OutPoint, Script, Txid,
+    },
+    database::Database,
+    signer::{Signer, SignerError, SignerId},
+};
+use std::sync::Mutex;
+
+pub const APPROVER_PASSPHRASE_ENV: &str = "GUN_APPROVER_PASSPHRASE";
+const DEFAULT_WINDOW_MINUTES: u32 = 60;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApprovalPolicy {
+    /// Sends paying out more than this to other wallets need approval
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub threshold: Amount,
+    /// How long a send can wait for approval before it has to be made again
+    #[serde(default = "default_window_minutes")]
+    pub window_minutes: u32,
+    #[serde(default)]
+    pub approvers: Vec<Approver>,
+}
+
+fn default_window_minutes() -> u32 {
+    DEFAULT_WINDOW_MINUTES
+}
+
+impl ApprovalPolicy {
+    pub fn new(threshold: Amount) -> Self {
+        Self {
+            threshold,
+            window_minutes: DEFAULT_WINDOW_MINUTES,
+            approvers: vec![],
+        }
+    }
+
+    /// The approver whose passphrase this is.
+    pub fn identify(&self, passphrase: &str) -> Option<&Approver> {
+        self.approvers
+            .iter()
+            .find(|approver| approver.has_passphrase(passphrase))
+    }
+
+    /// Whether a send requested at `requested_at` can still be approved.
+    pub fn is_expired(&self, requested_at: NaiveDateTime) -> bool {
+        crate::clock::real_now() - requested_at
+            > crate::chrono::Duration::minutes(self.window_minutes as i64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Approver {
+    pub name: String,
+    /// hex
+    pub salt: String,
+    /// hex of the PBKDF2-HMAC-SHA512 of the passphrase
+    pub passphrase_hash: String,
+}
+
+impl Approver {
+    pub fn new(name: String, passphrase: &str) -> Self {
+        let (salt, passphrase_hash) = hash_passphrase(passphrase);
+        Self {
+            name,
+            salt,
+            passphrase_hash,
+        }
+    }
+
+    pub fn has_passphrase(&self, passphrase: &str) -> bool {
+        check_passphrase(passphrase, &self.salt, &self.passphrase_hash)
+    }
+}
+
+/// Transactions that can be signed whatever they send (see [`allow`])
+static ALLOWED: Mutex<Vec<Txid>> = Mutex::new(Vec::new());
+
+/// Lets the transaction `txid` be signed in this process even if it sends more than the threshold
+/// e.g. because it was approved with `gun approval confirm`.
+pub fn allow(txid: Txid) {
+    ALLOWED
+        .lock()
+        .expect("allowed transactions lock isn't poisoned")
+        .push(txid)
+}
+
+fn is_allowed(txid: Txid) -> bool {
+    ALLOWED
+        .lock()
+        .expect("allowed transactions lock isn't poisoned")
+        .contains(&txid)
+}
+
+/// What signing `psbt` would send out of the wallet: what it pays to scripts `is_ours` says
+/// aren't the wallet's but no more than the wallet puts in less what it gets back. The coins
+/// `is_ours` says are the wallet's and those in `also_ours` (e.g. won bets) are what it puts in.
+pub fn outgoing(psbt: &Psbt, is_ours: impl Fn(&Script) -> bool, also_ours: &[OutPoint]) -> Amount {
+    let tx = &psbt.global.unsigned_tx;
+    let put_in: u64 = tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .filter_map(|(txin, input)| {
+            let txout = input.witness_utxo.as_ref()?;
+            match also_ours.contains(&txin.previous_output) || is_ours(&txout.script_pubkey) {
+                true => Some(txout.value),
+                false => None,
+            }
+        })
+        .sum();
+    let (back, paid): (Vec<_>, Vec<_>) = tx
+        .output
+        .iter()
+        .partition(|txout| is_ours(&txout.script_pubkey));
+    let back: u64 = back.iter().map(|txout| txout.value).sum();
+    let paid: u64 = paid.iter().map(|txout| txout.value).sum();
+    Amount::from_sat(paid.min(put_in.saturating_sub(back)))
+}
+
+/// Fails if signing `psbt` would send more than the threshold out of the wallet (see [`outgoing`])
+/// and it hasn't been [allowed](allow).
+pub fn check(
+    policy: &ApprovalPolicy,
+    psbt: &Psbt,
+    is_ours: impl Fn(&Script) -> bool,
+    also_ours: &[OutPoint],
+) -> anyhow::Result<()> {
+    let outgoing = outgoing(psbt, is_ours, also_ours);
+    if outgoing > policy.threshold && !is_allowed(psbt.global.unsigned_tx.txid()) {
+        return Err(ErrorKind::Policy.error(format!(
+            "signing {} would send {} out of the wallet which is more than the approval threshold of {}. Only sends made with gun can be approved (see `gun approval`).",
+            psbt.global.unsigned_tx.txid(),
+            outgoing,
+            policy.threshold
+        )));
+    }
+    Ok(())
+}
+
+/// A signer that goes first and refuses to let the wallet sign anything that [`check`] fails.
+#[derive(Debug)]
+pub struct ApprovalSigner<D> {
+    pub policy: ApprovalPolicy,
+    /// The wallet's database to tell its scripts from others'
+    pub database: D,
+}
+
+impl<D: Database + std::fmt::Debug + Send + Sync> Signer for ApprovalSigner<D> {
+    fn sign(
+        &self,
+        psbt: &mut Psbt,
+        _input_index: Option<usize>,
+        _secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        let is_ours = |script: &Script| {
+            self.database
+                .get_path_from_script_pubkey(script)
+                .map(|path| path.is_some())
+                .unwrap_or(false)
+        };
+        match check(&self.policy, psbt, is_ours, &[]) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // SignerError has no variant that can carry our error so print it here
+                tracing::error!("{}", e);
+                Err(SignerError::UserCanceled)
+            }
+        }
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
+        SignerId::PkHash(hash160::Hash::hash(b"gun-approval"))
+    }
+}
+
+/// Attached to a pending transaction that is waiting for a second approver.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApprovalRequest {
+    pub requested_by: String,
+    pub requested_at: NaiveDateTime,
+    /// What the transaction pays to other wallets
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub outgoing: Amount,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identify_approvers() {
+        let mut policy = ApprovalPolicy::new(Amount::from_sat(100_000));
+        policy.approvers.push(Approver::new("alice".into(), "a"));
+        policy.approvers.push(Approver::new("bob".into(), "b"));
+        assert_eq!(policy.identify("b").unwrap().name, "bob");
+        assert!(policy.identify("c").is_none());
+    }
+
+    #[test]
+    fn outgoing_is_what_leaves_the_wallet() {
+        use bdk::bitcoin::{Transaction, TxIn, TxOut};
+        let ours = Script::from(vec![0x51]);
+        let theirs = Script::from(vec![0x52]);
+        let won_bet = OutPoint::new(Txid::default(), 7);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn::default(),
+                TxIn {
+                    previous_output: won_bet,
+                    ..Default::default()
+                },
+            ],
+            output: vec![
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: theirs.clone(),
+                },
+                TxOut {
+                    value: 60_000,
+                    script_pubkey: ours.clone(),
+                },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: ours.clone(),
+        });
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 50_000,
+            script_pubkey: theirs.clone(),
+        });
+        let is_ours = |script: &Script| *script == ours;
+        // the fee isn't counted
+        assert_eq!(outgoing(&psbt, is_ours, &[]), Amount::from_sat(30_000));
+        // someone else's coins pay for part of it
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = 70_000;
+        assert_eq!(outgoing(&psbt, is_ours, &[]), Amount::from_sat(10_000));
+        assert_eq!(
+            outgoing(&psbt, is_ours, &[won_bet]),
+            Amount::from_sat(30_000)
+        );
+
+        let policy = ApprovalPolicy::new(Amount::from_sat(20_000));
+        assert!(check(&policy, &psbt, is_ours, &[]).is_ok());
+        assert!(check(&policy, &psbt, is_ours, &[won_bet]).is_err());
+        allow(psbt.global.unsigned_tx.txid());
+        assert!(check(&policy, &psbt, is_ours, &[won_bet]).is_ok());
+    }
+}
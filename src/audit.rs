@@ -0,0 +1,257 @@
+//! An append-only log of every transaction gun signs or broadcasts.
+//!
+//! Each entry commits to the one before it through `prev-hash` so editing or removing an entry in
+//! the middle of the log breaks the chain at that point. Removing entries from the end can only be
+//! noticed by comparing against a head hash recorded somewhere else (`gun audit verify` shows it).
+use crate::chrono::NaiveDateTime;
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{
+    hashes::{sha256, Hash},
+    util::psbt::PartiallySignedTransaction as Psbt,
+    Address, Network, Transaction, Txid,
+};
+use std::path::{Path, PathBuf};
+
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOperation {
+    Sign,
+    Broadcast,
+}
+
+impl AuditOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditOperation::Sign => "sign",
+            AuditOperation::Broadcast => "broadcast",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditOutput {
+    /// The address or if the script doesn't have one the script in hex
+    pub destination: String,
+    pub value: u64,
+}
+
+/// What gets hashed for an entry.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub time: NaiveDateTime,
+    pub operation: AuditOperation,
+    /// What the transaction was for e.g. send, claim, cancel
+    pub context: String,
+    pub txid: Txid,
+    pub outputs: Vec<AuditOutput>,
+    /// Only known when all the input values are
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+    /// The arguments gun was run with
+    pub command: Vec<String>,
+    pub prev_hash: sha256::Hash,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    pub hash: sha256::Hash,
+}
+
+impl AuditRecord {
+    pub fn hash(&self) -> sha256::Hash {
+        sha256::Hash::hash(&serde_json::to_vec(self).unwrap())
+    }
+}
+
+/// The hash the first entry points back to.
+pub fn genesis_hash() -> sha256::Hash {
+    sha256::Hash::from_inner([0u8; 32])
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    network: Network,
+}
+
+impl AuditLog {
+    pub fn new(wallet_dir: &Path, network: Network) -> Self {
+        Self {
+            path: wallet_dir.join(AUDIT_LOG_FILE),
+            network,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> anyhow::Result<Vec<AuditEntry>> {
+        let contents = match crate::ephemeral::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).context(format!("reading {}", self.path.display())),
+        };
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("line {} of the audit log is malformed", i + 1))
+            })
+            .collect()
+    }
+
+    /// Records a transaction we signed. `psbt` is used for the fee.
+    pub fn record_psbt(
+        &self,
+        operation: AuditOperation,
+        context: &str,
+        psbt: &Psbt,
+    ) -> anyhow::Result<()> {
+        let input_value = psbt
+            .inputs
+            .iter()
+            .map(|input| input.witness_utxo.as_ref().map(|txout| txout.value))
+            .sum::<Option<u64>>();
+        let output_value = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum::<u64>();
+        let fee = input_value.and_then(|input_value| input_value.checked_sub(output_value));
+        self.record(operation, context, &psbt.global.unsigned_tx, fee)
+    }
+
+    pub fn record(
+        &self,
+        operation: AuditOperation,
+        context: &str,
+        tx: &Transaction,
+        fee: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let last = self.entries()?.pop();
+        let record = AuditRecord {
+            seq: last.as_ref().map(|entry| entry.record.seq + 1).unwrap_or(0),
+            time: crate::clock::real_now(),
+            operation,
+            context: context.to_string(),
+            txid: tx.txid(),
+            outputs: tx
+                .output
+                .iter()
+                .map(|txout| AuditOutput {
+                    destination: Address::from_script(&txout.script_pubkey, self.network)
+                        .map(|address| address.to_string())
+                        .unwrap_or_else(|| crate::hex::encode(txout.script_pubkey.as_bytes())),
+                    value: txout.value,
+                })
+                .collect(),
+            fee,
+            command: std::env::args().collect(),
+            prev_hash: last.map(|entry| entry.hash).unwrap_or_else(genesis_hash),
+        };
+        let entry = AuditEntry {
+            hash: record.hash(),
+            record,
+        };
+
+        crate::ephemeral::append(
+            &self.path,
+            format!("{}\n", serde_json::to_string(&entry).unwrap()),
+        )
+        .with_context(|| format!("writing to the audit log {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Checks every entry's hash and link to the one before it. Returns the number of entries and
+    /// the hash of the last one.
+    pub fn verify(&self) -> anyhow::Result<(usize, sha256::Hash)> {
+        let entries = self.entries()?;
+        let mut prev_hash = genesis_hash();
+        for (i, entry) in entries.iter().enumerate() {
+            if entry.record.seq != i as u64 {
+                return Err(anyhow!(
+                    "entry {} has sequence number {} -- entries have been removed or reordered",
+                    i,
+                    entry.record.seq
+                ));
+            }
+            if entry.record.prev_hash != prev_hash {
+                return Err(anyhow!(
+                    "entry {} doesn't follow on from the one before it -- the log has been tampered with",
+                    entry.record.seq
+                ));
+            }
+            if entry.record.hash() != entry.hash {
+                return Err(anyhow!(
+                    "entry {} doesn't match its hash -- it has been edited",
+                    entry.record.seq
+                ));
+            }
+            prev_hash = entry.hash;
+        }
+        Ok((entries.len(), prev_hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{Script, TxOut};
+    use std::fs;
+
+    fn tx(value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let dir = std::env::temp_dir().join(format!(
+            "gun-audit-test-{}",
+            crate::chrono::Utc::now().timestamp_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let audit_log = AuditLog::new(&dir, Network::Regtest);
+        for value in 1..=3 {
+            audit_log
+                .record(AuditOperation::Sign, "send", &tx(value), Some(100))
+                .unwrap();
+        }
+        let (n_entries, _) = audit_log.verify().unwrap();
+        assert_eq!(n_entries, 3);
+
+        let contents = fs::read_to_string(audit_log.path()).unwrap();
+        fs::write(
+            audit_log.path(),
+            contents.replacen("\"value\":2", "\"value\":20", 1),
+        )
+        .unwrap();
+        assert!(audit_log.verify().is_err());
+
+        let mut lines = contents.lines().collect::<Vec<_>>();
+        lines.remove(1);
+        fs::write(audit_log.path(), lines.join("\n")).unwrap();
+        assert!(audit_log.verify().is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
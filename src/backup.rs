@@ -0,0 +1,306 @@
+//! Encrypted backups of everything gun knows that can't be recovered from the seed words.
+//!
+//! A backup is JSON encrypted with ChaCha20 under a synthetic nonce: the HMAC-SHA256 of the
+//! plaintext is both the authentication tag and (truncated) the nonce. Normally the keys are
+//! derived from the seed so only the wallet's owner can open it. Backups that contain the seed
+//! words themselves are locked with a passphrase instead.
+use crate::{chrono::NaiveDateTime, config::Config};
+use anyhow::anyhow;
+use bdk::bitcoin::{
+    hashes::{sha256, sha512, Hash, HashEngine, Hmac, HmacEngine},
+    util::bip32::Fingerprint,
+};
+use chacha20::{
+    cipher::{NewCipher, StreamCipher},
+    ChaCha20, Key, Nonce,
+};
+
+const MAGIC: &[u8; 9] = b"gunbackup";
+pub const FORMAT_VERSION: u8 = 0;
+pub const PASSPHRASE_ROUNDS: u32 = 100_000;
+/// Where the wallet directory keeps the [`LastBackup`]
+pub const LAST_BACKUP_FILE: &str = "last-backup.json";
+
+/// The latest backup `gun backup create` made so `gun db verify` can say whether it has what's
+/// missing from the database.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LastBackup {
+    pub file: std::path::PathBuf,
+    pub created_at: NaiveDateTime,
+}
+
+/// What goes in a backup.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Backup {
+    pub created_at: NaiveDateTime,
+    /// Fingerprint of the master key of the wallet's seed
+    pub fingerprint: Fingerprint,
+    pub config: Config,
+    /// The raw entries of the bet database (bets, labels, memos...)
+    pub entries: Vec<BackupEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed_words: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BackupEntry {
+    #[serde(with = "hex_bytes")]
+    pub key: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub value: Vec<u8>,
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&crate::hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        crate::hex::decode(&String::deserialize(deserializer)?)
+            .map_err(|e| serde::de::Error::custom(e.to_string()))
+    }
+}
+
+/// How the keys for a backup are found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackupLock {
+    Seed,
+    Passphrase { salt: [u8; 16] },
+}
+
+pub struct BackupKeys {
+    cipher_key: [u8; 32],
+    mac_key: [u8; 32],
+}
+
+impl BackupKeys {
+    /// `secret` should come from [`Keychain::backup_secret`](crate::keychain::Keychain::backup_secret).
+    pub fn from_secret(secret: [u8; 64]) -> Self {
+        let mut cipher_key = [0u8; 32];
+        let mut mac_key = [0u8; 32];
+        cipher_key.copy_from_slice(&secret[..32]);
+        mac_key.copy_from_slice(&secret[32..]);
+        Self {
+            cipher_key,
+            mac_key,
+        }
+    }
+
+    pub fn from_passphrase(passphrase: &str, salt: &[u8; 16], rounds: u32) -> Self {
+        Self::from_secret(pbkdf2_sha512(passphrase, salt, rounds))
+    }
+
+    fn tag(&self, plaintext: &[u8]) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.mac_key);
+        engine.input(plaintext);
+        Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+    }
+
+    fn cipher(&self, tag: &[u8; 32]) -> ChaCha20 {
+        ChaCha20::new(
+            Key::from_slice(&self.cipher_key),
+            Nonce::from_slice(&tag[..12]),
+        )
+    }
+
+    /// Encrypts `plaintext` in place and returns the tag.
+    pub fn seal_in_place(&self, plaintext: &mut [u8]) -> [u8; 32] {
+        let tag = self.tag(plaintext);
+        self.cipher(&tag).apply_keystream(plaintext);
+        tag
+    }
+
+    /// Decrypts what [`seal_in_place`](Self::seal_in_place) encrypted or `None` if `tag` doesn't
+    /// match i.e. the keys are wrong or the ciphertext was changed.
+    pub fn open(&self, tag: &[u8; 32], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let mut plaintext = ciphertext.to_vec();
+        self.cipher(tag).apply_keystream(&mut plaintext);
+        match self.tag(&plaintext) == *tag {
+            true => Some(plaintext),
+            false => None,
+        }
+    }
+}
+
+/// An encrypted backup as it is stored in a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SealedBackup {
+    pub lock: BackupLock,
+    tag: [u8; 32],
+    ciphertext: Vec<u8>,
+}
+
+impl SealedBackup {
+    pub fn seal(backup: &Backup, lock: BackupLock, keys: &BackupKeys) -> Self {
+        let mut ciphertext = serde_json::to_vec(backup).unwrap();
+        let tag = keys.seal_in_place(&mut ciphertext);
+        Self {
+            lock,
+            tag,
+            ciphertext,
+        }
+    }
+
+    pub fn open(&self, keys: &BackupKeys) -> anyhow::Result<Backup> {
+        let plaintext = keys.open(&self.tag, &self.ciphertext).ok_or(anyhow!(match self.lock {
+            BackupLock::Seed =>
+                "couldn't decrypt the backup -- it was made with different seed words or it is corrupted",
+            BackupLock::Passphrase { .. } =>
+                "couldn't decrypt the backup -- the passphrase is wrong or the file is corrupted",
+        }))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION);
+        match self.lock {
+            BackupLock::Seed => bytes.push(0),
+            BackupLock::Passphrase { salt } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&salt);
+            }
+        }
+        bytes.extend_from_slice(&self.tag);
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let rest = bytes
+            .strip_prefix(&MAGIC[..])
+            .ok_or(anyhow!("this is not a gun backup file"))?;
+        let (version, rest) = rest.split_first().ok_or(anyhow!("backup is truncated"))?;
+        if *version != FORMAT_VERSION {
+            return Err(anyhow!(
+                "backup format version {} is not supported by this version of gun",
+                version
+            ));
+        }
+        let (lock, rest) = match rest.split_first() {
+            Some((0, rest)) => (BackupLock::Seed, rest),
+            Some((1, rest)) if rest.len() >= 16 => {
+                let mut salt = [0u8; 16];
+                salt.copy_from_slice(&rest[..16]);
+                (BackupLock::Passphrase { salt }, &rest[16..])
+            }
+            _ => return Err(anyhow!("backup is truncated or corrupted")),
+        };
+        if rest.len() < 32 {
+            return Err(anyhow!("backup is truncated"));
+        }
+        let mut tag = [0u8; 32];
+        tag.copy_from_slice(&rest[..32]);
+        Ok(Self {
+            lock,
+            tag,
+            ciphertext: rest[32..].to_vec(),
+        })
+    }
+}
+
+/// PBKDF2-HMAC-SHA512 of a passphrase (the first 64 byte block is all we ever need).
+pub fn pbkdf2_sha512(passphrase: &str, salt: &[u8], rounds: u32) -> [u8; 64] {
+    let prf = |input: &[u8]| {
+        let mut engine = HmacEngine::<sha512::Hash>::new(passphrase.as_bytes());
+        engine.input(input);
+        Hmac::<sha512::Hash>::from_engine(engine).into_inner()
+    };
+    let mut block = prf(&[salt, &1u32.to_be_bytes()[..]].concat());
+    let mut secret = block;
+    for _ in 1..rounds {
+        block = prf(&block[..]);
+        for (secret_byte, block_byte) in secret.iter_mut().zip(block.iter()) {
+            *secret_byte ^= block_byte;
+        }
+    }
+    secret
+}
+
+/// A salt for a passphrase from the OS's random number generator. Backups, approvers and spending
+/// locks all get their salts from here.
+pub fn passphrase_salt() -> [u8; 16] {
+    let mut salt = [0u8; 16];
+    getrandom::getrandom(&mut salt).expect("the OS random number generator failed");
+    salt
+}
+
+/// Hashes `passphrase` with a new salt for keeping in the config. Returns the salt and the hash as
+/// hex. Approvers and spending locks are both checked this way.
+pub fn hash_passphrase(passphrase: &str) -> (String, String) {
+    let salt = passphrase_salt();
+    (
+        crate::hex::encode(&salt),
+        crate::hex::encode(&pbkdf2_sha512(passphrase, &salt, PASSPHRASE_ROUNDS)),
+    )
+}
+
+/// Whether `passphrase` is the one [`hash_passphrase`] returned `salt` and `passphrase_hash` for.
+pub fn check_passphrase(passphrase: &str, salt: &str, passphrase_hash: &str) -> bool {
+    match crate::hex::decode(salt) {
+        Ok(salt) => {
+            crate::hex::encode(&pbkdf2_sha512(passphrase, &salt, PASSPHRASE_ROUNDS))
+                == passphrase_hash
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::Network;
+
+    fn backup() -> Backup {
+        Backup {
+            created_at: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+            fingerprint: Fingerprint::from(&[1u8, 2, 3, 4][..]),
+            config: Config::default_config(Network::Regtest),
+            entries: vec![BackupEntry {
+                key: vec![0, 1, 2],
+                value: b"{}".to_vec(),
+            }],
+            seed_words: None,
+        }
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let keys = BackupKeys::from_secret([7u8; 64]);
+        let sealed = SealedBackup::seal(&backup(), BackupLock::Seed, &keys);
+        let decoded = SealedBackup::from_bytes(&sealed.to_bytes()).unwrap();
+        assert_eq!(decoded, sealed);
+        let opened = decoded.open(&keys).unwrap();
+        assert_eq!(opened.entries, backup().entries);
+
+        let wrong_keys = BackupKeys::from_secret([8u8; 64]);
+        assert!(decoded.open(&wrong_keys).is_err());
+    }
+
+    #[test]
+    fn passphrase_lock() {
+        let salt = [3u8; 16];
+        let keys = BackupKeys::from_passphrase("hunter2", &salt, 10);
+        let sealed = SealedBackup::seal(&backup(), BackupLock::Passphrase { salt }, &keys);
+        let decoded = SealedBackup::from_bytes(&sealed.to_bytes()).unwrap();
+        assert_eq!(decoded.lock, BackupLock::Passphrase { salt });
+        assert!(decoded
+            .open(&BackupKeys::from_passphrase("hunter2", &salt, 10))
+            .is_ok());
+        assert!(decoded
+            .open(&BackupKeys::from_passphrase("hunter3", &salt, 10))
+            .is_err());
+    }
+
+    #[test]
+    fn config_passphrase_hashes() {
+        let (salt, passphrase_hash) = hash_passphrase("hunter2");
+        assert!(check_passphrase("hunter2", &salt, &passphrase_hash));
+        assert!(!check_passphrase("hunter3", &salt, &passphrase_hash));
+        assert_ne!(hash_passphrase("hunter2").0, salt, "salts are random");
+    }
+}
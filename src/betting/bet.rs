@@ -1,6 +1,7 @@
 use crate::betting::*;
 use bdk::bitcoin::{
-    self, util::psbt::PartiallySignedTransaction as Psbt, Amount, OutPoint, Transaction, Txid,
+    self, util::psbt::PartiallySignedTransaction as Psbt, Address, Amount, OutPoint, Transaction,
+    Txid,
 };
 use olivia_core::{OracleEvent, OracleId, Outcome};
 use olivia_secp256k1::Secp256k1;
@@ -20,6 +21,15 @@ pub struct Bet {
     pub i_chose_right: bool,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Claim the winnings to this address rather than the wallet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_to: Option<Address>,
+    /// The price when the bet was funded if there's a `price-source`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_basis: Option<crate::price::CostBasis>,
+    /// The offline key that has to sign with ours to claim (see [`BetCosigner`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosigner: Option<CosignerKey>,
 }
 
 impl Bet {
@@ -182,6 +192,33 @@ impl BetState {
         }
     }
 
+    /// Like [`name`](Self::name) but says how far along a confirmed transaction is if it hasn't
+    /// reached the number of confirmations in `targets` yet.
+    pub fn status(&self, tip_height: Option<u32>, targets: &ConfirmationTargets) -> String {
+        use BetState::*;
+        let (height, target) = match self {
+            Included { height, .. } | Canceled { height, .. } => (*height, targets.bet_funding),
+            Claimed { height, .. } => (*height, targets.claim),
+            _ => return self.name().to_string(),
+        };
+        match (height, tip_height) {
+            (Some(_), Some(tip_height)) => {
+                let confirmations = confirmations(height, tip_height);
+                if confirmations < target {
+                    let pending = match self {
+                        Included { .. } => "confirming",
+                        Claimed { .. } => "claiming",
+                        _ => "canceling",
+                    };
+                    format!("{} ({}/{})", pending, confirmations, target)
+                } else {
+                    self.name().to_string()
+                }
+            }
+            _ => self.name().to_string(),
+        }
+    }
+
     pub fn reserved_utxos(&self) -> Vec<OutPoint> {
         use BetState::*;
         match self {
@@ -0,0 +1,180 @@
+//! Bets whose winnings can only be claimed with an offline key as well as gun's (`bet-cosigner` in
+//! the config).
+//!
+//! Our side of a bet output is normally spent with our bet key and the oracle's attestation alone.
+//! With a cosigner it's a 2-of-2 of that key and one derived from the cosigner's xpub so someone who
+//! gets into the machine gun runs on still can't take the winnings. The cosigner's key for each bet
+//! goes in the proposal or offer so the other side can make the output. Claims are signed with our
+//! key and saved like any other transaction this wallet can't finish by itself: `gun psbt export`
+//! it, sign it with the offline key and `gun psbt import` it.
+use super::BetDatabase;
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{
+        secp256k1::Secp256k1,
+        util::{
+            bip32::{DerivationPath, Fingerprint},
+            psbt,
+        },
+        Network,
+    },
+    descriptor::DescriptorPublicKey,
+};
+use olivia_secp256k1::fun::Point;
+use std::str::FromStr;
+
+/// How many of a cosigner's keys are looked through when a bet is recovered.
+pub const COSIGNER_RECOVERY_GAP: u32 = 100;
+
+/// The cosigner's key in one bet and where it comes from so the offline signer can find it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CosignerKey {
+    pub public_key: Point,
+    pub fingerprint: Fingerprint,
+    pub path: DerivationPath,
+}
+
+impl CosignerKey {
+    /// Tells the signer of a claim input which of its keys has to sign it.
+    pub fn add_to_psbt_input(&self, psbt_input: &mut psbt::Input) {
+        psbt_input.bip32_derivation.insert(
+            bdk::bitcoin::PublicKey {
+                compressed: true,
+                key: self.public_key.into(),
+            },
+            (self.fingerprint, self.path.clone()),
+        );
+    }
+}
+
+/// The offline key bets are cosigned with e.g. `[d34db33f/84'/0'/9']xpub.../*`. With a wildcard
+/// each bet gets a key of its own.
+#[derive(Clone, Debug)]
+pub struct BetCosigner {
+    key: DescriptorPublicKey,
+}
+
+impl BetCosigner {
+    pub fn new(key: &str, network: Network) -> anyhow::Result<Self> {
+        let key = DescriptorPublicKey::from_str(key)
+            .map_err(|e| anyhow!("{}", e))
+            .context("parsing bet-cosigner")?;
+        match &key {
+            DescriptorPublicKey::XPub(xkey) => {
+                if (xkey.xkey.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+                    return Err(anyhow!(
+                        "the bet-cosigner xpub is for {} but this wallet is on {}",
+                        xkey.xkey.network,
+                        network
+                    ));
+                }
+            }
+            DescriptorPublicKey::SinglePub(_) => {
+                return Err(anyhow!(
+                    "bet-cosigner has to be an xpub so the offline signer can tell which key to sign with"
+                ))
+            }
+        }
+        let cosigner = Self { key };
+        cosigner.key_at(0)?;
+        Ok(cosigner)
+    }
+
+    pub fn key_at(&self, index: u32) -> anyhow::Result<CosignerKey> {
+        let key = self.key.clone().derive(index);
+        let public_key = key
+            .derive_public_key(&Secp256k1::verification_only())
+            .map_err(|e| anyhow!("can't derive the bet-cosigner key: {:?}", e))?;
+        Ok(CosignerKey {
+            public_key: Point::from_bytes(public_key.key.serialize())
+                .expect("a public key is a valid point"),
+            fingerprint: key.master_fingerprint(),
+            path: key.full_derivation_path(),
+        })
+    }
+
+    /// The key for a new bet. Each call moves on to a new one if the key has a wildcard.
+    pub fn next_key(&self, bet_db: &BetDatabase) -> anyhow::Result<CosignerKey> {
+        match self.key.is_deriveable() {
+            true => self.key_at(bet_db.next_change_index(&format!("bet-cosigner {}", self.key))?),
+            false => self.key_at(0),
+        }
+    }
+
+    /// The keys a bet made by this wallet could have been cosigned with.
+    pub fn keys_to_recover(&self) -> Vec<CosignerKey> {
+        let gap = match self.key.is_deriveable() {
+            true => COSIGNER_RECOVERY_GAP,
+            false => 1,
+        };
+        (0..gap)
+            .filter_map(|index| self.key_at(index).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::betting::descriptor_of;
+    use bdk::{
+        bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey},
+        miniscript::DescriptorTrait,
+    };
+
+    fn bet_cosigner(wildcard: &str) -> BetCosigner {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Regtest, &[42u8; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/9'").unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &master.derive_priv(&secp, &path).unwrap());
+        let key = format!(
+            "[{}/84'/1'/9']{}{}",
+            master.fingerprint(&secp),
+            xpub,
+            wildcard
+        );
+        BetCosigner::new(&key, Network::Regtest).unwrap()
+    }
+
+    #[test]
+    fn each_bet_gets_its_own_key() {
+        let cosigner = bet_cosigner("/*");
+        let first = cosigner.key_at(0).unwrap();
+        let second = cosigner.key_at(1).unwrap();
+        assert_ne!(first.public_key, second.public_key);
+        assert_eq!(second.path.to_string(), "m/84'/1'/9'/1");
+        assert_eq!(cosigner.keys_to_recover()[1], second);
+
+        let fixed = bet_cosigner("");
+        assert_eq!(fixed.key_at(0).unwrap().path.to_string(), "m/84'/1'/9'");
+        assert_eq!(fixed.keys_to_recover().len(), 1);
+        assert!(BetCosigner::new(&fixed.key.to_string(), Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn cosigned_side_needs_both_keys() {
+        let keys = bet_cosigner("/*");
+        let cosigner = keys.key_at(0).unwrap().public_key;
+        let output_keys = [
+            keys.key_at(1).unwrap().public_key,
+            keys.key_at(2).unwrap().public_key,
+        ];
+        let plain = descriptor_of(output_keys, [None, None], false);
+        let cosigned = descriptor_of(output_keys, [Some(cosigner), None], false);
+        assert_ne!(plain.script_pubkey(), cosigned.script_pubkey());
+        assert!(
+            cosigned.max_satisfaction_weight().unwrap() > plain.max_satisfaction_weight().unwrap()
+        );
+        // swapping the output keys swaps the cosigners with them
+        assert_eq!(
+            descriptor_of(
+                [output_keys[1], output_keys[0]],
+                [None, Some(cosigner)],
+                true
+            )
+            .script_pubkey(),
+            cosigned.script_pubkey()
+        );
+    }
+}
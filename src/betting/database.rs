@@ -1,13 +1,30 @@
-use crate::betting::*;
+use crate::{
+    allowance::Allowance,
+    approval::ApprovalRequest,
+    betting::*,
+    broadcast_queue::QueuedBroadcast,
+    coinjoin::CoinjoinOutput,
+    oracle_poll::{OracleHostPoll, OraclePoll},
+    price::CostBasis,
+    schedule::ScheduledPayment,
+};
 use anyhow::{anyhow, Context};
 use bdk::{
-    bitcoin::{OutPoint, Txid},
+    bitcoin::{
+        hashes::{sha256, Hash},
+        util::psbt::PartiallySignedTransaction as Psbt,
+        BlockHash, OutPoint, Script, Txid,
+    },
     sled::{
         self,
-        transaction::{ConflictableTransactionError, TransactionalTree},
+        transaction::{
+            ConflictableTransactionError, TransactionError, TransactionalTree,
+            UnabortableTransactionError,
+        },
     },
 };
-use olivia_core::OracleId;
+use olivia_core::{chrono::NaiveDateTime, OracleId};
+use std::collections::HashMap;
 
 pub const DB_VERSION: u8 = 0;
 pub type BetId = u32;
@@ -18,6 +35,27 @@ pub enum MapKey {
     OracleInfo(OracleId),
     Bet(BetId),
     ClaimTx(Txid),
+    Frozen(OutPoint),
+    AddressLabel(Script),
+    PendingPsbt(Txid),
+    ChainTip,
+    Conflict(Txid),
+    TxMemo(Txid),
+    ChangeIndex(String),
+    Journal(BetId),
+    SeenAttestations(String),
+    Chat(BetId),
+    Publication(BetId),
+    CostBasis(Txid),
+    Coinjoin(OutPoint),
+    Allowance(u32),
+    Schedule(u32),
+    OraclePoll(String),
+    OracleHost(String),
+    Rotation,
+    /// The checksum of the entry with this (raw) key (see [`RecordChecksum`])
+    Checksum(Vec<u8>),
+    Undo(u32),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
@@ -50,6 +88,28 @@ pub enum KeyKind {
     BetId,
     OracleInfo,
     Bet,
+    // the order must stay in sync with MapKey
+    ClaimTx,
+    Frozen,
+    AddressLabel,
+    PendingPsbt,
+    ChainTip,
+    Conflict,
+    TxMemo,
+    ChangeIndex,
+    Journal,
+    SeenAttestations,
+    Chat,
+    Publication,
+    CostBasis,
+    Coinjoin,
+    Allowance,
+    Schedule,
+    OraclePoll,
+    OracleHost,
+    Rotation,
+    Checksum,
+    Undo,
 }
 
 impl KeyKind {
@@ -108,17 +168,478 @@ macro_rules! impl_entity {
 
 impl_entity!(OracleId, OracleInfo, OracleInfo);
 impl_entity!(BetId, BetState, Bet);
+impl_entity!(OutPoint, FrozenUtxo, Frozen);
+impl_entity!(Script, AddressLabel, AddressLabel);
+impl_entity!(Txid, PendingPsbt, PendingPsbt);
+impl_entity!(Txid, SeenConflict, Conflict);
+impl_entity!(Txid, TxMemo, TxMemo);
+impl_entity!(String, ChangeIndex, ChangeIndex);
+impl_entity!(BetId, JournalEntry, Journal);
+impl_entity!(String, SeenAttestations, SeenAttestations);
+impl_entity!(BetId, BetChat, Chat);
+impl_entity!(BetId, Publication, Publication);
+impl_entity!(Txid, CostBasis, CostBasis);
+impl_entity!(OutPoint, CoinjoinOutput, Coinjoin);
+impl_entity!(u32, Allowance, Allowance);
+impl_entity!(u32, ScheduledPayment, Schedule);
+impl_entity!(String, OraclePoll, OraclePoll);
+impl_entity!(String, OracleHostPoll, OracleHost);
+impl_entity!(Vec<u8>, RecordChecksum, Checksum);
+impl_entity!(u32, UndoEntry, Undo);
+
+/// A UTXO the user doesn't want to be spent.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrozenUtxo {
+    pub frozen_at: NaiveDateTime,
+}
+
+/// A transaction that has been made but not broadcast yet. Either it is waiting for signatures from
+/// somewhere else (e.g. a hardware wallet) or for someone else to be ready (`gun fund-psbt`). Its
+/// inputs are reserved until it is removed.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PendingPsbt {
+    pub psbt: Psbt,
+    pub created_at: NaiveDateTime,
+    /// Bets that this transaction claims
+    #[serde(default)]
+    pub claiming_bets: Vec<BetId>,
+    /// Set while the transaction is waiting for a second approver before it can be signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalRequest>,
+    /// Set when it's signed but the backend wouldn't take it yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued: Option<QueuedBroadcast>,
+}
+
+/// A note the user has attached to one of their addresses.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AddressLabel {
+    pub label: String,
+}
+
+/// A note the user has attached to one of their transactions.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TxMemo {
+    pub memo: String,
+}
+
+/// The next index to send change to on a change descriptor (see [`crate::change_descriptor`]). It
+/// is keyed by the descriptor's checksum.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChangeIndex {
+    pub next: u32,
+}
+
+/// Written before a bet state transition that involves broadcasting and removed once it is
+/// recorded. If gun stops in between it is finished off or rolled back by
+/// [`Party::resume_journal`](crate::betting::Party::resume_journal).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    pub started_at: NaiveDateTime,
+    pub operation: JournalOperation,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "operation", rename_all = "kebab-case")]
+pub enum JournalOperation {
+    /// Broadcasting the funding transaction of a bet we took an offer for
+    Take { bet: Bet },
+}
+
+impl JournalOperation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JournalOperation::Take { .. } => "take",
+        }
+    }
+}
+
+/// Every different attestation an oracle has made to an event that we've seen, each with the signed
+/// announcement it came with. More than one means the oracle equivocated. It is keyed by the
+/// event's url (see [`SeenAttestations::key`]).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SeenAttestations {
+    pub first_seen: NaiveDateTime,
+    pub responses: Vec<EventResponse>,
+}
+
+impl SeenAttestations {
+    pub fn key(oracle_id: &str, event_id: &olivia_core::EventId) -> String {
+        format!("{}{}", oracle_id, event_id)
+    }
+
+    pub fn outcomes(&self) -> Vec<&str> {
+        self.responses
+            .iter()
+            .filter_map(|response| response.attestation.as_ref())
+            .map(|attestation| attestation.outcome.as_str())
+            .collect()
+    }
+}
+
+/// The messages sent between us and the other side of a bet (see `gun bet chat`).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct BetChat {
+    /// The proposal the bet was made from which our key for the chat is derived from
+    pub proposal: Proposal,
+    pub i_proposed: bool,
+    pub remote_public_key: PublicKey,
+    pub messages: Vec<ChatMessage>,
+}
+
+impl BetChat {
+    pub fn new(proposal: Proposal, i_proposed: bool, remote_public_key: PublicKey) -> Self {
+        BetChat {
+            proposal,
+            i_proposed,
+            remote_public_key,
+            messages: vec![],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChatMessage {
+    /// The nostr event the message was sent in
+    pub id: String,
+    pub sent_at: NaiveDateTime,
+    pub from_me: bool,
+    pub text: String,
+}
+
+/// Where a proposal has been advertised (see `gun bet publish`).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Publication {
+    /// Kept so the adverts can be taken down after the proposal has become a bet
+    pub proposal: Proposal,
+    pub adverts: Vec<Advert>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Advert {
+    /// The name of the board in `proposal-boards`
+    pub board: String,
+    /// What the board knows the proposal by
+    pub id: String,
+    pub published_at: NaiveDateTime,
+    pub unpublished_at: Option<NaiveDateTime>,
+}
+
+impl Publication {
+    pub fn live_adverts(&self) -> impl Iterator<Item = &Advert> {
+        self.adverts
+            .iter()
+            .filter(|advert| advert.unpublished_at.is_none())
+    }
+}
+
+/// A transaction we've seen spending the inputs of one of our bets that isn't the bet.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SeenConflict {
+    pub bet_id: BetId,
+    pub first_seen: NaiveDateTime,
+    /// Whether it spent the counterparty's input rather than ours
+    pub by_counterparty: bool,
+}
+
+/// The last few blocks we saw at the tip of the chain so we can tell when there's been a reorg.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChainTip {
+    /// `(height, hash)` from lowest to highest
+    pub recent: Vec<(u32, BlockHash)>,
+    /// When we last alerted that the tip was stale (a unix timestamp) if it's still stale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_alerted_at: Option<i64>,
+}
+
+/// Everything in this wallet being moved to a new wallet (made by `gun rotate`) e.g. because its
+/// seed words might have been seen.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Rotation {
+    /// The new wallet's directory
+    pub to: std::path::PathBuf,
+    pub started_at: NaiveDateTime,
+    /// The fee for the sweeps (see `gun send --fee`)
+    pub fee: String,
+    /// The most coins one sweep spends
+    pub batch_inputs: usize,
+    /// The sweeps to the new wallet so far
+    #[serde(default)]
+    pub sweeps: Vec<Txid>,
+}
+
+/// How many commands `gun undo` can go back through.
+pub const UNDO_HISTORY: usize = 100;
+
+/// A command that changed the database without broadcasting anything and what the entries it
+/// changed were before so `gun undo` can put them back.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UndoEntry {
+    pub done_at: NaiveDateTime,
+    /// What the command did e.g. `freeze 1234..:0`
+    pub description: String,
+    /// The arguments gun was run with
+    pub command: Vec<String>,
+    /// Each entry the command changed and its value before (or `None` if it didn't exist)
+    pub before: Vec<(MapKey, Option<serde_json::Value>)>,
+}
+
+/// Checks that a raw database entry holds what its key says it does and returns the key.
+pub fn decode_raw_entry(key: &[u8], value: &[u8]) -> anyhow::Result<MapKey> {
+    fn check<T: Entity>(value: &[u8]) -> anyhow::Result<()> {
+        serde_json::from_slice::<T>(value)
+            .with_context(|| format!("invalid {} entry", T::name()))?;
+        Ok(())
+    }
+    let versioned_key =
+        crate::encode::deserialize::<VersionedKey>(key).context("entry has an invalid key")?;
+    if versioned_key.version != DB_VERSION {
+        return Err(anyhow!(
+            "entry is from database version {} but this is version {}",
+            versioned_key.version,
+            DB_VERSION
+        ));
+    }
+    match &versioned_key.key {
+        MapKey::BetId => {
+            if value.len() != 4 {
+                return Err(anyhow!("invalid bet id counter"));
+            }
+        }
+        MapKey::OracleInfo(_) => check::<OracleInfo>(value)?,
+        MapKey::Bet(_) => check::<BetState>(value)?,
+        MapKey::ClaimTx(_) => {
+            serde_json::from_slice::<serde_json::Value>(value).context("invalid ClaimTx entry")?;
+        }
+        MapKey::Frozen(_) => check::<FrozenUtxo>(value)?,
+        MapKey::AddressLabel(_) => check::<AddressLabel>(value)?,
+        MapKey::PendingPsbt(_) => check::<PendingPsbt>(value)?,
+        MapKey::ChainTip => {
+            serde_json::from_slice::<ChainTip>(value).context("invalid ChainTip entry")?;
+        }
+        MapKey::Conflict(_) => check::<SeenConflict>(value)?,
+        MapKey::TxMemo(_) => check::<TxMemo>(value)?,
+        MapKey::ChangeIndex(_) => check::<ChangeIndex>(value)?,
+        MapKey::Journal(_) => check::<JournalEntry>(value)?,
+        MapKey::SeenAttestations(_) => check::<SeenAttestations>(value)?,
+        MapKey::Chat(_) => check::<BetChat>(value)?,
+        MapKey::Publication(_) => check::<Publication>(value)?,
+        MapKey::CostBasis(_) => check::<CostBasis>(value)?,
+        MapKey::Coinjoin(_) => check::<CoinjoinOutput>(value)?,
+        MapKey::Allowance(_) => check::<Allowance>(value)?,
+        MapKey::Schedule(_) => check::<ScheduledPayment>(value)?,
+        MapKey::OraclePoll(_) => check::<OraclePoll>(value)?,
+        MapKey::OracleHost(_) => check::<OracleHostPoll>(value)?,
+        MapKey::Rotation => {
+            serde_json::from_slice::<Rotation>(value).context("invalid Rotation entry")?;
+        }
+        MapKey::Checksum(_) => check::<RecordChecksum>(value)?,
+        MapKey::Undo(_) => check::<UndoEntry>(value)?,
+    }
+    Ok(versioned_key.key)
+}
+
+/// Whether losing or silently corrupting the entry could cost money: the bets and the bet id
+/// counter, the coins that are reserved (frozen or spent by a pending transaction) and the
+/// change indices. These have a [`RecordChecksum`] written along with them.
+pub fn is_checksummed(key: &MapKey) -> bool {
+    matches!(
+        key,
+        MapKey::BetId
+            | MapKey::Bet(_)
+            | MapKey::Frozen(_)
+            | MapKey::PendingPsbt(_)
+            | MapKey::ChangeIndex(_)
+    )
+}
+
+/// The SHA256 of the value of a checksummed entry as it was last written by gun. If the value
+/// no longer matches it something other than gun (a disk or sled bug) changed it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordChecksum {
+    pub sha256: sha256::Hash,
+}
+
+impl RecordChecksum {
+    pub fn of(value: &[u8]) -> Self {
+        RecordChecksum {
+            sha256: sha256::Hash::hash(value),
+        }
+    }
+
+    pub fn matches(&self, value: &[u8]) -> bool {
+        *self == Self::of(value)
+    }
+
+    fn to_vec(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+}
+
+/// The raw key the checksum of the entry with `raw_key` is kept under.
+pub fn checksum_key(raw_key: &[u8]) -> Vec<u8> {
+    VersionedKey::from(MapKey::Checksum(raw_key.to_vec())).to_bytes()
+}
+
+/// A checksummed entry that isn't as gun last wrote it found by
+/// [`BetDatabase::verify_checksums`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChecksumProblem {
+    /// The entry's value doesn't match its checksum
+    Changed { key: Vec<u8> },
+    /// There's a checksum but the entry is gone
+    Missing { key: Vec<u8> },
+}
+
+impl ChecksumProblem {
+    /// The raw key of the entry
+    pub fn key(&self) -> &[u8] {
+        match self {
+            ChecksumProblem::Changed { key } | ChecksumProblem::Missing { key } => key,
+        }
+    }
+}
+
+impl core::fmt::Display for ChecksumProblem {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let describe = |key: &[u8]| match crate::encode::deserialize::<VersionedKey>(key) {
+            Ok(versioned_key) => format!("{:?}", versioned_key.key),
+            Err(_) => format!("entry {}", crate::hex::encode(key)),
+        };
+        match self {
+            ChecksumProblem::Changed { key } => write!(
+                f,
+                "{} doesn't match its checksum -- it was changed by something other than gun",
+                describe(key)
+            ),
+            ChecksumProblem::Missing { key } => write!(
+                f,
+                "{} has a checksum but is gone from the database",
+                describe(key)
+            ),
+        }
+    }
+}
+
+/// What [`BetDatabase::verify_checksums`] found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChecksumReport {
+    /// The entries checked against their checksum
+    pub checked: usize,
+    /// The entries that didn't have a checksum yet (e.g. they were written by an older gun) and
+    /// have been given one
+    pub added: usize,
+    pub problems: Vec<ChecksumProblem>,
+}
+
+/// Something wrong with an entry found by [`BetDatabase::check_integrity`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum IntegrityProblem {
+    /// The entry doesn't decode as what its key says it is
+    Invalid { key: Vec<u8>, error: String },
+    /// The entry belongs to a bet that isn't in the database
+    Orphaned { key: MapKey, bet_id: BetId },
+    /// A bet with an id the bet id counter hasn't reached. The next new bet would replace it.
+    BetIdAhead {
+        bet_id: BetId,
+        counter: Option<BetId>,
+    },
+    /// A kind of entry nothing uses anymore
+    Unused { key: MapKey },
+}
+
+impl core::fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            IntegrityProblem::Invalid { key, error } => {
+                write!(f, "entry {} is invalid: {}", crate::hex::encode(key), error)
+            }
+            IntegrityProblem::Orphaned { key, bet_id } => {
+                write!(f, "{:?} refers to bet {} which doesn't exist", key, bet_id)
+            }
+            IntegrityProblem::BetIdAhead { bet_id, counter } => match counter {
+                Some(counter) => write!(
+                    f,
+                    "bet {} is ahead of the bet id counter ({})",
+                    bet_id, counter
+                ),
+                None => write!(f, "bet {} exists but there is no bet id counter", bet_id),
+            },
+            IntegrityProblem::Unused { key } => write!(f, "{:?} is not used anymore", key),
+        }
+    }
+}
+
+/// Why one of our coins can't be spent by a normal send.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reservation {
+    /// It's an input to a bet that's been proposed or offered
+    Bet(BetId),
+    /// It's spent by a transaction waiting to be signed or broadcast
+    PendingPsbt(Txid),
+    /// The user froze it
+    Frozen,
+}
 
 pub struct BetDatabase(sled::Tree);
 
 fn insert<O: serde::Serialize>(tree: &sled::Tree, key: MapKey, value: O) -> anyhow::Result<()> {
-    tree.insert(
-        VersionedKey::from(key).to_bytes(),
-        serde_json::to_string(&value).unwrap().into_bytes(),
-    )?;
+    let raw_key = VersionedKey::from(key.clone()).to_bytes();
+    insert_raw(tree, &key, raw_key, serde_json::to_vec(&value).unwrap())
+}
+
+/// Writes `value` under `raw_key` (the encoding of `key`) along with its checksum if it has one.
+fn insert_raw(
+    tree: &sled::Tree,
+    key: &MapKey,
+    raw_key: Vec<u8>,
+    value: Vec<u8>,
+) -> anyhow::Result<()> {
+    let mut batch = sled::Batch::default();
+    if is_checksummed(key) {
+        batch.insert(checksum_key(&raw_key), RecordChecksum::of(&value).to_vec());
+    }
+    batch.insert(raw_key, value);
+    tree.apply_batch(batch)?;
     Ok(())
 }
 
+/// Like [`insert_raw`] but in a transaction.
+fn tx_insert_raw(
+    db: &TransactionalTree,
+    key: &MapKey,
+    raw_key: Vec<u8>,
+    value: Vec<u8>,
+) -> Result<(), UnabortableTransactionError> {
+    if is_checksummed(key) {
+        db.insert(checksum_key(&raw_key), RecordChecksum::of(&value).to_vec())?;
+    }
+    db.insert(raw_key, value)?;
+    Ok(())
+}
+
+fn transaction_error(e: TransactionError<anyhow::Error>) -> anyhow::Error {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => e.into(),
+    }
+}
+
+fn decode_entity<T: Entity>(
+    item: sled::Result<(sled::IVec, sled::IVec)>,
+) -> anyhow::Result<(T::Key, T)> {
+    let (key, value) = item?;
+    Ok((
+        T::deserialize_key(&key[..])
+            .with_context(|| format!("Error Deserializing key for {}", T::name()))?,
+        serde_json::from_slice(&value[..])
+            .with_context(|| format!("Error Deserialzing {}", T::name()))?,
+    ))
+}
+
 impl BetDatabase {
     pub fn new(tree: sled::Tree) -> Self {
         BetDatabase(tree)
@@ -126,25 +647,26 @@ impl BetDatabase {
 
     pub fn insert_bet(&self, bet: BetState) -> anyhow::Result<BetId> {
         use std::convert::TryFrom;
-        let i = self
-            .0
-            .update_and_fetch(
-                VersionedKey::from(MapKey::BetId).to_bytes(),
-                |prev| match prev {
-                    Some(prev) => Some(
-                        (u32::from_be_bytes(<[u8; 4]>::try_from(prev).unwrap()) + 1)
-                            .to_be_bytes()
-                            .to_vec(),
-                    ),
-                    None => Some(0u32.to_be_bytes().to_vec()),
-                },
-            )?
-            .unwrap();
-        let i = u32::from_be_bytes(<[u8; 4]>::try_from(i.to_vec()).unwrap());
-
-        insert(&self.0, MapKey::Bet(i), bet)?;
-
-        Ok(i)
+        let counter_key = VersionedKey::from(MapKey::BetId).to_bytes();
+        let value = serde_json::to_vec(&bet).unwrap();
+        self.0
+            .transaction(|db| {
+                let i = match db.get(&counter_key)? {
+                    Some(prev) => u32::from_be_bytes(<[u8; 4]>::try_from(&prev[..]).unwrap()) + 1,
+                    None => 0,
+                };
+                tx_insert_raw(
+                    db,
+                    &MapKey::BetId,
+                    counter_key.clone(),
+                    i.to_be_bytes().to_vec(),
+                )?;
+                let key = MapKey::Bet(i);
+                let raw_key = VersionedKey::from(key.clone()).to_bytes();
+                tx_insert_raw(db, &key, raw_key, value.clone())?;
+                Ok::<_, ConflictableTransactionError<anyhow::Error>>(i)
+            })
+            .map_err(transaction_error)
     }
 
     pub fn currently_used_utxos(&self, ignore: &[BetId]) -> anyhow::Result<Vec<OutPoint>> {
@@ -154,9 +676,686 @@ impl BetDatabase {
             .into_iter()
             .filter(|(bet_id, _)| !ignore.contains(bet_id))
             .flat_map(|(_, bet)| bet.reserved_utxos())
+            .chain(self.pending_psbt_utxos()?)
+            .collect())
+    }
+
+    /// The other proposals that share a pot (some of their inputs) with `bet_id`.
+    pub fn sibling_proposals(&self, bet_id: BetId) -> anyhow::Result<Vec<BetId>> {
+        let inputs = match self.get_entity::<BetState>(bet_id)? {
+            Some(bet_state) => bet_state.into_bet_or_prop().inputs(),
+            None => return Ok(vec![]),
+        };
+        Ok(self
+            .list_entities::<BetState>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|(other_id, bet_state)| {
+                *other_id != bet_id
+                    && matches!(bet_state, BetState::Proposed { .. })
+                    && bet_state
+                        .reserved_utxos()
+                        .iter()
+                        .any(|input| inputs.contains(input))
+            })
+            .map(|(other_id, _)| other_id)
             .collect())
     }
 
+    /// Every coin that something has a claim on and why. Coins used by a bet or a pending
+    /// transaction take precedence over being frozen.
+    pub fn reservations(&self) -> anyhow::Result<HashMap<OutPoint, Reservation>> {
+        let mut reservations = HashMap::new();
+        for outpoint in self.frozen_utxos()? {
+            reservations.insert(outpoint, Reservation::Frozen);
+        }
+        for (txid, pending) in self
+            .list_entities::<PendingPsbt>()
+            .collect::<Result<Vec<_>, _>>()?
+        {
+            for txin in pending.psbt.global.unsigned_tx.input {
+                reservations.insert(txin.previous_output, Reservation::PendingPsbt(txid));
+            }
+        }
+        for (bet_id, bet_state) in self
+            .list_entities::<BetState>()
+            .collect::<Result<Vec<_>, _>>()?
+        {
+            for outpoint in bet_state.reserved_utxos() {
+                reservations.insert(outpoint, Reservation::Bet(bet_id));
+            }
+        }
+        Ok(reservations)
+    }
+
+    /// The coins spent by transactions waiting for signatures
+    pub fn pending_psbt_utxos(&self) -> anyhow::Result<Vec<OutPoint>> {
+        Ok(self
+            .list_entities::<PendingPsbt>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flat_map(|(_, pending)| {
+                pending
+                    .psbt
+                    .global
+                    .unsigned_tx
+                    .input
+                    .into_iter()
+                    .map(|txin| txin.previous_output)
+            })
+            .collect())
+    }
+
+    pub fn insert_pending_psbt(
+        &self,
+        psbt: Psbt,
+        claiming_bets: Vec<BetId>,
+    ) -> anyhow::Result<Txid> {
+        let txid = psbt.global.unsigned_tx.txid();
+        insert(
+            &self.0,
+            MapKey::PendingPsbt(txid),
+            PendingPsbt {
+                psbt,
+                created_at: crate::clock::now(),
+                claiming_bets,
+                approval: None,
+                queued: None,
+            },
+        )?;
+        Ok(txid)
+    }
+
+    /// Keeps `psbt` (which is ready to broadcast) to try broadcasting again later. Its coins stay
+    /// reserved until it's removed.
+    pub fn queue_broadcast(&self, psbt: Psbt, queued: QueuedBroadcast) -> anyhow::Result<Txid> {
+        let txid = psbt.global.unsigned_tx.txid();
+        let existing = self.get_entity::<PendingPsbt>(txid)?;
+        insert(
+            &self.0,
+            MapKey::PendingPsbt(txid),
+            PendingPsbt {
+                psbt,
+                created_at: existing
+                    .as_ref()
+                    .map(|pending| pending.created_at)
+                    .unwrap_or_else(crate::clock::now),
+                claiming_bets: existing
+                    .map(|pending| pending.claiming_bets)
+                    .unwrap_or_default(),
+                approval: None,
+                queued: Some(queued),
+            },
+        )?;
+        Ok(txid)
+    }
+
+    /// Removes the pending transaction `txid` now that it's been broadcast. It's left if it was
+    /// queued to be broadcast later instead.
+    pub fn remove_broadcast_psbt(&self, txid: Txid) -> anyhow::Result<()> {
+        match self.get_entity::<PendingPsbt>(txid)? {
+            Some(pending) if pending.queued.is_none() => {
+                self.remove_entity::<PendingPsbt>(txid)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The transactions waiting to be broadcast again
+    pub fn queued_broadcasts(&self) -> anyhow::Result<Vec<(Txid, PendingPsbt)>> {
+        Ok(self
+            .list_entities::<PendingPsbt>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter(|(_, pending)| pending.queued.is_some())
+            .collect())
+    }
+
+    /// Marks a pending transaction as waiting for approval or with `None` as no longer waiting.
+    pub fn set_pending_approval(
+        &self,
+        txid: Txid,
+        approval: Option<ApprovalRequest>,
+    ) -> anyhow::Result<()> {
+        let mut pending = self
+            .get_entity::<PendingPsbt>(txid)?
+            .ok_or(anyhow!("transaction {} isn't pending", txid))?;
+        pending.approval = approval;
+        insert(&self.0, MapKey::PendingPsbt(txid), pending)
+    }
+
+    pub fn frozen_utxos(&self) -> anyhow::Result<Vec<OutPoint>> {
+        Ok(self
+            .list_entities::<FrozenUtxo>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(outpoint, _)| outpoint)
+            .collect())
+    }
+
+    pub fn freeze_utxo(&self, outpoint: OutPoint) -> anyhow::Result<()> {
+        insert(
+            &self.0,
+            MapKey::Frozen(outpoint),
+            FrozenUtxo {
+                frozen_at: crate::clock::now(),
+            },
+        )
+    }
+
+    pub fn address_labels(&self) -> anyhow::Result<HashMap<Script, String>> {
+        Ok(self
+            .list_entities::<AddressLabel>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(script, address_label)| (script, address_label.label))
+            .collect())
+    }
+
+    pub fn tx_memos(&self) -> anyhow::Result<HashMap<Txid, String>> {
+        Ok(self
+            .list_entities::<TxMemo>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(txid, tx_memo)| (txid, tx_memo.memo))
+            .collect())
+    }
+
+    /// The price recorded when each of our sends was broadcast.
+    pub fn cost_bases(&self) -> anyhow::Result<HashMap<Txid, CostBasis>> {
+        self.list_entities::<CostBasis>().collect()
+    }
+
+    pub fn set_cost_basis(&self, txid: Txid, cost_basis: CostBasis) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::CostBasis(txid), cost_basis)
+    }
+
+    pub fn coinjoin_outputs(&self) -> anyhow::Result<HashMap<OutPoint, CoinjoinOutput>> {
+        self.list_entities::<CoinjoinOutput>().collect()
+    }
+
+    pub fn set_coinjoin_output(
+        &self,
+        outpoint: OutPoint,
+        coinjoin_output: CoinjoinOutput,
+    ) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::Coinjoin(outpoint), coinjoin_output)
+    }
+
+    /// Stores `allowance` under the next free index and returns it.
+    pub fn add_allowance(&self, allowance: Allowance) -> anyhow::Result<u32> {
+        let index = self
+            .list_entities::<Allowance>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(index, _)| index + 1)
+            .max()
+            .unwrap_or(0);
+        insert(&self.0, MapKey::Allowance(index), allowance)?;
+        Ok(index)
+    }
+
+    /// Stores `payment` under the next free id and returns it.
+    pub fn add_scheduled_payment(&self, payment: ScheduledPayment) -> anyhow::Result<u32> {
+        let id = self
+            .list_entities::<ScheduledPayment>()
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|(id, _)| id + 1)
+            .max()
+            .unwrap_or(0);
+        insert(&self.0, MapKey::Schedule(id), payment)?;
+        Ok(id)
+    }
+
+    pub fn set_scheduled_payment(&self, id: u32, payment: ScheduledPayment) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::Schedule(id), payment)?;
+        Ok(())
+    }
+
+    pub fn set_oracle_poll(&self, event_url: String, poll: OraclePoll) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::OraclePoll(event_url), poll)
+    }
+
+    pub fn set_oracle_host_poll(&self, host: String, poll: OracleHostPoll) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::OracleHost(host), poll)
+    }
+
+    /// Returns the next change index of the descriptor with `checksum` and moves it on by one.
+    pub fn next_change_index(&self, checksum: &str) -> anyhow::Result<u32> {
+        let next = self
+            .get_entity::<ChangeIndex>(checksum.to_string())?
+            .map(|change_index| change_index.next)
+            .unwrap_or(0);
+        insert(
+            &self.0,
+            MapKey::ChangeIndex(checksum.to_string()),
+            ChangeIndex { next: next + 1 },
+        )?;
+        Ok(next)
+    }
+
+    /// Records that `operation` on `bet_id` has started (see [`JournalEntry`]).
+    pub fn begin_journal(&self, bet_id: BetId, operation: JournalOperation) -> anyhow::Result<()> {
+        insert(
+            &self.0,
+            MapKey::Journal(bet_id),
+            JournalEntry {
+                started_at: crate::clock::now(),
+                operation,
+            },
+        )?;
+        // it has to be on disk before the broadcast
+        self.0.flush()?;
+        Ok(())
+    }
+
+    pub fn end_journal(&self, bet_id: BetId) -> anyhow::Result<()> {
+        self.remove_entity::<JournalEntry>(bet_id)?;
+        Ok(())
+    }
+
+    /// Starts the chat for `bet_id` unless it already has one.
+    pub fn start_chat(&self, bet_id: BetId, chat: BetChat) -> anyhow::Result<()> {
+        if self.get_entity::<BetChat>(bet_id)?.is_none() {
+            insert(&self.0, MapKey::Chat(bet_id), chat)?;
+        }
+        Ok(())
+    }
+
+    /// Records that the proposal `bet_id` has been put on a board.
+    pub fn add_advert(
+        &self,
+        bet_id: BetId,
+        proposal: &Proposal,
+        advert: Advert,
+    ) -> anyhow::Result<Publication> {
+        let mut publication = self
+            .get_entity::<Publication>(bet_id)?
+            .unwrap_or(Publication {
+                proposal: proposal.clone(),
+                adverts: vec![],
+            });
+        publication.adverts.push(advert);
+        insert(&self.0, MapKey::Publication(bet_id), publication.clone())?;
+        Ok(publication)
+    }
+
+    /// Records that the advert for `bet_id` on `board` has been taken down.
+    pub fn set_unpublished(
+        &self,
+        bet_id: BetId,
+        board: &str,
+        unpublished_at: NaiveDateTime,
+    ) -> anyhow::Result<()> {
+        let mut publication = self
+            .get_entity::<Publication>(bet_id)?
+            .ok_or(anyhow!("proposal {} hasn't been published", bet_id))?;
+        for advert in &mut publication.adverts {
+            if advert.board == board && advert.unpublished_at.is_none() {
+                advert.unpublished_at = Some(unpublished_at);
+            }
+        }
+        insert(&self.0, MapKey::Publication(bet_id), publication)?;
+        Ok(())
+    }
+
+    /// Adds the messages we don't have yet to the chat for `bet_id` and returns how many there were.
+    pub fn add_chat_messages(
+        &self,
+        bet_id: BetId,
+        messages: Vec<ChatMessage>,
+    ) -> anyhow::Result<usize> {
+        let mut chat = self
+            .get_entity::<BetChat>(bet_id)?
+            .ok_or(anyhow!("bet {} doesn't have a chat", bet_id))?;
+        let before = chat.messages.len();
+        for message in messages {
+            if !chat
+                .messages
+                .iter()
+                .any(|existing| existing.id == message.id)
+            {
+                chat.messages.push(message);
+            }
+        }
+        chat.messages.sort_by_key(|message| message.sent_at);
+        let added = chat.messages.len() - before;
+        if added > 0 {
+            insert(&self.0, MapKey::Chat(bet_id), chat)?;
+        }
+        Ok(added)
+    }
+
+    /// Adds `response` to what we've seen attested for the event unless we've already seen its
+    /// outcome and returns everything seen so far. The attestation should have been checked first.
+    pub fn record_attestation(
+        &self,
+        oracle_id: &str,
+        event_id: &olivia_core::EventId,
+        response: EventResponse,
+    ) -> anyhow::Result<SeenAttestations> {
+        let key = SeenAttestations::key(oracle_id, event_id);
+        let mut seen =
+            self.get_entity::<SeenAttestations>(key.clone())?
+                .unwrap_or(SeenAttestations {
+                    first_seen: crate::clock::now(),
+                    responses: vec![],
+                });
+        let is_new = match &response.attestation {
+            Some(attestation) => !seen.outcomes().contains(&attestation.outcome.as_str()),
+            None => false,
+        };
+        if is_new {
+            seen.responses.push(response);
+            insert(&self.0, MapKey::SeenAttestations(key), seen.clone())?;
+        }
+        Ok(seen)
+    }
+
+    pub fn set_tx_memo(&self, txid: Txid, memo: String) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::TxMemo(txid), TxMemo { memo })
+    }
+
+    pub fn set_address_label(&self, script: Script, label: String) -> anyhow::Result<()> {
+        insert(
+            &self.0,
+            MapKey::AddressLabel(script),
+            AddressLabel { label },
+        )
+    }
+
+    /// Remembers the entries at `keys` before a command that's about to change them so it can be
+    /// undone. Only the last [`UNDO_HISTORY`] commands are kept.
+    pub fn record_undo(&self, description: String, keys: Vec<MapKey>) -> anyhow::Result<()> {
+        let mut before = vec![];
+        for key in keys {
+            let value = self
+                .0
+                .get(VersionedKey::from(key.clone()).to_bytes())?
+                .map(|bytes| serde_json::from_slice(&bytes))
+                .transpose()
+                .with_context(|| format!("reading {:?} before changing it", key))?;
+            before.push((key, value));
+        }
+        let history = self.undo_history()?;
+        let next = history.last().map(|(id, _)| id + 1).unwrap_or(0);
+        for (id, _) in history.iter().rev().skip(UNDO_HISTORY - 1) {
+            self.0
+                .remove(VersionedKey::from(MapKey::Undo(*id)).to_bytes())?;
+        }
+        insert(
+            &self.0,
+            MapKey::Undo(next),
+            UndoEntry {
+                done_at: crate::clock::now(),
+                description,
+                command: std::env::args().collect(),
+                before,
+            },
+        )
+    }
+
+    /// The commands that can be undone from the oldest to the most recent.
+    pub fn undo_history(&self) -> anyhow::Result<Vec<(u32, UndoEntry)>> {
+        let mut history = self
+            .list_entities::<UndoEntry>()
+            .collect::<Result<Vec<_>, _>>()?;
+        // keys are varint encoded so they aren't stored in order
+        history.sort_by_key(|(id, _)| *id);
+        Ok(history)
+    }
+
+    /// Puts back what the most recent command in the undo history changed and removes it from the
+    /// history.
+    pub fn undo_last(&self) -> anyhow::Result<Option<UndoEntry>> {
+        let (id, entry) = match self.undo_history()?.pop() {
+            Some(last) => last,
+            None => return Ok(None),
+        };
+        let undo_key = VersionedKey::from(MapKey::Undo(id)).to_bytes();
+        self.0
+            .transaction(|db| {
+                for (key, value) in &entry.before {
+                    let raw_key = VersionedKey::from(key.clone()).to_bytes();
+                    match value {
+                        Some(value) => {
+                            tx_insert_raw(db, key, raw_key, serde_json::to_vec(value).unwrap())?
+                        }
+                        None => {
+                            if is_checksummed(key) {
+                                db.remove(checksum_key(&raw_key))?;
+                            }
+                            db.remove(raw_key)?;
+                        }
+                    }
+                }
+                db.remove(undo_key.clone())?;
+                Ok::<_, ConflictableTransactionError<anyhow::Error>>(())
+            })
+            .map_err(transaction_error)?;
+        Ok(Some(entry))
+    }
+
+    /// Every key and value in the database as they are stored.
+    pub fn raw_entries(&self) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.0
+            .iter()
+            .map(|item| {
+                let (key, value) = item?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Reads every entry and checks that it decodes and that the entries that refer to bets refer
+    /// to ones that exist. Returns the number of entries and the problems found. Errors if the
+    /// database itself can't be read.
+    pub fn check_integrity(&self) -> anyhow::Result<(usize, Vec<IntegrityProblem>)> {
+        use std::convert::TryFrom;
+        let mut problems = vec![];
+        let mut counter = None;
+        let mut bets = vec![];
+        let mut references = vec![];
+        let mut n_entries = 0;
+
+        for item in self.0.iter() {
+            let (key, value) = item.context("reading the bet database")?;
+            n_entries += 1;
+            let map_key = match decode_raw_entry(&key, &value) {
+                Ok(map_key) => map_key,
+                Err(e) => {
+                    problems.push(IntegrityProblem::Invalid {
+                        key: key.to_vec(),
+                        error: format!("{:#}", e),
+                    });
+                    continue;
+                }
+            };
+            match &map_key {
+                MapKey::BetId => {
+                    counter = Some(u32::from_be_bytes(
+                        <[u8; 4]>::try_from(&value[..]).expect("checked when decoding"),
+                    ))
+                }
+                MapKey::Bet(bet_id) => bets.push(*bet_id),
+                MapKey::Conflict(_) => {
+                    let conflict = serde_json::from_slice::<SeenConflict>(&value)?;
+                    references.push((map_key.clone(), conflict.bet_id));
+                }
+                MapKey::PendingPsbt(_) => {
+                    let pending = serde_json::from_slice::<PendingPsbt>(&value)?;
+                    for bet_id in pending.claiming_bets {
+                        references.push((map_key.clone(), bet_id));
+                    }
+                }
+                MapKey::Journal(bet_id) | MapKey::Chat(bet_id) | MapKey::Publication(bet_id) => {
+                    references.push((map_key.clone(), *bet_id))
+                }
+                MapKey::ClaimTx(_) => problems.push(IntegrityProblem::Unused { key: map_key }),
+                _ => {}
+            }
+        }
+
+        for bet_id in &bets {
+            if counter.map(|counter| *bet_id > counter).unwrap_or(true) {
+                problems.push(IntegrityProblem::BetIdAhead {
+                    bet_id: *bet_id,
+                    counter,
+                });
+            }
+        }
+        for (key, bet_id) in references {
+            if !bets.contains(&bet_id) {
+                problems.push(IntegrityProblem::Orphaned { key, bet_id });
+            }
+        }
+
+        Ok((n_entries, problems))
+    }
+
+    /// Checks that every checksummed entry (see [`is_checksummed`]) still matches the checksum
+    /// written with it. Entries that don't have one yet are given one. Errors if the database
+    /// itself can't be read.
+    pub fn verify_checksums(&self) -> anyhow::Result<ChecksumReport> {
+        let mut report = ChecksumReport::default();
+        let mut checksums = HashMap::new();
+        let mut records = vec![];
+        for item in self.0.iter() {
+            let (key, value) = item.context("reading the bet database")?;
+            // entries that can't be decoded are for check_integrity to report
+            let versioned_key = match crate::encode::deserialize::<VersionedKey>(&key) {
+                Ok(versioned_key) => versioned_key,
+                Err(_) => continue,
+            };
+            match versioned_key.key {
+                MapKey::Checksum(record_key) => {
+                    match serde_json::from_slice::<RecordChecksum>(&value) {
+                        Ok(checksum) => {
+                            checksums.insert(record_key, checksum);
+                        }
+                        // a checksum that's invalid itself can't vouch for its entry
+                        Err(_) => report
+                            .problems
+                            .push(ChecksumProblem::Changed { key: record_key }),
+                    }
+                }
+                map_key if is_checksummed(&map_key) => records.push((key.to_vec(), value)),
+                _ => {}
+            }
+        }
+
+        for (key, value) in records {
+            match checksums.remove(&key) {
+                Some(checksum) => {
+                    report.checked += 1;
+                    if !checksum.matches(&value) {
+                        report.problems.push(ChecksumProblem::Changed { key });
+                    }
+                }
+                None => {
+                    if report
+                        .problems
+                        .iter()
+                        .all(|problem| problem.key() != &key[..])
+                    {
+                        self.0
+                            .insert(checksum_key(&key), RecordChecksum::of(&value).to_vec())?;
+                        report.added += 1;
+                    }
+                }
+            }
+        }
+        for (key, _) in checksums {
+            report.problems.push(ChecksumProblem::Missing { key });
+        }
+        report.problems.sort_by(|a, b| a.key().cmp(b.key()));
+
+        Ok(report)
+    }
+
+    /// The value stored under a raw key.
+    pub fn get_raw(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.get(key)?.map(|value| value.to_vec()))
+    }
+
+    /// Adds an entry taken from [`raw_entries`](Self::raw_entries) of another database. Existing
+    /// entries are only replaced if `overwrite` is set except for the bet id counter which becomes
+    /// the larger of the two. Returns whether anything was written.
+    pub fn merge_raw_entry(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        overwrite: bool,
+    ) -> anyhow::Result<bool> {
+        let map_key = decode_raw_entry(key, value)?;
+        let existing = self.0.get(key)?;
+        match (&map_key, existing) {
+            // the tip we saw on another machine doesn't tell us anything
+            (MapKey::ChainTip, _) => Ok(false),
+            // checksums are written along with the entries they're for and what can be undone
+            // is up to the machine it was done on
+            (MapKey::Checksum(_), _) | (MapKey::Undo(_), _) => Ok(false),
+            (MapKey::BetId, Some(existing)) => {
+                use std::convert::TryFrom;
+                let as_u32 = |bytes: &[u8]| -> anyhow::Result<u32> {
+                    Ok(u32::from_be_bytes(
+                        <[u8; 4]>::try_from(bytes)
+                            .map_err(|_| anyhow!("invalid bet id counter"))?,
+                    ))
+                };
+                if as_u32(value)? > as_u32(&existing)? {
+                    insert_raw(&self.0, &map_key, key.to_vec(), value.to_vec())?;
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            (_, Some(_)) if !overwrite => Ok(false),
+            _ => {
+                insert_raw(&self.0, &map_key, key.to_vec(), value.to_vec())?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn get_chain_tip(&self) -> anyhow::Result<Option<ChainTip>> {
+        Ok(self
+            .0
+            .get(VersionedKey::from(MapKey::ChainTip).to_bytes())?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()?)
+    }
+
+    /// The height of the tip the last time we synced.
+    pub fn tip_height(&self) -> anyhow::Result<Option<u32>> {
+        Ok(self
+            .get_chain_tip()?
+            .and_then(|chain_tip| chain_tip.recent.last().map(|(height, _)| *height)))
+    }
+
+    pub fn set_chain_tip(&self, chain_tip: ChainTip) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::ChainTip, chain_tip)
+    }
+
+    /// The move to a new wallet started by `gun rotate` if there is one.
+    pub fn get_rotation(&self) -> anyhow::Result<Option<Rotation>> {
+        Ok(self
+            .0
+            .get(VersionedKey::from(MapKey::Rotation).to_bytes())?
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()?)
+    }
+
+    pub fn set_rotation(&self, rotation: Rotation) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::Rotation, rotation)
+    }
+
+    pub fn insert_conflict(&self, txid: Txid, conflict: SeenConflict) -> anyhow::Result<()> {
+        insert(&self.0, MapKey::Conflict(txid), conflict)
+    }
+
     pub fn insert_oracle_info(&self, oracle_info: OracleInfo) -> anyhow::Result<()> {
         let key = MapKey::OracleInfo(oracle_info.id.clone());
         insert(&self.0, key, oracle_info)
@@ -171,9 +1370,20 @@ impl BetDatabase {
     }
 
     pub fn remove_entity<T: Entity>(&self, key: T::Key) -> anyhow::Result<Option<T>> {
-        Ok(self
-            .0
-            .remove(VersionedKey::from(T::to_map_key(key)).to_bytes())?
+        let map_key = T::to_map_key(key);
+        let raw_key = VersionedKey::from(map_key.clone()).to_bytes();
+        let removed = if is_checksummed(&map_key) {
+            self.0
+                .transaction(|db| {
+                    let removed = db.remove(raw_key.clone())?;
+                    db.remove(checksum_key(&raw_key))?;
+                    Ok::<_, ConflictableTransactionError<anyhow::Error>>(removed)
+                })
+                .map_err(transaction_error)?
+        } else {
+            self.0.remove(raw_key)?
+        };
+        Ok(removed
             .map(|bytes| serde_json::from_slice(&bytes))
             .transpose()?)
     }
@@ -197,33 +1407,55 @@ impl BetDatabase {
                         .expect("it's in the DB so it should be deserializable");
                     let new_state = f(old_state, *bet_id, TxDb(db))
                         .map_err(ConflictableTransactionError::Abort)?;
-                    db.insert(key, serde_json::to_vec(&new_state).unwrap())?;
+                    tx_insert_raw(
+                        db,
+                        &MapKey::Bet(*bet_id),
+                        key,
+                        serde_json::to_vec(&new_state).unwrap(),
+                    )?;
                 }
                 Ok(())
             })
-            .map_err(|e| match e {
-                bdk::sled::transaction::TransactionError::Abort(e) => e,
-                bdk::sled::transaction::TransactionError::Storage(e) => e.into(),
-            })
+            .map_err(transaction_error)
     }
 
     pub fn list_entities<T: Entity>(&self) -> impl Iterator<Item = anyhow::Result<(T::Key, T)>> {
-        self.0.scan_prefix(T::key_kind().prefix()).map(|item| {
-            let (key, value) = item?;
-            Ok((
-                T::deserialize_key(&key[..])
-                    .with_context(|| format!("Error Deserializing key for {}", T::name()))?,
-                serde_json::from_slice(&value[..])
-                    .with_context(|| format!("Error Deserialzing {}", T::name()))?,
-            ))
-        })
+        self.0
+            .scan_prefix(T::key_kind().prefix())
+            .map(decode_entity::<T>)
+    }
+
+    /// Lists at most `limit` entities starting `offset` entities in (in key order). The ones
+    /// skipped aren't decoded so paging through lots of them is cheap.
+    pub fn list_entities_page<T: Entity>(
+        &self,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> impl Iterator<Item = (T::Key, T)> {
+        self.0
+            .scan_prefix(T::key_kind().prefix())
+            .skip(offset)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(decode_entity::<T>)
+            .filter_map(|entity| match entity {
+                Ok(entity) => Some(entity),
+                Err(e) => {
+                    tracing::error!("Error retreiving an {}: {}", T::name(), e);
+                    None
+                }
+            })
+    }
+
+    /// How many entities of type `T` there are (without decoding them).
+    pub fn count_entities<T: Entity>(&self) -> usize {
+        self.0.scan_prefix(T::key_kind().prefix()).keys().count()
     }
 
     pub fn list_entities_print_error<T: Entity>(&self) -> impl Iterator<Item = (T::Key, T)> {
         self.list_entities().filter_map(|entity| match entity {
             Ok(entity) => Some(entity),
             Err(e) => {
-                eprintln!("Error retreiving an {}: {}", T::name(), e);
+                tracing::error!("Error retreiving an {}: {}", T::name(), e);
                 None
             }
         })
@@ -293,4 +1525,132 @@ mod test {
             vec![(info1.id.clone(), info1), (info2.id.clone(), info2)]
         );
     }
+
+    #[test]
+    fn pages_of_entities() {
+        let db = BetDatabase::test_new();
+        for id in &["a.test", "b.test", "c.test"] {
+            let mut info = OracleInfo::test_oracle_info();
+            info.id = id.to_string().into();
+            db.insert_oracle_info(info).unwrap();
+        }
+        let all = db
+            .list_entities::<OracleInfo>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(db.count_entities::<OracleInfo>(), 3);
+        assert_eq!(
+            db.list_entities_page::<OracleInfo>(1, Some(1))
+                .collect::<Vec<_>>(),
+            all[1..2].to_vec()
+        );
+        assert_eq!(
+            db.list_entities_page::<OracleInfo>(1, None)
+                .collect::<Vec<_>>(),
+            all[1..].to_vec()
+        );
+    }
+
+    #[test]
+    fn integrity_finds_orphaned_conflicts() {
+        let db = BetDatabase::test_new();
+        db.insert_oracle_info(OracleInfo::test_oracle_info())
+            .unwrap();
+        assert_eq!(db.check_integrity().unwrap(), (1, vec![]));
+
+        let txid = Txid::default();
+        db.insert_conflict(
+            txid,
+            SeenConflict {
+                bet_id: 3,
+                first_seen: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+                by_counterparty: false,
+            },
+        )
+        .unwrap();
+        let (n_entries, problems) = db.check_integrity().unwrap();
+        assert_eq!(n_entries, 2);
+        assert_eq!(
+            problems,
+            vec![IntegrityProblem::Orphaned {
+                key: MapKey::Conflict(txid),
+                bet_id: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn checksums_catch_entries_gun_did_not_write() {
+        let db = BetDatabase::test_new();
+        let raw_key = |name: &str| VersionedKey::from(MapKey::ChangeIndex(name.into())).to_bytes();
+        for name in &["a", "a", "b", "c"] {
+            db.next_change_index(name).unwrap();
+        }
+        db.insert_oracle_info(OracleInfo::test_oracle_info())
+            .unwrap();
+        let report = db.verify_checksums().unwrap();
+        assert_eq!((report.checked, report.added), (3, 0));
+        assert_eq!(report.problems, vec![]);
+
+        // written behind gun's back or by a gun from before checksums
+        db.0.insert(raw_key("a"), b"{\"next\":7}".to_vec()).unwrap();
+        db.0.remove(raw_key("b")).unwrap();
+        db.0.insert(raw_key("d"), b"{\"next\":1}".to_vec()).unwrap();
+        let report = db.verify_checksums().unwrap();
+        assert_eq!((report.checked, report.added), (2, 1));
+        assert_eq!(
+            report.problems,
+            vec![
+                ChecksumProblem::Changed { key: raw_key("a") },
+                ChecksumProblem::Missing { key: raw_key("b") },
+            ]
+        );
+
+        // removing an entry through gun removes its checksum
+        db.remove_entity::<ChangeIndex>("c".into()).unwrap();
+        let report = db.verify_checksums().unwrap();
+        assert_eq!((report.checked, report.added), (2, 0));
+        assert_eq!(report.problems.len(), 2);
+        // and restoring it from elsewhere writes one
+        db.merge_raw_entry(&raw_key("a"), b"{\"next\":2}", true)
+            .unwrap();
+        assert_eq!(db.verify_checksums().unwrap().problems.len(), 1);
+    }
+
+    #[test]
+    fn undo_puts_back_what_was_there() {
+        let db = BetDatabase::test_new();
+        let outpoint = OutPoint::default();
+        let label = |db: &BetDatabase| {
+            db.get_entity::<AddressLabel>(Script::new())
+                .unwrap()
+                .map(|label| label.label)
+        };
+        db.record_undo("label".into(), vec![MapKey::AddressLabel(Script::new())])
+            .unwrap();
+        db.set_address_label(Script::new(), "first".into()).unwrap();
+        db.record_undo("relabel".into(), vec![MapKey::AddressLabel(Script::new())])
+            .unwrap();
+        db.set_address_label(Script::new(), "second".into())
+            .unwrap();
+        db.record_undo("freeze".into(), vec![MapKey::Frozen(outpoint)])
+            .unwrap();
+        db.freeze_utxo(outpoint).unwrap();
+
+        assert_eq!(db.undo_last().unwrap().unwrap().description, "freeze");
+        assert_eq!(db.frozen_utxos().unwrap(), vec![]);
+        assert_eq!(db.undo_last().unwrap().unwrap().description, "relabel");
+        assert_eq!(label(&db), Some("first".to_string()));
+        assert_eq!(db.undo_last().unwrap().unwrap().description, "label");
+        assert_eq!(label(&db), None);
+        assert_eq!(db.undo_last().unwrap(), None);
+        assert_eq!(db.verify_checksums().unwrap().problems, vec![]);
+
+        for i in 0..UNDO_HISTORY + 5 {
+            db.record_undo(i.to_string(), vec![]).unwrap();
+        }
+        let history = db.undo_history().unwrap();
+        assert_eq!(history.len(), UNDO_HISTORY);
+        assert_eq!(history[0].1.description, "5");
+    }
 }
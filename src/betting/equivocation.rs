@@ -0,0 +1,127 @@
+use crate::betting::*;
+use anyhow::anyhow;
+use olivia_core::{EventId, OracleId};
+
+/// Two attestations by an oracle to different outcomes of the same event, each with the
+/// announcement the oracle signed for it. Anyone who knows the oracle's keys can check it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EquivocationProof {
+    pub oracle: OracleInfo,
+    pub event_id: EventId,
+    pub responses: [EventResponse; 2],
+}
+
+impl EquivocationProof {
+    /// Finds two valid attestations to different outcomes among those we've seen.
+    pub fn from_seen(
+        oracle: OracleInfo,
+        event_id: EventId,
+        seen: &SeenAttestations,
+    ) -> Option<Self> {
+        let valid = seen
+            .responses
+            .iter()
+            .filter(|response| verify_response(&oracle, &event_id, response).is_ok())
+            .collect::<Vec<_>>();
+        let (first, second) = valid.iter().enumerate().find_map(|(i, first)| {
+            valid[i + 1..]
+                .iter()
+                .find(|second| outcome(second) != outcome(first))
+                .map(|second| (*first, *second))
+        })?;
+        Some(EquivocationProof {
+            oracle,
+            event_id,
+            responses: [first.clone(), second.clone()],
+        })
+    }
+
+    pub fn verify(&self) -> anyhow::Result<()> {
+        for response in &self.responses {
+            verify_response(&self.oracle, &self.event_id, response)?;
+        }
+        if outcome(&self.responses[0]) == outcome(&self.responses[1]) {
+            return Err(anyhow!("both attestations are to the same outcome"));
+        }
+        Ok(())
+    }
+}
+
+fn outcome(response: &EventResponse) -> Option<&str> {
+    response
+        .attestation
+        .as_ref()
+        .map(|attestation| attestation.outcome.as_str())
+}
+
+/// Checks the announcement in `response` is signed by the oracle and the attestation is valid for
+/// it.
+pub fn verify_response(
+    oracle: &OracleInfo,
+    event_id: &EventId,
+    response: &EventResponse,
+) -> anyhow::Result<OracleEvent> {
+    let oracle_event = response
+        .announcement
+        .verify_against_id(event_id, &oracle.oracle_keys.announcement)
+        .ok_or(anyhow!("the announcement isn't signed by {}", oracle.id))?;
+    let attestation = response
+        .attestation
+        .as_ref()
+        .ok_or(anyhow!("there is no attestation"))?;
+    if attestation
+        .verify_olivia_v1_attestation(&oracle_event, &oracle.oracle_keys)
+        .is_err()
+    {
+        return Err(anyhow!(
+            "the attestation to {} isn't valid",
+            attestation.outcome
+        ));
+    }
+    Ok(oracle_event)
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Keeps the attestation in `response` (if it's valid) so we notice if the oracle ever attests
+    /// to a different outcome and can prove it.
+    pub fn remember_attestation(
+        &self,
+        oracle_id: &OracleId,
+        event_id: &EventId,
+        response: &EventResponse,
+    ) -> anyhow::Result<()> {
+        if response.attestation.is_none() {
+            return Ok(());
+        }
+        let oracle = match self.bet_db().get_entity::<OracleInfo>(oracle_id.clone())? {
+            Some(oracle) => oracle,
+            None => return Ok(()),
+        };
+        if let Err(e) = verify_response(&oracle, event_id, response) {
+            tracing::warn!(%oracle_id, %event_id, "ignoring invalid attestation: {}", e);
+            return Ok(());
+        }
+        let seen = self
+            .bet_db()
+            .record_attestation(oracle_id, event_id, response.clone())?;
+        let outcomes = seen.outcomes();
+        if outcomes.len() > 1 {
+            self.alert(
+                "oracle-equivocation",
+                &format!(
+                    "oracle {} attested to {} for {}. Run `gun bet oracle prove-equivocation {}{}` to get a proof",
+                    oracle_id,
+                    outcomes.join(" and "),
+                    event_id,
+                    oracle_id,
+                    event_id
+                ),
+            );
+        }
+        Ok(())
+    }
+}
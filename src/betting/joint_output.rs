@@ -36,6 +36,14 @@ pub struct JointOutput {
     pub output_keys: [Point; 2],
     pub my_key: Either<Scalar>,
     pub swapped: bool,
+    /// The offline key that has to sign along with each output key if that side has one (see
+    /// [`crate::betting::BetCosigner`])
+    #[serde(default, skip_serializing_if = "no_cosigners")]
+    pub cosigners: [Option<Point>; 2],
+}
+
+fn no_cosigners(cosigners: &[Option<Point>; 2]) -> bool {
+    cosigners.iter().all(Option::is_none)
 }
 
 impl JointOutput {
@@ -90,28 +98,25 @@ impl JointOutput {
             output_keys: output_keys.try_into().unwrap(),
             my_key,
             swapped: swap_points,
+            cosigners: [None, None],
         }
     }
 
-    pub fn policy(&self) -> Policy<bitcoin::PublicKey> {
-        let keys = &match self.swapped {
-            false => self.output_keys,
-            true => [self.output_keys[1], self.output_keys[0]],
-        };
+    /// Makes each side's output key need its cosigner's signature too (proposal then offer).
+    pub fn with_cosigners(mut self, cosigners: [Option<Point>; 2]) -> Self {
+        self.cosigners = cosigners;
+        self
+    }
 
-        Policy::<bitcoin::PublicKey>::Or(
-            keys.iter()
-                .map(|key| {
-                    (
-                        1,
-                        Policy::Key(PublicKey {
-                            compressed: true,
-                            key: (*key).into(),
-                        }),
-                    )
-                })
-                .collect(),
-        )
+    pub fn my_cosigner(&self) -> Option<&Point> {
+        match self.my_key {
+            Either::Left(_) => self.cosigners[0].as_ref(),
+            Either::Right(_) => self.cosigners[1].as_ref(),
+        }
+    }
+
+    pub fn policy(&self) -> Policy<bitcoin::PublicKey> {
+        policy_of(self.output_keys, self.cosigners, self.swapped)
     }
 
     // pub fn compute_privkey<B: Blockchain>(
@@ -157,6 +162,60 @@ impl JointOutput {
     }
 
     pub fn descriptor(&self) -> Descriptor<bitcoin::PublicKey> {
-        Descriptor::Wsh(Wsh::new(self.policy().compile().unwrap()).unwrap())
+        descriptor_of(self.output_keys, self.cosigners, self.swapped)
     }
 }
+
+fn policy_of(
+    output_keys: [Point; 2],
+    cosigners: [Option<Point>; 2],
+    swapped: bool,
+) -> Policy<bitcoin::PublicKey> {
+    let sides = [
+        (output_keys[0], cosigners[0]),
+        (output_keys[1], cosigners[1]),
+    ];
+    let sides = &match swapped {
+        false => sides,
+        true => [sides[1], sides[0]],
+    };
+    let key = |key: Point| {
+        Policy::Key(PublicKey {
+            compressed: true,
+            key: key.into(),
+        })
+    };
+
+    Policy::<bitcoin::PublicKey>::Or(
+        sides
+            .iter()
+            .map(|(output_key, cosigner)| {
+                (
+                    1,
+                    match cosigner {
+                        Some(cosigner) => Policy::And(vec![key(*output_key), key(*cosigner)]),
+                        None => key(*output_key),
+                    },
+                )
+            })
+            .collect(),
+    )
+}
+
+/// The descriptor of a bet output with `output_keys` and `cosigners`. Unlike
+/// [`JointOutput::descriptor`] it doesn't need either side's secret key (e.g. for checking a
+/// [`BetReceipt`]).
+pub fn descriptor_of(
+    output_keys: [Point; 2],
+    cosigners: [Option<Point>; 2],
+    swapped: bool,
+) -> Descriptor<bitcoin::PublicKey> {
+    Descriptor::Wsh(
+        Wsh::new(
+            policy_of(output_keys, cosigners, swapped)
+                .compile()
+                .unwrap(),
+        )
+        .unwrap(),
+    )
+}
@@ -1,20 +1,28 @@
 mod bet;
+mod cosigner;
 mod database;
+mod equivocation;
 mod joint_output;
 mod offer;
 mod party;
 mod proposal;
 mod randomize;
+mod receipt;
+mod simulate;
 mod witness;
 
 pub use bet::*;
+pub use cosigner::*;
 pub use database::*;
+pub use equivocation::*;
 pub use joint_output::*;
 pub use offer::*;
 use olivia_secp256k1::fun::{marker::EvenY, Point};
 pub use party::*;
 pub use proposal::*;
 pub use randomize::*;
+pub use receipt::*;
+pub use simulate::*;
 pub use witness::*;
 
 pub type OracleInfo = olivia_core::OracleInfo<olivia_secp256k1::Secp256k1>;
@@ -14,11 +14,18 @@ pub struct Offer {
     pub choose_right: bool,
     #[serde(with = "bitcoin::util::amount::serde::as_sat")]
     pub value: Amount,
+    /// The key that has to sign with the offerer's to claim (see [`BetCosigner`]). It's sent next
+    /// to the offer rather than in it (see [`Plaintext`]) so offers without one are the same as
+    /// they always were.
+    #[serde(skip)]
+    pub cosigner: Option<Point>,
 }
 
 pub struct ValidatedOffer {
     pub bet_id: BetId,
     pub bet: Bet,
+    pub proposal: Proposal,
+    pub offer_public_key: Point<EvenY>,
 }
 
 impl ValidatedOffer {
@@ -77,6 +84,7 @@ impl Ciphertext {
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq)]
+#[serde(from = "PlaintextWire", into = "PlaintextWire")]
 pub enum Plaintext {
     Offerv1 {
         offer: Offer,
@@ -85,6 +93,55 @@ pub enum Plaintext {
     Messagev1(String),
 }
 
+/// How a [`Plaintext`] is encoded. An offer with a cosigner gets a variant of its own which
+/// clients that don't know about cosigners fail to decrypt rather than taking it without one.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum PlaintextWire {
+    Offerv1 {
+        offer: Offer,
+        message: Option<String>,
+    },
+    Messagev1(String),
+    CosignedOfferv1 {
+        offer: Offer,
+        message: Option<String>,
+        cosigner: Point,
+    },
+}
+
+impl From<PlaintextWire> for Plaintext {
+    fn from(wire: PlaintextWire) -> Self {
+        match wire {
+            PlaintextWire::Offerv1 { offer, message } => Plaintext::Offerv1 { offer, message },
+            PlaintextWire::Messagev1(message) => Plaintext::Messagev1(message),
+            PlaintextWire::CosignedOfferv1 {
+                mut offer,
+                message,
+                cosigner,
+            } => {
+                offer.cosigner = Some(cosigner);
+                Plaintext::Offerv1 { offer, message }
+            }
+        }
+    }
+}
+
+impl From<Plaintext> for PlaintextWire {
+    fn from(plaintext: Plaintext) -> Self {
+        match plaintext {
+            Plaintext::Offerv1 { offer, message } => match offer.cosigner {
+                Some(cosigner) => PlaintextWire::CosignedOfferv1 {
+                    offer,
+                    message,
+                    cosigner,
+                },
+                None => PlaintextWire::Offerv1 { offer, message },
+            },
+            Plaintext::Messagev1(message) => PlaintextWire::Messagev1(message),
+        }
+    }
+}
+
 impl Plaintext {
     pub fn into_offer(self) -> Offer {
         match self {
@@ -136,6 +193,7 @@ mod test {
                 change: None,
                 choose_right: false,
                 value: Amount::from_str_with_denomination("1 BTC").unwrap(),
+                cosigner: None,
             },
         )
     }
@@ -161,6 +219,31 @@ mod test {
         );
     }
 
+    #[test]
+    fn offer_with_cosigner() {
+        let (public_key, mut offer) = test_offer();
+        let plain = crate::encode::serialize(&Plaintext::Offerv1 {
+            offer: offer.clone(),
+            message: None,
+        });
+        offer.cosigner = Some(Point::random(&mut rand::thread_rng()));
+        let mut cipher1 = ChaCha20::new(&[2u8; 32].into(), &[2u8; 12].into());
+        let mut cipher2 = ChaCha20::new(&[2u8; 32].into(), &[2u8; 12].into());
+        let encrypted_offer = Ciphertext::create(
+            public_key,
+            &mut cipher1,
+            Plaintext::Offerv1 {
+                offer: offer.clone(),
+                message: None,
+            },
+        );
+        assert_ne!(encrypted_offer.encrypted_bytes.len(), plain.len());
+        assert_eq!(
+            encrypted_offer.decrypt(&mut cipher2).unwrap().into_offer(),
+            offer
+        );
+    }
+
     #[test]
     fn offer_with_message_attached() {
         let (public_key, offer) = test_offer();
@@ -0,0 +1,172 @@
+use crate::betting::*;
+use bdk::{
+    bitcoin::{Amount, Txid},
+    blockchain::Blockchain,
+    KeychainKind,
+};
+use std::collections::{BTreeMap, HashMap};
+
+/// Coinbase outputs can't be spent until they have this many confirmations.
+const COINBASE_MATURITY: u32 = 100;
+
+/// The buckets [`Party::balance`] sorts our coins into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BalanceCategory {
+    /// Can be used by sends, splits and bets right now
+    Spendable,
+    /// Sent to us but without enough confirmations yet
+    UnconfirmedIncoming,
+    /// Our side of bets whose transaction has enough confirmations
+    InBet,
+    /// Our side of bets whose transaction doesn't have enough confirmations yet
+    InBetUnconfirmed,
+    /// Bets we've won but not claimed
+    Unclaimed,
+    /// Coins set aside for proposals, offers and transactions waiting for signatures
+    Reserved,
+    /// Coins the user has frozen
+    Frozen,
+    /// Coinbase outputs that can't be spent yet
+    Immature,
+}
+
+impl BalanceCategory {
+    pub const ALL: [BalanceCategory; 8] = [
+        BalanceCategory::Spendable,
+        BalanceCategory::UnconfirmedIncoming,
+        BalanceCategory::InBet,
+        BalanceCategory::InBetUnconfirmed,
+        BalanceCategory::Unclaimed,
+        BalanceCategory::Reserved,
+        BalanceCategory::Frozen,
+        BalanceCategory::Immature,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        use BalanceCategory::*;
+        match self {
+            Spendable => "spendable",
+            UnconfirmedIncoming => "unconfirmed-incoming",
+            InBet => "in-bet",
+            InBetUnconfirmed => "in-bet-unconfirmed",
+            Unclaimed => "unclaimed",
+            Reserved => "reserved",
+            Frozen => "frozen",
+            Immature => "immature",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CategoryTotal {
+    pub value: Amount,
+    /// The number of coins (or bets) making up the value
+    pub count: usize,
+}
+
+impl Default for CategoryTotal {
+    fn default() -> Self {
+        Self {
+            value: Amount::ZERO,
+            count: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Balance {
+    pub categories: BTreeMap<BalanceCategory, CategoryTotal>,
+}
+
+impl Balance {
+    fn add(&mut self, category: BalanceCategory, value: Amount) {
+        let total = self.categories.entry(category).or_default();
+        total.value += value;
+        total.count += 1;
+    }
+
+    pub fn get(&self, category: BalanceCategory) -> CategoryTotal {
+        self.categories.get(&category).cloned().unwrap_or_default()
+    }
+
+    pub fn total(&self) -> Amount {
+        self.categories
+            .values()
+            .fold(Amount::ZERO, |total, category| total + category.value)
+    }
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Sorts everything we own into exactly one [`BalanceCategory`].
+    pub fn balance(&self) -> anyhow::Result<Balance> {
+        let targets = self.settings.confirmations;
+        let tip_height = match self.bet_db.tip_height()? {
+            Some(tip_height) => tip_height,
+            None => self.wallet.client().get_height()?,
+        };
+        let mut balance = Balance::default();
+
+        for (_, bet_state) in self.bet_db.list_entities_print_error::<BetState>() {
+            match bet_state {
+                BetState::Included { bet, height } => {
+                    if confirmations(height, tip_height) >= targets.bet_funding {
+                        balance.add(BalanceCategory::InBet, bet.local_value)
+                    } else {
+                        balance.add(BalanceCategory::InBetUnconfirmed, bet.local_value)
+                    }
+                }
+                BetState::Won { bet, .. } => {
+                    balance.add(BalanceCategory::Unclaimed, bet.joint_output_value)
+                }
+                _ => {}
+            }
+        }
+
+        let txs = self
+            .wallet
+            .list_transactions(true)?
+            .into_iter()
+            .map(|tx_details| {
+                let is_coinbase = tx_details
+                    .transaction
+                    .as_ref()
+                    .map(|tx| tx.is_coin_base())
+                    .unwrap_or(false);
+                (
+                    tx_details.txid,
+                    (
+                        tx_details.confirmation_time.map(|time| time.height),
+                        is_coinbase,
+                    ),
+                )
+            })
+            .collect::<HashMap<Txid, _>>();
+        let reservations = self.bet_db.reservations()?;
+
+        for utxo in self.wallet.list_unspent()? {
+            let value = Amount::from_sat(utxo.txout.value);
+            let (height, is_coinbase) = txs.get(&utxo.outpoint.txid).cloned().unwrap_or_default();
+            let confirmations = confirmations(height, tip_height);
+            let category = match reservations.get(&utxo.outpoint) {
+                Some(Reservation::Frozen) => BalanceCategory::Frozen,
+                Some(_) => BalanceCategory::Reserved,
+                None if is_coinbase && confirmations < COINBASE_MATURITY => {
+                    BalanceCategory::Immature
+                }
+                // our own change is as good as confirmed
+                None if confirmations < targets.incoming
+                    && utxo.keychain == KeychainKind::External =>
+                {
+                    BalanceCategory::UnconfirmedIncoming
+                }
+                None => BalanceCategory::Spendable,
+            };
+            balance.add(category, value);
+        }
+
+        Ok(balance)
+    }
+}
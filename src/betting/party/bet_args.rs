@@ -1,7 +1,7 @@
 use crate::{betting::*, ValueChoice};
 use anyhow::{anyhow, Context};
 use bdk::{
-    bitcoin::Amount,
+    bitcoin::{Address, Amount},
     database::BatchDatabase,
     wallet::{coin_selection::CoinSelectionAlgorithm, tx_builder::TxBuilderContext},
     TxBuilder,
@@ -13,7 +13,16 @@ pub struct BetArgs<'a, 'b> {
     pub value: ValueChoice,
     pub may_overlap: &'a [BetId],
     pub must_overlap: &'b [BetId],
+    /// Only spend the coins of the `must_overlap` bets and nothing else
+    pub only_overlap: bool,
     pub tags: Vec<String>,
+    /// Whether the bet transaction signals RBF. `None` means use the `bets` RBF setting.
+    pub rbf: Option<bool>,
+    /// Claim the winnings to this address instead of the wallet. `None` means use the `claim-to`
+    /// setting.
+    pub claim_to: Option<Address>,
+    /// Make the bet even if it's more than the `bet-limits` setting allows
+    pub over_limits: bool,
 }
 
 impl Default for BetArgs<'_, '_> {
@@ -23,7 +32,11 @@ impl Default for BetArgs<'_, '_> {
             value: ValueChoice::Amount(Amount::ZERO),
             may_overlap: &EMPTY,
             must_overlap: &EMPTY,
+            only_overlap: false,
             tags: vec![],
+            rbf: None,
+            claim_to: None,
+            over_limits: false,
         }
     }
 }
@@ -41,6 +54,13 @@ impl BetArgs<'_, '_> {
         builder: &mut TxBuilder<B, D, Cs, Ctx>,
     ) -> anyhow::Result<()> {
         builder.unspendable(bet_db.currently_used_utxos(self.may_overlap)?);
+        for frozen in bet_db.frozen_utxos()? {
+            builder.add_unspendable(frozen);
+        }
+        // betting with mixed coins would tie them to the rest of the bet's coins
+        for (outpoint, _) in bet_db.coinjoin_outputs()? {
+            builder.add_unspendable(outpoint);
+        }
         for bet_id in self.must_overlap {
             let bet = bet_db.get_entity::<BetState>(*bet_id)?.ok_or(anyhow!(
                 "bet {} that we must overlap with does not exist",
@@ -52,6 +72,9 @@ impl BetArgs<'_, '_> {
                 })?;
             }
         }
+        if self.only_overlap {
+            builder.manually_selected_only();
+        }
 
         Ok(())
     }
@@ -0,0 +1,149 @@
+use crate::{betting::*, exit_code::ErrorKind};
+use bdk::bitcoin::Amount;
+
+/// How much of the wallet bets are allowed to risk e.g. `{ "max-bet-percent": 10,
+/// "max-at-risk-percent": 40 }`. Neither is checked unless it's set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct BetLimits {
+    /// The most of the spendable balance one bet can risk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bet_percent: Option<u8>,
+    /// The most of the bankroll (what's spendable, reserved or in bets) that all our bets
+    /// together can risk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_at_risk_percent: Option<u8>,
+}
+
+impl BetLimits {
+    pub fn is_set(&self) -> bool {
+        self.max_bet_percent.is_some() || self.max_at_risk_percent.is_some()
+    }
+
+    /// The biggest bet that can be made when `spendable` can be bet, `at_risk` is already in bets,
+    /// proposals and offers and `bankroll` is everything bets could be made from. `None` if there's
+    /// no limit.
+    pub fn max_bet(&self, spendable: Amount, at_risk: Amount, bankroll: Amount) -> Option<Amount> {
+        let percent_of = |amount: Amount, percent: u8| {
+            Amount::from_sat((amount.as_sat() as u128 * percent.min(100) as u128 / 100) as u64)
+        };
+        let single = self
+            .max_bet_percent
+            .map(|percent| percent_of(spendable, percent));
+        let total = self.max_at_risk_percent.map(|percent| {
+            percent_of(bankroll, percent)
+                .checked_sub(at_risk)
+                .unwrap_or(Amount::ZERO)
+        });
+        match (single, total) {
+            (Some(single), Some(total)) => Some(single.min(total)),
+            (single, total) => single.or(total),
+        }
+    }
+}
+
+/// What [`Party::bet_limit`] worked the limit out from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BetLimit {
+    pub spendable: Amount,
+    /// Our side of bets that haven't been decided and of proposals and offers that haven't been
+    /// taken
+    pub at_risk: Amount,
+    pub bankroll: Amount,
+    /// `None` if no limit is set
+    pub max_bet: Option<Amount>,
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// The biggest bet the `bet-limits` setting allows right now.
+    pub fn bet_limit(&self) -> anyhow::Result<BetLimit> {
+        let balance = self.balance()?;
+        let spendable = balance.get(BalanceCategory::Spendable).value;
+        let mut at_risk = balance.get(BalanceCategory::InBet).value
+            + balance.get(BalanceCategory::InBetUnconfirmed).value;
+        for (_, bet_state) in self.bet_db.list_entities_print_error::<BetState>() {
+            match bet_state {
+                BetState::Proposed { local_proposal } => at_risk += local_proposal.proposal.value,
+                BetState::Offered { bet, .. } => at_risk += bet.local_value,
+                _ => {}
+            }
+        }
+        // the coins of proposals and offers are reserved so they're already in the bankroll
+        let bankroll = spendable
+            + balance.get(BalanceCategory::Reserved).value
+            + balance.get(BalanceCategory::InBet).value
+            + balance.get(BalanceCategory::InBetUnconfirmed).value;
+        Ok(BetLimit {
+            spendable,
+            at_risk,
+            bankroll,
+            max_bet: self
+                .settings
+                .bet_limits
+                .max_bet(spendable, at_risk, bankroll),
+        })
+    }
+
+    /// Errors if betting `value` would go over the `bet-limits` setting.
+    pub(crate) fn check_bet_limits(&self, value: Amount, args: &BetArgs) -> anyhow::Result<()> {
+        if args.over_limits || !self.settings.bet_limits.is_set() {
+            return Ok(());
+        }
+        let limit = self.bet_limit()?;
+        match limit.max_bet {
+            Some(max_bet) if value > max_bet => Err(ErrorKind::Policy.error(format!(
+                "betting {} is more than the bet-limits setting allows -- the most you can bet now is {} ({} spendable, {} already at risk). Use --over-limits to bet it anyway.",
+                value,
+                max_bet,
+                limit.spendable,
+                limit.at_risk
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn max_bet_is_the_smaller_limit() {
+        let sats = Amount::from_sat;
+        let limits = BetLimits {
+            max_bet_percent: Some(10),
+            max_at_risk_percent: Some(40),
+        };
+        assert_eq!(
+            limits.max_bet(sats(1_000_000), sats(0), sats(1_000_000)),
+            Some(sats(100_000))
+        );
+        assert_eq!(
+            limits.max_bet(sats(700_000), sats(300_000), sats(1_000_000)),
+            Some(sats(70_000))
+        );
+        assert_eq!(
+            limits.max_bet(sats(650_000), sats(350_000), sats(1_000_000)),
+            Some(sats(50_000))
+        );
+        assert_eq!(
+            limits.max_bet(sats(500_000), sats(500_000), sats(1_000_000)),
+            Some(sats(0))
+        );
+        assert_eq!(
+            BetLimits::default().max_bet(sats(1_000_000), sats(0), sats(1_000_000)),
+            None
+        );
+        let only_total = BetLimits {
+            max_bet_percent: None,
+            max_at_risk_percent: Some(40),
+        };
+        assert_eq!(
+            only_total.max_bet(sats(800_000), sats(200_000), sats(1_000_000)),
+            Some(sats(200_000))
+        );
+    }
+}
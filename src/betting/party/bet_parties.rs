@@ -0,0 +1,111 @@
+use crate::{betting::*, exit_code::ErrorKind};
+use olivia_secp256k1::fun::{marker::EvenY, Point};
+
+/// Which oracles and counterparties bets can be made with e.g. `{ "allow-oracles": ["h00.ooo"],
+/// "deny-counterparties": ["<public key>"] }`. Oracles are given by their id or one of their public
+/// keys and counterparties by the public key they bet with. Proposals, offers and taking offers are
+/// all refused if they'd involve someone who isn't allowed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct BetParties {
+    /// Only bet on what these oracles attest to. Any oracle can be used if it isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_oracles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny_oracles: Vec<String>,
+    /// Only bet against these keys. Anyone can be bet against if it isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_counterparties: Option<Vec<Point<EvenY>>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny_counterparties: Vec<Point<EvenY>>,
+}
+
+impl BetParties {
+    /// Errors if `oracle_id` (with keys from `oracle_info` if we have it) can't be bet on.
+    pub fn check_oracle(
+        &self,
+        oracle_id: &str,
+        oracle_info: Option<&OracleInfo>,
+    ) -> anyhow::Result<()> {
+        let mut names = vec![oracle_id.to_string()];
+        if let Some(oracle_info) = oracle_info {
+            names.push(oracle_info.oracle_keys.announcement.to_string());
+            names.extend(
+                oracle_info
+                    .oracle_keys
+                    .olivia_v1
+                    .as_ref()
+                    .map(ToString::to_string),
+            );
+        }
+        let listed = |list: &[String]| {
+            list.iter()
+                .any(|entry| names.iter().any(|name| entry.eq_ignore_ascii_case(name)))
+        };
+        if listed(&self.deny_oracles) {
+            return Err(ErrorKind::Policy.error(format!(
+                "oracle {} is in deny-oracles in the bet-parties setting",
+                oracle_id
+            )));
+        }
+        match &self.allow_oracles {
+            Some(allowed) if !listed(allowed) => Err(ErrorKind::Policy.error(format!(
+                "oracle {} isn't in allow-oracles in the bet-parties setting",
+                oracle_id
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Errors if the counterparty betting with `public_key` can't be bet against.
+    pub fn check_counterparty(&self, public_key: &Point<EvenY>) -> anyhow::Result<()> {
+        if self.deny_counterparties.contains(public_key) {
+            return Err(ErrorKind::Policy.error(format!(
+                "counterparty {} is in deny-counterparties in the bet-parties setting",
+                public_key
+            )));
+        }
+        match &self.allow_counterparties {
+            Some(allowed) if !allowed.contains(public_key) => {
+                Err(ErrorKind::Policy.error(format!(
+                    "counterparty {} isn't in allow-counterparties in the bet-parties setting",
+                    public_key
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lists_are_checked() {
+        let (oracle, other) = ("h00.ooo", "oracle.example.com");
+        let key = crate::placeholder_point();
+
+        let anyone = BetParties::default();
+        assert!(anyone.check_oracle(oracle, None).is_ok());
+        assert!(anyone.check_counterparty(&key).is_ok());
+
+        let only = BetParties {
+            allow_oracles: Some(vec!["H00.ooo".into()]),
+            allow_counterparties: Some(vec![]),
+            ..Default::default()
+        };
+        assert!(only.check_oracle(oracle, None).is_ok());
+        assert!(only.check_oracle(other, None).is_err());
+        assert!(only.check_counterparty(&key).is_err());
+
+        let denied = BetParties {
+            deny_oracles: vec!["h00.ooo".into()],
+            deny_counterparties: vec![key],
+            ..Default::default()
+        };
+        assert!(denied.check_oracle(oracle, None).is_err());
+        assert!(denied.check_oracle(other, None).is_ok());
+        assert!(denied.check_counterparty(&key).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    betting::*,
+    keychain::KeyPair,
+    nostr::{self, Event},
+};
+use anyhow::anyhow;
+use bdk::bitcoin::hashes::{sha256, Hash, HashEngine};
+use chacha20::{
+    cipher::{NewCipher, StreamCipher},
+    ChaCha20, Key, Nonce,
+};
+use olivia_core::chrono::{NaiveDateTime, Utc};
+
+use super::Party;
+
+/// Chat messages are sent as nostr "encrypted direct messages" to the key the other side used in
+/// the bet though they are encrypted differently to NIP-04 ones.
+pub const CHAT_KIND: u64 = 4;
+
+/// Encrypts `text` to `hex(nonce || ciphertext)` where the nonce is made from the key, time and
+/// text so it never repeats for different messages.
+fn seal(key: &[u8; 32], created_at: u64, text: &str) -> String {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&key[..]);
+    engine.input(&created_at.to_be_bytes());
+    engine.input(text.as_bytes());
+    let nonce = sha256::Hash::from_engine(engine);
+    let mut bytes = text.as_bytes().to_vec();
+    ChaCha20::new(Key::from_slice(&key[..]), Nonce::from_slice(&nonce[..12]))
+        .apply_keystream(&mut bytes);
+    let mut content = nonce[..12].to_vec();
+    content.extend(bytes);
+    crate::hex::encode(&content)
+}
+
+fn open(key: &[u8; 32], content: &str) -> anyhow::Result<String> {
+    let content = crate::hex::decode(content).map_err(|_| anyhow!("message isn't hex"))?;
+    if content.len() < 12 {
+        return Err(anyhow!("message is too short"));
+    }
+    let (nonce, ciphertext) = content.split_at(12);
+    let mut bytes = ciphertext.to_vec();
+    ChaCha20::new(Key::from_slice(&key[..]), Nonce::from_slice(nonce)).apply_keystream(&mut bytes);
+    Ok(String::from_utf8(bytes)?)
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    fn chat_keys(&self, chat: &BetChat) -> (KeyPair, [u8; 32]) {
+        let keypair = match chat.i_proposed {
+            true => self.keychain.get_key_for_proposal(&chat.proposal),
+            false => self.keychain.keypair_for_offer(&chat.proposal),
+        };
+        let key = crate::ecdh::shared_key(&keypair, &chat.remote_public_key, b"gun-chat");
+        (keypair, key)
+    }
+
+    fn get_chat(&self, bet_id: BetId) -> anyhow::Result<BetChat> {
+        self.bet_db.get_entity::<BetChat>(bet_id)?.ok_or(anyhow!(
+            "bet {} doesn't have a chat. Only bets made from an offer have one.",
+            bet_id
+        ))
+    }
+
+    /// Sends `text` to the other side of the bet through every relay that takes it.
+    pub fn send_chat_message(
+        &self,
+        bet_id: BetId,
+        text: String,
+        relays: &[String],
+    ) -> anyhow::Result<ChatMessage> {
+        if relays.is_empty() {
+            return Err(anyhow!("no nostr relays are configured"));
+        }
+        let chat = self.get_chat(bet_id)?;
+        let (keypair, key) = self.chat_keys(&chat);
+        let created_at = Utc::now().timestamp() as u64;
+        let event = Event::sign(
+            &keypair,
+            created_at,
+            CHAT_KIND,
+            vec![vec![
+                "p".into(),
+                nostr::public_key_hex(&chat.remote_public_key),
+            ]],
+            seal(&key, created_at, &text),
+        );
+
+        let mut errors = vec![];
+        for relay in relays {
+            if let Err(e) = nostr::publish(relay, &event) {
+                tracing::warn!(%relay, "couldn't send chat message: {}", e);
+                errors.push(e);
+            }
+        }
+        if errors.len() == relays.len() {
+            return Err(anyhow!(
+                "no relay took the message: {}",
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        let message = ChatMessage {
+            id: event.id,
+            sent_at: NaiveDateTime::from_timestamp(created_at as i64, 0),
+            from_me: true,
+            text,
+        };
+        self.bet_db
+            .add_chat_messages(bet_id, vec![message.clone()])?;
+        Ok(message)
+    }
+
+    /// Gets the messages the other side has sent from the relays and returns how many were new.
+    pub fn fetch_chat_messages(&self, bet_id: BetId, relays: &[String]) -> anyhow::Result<usize> {
+        let chat = self.get_chat(bet_id)?;
+        let (keypair, key) = self.chat_keys(&chat);
+        let remote = nostr::public_key_hex(&chat.remote_public_key);
+        let filter = serde_json::json!({
+            "kinds": [CHAT_KIND],
+            "authors": [remote],
+            "#p": [nostr::public_key_hex(&keypair.public_key)],
+        });
+
+        let mut messages = vec![];
+        for relay in relays {
+            let events = match nostr::query(relay, filter.clone()) {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!(%relay, "couldn't get chat messages: {}", e);
+                    continue;
+                }
+            };
+            for event in events {
+                if event.pubkey != remote || event.verify().is_err() {
+                    continue;
+                }
+                match open(&key, &event.content) {
+                    Ok(text) => messages.push(ChatMessage {
+                        id: event.id,
+                        sent_at: NaiveDateTime::from_timestamp(event.created_at as i64, 0),
+                        from_me: false,
+                        text,
+                    }),
+                    Err(e) => {
+                        tracing::debug!(id = %event.id, "couldn't decrypt chat message: {}", e)
+                    }
+                }
+            }
+        }
+
+        self.bet_db.add_chat_messages(bet_id, messages)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = [7u8; 32];
+        let sealed = seal(&key, 1_600_000_000, "I'll claim tomorrow");
+        assert_eq!(open(&key, &sealed).unwrap(), "I'll claim tomorrow");
+        // the same text at another time doesn't look the same
+        assert_ne!(seal(&key, 1_600_000_001, "I'll claim tomorrow"), sealed);
+        assert!(open(&[8u8; 32], &sealed)
+            .map(|text| text != "I'll claim tomorrow")
+            .unwrap_or(true));
+    }
+}
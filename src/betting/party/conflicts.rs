@@ -0,0 +1,115 @@
+use crate::{audit::AuditOperation, betting::*, psbt_ext::PsbtFeeRate, FeeSpec};
+use bdk::bitcoin::{Amount, Txid};
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Remember that `conflict_txid` spends one of the inputs of a bet and raise an alarm the first
+    /// time we see the counterparty do it.
+    pub(crate) fn record_conflict(
+        &self,
+        bet_id: BetId,
+        conflict_txid: Txid,
+        i_intend_cancel: bool,
+        height: Option<u32>,
+    ) -> anyhow::Result<()> {
+        if self
+            .bet_db
+            .get_entity::<SeenConflict>(conflict_txid)?
+            .is_some()
+        {
+            return Ok(());
+        }
+        self.bet_db.insert_conflict(
+            conflict_txid,
+            SeenConflict {
+                bet_id,
+                first_seen: crate::clock::now(),
+                by_counterparty: !i_intend_cancel,
+            },
+        )?;
+
+        if !i_intend_cancel {
+            self.alert(
+                "counterparty-conflict",
+                &format!(
+                    "the counterparty to bet {} spent one of their inputs to it in {} ({}) -- they may be trying to back out of the bet",
+                    bet_id,
+                    conflict_txid,
+                    match height {
+                        Some(height) => format!("confirmed at height {}", height),
+                        None => "still in the mempool".to_string(),
+                    }
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    /// While our cancel transaction is unconfirmed the counterparty can still get the bet
+    /// transaction mined instead if it pays a higher fee. When `fee_bump_cancels` is set we replace
+    /// our cancel transactions so they always pay more than the bet they are canceling.
+    pub fn respond_to_conflicts(&self) -> anyhow::Result<()> {
+        if !self.settings.fee_bump_cancels {
+            return Ok(());
+        }
+
+        for (bet_id, bet_state) in self.bet_db.list_entities_print_error::<BetState>() {
+            let (bet, cancel_txid) = match bet_state {
+                BetState::Canceled {
+                    pre_cancel: BetOrProp::Bet(bet),
+                    cancel_txid,
+                    height: None,
+                    i_intend_cancel: true,
+                    ..
+                }
+                | BetState::Canceled {
+                    pre_cancel:
+                        BetOrProp::OfferedBet {
+                            bet: OfferedBet(bet),
+                            ..
+                        },
+                    cancel_txid,
+                    height: None,
+                    i_intend_cancel: true,
+                    ..
+                } => (bet, cancel_txid),
+                _ => continue,
+            };
+
+            let (bet_fee, _) = bet.psbt.fee();
+            let cancel_tx = match self.wallet.query_db(|db| db.get_tx(&cancel_txid, true))? {
+                Some(cancel_tx) => cancel_tx,
+                None => continue,
+            };
+            let cancel_fee = match cancel_tx.fee {
+                Some(fee) => Amount::from_sat(fee),
+                None => continue,
+            };
+            if cancel_fee > bet_fee {
+                continue;
+            }
+
+            // A replacement has to pay for its own relay on top of what it replaces (1 sat/vB).
+            let cancel_vsize = cancel_tx
+                .transaction
+                .as_ref()
+                .map(|tx| (tx.get_weight() as u64 + 3) / 4)
+                .unwrap_or(0);
+            let new_fee = bet_fee + Amount::from_sat(cancel_vsize);
+            if let Some(psbt) = self.generate_cancel_tx(&[bet_id], FeeSpec::Absolute(new_fee))? {
+                self.audit_psbt(AuditOperation::Broadcast, "cancel-bump", &psbt)?;
+                let tx = psbt.extract_tx();
+                let txid = tx.txid();
+                crate::read_only::broadcast(self.wallet.client(), tx)?;
+                tracing::warn!(
+                    "replaced cancel tx {} for bet {} with {} paying {} so the bet can't be mined in its place",
+                    cancel_txid, bet_id, txid, new_fee
+                );
+                self.take_next_action(bet_id, false)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use crate::betting::*;
+use anyhow::anyhow;
+use bdk::{
+    bitcoin::{util::psbt, Amount},
+    blockchain::{Blockchain, TransactionState, TxState},
+    database::BatchDatabase,
+};
+
+/// How many confirmations the coins the other side of a bet puts in need, depending on how big the
+/// bet is. A coin that isn't confirmed can be double spent which cancels the bet.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct CounterpartyConfirmations {
+    pub tiers: Vec<ConfirmationTier>,
+    /// Refuse to take or fund a bet that doesn't meet them instead of warning about it
+    pub refuse: bool,
+}
+
+/// Bets worth more than `above` (both sides together) need `confirmations` on the other side's
+/// coins.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfirmationTier {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub above: Amount,
+    pub confirmations: u32,
+}
+
+impl Default for CounterpartyConfirmations {
+    fn default() -> Self {
+        Self {
+            tiers: vec![ConfirmationTier {
+                above: Amount::from_sat(100_000),
+                confirmations: 1,
+            }],
+            refuse: false,
+        }
+    }
+}
+
+impl CounterpartyConfirmations {
+    pub fn required(&self, bet_value: Amount) -> u32 {
+        self.tiers
+            .iter()
+            .filter(|tier| bet_value > tier.above)
+            .map(|tier| tier.confirmations)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
+    /// Checks the other side's `inputs` to a bet worth `bet_value` have as many confirmations as
+    /// the `counterparty-confirmations` setting asks for.
+    pub fn check_counterparty_inputs(
+        &self,
+        inputs: &[psbt::Input],
+        bet_value: Amount,
+    ) -> anyhow::Result<()> {
+        let policy = &self.settings.counterparty_confirmations;
+        let required = policy.required(bet_value);
+        if required == 0 {
+            return Ok(());
+        }
+        let blockchain = self.wallet.client();
+        let tip_height = blockchain.get_height()?;
+
+        let mut short = vec![];
+        for input in inputs {
+            let tx = input
+                .non_witness_utxo
+                .as_ref()
+                .expect("we always look up the transaction of the other side's inputs");
+            let have = match blockchain.tx_state(tx)? {
+                TxState::Present { height } => confirmations(height, tip_height),
+                _ => 0,
+            };
+            if have < required {
+                short.push(format!("{} has {}", tx.txid(), have));
+            }
+        }
+        if short.is_empty() {
+            return Ok(());
+        }
+
+        let problem = format!(
+            "a bet of {} needs the other side's coins to have {} confirmations but {}",
+            bet_value,
+            required,
+            short.join(", ")
+        );
+        if policy.refuse {
+            return Err(anyhow!(problem));
+        }
+        tracing::warn!("{}", problem);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bigger_bets_need_more_confirmations() {
+        let policy = CounterpartyConfirmations {
+            tiers: vec![
+                ConfirmationTier {
+                    above: Amount::from_sat(100_000),
+                    confirmations: 1,
+                },
+                ConfirmationTier {
+                    above: Amount::from_sat(1_000_000),
+                    confirmations: 3,
+                },
+            ],
+            refuse: true,
+        };
+        assert_eq!(policy.required(Amount::from_sat(100_000)), 0);
+        assert_eq!(policy.required(Amount::from_sat(100_001)), 1);
+        assert_eq!(policy.required(Amount::from_sat(5_000_000)), 3);
+        assert_eq!(
+            CounterpartyConfirmations {
+                tiers: vec![],
+                refuse: false
+            }
+            .required(Amount::from_sat(5_000_000)),
+            0
+        );
+    }
+}
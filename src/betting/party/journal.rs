@@ -0,0 +1,74 @@
+use crate::betting::*;
+use bdk::blockchain::{EsploraBlockchain, TransactionState, TxState};
+
+use super::Party;
+
+/// What happened to an interrupted operation found in the journal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resumed {
+    /// The transaction made it out so the bet was moved on as if nothing happened
+    Completed,
+    /// The transaction never made it out so the bet was left as it was before
+    RolledBack,
+}
+
+impl Resumed {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Resumed::Completed => "completed",
+            Resumed::RolledBack => "rolled-back",
+        }
+    }
+}
+
+impl<D> Party<EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Completes or rolls back every operation in the journal that was interrupted.
+    pub fn resume_journal(&self) -> anyhow::Result<Vec<(BetId, JournalOperation, Resumed)>> {
+        let mut resumed = vec![];
+        for (bet_id, entry) in self
+            .bet_db
+            .list_entities::<JournalEntry>()
+            .collect::<anyhow::Result<Vec<_>>>()?
+        {
+            let _span = tracing::info_span!("resume", bet_id).entered();
+            let result = match &entry.operation {
+                JournalOperation::Take { bet } => self.resume_take(bet_id, bet)?,
+            };
+            tracing::info!(
+                operation = entry.operation.name(),
+                result = result.name(),
+                "resumed interrupted operation"
+            );
+            self.bet_db.end_journal(bet_id)?;
+            resumed.push((bet_id, entry.operation, result));
+        }
+        Ok(resumed)
+    }
+
+    fn resume_take(&self, bet_id: BetId, bet: &Bet) -> anyhow::Result<Resumed> {
+        match self.bet_db.get_entity::<BetState>(bet_id)? {
+            Some(BetState::Proposed { .. }) => {}
+            // it was recorded after all (or the bet was forgotten)
+            _ => return Ok(Resumed::Completed),
+        }
+        match self.wallet.client().tx_state(&bet.tx())? {
+            TxState::Present { height } => {
+                self.bet_db
+                    .update_bets(&[bet_id], |bet_state, _, _| match bet_state {
+                        BetState::Proposed { .. } => Ok(BetState::Included {
+                            bet: bet.clone(),
+                            height,
+                        }),
+                        _ => Ok(bet_state),
+                    })?;
+                Ok(Resumed::Completed)
+            }
+            // it never got out or something else spent the inputs so the proposal stays as it
+            // was and the state machine deals with any conflict
+            TxState::NotFound | TxState::Conflict { .. } => Ok(Resumed::RolledBack),
+        }
+    }
+}
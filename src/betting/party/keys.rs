@@ -0,0 +1,303 @@
+use crate::{betting::*, exit_code::ErrorKind, keychain::KeyPair};
+use bdk::{database::BatchDatabase, miniscript::DescriptorTrait};
+use olivia_secp256k1::fun::{g, marker::*, Point, G};
+use std::convert::TryInto;
+
+/// How the keys of a bet were made from the seed and whether making them again gives the same
+/// thing.
+///
+/// The bet key comes from the seed and the proposal alone. The tweaks (`r1`, `r2` and whether the
+/// output keys are swapped) come from an ECDH between our bet key and the other side's. The output
+/// keys add the oracle's anticipated attestation points to those. So with the seed, the proposal
+/// and the other side's public key everything needed to claim can be made again.
+#[derive(Clone, Debug)]
+pub struct BetKeys {
+    pub i_proposed: bool,
+    /// `None` if the bet was made before we started keeping it (see [`BetChat`])
+    pub proposal: Option<Proposal>,
+    pub bet_key: Option<PublicKey>,
+    pub remote_key: Option<PublicKey>,
+    /// `r1 * G`, `r2 * G` and whether the keys are swapped in the script
+    pub tweaks: Option<(Point, Point, bool)>,
+    /// The point the oracle's attestation to each outcome will have (left then right)
+    pub oracle_points: Option<[Point<Jacobian, Public, Zero>; 2]>,
+    pub output_keys: Option<[Point; 2]>,
+    /// What was checked and the problem if it didn't check out
+    pub checks: Vec<(&'static str, Option<String>)>,
+}
+
+impl BetKeys {
+    pub fn problems(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter_map(|(check, problem)| {
+                problem
+                    .as_ref()
+                    .map(|problem| format!("{}: {}", check, problem))
+            })
+            .collect()
+    }
+
+    /// How the bet key is made from the seed
+    pub fn derivation(&self) -> &'static str {
+        match self.i_proposed {
+            true => "HMAC-SHA512(key = HMAC-SHA512(key = \"gun-proposal\", seed), proposal with a placeholder public key)",
+            false => "HMAC-SHA512(key = HMAC-SHA512(key = \"gun-offer\", seed), proposal)",
+        }
+    }
+}
+
+/// Makes the joint output of a bet again from our bet key and the other side's. This is what
+/// [`Party::generate_offer_with_oracle_event`] and [`Party::validate_offer`] do.
+pub fn rederive_joint_output(
+    keypair: &KeyPair,
+    remote_key: &PublicKey,
+    i_proposed: bool,
+    oracle_points: [Point<impl PointType, Public, Zero>; 2],
+    offer_choose_right: bool,
+) -> (JointOutput, Randomize) {
+    let (_, mut rng) = crate::ecdh::ecdh(keypair, remote_key);
+    let randomize = Randomize::new(&mut rng);
+    let (public_keys, my_key) = match i_proposed {
+        true => (
+            [keypair.public_key, *remote_key],
+            Either::Left(keypair.secret_key.clone()),
+        ),
+        false => (
+            [*remote_key, keypair.public_key],
+            Either::Right(keypair.secret_key.clone()),
+        ),
+    };
+    let joint_output = JointOutput::new(
+        public_keys,
+        my_key,
+        oracle_points,
+        offer_choose_right,
+        randomize.clone(),
+    );
+    (joint_output, randomize)
+}
+
+fn check(problem: bool, description: impl FnOnce() -> String) -> Option<String> {
+    match problem {
+        true => Some(description()),
+        false => None,
+    }
+}
+
+impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
+    /// Works out the keys of bet `bet_id` again from the seed and checks they match the ones in
+    /// the database.
+    pub fn bet_keys(&self, bet_id: BetId) -> anyhow::Result<BetKeys> {
+        let bet_state = self
+            .bet_db
+            .get_entity::<BetState>(bet_id)?
+            .ok_or(ErrorKind::NotFound.error(format!("bet {} doesn\'t exist", bet_id)))?;
+        let chat = self.bet_db.get_entity::<BetChat>(bet_id)?;
+
+        let (bet, proposal, i_proposed, remote_key) = match bet_state.into_bet_or_prop() {
+            BetOrProp::Proposal(local_proposal) => {
+                (None, Some(local_proposal.proposal), true, None)
+            }
+            BetOrProp::Bet(bet)
+            | BetOrProp::OfferedBet {
+                bet: OfferedBet(bet),
+                ..
+            } => {
+                let i_proposed = matches!(bet.joint_output.my_key, Either::Left(_));
+                match chat {
+                    Some(chat) => (
+                        Some(bet),
+                        Some(chat.proposal),
+                        chat.i_proposed,
+                        Some(chat.remote_public_key),
+                    ),
+                    None => (Some(bet), None, i_proposed, None),
+                }
+            }
+        };
+
+        let mut keys = BetKeys {
+            i_proposed,
+            proposal: proposal.clone(),
+            bet_key: None,
+            remote_key,
+            tweaks: None,
+            oracle_points: None,
+            output_keys: bet.as_ref().map(|bet| bet.joint_output.output_keys),
+            checks: vec![],
+        };
+
+        let keypair = proposal.as_ref().map(|proposal| match i_proposed {
+            true => self.keychain.get_key_for_proposal(proposal),
+            false => self.keychain.keypair_for_offer(proposal),
+        });
+        keys.bet_key = keypair.as_ref().map(|keypair| keypair.public_key);
+
+        if let (Some(proposal), Some(keypair), true) = (&proposal, &keypair, i_proposed) {
+            keys.checks.push((
+                "proposal key comes from the seed",
+                check(proposal.public_key != keypair.public_key, || {
+                    format!(
+                        "the seed gives {} but the proposal has {}",
+                        keypair.public_key, proposal.public_key
+                    )
+                }),
+            ));
+        }
+
+        let bet = match bet {
+            Some(bet) => bet,
+            None => return Ok(keys),
+        };
+
+        let oracle_points = self
+            .bet_db
+            .get_entity::<OracleInfo>(bet.oracle_id.clone())?
+            .and_then(|oracle_info| {
+                bet.oracle_event
+                    .anticipate_attestations_olivia_v1(&oracle_info.oracle_keys.olivia_v1?, 0)
+            })
+            .and_then(|points| points[..].get(..2)?.try_into().ok());
+        keys.oracle_points = oracle_points;
+        let oracle_points: [Point<Jacobian, Public, Zero>; 2] = match oracle_points {
+            Some(oracle_points) => oracle_points,
+            None => {
+                keys.checks.push((
+                    "oracle points",
+                    Some(format!(
+                        "couldn't anticipate {}'s attestations to {}",
+                        bet.oracle_id, bet.oracle_event.event.id
+                    )),
+                ));
+                return Ok(keys);
+            }
+        };
+
+        let my_scalar = bet.joint_output.my_key.unwrap();
+        let my_oracle_point = oracle_points[bet.i_chose_right as usize];
+        keys.checks.push((
+            "our secret key and the oracle's attestation give our output key",
+            check(
+                g!(my_scalar * G + my_oracle_point).mark::<(Normal, NonZero)>()
+                    != Some(*bet.joint_output.my_point()),
+                || "the key we'd claim with wouldn't spend the bet output".into(),
+            ),
+        ));
+
+        let script_pubkey = bet.joint_output.descriptor().script_pubkey();
+        keys.checks.push((
+            "output keys make the bet output",
+            check(
+                bet.tx()
+                    .output
+                    .get(bet.vout as usize)
+                    .map(|txout| txout.script_pubkey != script_pubkey)
+                    .unwrap_or(true),
+                || {
+                    format!(
+                        "output {} of the bet transaction is something else",
+                        bet.vout
+                    )
+                },
+            ),
+        ));
+
+        if let (Some(keypair), Some(remote_key)) = (&keypair, &keys.remote_key) {
+            let offer_choose_right = match i_proposed {
+                true => !bet.i_chose_right,
+                false => bet.i_chose_right,
+            };
+            let (joint_output, randomize) = rederive_joint_output(
+                keypair,
+                remote_key,
+                i_proposed,
+                oracle_points,
+                offer_choose_right,
+            );
+            let Randomize {
+                r1,
+                r2,
+                swap_points,
+            } = randomize;
+            keys.tweaks = Some((
+                g!(r1 * G).mark::<Normal>(),
+                g!(r2 * G).mark::<Normal>(),
+                swap_points,
+            ));
+            keys.checks.push((
+                "joint output comes from the seed and the other side's key",
+                check(
+                    joint_output.with_cosigners(bet.joint_output.cosigners) != bet.joint_output,
+                    || "making the joint output again gave different keys".into(),
+                ),
+            ));
+        }
+
+        if bet.cosigner.is_some() || bet.joint_output.my_cosigner().is_some() {
+            keys.checks.push((
+                "our cosigner's key is in the bet output",
+                check(
+                    bet.cosigner.as_ref().map(|cosigner| &cosigner.public_key)
+                        != bet.joint_output.my_cosigner(),
+                    || "claiming would need a different offline key than the one recorded".into(),
+                ),
+            ));
+        }
+
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn both_sides_derive_the_same_output_keys() {
+        let proposer = KeyPair::from_slice(&[1u8; 32]).unwrap();
+        let offerer = KeyPair::from_slice(&[2u8; 32]).unwrap();
+        let oracle_points = [
+            KeyPair::from_slice(&[3u8; 32])
+                .unwrap()
+                .public_key
+                .mark::<Zero>(),
+            KeyPair::from_slice(&[4u8; 32])
+                .unwrap()
+                .public_key
+                .mark::<Zero>(),
+        ];
+        for offer_choose_right in [false, true] {
+            let (proposer_output, proposer_randomize) = rederive_joint_output(
+                &proposer,
+                &offerer.public_key,
+                true,
+                oracle_points,
+                offer_choose_right,
+            );
+            let (offerer_output, offerer_randomize) = rederive_joint_output(
+                &offerer,
+                &proposer.public_key,
+                false,
+                oracle_points,
+                offer_choose_right,
+            );
+            assert_eq!(proposer_output.output_keys, offerer_output.output_keys);
+            assert_eq!(proposer_output.swapped, offerer_output.swapped);
+            assert_eq!(proposer_randomize.r1, offerer_randomize.r1);
+            // each side can only make its own secret key
+            let proposer_key = proposer_output.my_key.unwrap();
+            let proposer_point = oracle_points[!offer_choose_right as usize];
+            assert_eq!(
+                g!(proposer_key * G + proposer_point).mark::<(Normal, NonZero)>(),
+                Some(proposer_output.output_keys[0])
+            );
+            let offerer_key = offerer_output.my_key.unwrap();
+            let offerer_point = oracle_points[offer_choose_right as usize];
+            assert_eq!(
+                g!(offerer_key * G + offerer_point).mark::<(Normal, NonZero)>(),
+                Some(offerer_output.output_keys[1])
+            );
+        }
+    }
+}
@@ -1,23 +1,50 @@
+mod balance;
 mod bet_args;
+mod bet_limits;
+mod bet_parties;
+mod chat;
+mod conflicts;
+mod counterparty_inputs;
+mod journal;
+mod keys;
 mod offer;
 mod proposal;
+mod publish;
+mod recover;
+mod reorg;
 mod spend_won;
 mod state_machine;
+mod sync;
 mod take_offer;
 
+pub use balance::*;
 pub use bet_args::*;
+pub use bet_limits::*;
+pub use bet_parties::*;
+pub use counterparty_inputs::*;
+pub use journal::Resumed;
+pub use keys::*;
 use miniscript::DescriptorTrait;
+pub use recover::BetCandidate;
+pub use reorg::TipChange;
+pub use sync::SyncScope;
 
-use crate::{betting::*, keychain::Keychain, FeeSpec};
+use crate::{
+    audit::{AuditLog, AuditOperation},
+    betting::*,
+    coin_select::CoinSelectPolicy,
+    keychain::Keychain,
+    notify::{NotificationKind, NotificationSettings},
+    tx_ordering::TxOrderingPolicy,
+    FeeAliases, FeeSpec,
+};
 use anyhow::{anyhow, Context};
 use bdk::{
     bitcoin::{
         util::psbt::{self, PartiallySignedTransaction as Psbt},
-        OutPoint, Txid,
-    },
-    blockchain::{
-        noop_progress, AnyBlockchain, AnyBlockchainConfig, Blockchain, ConfigurableBlockchain,
+        Amount, OutPoint, Transaction, Txid,
     },
+    blockchain::{AnyBlockchain, AnyBlockchainConfig, Blockchain, ConfigurableBlockchain},
     database::MemoryDatabase,
     descriptor::ExtendedDescriptor,
     wallet::{AddressIndex, Wallet},
@@ -35,8 +62,164 @@ pub struct Party<B, D> {
     client: crate::reqwest::blocking::Client,
     bet_db: BetDatabase,
     blockchain_config: AnyBlockchainConfig,
+    settings: PartySettings,
+    audit_log: Option<AuditLog>,
+}
+
+/// Knobs that change how a [`Party`] builds transactions.
+#[derive(Clone, Debug)]
+pub struct PartySettings {
+    /// Change outputs worth less than this are left to the miners rather than created
+    pub dust_change_threshold: Amount,
+    /// Sends and splits don't make outputs worth less than this (see [`crate::dust`])
+    pub min_output_value: Amount,
+    pub coin_select: CoinSelectPolicy,
+    pub tx_ordering: TxOrderingPolicy,
+    /// Warn if the backend's newest block is older than this
+    pub stale_tip_minutes: u32,
+    /// Run with the message whenever we raise an alert
+    pub alert_command: Option<Vec<String>>,
+    /// Replace our unconfirmed cancel transactions if the bet they cancel pays a higher fee
+    pub fee_bump_cancels: bool,
+    pub confirmations: ConfirmationTargets,
+    /// Sends above its threshold are held until a second approver confirms them
+    pub approval: Option<crate::approval::ApprovalPolicy>,
+    pub rbf: RbfDefaults,
+    /// Where sends put their change if not the wallet's internal descriptor
+    pub change_descriptor: Option<String>,
+    /// Where winnings are claimed to if not the wallet and the bet doesn't say otherwise
+    pub claim_to: Option<bdk::bitcoin::Address>,
+    /// The offline key new bets are cosigned with (see [`BetCosigner`])
+    pub bet_cosigner: Option<String>,
+    pub fee_aliases: FeeAliases,
+    /// Where announcements and attestations come from for each oracle
+    pub oracle_sources: crate::event_source::EventSources,
+    pub notifications: NotificationSettings,
+    pub counterparty_confirmations: CounterpartyConfirmations,
+    pub proposal_boards: crate::board::ProposalBoards,
+    /// Where to get the price recorded as the cost basis of bets and sends
+    pub price_source: Option<crate::price::PriceSource>,
+    pub coinjoin: crate::coinjoin::CoinjoinSettings,
+    /// The most bets can risk of the wallet
+    pub bet_limits: BetLimits,
+    /// The oracles and counterparties bets can be made with
+    pub bet_parties: BetParties,
+    /// Don't warn about send amounts that look like typos
+    pub ignore_unusual_amounts: bool,
+    /// The wallet directory whose spending session has to be open for anything to be signed if
+    /// the wallet has a spending lock (see [`crate::session`])
+    pub spending_lock_dir: Option<std::path::PathBuf>,
+}
+
+impl Default for PartySettings {
+    fn default() -> Self {
+        Self {
+            dust_change_threshold: Amount::from_sat(DEFAULT_DUST_CHANGE_THRESHOLD_SATS),
+            min_output_value: Amount::ZERO,
+            coin_select: CoinSelectPolicy::default(),
+            tx_ordering: TxOrderingPolicy::default(),
+            stale_tip_minutes: DEFAULT_STALE_TIP_MINUTES,
+            alert_command: None,
+            fee_bump_cancels: false,
+            confirmations: ConfirmationTargets::default(),
+            approval: None,
+            rbf: RbfDefaults::default(),
+            change_descriptor: None,
+            fee_aliases: FeeAliases::default(),
+            claim_to: None,
+            bet_cosigner: None,
+            oracle_sources: Default::default(),
+            notifications: NotificationSettings::default(),
+            counterparty_confirmations: CounterpartyConfirmations::default(),
+            proposal_boards: Default::default(),
+            price_source: None,
+            coinjoin: Default::default(),
+            bet_limits: BetLimits::default(),
+            bet_parties: BetParties::default(),
+            ignore_unusual_amounts: false,
+            spending_lock_dir: None,
+        }
+    }
+}
+
+/// How many confirmations each kind of transaction needs before we treat it as final.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ConfirmationTargets {
+    /// Coins sent to us by someone else
+    pub incoming: u32,
+    /// Bet transactions and the transactions that cancel them
+    pub bet_funding: u32,
+    /// Transactions claiming bets we've won
+    pub claim: u32,
+}
+
+impl Default for ConfirmationTargets {
+    fn default() -> Self {
+        Self {
+            incoming: 1,
+            bet_funding: 1,
+            claim: 1,
+        }
+    }
+}
+
+/// Whether each kind of transaction signals replace-by-fee (BIP125) when it isn't chosen on the
+/// command line.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct RbfDefaults {
+    /// `gun send`, `gun split` and so on
+    pub sends: bool,
+    /// Bet transactions we make offers with and the transactions claiming them. The other side of
+    /// a bet always uses whatever the offer's signatures were made with.
+    pub bets: bool,
+}
+
+impl Default for RbfDefaults {
+    fn default() -> Self {
+        Self {
+            sends: true,
+            bets: true,
+        }
+    }
 }
 
+/// The number of confirmations a transaction at `height` has if the chain tip is at `tip_height`.
+pub fn confirmations(height: Option<u32>, tip_height: u32) -> u32 {
+    match height {
+        Some(height) if height <= tip_height => tip_height - height + 1,
+        // the backend has the tx in a block it hasn't told us about as the tip yet
+        Some(_) => 1,
+        None => 0,
+    }
+}
+
+/// Logs `message` loudly and runs `alert_command` (see [`PartySettings::alert_command`]) with it.
+pub fn alert(alert_command: Option<&[String]>, kind: &str, message: &str) {
+    tracing::warn!("ALERT ({}): {}", kind, message);
+    run_alert_command(alert_command, kind, message)
+}
+
+/// Runs `alert_command` with `message` as its last argument and `kind` in `GUN_ALERT`.
+pub fn run_alert_command(alert_command: Option<&[String]>, kind: &str, message: &str) {
+    if let Some((program, args)) = alert_command.and_then(|command| command.split_first()) {
+        let result = std::process::Command::new(program)
+            .args(args)
+            .arg(message)
+            .env("GUN_ALERT", kind)
+            .status();
+        match result {
+            Ok(status) if status.success() => {}
+            Ok(status) => tracing::warn!("alert command exited with {}", status),
+            Err(e) => tracing::warn!("couldn't run alert command {}: {}", program, e),
+        }
+    }
+}
+
+pub const DEFAULT_DUST_CHANGE_THRESHOLD_SATS: u64 = 1_000;
+pub const DEFAULT_STALE_TIP_MINUTES: u32 = 90;
+
 impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
 where
     D: bdk::database::BatchDatabase,
@@ -51,8 +234,66 @@ where
             wallet,
             keychain,
             bet_db,
-            client: crate::reqwest::blocking::Client::new(),
+            client: crate::endpoint::http_client()
+                .build()
+                .expect("the http client can always be built"),
             blockchain_config,
+            settings: PartySettings::default(),
+            audit_log: None,
+        }
+    }
+
+    pub fn with_settings(mut self, settings: PartySettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Record everything we sign and broadcast in `audit_log`.
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    /// Records a transaction in the audit log if there is one.
+    pub fn audit_psbt(
+        &self,
+        operation: AuditOperation,
+        context: &str,
+        psbt: &Psbt,
+    ) -> anyhow::Result<()> {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.record_psbt(operation, context, psbt),
+            None => Ok(()),
+        }
+    }
+
+    pub fn audit_tx(
+        &self,
+        operation: AuditOperation,
+        context: &str,
+        tx: &Transaction,
+    ) -> anyhow::Result<()> {
+        match &self.audit_log {
+            Some(audit_log) => audit_log.record(operation, context, tx, None),
+            None => Ok(()),
+        }
+    }
+
+    pub fn settings(&self) -> &PartySettings {
+        &self.settings
+    }
+
+    /// The key for the cosigner of a new bet if there is one.
+    pub fn next_cosigner_key(&self) -> anyhow::Result<Option<CosignerKey>> {
+        match &self.settings.bet_cosigner {
+            Some(bet_cosigner) => Ok(Some(
+                BetCosigner::new(bet_cosigner, self.wallet.network())?.next_key(&self.bet_db)?,
+            )),
+            None => Ok(None),
         }
     }
 
@@ -142,7 +383,10 @@ where
         bet_ids: &[BetId],
         feespec: FeeSpec,
     ) -> anyhow::Result<Option<Psbt>> {
+        let _span = tracing::info_span!("build_tx", kind = "cancel", ?bet_ids).entered();
         let mut utxos_that_need_canceling: Vec<OutPoint> = vec![];
+        // the highest feerate of the transactions we are replacing
+        let mut replaced_feerate: Option<bdk::FeeRate> = None;
 
         for bet_id in bet_ids {
             let bet_state = self.bet_db().get_entity(*bet_id)?.ok_or(anyhow!(
@@ -181,6 +425,13 @@ where
                 | BetState::Included {
                     bet, height: None, ..
                 } => {
+                    use crate::psbt_ext::PsbtFeeRate;
+                    let (_, feerate) = bet.psbt.fee();
+                    if replaced_feerate
+                        .map_or(true, |replaced| feerate.as_sat_vb() > replaced.as_sat_vb())
+                    {
+                        replaced_feerate = Some(feerate);
+                    }
                     let tx = bet.tx();
                     let inputs = bet
                         .my_input_indexes
@@ -210,6 +461,15 @@ where
             .manually_selected_only()
             .enable_rbf()
             .only_witness_utxo();
+        let feespec = match (feespec, replaced_feerate) {
+            (feespec, Some(replaced_feerate)) => feespec.relative_to(replaced_feerate)?,
+            (FeeSpec::Bump(_), None) => {
+                return Err(anyhow!(
+                    "there is no transaction to bump the fee of because none of the bets have been broadcast"
+                ))
+            }
+            (feespec, None) => feespec,
+        };
         feespec.apply_to_builder(self.wallet.client(), &mut builder)?;
 
         for utxo in utxos_that_need_canceling {
@@ -245,6 +505,7 @@ where
             Ok(res) => res,
             e => e?,
         };
+        crate::psbt_ext::log_built_tx(&psbt);
         let finalized = self.wallet.sign(
             &mut psbt,
             SignOptions {
@@ -253,6 +514,7 @@ where
             },
         )?;
         assert!(finalized, "we should have signed all inputs");
+        self.audit_psbt(AuditOperation::Sign, "cancel", &psbt)?;
         Ok(Some(psbt))
     }
 
@@ -349,18 +611,150 @@ where
     }
 
     // convenience methods
+    /// Syncs the wallet's own scripts (see [`SyncScope::Wallet`]).
     pub fn sync(&self) -> anyhow::Result<()> {
-        eprintln!("syncing wallet with {:?}", self.blockchain_config);
-        self.wallet.sync(noop_progress(), None)?;
+        self.sync_scope(SyncScope::Wallet)
+    }
+
+    /// Tell the user loudly that something happened and run the configured alert command.
+    pub fn alert(&self, kind: &str, message: &str) {
+        alert(self.settings.alert_command.as_deref(), kind, message)
+    }
+
+    /// Tell the user about something if they've asked for it in the notification settings.
+    pub fn notify(&self, kind: NotificationKind, message: &str) {
+        crate::notify::notify(
+            &self.settings.notifications,
+            self.settings.alert_command.as_deref(),
+            kind,
+            message,
+        )
+    }
+
+    /// The current price to record as a cost basis if there's a price source. The bet or send goes
+    /// ahead without one if the price can't be fetched.
+    pub fn current_cost_basis(&self) -> Option<crate::price::CostBasis> {
+        let price_source = self.settings.price_source.as_ref()?;
+        match price_source.fetch() {
+            Ok(price) => Some(crate::price::CostBasis {
+                price,
+                recorded_at: crate::clock::now(),
+            }),
+            Err(e) => {
+                tracing::warn!("couldn't get the price to record as the cost basis: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Records the current price as the cost basis of the send `txid`.
+    pub fn record_cost_basis(&self, txid: Txid) -> anyhow::Result<()> {
+        if let Some(cost_basis) = self.current_cost_basis() {
+            self.bet_db.set_cost_basis(txid, cost_basis)?;
+        }
+        Ok(())
+    }
+
+    /// Records our new coins that came out of coinjoins and freezes them if the policy says to.
+    fn label_coinjoins(&self) -> anyhow::Result<()> {
+        use crate::coinjoin::{self, CoinjoinOutput, CoinjoinPolicy};
+        let settings = self.settings.coinjoin;
+        if settings.policy == CoinjoinPolicy::Ignore {
+            return Ok(());
+        }
+        let known = self.bet_db.coinjoin_outputs()?;
+        for utxo in self.wallet.list_unspent()? {
+            if known.contains_key(&utxo.outpoint) {
+                continue;
+            }
+            let tx = match self
+                .wallet
+                .query_db(|db| db.get_tx(&utxo.outpoint.txid, true))?
+            {
+                Some(tx) => tx,
+                None => continue,
+            };
+            // our own transactions (e.g. `gun split`) can have equal outputs too
+            if tx.sent > 0 {
+                continue;
+            }
+            let transaction = match &tx.transaction {
+                Some(transaction) => transaction,
+                None => continue,
+            };
+            let (denomination, participants) =
+                match coinjoin::detect(transaction, settings.min_participants) {
+                    Some(detected) => detected,
+                    None => continue,
+                };
+            // the other outputs are change from the coinjoin and not mixed
+            if utxo.txout.value != denomination.as_sat() {
+                continue;
+            }
+            tracing::info!(
+                outpoint = %utxo.outpoint,
+                %denomination,
+                participants,
+                "received a coinjoin output"
+            );
+            self.bet_db.set_coinjoin_output(
+                utxo.outpoint,
+                CoinjoinOutput {
+                    denomination,
+                    participants,
+                    detected_at: crate::clock::now(),
+                },
+            )?;
+            if settings.policy == CoinjoinPolicy::Quarantine {
+                self.bet_db.freeze_utxo(utxo.outpoint)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn notify_wallet_changes(&self, before: Vec<bdk::TransactionDetails>) -> anyhow::Result<()> {
+        for tx in self.wallet.list_transactions(false)? {
+            let previously = before.iter().find(|previous| previous.txid == tx.txid);
+            let net = tx.received.saturating_sub(tx.sent);
+            if previously.is_none() && net > 0 {
+                self.notify(
+                    NotificationKind::IncomingPayment,
+                    &format!("received {} in {}", Amount::from_sat(net), tx.txid),
+                );
+            }
+            if let (Some(previously), Some(confirmation_time)) =
+                (previously, tx.confirmation_time.as_ref())
+            {
+                if previously.confirmation_time.is_none() {
+                    self.notify(
+                        NotificationKind::Confirmation,
+                        &format!(
+                            "{} was confirmed in block {}",
+                            tx.txid, confirmation_time.height
+                        ),
+                    );
+                }
+            }
+        }
         Ok(())
     }
 
     pub fn poke_bets(&self) {
+        let _span = tracing::info_span!("poke_bets").entered();
         for (bet_id, _) in self.bet_db().list_entities_print_error::<BetState>() {
             match self.take_next_action(bet_id, true) {
                 Ok(_updated) => {}
-                Err(e) => eprintln!("Error trying to take action on bet {}: {:?}", bet_id, e),
+                Err(e) => {
+                    tracing::error!("Error trying to take action on bet {}: {:?}", bet_id, e)
+                }
             }
         }
+        if let Err(e) = self.respond_to_conflicts() {
+            tracing::error!(
+                "Error trying to respond to conflicting transactions: {:?}",
+                e
+            );
+        }
+        self.unpublish_stale_proposals();
     }
 }
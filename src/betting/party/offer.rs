@@ -1,5 +1,8 @@
 use super::BetArgs;
-use crate::{betting::*, change::Change, FeeSpec, ValueChoice};
+use crate::{
+    betting::*, change::Change, coin_select::PolicyCoinSelection, tx_ordering::TxOrderingPolicy,
+    FeeSpec, ValueChoice,
+};
 use anyhow::{anyhow, Context};
 use bdk::{
     bitcoin::Amount,
@@ -21,6 +24,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
         args: BetArgs<'_, '_>,
         fee_spec: FeeSpec,
     ) -> anyhow::Result<(Bet, Offer, Point<EvenY>, impl StreamCipher)> {
+        let _span = tracing::info_span!("build_tx", kind = "offer").entered();
         let remote_public_key = &proposal.public_key;
         let event_id = &oracle_event.event.id;
         if event_id.n_outcomes() != 2 {
@@ -30,6 +34,13 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             ));
         }
 
+        self.settings
+            .bet_parties
+            .check_oracle(&oracle_info.id, Some(&oracle_info))?;
+        self.settings
+            .bet_parties
+            .check_counterparty(remote_public_key)?;
+
         let anticipated_attestations = oracle_event
             .anticipate_attestations_olivia_v1(&oracle_info.oracle_keys.olivia_v1.ok_or(anyhow!("Oracle {} does not support olivia_v1"))?, 0)
             .ok_or(anyhow!("Cannot make bet on {} since {} doesn't support olivia_v1 attestation for this event", event_id, oracle_info.id))?
@@ -37,24 +48,36 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             .try_into()
             .unwrap();
 
+        self.check_claim_to(args.claim_to.as_ref())?;
         let local_keypair = self.keychain.keypair_for_offer(&proposal);
         let (cipher, mut rng) = crate::ecdh::ecdh(&local_keypair, remote_public_key);
 
         let remote_public_key = proposal.public_key;
         let randomize = Randomize::new(&mut rng);
 
+        let cosigner = self.next_cosigner_key()?;
         let joint_output = JointOutput::new(
             [remote_public_key, local_keypair.public_key],
             Either::Right(local_keypair.secret_key),
             anticipated_attestations,
             choose_right,
             randomize,
-        );
+        )
+        .with_cosigners([
+            proposal.cosigner,
+            cosigner.as_ref().map(|cosigner| cosigner.public_key),
+        ]);
 
-        let mut builder = self.wallet.build_tx();
-        builder
-            .ordering(TxOrdering::Bip69Lexicographic)
-            .enable_rbf();
+        let mut builder = self
+            .wallet
+            .build_tx()
+            .coin_selection(PolicyCoinSelection(self.settings.coin_select));
+        // the other side has to be able to rebuild it so it starts from BIP69 and any shuffling
+        // is done with the ECDH RNG below
+        builder.ordering(TxOrdering::Bip69Lexicographic);
+        if args.rbf.unwrap_or(self.settings.rbf.bets) {
+            builder.enable_rbf();
+        }
 
         let output_script = joint_output.descriptor().script_pubkey();
 
@@ -73,11 +96,13 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
         args.apply_args(self.bet_db(), &mut builder)?;
 
         let mut input_value = 0;
+        let mut proposal_psbt_inputs = vec![];
         for proposal_input in &proposal.inputs {
             let psbt_input = self
                 .p2wpkh_outpoint_to_psbt_input(*proposal_input)
                 .context("retrieving proposal input")?;
             input_value += psbt_input.witness_utxo.as_ref().unwrap().value;
+            proposal_psbt_inputs.push(psbt_input.clone());
             builder.add_foreign_utxo(
                 *proposal_input,
                 psbt_input,
@@ -104,6 +129,33 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
         let (mut psbt, _tx_details) = builder
             .finish()
             .context("Unable to create offer transaction")?;
+        crate::psbt_ext::log_built_tx(&psbt);
+
+        let bet_value = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|txout| txout.script_pubkey == output_script)
+            .map(|txout| Amount::from_sat(txout.value))
+            .expect("The bet output must be in there");
+        self.check_counterparty_inputs(&proposal_psbt_inputs, bet_value)?;
+
+        let dust_change_threshold = self.settings.dust_change_threshold;
+        let donated =
+            crate::psbt_ext::fold_dust_change(&mut psbt, dust_change_threshold, |txout| {
+                txout.script_pubkey != output_script
+                    && self.wallet.is_mine(&txout.script_pubkey).unwrap_or(false)
+            });
+        if donated > Amount::ZERO {
+            tracing::warn!(
+                "{} of change would have been below the dust change threshold of {} so it was added to the fee instead",
+                donated, dust_change_threshold
+            );
+        }
+        if self.settings.tx_ordering == TxOrderingPolicy::Random {
+            crate::tx_ordering::shuffle_psbt(&mut psbt, &mut rng);
+        }
 
         // the inputs we own have witnesses
         let my_input_indexes = psbt
@@ -120,6 +172,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             .wallet
             .sign(&mut psbt, SignOptions::default())
             .context("Unable to sign offer transaction")?;
+        self.audit_psbt(crate::audit::AuditOperation::Sign, "offer", &psbt)?;
 
         if is_final {
             // the only reason it would be final is that the wallet is doing a bet with itself
@@ -137,6 +190,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
 
         let joint_output_value = Amount::from_sat(txout.value);
         let local_value = joint_output_value - proposal.value;
+        self.check_bet_limits(local_value, &args)?;
 
         let signed_inputs: Vec<SignedInput> = my_input_indexes
             .iter()
@@ -170,6 +224,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             inputs: signed_inputs,
             choose_right,
             value: local_value,
+            cosigner: cosigner.as_ref().map(|cosigner| cosigner.public_key),
         };
         let bet = Bet {
             psbt,
@@ -182,6 +237,9 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             joint_output_value,
             i_chose_right: choose_right,
             tags: args.tags,
+            claim_to: args.claim_to,
+            cost_basis: self.current_cost_basis(),
+            cosigner,
         };
 
         Ok((bet, offer, local_keypair.public_key, cipher))
@@ -1,4 +1,4 @@
-use crate::{betting::*, change::Change, ValueChoice};
+use crate::{betting::*, change::Change, coin_select::PolicyCoinSelection, ValueChoice};
 use anyhow::{anyhow, Context};
 use bdk::{
     bitcoin::{Amount, Script},
@@ -25,7 +25,11 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             ));
         }
 
-        let mut builder = self.wallet.build_tx();
+        let _span = tracing::info_span!("build_tx", kind = "proposal").entered();
+        let mut builder = self
+            .wallet
+            .build_tx()
+            .coin_selection(PolicyCoinSelection(self.settings.coin_select));
         // we use a 0 feerate because the offerer will pay the fee
         builder.fee_rate(FeeRate::from_sat_per_vb(0.0));
 
@@ -36,11 +40,19 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             }
         };
 
+        self.check_claim_to(args.claim_to.as_ref())?;
+        self.settings.bet_parties.check_oracle(
+            &oracle_id,
+            self.bet_db
+                .get_entity::<OracleInfo>(oracle_id.clone())?
+                .as_ref(),
+        )?;
         args.apply_args(self.bet_db(), &mut builder)?;
 
         let (psbt, txdetails) = builder
             .finish()
             .context("Failed to gather proposal outputs")?;
+        crate::psbt_ext::log_built_tx(&psbt);
 
         debug_assert!(
             // The tx fee *should* be nothing but it's possible the bet value is so close to the
@@ -66,6 +78,8 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
                 .value,
         );
 
+        self.check_bet_limits(value, &args)?;
+
         let change = if outputs.len() > 1 {
             if outputs.len() != 2 {
                 return Err(anyhow!(
@@ -91,7 +105,10 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             inputs: tx_inputs,
             public_key: crate::placeholder_point(),
             change_script: change.as_ref().map(|x| x.binscript().clone()),
+            cosigner: None,
         };
+        let cosigner = self.next_cosigner_key()?;
+        proposal.cosigner = cosigner.as_ref().map(|cosigner| cosigner.public_key);
 
         let keypair = self.keychain.get_key_for_proposal(&proposal);
         proposal.public_key = keypair.public_key;
@@ -101,6 +118,8 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             oracle_event,
             change,
             tags: args.tags,
+            claim_to: args.claim_to,
+            cosigner,
         };
 
         Ok(local_proposal)
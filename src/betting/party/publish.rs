@@ -0,0 +1,198 @@
+use crate::{betting::*, board::ProposalBoard, exit_code::ErrorKind};
+use anyhow::anyhow;
+use bdk::database::BatchDatabase;
+use olivia_core::chrono::{Duration, NaiveDateTime};
+
+/// When we can next put a proposal on a board given when we last did and its `min-interval-secs`.
+fn next_allowed(board: &ProposalBoard, last_published: Option<NaiveDateTime>) -> NaiveDateTime {
+    match last_published {
+        Some(last_published) => last_published + Duration::seconds(board.min_interval_secs as i64),
+        None => NaiveDateTime::from_timestamp(0, 0),
+    }
+}
+
+impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
+    fn get_board(&self, name: &str) -> anyhow::Result<&ProposalBoard> {
+        self.settings.proposal_boards.get(name).ok_or(anyhow!(
+            "there's no board called {} in proposal-boards",
+            name
+        ))
+    }
+
+    fn last_published(&self, board: &str) -> Option<NaiveDateTime> {
+        self.bet_db
+            .list_entities_print_error::<Publication>()
+            .flat_map(|(_, publication)| publication.adverts)
+            .filter(|advert| advert.board == board)
+            .map(|advert| advert.published_at)
+            .max()
+    }
+
+    /// Advertises the proposal `bet_id` on each of `boards` (every board in `proposal-boards` if
+    /// it's empty). Fails if none of them took it.
+    pub fn publish_proposal(
+        &self,
+        bet_id: BetId,
+        boards: &[String],
+    ) -> anyhow::Result<Vec<Advert>> {
+        let local_proposal = match self.bet_db.get_entity::<BetState>(bet_id)? {
+            Some(BetState::Proposed { local_proposal }) => local_proposal,
+            Some(_) => return Err(anyhow!("bet {} isn't a proposal any more", bet_id)),
+            None => return Err(ErrorKind::NotFound.error(format!("bet {} doesn\'t exist", bet_id))),
+        };
+        if let Some(expected_outcome_time) = local_proposal.oracle_event.event.expected_outcome_time
+        {
+            if expected_outcome_time <= crate::clock::now() {
+                return Err(anyhow!(
+                    "the event of proposal {} has already happened",
+                    bet_id
+                ));
+            }
+        }
+        let boards = match boards.is_empty() {
+            true => self
+                .settings
+                .proposal_boards
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>(),
+            false => boards.to_vec(),
+        };
+        if boards.is_empty() {
+            return Err(anyhow!("no proposal-boards are configured"));
+        }
+        let already = self
+            .bet_db
+            .get_entity::<Publication>(bet_id)?
+            .map(|publication| {
+                publication
+                    .live_adverts()
+                    .map(|advert| advert.board.clone())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let proposal = &local_proposal.proposal;
+        let keypair = self.keychain.get_key_for_proposal(proposal);
+        let mut adverts = vec![];
+        let mut errors = vec![];
+        for name in boards {
+            if already.contains(&name) {
+                errors.push(format!("{}: it's already there", name));
+                continue;
+            }
+            let board = self.get_board(&name)?;
+            let now = crate::clock::now();
+            let next_allowed = next_allowed(board, self.last_published(&name));
+            if now < next_allowed {
+                errors.push(format!(
+                    "{}: we can publish to it again in {}s",
+                    name,
+                    (next_allowed - now).num_seconds() + 1
+                ));
+                continue;
+            }
+            match board.publish(proposal, &keypair, now.timestamp() as u64) {
+                Ok(id) => {
+                    let advert = Advert {
+                        board: name.clone(),
+                        id,
+                        published_at: now,
+                        unpublished_at: None,
+                    };
+                    self.bet_db.add_advert(bet_id, proposal, advert.clone())?;
+                    tracing::info!(bet_id, board = %name, "published proposal");
+                    adverts.push(advert);
+                }
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+
+        if adverts.is_empty() {
+            return Err(anyhow!(
+                "the proposal wasn't published: {}",
+                errors.join(", ")
+            ));
+        }
+        for error in errors {
+            tracing::warn!(bet_id, "didn't publish proposal to {}", error);
+        }
+        Ok(adverts)
+    }
+
+    /// Takes the adverts for `bet_id` down from every board they're on and returns the boards.
+    pub fn unpublish_proposal(&self, bet_id: BetId) -> anyhow::Result<Vec<String>> {
+        let publication = self
+            .bet_db
+            .get_entity::<Publication>(bet_id)?
+            .ok_or(anyhow!("proposal {} hasn't been published", bet_id))?;
+        let keypair = self.keychain.get_key_for_proposal(&publication.proposal);
+        let mut unpublished = vec![];
+        for advert in publication.live_adverts() {
+            let now = crate::clock::now();
+            self.get_board(&advert.board)?.unpublish(
+                &advert.id,
+                &keypair,
+                now.timestamp() as u64,
+            )?;
+            self.bet_db.set_unpublished(bet_id, &advert.board, now)?;
+            unpublished.push(advert.board.clone());
+        }
+        Ok(unpublished)
+    }
+
+    /// Takes down the adverts for proposals that have been taken, canceled or whose event has
+    /// happened. They are tried again next time if a board can't be reached.
+    pub fn unpublish_stale_proposals(&self) {
+        let now = crate::clock::now();
+        for (bet_id, publication) in self.bet_db.list_entities_print_error::<Publication>() {
+            if publication.live_adverts().next().is_none() {
+                continue;
+            }
+            let stale = match self.bet_db.get_entity::<BetState>(bet_id) {
+                Ok(Some(BetState::Proposed { local_proposal })) => local_proposal
+                    .oracle_event
+                    .event
+                    .expected_outcome_time
+                    .map(|expected_outcome_time| expected_outcome_time <= now)
+                    .unwrap_or(false),
+                Ok(_) => true,
+                Err(e) => {
+                    tracing::error!(bet_id, "couldn't get bet: {:?}", e);
+                    continue;
+                }
+            };
+            if !stale {
+                continue;
+            }
+            match self.unpublish_proposal(bet_id) {
+                Ok(boards) => {
+                    tracing::info!(bet_id, "unpublished proposal from {}", boards.join(", "))
+                }
+                Err(e) => tracing::warn!(bet_id, "couldn't unpublish proposal: {:?}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::board::BoardKind;
+
+    #[test]
+    fn boards_are_rate_limited() {
+        let board = ProposalBoard {
+            kind: BoardKind::Http {
+                url: "https://bets.example.com/proposals".into(),
+            },
+            min_interval_secs: 60,
+        };
+        let last = NaiveDateTime::from_timestamp(1_000_000, 0);
+        assert_eq!(
+            next_allowed(&board, Some(last)),
+            NaiveDateTime::from_timestamp(1_000_060, 0)
+        );
+        assert!(next_allowed(&board, None) < last);
+    }
+}
@@ -0,0 +1,334 @@
+use crate::betting::*;
+use anyhow::anyhow;
+use bdk::{
+    bitcoin::{util::psbt::PartiallySignedTransaction as Psbt, Amount, Script, Transaction, Txid},
+    database::BatchDatabase,
+    miniscript::DescriptorTrait,
+};
+use std::{collections::HashMap, convert::TryInto};
+
+/// A transaction in the wallet's history that looks like a bet the database doesn't know about:
+/// it spends coins of ours and someone else's into a P2WSH output.
+///
+/// The joint output's script can't be found from the seed alone since it depends on the other
+/// side's key and the oracle's event. Given the proposal (and our offer if we were the proposer)
+/// [`Party::recover_bet`] can make it all again though.
+#[derive(Clone, Debug)]
+pub struct BetCandidate {
+    pub tx: Transaction,
+    /// `None` if it's in the mempool
+    pub height: Option<u32>,
+    pub vout: u32,
+    pub value: Amount,
+    pub my_input_indexes: Vec<u32>,
+    pub my_input_value: Amount,
+}
+
+impl BetCandidate {
+    pub fn txid(&self) -> Txid {
+        self.tx.txid()
+    }
+}
+
+/// The bet transaction as a PSBT with the witnesses it was broadcast with (which is what
+/// [`Bet::tx`] extracts).
+fn psbt_from_signed_tx(tx: &Transaction) -> anyhow::Result<Psbt> {
+    let mut unsigned_tx = tx.clone();
+    for txin in &mut unsigned_tx.input {
+        txin.witness = vec![];
+        txin.script_sig = Script::new();
+    }
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)?;
+    for (psbt_input, txin) in psbt.inputs.iter_mut().zip(&tx.input) {
+        psbt_input.final_script_witness = Some(txin.witness.clone());
+    }
+    Ok(psbt)
+}
+
+impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
+    /// Looks through the wallet's transactions confirmed between `from_height` and `to_height` (and
+    /// in the mempool if there is no `to_height`) for bets the database doesn't have.
+    pub fn find_lost_bets(
+        &self,
+        from_height: Option<u32>,
+        to_height: Option<u32>,
+    ) -> anyhow::Result<Vec<BetCandidate>> {
+        let known = self
+            .bet_db
+            .list_entities_print_error::<BetState>()
+            .filter_map(|(_, bet_state)| match bet_state.into_bet_or_prop() {
+                BetOrProp::Bet(bet)
+                | BetOrProp::OfferedBet {
+                    bet: OfferedBet(bet),
+                    ..
+                } => Some(bet.tx().txid()),
+                BetOrProp::Proposal(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        let history = self.wallet.list_transactions(true)?;
+        let txs = history
+            .iter()
+            .filter_map(|details| Some((details.txid, details.transaction.as_ref()?)))
+            .collect::<HashMap<_, _>>();
+
+        let mut candidates = vec![];
+        for details in &history {
+            let tx = match &details.transaction {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let height = details.confirmation_time.as_ref().map(|time| time.height);
+            let in_window = match height {
+                Some(height) => {
+                    from_height.map(|from| height >= from).unwrap_or(true)
+                        && to_height.map(|to| height <= to).unwrap_or(true)
+                }
+                None => to_height.is_none(),
+            };
+            if !in_window || known.contains(&details.txid) {
+                continue;
+            }
+
+            let mut my_input_indexes = vec![];
+            let mut my_input_value = 0;
+            for (i, txin) in tx.input.iter().enumerate() {
+                let prev_txout = txs
+                    .get(&txin.previous_output.txid)
+                    .and_then(|prev_tx| prev_tx.output.get(txin.previous_output.vout as usize));
+                if let Some(prev_txout) = prev_txout {
+                    if self.wallet.is_mine(&prev_txout.script_pubkey)? {
+                        my_input_indexes.push(i as u32);
+                        my_input_value += prev_txout.value;
+                    }
+                }
+            }
+            if my_input_indexes.is_empty() || my_input_indexes.len() == tx.input.len() {
+                continue;
+            }
+
+            if let Some((vout, txout)) = tx
+                .output
+                .iter()
+                .enumerate()
+                .find(|(_, txout)| txout.script_pubkey.is_v0_p2wsh())
+            {
+                candidates.push(BetCandidate {
+                    tx: tx.clone(),
+                    height,
+                    vout: vout as u32,
+                    value: Amount::from_sat(txout.value),
+                    my_input_indexes,
+                    my_input_value: Amount::from_sat(my_input_value),
+                });
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Makes the bet in `candidate` again from the `proposal` it was made from. If we made the
+    /// proposal we also need the `encrypted_offer` that was taken since only it has the other
+    /// side's key. Returns `None` if the bet wasn't made from them.
+    pub fn recover_bet(
+        &self,
+        candidate: &BetCandidate,
+        proposal: &Proposal,
+        encrypted_offer: Option<&Ciphertext>,
+        oracle_event: &OracleEvent,
+        oracle_info: &OracleInfo,
+    ) -> anyhow::Result<Option<Bet>> {
+        let input_is_mine = |outpoint: &bdk::bitcoin::OutPoint| {
+            candidate
+                .my_input_indexes
+                .iter()
+                .any(|i| candidate.tx.input[*i as usize].previous_output == *outpoint)
+        };
+        let spends_proposal_inputs = proposal.inputs.iter().all(|outpoint| {
+            candidate
+                .tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == *outpoint)
+        });
+        if !spends_proposal_inputs {
+            return Ok(None);
+        }
+        let i_proposed = proposal.inputs.iter().all(input_is_mine);
+
+        let (keypair, remote_key, choices, remote_cosigner) = match i_proposed {
+            true => {
+                let keypair = self.keychain.get_key_for_proposal(proposal);
+                if keypair.public_key != proposal.public_key {
+                    return Err(anyhow!("the proposal wasn't made by this wallet"));
+                }
+                let encrypted_offer = encrypted_offer.ok_or(anyhow!(
+                    "we made the proposal so the offer that was taken is needed too"
+                ))?;
+                let (mut cipher, _) = crate::ecdh::ecdh(&keypair, &encrypted_offer.public_key);
+                let offer = match encrypted_offer.decrypt(&mut cipher) {
+                    Ok(Plaintext::Offerv1 { offer, .. }) => offer,
+                    _ => return Ok(None),
+                };
+                (
+                    keypair,
+                    encrypted_offer.public_key,
+                    vec![offer.choose_right],
+                    offer.cosigner,
+                )
+            }
+            false => (
+                self.keychain.keypair_for_offer(proposal),
+                proposal.public_key,
+                vec![false, true],
+                proposal.cosigner,
+            ),
+        };
+        // the index of our cosigner's key isn't kept anywhere but the database so the first few
+        // are tried
+        let our_cosigners = match (i_proposed, proposal.cosigner) {
+            (true, None) => vec![None],
+            (true, Some(cosigner)) => vec![Some(
+                self.cosigner_keys_to_recover()?
+                    .into_iter()
+                    .find(|key| key.public_key == cosigner)
+                    .ok_or(anyhow!(
+                        "the proposal is cosigned by a key that isn't one of bet-cosigner's"
+                    ))?,
+            )],
+            (false, _) => std::iter::once(None)
+                .chain(self.cosigner_keys_to_recover()?.into_iter().map(Some))
+                .collect(),
+        };
+
+        let oracle_points: [_; 2] = oracle_event
+            .anticipate_attestations_olivia_v1(
+                &oracle_info.oracle_keys.olivia_v1.ok_or(anyhow!(
+                    "oracle {} does not support olivia_v1",
+                    oracle_info.id
+                ))?,
+                0,
+            )
+            .ok_or(anyhow!(
+                "{} doesn't support olivia_v1 attestation for {}",
+                oracle_info.id,
+                oracle_event.event.id
+            ))?[..2]
+            .try_into()
+            .unwrap();
+
+        let script_pubkey = &candidate.tx.output[candidate.vout as usize].script_pubkey;
+        for offer_choose_right in choices {
+            let (joint_output, _) = rederive_joint_output(
+                &keypair,
+                &remote_key,
+                i_proposed,
+                oracle_points,
+                offer_choose_right,
+            );
+            let cosigned = our_cosigners.iter().find_map(|our_cosigner| {
+                let our_point = our_cosigner.as_ref().map(|key| key.public_key);
+                let cosigners = match i_proposed {
+                    true => [our_point, remote_cosigner],
+                    false => [remote_cosigner, our_point],
+                };
+                let joint_output = joint_output.clone().with_cosigners(cosigners);
+                match joint_output.descriptor().script_pubkey() == *script_pubkey {
+                    true => Some((joint_output, our_cosigner.clone())),
+                    false => None,
+                }
+            });
+            let (joint_output, cosigner) = match cosigned {
+                Some(cosigned) => cosigned,
+                None => continue,
+            };
+            let local_value = match i_proposed {
+                true => proposal.value,
+                false => candidate
+                    .value
+                    .checked_sub(proposal.value)
+                    .ok_or(anyhow!("the bet output is worth less than the proposal"))?,
+            };
+            return Ok(Some(Bet {
+                psbt: psbt_from_signed_tx(&candidate.tx)?,
+                my_input_indexes: candidate.my_input_indexes.clone(),
+                vout: candidate.vout,
+                joint_output,
+                oracle_id: oracle_info.id.clone(),
+                oracle_event: oracle_event.clone(),
+                local_value,
+                joint_output_value: candidate.value,
+                i_chose_right: match i_proposed {
+                    true => !offer_choose_right,
+                    false => offer_choose_right,
+                },
+                tags: vec!["recovered".into()],
+                claim_to: None,
+                cost_basis: None,
+                cosigner,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn cosigner_keys_to_recover(&self) -> anyhow::Result<Vec<CosignerKey>> {
+        match &self.settings.bet_cosigner {
+            Some(bet_cosigner) => {
+                Ok(BetCosigner::new(bet_cosigner, self.wallet.network())?.keys_to_recover())
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Puts a bet from [`Party::recover_bet`] back in the database. Syncing takes it from there.
+    pub fn insert_recovered_bet(
+        &self,
+        bet: Bet,
+        height: Option<u32>,
+        proposal: Proposal,
+        encrypted_offer: Option<&Ciphertext>,
+    ) -> anyhow::Result<BetId> {
+        let i_proposed = matches!(bet.joint_output.my_key, Either::Left(_));
+        let remote_key = match i_proposed {
+            true => {
+                encrypted_offer
+                    .ok_or(anyhow!("we made the proposal so the offer is needed too"))?
+                    .public_key
+            }
+            false => proposal.public_key,
+        };
+        let bet_id = self.bet_db.insert_bet(BetState::Included { bet, height })?;
+        self.bet_db
+            .start_chat(bet_id, BetChat::new(proposal, i_proposed, remote_key))?;
+        Ok(bet_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{hashes::Hash, OutPoint, TxIn, TxOut};
+
+    #[test]
+    fn psbt_keeps_the_witnesses() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_slice(&[1u8; 32]).unwrap(),
+                    vout: 3,
+                },
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFD,
+                witness: vec![vec![2u8; 72], vec![3u8; 33]],
+            }],
+            output: vec![TxOut {
+                value: 20_000,
+                script_pubkey: Script::new_v0_wsh(&Hash::from_slice(&[4u8; 32]).unwrap()),
+            }],
+        };
+        let psbt = psbt_from_signed_tx(&tx).unwrap();
+        assert_eq!(psbt.extract_tx(), tx);
+    }
+}
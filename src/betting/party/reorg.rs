@@ -0,0 +1,239 @@
+use crate::betting::*;
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{BlockHash, Network},
+    blockchain::AnyBlockchainConfig,
+};
+use std::str::FromStr;
+
+/// How many of the latest blocks we remember so we can find where a reorg forked off.
+const REMEMBERED_BLOCKS: usize = 12;
+/// How many times longer the tip can go without a block on testnet before it looks stale.
+const TESTNET_STALE_TIP_FACTOR: i64 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TipChange {
+    /// The first time we've looked at the chain
+    New,
+    /// The chain just got longer
+    Extended,
+    /// Blocks above `fork_height` were replaced
+    Reorg { fork_height: u32 },
+    /// None of the blocks we remember are in the chain anymore
+    DeepReorg,
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    fn esplora_get(&self, path: &str) -> anyhow::Result<String> {
+        let base_url = match &self.blockchain_config {
+            AnyBlockchainConfig::Esplora(esplora) => esplora.base_url.trim_end_matches('/'),
+            #[allow(unreachable_patterns)]
+            _ => return Err(anyhow!("chain tip checks only work with esplora")),
+        };
+        Ok(self
+            .client
+            .get(format!("{}/{}", base_url, path))
+            .send()?
+            .error_for_status()?
+            .text()?)
+    }
+
+    fn block_hash_at(&self, height: u32) -> anyhow::Result<BlockHash> {
+        Ok(BlockHash::from_str(
+            self.esplora_get(&format!("block-height/{}", height))?
+                .trim(),
+        )?)
+    }
+
+    /// How many seconds the tip can go without a new block before the backend looks stuck or
+    /// `None` on networks where blocks don't come regularly enough to tell.
+    fn stale_tip_after(&self) -> Option<i64> {
+        let seconds = self.settings.stale_tip_minutes as i64 * 60;
+        match self.wallet.network() {
+            Network::Bitcoin => Some(seconds),
+            // testnet goes from blocks every few seconds to none for hours
+            Network::Testnet => Some(seconds * TESTNET_STALE_TIP_FACTOR),
+            // regtest only gets blocks when someone mines them and signets are often run that way
+            Network::Regtest | Network::Signet => None,
+        }
+    }
+
+    /// Compares the backend's chain with the blocks we saw last time to find reorgs and records
+    /// the new tip. Also warns if the tip is so old that the backend may be stuck, and again each
+    /// time that much longer passes without a new block.
+    pub fn check_chain_tip(&self) -> anyhow::Result<TipChange> {
+        let tip_height = u32::from_str(self.esplora_get("blocks/tip/height")?.trim())
+            .context("parsing tip height from backend")?;
+        let tip_hash = self.block_hash_at(tip_height)?;
+        let previous = self.bet_db.get_chain_tip()?;
+        let mut stale_alerted_at = None;
+
+        let tip_block: serde_json::Value =
+            serde_json::from_str(&self.esplora_get(&format!("block/{}", tip_hash))?)?;
+        if let Some(timestamp) = tip_block.get("timestamp").and_then(|t| t.as_i64()) {
+            if let Some(behind) = crate::clock::behind_block(timestamp as u32) {
+                if !crate::clock::is_pinned() {
+                    self.alert(
+                        "clock-skew",
+                        &format!(
+                            "the latest block ({}) is timestamped {} minutes ahead of this computer's clock which no block can be -- the clock is wrong so expiries and outcome times will be judged wrong",
+                            tip_height,
+                            behind / 60
+                        ),
+                    );
+                }
+            }
+            // the backend being stuck is about real time even when the clock is pinned
+            let now = crate::chrono::Utc::now().timestamp();
+            let age = now - timestamp;
+            if let Some(stale_after) = self.stale_tip_after() {
+                if age > stale_after {
+                    let last_alert = previous
+                        .as_ref()
+                        .and_then(|previous| previous.stale_alerted_at);
+                    stale_alerted_at = match last_alert {
+                        Some(last_alert) if now - last_alert < stale_after => Some(last_alert),
+                        _ => {
+                            self.alert(
+                                "stale-tip",
+                                &format!(
+                                    "the latest block the backend knows about ({}) is {} minutes old -- it might not be in sync with the network",
+                                    tip_height,
+                                    age / 60
+                                ),
+                            );
+                            Some(now)
+                        }
+                    };
+                }
+            }
+        }
+
+        let change = match &previous {
+            None => TipChange::New,
+            Some(previous) => {
+                let mut change = TipChange::DeepReorg;
+                for (i, (height, hash)) in previous.recent.iter().rev().enumerate() {
+                    if *height > tip_height {
+                        continue;
+                    }
+                    let current = if *height == tip_height {
+                        tip_hash
+                    } else {
+                        self.block_hash_at(*height)?
+                    };
+                    if current == *hash {
+                        change = if i == 0 {
+                            TipChange::Extended
+                        } else {
+                            TipChange::Reorg {
+                                fork_height: *height,
+                            }
+                        };
+                        break;
+                    }
+                }
+                change
+            }
+        };
+
+        let mut recent = match (previous, &change) {
+            (Some(previous), TipChange::Extended) => previous.recent,
+            (Some(previous), TipChange::Reorg { fork_height }) => previous
+                .recent
+                .into_iter()
+                .filter(|(height, _)| height <= fork_height)
+                .collect(),
+            _ => vec![],
+        };
+        if recent.last().map(|(height, _)| *height) != Some(tip_height) {
+            recent.push((tip_height, tip_hash));
+        }
+        let excess = recent.len().saturating_sub(REMEMBERED_BLOCKS);
+        recent.drain(..excess);
+        self.bet_db.set_chain_tip(ChainTip {
+            recent,
+            stale_alerted_at,
+        })?;
+
+        match change {
+            TipChange::Reorg { fork_height } => self.handle_reorg(Some(fork_height))?,
+            TipChange::DeepReorg => self.handle_reorg(None)?,
+            _ => {}
+        }
+
+        Ok(change)
+    }
+
+    /// Forget the confirmation of anything above `fork_height` (or everything if we don't know
+    /// where the fork is) so the state machine checks them again.
+    fn handle_reorg(&self, fork_height: Option<u32>) -> anyhow::Result<()> {
+        let reorged = |height: &Option<u32>| match (height, fork_height) {
+            (Some(height), Some(fork_height)) => *height > fork_height,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+
+        let affected = self
+            .bet_db
+            .list_entities_print_error::<BetState>()
+            .filter(|(_, bet_state)| match bet_state {
+                BetState::Included { height, .. }
+                | BetState::Claimed { height, .. }
+                | BetState::Canceled { height, .. } => reorged(height),
+                _ => false,
+            })
+            .map(|(bet_id, _)| bet_id)
+            .collect::<Vec<_>>();
+
+        self.alert(
+            "reorg",
+            &format!(
+                "the chain reorganized {} -- bets that need rechecking: {:?}",
+                match fork_height {
+                    Some(fork_height) => format!("above block {}", fork_height),
+                    None => "deeper than we can tell".to_string(),
+                },
+                affected
+            ),
+        );
+
+        self.bet_db.update_bets(&affected, |old_state, _, _| {
+            Ok(match old_state {
+                BetState::Included { bet, .. } => BetState::Included { bet, height: None },
+                BetState::Claimed {
+                    bet,
+                    txid,
+                    secret_key,
+                    attestation,
+                    ..
+                } => BetState::Claimed {
+                    bet,
+                    txid,
+                    height: None,
+                    secret_key,
+                    attestation,
+                },
+                BetState::Canceled {
+                    pre_cancel,
+                    bet_spent_vin,
+                    cancel_txid,
+                    cancel_vin,
+                    i_intend_cancel,
+                    ..
+                } => BetState::Canceled {
+                    pre_cancel,
+                    bet_spent_vin,
+                    cancel_txid,
+                    cancel_vin,
+                    height: None,
+                    i_intend_cancel,
+                },
+                old_state => old_state,
+            })
+        })
+    }
+}
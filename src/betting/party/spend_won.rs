@@ -1,17 +1,15 @@
 use crate::{betting::*, FeeSpec};
 use bdk::{
     bitcoin::{
+        self,
         util::psbt::{self, PartiallySignedTransaction as Psbt},
-        PrivateKey, TxOut,
+        Address, TxOut,
     },
     blockchain::Blockchain,
-    database::MemoryDatabase,
     miniscript::DescriptorTrait,
-    signer::SignerOrdering,
     wallet::{coin_selection::CoinSelectionAlgorithm, tx_builder::TxBuilderContext, AddressIndex},
-    KeychainKind, SignOptions, TxBuilder, Wallet,
+    SignOptions, TxBuilder,
 };
-use std::sync::Arc;
 
 use super::Party;
 
@@ -19,74 +17,123 @@ impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
 where
     D: bdk::database::BatchDatabase,
 {
+    /// Makes a transaction claiming the bets we've won for each place they are claimed to and
+    /// whether it's finalized. It isn't if any of the bets have a cosigner since it still needs
+    /// their signature (see [`BetCosigner`]).
     pub fn claim(
         &self,
         fee: FeeSpec,
         bump_claiming: bool,
-    ) -> anyhow::Result<Option<(Vec<BetId>, Psbt)>> {
+        rbf: bool,
+    ) -> anyhow::Result<Vec<(Vec<BetId>, Psbt, bool)>> {
         let wallet = self.wallet();
-        let mut builder = wallet.build_tx();
-        builder.manually_selected_only().enable_rbf();
+        let mut destinations = vec![];
+        for won in self.claimable_bets(bump_claiming)? {
+            let destination = self.claim_destination(&won.bet);
+            if !destinations.contains(&destination) {
+                destinations.push(destination);
+            }
+        }
 
-        fee.apply_to_builder(wallet.client(), &mut builder)?;
+        let mut claims = vec![];
+        for destination in destinations {
+            let mut builder = wallet.build_tx();
+            builder.manually_selected_only();
+            if rbf {
+                builder.enable_rbf();
+            }
 
-        let recipient = wallet
-            .get_change_address(AddressIndex::New)?
-            .script_pubkey();
+            fee.apply_to_builder(wallet.client(), &mut builder)?;
 
-        builder.drain_to(recipient);
+            let recipient = match &destination {
+                Some(address) => address.script_pubkey(),
+                None => wallet
+                    .get_change_address(AddressIndex::New)?
+                    .script_pubkey(),
+            };
 
-        let (mut psbt, claiming_bet_ids) = match self.spend_won_bets(builder, bump_claiming)? {
-            Some(res) => res,
-            None => return Ok(None),
-        };
+            builder.drain_to(recipient);
 
-        let finalized = wallet.finalize_psbt(&mut psbt, SignOptions::default())?;
+            let (mut psbt, claiming_bet_ids) =
+                match self.spend_won_bets(builder, bump_claiming, destination.as_ref())? {
+                    Some(res) => res,
+                    None => continue,
+                };
 
-        assert!(
-            finalized,
-            "since we have signed each input is must be finalized"
-        );
+            let finalized = wallet.finalize_psbt(&mut psbt, SignOptions::default())?;
+            let cosigned = self
+                .won_bets(&claiming_bet_ids)?
+                .iter()
+                .any(|won| won.bet.cosigner.is_some());
+
+            assert!(
+                finalized || cosigned,
+                "since we have signed each input is must be finalized"
+            );
+            self.audit_psbt(crate::audit::AuditOperation::Sign, "claim", &psbt)?;
+            claims.push((claiming_bet_ids, psbt, finalized));
+        }
 
-        Ok(Some((claiming_bet_ids, psbt)))
+        Ok(claims)
+    }
+
+    /// Where the winnings of `bet` go. `None` means back into the wallet.
+    pub fn claim_destination(&self, bet: &Bet) -> Option<Address> {
+        bet.claim_to
+            .clone()
+            .or_else(|| self.settings.claim_to.clone())
+    }
+
+    /// Checks an address winnings are to be claimed to can be paid from this wallet (see
+    /// [`crate::address::check`]).
+    pub fn check_claim_to(&self, claim_to: Option<&Address>) -> anyhow::Result<()> {
+        if let Some(address) = claim_to {
+            crate::address::check(address, self.wallet.network(), "the claim-to address")?;
+        }
+        Ok(())
     }
 
     pub fn spend_won_bets<B: Blockchain, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext>(
         &self,
         mut builder: TxBuilder<'_, B, D, Cs, Ctx>,
         bump_claiming: bool,
+        destination: Option<&Address>,
     ) -> anyhow::Result<Option<(Psbt, Vec<BetId>)>> {
-        let claimable_bets = self
-            .bet_db
-            .list_entities::<BetState>()
-            .filter_map(|result| match result {
-                Ok(ok) => Some(ok),
-                Err(e) => {
-                    eprintln!("Eror with entry in database: {}", e);
-                    None
-                }
-            })
-            .filter_map(|(bet_id, bet_state)| match bet_state {
-                BetState::Won {
-                    bet, secret_key, ..
-                } => Some((bet_id, bet, secret_key)),
-                BetState::Claimed {
-                    height: None,
-                    bet,
-                    secret_key,
-                    ..
-                } if bump_claiming => Some((bet_id, bet, secret_key)),
-                _ => None,
-            })
-            .collect::<Vec<_>>();
+        let _span = tracing::info_span!("build_tx", kind = "claim").entered();
+        let claimable_bets = self.add_won_bets(&mut builder, bump_claiming, destination)?;
 
-        let claimable_bet_ids = claimable_bets
-            .iter()
-            .map(|(bet_id, _, _)| *bet_id)
+        let (mut psbt, _) = match builder.finish() {
+            Ok(res) => res,
+            Err(bdk::Error::NoUtxosSelected) => return Ok(None),
+            e => e?,
+        };
+        crate::psbt_ext::log_built_tx(&psbt);
+
+        self.sign_won_bets(&mut psbt, &claimable_bets)?;
+
+        Ok(Some((
+            psbt,
+            claimable_bets.into_iter().map(|won| won.bet_id).collect(),
+        )))
+    }
+
+    /// Adds the outputs of bets we've won (and haven't claimed yet) as inputs to the transaction.
+    ///
+    /// Only bets that are claimed to `destination` are added (`None` being the wallet).
+    pub fn add_won_bets<B: Blockchain, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext>(
+        &self,
+        builder: &mut TxBuilder<'_, B, D, Cs, Ctx>,
+        bump_claiming: bool,
+        destination: Option<&Address>,
+    ) -> anyhow::Result<Vec<WonBet>> {
+        let claimable_bets = self
+            .claimable_bets(bump_claiming)?
+            .into_iter()
+            .filter(|won| self.claim_destination(&won.bet).as_ref() == destination)
             .collect::<Vec<_>>();
 
-        for (_, bet, _) in &claimable_bets {
-            let psbt_input = psbt::Input {
+        for WonBet { bet, .. } in &claimable_bets {
+            let mut psbt_input = psbt::Input {
                 witness_utxo: Some(TxOut {
                     value: bet.joint_output_value.as_sat(),
                     script_pubkey: bet.joint_output.descriptor().script_pubkey(),
@@ -95,6 +142,9 @@ where
                 witness_script: Some(bet.joint_output.descriptor().script_code()),
                 ..Default::default()
             };
+            if let Some(cosigner) = &bet.cosigner {
+                cosigner.add_to_psbt_input(&mut psbt_input);
+            }
             builder
                 .add_foreign_utxo(
                     bet.outpoint(),
@@ -107,34 +157,114 @@ where
                 .unwrap();
         }
 
-        let (mut psbt, _) = match builder.finish() {
-            Ok(res) => res,
-            Err(bdk::Error::NoUtxosSelected) => return Ok(None),
-            e => e?,
-        };
+        Ok(claimable_bets)
+    }
 
-        for (_, bet, secret_key) in claimable_bets {
-            let signer = PrivateKey {
-                compressed: true,
-                network: self.wallet.network(),
-                key: secret_key,
-            };
-            let output_descriptor = bet.joint_output.wallet_descriptor();
-            let mut tmp_wallet = Wallet::new_offline(
-                output_descriptor,
-                None,
-                self.wallet.network(),
-                MemoryDatabase::default(),
+    /// The bets we've won and haven't claimed yet (or are claiming if `bump_claiming`). Bets
+    /// spent by a transaction that's waiting for signatures (e.g. a claim that needs the
+    /// cosigner's) are left out.
+    pub fn claimable_bets(&self, bump_claiming: bool) -> anyhow::Result<Vec<WonBet>> {
+        let waiting_for_signatures = self.bet_db.pending_psbt_utxos()?;
+        Ok(self
+            .bet_db
+            .list_entities::<BetState>()
+            .filter_map(|result| match result {
+                Ok(ok) => Some(ok),
+                Err(e) => {
+                    tracing::error!("Eror with entry in database: {}", e);
+                    None
+                }
+            })
+            .filter_map(|(bet_id, bet_state)| match bet_state {
+                BetState::Won {
+                    bet, secret_key, ..
+                } => Some(WonBet {
+                    bet_id,
+                    bet,
+                    secret_key,
+                }),
+                BetState::Claimed {
+                    height: None,
+                    bet,
+                    secret_key,
+                    ..
+                } if bump_claiming => Some(WonBet {
+                    bet_id,
+                    bet,
+                    secret_key,
+                }),
+                _ => None,
+            })
+            .filter(|won| !waiting_for_signatures.contains(&won.bet.outpoint()))
+            .collect())
+    }
+
+    /// Looks up bets added by [`add_won_bets`](Self::add_won_bets) for a transaction that is
+    /// being signed later.
+    pub fn won_bets(&self, bet_ids: &[BetId]) -> anyhow::Result<Vec<WonBet>> {
+        bet_ids
+            .iter()
+            .map(
+                |bet_id| match self.bet_db.get_entity::<BetState>(*bet_id)? {
+                    Some(BetState::Won {
+                        bet, secret_key, ..
+                    })
+                    | Some(BetState::Claimed {
+                        bet, secret_key, ..
+                    }) => Ok(WonBet {
+                        bet_id: *bet_id,
+                        bet,
+                        secret_key,
+                    }),
+                    _ => Err(anyhow::anyhow!("bet {} is no longer claimable", bet_id)),
+                },
             )
-            .expect("nothing can go wrong here");
-            tmp_wallet.add_signer(
-                KeychainKind::External,
-                SignerOrdering::default(),
-                Arc::new(signer),
-            );
-            tmp_wallet.sign(&mut psbt, SignOptions::default())?;
-        }
+            .collect()
+    }
 
-        Ok(Some((psbt, claimable_bet_ids)))
+    /// Signs the inputs added by [`add_won_bets`](Self::add_won_bets).
+    pub fn sign_won_bets(&self, psbt: &mut Psbt, won_bets: &[WonBet]) -> anyhow::Result<()> {
+        // these aren't signed by the wallet so neither the read-only signer nor the session
+        // signer is asked
+        crate::read_only::check("sign claims")?;
+        if let Some(wallet_dir) = &self.settings.spending_lock_dir {
+            crate::session::check(wallet_dir, "sign claims")?;
+        }
+        if let Some(policy) = &self.settings.approval {
+            let bet_outpoints = won_bets
+                .iter()
+                .map(|won| won.bet.outpoint())
+                .collect::<Vec<_>>();
+            crate::approval::check(
+                policy,
+                psbt,
+                |script| self.wallet.is_mine(script).unwrap_or(false),
+                &bet_outpoints,
+            )?;
+        }
+        let keys = won_bets
+            .iter()
+            .filter_map(
+                |WonBet {
+                     bet, secret_key, ..
+                 }| {
+                    let index = psbt
+                        .global
+                        .unsigned_tx
+                        .input
+                        .iter()
+                        .position(|txin| txin.previous_output == bet.outpoint())?;
+                    Some((index, *secret_key))
+                },
+            )
+            .collect::<Vec<_>>();
+        crate::psbt_ext::sign_inputs(psbt, &keys, &bitcoin::secp256k1::Secp256k1::new())
     }
 }
+
+/// A bet we've won that can be claimed
+pub struct WonBet {
+    pub bet_id: BetId,
+    pub bet: Bet,
+    pub secret_key: bitcoin::secp256k1::SecretKey,
+}
@@ -1,8 +1,8 @@
-use crate::betting::*;
-use anyhow::{anyhow, Context};
-use bdk::blockchain::{
-    Blockchain, Broadcast, GetInputState, InputState, TransactionState, TxState,
+use crate::{
+    audit::AuditOperation, betting::*, notify::NotificationKind, oracle_poll::OraclePoller,
 };
+use anyhow::{anyhow, Context};
+use bdk::blockchain::{Blockchain, GetInputState, InputState, TransactionState, TxState};
 
 use super::Party;
 
@@ -27,11 +27,16 @@ where
     /// The `try_learn_outcome` exists so during tests it can be turned off so this doesn't try and contact a non-existent oracle.
     /// TODO: fix this with an oracle trait that can be mocked in tests.
     pub fn take_next_action(&self, bet_id: BetId, try_learn_outcome: bool) -> anyhow::Result<()> {
+        let _span = tracing::debug_span!("bet", bet_id).entered();
         let bet_state = self
             .bet_db
-            .get_entity(bet_id)?
+            .get_entity::<BetState>(bet_id)?
             .ok_or(anyhow!("Bet {} does not exist"))?;
+        let old_name = bet_state.name();
+        let old_state = bet_state.clone();
+        tracing::trace!(state = old_name, "looking for the next action");
         let blockchain = self.wallet.client();
+        let tip_height = self.bet_db.tip_height()?;
 
         match bet_state {
             BetState::Canceled {
@@ -70,6 +75,16 @@ where
                             vin,
                             height,
                         } => {
+                            let spent_mine = i_intend_cancel
+                                || match &pre_cancel {
+                                    BetOrProp::Bet(bet)
+                                    | BetOrProp::OfferedBet {
+                                        bet: OfferedBet(bet),
+                                        ..
+                                    } => bet.my_input_indexes.contains(&(index as u32)),
+                                    BetOrProp::Proposal(_) => true,
+                                };
+                            self.record_conflict(bet_id, txid, spent_mine, height)?;
                             update_bet! {
                                     self, bet_id,
                                     BetState::Canceled { pre_cancel, mut i_intend_cancel, .. } => {
@@ -102,7 +117,12 @@ where
                             }
                             BetOrProp::Bet(bet) => {
                                 if !i_intend_cancel {
-                                    Broadcast::broadcast(blockchain, bet.tx())
+                                    self.audit_tx(
+                                        AuditOperation::Broadcast,
+                                        "rebroadcast",
+                                        &bet.tx(),
+                                    )?;
+                                    crate::read_only::broadcast(blockchain, bet.tx())
                                         .context("broadcasting bet tx because it left mempool")?;
                                 }
                                 update_bet! { self, bet_id, BetState::Canceled { height: None, .. } => BetState::Included { bet: bet.clone(), height: None } }
@@ -152,6 +172,7 @@ where
                         height,
                     } => {
                         let i_intend_cancel = bet.my_input_indexes.contains(&vin_target);
+                        self.record_conflict(bet_id, txid, i_intend_cancel, height)?;
                         if height.is_some() || i_intend_cancel {
                             update_bet! { self, bet_id,
                                BetState::Offered { bet, encrypted_offer } => BetState::Canceled {
@@ -176,26 +197,31 @@ where
                         vin,
                         vin_target,
                         height,
-                    } => update_bet! { self, bet_id,
-                        BetState::Included { bet, .. } => BetState::Canceled {
-                            i_intend_cancel: bet.my_input_indexes.contains(&vin_target),
-                            pre_cancel: BetOrProp::Bet(bet),
-                            bet_spent_vin: vin_target,
-                            cancel_txid: txid,
-                            cancel_vin: vin,
-                            height,
+                    } => {
+                        let i_intend_cancel = bet.my_input_indexes.contains(&vin_target);
+                        self.record_conflict(bet_id, txid, i_intend_cancel, height)?;
+                        update_bet! { self, bet_id,
+                            BetState::Included { bet, .. } => BetState::Canceled {
+                                i_intend_cancel,
+                                pre_cancel: BetOrProp::Bet(bet),
+                                bet_spent_vin: vin_target,
+                                cancel_txid: txid,
+                                cancel_vin: vin,
+                                height,
+                            }
                         }
-                    },
+                    }
                     // Update height if it gto confirmed somewhere else
                     TxState::Present { height } => update_bet! { self, bet_id,
                         BetState::Included { bet,..} => BetState::Included { bet, height }
                     },
                     TxState::NotFound => {
-                        eprintln!(
+                        tracing::warn!(
                             "The bet tx for {} has fallen out of mempool -- rebroadcasting it!",
                             bet_id
                         );
-                        Broadcast::broadcast(blockchain, bet.tx())?
+                        self.audit_tx(AuditOperation::Broadcast, "rebroadcast", &bet.tx())?;
+                        crate::read_only::broadcast(blockchain, bet.tx())?
                     }
                 }
                 if try_learn_outcome {
@@ -214,34 +240,105 @@ where
                     }
                 }
             }
-            // TODO: To be more robust, check if height is below some threshold rather than just None
-            BetState::Claimed {
-                bet, height: None, ..
-            } => match blockchain.input_state(&[bet.outpoint()])? {
-                InputState::Spent { txid, height, .. } => update_bet! {self, bet_id,
-                   BetState::Claimed { bet, attestation, secret_key, .. } => BetState::Claimed { bet, txid, height, secret_key, attestation}
-                },
-                InputState::Unspent => update_bet! { self, bet_id,
-                   BetState::Claimed { bet, secret_key, attestation, .. } => BetState::Won { bet, secret_key, attestation }
-                },
-            },
-            BetState::Claimed {
-                height: Some(_), ..
+            // keep checking the claim until it has enough confirmations in case it gets reorged out
+            BetState::Claimed { bet, height, .. }
+                if height.is_none()
+                    || tip_height.map_or(false, |tip_height| {
+                        confirmations(height, tip_height) < self.settings.confirmations.claim
+                    }) =>
+            {
+                match blockchain.input_state(&[bet.outpoint()])? {
+                    InputState::Spent { txid, height, .. } => update_bet! {self, bet_id,
+                       BetState::Claimed { bet, attestation, secret_key, .. } => BetState::Claimed { bet, txid, height, secret_key, attestation}
+                    },
+                    InputState::Unspent => update_bet! { self, bet_id,
+                       BetState::Claimed { bet, secret_key, attestation, .. } => BetState::Won { bet, secret_key, attestation }
+                    },
+                }
+            }
+            BetState::Claimed { .. } | BetState::Lost { .. } => { /* terminal states */ }
+        }
+
+        if let Some(new_state) = self.bet_db.get_entity::<BetState>(bet_id)? {
+            if new_state.name() != old_name {
+                tracing::info!(from = old_name, to = new_state.name(), "bet changed state");
             }
-            | BetState::Lost { .. } => { /* terminal states */ }
+            self.notify_bet_change(bet_id, &old_state, &new_state);
         }
         Ok(())
     }
 
+    fn notify_bet_change(&self, bet_id: BetId, old_state: &BetState, new_state: &BetState) {
+        use BetState::*;
+        let (kind, message) = match (old_state, new_state) {
+            (Offered { .. }, Included { .. }) => (
+                NotificationKind::OfferTaken,
+                format!("your offer was taken -- bet {} is on", bet_id),
+            ),
+            (
+                Included { height: None, .. },
+                Included {
+                    height: Some(height),
+                    ..
+                },
+            ) => (
+                NotificationKind::Confirmation,
+                format!("bet {} was confirmed in block {}", bet_id, height),
+            ),
+            (Included { .. }, Won { bet, .. }) => (
+                NotificationKind::Attestation,
+                format!(
+                    "{} was attested -- you won bet {} ({} to claim)",
+                    bet.oracle_event.event.id, bet_id, bet.joint_output_value
+                ),
+            ),
+            (Included { .. }, Lost { bet, .. }) => (
+                NotificationKind::Attestation,
+                format!(
+                    "{} was attested -- you lost bet {} ({})",
+                    bet.oracle_event.event.id, bet_id, bet.local_value
+                ),
+            ),
+            (
+                Won { .. },
+                Claimed {
+                    height: Some(height),
+                    ..
+                },
+            )
+            | (
+                Claimed { height: None, .. },
+                Claimed {
+                    height: Some(height),
+                    ..
+                },
+            ) => (
+                NotificationKind::ClaimConfirmed,
+                format!(
+                    "the claim of bet {} was confirmed in block {}",
+                    bet_id, height
+                ),
+            ),
+            _ => return,
+        };
+        self.notify(kind, &message);
+    }
+
     fn try_get_outcome(&self, bet_id: BetId, bet: Bet) -> anyhow::Result<()> {
         let event_id = bet.oracle_event.event.id;
-        let event_url = reqwest::Url::parse(&format!("https://{}{}", bet.oracle_id, event_id))?;
-        let event_response = self
-            .client
-            .get(event_url)
-            .send()?
-            .error_for_status()?
-            .json::<EventResponse>()?;
+        let event_url = crate::endpoint::oracle_event_url(&bet.oracle_id, &event_id)?;
+        tracing::debug!(%event_url, "asking the oracle for the outcome");
+        let event_response = match OraclePoller::new(&self.bet_db, &self.settings.oracle_sources)
+            .poll(&event_url)?
+        {
+            Some(event_response) => event_response,
+            // it's backing off after failing to reach the oracle
+            None => return Ok(()),
+        };
+
+        if let Err(e) = self.remember_attestation(&bet.oracle_id, &event_id, &event_response) {
+            tracing::warn!("couldn't remember the attestation: {}", e);
+        }
 
         if let Some(attestation) = event_response.attestation {
             self.learn_outcome(bet_id, attestation)?;
@@ -0,0 +1,90 @@
+use super::{Party, TipChange};
+use crate::notify::NotificationKind;
+use bdk::blockchain::noop_progress;
+
+/// How much a command needs synced before it runs. Syncing the whole wallet can take a long time on
+/// a slow connection so commands only ask for what they use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncScope {
+    /// Don't talk to the backend at all e.g. for a new address
+    Nothing,
+    /// Only the wallet's own scripts e.g. for the balance or a send
+    Wallet,
+    /// Only what bets need: the chain tip and the scripts of the bets that are waiting on something
+    /// e.g. to claim
+    Bets,
+    /// The wallet's scripts and the bets
+    Full,
+}
+
+impl SyncScope {
+    pub fn includes_wallet(self) -> bool {
+        matches!(self, SyncScope::Wallet | SyncScope::Full)
+    }
+
+    pub fn includes_bets(self) -> bool {
+        matches!(self, SyncScope::Bets | SyncScope::Full)
+    }
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Syncs what's in `scope`. Interrupted bet operations are finished off whenever anything is
+    /// synced.
+    pub fn sync_scope(&self, scope: SyncScope) -> anyhow::Result<()> {
+        if scope == SyncScope::Nothing {
+            return Ok(());
+        }
+        let _span = tracing::info_span!("sync", ?scope).entered();
+        if scope.includes_wallet() {
+            self.sync_wallet()?;
+        }
+        match self.check_chain_tip() {
+            Ok(TipChange::Reorg { .. }) | Ok(TipChange::DeepReorg) if !scope.includes_bets() => {
+                self.poke_bets()
+            }
+            Ok(change) => tracing::debug!(?change, "checked chain tip"),
+            Err(e) => tracing::warn!("couldn't check the backend's chain tip: {}", e),
+        }
+        // finish off anything a crash or backend failure interrupted last time
+        for (bet_id, operation, resumed) in self.resume_journal()? {
+            tracing::warn!(
+                "the {} of bet {} was interrupted and has been {}",
+                operation.name(),
+                bet_id,
+                resumed.name()
+            );
+        }
+        if scope.includes_bets() {
+            // each bet looks up its own scripts so this doesn't need the wallet synced
+            self.poke_bets();
+        }
+        Ok(())
+    }
+
+    fn sync_wallet(&self) -> anyhow::Result<()> {
+        tracing::info!("syncing wallet with {:?}", self.blockchain_config);
+        let started = std::time::Instant::now();
+        let notifications = &self.settings.notifications;
+        let before = match notifications.wants(NotificationKind::IncomingPayment)
+            || notifications.wants(NotificationKind::Confirmation)
+        {
+            true => Some(self.wallet.list_transactions(false)?),
+            false => None,
+        };
+        self.wallet.sync(noop_progress(), None)?;
+        if let Some(before) = before {
+            self.notify_wallet_changes(before)?;
+        }
+        if let Err(e) = self.label_coinjoins() {
+            tracing::warn!("couldn't look for coinjoins: {}", e);
+        }
+        tracing::debug!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "wallet synced"
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
-use crate::betting::*;
+use crate::{
+    betting::*,
+    external_signer::verify_final_witnesses,
+    psbt_ext::{FINAL_SEQUENCE, RBF_SEQUENCE},
+};
 use anyhow::{anyhow, Context};
 use bdk::{
     bitcoin::{
@@ -59,6 +63,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
         offer_public_key: Point<EvenY>,
         mut rng: ChaCha20Rng,
     ) -> anyhow::Result<ValidatedOffer> {
+        let _span = tracing::info_span!("build_tx", kind = "take", bet_id).entered();
         let (offer_psbt_inputs, offer_input_value) = self.lookup_offer_inputs(&offer)?;
 
         let randomize = Randomize::new(&mut rng);
@@ -85,6 +90,12 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             .bet_db
             .get_entity::<OracleInfo>(oracle_id.clone())?
             .ok_or(anyhow!("Oracle {} isn't in the database", oracle_id))?;
+        self.settings
+            .bet_parties
+            .check_oracle(oracle_id, Some(&oracle_info))?;
+        self.settings
+            .bet_parties
+            .check_counterparty(&offer_public_key)?;
 
         let anticipated_attestations = oracle_event
             .anticipate_attestations_olivia_v1(
@@ -104,12 +115,14 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             anticipated_attestations,
             offer.choose_right,
             randomize.clone(),
-        );
+        )
+        .with_cosigners([proposal.cosigner, offer.cosigner]);
         let joint_output_value = offer
             .value
             .checked_add(proposal.value)
             .expect("we've checked the offer value on the chain");
         let joint_output_script_pubkey = joint_output.descriptor().script_pubkey();
+        self.check_counterparty_inputs(&offer_psbt_inputs, joint_output_value)?;
 
         let mut builder = self.wallet.build_tx();
 
@@ -148,7 +161,29 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             )
             .fee_absolute(absolute_fee.as_sat());
 
-        let (mut psbt, _tx_details) = builder.finish()?;
+        let (bip69_psbt, _tx_details) = builder.finish()?;
+        let mut shuffled_psbt = bip69_psbt.clone();
+        crate::tx_ordering::shuffle_psbt(&mut shuffled_psbt, &mut rng);
+
+        // The offer's signatures commit to whether it signals RBF and to the order the other
+        // side's `tx-ordering` put things in so we have to go with what it chose.
+        let secp = bdk::bitcoin::secp256k1::Secp256k1::new();
+        let mut matching_offer = None;
+        'orderings: for mut psbt in vec![bip69_psbt, shuffled_psbt] {
+            for sequence in &[RBF_SEQUENCE, FINAL_SEQUENCE] {
+                for txin in &mut psbt.global.unsigned_tx.input {
+                    txin.sequence = *sequence;
+                }
+                if verify_final_witnesses(&psbt, &secp).is_ok() {
+                    matching_offer = Some(psbt);
+                    break 'orderings;
+                }
+            }
+        }
+        let mut psbt = matching_offer.ok_or(anyhow!(
+            "the offer's signatures aren't valid for the bet transaction"
+        ))?;
+        crate::psbt_ext::log_built_tx(&psbt);
 
         let is_final = self
             .wallet
@@ -158,6 +193,7 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
         if !is_final {
             return Err(anyhow!("Transaction is incomplete after signing it"));
         }
+        self.audit_psbt(crate::audit::AuditOperation::Sign, "take", &psbt)?;
 
         let vout = psbt
             .global
@@ -200,15 +236,30 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
             joint_output_value,
             i_chose_right: !offer.choose_right,
             tags: local_proposal.tags,
+            claim_to: local_proposal.claim_to,
+            cost_basis: self.current_cost_basis(),
+            cosigner: local_proposal.cosigner,
         };
 
-        Ok(ValidatedOffer { bet_id, bet })
+        Ok(ValidatedOffer {
+            bet_id,
+            bet,
+            proposal,
+            offer_public_key,
+        })
     }
 
     pub fn set_offer_taken(
         &self,
-        ValidatedOffer { bet_id, bet, .. }: ValidatedOffer,
+        ValidatedOffer {
+            bet_id,
+            bet,
+            proposal,
+            offer_public_key,
+        }: ValidatedOffer,
     ) -> anyhow::Result<Psbt> {
+        // proposals sharing a pot with this one can't be taken anymore because the bet spends it
+        let siblings = self.bet_db.sibling_proposals(bet_id)?;
         self.bet_db
             .update_bets(&[bet_id], |bet_state, _, _| match bet_state {
                 BetState::Proposed { .. } => Ok(BetState::Included {
@@ -218,6 +269,42 @@ impl<D: BatchDatabase> Party<bdk::blockchain::EsploraBlockchain, D> {
                 _ => Ok(bet_state),
             })?;
 
+        let tx = bet.tx();
+        self.bet_db
+            .update_bets(&siblings, |bet_state, _, _| match bet_state {
+                BetState::Proposed { local_proposal } => {
+                    let spent = local_proposal.proposal.inputs.iter().enumerate().find_map(
+                        |(index, input)| {
+                            tx.input
+                                .iter()
+                                .position(|txin| txin.previous_output == *input)
+                                .map(|vin| (index as u32, vin as u32))
+                        },
+                    );
+                    Ok(match spent {
+                        Some((bet_spent_vin, cancel_vin)) => BetState::Canceled {
+                            pre_cancel: BetOrProp::Proposal(local_proposal),
+                            bet_spent_vin,
+                            cancel_txid: tx.txid(),
+                            cancel_vin,
+                            height: None,
+                            i_intend_cancel: true,
+                        },
+                        None => BetState::Proposed { local_proposal },
+                    })
+                }
+                bet_state => Ok(bet_state),
+            })?;
+        for sibling in siblings {
+            tracing::info!(
+                bet_id,
+                sibling,
+                "canceled proposal that shared its coins with the taken one"
+            );
+        }
+        self.bet_db
+            .start_chat(bet_id, BetChat::new(proposal, true, offer_public_key))?;
+
         Ok(bet.psbt)
     }
 }
@@ -5,7 +5,7 @@ use crate::{
     change::{BinScript, Change},
 };
 use anyhow::anyhow;
-use bdk::bitcoin::{self, Amount};
+use bdk::bitcoin::{self, Address, Amount};
 use olivia_core::EventId;
 
 #[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -30,6 +30,11 @@ pub struct Proposal {
     pub inputs: Vec<bdk::bitcoin::OutPoint>,
     pub public_key: Point<EvenY>,
     pub change_script: Option<BinScript>,
+    /// The key that has to sign with the proposer's to claim (see [`BetCosigner`]). It's left out
+    /// of the encoding when there isn't one so the bet key of a proposal without one doesn't
+    /// change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosigner: Option<Point>,
 }
 
 impl Proposal {
@@ -45,6 +50,12 @@ pub struct LocalProposal {
     pub change: Option<Change>,
     #[serde(default)]
     pub tags: Vec<String>,
+    /// Where the bet made from this proposal is claimed to (see [`Bet::claim_to`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_to: Option<Address>,
+    /// Where the key in [`Proposal::cosigner`] comes from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosigner: Option<CosignerKey>,
 }
 
 #[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
@@ -54,6 +65,15 @@ pub struct Payload {
     pub change_script: Option<BinScript>,
 }
 
+/// A [`Payload`] followed by the proposer's cosigner. Clients that don't know about cosigners
+/// decode it as a `Payload` (trailing bytes are ignored) and their offer doesn't make the output
+/// the proposer expects so it's never taken.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CosignedPayload {
+    pub payload: Payload,
+    pub cosigner: Point,
+}
+
 impl Proposal {
     pub fn to_sentence(&self) -> String {
         format!(
@@ -72,6 +92,12 @@ impl core::fmt::Display for VersionedProposal {
                     public_key: proposal.public_key,
                     change_script: proposal.change_script.clone(),
                 };
+                let payload = match proposal.cosigner {
+                    Some(cosigner) => {
+                        crate::encode::serialize_base2048(&CosignedPayload { payload, cosigner })
+                    }
+                    None => crate::encode::serialize_base2048(&payload),
+                };
                 write!(
                     f,
                     "{}#{}#{}#{}",
@@ -82,7 +108,7 @@ impl core::fmt::Display for VersionedProposal {
                         .trim_end_matches('0'),
                     proposal.oracle,
                     proposal.event_id,
-                    crate::encode::serialize_base2048(&payload)
+                    payload
                 )
             }
         }
@@ -107,7 +133,15 @@ impl FromStr for VersionedProposal {
             .next()
             .ok_or(anyhow!("missing base2048 encoded data"))?;
 
-        let payload: Payload = crate::encode::deserialize_base2048(base2048_encoded_payload)?;
+        let (payload, cosigner) = match crate::encode::deserialize_base2048::<CosignedPayload>(
+            base2048_encoded_payload,
+        ) {
+            Ok(CosignedPayload { payload, cosigner }) => (payload, Some(cosigner)),
+            Err(_) => (
+                crate::encode::deserialize_base2048::<Payload>(base2048_encoded_payload)?,
+                None,
+            ),
+        };
 
         Ok(VersionedProposal::One(Proposal {
             oracle,
@@ -116,6 +150,7 @@ impl FromStr for VersionedProposal {
             inputs: payload.inputs,
             public_key: payload.public_key,
             change_script: payload.change_script,
+            cosigner,
         }))
     }
 }
@@ -124,7 +159,7 @@ impl FromStr for VersionedProposal {
 mod test {
     use super::*;
     use bdk::bitcoin::{hashes::Hash, Address, OutPoint, Txid};
-    use olivia_secp256k1::schnorr_fun::fun::{s, G};
+    use olivia_secp256k1::schnorr_fun::fun::{marker::Normal, s, G};
 
     #[test]
     fn to_and_from_str() {
@@ -143,6 +178,7 @@ mod test {
             ],
             public_key: forty_two,
             change_script: None,
+            cosigner: None,
         };
 
         let encoded = proposal.clone().into_versioned().to_string();
@@ -154,6 +190,15 @@ mod test {
         let encoded = proposal.clone().into_versioned().to_string();
         let decoded = VersionedProposal::from_str(&encoded).unwrap();
         assert_eq!(proposal, decoded.into());
+
+        proposal.cosigner = Some(forty_two.mark::<Normal>());
+        let encoded = proposal.clone().into_versioned().to_string();
+        let decoded = VersionedProposal::from_str(&encoded).unwrap();
+        assert_eq!(proposal, decoded.into());
+        // clients that don't know about cosigners still read the rest
+        let payload = encoded.rsplit('#').next().unwrap();
+        let old: Payload = crate::encode::deserialize_base2048(payload).unwrap();
+        assert_eq!(old.public_key, proposal.public_key);
     }
 
     #[test]
@@ -179,6 +224,7 @@ mod test {
                     .script_pubkey()
                     .into(),
             ),
+            cosigner: None,
         });
 
         let string =  "0.01#h00.ooo#/EPL/match/2021-08-22/ARS_CHE.vs=CHE_win#ǔ༖ǼभݸჷતϧષগழਞഹเϕॐಋచଚڮݻɈపŉɋʍҞɒŴݦസӫၒӵݎஜؽͼɹঊڄՓॠఖஷߣၦაŐƍۂʎӯسՉهƽཀލǂޞඤӖყଋم༎";
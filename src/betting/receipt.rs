@@ -0,0 +1,327 @@
+use crate::{betting::*, exit_code::ErrorKind, keychain::KeyPair};
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{
+    self,
+    consensus::encode,
+    hashes::{sha256, Hash, HashEngine},
+    Amount, Transaction, Txid,
+};
+use olivia_core::{chrono::NaiveDateTime, Outcome};
+use olivia_secp256k1::schnorr_fun::{
+    fun::{g, marker::*, Point, G},
+    nonce::Deterministic,
+    Message, MessageKind, Schnorr, Signature,
+};
+use sha2::Sha256;
+use std::convert::TryInto;
+
+/// What shows how a bet that the oracle has attested to went: the proposal, what the offer put in,
+/// the oracle's signed announcement and attestation and the bet transaction with both sides'
+/// signatures. It's signed with the key we used in the bet which the other side knows from the
+/// proposal or offer so either side can show it to the other or to anyone else.
+///
+/// Everything but the claim can be checked without the wallet that made it. The bet output is
+/// checked to be made from the two bet keys, the oracle's anticipated attestations and the tweaks
+/// `r1 * G` and `r2 * G` (the scalars stay secret).
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BetReceipt {
+    pub proposal: Proposal,
+    pub offer: ReceiptOffer,
+    pub oracle: OracleInfo,
+    /// The announcement and attestation as the oracle signed them
+    pub event_response: EventResponse,
+    /// The bet transaction (hex)
+    pub bet_tx: String,
+    pub funding_txid: Txid,
+    pub vout: u32,
+    /// `r1 * G` and `r2 * G`
+    pub output_tweaks: [Point; 2],
+    pub swapped: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_txid: Option<Txid>,
+    pub made_at: NaiveDateTime,
+    /// The bet key of the side that made the receipt
+    pub signer: PublicKey,
+    /// BIP340 signature by `signer` (hex)
+    #[serde(default)]
+    pub signature: String,
+}
+
+/// What the offer that took the proposal put into the bet.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReceiptOffer {
+    pub public_key: PublicKey,
+    pub choose_right: bool,
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    pub value: Amount,
+    /// See [`Offer::cosigner`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cosigner: Option<Point>,
+}
+
+/// What a receipt shows once it's checked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedReceipt {
+    pub outcome: String,
+    pub proposer_won: bool,
+    pub signed_by_proposer: bool,
+    pub joint_output_value: Amount,
+}
+
+fn schnorr() -> Schnorr<Sha256, Deterministic<Sha256>> {
+    Schnorr::new(Deterministic::<Sha256>::default(), MessageKind::Prehashed)
+}
+
+impl BetReceipt {
+    /// What's signed: everything but the signature.
+    fn digest(&self) -> [u8; 32] {
+        let mut unsigned = self.clone();
+        unsigned.signature = String::new();
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"gun-bet-receipt");
+        engine.input(&serde_json::to_vec(&unsigned).expect("receipts always serialize"));
+        sha256::Hash::from_engine(engine).into_inner()
+    }
+
+    fn sign(&mut self, keypair: &KeyPair) {
+        self.signer = keypair.public_key;
+        let schnorr = schnorr();
+        let signing_keypair = schnorr.new_keypair(keypair.secret_key.clone());
+        let sig = schnorr.sign(&signing_keypair, Message::<Public>::raw(&self.digest()[..]));
+        self.signature = crate::hex::encode(&sig.to_bytes()[..]);
+    }
+
+    pub fn bet_tx(&self) -> anyhow::Result<Transaction> {
+        let bytes = crate::hex::decode(&self.bet_tx).map_err(|_| anyhow!("bet-tx isn't hex"))?;
+        Ok(encode::deserialize(&bytes).context("decoding bet-tx")?)
+    }
+
+    /// Checks the receipt was signed by one side of the bet, the oracle attested to the outcome
+    /// and the bet transaction pays to the output the two sides and the oracle agreed on.
+    pub fn verify(&self) -> anyhow::Result<VerifiedReceipt> {
+        let signed_by_proposer = if self.signer == self.proposal.public_key {
+            true
+        } else if self.signer == self.offer.public_key {
+            false
+        } else {
+            return Err(anyhow!(
+                "it's signed by {} which is neither side's key",
+                self.signer
+            ));
+        };
+        let sig = crate::hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .and_then(|bytes: [u8; 64]| Signature::from_bytes(bytes))
+            .ok_or(anyhow!("the signature is invalid"))?;
+        if !schnorr().verify(
+            &self.signer,
+            Message::<Public>::raw(&self.digest()[..]),
+            &sig,
+        ) {
+            return Err(anyhow!(
+                "the signature is invalid -- the receipt was changed after it was made"
+            ));
+        }
+
+        if self.oracle.id != self.proposal.oracle {
+            return Err(anyhow!(
+                "the oracle is {} but the bet was on {}",
+                self.oracle.id,
+                self.proposal.oracle
+            ));
+        }
+        let oracle_event =
+            verify_response(&self.oracle, &self.proposal.event_id, &self.event_response)
+                .context("checking the oracle's attestation")?;
+        let attestation = self
+            .event_response
+            .attestation
+            .as_ref()
+            .expect("verify_response checks there's an attestation");
+        let outcome =
+            Outcome::try_from_id_and_outcome(self.proposal.event_id.clone(), &attestation.outcome)
+                .context("parsing the outcome")?;
+
+        let tx = self.bet_tx()?;
+        if tx.txid() != self.funding_txid {
+            return Err(anyhow!("bet-tx is {} not {}", tx.txid(), self.funding_txid));
+        }
+        if let Some(input) = self
+            .proposal
+            .inputs
+            .iter()
+            .find(|input| !tx.input.iter().any(|txin| txin.previous_output == **input))
+        {
+            return Err(anyhow!(
+                "the bet transaction doesn't spend {} from the proposal",
+                input
+            ));
+        }
+
+        let oracle_points: [Point<Jacobian, Public, Zero>; 2] = self
+            .oracle
+            .oracle_keys
+            .olivia_v1
+            .as_ref()
+            .and_then(|key| oracle_event.anticipate_attestations_olivia_v1(key, 0))
+            .and_then(|points| points[..].get(..2)?.try_into().ok())
+            .ok_or(anyhow!(
+                "couldn't anticipate {}'s attestations",
+                self.oracle.id
+            ))?;
+        let (left, right) = (oracle_points[0], oracle_points[1]);
+        let (proposal_point, offer_point) = match self.offer.choose_right {
+            false => (right, left),
+            true => (left, right),
+        };
+        let (proposal_key, offer_key) = (&self.proposal.public_key, &self.offer.public_key);
+        let (r1, r2) = (&self.output_tweaks[0], &self.output_tweaks[1]);
+        let output_keys = [
+            g!(proposal_key + proposal_point + r1).mark::<(Normal, NonZero)>(),
+            g!(offer_key + offer_point + r2).mark::<(Normal, NonZero)>(),
+        ];
+        let output_keys = match output_keys {
+            [Some(proposal_output_key), Some(offer_output_key)] => {
+                [proposal_output_key, offer_output_key]
+            }
+            _ => return Err(anyhow!("the output keys add up to nothing")),
+        };
+        let joint_output_value = self
+            .proposal
+            .value
+            .checked_add(self.offer.value)
+            .ok_or(anyhow!("the bet is worth more than there is"))?;
+        let expected = bdk::bitcoin::TxOut {
+            value: joint_output_value.as_sat(),
+            script_pubkey: descriptor_of(
+                output_keys,
+                [self.proposal.cosigner, self.offer.cosigner],
+                self.swapped,
+            )
+            .script_pubkey(),
+        };
+        if tx.output.get(self.vout as usize) != Some(&expected) {
+            return Err(anyhow!(
+                "output {} of the bet transaction isn't the bet of the proposal and offer on the oracle's event",
+                self.vout
+            ));
+        }
+
+        Ok(VerifiedReceipt {
+            outcome: attestation.outcome.clone(),
+            proposer_won: (outcome.value == 1) != self.offer.choose_right,
+            signed_by_proposer,
+            joint_output_value,
+        })
+    }
+}
+
+impl<D> Party<bdk::blockchain::EsploraBlockchain, D>
+where
+    D: bdk::database::BatchDatabase,
+{
+    /// Makes a signed receipt for a bet the oracle has attested to.
+    pub fn bet_receipt(&self, bet_id: BetId) -> anyhow::Result<BetReceipt> {
+        let bet_state = self
+            .bet_db()
+            .get_entity::<BetState>(bet_id)?
+            .ok_or(ErrorKind::NotFound.error(format!("bet {} doesn\'t exist", bet_id)))?;
+        let (bet, attestation, claim_txid) = match bet_state {
+            BetState::Won {
+                bet, attestation, ..
+            }
+            | BetState::Lost { bet, attestation } => (bet, attestation, None),
+            BetState::Claimed {
+                bet,
+                attestation,
+                txid,
+                ..
+            } => (bet, attestation, Some(txid)),
+            bet_state => {
+                return Err(anyhow!(
+                    "bet {} is {} -- there's only a receipt once the oracle has attested to the outcome",
+                    bet_id,
+                    bet_state.name()
+                ))
+            }
+        };
+        let chat = self.bet_db().get_entity::<BetChat>(bet_id)?.ok_or(anyhow!(
+            "bet {} was made before gun kept the proposal bets were made from so it can't have a receipt",
+            bet_id
+        ))?;
+        let oracle = self
+            .bet_db()
+            .get_entity::<OracleInfo>(bet.oracle_id.clone())?
+            .ok_or(anyhow!("oracle {} isn't in the database", bet.oracle_id))?;
+
+        let event_id = &bet.oracle_event.event.id;
+        let seen = self
+            .bet_db()
+            .get_entity::<SeenAttestations>(SeenAttestations::key(&bet.oracle_id, event_id))?;
+        let event_response = match seen.and_then(|seen| {
+            seen.responses.into_iter().find(|response| {
+                response.attestation.as_ref().map(|a| &a.outcome) == Some(&attestation.outcome)
+            })
+        }) {
+            Some(event_response) => event_response,
+            None => {
+                // we didn't keep the announcement the oracle signed so get it again
+                let event_url = crate::endpoint::oracle_event_url(&bet.oracle_id, event_id)?;
+                self.settings()
+                    .oracle_sources
+                    .for_url(&event_url)
+                    .event(&event_url)
+                    .context("getting the oracle's announcement and attestation")?
+            }
+        };
+
+        let keypair = match chat.i_proposed {
+            true => self.keychain.get_key_for_proposal(&chat.proposal),
+            false => self.keychain.keypair_for_offer(&chat.proposal),
+        };
+        let (_, mut rng) = crate::ecdh::ecdh(&keypair, &chat.remote_public_key);
+        let Randomize {
+            r1,
+            r2,
+            swap_points,
+        } = Randomize::new(&mut rng);
+        let offer = ReceiptOffer {
+            public_key: match chat.i_proposed {
+                true => chat.remote_public_key,
+                false => keypair.public_key,
+            },
+            choose_right: match chat.i_proposed {
+                true => !bet.i_chose_right,
+                false => bet.i_chose_right,
+            },
+            value: bet
+                .joint_output_value
+                .checked_sub(chat.proposal.value)
+                .ok_or(anyhow!("the bet is worth less than the proposal"))?,
+            cosigner: bet.joint_output.cosigners[1],
+        };
+        let tx = bet.tx();
+
+        let mut receipt = BetReceipt {
+            proposal: chat.proposal,
+            offer,
+            oracle,
+            event_response,
+            bet_tx: encode::serialize_hex(&tx),
+            funding_txid: tx.txid(),
+            vout: bet.vout,
+            output_tweaks: [g!(r1 * G).mark::<Normal>(), g!(r2 * G).mark::<Normal>()],
+            swapped: swap_points,
+            claim_txid,
+            made_at: crate::clock::now(),
+            signer: keypair.public_key,
+            signature: String::new(),
+        };
+        receipt.sign(&keypair);
+        receipt.verify().context("the receipt doesn't check out")?;
+        Ok(receipt)
+    }
+}
@@ -0,0 +1,177 @@
+//! Working out what a bet on a proposal would look like on chain without making it.
+use crate::{
+    betting::Proposal,
+    coin_select::{P2WPKH_INPUT_VBYTES, P2WPKH_OUTPUT_VBYTES, TX_OVERHEAD_VBYTES},
+};
+use anyhow::anyhow;
+use bdk::{
+    bitcoin::{Amount, SignedAmount},
+    wallet::IsDust,
+    FeeRate,
+};
+use olivia_core::chrono::{NaiveDate, NaiveDateTime};
+
+/// A p2wsh output (the joint output is `wsh(or(pk(A),pk(B)))`).
+const P2WSH_OUTPUT_VBYTES: f32 = 43.0;
+/// Spending the joint output with the key the oracle's attestation completes. The witness is a
+/// signature, an empty element to skip the other key and the witness script.
+const JOINT_OUTPUT_INPUT_VBYTES: f32 = 41.0 + (1.0 + 73.0 + 1.0 + 1.0 + 70.0) / 4.0;
+
+/// What the coins would do if the bet went ahead and the winner claimed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    pub joint_output_value: Amount,
+    pub bet_tx_vsize: u64,
+    /// Paid by the offerer
+    pub bet_tx_fee: Amount,
+    pub claim_tx_vsize: u64,
+    /// Paid by the winner out of the joint output
+    pub claim_tx_fee: Amount,
+    pub offerer_wins: bool,
+    pub proposer_net: SignedAmount,
+    pub offerer_net: SignedAmount,
+}
+
+impl Simulation {
+    /// The coins that end up in the winner's wallet once they've claimed.
+    pub fn payout(&self) -> Amount {
+        self.joint_output_value - self.claim_tx_fee
+    }
+}
+
+/// Simulates an offer of `offer_value` (spending `offer_inputs` p2wpkh coins with change) being
+/// taken and then claimed by the winner at `feerate`.
+pub fn simulate(
+    proposal: &Proposal,
+    offer_value: Amount,
+    offer_inputs: usize,
+    offerer_wins: bool,
+    feerate: FeeRate,
+) -> anyhow::Result<Simulation> {
+    if offer_value == Amount::ZERO {
+        return Err(anyhow!("the offer has to risk something"));
+    }
+    let inputs = (proposal.inputs.len() + offer_inputs) as f32;
+    let change_outputs = 1.0 + proposal.change_script.is_some() as u8 as f32;
+    let bet_tx_vsize = (TX_OVERHEAD_VBYTES
+        + inputs * P2WPKH_INPUT_VBYTES
+        + P2WSH_OUTPUT_VBYTES
+        + change_outputs * P2WPKH_OUTPUT_VBYTES)
+        .ceil() as u64;
+    let claim_tx_vsize =
+        (TX_OVERHEAD_VBYTES + JOINT_OUTPUT_INPUT_VBYTES + P2WPKH_OUTPUT_VBYTES).ceil() as u64;
+    let fee = |vsize: u64| Amount::from_sat((feerate.as_sat_vb() * vsize as f32).ceil() as u64);
+    let bet_tx_fee = fee(bet_tx_vsize);
+    let claim_tx_fee = fee(claim_tx_vsize);
+
+    let joint_output_value = proposal.value + offer_value;
+    if joint_output_value
+        .checked_sub(claim_tx_fee)
+        .map(|payout| payout.as_sat().is_dust())
+        .unwrap_or(true)
+    {
+        return Err(anyhow!(
+            "the winner couldn't claim {} at {} sats per vbyte",
+            joint_output_value,
+            feerate.as_sat_vb()
+        ));
+    }
+
+    let signed = |amount: Amount| amount.to_signed().expect("bitcoin amounts fit");
+    let loser_stake = match offerer_wins {
+        true => proposal.value,
+        false => offer_value,
+    };
+    let winner_net = signed(loser_stake) - signed(claim_tx_fee);
+    let loser_net = -signed(loser_stake);
+    let (proposer_net, offerer_net) = match offerer_wins {
+        true => (loser_net, winner_net - signed(bet_tx_fee)),
+        false => (winner_net, loser_net - signed(bet_tx_fee)),
+    };
+
+    Ok(Simulation {
+        joint_output_value,
+        bet_tx_vsize,
+        bet_tx_fee,
+        claim_tx_vsize,
+        claim_tx_fee,
+        offerer_wins,
+        proposer_net,
+        offerer_net,
+    })
+}
+
+/// The time written into an event id like `/random/2020-09-25T08:00:00/heads_tails.winner` (or
+/// just the day).
+pub fn event_id_time(event_id: &olivia_core::EventId) -> Option<NaiveDateTime> {
+    event_id.to_string().split('/').find_map(|segment| {
+        NaiveDateTime::parse_from_str(segment, "%Y-%m-%dT%H:%M:%S")
+            .ok()
+            .or_else(|| {
+                NaiveDate::parse_from_str(segment, "%Y-%m-%d")
+                    .ok()
+                    .map(|date| date.and_hms(0, 0, 0))
+            })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{hashes::Hash, OutPoint, Txid};
+    use olivia_core::EventId;
+    use olivia_secp256k1::schnorr_fun::fun::{marker::*, s, Point, G};
+    use std::str::FromStr;
+
+    fn proposal(inputs: usize) -> Proposal {
+        Proposal {
+            oracle: "h00.ooo".into(),
+            value: Amount::from_sat(100_000),
+            event_id: EventId::from_str("/random/2020-09-25T08:00:00/heads_tails.winner").unwrap(),
+            inputs: (0..inputs)
+                .map(|i| OutPoint::new(Txid::from_slice(&[1u8; 32]).unwrap(), i as u32))
+                .collect(),
+            public_key: Point::<EvenY>::from_scalar_mul(G, &mut s!(42)),
+            change_script: None,
+            cosigner: None,
+        }
+    }
+
+    #[test]
+    fn winner_takes_the_pot_minus_fees() {
+        let feerate = FeeRate::from_sat_per_vb(2.0);
+        let proposal = proposal(1);
+        let simulation = simulate(&proposal, Amount::from_sat(50_000), 1, false, feerate).unwrap();
+        // 10.5 + 2 * 68 + 43 + 31
+        assert_eq!(simulation.bet_tx_vsize, 221);
+        assert_eq!(simulation.bet_tx_fee, Amount::from_sat(442));
+        assert_eq!(
+            simulation.payout(),
+            simulation.joint_output_value - simulation.claim_tx_fee
+        );
+        assert_eq!(
+            simulation.proposer_net,
+            SignedAmount::from_sat(50_000 - simulation.claim_tx_fee.as_sat() as i64)
+        );
+        assert_eq!(simulation.offerer_net, SignedAmount::from_sat(-50_442));
+
+        let simulation = simulate(&proposal, Amount::from_sat(50_000), 1, true, feerate).unwrap();
+        assert_eq!(simulation.proposer_net, SignedAmount::from_sat(-100_000));
+        assert_eq!(
+            simulation.offerer_net,
+            SignedAmount::from_sat(100_000 - 442 - simulation.claim_tx_fee.as_sat() as i64)
+        );
+    }
+
+    #[test]
+    fn time_from_event_id() {
+        assert_eq!(
+            event_id_time(&proposal(1).event_id),
+            Some(NaiveDate::from_ymd(2020, 9, 25).and_hms(8, 0, 0))
+        );
+        assert_eq!(
+            event_id_time(&EventId::from_str("/EPL/match/2021-08-22/ARS_CHE.vs=CHE_win").unwrap()),
+            Some(NaiveDate::from_ymd(2021, 8, 22).and_hms(0, 0, 0))
+        );
+    }
+}
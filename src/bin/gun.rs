@@ -1,5 +1,15 @@
-use gun_wallet::cmd::{
-    self, bet::BetOpt, AddressOpt, InitOpt, SendOpt, SplitOpt, TransactionOpt, UtxoOpt,
+#[cfg(feature = "betting")]
+use gun_wallet::cmd::{bet::BetOpt, SelftestOpt};
+use gun_wallet::{
+    amount_ext::{set_display_unit, AmountUnit},
+    cmd::{
+        self, AddressOpt, AllowanceOpt, ApprovalOpt, AuditOpt, BackendOpt, BackupOpt,
+        ColdStorageOpt, ConfigOpt, DbOpt, DevOpt, ExportOpt, FeesOpt, FundPsbtOpt, InitOpt,
+        PsbtOpt, RotateOpt, ScanPathsOpt, ScheduleOpt, SendOpt, SplitOpt, StateOpt,
+        SweepDescriptorOpt, SweepKeyOpt, TransactionOpt, UndoOpt, UtxoOpt, WatchOpt,
+    },
+    exit_code::{self, ErrorKind},
+    i18n::{set_locale, Locale},
 };
 use std::path::PathBuf;
 use structopt::StructOpt;
@@ -13,19 +23,63 @@ pub struct Opt {
     #[structopt(subcommand)]
     command: Commands,
     #[structopt(short, long)]
-    /// Tell the wallet to sync itself.
+    /// Tell the wallet to sync itself. Only what the command needs is synced e.g. nothing for `gun
+    /// address new`, the wallet's own scripts for `gun balance` and only bets for `gun bet claim`.
     sync: bool,
     #[structopt(short, long)]
     /// Return output in JSON format
     json: bool,
+    /// Write errors to stderr as JSON with the kind of failure and its exit code: 1 other, 2 bad
+    /// input, 3 network, 4 insufficient funds, 5 policy, 6 locked, 7 not found and 8 config
+    #[structopt(long)]
+    json_errors: bool,
     /// Return outupt in simplified UNIX table (tabs and newlines)
     #[structopt(short, long)]
     tabs: bool,
+    /// Put a block explorer link under every txid and address
+    #[structopt(long)]
+    links: bool,
+    /// Show amounts in this unit (BTC, mBTC, bits or sat) instead of the `display-unit` from the
+    /// config
+    #[structopt(long)]
+    unit: Option<AmountUnit>,
+    /// Refuse to sign or broadcast anything. Queries, decoding and dry runs still work.
+    #[structopt(long, env = "GUN_READ_ONLY")]
+    read_only: bool,
+    /// Use a wallet on NETWORK that is only kept in memory e.g. to sweep a paper wallet or look at
+    /// a seed on a live USB. Its seed words or descriptors (external then optionally internal) are
+    /// read from GUN_EPHEMERAL_KEYS or stdin and nothing is written to the wallet directory.
+    #[structopt(long, value_name = "NETWORK")]
+    ephemeral: Option<bdk::bitcoin::Network>,
+    /// Make the random choices in building transactions (e.g. coin selection) from this seed so
+    /// the same wallet state always gives the same transaction. For tests and audits only.
+    #[structopt(long, env = gun_wallet::deterministic::SEED_ENV, hide_env_values = true)]
+    deterministic_seed: Option<String>,
+    /// Act as if it's this time (a UNIX timestamp or UTC time like 2021-06-01T12:00:00) when
+    /// deciding what has expired or is due. For tests and audits only. On mainnet it needs
+    /// --read-only.
+    #[structopt(long, env = gun_wallet::clock::NOW_ENV, parse(try_from_str = gun_wallet::clock::parse))]
+    now: Option<gun_wallet::chrono::NaiveDateTime>,
+    /// Copy the main thing the command outputs (e.g. an address, proposal or offer) to the
+    /// clipboard. It's read back to check nothing swapped it.
+    #[structopt(long)]
+    copy: bool,
+    /// Show the main thing the command outputs (e.g. an address or proposal) as a QR code on
+    /// stderr. See `gun address qr` for bigger or inverted ones.
+    #[structopt(long)]
+    qr: bool,
+    /// Log more to stderr (-v for info, -vv for debug, -vvv for everything)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Also log to this file. A new one is started each day with the date appended to the name.
+    #[structopt(long, parse(from_os_str), env = "GUN_LOG_FILE")]
+    log_file: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum Commands {
     /// Make or take a bet
+    #[cfg(feature = "betting")]
     Bet(BetOpt),
     /// View the balance of the wallet
     Balance,
@@ -41,10 +95,169 @@ pub enum Commands {
     Init(InitOpt),
     /// Split coins into evenly sized outputs.
     Split(SplitOpt),
+    /// Sign transactions somewhere else (e.g. a Coldcard) and broadcast them
+    Psbt(PsbtOpt),
+    /// Export the wallet for use in other software
+    Export(ExportOpt),
+    /// Make large sends need a second person to approve them
+    Approval(ApprovalOpt),
+    /// Check the log of everything the wallet has signed and broadcast
+    Audit(AuditOpt),
+    /// Back up and restore what can't be recovered from the seed words
+    Backup(BackupOpt),
+    /// See or change the backend the wallet gets blocks and transactions from
+    Backend(BackendOpt),
+    /// Check config.json for mistakes
+    Config(ConfigOpt),
+    /// Look after the wallet's database
+    Db(DbOpt),
+    /// Things for trying gun out on test networks e.g. getting coins from a faucet
+    Dev(DevOpt),
+    /// Check for problems with the wallet, its database and the servers it uses
+    Doctor,
+    /// Check that everything from receiving coins to claiming a bet still works by doing it with
+    /// two new wallets on a local regtest node
+    #[cfg(feature = "betting")]
+    Selftest(SelftestOpt),
+    /// Show what identifies the wallet (fingerprint, public descriptors, backend and versions)
+    /// with nothing secret in it to paste into support requests
+    Whoami,
+    /// Show what it costs to get a transaction confirmed right now
+    Fees(FeesOpt),
+    /// Fund the outputs of an externally made PSBT (e.g. a lightning channel)
+    FundPsbt(FundPsbtOpt),
+    /// Move the coins of a private key (e.g. a paper wallet) into the wallet without importing it
+    SweepKey(SweepKeyOpt),
+    /// Move the coins on the addresses of a descriptor into the wallet
+    SweepDescriptor(SweepDescriptorOpt),
+    /// Look for coins on the derivation paths other wallets use for the seed words (BIP44, BIP49,
+    /// BIP84 and BIP86) and sweep them into the wallet
+    ScanPaths(ScanPathsOpt),
+    /// Keep an eye on another wallet's bets without its keys
+    Watch(WatchOpt),
+    /// Keep a small amount on another device and take back what's left later
+    Allowance(AllowanceOpt),
+    /// Make recurring payments
+    Schedule(ScheduleOpt),
+    /// Sweep what's above a ceiling in the hot wallet to cold storage
+    ColdStorage(ColdStorageOpt),
+    /// Move everything to a new wallet with new seed words e.g. after the seed words might have
+    /// been seen
+    Rotate(RotateOpt),
+    /// Snapshot the wallet's state and compare snapshots
+    State(StateOpt),
+    /// Undo the last label, note, freeze, tag or forgotten bet
+    Undo(UndoOpt),
+    /// Unlock the seed words for a while when the wallet has a spending lock
+    Unlock {
+        /// How long until it locks again e.g. 90s, 15m or 2h
+        #[structopt(long = "for", default_value = gun_wallet::session::DEFAULT_SESSION)]
+        length: String,
+    },
+    /// End the spending session (see `gun unlock`)
+    Lock {
+        /// Set the spending passphrase and encrypt the seed words with it so using the wallet
+        /// needs `gun unlock` from now on
+        #[structopt(long)]
+        setup: bool,
+        /// Remove the spending lock. The seed words go back in seed.txt unencrypted.
+        #[structopt(long, conflicts_with = "setup")]
+        remove: bool,
+        /// Make the wallet in this directory (made with `gun -d <dir> init`) a decoy that
+        /// unlocking with the decoy passphrase shows instead of this one. It's moved into this
+        /// wallet's directory under a name only the decoy passphrase gives.
+        #[structopt(long, parse(from_os_str), conflicts_with_all = &["setup", "remove"])]
+        decoy: Option<PathBuf>,
+    },
+    /// Hold a spending session open (started by `gun unlock`)
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    Agent {
+        #[structopt(long)]
+        until: i64,
+    },
+    /// Open a transaction or address on the block explorer
+    Open {
+        /// The txid or address
+        id: String,
+    },
+    /// Run an external `gun-<name>` command from $PATH
+    #[structopt(external_subcommand)]
+    External(Vec<String>),
 }
 
-fn main() -> anyhow::Result<()> {
-    let opt = Opt::from_args();
+fn main() {
+    let opt = match Opt::from_args_safe() {
+        Ok(opt) => opt,
+        // --help and --version aren't failures
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            match std::env::args().any(|arg| arg == "--json-errors") {
+                true => report_error(&ErrorKind::BadInput.error(e.message), false, true),
+                false => eprintln!("{}", e.message),
+            }
+            std::process::exit(ErrorKind::BadInput.code())
+        }
+    };
+    let (json, json_errors) = (opt.json, opt.json_errors);
+    let log_guard = match gun_wallet::logging::init(opt.verbose, opt.log_file.as_deref()) {
+        Ok(log_guard) => log_guard,
+        Err(e) => {
+            report_error(&e, json, json_errors);
+            std::process::exit(exit_code::classify(&e).code())
+        }
+    };
+    let code = match run(opt) {
+        Ok(code) => code,
+        Err(e) => {
+            tracing::debug!("command failed: {:?}", e);
+            report_error(&e, json, json_errors);
+            exit_code::classify(&e).code()
+        }
+    };
+    // exiting doesn't run destructors so the log has to be flushed and the temporary database
+    // deleted first
+    gun_wallet::ephemeral::disable();
+    drop(log_guard);
+    std::process::exit(code)
+}
+
+/// Writes `error` to stderr (or stdout with `--json` so it's where the output would have been).
+fn report_error(error: &anyhow::Error, json: bool, json_errors: bool) {
+    if json || json_errors {
+        let error_json = serde_json::to_string_pretty(&exit_code::error_json(error)).unwrap();
+        match json {
+            true => println!("{}", error_json),
+            false => eprintln!("{}", error_json),
+        }
+    } else {
+        eprintln!("Error: {:?}", error);
+    }
+}
+
+/// What `command` does that can't be done with an `--ephemeral` wallet.
+fn needs_a_kept_wallet(command: &Commands) -> Option<&'static str> {
+    use Commands::*;
+    Some(match command {
+        Init(_) => "initialize a wallet",
+        #[cfg(feature = "betting")]
+        Bet(_) => "make or claim bets",
+        Backup(_) => "back up or restore",
+        Rotate(_) => "rotate",
+        Db(_) => "look after the database",
+        Config(_) => "check config.json",
+        Doctor => "check the wallet directory",
+        #[cfg(feature = "betting")]
+        Selftest(_) => "run the self-test",
+        Watch(_) => "watch another wallet",
+        Schedule(_) => "schedule payments",
+        Unlock { .. } | Lock { .. } | Agent { .. } => "lock or unlock",
+        External(_) => "run external commands",
+        _ => return None,
+    })
+}
+
+/// Runs the command and returns what to exit with.
+fn run(opt: Opt) -> anyhow::Result<i32> {
     let sync = opt.sync;
 
     let wallet_dir = opt.gun_dir.unwrap_or_else(|| {
@@ -53,24 +266,115 @@ fn main() -> anyhow::Result<()> {
         default_dir.push(".gun");
         default_dir
     });
+    if let Some(network) = opt.ephemeral {
+        if let Some(action) = needs_a_kept_wallet(&opt.command) {
+            return Err(gun_wallet::ephemeral::unsupported(action));
+        }
+        let keys = match std::env::var(gun_wallet::ephemeral::KEYS_ENV) {
+            Ok(keys) => keys,
+            Err(_) => {
+                use std::io::BufRead;
+                eprint!("seed words or descriptors: ");
+                let mut line = String::new();
+                std::io::stdin().lock().read_line(&mut line)?;
+                line
+            }
+        };
+        let keys =
+            gun_wallet::ephemeral::Keys::parse(&keys).map_err(|e| ErrorKind::BadInput.wrap(e))?;
+        gun_wallet::ephemeral::enable(network, keys)?;
+    }
+    // locking and unlocking are about the wallet that was asked for but everything else has to use
+    // the decoy's directory during a decoy session
+    let wallet_dir = match opt.command {
+        Commands::Unlock { .. } | Commands::Lock { .. } | Commands::Agent { .. } => wallet_dir,
+        _ => cmd::select_wallet_dir(&wallet_dir),
+    };
+
+    gun_wallet::fee_snapshot::keep_in(&wallet_dir);
+
+    // these show the problems themselves and an ephemeral wallet doesn't use config.json
+    if !matches!(opt.command, Commands::Config(_) | Commands::Doctor) && opt.ephemeral.is_none() {
+        cmd::warn_about_config(&wallet_dir);
+    }
+    let config = cmd::load_config(&wallet_dir).ok();
+    if let Some(display_unit) = opt
+        .unit
+        .or_else(|| config.as_ref().map(|config| config.display_unit))
+    {
+        set_display_unit(display_unit);
+    }
+    // a locale gun doesn't have in the config is warned about by the config check
+    set_locale(
+        config
+            .as_ref()
+            .and_then(|config| config.locale.as_deref())
+            .and_then(Locale::from_tag)
+            .or_else(Locale::from_env)
+            .unwrap_or_default(),
+    );
+    if let Some(config) = &config {
+        gun_wallet::endpoint::set_proxies(config.proxies.clone())?;
+        gun_wallet::endpoint::proxy_backend(&cmd::backend_url(&config.blockchain));
+    }
+    if opt.read_only
+        || config
+            .as_ref()
+            .map(|config| config.read_only)
+            .unwrap_or(false)
+    {
+        gun_wallet::read_only::enable();
+    }
+    if let Some(seed) = &opt.deterministic_seed {
+        if config.as_ref().map(|config| config.network) == Some(bdk::bitcoin::Network::Bitcoin) {
+            return Err(anyhow::anyhow!(
+                "deterministic mode makes it possible to predict which coins are spent so it can't be used on mainnet"
+            ));
+        }
+        gun_wallet::deterministic::set_seed(seed);
+    }
+    if let Some(now) = opt.now {
+        if config.as_ref().map(|config| config.network) == Some(bdk::bitcoin::Network::Bitcoin)
+            && !gun_wallet::read_only::is_enabled()
+        {
+            return Err(ErrorKind::BadInput.error(
+                "--now could make gun act on expiries that haven't happened so on mainnet it only works with --read-only",
+            ));
+        }
+        gun_wallet::clock::pin(now);
+    }
 
     if sync {
+        use gun_wallet::betting::SyncScope;
         use Commands::*;
 
-        if let Balance | Address(_) | Send(_) | Tx(_) | Utxo(_) = opt.command {
-            let (wallet, _, _, config) = cmd::load_wallet(&wallet_dir)?;
-            eprintln!("syncing wallet with {:?}", config.blockchain);
-            wallet.sync(bdk::blockchain::noop_progress(), None)?;
+        // bet commands work out what to sync themselves
+        let scope = match &opt.command {
+            Balance | Send(_) | Tx(_) | Utxo(_) | FundPsbt(_) => SyncScope::Wallet,
+            Address(opt) => opt.sync_scope(),
+            _ => SyncScope::Nothing,
+        };
+        if scope != SyncScope::Nothing {
+            let config = cmd::load_config(&wallet_dir)?;
+            cmd::check_backend_certificate(&wallet_dir, &config)?;
+            // syncing through the party also checks for reorgs and a stale tip
+            cmd::load_party(&wallet_dir)?.sync_scope(scope)?;
         }
+    }
 
-        // we poke bets to update balance from bets as well.
-        if let Balance = opt.command {
-            let party = cmd::load_party(&wallet_dir)?;
-            party.poke_bets();
+    if let Commands::External(args) = &opt.command {
+        // a command this binary was built without isn't left to look like a missing plugin
+        if let Some(e) = args
+            .get(0)
+            .and_then(|name| gun_wallet::features::missing_command(name))
+        {
+            return Err(e);
         }
+        return gun_wallet::plugin::run_external_command(&wallet_dir, args);
     }
 
     let res = match opt.command {
+        #[cfg(feature = "betting")]
         Commands::Bet(opt) => cmd::run_bet_cmd(&wallet_dir, opt, sync),
         Commands::Balance => cmd::run_balance(wallet_dir),
         Commands::Address(opt) => cmd::get_address(&wallet_dir, opt),
@@ -79,35 +383,82 @@ fn main() -> anyhow::Result<()> {
         Commands::Tx(opt) => cmd::run_transaction_cmd(&wallet_dir, opt),
         Commands::Utxo(opt) => cmd::run_utxo_cmd(&wallet_dir, opt),
         Commands::Split(opt) => cmd::run_split_cmd(&wallet_dir, opt),
+        Commands::Psbt(opt) => cmd::run_psbt_cmd(&wallet_dir, opt),
+        Commands::Export(opt) => cmd::run_export_cmd(&wallet_dir, opt),
+        Commands::Fees(opt) => cmd::run_fees_cmd(&wallet_dir, opt),
+        Commands::FundPsbt(opt) => cmd::run_fund_psbt(&wallet_dir, opt),
+        Commands::SweepKey(opt) => cmd::run_sweep_key(&wallet_dir, opt),
+        Commands::SweepDescriptor(opt) => cmd::run_sweep_descriptor(&wallet_dir, opt),
+        Commands::ScanPaths(opt) => cmd::run_scan_paths(&wallet_dir, opt),
+        Commands::Approval(opt) => cmd::run_approval_cmd(&wallet_dir, opt),
+        Commands::Audit(opt) => cmd::run_audit_cmd(&wallet_dir, opt),
+        Commands::Backup(opt) => cmd::run_backup_cmd(&wallet_dir, opt),
+        Commands::Backend(opt) => cmd::run_backend_cmd(&wallet_dir, opt),
+        Commands::Config(opt) => cmd::run_config_cmd(&wallet_dir, opt),
+        Commands::Db(opt) => cmd::run_db_cmd(&wallet_dir, opt),
+        Commands::Dev(opt) => cmd::run_dev_cmd(&wallet_dir, opt),
+        Commands::Doctor => cmd::run_doctor(&wallet_dir),
+        #[cfg(feature = "betting")]
+        Commands::Selftest(opt) => cmd::run_selftest(opt),
+        Commands::Whoami => cmd::run_whoami(&wallet_dir),
+        Commands::Watch(opt) => cmd::run_watch_cmd(&wallet_dir, opt),
+        Commands::Allowance(opt) => cmd::run_allowance_cmd(&wallet_dir, opt),
+        Commands::Schedule(opt) => cmd::run_schedule_cmd(&wallet_dir, opt),
+        Commands::ColdStorage(opt) => cmd::run_cold_storage_cmd(&wallet_dir, opt),
+        Commands::Rotate(opt) => cmd::run_rotate_cmd(&wallet_dir, opt),
+        Commands::State(opt) => cmd::run_state_cmd(&wallet_dir, opt),
+        Commands::Undo(opt) => cmd::run_undo_cmd(&wallet_dir, opt),
+        Commands::Open { id } => cmd::run_open(&wallet_dir, id),
+        Commands::Unlock { length } => cmd::run_unlock(&wallet_dir, length),
+        Commands::Lock {
+            setup,
+            remove,
+            decoy,
+        } => cmd::run_lock(&wallet_dir, setup, remove, decoy),
+        Commands::Agent { until } => cmd::run_agent(&wallet_dir, until),
+        Commands::External(_) => unreachable!("handled above"),
     };
 
-    match res {
-        Ok(output) => {
-            if opt.json {
-                println!(
-                    "{}",
-                    serde_json::to_string_pretty(&output.render_json()).unwrap()
-                )
-            } else if opt.tabs {
-                println!("{}", output.render_simple())
-            } else {
-                if let Some(output) = output.render() {
-                    println!("{}", output)
-                }
+    let output = res?;
+    if opt.copy {
+        match output.main_value() {
+            Some(value) => {
+                gun_wallet::clipboard::copy(&value)?;
+                eprintln!(
+                    "copied {} to the clipboard",
+                    gun_wallet::clipboard::fingerprint(&value)
+                );
             }
+            None => eprintln!("there was nothing to copy"),
         }
-        Err(e) => {
-            if opt.json {
-                let err_json = serde_json::json!({
-                    "error" : format!("{}", e),
-                });
-                println!("{}", serde_json::to_string_pretty(&err_json).unwrap());
-                std::process::exit(1)
-            } else {
-                return Err(e);
+    }
+    if opt.qr {
+        match output.main_value() {
+            Some(value) => {
+                eprintln!("{}", gun_wallet::qr::render(&value, Default::default())?)
+            }
+            None => eprintln!("there was nothing to show as a QR code"),
+        }
+    }
+    if opt.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output.render_json()).unwrap()
+        )
+    } else if opt.tabs {
+        println!("{}", output.render_simple())
+    } else {
+        let config = cmd::load_config(&wallet_dir).ok();
+        let output = match config {
+            Some(config) if opt.links || config.explorer_links => {
+                output.with_explorer_links(&config)
             }
+            _ => output,
+        };
+        if let Some(output) = output.render() {
+            println!("{}", output)
         }
     }
 
-    Ok(())
+    Ok(0)
 }
@@ -0,0 +1,183 @@
+//! Advertising proposals on public boards so people who want to bet can find them.
+//!
+//! Boards are set in `proposal-boards` in the config e.g.
+//!
+//! ```json
+//! "proposal-boards": {
+//!     "bets.example.com": { "kind": "http", "url": "https://bets.example.com/proposals" },
+//!     "nostr": { "kind": "nostr", "relays": ["wss://relay.damus.io"] }
+//! }
+//! ```
+//!
+//! - `http` boards are sent `{ "proposal": <proposal>, "oracle": .., "event-id": .., "value": <sats> }`
+//! with a POST to `url` and are expected to reply with `{ "id": <id> }`. Unpublishing is a DELETE
+//! to `<url>/<id>`.
+//! - `nostr` boards get a kind 30078 event tagged `t` = `gun-proposal` whose content is the
+//! proposal. It's signed with the proposal's key so it doesn't link to anything else of yours.
+//! Unpublishing is a NIP-09 deletion of it.
+//!
+//! Each board takes at most one proposal every `min-interval-secs` (60 by default) from us.
+use crate::{betting::Proposal, keychain::KeyPair, nostr};
+use anyhow::{anyhow, Context};
+use std::{collections::BTreeMap, time::Duration};
+
+pub const NOSTR_KIND: u64 = crate::event_source::NOSTR_KIND;
+/// NIP-09 event deletion
+pub const NOSTR_DELETION_KIND: u64 = 5;
+pub const DEFAULT_MIN_INTERVAL_SECS: u64 = 60;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum BoardKind {
+    Http { url: String },
+    Nostr { relays: Vec<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProposalBoard {
+    #[serde(flatten)]
+    pub kind: BoardKind,
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+fn default_min_interval_secs() -> u64 {
+    DEFAULT_MIN_INTERVAL_SECS
+}
+
+/// The boards from the config by name.
+pub type ProposalBoards = BTreeMap<String, ProposalBoard>;
+
+fn http_client() -> anyhow::Result<reqwest::blocking::Client> {
+    Ok(crate::endpoint::http_client()
+        .timeout(Duration::from_secs(30))
+        .build()?)
+}
+
+impl ProposalBoard {
+    /// Puts `proposal` on the board and returns the id the board knows it by.
+    pub fn publish(
+        &self,
+        proposal: &Proposal,
+        keypair: &KeyPair,
+        created_at: u64,
+    ) -> anyhow::Result<String> {
+        crate::read_only::check("publish the proposal")?;
+        let proposal_string = proposal.clone().into_versioned().to_string();
+        match &self.kind {
+            BoardKind::Http { url } => {
+                let body = serde_json::json!({
+                    "proposal": proposal_string,
+                    "oracle": proposal.oracle,
+                    "event-id": proposal.event_id.to_string(),
+                    "value": proposal.value.as_sat(),
+                });
+                let response = http_client()?
+                    .post(url)
+                    .json(&body)
+                    .send()
+                    .and_then(|response| response.error_for_status())
+                    .and_then(|response| response.text())
+                    .with_context(|| format!("while publishing to {}", url))?;
+                let response = serde_json::from_str::<serde_json::Value>(&response)
+                    .with_context(|| format!("while decoding the response from {}", url))?;
+                response["id"]
+                    .as_str()
+                    .map(str::to_string)
+                    .or_else(|| response["id"].as_u64().map(|id| id.to_string()))
+                    .ok_or(anyhow!("{} didn't say what id it gave the proposal", url))
+            }
+            BoardKind::Nostr { relays } => {
+                let event = nostr::Event::sign(
+                    keypair,
+                    created_at,
+                    NOSTR_KIND,
+                    vec![
+                        vec!["d".into(), format!("gun-proposal/{}", proposal.public_key)],
+                        vec!["t".into(), "gun-proposal".into()],
+                        vec!["oracle".into(), proposal.oracle.clone()],
+                        vec!["event-id".into(), proposal.event_id.to_string()],
+                    ],
+                    proposal_string,
+                );
+                publish_to_relays(relays, &event)?;
+                Ok(event.id)
+            }
+        }
+    }
+
+    /// Takes the proposal the board knows as `id` down.
+    pub fn unpublish(&self, id: &str, keypair: &KeyPair, created_at: u64) -> anyhow::Result<()> {
+        match &self.kind {
+            BoardKind::Http { url } => {
+                let url = format!("{}/{}", url.trim_end_matches('/'), id);
+                let response = http_client()?
+                    .delete(&url)
+                    .send()
+                    .with_context(|| format!("while unpublishing from {}", url))?;
+                // it's already gone
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(());
+                }
+                response.error_for_status()?;
+                Ok(())
+            }
+            BoardKind::Nostr { relays } => {
+                let event = nostr::Event::sign(
+                    keypair,
+                    created_at,
+                    NOSTR_DELETION_KIND,
+                    vec![vec!["e".into(), id.to_string()]],
+                    "taken or expired".into(),
+                );
+                publish_to_relays(relays, &event)
+            }
+        }
+    }
+}
+
+/// Sends `event` to every relay and succeeds if at least one took it.
+fn publish_to_relays(relays: &[String], event: &nostr::Event) -> anyhow::Result<()> {
+    if relays.is_empty() {
+        return Err(anyhow!("the board has no relays"));
+    }
+    let errors = relays
+        .iter()
+        .filter_map(|relay| {
+            nostr::publish(relay, event)
+                .err()
+                .map(|e| format!("{}: {}", relay, e))
+        })
+        .collect::<Vec<_>>();
+    if errors.len() == relays.len() {
+        return Err(anyhow!("no relay took it: {}", errors.join(", ")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn boards_from_config() {
+        let boards: ProposalBoards = serde_json::from_str(
+            r#"{
+                "example": { "kind": "http", "url": "https://bets.example.com/proposals", "min-interval-secs": 600 },
+                "nostr": { "kind": "nostr", "relays": ["wss://relay.example.com"] }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            boards["example"],
+            ProposalBoard {
+                kind: BoardKind::Http {
+                    url: "https://bets.example.com/proposals".into()
+                },
+                min_interval_secs: 600,
+            }
+        );
+        assert_eq!(boards["nostr"].min_interval_secs, DEFAULT_MIN_INTERVAL_SECS);
+    }
+}
@@ -0,0 +1,167 @@
+//! Transactions the backend wouldn't take yet.
+//!
+//! When a broadcast times out or is turned away because the mempool is full or the fee is below
+//! what nodes will relay right now, the transaction is kept as a pending transaction (so its coins
+//! stay reserved) along with a [`QueuedBroadcast`] and tried again later with exponential backoff.
+//! `gun schedule run` (and the daemon) retries the ones that are due and `gun tx queue` shows them.
+//! With `broadcast-bump-after` in the config the fee is raised out of the change every so many
+//! failed attempts.
+use crate::chrono::{Duration, NaiveDateTime};
+
+/// How long to wait after the first failed attempt. It doubles with each one after that.
+const FIRST_RETRY_SECS: i64 = 60;
+/// The longest to wait between attempts
+const MAX_RETRY_SECS: i64 = 6 * 60 * 60;
+
+/// How much a bump raises the fee each time (as a percentage of the fee)
+pub const BUMP_PERCENT: u64 = 25;
+
+/// What nodes say when they might take the transaction later (or with a higher fee).
+const COME_BACK_LATER: [&str; 5] = [
+    "mempool full",
+    "mempool min fee not met",
+    "min relay fee not met",
+    "too-long-mempool-chain",
+    "timed out",
+];
+
+/// What nodes say when the transaction is already out there.
+const ALREADY_THERE: [&str; 3] = [
+    "txn-already-in-mempool",
+    "txn-already-known",
+    "transaction already in block chain",
+];
+
+/// What nodes say when the transaction will never be valid.
+const NEVER: [&str; 5] = [
+    "missing-inputs",
+    "missingorspent",
+    "txn-mempool-conflict",
+    "non-mandatory-script-verify-flag",
+    "mandatory-script-verify-flag-failed",
+];
+
+/// What a failed broadcast means for the transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BroadcastFailure {
+    /// It could go through if it's tried again later
+    TryLater,
+    /// The backend already has it
+    AlreadyBroadcast,
+    /// It's invalid or conflicts with something so it'll never go through
+    Rejected,
+}
+
+impl BroadcastFailure {
+    pub fn of(error: &anyhow::Error) -> Self {
+        let message = format!("{:#}", error).to_lowercase();
+        let says = |phrases: &[&str]| phrases.iter().any(|phrase| message.contains(phrase));
+        if says(&ALREADY_THERE) {
+            BroadcastFailure::AlreadyBroadcast
+        } else if says(&COME_BACK_LATER) {
+            BroadcastFailure::TryLater
+        } else if says(&NEVER) {
+            BroadcastFailure::Rejected
+        } else {
+            match crate::exit_code::classify(error) {
+                // the backend couldn't be reached (or won't say why)
+                crate::exit_code::ErrorKind::Network => BroadcastFailure::TryLater,
+                _ => BroadcastFailure::Rejected,
+            }
+        }
+    }
+}
+
+/// How a pending transaction that couldn't be broadcast is getting on.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueuedBroadcast {
+    /// What made the transaction e.g. `send` (as in the audit log)
+    pub context: String,
+    pub attempts: u32,
+    /// How many times the fee has been raised
+    #[serde(default)]
+    pub bumps: u32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: String,
+}
+
+impl QueuedBroadcast {
+    /// The queue entry for a transaction made by `context` whose first broadcast failed with
+    /// `error`.
+    pub fn new(context: &str, error: &anyhow::Error) -> Self {
+        let mut queued = QueuedBroadcast {
+            context: context.to_string(),
+            attempts: 0,
+            bumps: 0,
+            next_attempt: crate::clock::now(),
+            last_error: String::new(),
+        };
+        queued.failed(error);
+        queued
+    }
+
+    /// Records another failed attempt and puts the next one off for longer.
+    pub fn failed(&mut self, error: &anyhow::Error) {
+        self.attempts += 1;
+        self.last_error = format!("{:#}", error);
+        self.next_attempt = crate::clock::now() + backoff(self.attempts);
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.next_attempt <= crate::clock::now()
+    }
+
+    /// Whether the fee should be raised before the next attempt when it's raised every
+    /// `bump_after` failed attempts.
+    pub fn needs_bump(&self, bump_after: u32) -> bool {
+        bump_after > 0 && self.attempts >= bump_after * (self.bumps + 1)
+    }
+}
+
+/// How long to wait after `attempts` failed attempts.
+fn backoff(attempts: u32) -> Duration {
+    let doublings = attempts.saturating_sub(1).min(16);
+    Duration::seconds((FIRST_RETRY_SECS << doublings).min(MAX_RETRY_SECS))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn waits_longer_after_each_attempt() {
+        assert_eq!(backoff(1), Duration::minutes(1));
+        assert_eq!(backoff(2), Duration::minutes(2));
+        assert_eq!(backoff(5), Duration::minutes(16));
+        assert_eq!(backoff(100), Duration::hours(6));
+
+        let mut queued = QueuedBroadcast::new("send", &anyhow!("mempool full"));
+        assert!(!queued.is_due());
+        assert!(!queued.needs_bump(2));
+        queued.failed(&anyhow!("mempool full"));
+        assert!(queued.needs_bump(2));
+        queued.bumps += 1;
+        assert!(!queued.needs_bump(2));
+        assert!(!queued.needs_bump(0));
+    }
+
+    #[test]
+    fn tells_failures_apart() {
+        let failure = |message: &str| BroadcastFailure::of(&anyhow!("{}", message));
+        assert_eq!(
+            failure("sendrawtransaction RPC error: mempool min fee not met, 150 < 300"),
+            BroadcastFailure::TryLater
+        );
+        assert_eq!(
+            failure("bad-txns-inputs-missingorspent"),
+            BroadcastFailure::Rejected
+        );
+        assert_eq!(
+            failure("txn-already-in-mempool"),
+            BroadcastFailure::AlreadyBroadcast
+        );
+        assert_eq!(failure("something else"), BroadcastFailure::Rejected);
+    }
+}
@@ -0,0 +1,135 @@
+//! Sending change somewhere other than the wallet's internal addresses e.g. a separate account kept
+//! as a "war chest".
+//!
+//! Change is only sent to descriptors whose keys all come from this wallet's seed words so a
+//! mistake in the config or on the command line can't give it away.
+use crate::{betting::BetDatabase, keychain::Keychain};
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{
+        secp256k1::Secp256k1,
+        util::bip32::{DerivationPath, ExtendedPubKey},
+        Network, Script,
+    },
+    database::{BatchOperations, MemoryDatabase},
+    descriptor::{get_checksum, DescriptorPublicKey, IntoWalletDescriptor},
+    miniscript::ForEachKey,
+    wallet::AddressIndex,
+    KeychainKind, Wallet,
+};
+
+#[derive(Clone, Debug)]
+pub struct ChangeDescriptor {
+    descriptor: String,
+    /// Identifies the descriptor in the database
+    checksum: String,
+    network: Network,
+}
+
+impl ChangeDescriptor {
+    /// Parses `descriptor` and checks that every key in it can be derived from `keychain`.
+    pub fn new(descriptor: &str, keychain: &Keychain, network: Network) -> anyhow::Result<Self> {
+        let secp = Secp256k1::new();
+        let body = descriptor.splitn(2, '#').next().unwrap_or("");
+        let checksum = get_checksum(body).context("parsing change descriptor")?;
+        let (parsed, _) = descriptor
+            .into_wallet_descriptor(&secp, network)
+            .context("parsing change descriptor")?;
+
+        let master = keychain.main_wallet_xprv(network);
+        let master_fingerprint = master.fingerprint(&secp);
+        let owned = parsed.for_each_key(|key| match key.as_key() {
+            DescriptorPublicKey::XPub(xkey) => {
+                let (fingerprint, path) = match &xkey.origin {
+                    Some((fingerprint, path)) => (*fingerprint, path.clone()),
+                    // it has to be the master key itself
+                    None => (xkey.xkey.fingerprint(), DerivationPath::from(vec![])),
+                };
+                fingerprint == master_fingerprint
+                    && master
+                        .derive_priv(&secp, &path)
+                        .map(|derived| {
+                            let derived = ExtendedPubKey::from_private(&secp, &derived);
+                            derived.public_key == xkey.xkey.public_key
+                                && derived.chain_code == xkey.xkey.chain_code
+                        })
+                        .unwrap_or(false)
+            }
+            DescriptorPublicKey::SinglePub(_) => false,
+        });
+        if !owned {
+            return Err(anyhow!(
+                "the change descriptor has keys that don't come from this wallet's seed words (keys need their origin e.g. [{}/84'/0'/1']xpub...)",
+                master_fingerprint
+            ));
+        }
+
+        Ok(Self {
+            descriptor: descriptor.to_string(),
+            checksum,
+            network,
+        })
+    }
+
+    pub fn script_pubkey_at(&self, index: u32) -> anyhow::Result<Script> {
+        let mut database = MemoryDatabase::default();
+        if index > 0 {
+            database.set_last_index(KeychainKind::External, index - 1)?;
+        }
+        let wallet = Wallet::new_offline(self.descriptor.as_str(), None, self.network, database)?;
+        Ok(wallet
+            .get_address(AddressIndex::New)?
+            .address
+            .script_pubkey())
+    }
+
+    /// The script to send the next change to. Each call moves on to a new index.
+    pub fn next_script_pubkey(&self, bet_db: &BetDatabase) -> anyhow::Result<Script> {
+        self.script_pubkey_at(bet_db.next_change_index(&self.checksum)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::util::bip32::ExtendedPrivKey;
+    use std::str::FromStr;
+
+    fn account_descriptor(
+        master: &ExtendedPrivKey,
+        origin_fingerprint: &str,
+        account: u32,
+    ) -> String {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str(&format!("m/84'/1'/{}'", account)).unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &master.derive_priv(&secp, &path).unwrap());
+        format!(
+            "wpkh([{}/84'/1'/{}']{}/0/*)",
+            origin_fingerprint, account, xpub
+        )
+    }
+
+    #[test]
+    fn only_our_descriptors_are_accepted() {
+        let secp = Secp256k1::new();
+        let keychain = Keychain::new([42u8; 64]);
+        let master = keychain.main_wallet_xprv(Network::Regtest);
+        let fingerprint = master.fingerprint(&secp).to_string();
+
+        let ours = account_descriptor(&master, &fingerprint, 1);
+        let change = ChangeDescriptor::new(&ours, &keychain, Network::Regtest).unwrap();
+        assert_ne!(
+            change.script_pubkey_at(0).unwrap(),
+            change.script_pubkey_at(1).unwrap()
+        );
+
+        let other = Keychain::new([7u8; 64]).main_wallet_xprv(Network::Regtest);
+        let other_fingerprint = other.fingerprint(&secp).to_string();
+        let theirs = account_descriptor(&other, &other_fingerprint, 1);
+        assert!(ChangeDescriptor::new(&theirs, &keychain, Network::Regtest).is_err());
+
+        // claiming to be from our seed isn't enough
+        let lying = account_descriptor(&other, &fingerprint, 1);
+        assert!(ChangeDescriptor::new(&lying, &keychain, Network::Regtest).is_err());
+    }
+}
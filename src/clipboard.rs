@@ -0,0 +1,176 @@
+//! Copying to and pasting from the system clipboard with the tools each platform has (`pbcopy`,
+//! `wl-copy`, `xclip`, `xsel` or `clip`) so there's nothing to link against.
+//!
+//! Malware that watches the clipboard and swaps addresses for its own is common so every copy is
+//! read back and every paste is read twice. If the clipboard changes in between we refuse to go on.
+use anyhow::{anyhow, Context};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// How long to wait before reading the clipboard again. Swappers tend to act as soon as an address
+/// shows up.
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+fn copy_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", vec![])]
+    } else if cfg!(windows) {
+        vec![("clip", vec![])]
+    } else {
+        vec![
+            ("wl-copy", vec![]),
+            ("xclip", vec!["-selection", "clipboard"]),
+            ("xsel", vec!["--clipboard", "--input"]),
+        ]
+    }
+}
+
+fn paste_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", vec![])]
+    } else if cfg!(windows) {
+        vec![(
+            "powershell",
+            vec!["-NoProfile", "-Command", "Get-Clipboard"],
+        )]
+    } else {
+        vec![
+            ("wl-paste", vec!["--no-newline"]),
+            ("xclip", vec!["-selection", "clipboard", "-o"]),
+            ("xsel", vec!["--clipboard", "--output"]),
+        ]
+    }
+}
+
+fn write_clipboard(text: &str) -> anyhow::Result<()> {
+    let mut tried = vec![];
+    for (program, args) in copy_commands() {
+        let mut child = match Command::new(program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => {
+                tried.push(program);
+                continue;
+            }
+        };
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(text.as_bytes())?;
+        let status = child.wait()?;
+        if status.success() {
+            return Ok(());
+        }
+        tried.push(program);
+    }
+    Err(anyhow!(
+        "couldn't copy to the clipboard (tried {})",
+        tried.join(", ")
+    ))
+}
+
+fn read_clipboard() -> anyhow::Result<String> {
+    let mut tried = vec![];
+    for (program, args) in paste_commands() {
+        match Command::new(program)
+            .args(&args)
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                return String::from_utf8(output.stdout)
+                    .context("the clipboard doesn't have text on it")
+            }
+            _ => tried.push(program),
+        }
+    }
+    Err(anyhow!(
+        "couldn't read the clipboard (tried {})",
+        tried.join(", ")
+    ))
+}
+
+/// Checks what was read back from the clipboard is what we expected. Clipboard tools add and drop
+/// trailing newlines so those don't count.
+fn check_unchanged(expected: &str, read_back: &str) -> anyhow::Result<()> {
+    if expected.trim() != read_back.trim() {
+        return Err(anyhow!(
+            "the clipboard changed from {} to {} by itself! Something on this computer may be swapping what you copy for something else. Don't use the clipboard until you've found out what.",
+            fingerprint(expected),
+            fingerprint(read_back)
+        ));
+    }
+    Ok(())
+}
+
+/// The start and end of `text` for checking by eye e.g. `bc1qar0s…5mdq`.
+pub fn fingerprint(text: &str) -> String {
+    let text = text.trim();
+    let chars = text.chars().collect::<Vec<_>>();
+    if chars.len() <= 16 {
+        return text.to_string();
+    }
+    format!(
+        "{}…{}",
+        chars[..8].iter().collect::<String>(),
+        chars[chars.len() - 4..].iter().collect::<String>()
+    )
+}
+
+/// Copies `text` to the clipboard and makes sure it's still there a moment later.
+pub fn copy(text: &str) -> anyhow::Result<()> {
+    write_clipboard(text)?;
+    thread::sleep(SETTLE_TIME);
+    check_unchanged(text, &read_clipboard()?)
+}
+
+/// Pastes text from the clipboard after making sure it isn't changing under us. The start and end
+/// of it are printed so it can be checked against where it was copied from.
+pub fn paste() -> anyhow::Result<String> {
+    let first = read_clipboard()?;
+    thread::sleep(SETTLE_TIME);
+    check_unchanged(&first, &read_clipboard()?)?;
+    let text = first.trim().to_string();
+    if text.is_empty() {
+        return Err(anyhow!("the clipboard is empty"));
+    }
+    eprintln!(
+        "pasted {} — check it matches what you copied",
+        fingerprint(&text)
+    );
+    Ok(text)
+}
+
+/// Pastes from the clipboard and parses it as a `T`.
+pub fn paste_as<T>(what: &str) -> anyhow::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let text = paste()?;
+    T::from_str(&text).map_err(|e| anyhow!("the clipboard doesn't have {} on it: {}", what, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn swapped_clipboard_is_caught() {
+        let address = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        assert!(check_unchanged(address, &format!("{}\n", address)).is_ok());
+        assert!(check_unchanged(address, "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh").is_err());
+        assert_eq!(fingerprint(address), "bc1qar0s…5mdq");
+        assert_eq!(fingerprint("short"), "short");
+    }
+}
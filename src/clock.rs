@@ -0,0 +1,107 @@
+//! The time gun goes by when deciding whether something has expired or is due: bets whose outcome
+//! time has passed, adverts, scheduled payments and so on.
+//!
+//! It's the system clock unless it's been pinned with `--now` (or `GUN_NOW`) so integration tests
+//! and auditors can try out expiry paths deterministically. A pinned clock doesn't move. Timing
+//! that isn't about the wallet's state (timeouts, nonces, nostr event times) always uses the
+//! system clock and so does anything that guards spending (how long a session or an approval
+//! lasts and the times in the audit log, see [`real_now`]) since anyone can set `GUN_NOW`.
+use crate::chrono::{DateTime, NaiveDateTime, Utc};
+use anyhow::anyhow;
+use std::sync::Mutex;
+
+pub const NOW_ENV: &str = "GUN_NOW";
+
+/// How far ahead of the network's time a block's timestamp can be (the consensus rule).
+pub const MAX_BLOCK_TIME_AHEAD_SECS: i64 = 2 * 60 * 60;
+
+static PINNED: Mutex<Option<NaiveDateTime>> = Mutex::new(None);
+
+pub fn pin(now: NaiveDateTime) {
+    *PINNED.lock().unwrap() = Some(now);
+}
+
+pub fn is_pinned() -> bool {
+    PINNED.lock().unwrap().is_some()
+}
+
+/// The current time (UTC).
+pub fn now() -> NaiveDateTime {
+    PINNED
+        .lock()
+        .unwrap()
+        .unwrap_or_else(|| Utc::now().naive_utc())
+}
+
+/// The system's time even if the clock is pinned.
+pub fn real_now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// The current time as a UNIX timestamp.
+pub fn timestamp() -> i64 {
+    now().timestamp()
+}
+
+/// Parses a time for `--now`: a UNIX timestamp, an RFC3339 time or a UTC time like
+/// `2021-06-01 12:00` or `2021-06-01T12:00:00`.
+pub fn parse(string: &str) -> anyhow::Result<NaiveDateTime> {
+    let string = string.trim();
+    if let Ok(timestamp) = string.parse::<i64>() {
+        return NaiveDateTime::from_timestamp_opt(timestamp, 0)
+            .ok_or_else(|| anyhow!("{} is out of range", timestamp));
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(string) {
+        return Ok(datetime.naive_utc());
+    }
+    for format in &[
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(string, format) {
+            return Ok(datetime);
+        }
+    }
+    Err(anyhow!(
+        "'{}' should be a UNIX timestamp or a UTC time like 2021-06-01T12:00:00",
+        string
+    ))
+}
+
+/// How many seconds our clock is behind a block with timestamp `block_time` if it's by more than
+/// a block's timestamp can be ahead. A block can't be mined that far in the future so it's our
+/// clock that's wrong. Being ahead can't be told apart from the backend being behind.
+pub fn behind_block(block_time: u32) -> Option<i64> {
+    let behind = block_time as i64 - timestamp();
+    match behind > MAX_BLOCK_TIME_AHEAD_SECS {
+        true => Some(behind),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        let expected = NaiveDateTime::from_timestamp(1_622_548_800, 0);
+        assert_eq!(parse("1622548800").unwrap(), expected);
+        assert_eq!(parse("2021-06-01T12:00:00Z").unwrap(), expected);
+        assert_eq!(parse("2021-06-01T14:00:00+02:00").unwrap(), expected);
+        assert_eq!(parse("2021-06-01 12:00").unwrap(), expected);
+        assert_eq!(parse(" 2021-06-01T12:00:00 ").unwrap(), expected);
+        assert!(parse("June 1st").is_err());
+    }
+
+    #[test]
+    fn only_far_future_blocks_mean_our_clock_is_behind() {
+        let now = timestamp();
+        assert_eq!(behind_block(now as u32), None);
+        assert_eq!(behind_block((now + 60 * 60) as u32), None);
+        assert_eq!(behind_block((now - 24 * 60 * 60) as u32), None);
+        assert!(behind_block((now + 3 * 60 * 60) as u32).unwrap() > MAX_BLOCK_TIME_AHEAD_SECS);
+    }
+}
@@ -0,0 +1,183 @@
+use super::*;
+use crate::{
+    allowance::{self, Allowance, AllowanceKeys},
+    item,
+};
+use bdk::{
+    blockchain::Blockchain, database::MemoryDatabase, descriptor::IntoWalletDescriptor,
+    wallet::AddressIndex,
+};
+use structopt::StructOpt;
+
+/// About a month of blocks
+const DEFAULT_EXPIRY_BLOCKS: &str = "4320";
+
+#[derive(StructOpt, Debug, Clone)]
+/// Keep a small amount on another device (e.g. a phone) and take back what's left later
+pub enum AllowanceOpt {
+    /// Make a new allowance. Send coins to its address to fund it.
+    New {
+        /// How many blocks until the wallet can reclaim the coins
+        #[structopt(long, default_value = DEFAULT_EXPIRY_BLOCKS)]
+        expires_in: u32,
+        /// What the allowance is for
+        #[structopt(long)]
+        label: Option<String>,
+    },
+    /// List allowances with what is left on them
+    List,
+    /// Print the descriptor with the device's private key to import on the device
+    Export { index: u32 },
+    /// After the allowance has expired move what's left on it back into the wallet
+    Reclaim {
+        index: u32,
+        /// The derivation indexes to look for coins on (the end isn't included)
+        #[structopt(long, default_value = "0-100")]
+        range: IndexRange,
+        #[structopt(flatten)]
+        sweep_args: SweepArgs,
+    },
+}
+
+pub fn run_allowance_cmd(wallet_dir: &PathBuf, opt: AllowanceOpt) -> anyhow::Result<CmdOutput> {
+    let party = load_party(wallet_dir)?;
+    let network = party.wallet().network();
+    let get_allowance = |index: u32| {
+        party
+            .bet_db()
+            .get_entity::<Allowance>(index)?
+            .ok_or(anyhow!("there's no allowance {}", index))
+    };
+
+    match opt {
+        AllowanceOpt::New { expires_in, label } => {
+            let height = party.wallet().client().get_height()?;
+            let allowance = Allowance {
+                expiry_height: height + expires_in,
+                created_at: crate::clock::now(),
+                label,
+            };
+            let index = party.bet_db().add_allowance(allowance.clone())?;
+            let descriptor = allowance::descriptor(
+                &party.keychain,
+                network,
+                index,
+                &allowance,
+                AllowanceKeys::Public,
+            );
+            let address = Wallet::new_offline(
+                descriptor.as_str(),
+                None,
+                network,
+                MemoryDatabase::default(),
+            )?
+            .get_address(AddressIndex::Peek(0))?
+            .address;
+            Ok(item! {
+                "index" => Cell::Int(index.into()),
+                "address" => Cell::string(address),
+                "expiry-height" => Cell::Int(allowance.expiry_height.into()),
+            })
+        }
+        AllowanceOpt::List => {
+            let height = party.wallet().client().get_height()?;
+            let mut rows = vec![];
+            for (index, allowance) in party
+                .bet_db()
+                .list_entities::<Allowance>()
+                .collect::<Result<Vec<_>, _>>()?
+            {
+                let descriptor = allowance::descriptor(
+                    &party.keychain,
+                    network,
+                    index,
+                    &allowance,
+                    AllowanceKeys::Public,
+                );
+                let watch_wallet = Wallet::new(
+                    descriptor.as_str(),
+                    None,
+                    network,
+                    MemoryDatabase::default(),
+                    party.new_blockchain()?,
+                )?;
+                watch_wallet.sync(bdk::blockchain::noop_progress(), None)?;
+                rows.push(vec![
+                    Cell::Int(index.into()),
+                    allowance.label.map(Cell::String).unwrap_or(Cell::Empty),
+                    Cell::Amount(Amount::from_sat(watch_wallet.get_balance()?)),
+                    Cell::Int(allowance.expiry_height.into()),
+                    Cell::string(height >= allowance.expiry_height),
+                    Cell::string(watch_wallet.get_address(AddressIndex::New)?.address),
+                ]);
+            }
+            Ok(CmdOutput::table(
+                vec![
+                    "index",
+                    "label",
+                    "balance",
+                    "expiry-height",
+                    "expired",
+                    "address",
+                ],
+                rows,
+            ))
+        }
+        AllowanceOpt::Export { index } => {
+            let allowance = get_allowance(index)?;
+            eprintln!(
+                "This has the allowance's private key. Anyone who sees it can spend the coins on it."
+            );
+            Ok(CmdOutput::EmphasisedItem {
+                main: (
+                    "descriptor",
+                    Cell::String(allowance::descriptor(
+                        &party.keychain,
+                        network,
+                        index,
+                        &allowance,
+                        AllowanceKeys::Device,
+                    )),
+                ),
+                other: vec![("expiry-height", Cell::Int(allowance.expiry_height.into()))],
+            })
+        }
+        AllowanceOpt::Reclaim {
+            index,
+            range,
+            sweep_args,
+        } => {
+            let allowance = get_allowance(index)?;
+            let height = party.wallet().client().get_height()?;
+            if height < allowance.expiry_height {
+                return Err(anyhow!(
+                    "allowance {} can't be reclaimed until block {} ({} blocks from now)",
+                    index,
+                    allowance.expiry_height,
+                    allowance.expiry_height - height
+                ));
+            }
+            let descriptor = allowance::descriptor(
+                &party.keychain,
+                network,
+                index,
+                &allowance,
+                AllowanceKeys::Reclaim,
+            );
+            let (parsed, _) = descriptor
+                .as_str()
+                .into_wallet_descriptor(&bdk::bitcoin::secp256k1::Secp256k1::new(), network)?;
+            let coins = scan_descriptor(&party, parsed, range)?;
+            if coins.is_empty() {
+                return Err(anyhow!("there's nothing left on allowance {}", index));
+            }
+            sweep_coins(
+                &party,
+                coins,
+                &[descriptor],
+                sweep_args,
+                Some(allowance.expiry_height),
+            )
+        }
+    }
+}
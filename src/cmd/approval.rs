@@ -0,0 +1,301 @@
+use super::*;
+use crate::{
+    amount_ext::FromCliStr,
+    approval::{ApprovalPolicy, ApprovalRequest, Approver, APPROVER_PASSPHRASE_ENV},
+    betting::{BetId, PendingPsbt},
+    item,
+};
+use bdk::{blockchain::EsploraBlockchain, SignOptions};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Make sends above a threshold need a second person to approve them
+pub enum ApprovalOpt {
+    /// Add someone who can make and approve large sends. They are asked for a passphrase.
+    AddApprover {
+        name: String,
+        /// Sends paying more than this to other wallets need approving (needed the first time)
+        #[structopt(long, parse(try_from_str = FromCliStr::from_cli_str))]
+        threshold: Option<Amount>,
+    },
+    /// Remove an approver
+    RemoveApprover { name: String },
+    /// List the approvers
+    Approvers,
+    /// List the sends waiting to be approved
+    List,
+    /// Approve a send someone else made then sign and broadcast it. Asks for your approver
+    /// passphrase.
+    Confirm {
+        txid: Txid,
+        /// Don't prompt for answers just answer yes.
+        #[structopt(long, short)]
+        yes: bool,
+        /// Print the resulting transaction out in hex instead of broadcasting it.
+        #[structopt(long)]
+        print_tx: bool,
+    },
+}
+
+pub fn run_approval_cmd(wallet_dir: &PathBuf, opt: ApprovalOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        ApprovalOpt::AddApprover { name, threshold } => {
+            let mut config = load_config(wallet_dir)?;
+            let mut policy = match (config.approval.take(), threshold) {
+                (Some(mut policy), threshold) => {
+                    if let Some(threshold) = threshold {
+                        policy.threshold = threshold;
+                    }
+                    policy
+                }
+                (None, Some(threshold)) => ApprovalPolicy::new(threshold),
+                (None, None) => {
+                    return Err(anyhow!(
+                        "give a --threshold for the sends that need approving"
+                    ))
+                }
+            };
+            if policy
+                .approvers
+                .iter()
+                .any(|approver| approver.name == name)
+            {
+                return Err(anyhow!("there is already an approver called {}", name));
+            }
+            let passphrase = read_passphrase(
+                APPROVER_PASSPHRASE_ENV,
+                &format!("approver passphrase for {}", name),
+                true,
+            )?;
+            if policy.identify(&passphrase).is_some() {
+                return Err(anyhow!("another approver already has that passphrase"));
+            }
+            policy.approvers.push(Approver::new(name, &passphrase));
+            if policy.approvers.len() < 2 {
+                eprintln!("Add another approver -- large sends can't be made until there are two.");
+            }
+            config.approval = Some(policy);
+            write_config(wallet_dir, &config)?;
+            Ok(CmdOutput::None)
+        }
+        ApprovalOpt::RemoveApprover { name } => {
+            let mut config = load_config(wallet_dir)?;
+            let policy = config
+                .approval
+                .as_mut()
+                .ok_or(anyhow!("no approvers have been added"))?;
+            let n_approvers = policy.approvers.len();
+            policy.approvers.retain(|approver| approver.name != name);
+            if policy.approvers.len() == n_approvers {
+                return Err(anyhow!("there is no approver called {}", name));
+            }
+            write_config(wallet_dir, &config)?;
+            Ok(CmdOutput::None)
+        }
+        ApprovalOpt::Approvers => {
+            let config = load_config(wallet_dir)?;
+            let policy = config
+                .approval
+                .ok_or(anyhow!("no approvers have been added"))?;
+            Ok(CmdOutput::table(
+                vec!["name", "threshold", "window-minutes"],
+                policy
+                    .approvers
+                    .iter()
+                    .map(|approver| {
+                        vec![
+                            Cell::String(approver.name.clone()),
+                            Cell::Amount(policy.threshold),
+                            Cell::Int(policy.window_minutes as u64),
+                        ]
+                    })
+                    .collect(),
+            ))
+        }
+        ApprovalOpt::List => {
+            let config = load_config(wallet_dir)?;
+            let bet_db = load_bet_db(wallet_dir)?;
+            let memos = bet_db.tx_memos()?;
+            let rows = bet_db
+                .list_entities_print_error::<PendingPsbt>()
+                .filter_map(|(txid, pending)| {
+                    let approval = pending.approval?;
+                    let expires = config.approval.as_ref().map(|policy| {
+                        approval.requested_at
+                            + crate::chrono::Duration::minutes(policy.window_minutes as i64)
+                    });
+                    let expired = config
+                        .approval
+                        .as_ref()
+                        .map(|policy| policy.is_expired(approval.requested_at))
+                        .unwrap_or(true);
+                    Some(vec![
+                        Cell::string(txid),
+                        Cell::String(approval.requested_by),
+                        Cell::DateTime(approval.requested_at.timestamp() as u64),
+                        Cell::Amount(approval.outgoing),
+                        match expires {
+                            Some(expires) if !expired => Cell::DateTime(expires.timestamp() as u64),
+                            _ => Cell::string("expired"),
+                        },
+                        memos
+                            .get(&txid)
+                            .map(|memo| Cell::String(memo.clone()))
+                            .unwrap_or(Cell::Empty),
+                    ])
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec![
+                    "txid",
+                    "requested-by",
+                    "requested-at",
+                    "outgoing",
+                    "expires",
+                    "memo",
+                ],
+                rows,
+            ))
+        }
+        ApprovalOpt::Confirm {
+            txid,
+            yes,
+            print_tx,
+        } => {
+            let party = load_party(wallet_dir)?;
+            let bet_db = party.bet_db();
+            let wallet = party.wallet();
+            let policy = party
+                .settings()
+                .approval
+                .clone()
+                .ok_or(anyhow!("no approvers have been added"))?;
+            let pending = bet_db
+                .get_entity::<PendingPsbt>(txid)?
+                .ok_or(anyhow!("transaction {} isn't pending", txid))?;
+            let request = pending
+                .approval
+                .ok_or(anyhow!("transaction {} isn't waiting to be approved", txid))?;
+            if policy.is_expired(request.requested_at) {
+                return Err(anyhow!(
+                    "it's too late to approve {0} -- discard it with `gun psbt discard {0}` and make the send again",
+                    txid
+                ));
+            }
+
+            let passphrase =
+                read_passphrase(APPROVER_PASSPHRASE_ENV, "approver passphrase", false)?;
+            let approver = policy
+                .identify(&passphrase)
+                .ok_or(anyhow!("that passphrase doesn't belong to any approver"))?;
+            if approver.name == request.requested_by {
+                return Err(anyhow!(
+                    "{} made this send so someone else has to approve it",
+                    approver.name
+                ));
+            }
+            eprintln!(
+                "{} approved sending {} from {} requested by {}",
+                approver.name, request.outgoing, txid, request.requested_by
+            );
+
+            let mut psbt = pending.psbt;
+            crate::approval::allow(psbt.global.unsigned_tx.txid());
+            let won_bets = party.won_bets(&pending.claiming_bets)?;
+            party.sign_won_bets(&mut psbt, &won_bets)?;
+            wallet.sign(&mut psbt, SignOptions::default())?;
+            party.audit_psbt(
+                AuditOperation::Sign,
+                &format!("send approved by {}", approver.name),
+                &psbt,
+            )?;
+
+            if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+                // the approval is done so it can be signed elsewhere like any other
+                eprintln!("{}", display_psbt(wallet, &psbt));
+                bet_db.insert_pending_psbt(psbt, pending.claiming_bets)?;
+                eprintln!(
+                    "This wallet couldn't sign the transaction by itself. Use `gun psbt export {}` to get it signed and `gun psbt import` to broadcast it.",
+                    txid
+                );
+                return Ok(item! { "txid" => Cell::string(txid) });
+            }
+
+            let (output, broadcast_txid) = decide_to_broadcast(
+                wallet.network(),
+                wallet.client(),
+                psbt,
+                yes,
+                print_tx,
+                party.audit_log(),
+                party.bet_db(),
+                "send",
+            )?;
+
+            if broadcast_txid.is_some() && !print_tx {
+                bet_db.remove_broadcast_psbt(txid)?;
+                party.record_cost_basis(txid)?;
+                for bet_id in pending.claiming_bets {
+                    if let Err(e) = party.take_next_action(bet_id, false) {
+                        eprintln!(
+                            "error updating state of bet {} after broadcasting claim tx {}: {}",
+                            bet_id, txid, e
+                        );
+                    }
+                }
+            }
+
+            Ok(output)
+        }
+    }
+}
+
+/// Holds an unsigned send until someone other than whoever made it approves it with
+/// `gun approval confirm`.
+pub fn request_approval<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    policy: &ApprovalPolicy,
+    psbt: Psbt,
+    claiming_bets: Vec<BetId>,
+    outgoing: Amount,
+    memo: Option<String>,
+) -> anyhow::Result<CmdOutput> {
+    if policy.approvers.len() < 2 {
+        return Err(anyhow!(
+            "sends of more than {} need approving but there aren't two approvers to do it (see `gun approval add-approver`)",
+            policy.threshold
+        ));
+    }
+    eprintln!(
+        "Sending {} to other wallets needs approving by two people.",
+        outgoing
+    );
+    let passphrase = read_passphrase(APPROVER_PASSPHRASE_ENV, "approver passphrase", false)?;
+    let approver = policy
+        .identify(&passphrase)
+        .ok_or(anyhow!("that passphrase doesn't belong to any approver"))?;
+
+    eprintln!("{}", display_psbt(party.wallet(), &psbt));
+    let bet_db = party.bet_db();
+    let txid = bet_db.insert_pending_psbt(psbt, claiming_bets)?;
+    bet_db.set_pending_approval(
+        txid,
+        Some(ApprovalRequest {
+            requested_by: approver.name.clone(),
+            requested_at: crate::clock::real_now(),
+            outgoing,
+        }),
+    )?;
+    if let Some(memo) = memo {
+        bet_db.set_tx_memo(txid, memo)?;
+    }
+    eprintln!(
+        "The transaction has been saved. Someone other than {} has to run `gun approval confirm {}` within {} minutes to sign and broadcast it.",
+        approver.name, txid, policy.window_minutes
+    );
+
+    Ok(item! {
+        "txid" => Cell::string(txid),
+        "requested-by" => Cell::String(approver.name.clone()),
+    })
+}
@@ -0,0 +1,72 @@
+use super::*;
+use crate::item;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Look at the log of everything this wallet has signed and broadcast
+pub enum AuditOpt {
+    /// List the entries in the audit log
+    List,
+    /// Check that no entry in the audit log has been edited or removed
+    Verify,
+}
+
+pub fn run_audit_cmd(wallet_dir: &PathBuf, opt: AuditOpt) -> anyhow::Result<CmdOutput> {
+    let config = load_config(wallet_dir)?;
+    let audit_log = AuditLog::new(wallet_dir, config.network);
+    match opt {
+        AuditOpt::List => {
+            let rows = audit_log
+                .entries()?
+                .into_iter()
+                .map(|entry| {
+                    let record = entry.record;
+                    let total_out = record.outputs.iter().map(|output| output.value).sum();
+                    vec![
+                        Cell::Int(record.seq),
+                        Cell::datetime(record.time),
+                        Cell::string(record.operation.name()),
+                        Cell::String(record.context),
+                        Cell::string(record.txid),
+                        Cell::List(
+                            record
+                                .outputs
+                                .into_iter()
+                                .map(|output| Box::new(Cell::String(output.destination)))
+                                .collect(),
+                        ),
+                        Cell::Amount(Amount::from_sat(total_out)),
+                        record
+                            .fee
+                            .map(|fee| Cell::Amount(Amount::from_sat(fee)))
+                            .unwrap_or(Cell::Empty),
+                        Cell::String(record.command.join(" ")),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec![
+                    "seq",
+                    "time",
+                    "operation",
+                    "context",
+                    "txid",
+                    "destinations",
+                    "value",
+                    "fee",
+                    "command",
+                ],
+                rows,
+            ))
+        }
+        AuditOpt::Verify => {
+            let (n_entries, head_hash) = audit_log
+                .verify()
+                .with_context(|| format!("verifying {}", audit_log.path().display()))?;
+            Ok(item! {
+                "entries" => Cell::Int(n_entries as u64),
+                "head-hash" => Cell::string(head_hash),
+            })
+        }
+    }
+}
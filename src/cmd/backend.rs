@@ -0,0 +1,255 @@
+use super::{load_config, write_config, Cell, CmdOutput};
+use crate::{config::Config, item, Url};
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{blockdata::constants::genesis_block, Network},
+    blockchain::{esplora::EsploraBlockchainConfig, AnyBlockchainConfig},
+};
+use std::{path::PathBuf, time::Duration};
+use structopt::StructOpt;
+
+const SEEN_CERT_FILE: &str = "backend-cert-sha256.txt";
+
+#[derive(StructOpt, Debug, Clone)]
+/// See or change the backend the wallet gets blocks and transactions from
+pub enum BackendOpt {
+    /// Show the backend the wallet uses
+    Show,
+    /// Use the Esplora server at this URL (or host e.g. [::1]:3000 or xyz.onion)
+    Set {
+        #[structopt(parse(try_from_str = crate::endpoint::parse_endpoint))]
+        url: Url,
+        /// How many unused addresses in a row to look at before deciding there are no more
+        #[structopt(long)]
+        stop_gap: Option<usize>,
+        /// How many requests to make to the server at once
+        #[structopt(long)]
+        concurrency: Option<u8>,
+        /// Don't check that the server is reachable and on the wallet's network
+        #[structopt(long)]
+        no_check: bool,
+    },
+    /// Go back to the default backend for the wallet's network
+    Reset,
+}
+
+pub fn run_backend_cmd(wallet_dir: &PathBuf, opt: BackendOpt) -> anyhow::Result<CmdOutput> {
+    let mut config = load_config(wallet_dir)?;
+    let blockchain = match opt {
+        BackendOpt::Show => return Ok(show_backend(&config)),
+        BackendOpt::Set {
+            url,
+            stop_gap,
+            concurrency,
+            no_check,
+        } => {
+            let base_url = url.as_str().trim_end_matches('/').to_string();
+            if !no_check {
+                check_network(&base_url, config.network)?;
+            }
+            let (old_stop_gap, old_concurrency) = match &config.blockchain {
+                AnyBlockchainConfig::Esplora(esplora) => (esplora.stop_gap, esplora.concurrency),
+                #[allow(unreachable_patterns)]
+                _ => (10, Some(4)),
+            };
+            AnyBlockchainConfig::Esplora(EsploraBlockchainConfig {
+                base_url,
+                stop_gap: stop_gap.unwrap_or(old_stop_gap),
+                concurrency: concurrency.or(old_concurrency),
+            })
+        }
+        BackendOpt::Reset => Config::default_blockchain(config.network),
+    };
+
+    if backend_url(&blockchain) != backend_url(&config.blockchain) {
+        // the certificates we saw or pinned were the old backend's
+        let _ = crate::ephemeral::remove_file(&wallet_dir.join(SEEN_CERT_FILE));
+        if config.pinned_cert_sha256.take().is_some() {
+            eprintln!("The pinned certificate was the old backend's so it has been unpinned.");
+        }
+    }
+    config.blockchain = blockchain;
+    write_config(wallet_dir, &config)?;
+    Ok(show_backend(&config))
+}
+
+fn show_backend(config: &Config) -> CmdOutput {
+    match &config.blockchain {
+        AnyBlockchainConfig::Esplora(esplora) => item! {
+            "network" => Cell::string(config.network),
+            "kind" => Cell::string("esplora"),
+            "url" => Cell::string(&esplora.base_url),
+            "stop-gap" => Cell::Int(esplora.stop_gap as u64),
+            "concurrency" => esplora.concurrency.map(|n| Cell::Int(n as u64)).unwrap_or(Cell::Empty),
+            "pinned-cert-sha256" => config.pinned_cert_sha256.clone().map(Cell::String).unwrap_or(Cell::Empty),
+        },
+        #[allow(unreachable_patterns)]
+        _ => item! { "network" => Cell::string(config.network) },
+    }
+}
+
+/// Where the backend is for messages.
+pub fn backend_url(blockchain: &AnyBlockchainConfig) -> String {
+    match blockchain {
+        AnyBlockchainConfig::Esplora(esplora) => esplora.base_url.clone(),
+        #[allow(unreachable_patterns)]
+        _ => "the configured backend".to_string(),
+    }
+}
+
+/// Puts the default backend for the network in a config that doesn't have one. Returns whether it
+/// did.
+pub fn fill_in_default_backend(json_config: &mut serde_json::Value) -> anyhow::Result<bool> {
+    let config = match json_config.as_object_mut() {
+        Some(config) if !config.contains_key("blockchain") => config,
+        // anything else is left for deserializing the config to complain about
+        _ => return Ok(false),
+    };
+    let network = match config.get("network") {
+        Some(network) => serde_json::from_value::<Network>(network.clone())?,
+        None => return Ok(false),
+    };
+    config.insert(
+        "blockchain".to_string(),
+        serde_json::to_value(Config::default_blockchain(network))?,
+    );
+    Ok(true)
+}
+
+/// Checks that the Esplora server at `base_url` is on `network` by asking for its genesis block.
+fn check_network(base_url: &str, network: Network) -> anyhow::Result<()> {
+    let client = crate::endpoint::http_client()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let genesis = client
+        .get(format!("{}/block-height/0", base_url))
+        .send()
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.text())
+        .with_context(|| format!("asking {} for its genesis block", base_url))?;
+    if genesis.trim() != genesis_block(network).block_hash().to_string() {
+        return Err(anyhow!(
+            "{} isn't a {} backend (its genesis block is {}). Use --no-check if you're sure.",
+            base_url,
+            network,
+            genesis.trim()
+        ));
+    }
+    Ok(())
+}
+
+pub use crate::pinned_backend::normalize_fingerprint;
+
+/// Gets the SHA256 fingerprint of the certificate presented by the server at `url`.
+///
+/// Returns `None` if the url does not use TLS.
+pub fn fetch_cert_fingerprint(url: &Url) -> anyhow::Result<Option<String>> {
+    if url.scheme() != "https" {
+        return Ok(None);
+    }
+    if crate::endpoint::proxy_for(url).is_some() {
+        return Err(anyhow!(
+            "{} is reached through a proxy so the certificate it presents can't be looked at",
+            url
+        ));
+    }
+    let host = url.host_str().ok_or(anyhow!("url {} missing host", url))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    // We only want to look at the certificate. Self-signed certificates are common for personal
+    // servers so we don't validate it here -- the fingerprint check is the validation. The
+    // backend's traffic is checked against the pin on its own connections (see
+    // `crate::pinned_backend`).
+    let (_, fingerprint) = crate::pinned_backend::tls_connect(
+        host.trim_start_matches('[').trim_end_matches(']'),
+        port,
+    )?;
+    Ok(Some(fingerprint))
+}
+
+/// Checks the certificate of the configured backend against the pinned fingerprint (if any) and
+/// against the last one we saw.
+pub fn check_backend_certificate(wallet_dir: &PathBuf, config: &Config) -> anyhow::Result<()> {
+    let base_url = match &config.blockchain {
+        AnyBlockchainConfig::Esplora(esplora) => &esplora.base_url,
+        #[allow(unreachable_patterns)]
+        _ => return Ok(()),
+    };
+    let url = Url::parse(base_url).with_context(|| format!("parsing backend url {}", base_url))?;
+    let pinned = config
+        .pinned_cert_sha256
+        .as_ref()
+        .map(|pinned| normalize_fingerprint(pinned));
+    if crate::endpoint::proxy_for(&url).is_some() {
+        return match pinned {
+            Some(_) => Err(anyhow!(
+                "a certificate is pinned in the config but the backend {} is reached through a proxy so it can't be checked",
+                url
+            )),
+            // the proxy (e.g. Tor to an onion service) is what's trusted
+            None => Ok(()),
+        };
+    }
+
+    let fingerprint = match fetch_cert_fingerprint(&url)? {
+        Some(fingerprint) => fingerprint,
+        None => {
+            if pinned.is_some() {
+                return Err(anyhow!(
+                    "a certificate is pinned in the config but the backend {} doesn't use https",
+                    url
+                ));
+            }
+            return Ok(());
+        }
+    };
+
+    if let Some(pinned) = pinned {
+        if pinned != fingerprint {
+            return Err(anyhow!(
+                "the certificate of {} has fingerprint {} which does not match the pinned fingerprint {}. Someone may be intercepting your connection!",
+                url,
+                fingerprint,
+                pinned
+            ));
+        }
+    }
+
+    let mut seen_file = wallet_dir.clone();
+    seen_file.push(SEEN_CERT_FILE);
+    if let Ok(previous) = crate::ephemeral::read_to_string(&seen_file) {
+        if previous.trim() != fingerprint {
+            eprintln!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+            eprintln!(
+                "WARNING: the TLS certificate of {} has CHANGED since last time.",
+                url
+            );
+            eprintln!("  previous: {}", previous.trim());
+            eprintln!("  now:      {}", fingerprint);
+            eprintln!("This can be a routine certificate renewal or a man-in-the-middle.");
+            eprintln!("Set pinned-cert-sha256 in the config to refuse unexpected certificates.");
+            eprintln!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+        }
+    }
+    crate::ephemeral::write(&seen_file, &fingerprint)
+        .with_context(|| format!("writing {}", seen_file.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_backend_only_when_missing() {
+        let mut json_config = serde_json::json!({ "network": "regtest" });
+        assert!(fill_in_default_backend(&mut json_config).unwrap());
+        assert_eq!(
+            serde_json::from_value::<AnyBlockchainConfig>(json_config["blockchain"].clone())
+                .map(|blockchain| backend_url(&blockchain))
+                .unwrap(),
+            "http://localhost:3000"
+        );
+        assert!(!fill_in_default_backend(&mut json_config).unwrap());
+    }
+}
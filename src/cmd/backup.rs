@@ -0,0 +1,408 @@
+use super::*;
+use crate::{
+    backup::{
+        passphrase_salt, Backup, BackupEntry, BackupKeys, BackupLock, LastBackup, SealedBackup,
+        LAST_BACKUP_FILE, PASSPHRASE_ROUNDS,
+    },
+    betting::{decode_raw_entry, MapKey},
+    item,
+    keychain::Keychain,
+};
+use bdk::bitcoin::secp256k1::Secp256k1;
+use structopt::StructOpt;
+
+const PASSPHRASE_ENV: &str = "GUN_BACKUP_PASSPHRASE";
+
+#[derive(StructOpt, Debug, Clone)]
+/// Back up the labels, memos, bets and config that can't be recovered from the seed words
+pub enum BackupOpt {
+    /// Write an encrypted backup to a file
+    Create {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Put the seed words in the backup too. The backup is then locked with a passphrase
+        /// (taken from GUN_BACKUP_PASSPHRASE or asked for) instead of the seed.
+        #[structopt(long)]
+        include_seed: bool,
+    },
+    /// Merge a backup into this wallet. If the wallet doesn't exist and the backup has the seed
+    /// words the wallet is created from it.
+    Restore {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Also replace the config with the one in the backup
+        #[structopt(long)]
+        with_config: bool,
+        /// Replace entries that are already in the database with the ones from the backup
+        #[structopt(long)]
+        overwrite: bool,
+        /// Only restore the entry with this key (in hex as `gun db verify` shows it) replacing
+        /// what's in the database. Can be given more than once.
+        #[structopt(long = "entry", parse(try_from_str = parse_entry_key))]
+        entries: Vec<Vec<u8>>,
+    },
+    /// Check that a backup can be opened and belongs to this wallet and show what restoring it
+    /// would do. Nothing is changed.
+    Verify {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+pub fn run_backup_cmd(wallet_dir: &PathBuf, opt: BackupOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        BackupOpt::Create { file, include_seed } => {
+            let (_, bet_db, keychain, config) = load_wallet(wallet_dir)?;
+            let seed_words = if include_seed {
+                Some(read_seed_words(wallet_dir)?.to_string())
+            } else {
+                None
+            };
+            let backup = Backup {
+                created_at: crate::clock::now(),
+                fingerprint: fingerprint(&keychain, config.network),
+                config,
+                entries: bet_db
+                    .raw_entries()?
+                    .into_iter()
+                    .map(|(key, value)| BackupEntry { key, value })
+                    .collect(),
+                seed_words,
+            };
+            let sealed = if include_seed {
+                let salt = passphrase_salt();
+                let passphrase = read_passphrase(PASSPHRASE_ENV, "backup passphrase", true)?;
+                let keys = BackupKeys::from_passphrase(&passphrase, &salt, PASSPHRASE_ROUNDS);
+                SealedBackup::seal(&backup, BackupLock::Passphrase { salt }, &keys)
+            } else {
+                SealedBackup::seal(
+                    &backup,
+                    BackupLock::Seed,
+                    &BackupKeys::from_secret(keychain.backup_secret()),
+                )
+            };
+            fs::write(&file, sealed.to_bytes())
+                .with_context(|| format!("writing backup to {}", file.display()))?;
+            let last_backup = LastBackup {
+                file: fs::canonicalize(&file)?,
+                created_at: backup.created_at,
+            };
+            if let Err(e) = fs::write(
+                wallet_dir.join(LAST_BACKUP_FILE),
+                serde_json::to_string_pretty(&last_backup).unwrap(),
+            ) {
+                eprintln!("couldn't record this as the latest backup: {}", e);
+            }
+
+            Ok(item! {
+                "file" => Cell::string(file.display()),
+                "entries" => Cell::Int(backup.entries.len() as u64),
+                "includes-seed" => Cell::string(include_seed),
+            })
+        }
+        BackupOpt::Restore {
+            file,
+            with_config,
+            overwrite,
+            entries,
+        } => {
+            let backup = open_backup(wallet_dir, &read_sealed_backup(&file)?)?;
+            restore(wallet_dir, backup, with_config, overwrite, &entries)
+        }
+        BackupOpt::Verify { file } => {
+            let sealed = read_sealed_backup(&file)?;
+            let backup = open_backup(wallet_dir, &sealed)?;
+
+            if let Some(seed_words) = &backup.seed_words {
+                let keychain = keychain_from_seed_words(seed_words)
+                    .context("the seed words in the backup are invalid")?;
+                if fingerprint(&keychain, backup.config.network) != backup.fingerprint {
+                    return Err(anyhow!(
+                        "the seed words in the backup don't match its fingerprint {}",
+                        backup.fingerprint
+                    ));
+                }
+            }
+
+            let bet_db = if wallet_dir.exists() {
+                let (_, bet_db, keychain, config) = load_wallet(wallet_dir)?;
+                let wallet_fingerprint = fingerprint(&keychain, config.network);
+                if wallet_fingerprint != backup.fingerprint {
+                    return Err(anyhow!(
+                        "the backup is for the wallet with fingerprint {} but this wallet's is {}",
+                        backup.fingerprint,
+                        wallet_fingerprint
+                    ));
+                }
+                Some(bet_db)
+            } else {
+                None
+            };
+
+            let mut counts = BackupCounts::default();
+            for entry in &backup.entries {
+                let map_key = match decode_raw_entry(&entry.key, &entry.value) {
+                    Ok(map_key) => map_key,
+                    Err(e) => {
+                        eprintln!("invalid entry {}: {:#}", crate::hex::encode(&entry.key), e);
+                        counts.invalid += 1;
+                        continue;
+                    }
+                };
+                match map_key {
+                    MapKey::Bet(_) => counts.bets += 1,
+                    MapKey::OracleInfo(_) => counts.oracles += 1,
+                    MapKey::AddressLabel(_) => counts.address_labels += 1,
+                    MapKey::TxMemo(_) => counts.memos += 1,
+                    MapKey::Frozen(_) => counts.frozen += 1,
+                    MapKey::PendingPsbt(_) => counts.pending_psbts += 1,
+                    // the tip isn't restored, checksums are written with their entries and the
+                    // rest come along with the bets
+                    MapKey::ChainTip | MapKey::Checksum(_) => continue,
+                    MapKey::BetId
+                    | MapKey::ClaimTx(_)
+                    | MapKey::Conflict(_)
+                    | MapKey::ChangeIndex(_)
+                    | MapKey::Journal(_)
+                    | MapKey::SeenAttestations(_)
+                    | MapKey::Chat(_)
+                    | MapKey::Publication(_)
+                    | MapKey::CostBasis(_)
+                    | MapKey::Coinjoin(_)
+                    | MapKey::Allowance(_)
+                    | MapKey::Schedule(_)
+                    | MapKey::OraclePoll(_)
+                    | MapKey::OracleHost(_)
+                    | MapKey::Rotation
+                    | MapKey::Undo(_) => {}
+                }
+                match &bet_db {
+                    Some(bet_db) => match bet_db.get_raw(&entry.key)? {
+                        None => counts.new += 1,
+                        Some(existing) if existing == entry.value => counts.unchanged += 1,
+                        Some(_) => counts.differing += 1,
+                    },
+                    None => counts.new += 1,
+                }
+            }
+
+            if counts.invalid > 0 {
+                return Err(anyhow!(
+                    "{} of the {} entries in the backup are invalid",
+                    counts.invalid,
+                    backup.entries.len()
+                ));
+            }
+
+            Ok(item! {
+                "backup-created-at" => Cell::datetime(backup.created_at),
+                "fingerprint" => Cell::string(backup.fingerprint),
+                "network" => Cell::string(backup.config.network),
+                "includes-seed" => Cell::string(backup.seed_words.is_some()),
+                "bets" => Cell::Int(counts.bets),
+                "oracles" => Cell::Int(counts.oracles),
+                "address-labels" => Cell::Int(counts.address_labels),
+                "memos" => Cell::Int(counts.memos),
+                "frozen" => Cell::Int(counts.frozen),
+                "pending-psbts" => Cell::Int(counts.pending_psbts),
+                "new" => Cell::Int(counts.new),
+                "unchanged" => Cell::Int(counts.unchanged),
+                "differing" => Cell::Int(counts.differing),
+            })
+        }
+    }
+}
+
+#[derive(Default)]
+struct BackupCounts {
+    bets: u64,
+    oracles: u64,
+    address_labels: u64,
+    memos: u64,
+    frozen: u64,
+    pending_psbts: u64,
+    /// Entries that aren't in the database yet
+    new: u64,
+    unchanged: u64,
+    /// Entries that `restore --overwrite` would replace
+    differing: u64,
+    invalid: u64,
+}
+
+fn parse_entry_key(key: &str) -> anyhow::Result<Vec<u8>> {
+    crate::hex::decode(key).map_err(|e| anyhow!("invalid entry key: {}", e))
+}
+
+/// The latest backup `gun backup create` made of the wallet at `wallet_dir` if we know of one.
+pub(crate) fn last_backup(wallet_dir: &PathBuf) -> Option<LastBackup> {
+    let json = fs::read_to_string(wallet_dir.join(LAST_BACKUP_FILE)).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Opens the backup in `file` if it can be opened without asking for a passphrase.
+pub(crate) fn open_seed_locked_backup(
+    wallet_dir: &PathBuf,
+    file: &PathBuf,
+) -> anyhow::Result<Option<Backup>> {
+    let sealed = read_sealed_backup(file)?;
+    match sealed.lock {
+        BackupLock::Seed => Ok(Some(open_backup(wallet_dir, &sealed)?)),
+        BackupLock::Passphrase { .. } => Ok(None),
+    }
+}
+
+/// Merges `backup` into the wallet at `wallet_dir` (creating it if it doesn't exist).
+fn restore(
+    wallet_dir: &PathBuf,
+    backup: Backup,
+    with_config: bool,
+    overwrite: bool,
+    entries: &[Vec<u8>],
+) -> anyhow::Result<CmdOutput> {
+    // nothing is written until we know the backup is this wallet's
+    let seed_words = match wallet_dir.exists() {
+        true => None,
+        false => Some(backup.seed_words.as_ref().ok_or(anyhow!(
+            "there's no wallet at {} and the backup doesn't have the seed words -- recover the wallet with `gun init --from-existing` first",
+            wallet_dir.display()
+        ))?),
+    };
+    let (keychain, network) = match seed_words {
+        Some(seed_words) => (
+            keychain_from_seed_words(seed_words)
+                .context("the seed words in the backup are invalid")?,
+            backup.config.network,
+        ),
+        None => {
+            let (_, _, keychain, config) = load_wallet(wallet_dir)?;
+            let network = match with_config {
+                true => backup.config.network,
+                false => config.network,
+            };
+            (keychain, network)
+        }
+    };
+    if fingerprint(&keychain, network) != backup.fingerprint {
+        return Err(anyhow!(
+            "the backup is for the wallet with fingerprint {} but this wallet's is {}",
+            backup.fingerprint,
+            fingerprint(&keychain, network)
+        ));
+    }
+
+    if let Some(missing) = entries
+        .iter()
+        .find(|key| !backup.entries.iter().any(|entry| &entry.key == *key))
+    {
+        return Err(ErrorKind::NotFound.error(format!(
+            "the backup doesn't have entry {}",
+            crate::hex::encode(missing)
+        )));
+    }
+
+    if let Some(seed_words) = seed_words {
+        fs::create_dir_all(wallet_dir)?;
+        fs::write(get_seed_words_file(wallet_dir), seed_words)?;
+        write_config(wallet_dir, &backup.config)?;
+        eprintln!("Created wallet at {} from the backup", wallet_dir.display());
+    } else if with_config {
+        write_config(wallet_dir, &backup.config)?;
+    }
+    let (_, bet_db, _, _) = load_wallet(wallet_dir)?;
+
+    let mut restored = 0;
+    let mut skipped = 0;
+    for entry in &backup.entries {
+        if !entries.is_empty() {
+            if entries.contains(&entry.key)
+                && bet_db.merge_raw_entry(&entry.key, &entry.value, true)?
+            {
+                restored += 1;
+            }
+            continue;
+        }
+        if bet_db.merge_raw_entry(&entry.key, &entry.value, overwrite)? {
+            restored += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 && !overwrite {
+        eprintln!(
+            "{} entries were already in the database and were left alone -- use --overwrite to replace them",
+            skipped
+        );
+    }
+
+    Ok(item! {
+        "restored" => Cell::Int(restored),
+        "skipped" => Cell::Int(skipped),
+        "backup-created-at" => Cell::datetime(backup.created_at),
+    })
+}
+
+fn open_backup(wallet_dir: &PathBuf, sealed: &SealedBackup) -> anyhow::Result<Backup> {
+    match sealed.lock {
+        BackupLock::Passphrase { salt } => {
+            let passphrase = read_passphrase(PASSPHRASE_ENV, "backup passphrase", false)?;
+            sealed.open(&BackupKeys::from_passphrase(
+                &passphrase,
+                &salt,
+                PASSPHRASE_ROUNDS,
+            ))
+        }
+        BackupLock::Seed => {
+            let (_, _, keychain, _) = load_wallet(wallet_dir)
+                .context("this backup can only be opened by the wallet that made it")?;
+            sealed.open(&BackupKeys::from_secret(keychain.backup_secret()))
+        }
+    }
+}
+
+fn fingerprint(keychain: &Keychain, network: Network) -> bdk::bitcoin::util::bip32::Fingerprint {
+    keychain
+        .main_wallet_xprv(network)
+        .fingerprint(&Secp256k1::signing_only())
+}
+
+fn read_sealed_backup(file: &PathBuf) -> anyhow::Result<SealedBackup> {
+    let bytes = fs::read(file).with_context(|| format!("reading backup {}", file.display()))?;
+    SealedBackup::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{cmd::init::generate_seed_words, config::Config};
+
+    #[test]
+    fn mismatched_backup_leaves_config_alone() {
+        let wallet_dir =
+            std::env::temp_dir().join(format!("gun-restore-test-{}", std::process::id()));
+        let config = Config::default_config(Network::Regtest);
+        crate::cmd::init::create_wallet_dir(
+            &wallet_dir,
+            &config,
+            &generate_seed_words(12).unwrap(),
+        )
+        .unwrap();
+        let config_before = fs::read(wallet_dir.join("config.json")).unwrap();
+
+        let other = keychain_from_seed_words(&generate_seed_words(12).unwrap()).unwrap();
+        let mut backup_config = Config::default_config(Network::Regtest);
+        backup_config.signers = vec!["not-ours".into()];
+        let backup = Backup {
+            created_at: crate::clock::now(),
+            fingerprint: fingerprint(&other, Network::Regtest),
+            config: backup_config,
+            entries: vec![],
+            seed_words: None,
+        };
+
+        let result = restore(&wallet_dir, backup, true, false, &[]);
+        let config_after = fs::read(wallet_dir.join("config.json")).unwrap();
+        fs::remove_dir_all(&wallet_dir).unwrap();
+        assert!(result.is_err());
+        assert_eq!(config_after, config_before);
+    }
+}
@@ -1,17 +1,26 @@
 use super::{run_oralce_cmd, Cell};
 use crate::{
+    amount_ext::FromCliStr,
     betting::*,
     cmd::{self, read_answer, CmdOutput},
+    exit_code::ErrorKind,
+    i18n::{tr, tr_args},
     item,
     keychain::Keychain,
     psbt_ext::PsbtFeeRate,
+    watch::{WatchExport, WatchedBet},
     Url, ValueChoice,
 };
 use anyhow::*;
-use bdk::bitcoin::{Address, Script};
+use bdk::{
+    bitcoin::{Address, Amount, Script, Txid},
+    database::{BatchDatabase, Database},
+    Wallet,
+};
 use chacha20::cipher::StreamCipher;
-use olivia_core::{chrono::Utc, Descriptor, Outcome, OutcomeError};
-use std::{path::PathBuf, str::FromStr};
+use olivia_core::{Descriptor, Outcome, OutcomeError};
+use olivia_secp256k1::fun::marker::{NonZero, Normal};
+use std::{fs, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
 #[derive(Clone, Debug, structopt::StructOpt)]
@@ -21,6 +30,12 @@ pub struct BetArgs {
     /// tag the bet with short string
     #[structopt(short, long)]
     pub tags: Vec<String>,
+    /// Claim the winnings to this address (e.g. a cold wallet) instead of this wallet
+    #[structopt(long)]
+    pub claim_to: Option<Address>,
+    /// Make the bet even if it's more than the bet-limits setting allows
+    #[structopt(long)]
+    pub over_limits: bool,
 }
 
 impl From<BetArgs> for crate::betting::BetArgs<'_, '_> {
@@ -28,6 +43,8 @@ impl From<BetArgs> for crate::betting::BetArgs<'_, '_> {
         crate::betting::BetArgs {
             value: args.value,
             tags: args.tags,
+            claim_to: args.claim_to,
+            over_limits: args.over_limits,
             ..Default::default()
         }
     }
@@ -38,13 +55,31 @@ impl From<BetArgs> for crate::betting::BetArgs<'_, '_> {
 pub enum BetOpt {
     /// Propose an event to bet on
     Propose {
-        #[structopt(flatten)]
-        args: BetArgs,
+        /// The value you want to risk on the bet e.g all, 0.05BTC
+        #[structopt(required_unless = "batch")]
+        value: Option<ValueChoice>,
         /// the HTTP url for the event
-        event_url: Url,
+        #[structopt(required_unless = "batch")]
+        event_url: Option<Url>,
+        /// tag the bet with short string
+        #[structopt(short, long)]
+        tags: Vec<String>,
+        /// Claim the winnings to this address (e.g. a cold wallet) instead of this wallet
+        #[structopt(long)]
+        claim_to: Option<Address>,
+        /// Make a proposal for each market in a JSON or CSV file instead (see `Market`)
+        #[structopt(long, parse(from_os_str), conflicts_with_all = &["value", "event-url", "pot"])]
+        batch: Option<PathBuf>,
         /// Print the proposal without asking
         #[structopt(long, short)]
         yes: bool,
+        /// Use the same coins as this other proposal instead of reserving more. Once one of them is
+        /// taken the others are canceled.
+        #[structopt(long)]
+        pot: Option<BetId>,
+        /// Propose the bet even if it's more than the bet-limits setting allows
+        #[structopt(long)]
+        over_limits: bool,
     },
     /// Make an offer to a proposal
     Offer {
@@ -53,7 +88,11 @@ pub enum BetOpt {
         /// The outcome to choose
         choice: String,
         /// The propsal string
-        proposal: VersionedProposal,
+        #[structopt(required_unless = "paste")]
+        proposal: Option<VersionedProposal>,
+        /// Take the proposal from the clipboard
+        #[structopt(long, conflicts_with = "proposal")]
+        paste: bool,
         /// Make the offer without asking
         #[structopt(long, short)]
         yes: bool,
@@ -62,18 +101,52 @@ pub enum BetOpt {
         pad: usize,
         #[structopt(flatten)]
         fee_args: cmd::FeeArgs,
+        #[structopt(flatten)]
+        rbf_args: cmd::RbfArgs,
         /// Attach an additional message to the offer
         #[structopt(long, short)]
         message: Option<String>,
     },
     /// Inspect an offer or proposal string
     Inspect(InspectOpt),
+    /// Print every field of a proposal or offer string without saving anything. Offers can only be
+    /// read if they were made to one of your proposals.
+    Decode {
+        /// The proposal or offer string
+        string: String,
+    },
+    /// Show what would happen if an offer to a proposal was taken and the oracle attested to an
+    /// outcome: the transactions, who gets paid how much after fees and when. Nothing is signed,
+    /// saved or sent.
+    Simulate {
+        /// The proposal string
+        proposal: VersionedProposal,
+        /// The outcome the oracle attests to
+        #[structopt(long)]
+        outcome: String,
+        /// The outcome the offer chooses
+        #[structopt(long)]
+        offer_choice: String,
+        /// The value the offer risks (default: the same as the proposal)
+        #[structopt(long, parse(try_from_str = FromCliStr::from_cli_str))]
+        offer_value: Option<Amount>,
+        /// The number of coins the offer spends
+        #[structopt(long, default_value = "1")]
+        offer_inputs: usize,
+        /// The feerate (sats per vbyte) of the bet and claim transactions
+        #[structopt(long, default_value = "5")]
+        feerate: f32,
+    },
     /// Take on offer made to your proposal
     Take {
         /// The bet id you are taking the bet from
         id: BetId,
         /// The offer string (a base20248 string)
-        encrypted_offer: Ciphertext,
+        #[structopt(required_unless = "paste")]
+        encrypted_offer: Option<Ciphertext>,
+        /// Take the offer from the clipboard
+        #[structopt(long, conflicts_with = "encrypted-offer")]
+        paste: bool,
         /// Take the offer and broadacast tx without prompting.
         #[structopt(short, long)]
         yes: bool,
@@ -88,6 +161,8 @@ pub enum BetOpt {
     Claim {
         #[structopt(flatten)]
         fee_args: cmd::FeeArgs,
+        #[structopt(flatten)]
+        rbf_args: cmd::RbfArgs,
         /// Also spend bets that are already in the "claiming" state replacing the previous
         /// transaction.
         #[structopt(long)]
@@ -101,7 +176,10 @@ pub enum BetOpt {
         yes: bool,
     },
     /// List bets
-    List,
+    List {
+        #[structopt(flatten)]
+        page: cmd::PageArgs,
+    },
     /// Show details of a particular bet
     Show {
         /// The id of the bet you want to show.
@@ -110,6 +188,24 @@ pub enum BetOpt {
         #[structopt(long, short)]
         raw: bool,
     },
+    /// Show how the keys of a bet are made from the seed and check that making them again gives
+    /// the keys in the database.
+    Keys {
+        /// The bet to show the keys of
+        #[structopt(required_unless = "all")]
+        id: Option<BetId>,
+        /// Check the keys of every bet instead
+        #[structopt(long, conflicts_with = "id")]
+        all: bool,
+    },
+    /// Make a signed receipt of how a bet went once the oracle has attested to it. Check one with
+    /// `gun bet receipt verify <file>`.
+    Receipt {
+        /// The bet to make a receipt for
+        id: Option<BetId>,
+        #[structopt(subcommand)]
+        verify: Option<ReceiptOpt>,
+    },
     /// Cancel a bet
     Cancel {
         /// The bets to cancel.
@@ -131,6 +227,85 @@ pub enum BetOpt {
         /// The list of bet ids to forget about
         ids: Vec<BetId>,
     },
+    /// Remove old finished bets from the database, keeping a copy of them in an archive file.
+    ///
+    /// Run `gun db compact` afterwards to give the space back.
+    Prune {
+        /// Only prune bets whose outcome was due longer ago than this e.g. 30d, 12w, 1y
+        #[structopt(long, parse(try_from_str = parse_age))]
+        older_than: crate::chrono::Duration,
+        /// The states of the bets to prune out of lost, claimed and canceled
+        #[structopt(long, use_delimiter = true, default_value = "lost,claimed,canceled")]
+        state: Vec<String>,
+        /// The file to add the pruned bets to (default: pruned-bets.json in the wallet directory)
+        #[structopt(long, parse(from_os_str))]
+        archive: Option<PathBuf>,
+        /// Show what would be pruned without removing anything
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Finish or roll back bet operations that were interrupted (e.g. by a crash between
+    /// broadcasting and recording a bet). This also happens whenever gun syncs.
+    Resume,
+    /// Send encrypted messages to the other side of a bet over nostr (see `nostr-relays` in the
+    /// config) and show the messages so far.
+    Chat {
+        /// The bet to chat about
+        id: BetId,
+        /// The message to send. Without it new messages are fetched and the chat is shown.
+        #[structopt(short, long)]
+        message: Option<String>,
+        /// Only show the messages already fetched
+        #[structopt(long, conflicts_with = "message")]
+        offline: bool,
+    },
+    /// Advertise a proposal on the boards in `proposal-boards` in the config so others can find
+    /// it. It's taken down again when it's taken, canceled or its event happens.
+    Publish {
+        /// The proposal to publish
+        id: BetId,
+        /// The boards to publish to (default: all of them)
+        #[structopt(long)]
+        board: Vec<String>,
+    },
+    /// Take a proposal down from the boards it's been published to
+    Unpublish {
+        /// The proposal to take down
+        id: BetId,
+    },
+    /// List where proposals have been published
+    Published,
+    /// Write the public parts of bets to a file for `gun watch import` on another machine. Nothing
+    /// in it can be used to claim or cancel the bets.
+    ExportWatch {
+        /// The bets to export (default: every bet that isn't finished)
+        ids: Vec<BetId>,
+        /// Where to write them
+        #[structopt(long, short, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Find bets the database has lost (e.g. after restoring from the seed words) and put them
+    /// back.
+    ///
+    /// Without --proposal this only lists the wallet's transactions that look like bets. The
+    /// joint output of a bet depends on the other side's key and the oracle's event so to make it
+    /// again the proposal string of the bet is needed too and, for bets you proposed, the offer
+    /// that was taken. Restore from a backup with `gun backup restore` instead if you have one.
+    RecoverFromSeed {
+        /// Only look at transactions confirmed at or after this height
+        #[structopt(long)]
+        from_height: Option<u32>,
+        /// Only look at transactions confirmed at or before this height (this leaves out the
+        /// mempool)
+        #[structopt(long)]
+        to_height: Option<u32>,
+        /// The proposals lost bets may have been made from
+        #[structopt(long)]
+        proposal: Vec<VersionedProposal>,
+        /// The offers that were taken for your proposals
+        #[structopt(long)]
+        offer: Vec<Ciphertext>,
+    },
     /// Edit list of trusted oracles
     Oracle(crate::cmd::OracleOpt),
     /// Tag a bet
@@ -148,6 +323,40 @@ pub enum BetOpt {
     },
 }
 
+impl BetOpt {
+    /// What `--sync` syncs before the command runs.
+    pub fn sync_scope(&self) -> SyncScope {
+        use BetOpt::*;
+        match self {
+            // these spend the wallet's coins
+            Propose { .. } | Offer { .. } | Take { .. } => SyncScope::Full,
+            // these only spend or look at bets
+            Claim { .. }
+            | Cancel { .. }
+            | List { .. }
+            | Show { .. }
+            | Receipt { .. }
+            | Prune { .. }
+            | Resume => SyncScope::Bets,
+            // this syncs what it needs itself
+            RecoverFromSeed { .. } => SyncScope::Nothing,
+            Inspect(_)
+            | Decode { .. }
+            | Simulate { .. }
+            | Keys { .. }
+            | Forget { .. }
+            | Chat { .. }
+            | Publish { .. }
+            | Unpublish { .. }
+            | Published
+            | ExportWatch { .. }
+            | Oracle(_)
+            | Tag(_)
+            | Reply { .. } => SyncScope::Nothing,
+        }
+    }
+}
+
 #[derive(Clone, Debug, StructOpt)]
 #[structopt(about = "Inspect a base2048", rename_all = "kebab")]
 pub enum InspectOpt {
@@ -165,6 +374,16 @@ pub enum InspectOpt {
     },
 }
 
+#[derive(Clone, Debug, StructOpt)]
+pub enum ReceiptOpt {
+    /// Check a receipt made by either side of a bet. This doesn't need the wallet.
+    Verify {
+        /// The file with the receipt in it
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub enum TagOpt {
     /// Add a tag to a bet
@@ -188,31 +407,67 @@ pub fn run_bet_cmd(
     cmd: BetOpt,
     sync: bool,
 ) -> anyhow::Result<cmd::CmdOutput> {
-    // For now just always do this but we may want to do something more fine grained later.
-    if sync {
+    let scope = cmd.sync_scope();
+    if sync && scope != SyncScope::Nothing {
         let party = cmd::load_party(wallet_dir)?;
-        party.sync()?;
-        party.poke_bets();
+        cmd::check_backend_certificate(wallet_dir, &cmd::load_config(wallet_dir)?)?;
+        party.sync_scope(scope)?;
     }
 
     match cmd {
         BetOpt::Propose {
-            args,
+            value,
             event_url,
+            tags,
+            claim_to,
+            batch,
             yes,
+            pot,
+            over_limits,
         } => {
             let party = cmd::load_party(wallet_dir)?;
+            if let Some(batch) = batch {
+                return propose_batch(&party, &batch, yes);
+            }
+            let (args, event_url) = match (value, event_url) {
+                (Some(value), Some(event_url)) => (
+                    BetArgs {
+                        value,
+                        tags,
+                        claim_to,
+                        over_limits,
+                    },
+                    event_url,
+                ),
+                _ => return Err(anyhow!("a value and an event url are needed")),
+            };
             let oracle_id = event_url.host_str().unwrap().to_string();
-            let now = Utc::now().naive_utc();
-            let (oracle_event, _, is_attested) =
-                get_oracle_event_from_url(party.bet_db(), event_url)?;
+            if let Some(pot) = pot {
+                match party.bet_db().get_entity::<BetState>(pot)? {
+                    Some(BetState::Proposed { .. }) => {}
+                    Some(_) => {
+                        return Err(anyhow!(
+                            "bet {} isn't a proposal anymore so its coins can't be shared",
+                            pot
+                        ))
+                    }
+                    None => {
+                        return Err(ErrorKind::NotFound.error(format!("bet {} doesn\'t exist", pot)))
+                    }
+                }
+            }
+            let now = crate::clock::now();
+            let (oracle_event, _, is_attested) = get_oracle_event_from_url(&party, event_url)?;
             if is_attested {
                 return Err(anyhow!("{} already attested", oracle_event.event.id));
             }
 
-            let mut question = format!(
-                "You are proposing a bet on the {}.",
-                olivia_describe::event_id_short(&oracle_event.event.id)
+            let mut question = tr_args(
+                "You are proposing a bet on the {event}.",
+                &[(
+                    "event",
+                    &olivia_describe::event_id_short(&oracle_event.event.id),
+                )],
             );
             if let Some(expected_outcome_time) = oracle_event.event.expected_outcome_time {
                 if expected_outcome_time <= now {
@@ -223,14 +478,29 @@ pub fn run_bet_cmd(
                         now
                     ));
                 }
-                question += &format!(
-                    "\nThe outcome is expected to be known at {} UTC (in {}).",
-                    expected_outcome_time,
-                    crate::format_dt_diff_till_now(expected_outcome_time)
+                question += &tr_args(
+                    "\nThe outcome is expected to be known at {time} UTC (in {left}).",
+                    &[
+                        ("time", &expected_outcome_time),
+                        (
+                            "left",
+                            &crate::format_dt_diff_till_now(expected_outcome_time),
+                        ),
+                    ],
                 );
             }
-            question += " Ok?";
-            let local_proposal = party.make_proposal(oracle_id, oracle_event, args.into())?;
+            question += tr(" Ok?");
+            let pot = pot.into_iter().collect::<Vec<_>>();
+            let local_proposal = party.make_proposal(
+                oracle_id,
+                oracle_event,
+                crate::betting::BetArgs {
+                    may_overlap: &pot,
+                    must_overlap: &pot,
+                    only_overlap: !pot.is_empty(),
+                    ..args.into()
+                },
+            )?;
             if let Some(change) = &local_proposal.change {
                 eprintln!("This proposal will put {} “in-use” unnecessarily because the bet value {} does not match a sum of available utxos.\nYou can get a utxo with the exact amount using `gun split` first.\n--",  change.value(), local_proposal.proposal.value);
             }
@@ -255,14 +525,21 @@ pub fn run_bet_cmd(
             proposal,
             choice,
             fee_args,
+            rbf_args,
             yes,
             pad,
             message,
+            paste,
         } => {
             let party = cmd::load_party(wallet_dir)?;
-            let proposal: Proposal = proposal.into();
+            let proposal: Proposal = match proposal {
+                Some(proposal) => proposal,
+                None if paste => crate::clipboard::paste_as::<VersionedProposal>("a proposal")?,
+                None => unreachable!("structopt makes sure there's a proposal"),
+            }
+            .into();
             let event_id = proposal.event_id.clone();
-            let now = Utc::now().naive_utc();
+            let now = crate::clock::now();
 
             if event_id.n_outcomes() != 2 {
                 return Err(anyhow!(
@@ -287,10 +564,10 @@ pub fn run_bet_cmd(
             )?;
 
             let event_url =
-                Url::parse(&format!("https://{}{}", proposal.oracle, proposal.event_id))?;
+                crate::endpoint::oracle_event_url(&proposal.oracle, &proposal.event_id)?;
 
             let (oracle_event, oracle_info, is_attested) =
-                get_oracle_event_from_url(party.bet_db(), event_url)?;
+                get_oracle_event_from_url(&party, event_url)?;
 
             if is_attested {
                 return Err(anyhow!("{} already attested", oracle_event.event.id));
@@ -309,15 +586,18 @@ pub fn run_bet_cmd(
 
             let (bet, offer, local_public_key, mut cipher) = party
                 .generate_offer_with_oracle_event(
-                    proposal,
+                    proposal.clone(),
                     outcome.value == 1,
                     oracle_event,
                     oracle_info,
-                    args.into(),
-                    fee_args.fee,
+                    crate::betting::BetArgs {
+                        rbf: Some(rbf_args.signal(party.settings().rbf.bets)),
+                        ..args.into()
+                    },
+                    fee_args.fee_spec(party.settings()),
                 )?;
 
-            if yes || cmd::read_answer(&bet_prompt(&bet)) {
+            if yes || cmd::read_answer(&bet_prompt(&bet, party.wallet())) {
                 let (id, encrypted_offer) = party.save_and_encrypt_offer(
                     bet,
                     offer,
@@ -325,6 +605,10 @@ pub fn run_bet_cmd(
                     local_public_key,
                     &mut cipher,
                 )?;
+                let remote_public_key = proposal.public_key;
+                party
+                    .bet_db()
+                    .start_chat(id, BetChat::new(proposal, false, remote_public_key))?;
 
                 eprintln!("Post this offer in reponse to the proposal");
                 let (padded_encrypted_offer, overflow) =
@@ -348,9 +632,15 @@ pub fn run_bet_cmd(
         BetOpt::Take {
             id,
             encrypted_offer,
+            paste,
             yes,
             print_tx,
         } => {
+            let encrypted_offer = match encrypted_offer {
+                Some(encrypted_offer) => encrypted_offer,
+                None if paste => crate::clipboard::paste_as::<Ciphertext>("an offer")?,
+                None => unreachable!("structopt makes sure there's an offer"),
+            };
             let party = cmd::load_party(wallet_dir)?;
             let (plaintext, offer_public_key, rng) = party.decrypt_offer(id, encrypted_offer)?;
             match plaintext {
@@ -359,17 +649,34 @@ pub fn run_bet_cmd(
                         eprintln!("This message was attached to the offer:\n{}", message);
                     }
                     let validated_offer = party.validate_offer(id, offer, offer_public_key, rng)?;
-                    if yes || cmd::read_answer(&bet_prompt(&validated_offer.bet)) {
+                    if yes || cmd::read_answer(&bet_prompt(&validated_offer.bet, party.wallet())) {
+                        // if we stop between broadcasting and recording it `gun bet resume` picks
+                        // up from here
+                        if !print_tx {
+                            party.bet_db().begin_journal(
+                                id,
+                                JournalOperation::Take {
+                                    bet: validated_offer.bet.clone(),
+                                },
+                            )?;
+                        }
                         let (output, txid) = cmd::decide_to_broadcast(
                             party.wallet().network(),
                             party.wallet().client(),
                             validated_offer.bet.psbt.clone(),
                             yes,
                             print_tx,
+                            party.audit_log(),
+                            party.bet_db(),
+                            "take",
                         )?;
                         if let Some(_) = txid {
+                            for sibling in party.bet_db().sibling_proposals(id)? {
+                                eprintln!("proposal {} shares its coins with this one so it has been canceled", sibling);
+                            }
                             party.set_offer_taken(validated_offer)?;
                         }
+                        party.bet_db().end_journal(id)?;
                         Ok(output)
                     } else {
                         Ok(CmdOutput::None)
@@ -383,32 +690,69 @@ pub fn run_bet_cmd(
         }
         BetOpt::Claim {
             fee_args,
+            rbf_args,
             bump_claiming,
             print_tx,
             yes,
         } => {
             let party = cmd::load_party(wallet_dir)?;
             let wallet = party.wallet();
-            match party.claim(fee_args.fee, bump_claiming)? {
-                Some((ids, claim_psbt)) => {
-                    let (output, txid) = cmd::decide_to_broadcast(
-                        wallet.network(),
-                        wallet.client(),
-                        claim_psbt,
-                        yes,
-                        print_tx,
-                    )?;
-                    if let Some(txid) = txid {
-                        for id in ids {
-                            if let Err(e) = party.take_next_action(id, false) {
-                                eprintln!("error updating state of bet {} after broadcasting claim tx {}: {}", id, txid, e);
-                            }
+            let rbf = rbf_args.signal(party.settings().rbf.bets);
+            let claims = party.claim(fee_args.fee_spec(party.settings()), bump_claiming, rbf)?;
+            let n_claims = claims.len();
+            let mut outputs = vec![];
+            let mut claimed = vec![];
+            // bets claimed to different places each get their own transaction
+            for (i, (ids, claim_psbt, finalized)) in claims.into_iter().enumerate() {
+                if n_claims > 1 {
+                    eprintln!("claim transaction {} of {}", i + 1, n_claims);
+                }
+                if !finalized {
+                    // the bets are cosigned by an offline key
+                    let txid = party.bet_db().insert_pending_psbt(claim_psbt, ids)?;
+                    eprintln!(
+                        "The claim needs the bet cosigner's signature so it has been saved. Use `gun psbt export {}` to get it signed with the offline key and `gun psbt import` to broadcast it.",
+                        txid
+                    );
+                    claimed.push(Cell::string(txid));
+                    outputs.push(item! { "txid" => Cell::string(txid) });
+                    continue;
+                }
+                let tx = claim_psbt.clone().extract_tx();
+                let (output, txid) = cmd::decide_to_broadcast(
+                    wallet.network(),
+                    wallet.client(),
+                    claim_psbt,
+                    yes,
+                    print_tx,
+                    party.audit_log(),
+                    party.bet_db(),
+                    "claim",
+                )?;
+                if let Some(txid) = txid {
+                    claimed.push(if print_tx {
+                        Cell::String(crate::hex::encode(
+                            &bdk::bitcoin::consensus::encode::serialize(&tx),
+                        ))
+                    } else {
+                        Cell::string(txid)
+                    });
+                    for id in ids {
+                        if let Err(e) = party.take_next_action(id, false) {
+                            eprintln!(
+                                "error updating state of bet {} after broadcasting claim tx {}: {}",
+                                id, txid, e
+                            );
                         }
                     }
-                    Ok(output)
                 }
-                None => Ok(CmdOutput::None),
+                outputs.push(output);
             }
+            Ok(match outputs.len() {
+                0 => CmdOutput::None,
+                1 => outputs.remove(0),
+                _ => CmdOutput::List(claimed),
+            })
         }
         BetOpt::Cancel {
             ids,
@@ -417,30 +761,35 @@ pub fn run_bet_cmd(
             print_tx,
         } => {
             let party = cmd::load_party(wallet_dir)?;
-            Ok(match party.generate_cancel_tx(&ids, fee_args.fee)? {
-                Some(psbt) => {
-                    let (output, txid) = cmd::decide_to_broadcast(
-                        party.wallet().network(),
-                        party.wallet().client(),
-                        psbt,
-                        yes,
-                        print_tx,
-                    )?;
+            Ok(
+                match party.generate_cancel_tx(&ids, fee_args.fee_spec(party.settings()))? {
+                    Some(psbt) => {
+                        let (output, txid) = cmd::decide_to_broadcast(
+                            party.wallet().network(),
+                            party.wallet().client(),
+                            psbt,
+                            yes,
+                            print_tx,
+                            party.audit_log(),
+                            party.bet_db(),
+                            "cancel",
+                        )?;
 
-                    if let Some(txid) = txid {
-                        for id in ids {
-                            if let Err(e) = party.take_next_action(id, true) {
-                                eprintln!("error updating state of bet {} after broadcasting cancel tx: {}: {}", id, txid, e);
+                        if let Some(txid) = txid {
+                            for id in ids {
+                                if let Err(e) = party.take_next_action(id, true) {
+                                    eprintln!("error updating state of bet {} after broadcasting cancel tx: {}: {}", id, txid, e);
+                                }
                             }
                         }
+                        output
                     }
-                    output
-                }
-                None => {
-                    eprintln!("no bets needed canceling");
-                    CmdOutput::None
-                }
-            })
+                    None => {
+                        eprintln!("no bets needed canceling");
+                        CmdOutput::None
+                    }
+                },
+            )
         }
         BetOpt::Forget { ids } => {
             let bet_db = cmd::load_bet_db(wallet_dir)?;
@@ -450,13 +799,13 @@ pub fn run_bet_cmd(
                     Ok(Some(bet_state)) => match bet_state {
                         BetState::Proposed { local_proposal } => {
                             match local_proposal.oracle_event.event.expected_outcome_time {
-                                Some(expected_outcome_time) if expected_outcome_time < Utc::now().naive_utc() => to_remove.push(id),
-                                _ => if cmd::read_answer(&format!("You should only forget a proposal if you are you confident no one will make an offer to it.\nIf you're not sure it's better to cancel it properly using `gun bet cancel`.\nAre you sure you want to forget your proposed bet {}", id)) {
+                                Some(expected_outcome_time) if expected_outcome_time < crate::clock::now() => to_remove.push(id),
+                                _ => if cmd::read_answer(&tr_args("You should only forget a proposal if you are you confident no one will make an offer to it.\nIf you're not sure it's better to cancel it properly using `gun bet cancel`.\nAre you sure you want to forget your proposed bet {id}", &[("id", &id)])) {
                                     to_remove.push(id);
                                 }
                             }
                         },
-                        BetState::Offered { .. } => if cmd::read_answer(&format!("Forgetting an offer can lead to loss of funds if it has been seen by the proposer. Are you sure you want to forget bet {}", id)) {
+                        BetState::Offered { .. } => if cmd::read_answer(&tr_args("Forgetting an offer can lead to loss of funds if it has been seen by the proposer. Are you sure you want to forget bet {id}", &[("id", &id)])) {
                             to_remove.push(id);
                         },
                         BetState::Won { .. } | BetState::Claimed { height: None, .. } | BetState::Canceled { height: None, .. } | BetState::Included { .. }  => return Err(anyhow!("You may not forget bet {} because it is in the {} state", id, bet_state.name())),
@@ -470,14 +819,240 @@ pub fn run_bet_cmd(
                 }
             }
 
+            if !to_remove.is_empty() {
+                bet_db.record_undo(
+                    format!(
+                        "forget bet {}",
+                        to_remove
+                            .iter()
+                            .map(|id| id.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    to_remove
+                        .iter()
+                        .flat_map(|id| vec![MapKey::Bet(*id), MapKey::Chat(*id)])
+                        .collect(),
+                )?;
+            }
             for id in &to_remove {
                 let _ = bet_db.remove_entity::<BetState>(*id);
+                let _ = bet_db.remove_entity::<BetChat>(*id);
             }
 
             Ok(CmdOutput::List(
                 to_remove.into_iter().map(|x| Cell::string(x)).collect(),
             ))
         }
+        BetOpt::Prune {
+            older_than,
+            state,
+            archive,
+            dry_run,
+        } => {
+            let states = state
+                .iter()
+                .map(|state| match state.as_str() {
+                    "cancelled" => Ok("canceled"),
+                    state @ ("lost" | "claimed" | "canceled") => Ok(state),
+                    state => Err(anyhow!(
+                        "can't prune {} bets -- only lost, claimed and canceled ones",
+                        state
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let cutoff = crate::clock::now() - older_than;
+            let bet_db = cmd::load_bet_db(wallet_dir)?;
+            let conflicts = bet_db
+                .list_entities::<SeenConflict>()
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut pruned = vec![];
+            for (bet_id, bet_state) in bet_db.list_entities_print_error::<BetState>() {
+                if !states.contains(&bet_state.name()) {
+                    continue;
+                }
+                let oracle_event = match bet_state.clone().into_bet_or_prop() {
+                    BetOrProp::Bet(bet)
+                    | BetOrProp::OfferedBet {
+                        bet: OfferedBet(bet),
+                        ..
+                    } => bet.oracle_event,
+                    BetOrProp::Proposal(local_proposal) => local_proposal.oracle_event,
+                };
+                // without an outcome time we can't tell how old it is
+                match oracle_event.event.expected_outcome_time {
+                    Some(outcome_time) if outcome_time < cutoff => {}
+                    _ => continue,
+                }
+                pruned.push(PrunedBet {
+                    bet_id,
+                    chat: bet_db.get_entity::<BetChat>(bet_id)?,
+                    conflicts: conflicts
+                        .iter()
+                        .filter(|(_, conflict)| conflict.bet_id == bet_id)
+                        .cloned()
+                        .collect(),
+                    bet_state,
+                });
+            }
+
+            let ids = pruned
+                .iter()
+                .map(|pruned_bet| Cell::string(pruned_bet.bet_id))
+                .collect();
+            if dry_run || pruned.is_empty() {
+                return Ok(CmdOutput::List(ids));
+            }
+
+            let archive = archive.unwrap_or_else(|| wallet_dir.join("pruned-bets.json"));
+            let mut archived = match fs::read_to_string(&archive) {
+                Ok(existing) => serde_json::from_str::<Vec<PrunedBet>>(&existing)
+                    .with_context(|| format!("reading the archive {}", archive.display()))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+                Err(e) => return Err(e).context(format!("reading {}", archive.display())),
+            };
+            archived.extend(pruned.iter().cloned());
+            // the archive has to be written before anything is removed
+            fs::write(&archive, serde_json::to_string_pretty(&archived).unwrap())
+                .with_context(|| format!("writing the archive {}", archive.display()))?;
+
+            for pruned_bet in &pruned {
+                bet_db.remove_entity::<BetState>(pruned_bet.bet_id)?;
+                bet_db.remove_entity::<BetChat>(pruned_bet.bet_id)?;
+                for (txid, _) in &pruned_bet.conflicts {
+                    bet_db.remove_entity::<SeenConflict>(*txid)?;
+                }
+            }
+            eprintln!(
+                "Archived {} bets to {}. Run `gun db compact` to reclaim the space they took up.",
+                pruned.len(),
+                archive.display()
+            );
+
+            Ok(CmdOutput::List(ids))
+        }
+        BetOpt::Keys { id: None, .. } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let mut rows = vec![];
+            for (bet_id, _) in party.bet_db().list_entities_print_error::<BetState>() {
+                let keys = party.bet_keys(bet_id)?;
+                let problems = keys.problems();
+                rows.push(vec![
+                    Cell::Int(bet_id.into()),
+                    Cell::string(match keys.i_proposed {
+                        true => "proposer",
+                        false => "offerer",
+                    }),
+                    Cell::Int(keys.checks.len() as u64),
+                    Cell::string(match (problems.is_empty(), keys.remote_key.is_some()) {
+                        (false, _) => "FAILED",
+                        (true, true) => "ok",
+                        (true, false) => "ok (the other side's key isn't stored)",
+                    }),
+                    Cell::List(
+                        problems
+                            .into_iter()
+                            .map(|problem| Box::new(Cell::String(problem)))
+                            .collect(),
+                    ),
+                ]);
+            }
+            Ok(CmdOutput::table(
+                vec!["id", "role", "checks", "result", "problems"],
+                rows,
+            ))
+        }
+        BetOpt::Keys { id: Some(id), .. } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let keys = party.bet_keys(id)?;
+            let point_cells = |points: Option<Vec<String>>| match points {
+                Some(points) => Cell::List(
+                    points
+                        .into_iter()
+                        .map(|point| Box::new(Cell::String(point)))
+                        .collect(),
+                ),
+                None => Cell::Empty,
+            };
+            Ok(item! {
+                "role" => Cell::string(match keys.i_proposed {
+                    true => "proposer",
+                    false => "offerer",
+                }),
+                "bet-key-derivation" => Cell::string(keys.derivation()),
+                "proposal" => keys
+                    .proposal
+                    .clone()
+                    .map(|proposal| Cell::string(proposal.into_versioned()))
+                    .unwrap_or(Cell::Empty),
+                "bet-key" => keys.bet_key.map(Cell::string).unwrap_or(Cell::Empty),
+                "remote-key" => keys.remote_key.map(Cell::string).unwrap_or(Cell::Empty),
+                "tweak-derivation" => Cell::string("r1, r2 and swap come from ChaCha20 seeded with the second half of SHA512(x-coordinate of the ECDH point of bet-key and remote-key)"),
+                "r1*G" => keys.tweaks.map(|(r1, _, _)| Cell::string(r1)).unwrap_or(Cell::Empty),
+                "r2*G" => keys.tweaks.map(|(_, r2, _)| Cell::string(r2)).unwrap_or(Cell::Empty),
+                "swapped" => keys.tweaks.map(|(_, _, swapped)| Cell::string(swapped)).unwrap_or(Cell::Empty),
+                "oracle-points" => point_cells(keys.oracle_points.map(|points| {
+                    points
+                        .iter()
+                        .map(|point| match point.mark::<(Normal, NonZero)>() {
+                            Some(point) => point.to_string(),
+                            None => "infinity".to_string(),
+                        })
+                        .collect()
+                })),
+                "output-keys" => point_cells(keys.output_keys.map(|points| {
+                    vec![
+                        format!("proposer: {} = proposer key + oracle point + r1*G", points[0]),
+                        format!("offerer: {} = offerer key + oracle point + r2*G", points[1]),
+                    ]
+                })),
+                "checks" => Cell::List(
+                    keys.checks
+                        .iter()
+                        .map(|(check, problem)| {
+                            Box::new(Cell::String(match problem {
+                                None => format!("ok: {}", check),
+                                Some(problem) => format!("FAILED: {}: {}", check, problem),
+                            }))
+                        })
+                        .collect()
+                ),
+            })
+        }
+        BetOpt::Receipt {
+            verify: Some(ReceiptOpt::Verify { file }),
+            ..
+        } => {
+            let receipt =
+                fs::read_to_string(&file).with_context(|| format!("reading {}", file.display()))?;
+            let receipt = serde_json::from_str::<BetReceipt>(&receipt)
+                .with_context(|| format!("{} isn't a bet receipt", file.display()))?;
+            let verified = receipt.verify().context("the receipt is invalid")?;
+            let role = |proposer: bool| match proposer {
+                true => "proposer",
+                false => "offerer",
+            };
+            Ok(item! {
+                "oracle" => Cell::string(&receipt.oracle.id),
+                "event" => Cell::string(&receipt.proposal.event_id),
+                "outcome" => Cell::String(verified.outcome),
+                "winner" => Cell::string(role(verified.proposer_won)),
+                "value" => Cell::Amount(verified.joint_output_value),
+                "bet-txid" => Cell::string(receipt.funding_txid),
+                "claim-txid" => receipt.claim_txid.map(Cell::string).unwrap_or(Cell::Empty),
+                "signed-by" => Cell::string(role(verified.signed_by_proposer)),
+                "made-at" => Cell::string(receipt.made_at),
+            })
+        }
+        BetOpt::Receipt { id: Some(id), .. } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let receipt = party.bet_receipt(id)?;
+            Ok(CmdOutput::Json(serde_json::to_value(&receipt)?))
+        }
+        BetOpt::Receipt { id: None, .. } => Err(anyhow!(
+            "give the id of the bet to make a receipt for or `verify <file>` to check one"
+        )),
         BetOpt::Show { id, raw } => {
             let party = cmd::load_party(wallet_dir)?;
             let bet_db = party.bet_db();
@@ -489,7 +1064,21 @@ pub fn run_bet_cmd(
                 return Ok(CmdOutput::Json(serde_json::to_value(&bet_state).unwrap()));
             }
 
-            let name = bet_state.name();
+            let tip_height = bet_db.tip_height()?;
+            let name = bet_state.status(tip_height, &party.settings().confirmations);
+            let config = cmd::load_config(wallet_dir)?;
+            let tx_url = |txid: Txid| {
+                config
+                    .explorer_tx_url(txid)
+                    .map(Cell::String)
+                    .unwrap_or(Cell::Empty)
+            };
+            let (cancel_txid, cancel_url) = match &bet_state {
+                BetState::Canceled { cancel_txid, .. } => {
+                    (Cell::string(cancel_txid), tx_url(*cancel_txid))
+                }
+                _ => (Cell::Empty, Cell::Empty),
+            };
 
             Ok(match bet_state.clone().into_bet_or_prop() {
                 BetOrProp::Proposal(local_proposal) => item! {
@@ -502,13 +1091,58 @@ pub fn run_bet_cmd(
                     "change-addr" => local_proposal.change.as_ref().and_then(|change| Address::from_script(change.script(), party.wallet().network())).map(Cell::string).unwrap_or(Cell::Empty),
                     "change-value" => local_proposal.change.as_ref().map(|change| Cell::Amount(change.value())).unwrap_or(Cell::Empty),
                     "tags" => Cell::List(local_proposal.tags.iter().map(Cell::string).map(Box::new).collect()),
+                    "cancel-txid" => cancel_txid,
+                    "cancel-url" => cancel_url,
+                    "shares-pot-with" => Cell::List(bet_db.sibling_proposals(id)?.into_iter().map(Cell::string).map(Box::new).collect()),
                     "string" => Cell::string(local_proposal.proposal.clone().into_versioned()),
                 },
                 BetOrProp::Bet(bet)
                 | BetOrProp::OfferedBet {
                     bet: OfferedBet(bet),
                     ..
-                } => item! {
+                } => {
+                    let wallet = party.wallet();
+                    let funding_txid = bet.outpoint().txid;
+                    let funding_height = wallet
+                        .query_db(|db| db.get_tx(&funding_txid, false))?
+                        .and_then(|tx| tx.confirmation_time)
+                        .map(|time| time.height);
+                    let funding_confirmations = match tip_height {
+                        Some(tip_height) => Cell::Int(crate::betting::confirmations(
+                            funding_height,
+                            tip_height,
+                        ) as u64),
+                        None => Cell::Empty,
+                    };
+                    // our inputs are in the wallet so we know which keys they were derived from
+                    let my_input_descriptors = bet
+                        .my_input_indexes
+                        .iter()
+                        .map(|i| {
+                            let descriptor = bet.psbt.inputs[*i as usize]
+                                .witness_utxo
+                                .as_ref()
+                                .map(|txout| {
+                                    wallet.get_descriptor_for_script_pubkey(&txout.script_pubkey)
+                                })
+                                .transpose()?
+                                .flatten();
+                            Ok(Box::new(
+                                descriptor
+                                    .map(|descriptor| Cell::string(descriptor))
+                                    .unwrap_or(Cell::Empty),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let (claim_txid, claim_url) = match &bet_state {
+                        BetState::Claimed { txid, .. } => (Cell::string(txid), tx_url(*txid)),
+                        _ => (Cell::Empty, Cell::Empty),
+                    };
+                    // decimal odds i.e. what you get back for each coin risked if you win
+                    let odds =
+                        bet.joint_output_value.as_sat() as f64 / bet.local_value.as_sat() as f64;
+
+                    item! {
                     "state" => Cell::string(name),
                     "risk" => Cell::Amount(bet.local_value),
                     "reward" => Cell::Amount(bet.joint_output_value.checked_sub(bet.local_value).unwrap()),
@@ -520,21 +1154,262 @@ pub fn run_bet_cmd(
                     "bet-outpoint" => Cell::string(bet.outpoint()),
                     "bet-value" => Cell::Amount(bet.joint_output_value),
                     "bet-descriptor" => Cell::string(bet.joint_output.descriptor()),
-                    "claim-txid" => match bet_state {
-                        BetState::Claimed { txid, .. } => Cell::string(txid),
-                        _ => Cell::Empty
-                    },
-                    "tags" => Cell::List(bet.tags.iter().map(Cell::string).map(Box::new).collect())
-                },
+                    "payout" => Cell::Amount(bet.joint_output_value),
+                    "odds" => Cell::String(format!("{:.2}", odds)),
+                    "my-input-descriptors" => Cell::List(my_input_descriptors),
+                    "funding-txid" => Cell::string(funding_txid),
+                    "funding-confirmations" => funding_confirmations,
+                    "funding-url" => tx_url(funding_txid),
+                    "claim-to" => party.claim_destination(&bet).map(Cell::string).unwrap_or(Cell::string("wallet")),
+                    "cosigner" => bet.cosigner.as_ref().map(|cosigner| Cell::String(format!("[{}{}]{}", cosigner.fingerprint, cosigner.path.to_string().trim_start_matches('m'), cosigner.public_key))).unwrap_or(Cell::Empty),
+                    "claim-txid" => claim_txid,
+                    "claim-url" => claim_url,
+                    "cancel-txid" => cancel_txid,
+                    "cancel-url" => cancel_url,
+                    "tags" => Cell::List(bet.tags.iter().map(Cell::string).map(Box::new).collect()),
+                    "cost-basis" => bet.cost_basis.as_ref().map(Cell::string).unwrap_or(Cell::Empty),
+                    "risk-fiat" => bet.cost_basis.as_ref().map(|cost_basis| Cell::String(cost_basis.price.format(bet.local_value))).unwrap_or(Cell::Empty),
+                    }
+                }
             })
         }
-        BetOpt::List => {
+        BetOpt::Resume => {
+            let party = cmd::load_party(wallet_dir)?;
+            let rows = party
+                .resume_journal()?
+                .into_iter()
+                .map(|(bet_id, operation, resumed)| {
+                    vec![
+                        Cell::Int(bet_id.into()),
+                        Cell::string(operation.name()),
+                        Cell::string(resumed.name()),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(vec!["id", "operation", "result"], rows))
+        }
+        BetOpt::Decode { string } => decode(wallet_dir, string.trim()),
+        BetOpt::Simulate {
+            proposal,
+            outcome,
+            offer_choice,
+            offer_value,
+            offer_inputs,
+            feerate,
+        } => simulate(
+            proposal.into(),
+            &outcome,
+            &offer_choice,
+            offer_value,
+            offer_inputs,
+            bdk::FeeRate::from_sat_per_vb(feerate),
+        ),
+        BetOpt::List { page } => {
             let bet_db = cmd::load_bet_db(wallet_dir)?;
-            Ok(list_bets(&bet_db))
+            let targets = cmd::load_config(wallet_dir)?.party_settings().confirmations;
+            list_bets(&bet_db, &targets, &page)
+        }
+        BetOpt::Chat {
+            id,
+            message,
+            offline,
+        } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let relays = cmd::load_config(wallet_dir)?.nostr_relays;
+            if !offline && relays.is_empty() {
+                return Err(anyhow!(
+                    "add some nostr relays to nostr-relays in the config to chat"
+                ));
+            }
+            match message {
+                Some(message) => {
+                    party.send_chat_message(id, message, &relays)?;
+                }
+                None if !offline => {
+                    let new = party.fetch_chat_messages(id, &relays)?;
+                    eprintln!("{} new messages", new);
+                }
+                None => {}
+            }
+            let chat = party
+                .bet_db()
+                .get_entity::<BetChat>(id)?
+                .ok_or(anyhow!("bet {} doesn't have a chat", id))?;
+            let rows = chat
+                .messages
+                .into_iter()
+                .map(|message| {
+                    vec![
+                        Cell::datetime(message.sent_at),
+                        Cell::string(match message.from_me {
+                            true => "me",
+                            false => "them",
+                        }),
+                        Cell::String(message.text),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(vec!["sent-at", "from", "message"], rows))
+        }
+        BetOpt::Publish { id, board } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let adverts = party.publish_proposal(id, &board)?;
+            Ok(adverts_output(
+                adverts.into_iter().map(|advert| (id, advert)).collect(),
+            ))
+        }
+        BetOpt::Unpublish { id } => {
+            let party = cmd::load_party(wallet_dir)?;
+            let boards = party.unpublish_proposal(id)?;
+            if boards.is_empty() {
+                eprintln!("proposal {} isn't published anywhere", id);
+            }
+            Ok(CmdOutput::List(
+                boards.into_iter().map(Cell::String).collect(),
+            ))
+        }
+        BetOpt::Published => {
+            let bet_db = cmd::load_bet_db(wallet_dir)?;
+            let adverts = bet_db
+                .list_entities_print_error::<Publication>()
+                .flat_map(|(id, publication)| {
+                    publication
+                        .adverts
+                        .into_iter()
+                        .map(move |advert| (id, advert))
+                })
+                .collect();
+            Ok(adverts_output(adverts))
+        }
+        BetOpt::ExportWatch { ids, output } => {
+            let config = cmd::load_config(wallet_dir)?;
+            let bet_db = cmd::load_bet_db(wallet_dir)?;
+            let bets = match ids.is_empty() {
+                true => bet_db.list_entities_print_error::<BetState>().collect(),
+                false => ids
+                    .into_iter()
+                    .map(|id| {
+                        let bet_state = bet_db.get_entity::<BetState>(id)?.ok_or(
+                            ErrorKind::NotFound.error(format!("bet {} doesn\'t exist", id)),
+                        )?;
+                        Ok((id, bet_state))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            }
+            .into_iter()
+            .filter_map(|(id, bet_state)| WatchedBet::from_bet_state(id, &bet_state))
+            .collect::<Vec<_>>();
+
+            let mut oracles = vec![];
+            for bet in &bets {
+                if oracles
+                    .iter()
+                    .any(|oracle: &OracleInfo| oracle.id == bet.oracle_id)
+                {
+                    continue;
+                }
+                oracles.push(
+                    bet_db
+                        .get_entity::<OracleInfo>(bet.oracle_id.clone())?
+                        .ok_or(anyhow!("oracle {} is missing", bet.oracle_id))?,
+                );
+            }
+
+            let export = WatchExport {
+                network: config.network,
+                oracles,
+                bets,
+            };
+            fs::write(&output, serde_json::to_string_pretty(&export).unwrap())
+                .with_context(|| format!("writing {}", output.display()))?;
+            eprintln!(
+                "Run `gun watch import {}` on the watching machine",
+                output.display()
+            );
+            Ok(item! {
+                "bets" => Cell::Int(export.bets.len() as u64),
+                "file" => Cell::string(output.display()),
+            })
+        }
+        BetOpt::RecoverFromSeed {
+            from_height,
+            to_height,
+            proposal: proposals,
+            offer: offers,
+        } => {
+            let party = cmd::load_party(wallet_dir)?;
+            party.sync()?;
+            let candidates = party.find_lost_bets(from_height, to_height)?;
+
+            let mut events = vec![];
+            for proposal in &proposals {
+                let proposal = Proposal::from(proposal.clone());
+                let url = crate::endpoint::oracle_event_url(&proposal.oracle, &proposal.event_id)?;
+                let (oracle_event, oracle_info, _) = get_oracle_event_from_url(&party, url)?;
+                events.push((proposal, oracle_event, oracle_info));
+            }
+            let offers = match offers.is_empty() {
+                true => vec![None],
+                false => offers.iter().map(Some).collect(),
+            };
+
+            let mut rows = vec![];
+            for candidate in candidates {
+                let mut recovered = None;
+                'search: for (proposal, oracle_event, oracle_info) in &events {
+                    for encrypted_offer in &offers {
+                        if let Some(bet) = party.recover_bet(
+                            &candidate,
+                            proposal,
+                            *encrypted_offer,
+                            oracle_event,
+                            oracle_info,
+                        )? {
+                            recovered = Some(party.insert_recovered_bet(
+                                bet,
+                                candidate.height,
+                                proposal.clone(),
+                                *encrypted_offer,
+                            )?);
+                            break 'search;
+                        }
+                    }
+                }
+                rows.push(vec![
+                    Cell::string(candidate.txid()),
+                    Cell::Int(candidate.vout.into()),
+                    Cell::Amount(candidate.value),
+                    Cell::Amount(candidate.my_input_value),
+                    candidate
+                        .height
+                        .map(|height| Cell::Int(height.into()))
+                        .unwrap_or(Cell::Empty),
+                    recovered
+                        .map(|bet_id| Cell::Int(bet_id.into()))
+                        .unwrap_or(Cell::Empty),
+                ]);
+            }
+            if rows.iter().any(|row| matches!(row[5], Cell::Int(_))) {
+                eprintln!(
+                    "Recovered bets are picked up from where they are the next time gun syncs"
+                );
+            }
+            Ok(CmdOutput::table(
+                vec![
+                    "txid",
+                    "vout",
+                    "value",
+                    "my-inputs",
+                    "height",
+                    "recovered-as",
+                ],
+                rows,
+            ))
         }
         BetOpt::Oracle(oracle_cmd) => {
             let bet_db = cmd::load_bet_db(wallet_dir)?;
-            run_oralce_cmd(bet_db, oracle_cmd)
+            let oracle_sources = cmd::load_config(wallet_dir)?.oracle_sources;
+            run_oralce_cmd(bet_db, &oracle_sources, oracle_cmd)
         }
         BetOpt::Inspect(inspect_cmd) => Ok(match inspect_cmd {
             InspectOpt::Proposal {
@@ -546,6 +1421,7 @@ pub fn run_bet_cmd(
                         inputs,
                         public_key,
                         change_script,
+                        cosigner,
                     }),
             } => item! {
                 "oracle" => Cell::string(oracle),
@@ -553,7 +1429,8 @@ pub fn run_bet_cmd(
                 "value" => Cell::Amount(value),
                 "inputs" => Cell::List(inputs.into_iter().map(|x| Box::new(Cell::string(x))).collect()),
                 "public-key" => Cell::string(public_key),
-                "change-script" => change_script.map(|x| Cell::string(Script::from(x))).unwrap_or(Cell::Empty)
+                "change-script" => change_script.map(|x| Cell::string(Script::from(x))).unwrap_or(Cell::Empty),
+                "cosigner" => cosigner.map(Cell::string).unwrap_or(Cell::Empty)
             },
             InspectOpt::Offer {
                 id,
@@ -590,6 +1467,7 @@ pub fn run_bet_cmd(
                                     change,
                                     choose_right,
                                     value,
+                                    cosigner,
                                 } = offer;
 
                                 let chosen_outcome = Outcome {
@@ -603,6 +1481,7 @@ pub fn run_bet_cmd(
                                     "public-key" => Cell::string(&offer_public_key),
                                     "change-script" => change.map(|x| Cell::string(x.script())).unwrap_or(Cell::Empty),
                                     "inputs" => Cell::List(inputs.into_iter().map(|x| Cell::string(x.outpoint)).map(Box::new).collect()),
+                                    "cosigner" => cosigner.map(Cell::string).unwrap_or(Cell::Empty),
                                     "valid" => Cell::string(valid),
                                     "fee" => fee.map(Cell::Amount).unwrap_or(Cell::Empty),
                                     "feerate" => feerate.map(|x| Cell::string(x.as_sat_vb())).unwrap_or(Cell::Empty),
@@ -625,8 +1504,18 @@ pub fn run_bet_cmd(
         }),
         BetOpt::Tag(tagopt) => {
             let bet_db = cmd::load_bet_db(wallet_dir)?;
+            let id = match &tagopt {
+                TagOpt::Add { id, .. } | TagOpt::Remove { id, .. } => *id,
+            };
+            if bet_db.get_entity::<BetState>(id)?.is_none() {
+                return Err(ErrorKind::NotFound.error(format!("bet {} doesn't exist", id)));
+            }
             match tagopt {
                 TagOpt::Add { id, tag } => {
+                    bet_db.record_undo(
+                        format!("tag bet {} \"{}\"", id, tag),
+                        vec![MapKey::Bet(id)],
+                    )?;
                     bet_db.update_bets(&[id], |mut bet_state, _, _| {
                         bet_state.tags_mut().push(tag.clone());
                         Ok(bet_state)
@@ -634,6 +1523,10 @@ pub fn run_bet_cmd(
                     Ok(CmdOutput::None)
                 }
                 TagOpt::Remove { id, tag } => {
+                    bet_db.record_undo(
+                        format!("remove tag \"{}\" from bet {}", tag, id),
+                        vec![MapKey::Bet(id)],
+                    )?;
                     bet_db.update_bets(&[id], |mut bet_state, _, _| {
                         bet_state
                             .tags_mut()
@@ -670,6 +1563,38 @@ pub fn run_bet_cmd(
     }
 }
 
+/// A bet removed by `gun bet prune` as it is kept in the archive.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct PrunedBet {
+    bet_id: BetId,
+    bet_state: BetState,
+    conflicts: Vec<(Txid, SeenConflict)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chat: Option<BetChat>,
+}
+
+/// Parses an age like `30d`, `12w` or `1y`.
+fn parse_age(age: &str) -> Result<crate::chrono::Duration> {
+    use crate::chrono::Duration;
+    let split = age
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or(anyhow!("{} is missing a unit (h, d, w or y)", age))?;
+    let (n, unit) = age.split_at(split);
+    let n = i64::from_str(n).with_context(|| format!("{} is not a valid age", age))?;
+    Ok(match unit {
+        "h" => Duration::hours(n),
+        "d" => Duration::days(n),
+        "w" => Duration::weeks(n),
+        "y" => Duration::days(n * 365),
+        unit => {
+            return Err(anyhow!(
+                "{} is not a unit of time -- use h, d, w or y",
+                unit
+            ))
+        }
+    })
+}
+
 fn reply(
     keychain: &Keychain,
     proposal: VersionedProposal,
@@ -690,11 +1615,39 @@ fn reply(
     (ciphertext, cipher)
 }
 
-fn list_bets(bet_db: &BetDatabase) -> CmdOutput {
+fn adverts_output(adverts: Vec<(BetId, Advert)>) -> CmdOutput {
+    let rows = adverts
+        .into_iter()
+        .map(|(bet_id, advert)| {
+            vec![
+                Cell::Int(bet_id.into()),
+                Cell::String(advert.board),
+                Cell::String(advert.id),
+                Cell::datetime(advert.published_at),
+                advert
+                    .unpublished_at
+                    .map(Cell::datetime)
+                    .unwrap_or(Cell::Empty),
+            ]
+        })
+        .collect();
+    CmdOutput::table(
+        vec!["bet", "board", "id", "published-at", "unpublished-at"],
+        rows,
+    )
+}
+
+fn list_bets(
+    bet_db: &BetDatabase,
+    targets: &ConfirmationTargets,
+    page: &cmd::PageArgs,
+) -> anyhow::Result<CmdOutput> {
     let mut rows = vec![];
+    let tip_height = bet_db.tip_height()?;
 
-    for (id, bet_state) in bet_db.list_entities_print_error::<BetState>() {
-        let name = String::from(bet_state.name());
+    page.note_position(bet_db.count_entities::<BetState>());
+    for (id, bet_state) in bet_db.list_entities_page::<BetState>(page.offset, page.limit) {
+        let name = bet_state.status(tip_height, targets);
         match bet_state.into_bet_or_prop() {
             BetOrProp::Proposal(local_proposal) => rows.push(vec![
                 Cell::Int(id.into()),
@@ -756,7 +1709,7 @@ fn list_bets(bet_db: &BetDatabase) -> CmdOutput {
         }
     }
 
-    CmdOutput::table(
+    Ok(CmdOutput::table(
         vec![
             "id",
             "state",
@@ -770,19 +1723,393 @@ fn list_bets(bet_db: &BetDatabase) -> CmdOutput {
             "short-id",
         ],
         rows,
-    )
+    ))
 }
 
-fn get_oracle_event_from_url(
-    bet_db: &BetDatabase,
+fn simulate(
+    proposal: Proposal,
+    outcome: &str,
+    offer_choice: &str,
+    offer_value: Option<Amount>,
+    offer_inputs: usize,
+    feerate: bdk::FeeRate,
+) -> anyhow::Result<CmdOutput> {
+    let event_id = proposal.event_id.clone();
+    if event_id.n_outcomes() != 2 {
+        return Err(anyhow!(
+            "You can only bet on events with two outcomes but {} has {}",
+            event_id,
+            event_id.n_outcomes()
+        ));
+    }
+    let parse_outcome = |choice: &str| {
+        Outcome::try_from_id_and_outcome(event_id.clone(), choice)
+            .map_err(|_| anyhow!("{} is not a valid outcome for {}", choice, event_id))
+    };
+    let outcome_name = outcome;
+    let outcome = parse_outcome(outcome)?;
+    let offer_choice = parse_outcome(offer_choice)?;
+    let offer_value = offer_value.unwrap_or(proposal.value);
+    let offerer_wins = outcome.value == offer_choice.value;
+    let simulation =
+        crate::betting::simulate(&proposal, offer_value, offer_inputs, offerer_wins, feerate)?;
+    let proposal_choice = Outcome {
+        id: event_id.clone(),
+        value: (offer_choice.value == 0) as u64,
+    };
+    let (winner, loser) = match offerer_wins {
+        true => ("offerer", "proposer"),
+        false => ("proposer", "offerer"),
+    };
+    let outcome_time = event_id_time(&event_id)
+        .map(|time| format!("at about {} UTC", time))
+        .unwrap_or("when the event is over".into());
+
+    let timeline = vec![
+        format!(
+            "the proposer posts the proposal which marks {} of their coins in-use (no transaction yet)",
+            proposal.inputs.len()
+        ),
+        format!(
+            "the offerer bets that {} (and the proposer that {}) and sends an encrypted offer with a signed bet transaction",
+            olivia_describe::outcome(&offer_choice).positive,
+            olivia_describe::outcome(&proposal_choice).positive
+        ),
+        format!(
+            "the proposer takes the offer and broadcasts the bet transaction putting {} in the joint output",
+            simulation.joint_output_value
+        ),
+        "until it confirms the proposer can cancel by spending one of their inputs".into(),
+        format!(
+            "the oracle attests that {} {}",
+            olivia_describe::outcome(&outcome).positive,
+            outcome_time
+        ),
+        format!(
+            "the {} claims {} with the claim transaction and the {} gets nothing back",
+            winner,
+            simulation.payout(),
+            loser
+        ),
+    ];
+
+    Ok(item! {
+        "event-id" => Cell::string(&event_id),
+        "event" => Cell::string(olivia_describe::event_id_short(&event_id)),
+        "outcome" => Cell::string(outcome_name),
+        "winner" => Cell::string(winner),
+        "proposer-risks" => Cell::Amount(proposal.value),
+        "offerer-risks" => Cell::Amount(offer_value),
+        "bet-tx-vsize" => Cell::Int(simulation.bet_tx_vsize),
+        "bet-tx-fee" => Cell::Amount(simulation.bet_tx_fee),
+        "bet-tx-fee-paid-by" => Cell::string("offerer"),
+        "claim-tx-vsize" => Cell::Int(simulation.claim_tx_vsize),
+        "claim-tx-fee" => Cell::Amount(simulation.claim_tx_fee),
+        "claim-tx-fee-paid-by" => Cell::string(winner),
+        "payout" => Cell::Amount(simulation.payout()),
+        "proposer-net" => Cell::string(simulation.proposer_net),
+        "offerer-net" => Cell::string(simulation.offerer_net),
+        "timeline" => Cell::List(timeline.into_iter().map(Cell::String).map(Box::new).collect()),
+    })
+}
+
+fn decode(wallet_dir: &PathBuf, string: &str) -> anyhow::Result<CmdOutput> {
+    let network = cmd::load_config(wallet_dir)
+        .map(|config| config.network)
+        .ok();
+    let address = |script: &Script| {
+        network
+            .and_then(|network| Address::from_script(script, network))
+            .map(Cell::string)
+            .unwrap_or(Cell::Empty)
+    };
+
+    if let Ok(VersionedProposal::One(proposal)) = VersionedProposal::from_str(string) {
+        let change_script = proposal.change_script.clone().map(Script::from);
+        return Ok(item! {
+            "kind" => Cell::string("proposal"),
+            "version" => Cell::string("one"),
+            "oracle" => Cell::string(&proposal.oracle),
+            "event-id" => Cell::string(&proposal.event_id),
+            "event" => Cell::string(olivia_describe::event_id_short(&proposal.event_id)),
+            "value" => Cell::Amount(proposal.value),
+            "inputs" => Cell::List(proposal.inputs.iter().map(Cell::string).map(Box::new).collect()),
+            "public-key" => Cell::string(&proposal.public_key),
+            "change-script" => change_script.as_ref().map(|script| Cell::string(script.asm())).unwrap_or(Cell::Empty),
+            "change-address" => change_script.as_ref().map(address).unwrap_or(Cell::Empty),
+        });
+    }
+
+    let ciphertext = Ciphertext::from_str(string)
+        .map_err(|_| anyhow!("this is neither a proposal nor an offer string"))?;
+
+    // only the proposer can decrypt an offer so see if it was made to one of ours
+    let decrypted = cmd::load_party(wallet_dir).ok().and_then(|party| {
+        party
+            .bet_db()
+            .list_entities_print_error::<BetState>()
+            .find_map(|(bet_id, bet_state)| match bet_state {
+                BetState::Proposed { local_proposal } => party
+                    .decrypt_offer(bet_id, ciphertext.clone())
+                    .ok()
+                    .map(|(plaintext, _, _)| (bet_id, local_proposal, plaintext)),
+                _ => None,
+            })
+    });
+
+    Ok(match decrypted {
+        Some((bet_id, local_proposal, Plaintext::Offerv1 { offer, message })) => {
+            let their_choice = Outcome {
+                id: local_proposal.proposal.event_id.clone(),
+                value: offer.choose_right as u64,
+            };
+            let change_script = offer.change.as_ref().map(|change| change.script().clone());
+            item! {
+                "kind" => Cell::string("offer"),
+                "version" => Cell::string("offer-v1"),
+                "proposal-id" => Cell::Int(bet_id.into()),
+                "event-id" => Cell::string(&local_proposal.proposal.event_id),
+                "public-key" => Cell::string(&ciphertext.public_key),
+                "value" => Cell::Amount(offer.value),
+                "their-choice" => Cell::string(their_choice.outcome_string()),
+                "inputs" => Cell::List(offer.inputs.iter().map(|input| Box::new(Cell::string(input.outpoint))).collect()),
+                "change-script" => change_script.as_ref().map(|script| Cell::string(script.asm())).unwrap_or(Cell::Empty),
+                "change-address" => change_script.as_ref().map(address).unwrap_or(Cell::Empty),
+                "change-value" => offer.change.as_ref().map(|change| Cell::Amount(change.value())).unwrap_or(Cell::Empty),
+                "message" => message.map(Cell::string).unwrap_or(Cell::Empty),
+            }
+        }
+        Some((bet_id, _, Plaintext::Messagev1(message))) => item! {
+            "kind" => Cell::string("message"),
+            "version" => Cell::string("message-v1"),
+            "proposal-id" => Cell::Int(bet_id.into()),
+            "public-key" => Cell::string(&ciphertext.public_key),
+            "message" => Cell::string(message),
+        },
+        None => {
+            eprintln!(
+                "this offer wasn't made to any of your proposals so only its outside can be read"
+            );
+            item! {
+                "kind" => Cell::string("encrypted"),
+                "public-key" => Cell::string(&ciphertext.public_key),
+                "encrypted-bytes" => Cell::Int(ciphertext.encrypted_bytes.len() as u64),
+            }
+        }
+    })
+}
+
+/// One proposal to make in `gun bet propose --batch`.
+///
+/// In JSON the file is a list of `{ "event": <url>, "value": "0.01BTC", "tags": [..], "claim-to":
+/// <address> }` (tags and claim-to are optional). In CSV each line is
+/// `event,value[,tags separated by ;[,claim-to]]` and a header line is allowed.
+#[derive(Clone, Debug)]
+pub struct Market {
+    pub event: Url,
+    pub value: ValueChoice,
+    pub tags: Vec<String>,
+    pub claim_to: Option<Address>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct RawMarket {
+    event: String,
+    value: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    claim_to: Option<String>,
+}
+
+impl RawMarket {
+    fn parse(self) -> anyhow::Result<Market> {
+        Ok(Market {
+            event: Url::parse(&self.event)
+                .with_context(|| format!("parsing event url {}", self.event))?,
+            value: ValueChoice::from_str(&self.value)
+                .with_context(|| format!("parsing value {}", self.value))?,
+            tags: self.tags,
+            claim_to: self
+                .claim_to
+                .map(|claim_to| Address::from_str(&claim_to))
+                .transpose()
+                .context("parsing claim-to address")?,
+        })
+    }
+}
+
+fn parse_markets(contents: &str, json: bool) -> anyhow::Result<Vec<Market>> {
+    let raw = if json {
+        serde_json::from_str::<Vec<RawMarket>>(contents).context("parsing JSON market file")?
+    } else {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with("event"))
+            .map(|line| {
+                let mut fields = line.split(',').map(str::trim);
+                let event = fields.next().unwrap_or("").to_string();
+                let value = fields
+                    .next()
+                    .ok_or(anyhow!("line '{}' is missing a value", line))?
+                    .to_string();
+                let tags = fields
+                    .next()
+                    .map(|tags| {
+                        tags.split(';')
+                            .filter(|tag| !tag.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let claim_to = fields
+                    .next()
+                    .filter(|claim_to| !claim_to.is_empty())
+                    .map(String::from);
+                Ok(RawMarket {
+                    event,
+                    value,
+                    tags,
+                    claim_to,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+    raw.into_iter().map(RawMarket::parse).collect()
+}
+
+/// Makes a proposal for every market in `path`. Each proposal is saved as it is made so the coins
+/// it reserves aren't used by the next one.
+fn propose_batch<D: bdk::database::BatchDatabase>(
+    party: &Party<bdk::blockchain::EsploraBlockchain, D>,
+    path: &PathBuf,
+    yes: bool,
+) -> anyhow::Result<CmdOutput> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let json = path
+        .extension()
+        .map_or(false, |extension| extension == "json")
+        || contents.trim_start().starts_with('[');
+    let mut markets = parse_markets(&contents, json)?;
+    if markets.is_empty() {
+        return Err(anyhow!("{} has no markets in it", path.display()));
+    }
+    if markets
+        .iter()
+        .filter(|market| matches!(market.value, ValueChoice::All))
+        .count()
+        > 1
+    {
+        return Err(anyhow!("only one market can have the value 'all'"));
+    }
+    // the biggest go first so they get first pick of the coins and 'all' takes what's left
+    markets.sort_by_key(|market| match market.value {
+        ValueChoice::Amount(value) => std::cmp::Reverse(value),
+        ValueChoice::All => std::cmp::Reverse(bdk::bitcoin::Amount::ZERO),
+    });
+
+    // check every event before reserving anything
+    let now = crate::clock::now();
+    let mut events = vec![];
+    for market in &markets {
+        let (oracle_event, _, is_attested) =
+            get_oracle_event_from_url(party, market.event.clone())?;
+        if is_attested {
+            return Err(anyhow!("{} already attested", oracle_event.event.id));
+        }
+        if let Some(expected_outcome_time) = oracle_event.event.expected_outcome_time {
+            if expected_outcome_time <= now {
+                return Err(anyhow!(
+                    "{} is expected to complete at {} but it's already {}",
+                    oracle_event.event.id,
+                    expected_outcome_time,
+                    now
+                ));
+            }
+        }
+        events.push(oracle_event);
+    }
+
+    if !yes
+        && !read_answer(&tr_args(
+            "You are making {count} proposals from {file}. Ok?",
+            &[("count", &markets.len()), ("file", &path.display())],
+        ))
+    {
+        return Ok(CmdOutput::None);
+    }
+
+    let mut rows = vec![];
+    let mut with_change = 0;
+    for (market, oracle_event) in markets.into_iter().zip(events) {
+        let oracle_id = market
+            .event
+            .host_str()
+            .ok_or_else(|| anyhow!("market url {} has no host", market.event))?
+            .to_string();
+        let event_id = oracle_event.event.id.clone();
+        let args = crate::betting::BetArgs {
+            value: market.value.clone(),
+            tags: market.tags,
+            claim_to: market.claim_to,
+            ..Default::default()
+        };
+        match party.make_proposal(oracle_id, oracle_event, args) {
+            Ok(local_proposal) => {
+                if local_proposal.change.is_some() {
+                    with_change += 1;
+                }
+                let value = local_proposal.proposal.value;
+                let proposal_string = local_proposal.proposal.clone().into_versioned().to_string();
+                let id = party
+                    .bet_db()
+                    .insert_bet(BetState::Proposed { local_proposal })?;
+                rows.push(vec![
+                    Cell::Int(id.into()),
+                    Cell::string(event_id),
+                    Cell::Amount(value),
+                    Cell::String(proposal_string),
+                    Cell::Empty,
+                ]);
+            }
+            Err(e) => rows.push(vec![
+                Cell::Empty,
+                Cell::string(event_id),
+                match market.value {
+                    ValueChoice::Amount(value) => Cell::Amount(value),
+                    ValueChoice::All => Cell::string("all"),
+                },
+                Cell::Empty,
+                Cell::String(format!("{:#}", e)),
+            ]),
+        }
+    }
+
+    if with_change > 0 {
+        eprintln!("{} of the proposals put more coins “in-use” than they needed because their values don't match a sum of available utxos. Use `gun split` first to avoid this.", with_change);
+    }
+
+    Ok(CmdOutput::table(
+        vec!["id", "event-id", "value", "proposal", "error"],
+        rows,
+    ))
+}
+
+fn get_oracle_event_from_url<D: bdk::database::BatchDatabase>(
+    party: &Party<bdk::blockchain::EsploraBlockchain, D>,
     url: Url,
 ) -> anyhow::Result<(OracleEvent, OracleInfo, bool)> {
+    let bet_db = party.bet_db();
     let oracle_id = url.host_str().ok_or(anyhow!("url {} missing host", url))?;
 
-    let event_response = reqwest::blocking::get(url.clone())?
-        .error_for_status()
-        .with_context(|| format!("while getting {}", url))?
-        .json::<EventResponse>()
+    let event_response = party
+        .settings()
+        .oracle_sources
+        .for_url(&url)
+        .event(&url)
         .with_context(|| {
             format!(
                 "while decoding the response from {}. Are you sure this is a valid event url?",
@@ -807,11 +2134,14 @@ fn get_oracle_event_from_url(
         .ok_or(anyhow!("Invalid oracle announcement returned from {}", url))?;
 
     let is_attested = event_response.attestation.is_some();
+    if let Err(e) = party.remember_attestation(&oracle_info.id, &event_id, &event_response) {
+        eprintln!("couldn't remember the attestation to {}: {}", event_id, e);
+    }
 
     Ok((oracle_event, oracle_info, is_attested))
 }
 
-fn bet_prompt(bet: &Bet) -> String {
+fn bet_prompt<B, D: BatchDatabase>(bet: &Bet, wallet: &Wallet<B, D>) -> String {
     use std::fmt::Write;
     use term_table::{row::Row, Table};
     let mut res = String::new();
@@ -825,25 +2155,32 @@ fn bet_prompt(bet: &Bet) -> String {
         value: bet.i_chose_right as u64,
     };
 
-    let (fee, feerate) = bet.psbt.fee();
+    let (fee, _) = bet.psbt.fee();
+    // the other side's inputs may not be signed yet
+    let size = crate::tx_size::TxSize::for_wallet(wallet, &bet.psbt);
 
     let mut table = Table::new();
-    table.add_row(Row::new(vec!["event-id".into(), id.to_string()]));
-    table.add_row(Row::new(vec!["oracle", oracle]));
-    table.add_row(Row::new(vec!["risk".into(), i_risk.to_string()]));
-    table.add_row(Row::new(vec!["reward".into(), i_gain.to_string()]));
+    table.add_row(Row::new(vec![tr("event-id").into(), id.to_string()]));
+    table.add_row(Row::new(vec![tr("oracle"), oracle]));
+    table.add_row(Row::new(vec![tr("risk").into(), i_risk.to_string()]));
+    table.add_row(Row::new(vec![tr("reward").into(), i_gain.to_string()]));
     table.add_row(Row::new(vec![
-        "ratio".into(),
+        tr("ratio").into(),
         format!("{:.3}", i_risk.as_sat() as f64 / i_gain.as_sat() as f64),
     ]));
     table.add_row(Row::new(vec![
-        "fee".into(),
-        format!("{} ({} s/vb)", fee, feerate.as_sat_vb()),
+        tr("fee").into(),
+        format!(
+            "{} ({:.2} s/vb)",
+            fee,
+            fee.as_sat() as f32 / size.vsize() as f32
+        ),
     ]));
+    table.add_row(Row::new(vec![tr("size").into(), size.summary()]));
 
     if let Some(time) = expected_outcome_time {
         table.add_row(Row::new(vec![
-            "outcome-time".into(),
+            tr("outcome-time").into(),
             format!("{} (in {})", time, crate::format_dt_diff_till_now(time)),
         ]));
     }
@@ -852,12 +2189,15 @@ fn bet_prompt(bet: &Bet) -> String {
     write!(&mut res, "\n").unwrap();
     write!(
         &mut res,
-        "You are betting that {}",
-        olivia_describe::outcome(&outcome).positive
+        "{}",
+        tr_args(
+            "You are betting that {outcome}",
+            &[("outcome", &olivia_describe::outcome(&outcome).positive)]
+        )
     )
     .unwrap();
     write!(&mut res, "\n").unwrap();
-    write!(&mut res, "Do you want to take this bet?").unwrap();
+    write!(&mut res, "{}", tr("Do you want to take this bet?")).unwrap();
     res
 }
 
@@ -871,6 +2211,36 @@ mod test {
     use bdk::bitcoin::{Amount, OutPoint};
     use olivia_core::EventId;
 
+    #[test]
+    fn parse_market_files() {
+        let json = r#"[
+            { "event": "https://h00.ooo/EPL/match/2021-08-22/ARS_CHE.vs=CHE_win", "value": "0.01BTC", "tags": ["epl"] },
+            { "event": "https://h00.ooo/EPL/match/2021-08-28/LIV_CHE.vs=LIV_win", "value": "all" }
+        ]"#;
+        let csv = "event,value,tags\n\
+            https://h00.ooo/EPL/match/2021-08-22/ARS_CHE.vs=CHE_win,0.01BTC,epl\n\
+            # a comment\n\
+            https://h00.ooo/EPL/match/2021-08-28/LIV_CHE.vs=LIV_win,all\n";
+        for markets in [
+            parse_markets(json, true).unwrap(),
+            parse_markets(csv, false).unwrap(),
+        ] {
+            assert_eq!(markets.len(), 2);
+            assert_eq!(markets[0].tags, vec!["epl".to_string()]);
+            assert!(matches!(
+                markets[0].value,
+                ValueChoice::Amount(value) if value == Amount::from_str("0.01 BTC").unwrap()
+            ));
+            assert!(matches!(markets[1].value, ValueChoice::All));
+            assert!(markets[1].tags.is_empty());
+        }
+        assert!(parse_markets(
+            "https://h00.ooo/EPL/match/2021-08-22/ARS_CHE.vs=CHE_win",
+            false
+        )
+        .is_err());
+    }
+
     #[test]
     fn make_reply_to_proposal() {
         let keychain = Keychain::new([42u8; 64]);
@@ -890,6 +2260,7 @@ mod test {
                     .script_pubkey()
                     .into(),
             ),
+            cosigner: None,
         });
 
         let (ciphertext, mut pad_cipher) = reply(&keychain, fixed, "a test message".into());
@@ -0,0 +1,212 @@
+use super::*;
+use crate::{
+    betting::PendingPsbt,
+    broadcast_queue::{BroadcastFailure, QueuedBroadcast, BUMP_PERCENT},
+    item,
+    psbt_policy::PsbtPolicy,
+};
+use bdk::{KeychainKind, SignOptions};
+
+/// Shows the transactions waiting to be broadcast again. With `retry` each of them is tried now
+/// rather than when it's due and with `remove` it's taken out of the queue and its coins freed.
+pub fn run_queue_cmd(
+    wallet_dir: &PathBuf,
+    retry: bool,
+    remove: Option<Txid>,
+) -> anyhow::Result<CmdOutput> {
+    if let Some(txid) = remove {
+        let bet_db = load_bet_db(wallet_dir)?;
+        match bet_db.get_entity::<PendingPsbt>(txid)? {
+            Some(pending) if pending.queued.is_some() => {
+                bet_db.remove_entity::<PendingPsbt>(txid)?;
+                return Ok(item! { "removed" => Cell::string(txid) });
+            }
+            _ => {
+                return Err(
+                    ErrorKind::NotFound.error(format!("{} isn't waiting to be broadcast", txid))
+                )
+            }
+        }
+    }
+    if retry {
+        return Ok(CmdOutput::table(
+            vec!["txid", "result", "next-attempt"],
+            retry_broadcasts(wallet_dir, true)?,
+        ));
+    }
+
+    let rows = load_bet_db(wallet_dir)?
+        .queued_broadcasts()?
+        .into_iter()
+        .filter_map(|(txid, pending)| {
+            let queued = pending.queued?;
+            let (fee, feerate) = pending.psbt.fee();
+            Some(vec![
+                Cell::string(txid),
+                Cell::String(queued.context),
+                Cell::datetime(pending.created_at),
+                Cell::Int(queued.attempts as u64),
+                Cell::Int(queued.bumps as u64),
+                Cell::Amount(fee),
+                Cell::string(format!("{:.2}", feerate.as_sat_vb())),
+                Cell::datetime(queued.next_attempt),
+                Cell::String(queued.last_error),
+            ])
+        })
+        .collect();
+    Ok(CmdOutput::table(
+        vec![
+            "txid",
+            "context",
+            "queued",
+            "attempts",
+            "bumps",
+            "fee",
+            "feerate",
+            "next-attempt",
+            "last-error",
+        ],
+        rows,
+    ))
+}
+
+/// Tries broadcasting the queued transactions that are due (or all of them). Ones the backend
+/// already has or will never take are taken out of the queue.
+pub(crate) fn retry_broadcasts(wallet_dir: &PathBuf, all: bool) -> anyhow::Result<Vec<Vec<Cell>>> {
+    // don't load the wallet when there's nothing to do
+    let any_due = load_bet_db(wallet_dir)?
+        .queued_broadcasts()?
+        .iter()
+        .any(|(_, pending)| {
+            all || pending.queued.as_ref().map(QueuedBroadcast::is_due) == Some(true)
+        });
+    if !any_due {
+        return Ok(vec![]);
+    }
+    crate::read_only::check("broadcast")?;
+    let bump_after = load_config(wallet_dir)?.broadcast_bump_after;
+    let party = load_party(wallet_dir)?;
+    let bet_db = party.bet_db();
+    let mut rows = vec![];
+    for (txid, pending) in bet_db.queued_broadcasts()? {
+        let mut queued = pending.queued.expect("only queued ones are listed");
+        if !all && !queued.is_due() {
+            continue;
+        }
+        let mut psbt = pending.psbt;
+        if let Some(bump_after) = bump_after.filter(|bump_after| queued.needs_bump(*bump_after)) {
+            match bump_out_of_change(wallet_dir, &party, &psbt) {
+                Ok(bumped) => {
+                    queued.bumps += 1;
+                    eprintln!(
+                        "raised the fee of {} by {}% (it's now {})",
+                        txid,
+                        BUMP_PERCENT,
+                        bumped.global.unsigned_tx.txid()
+                    );
+                    psbt = bumped;
+                }
+                Err(e) => eprintln!("couldn't raise the fee of {}: {:#}", txid, e),
+            }
+        }
+        let new_txid = psbt.global.unsigned_tx.txid();
+        if new_txid != txid {
+            party.audit_psbt(AuditOperation::Broadcast, &queued.context, &psbt)?;
+        }
+
+        let (result, went_out, next_attempt) = match crate::read_only::broadcast(
+            party.wallet().client(),
+            psbt.clone().extract_tx(),
+        ) {
+            Ok(()) => ("broadcast".to_string(), true, None),
+            Err(e) => match BroadcastFailure::of(&e) {
+                BroadcastFailure::AlreadyBroadcast => ("already broadcast".to_string(), true, None),
+                BroadcastFailure::Rejected => {
+                    eprintln!(
+                            "{} was taken out of the broadcast queue since it will never be accepted: {:#}",
+                            new_txid, e
+                        );
+                    (format!("dropped: {:#}", e), false, None)
+                }
+                BroadcastFailure::TryLater => {
+                    queued.failed(&e);
+                    (format!("failed: {:#}", e), false, Some(queued.next_attempt))
+                }
+            },
+        };
+
+        if next_attempt.is_some() {
+            // its coins are still spoken for
+            bet_db.queue_broadcast(psbt, queued)?;
+            if new_txid != txid {
+                bet_db.remove_entity::<PendingPsbt>(txid)?;
+            }
+        } else {
+            bet_db.remove_entity::<PendingPsbt>(txid)?;
+            if went_out && new_txid != txid {
+                carry_over_to_replacement(&party, &[txid], new_txid)?;
+            }
+        }
+
+        rows.push(vec![
+            Cell::string(new_txid),
+            Cell::String(result),
+            next_attempt.map(Cell::datetime).unwrap_or(Cell::Empty),
+        ]);
+    }
+    Ok(rows)
+}
+
+/// A copy of `psbt` (finalized and made by this wallet) paying [`BUMP_PERCENT`] more fee out of
+/// its change and signed again. It has to follow the PSBT policy and leave the change above the
+/// smallest output the wallet makes.
+fn bump_out_of_change(
+    wallet_dir: &PathBuf,
+    party: &Party<EsploraBlockchain, impl BatchDatabase>,
+    psbt: &Psbt,
+) -> anyhow::Result<Psbt> {
+    let wallet = party.wallet();
+    let mut change = None;
+    for (i, txout) in psbt.global.unsigned_tx.output.iter().enumerate() {
+        let keychain = wallet
+            .query_db(|db| db.get_path_from_script_pubkey(&txout.script_pubkey))?
+            .map(|(keychain, _)| keychain);
+        if keychain == Some(KeychainKind::Internal) {
+            change = Some(i);
+            break;
+        }
+    }
+    let change = change.ok_or(anyhow!("it has no change to take the fee from"))?;
+
+    let (fee, _) = psbt.fee();
+    let size = TxSize::of(psbt, |_| None);
+    // at least 1 sat/vb more so the new fee is enough to relay as a replacement
+    let extra = (fee.as_sat() * BUMP_PERCENT / 100).max(size.vsize());
+    let txout = &psbt.global.unsigned_tx.output[change];
+    let min =
+        crate::dust::min_output_value(party.settings().min_output_value, &txout.script_pubkey);
+    if txout.value < min.as_sat() + extra {
+        return Err(anyhow!(
+            "the change of {} is too small to pay {} more",
+            Amount::from_sat(txout.value),
+            Amount::from_sat(extra)
+        ));
+    }
+
+    let mut bumped = psbt.clone();
+    bumped.global.unsigned_tx.output[change].value -= extra;
+    for input in &mut bumped.inputs {
+        input.partial_sigs.clear();
+        input.final_script_sig = None;
+        input.final_script_witness = None;
+    }
+    PsbtPolicy::load(wallet_dir)?
+        .check(&bumped, |script| wallet.is_mine(script).unwrap_or(false))?;
+    // it pays the same as the transaction it replaces which was already signed
+    crate::approval::allow(bumped.global.unsigned_tx.txid());
+    if !wallet.sign(&mut bumped, SignOptions::default())? {
+        return Err(anyhow!("this wallet can't sign it by itself"));
+    }
+    party.audit_psbt(AuditOperation::Sign, "broadcast queue bump", &bumped)?;
+    Ok(bumped)
+}
@@ -0,0 +1,436 @@
+use super::*;
+use crate::psbt_ext::{self, PsbtFeeRate};
+use bdk::{
+    bitcoin::{util::psbt, OutPoint, Script, TxOut, Txid},
+    miniscript::DescriptorTrait,
+    wallet::AddressIndex,
+    FeeRate, KeychainKind, SignOptions, TransactionDetails,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Replaces every stuck transaction of ours at `fee`. Transactions that spend each other's outputs
+/// are replaced together by one transaction since replacing the parent would drop the child
+/// anyway. With `merge` everything is replaced by a single transaction.
+pub fn run_bump_all(
+    wallet_dir: &PathBuf,
+    fee: FeeSpec,
+    merge: bool,
+    yes: bool,
+    print_tx: bool,
+) -> anyhow::Result<CmdOutput> {
+    let party = load_party(wallet_dir)?;
+    let wallet = party.wallet();
+    let unconfirmed = wallet
+        .list_transactions(true)?
+        .into_iter()
+        .filter(|tx| tx.confirmation_time.is_none())
+        .collect::<Vec<_>>();
+
+    let mut prevouts = HashMap::<OutPoint, TxOut>::new();
+    let mut replaceable = HashMap::<Txid, &TransactionDetails>::new();
+    for details in unconfirmed.iter().filter(|details| details.sent > 0) {
+        match replaceable_inputs(&party, details) {
+            Ok(inputs) => {
+                prevouts.extend(inputs);
+                replaceable.insert(details.txid, details);
+            }
+            Err(e) => eprintln!("skipping {}: {}", details.txid, e),
+        }
+    }
+
+    // replacing a transaction drops its children so we can't touch it if one of them can't be
+    // replaced too
+    let has_stuck_child = unconfirmed
+        .iter()
+        .filter(|details| !replaceable.contains_key(&details.txid))
+        .filter_map(|details| details.transaction.as_ref())
+        .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output.txid))
+        .collect::<HashSet<_>>();
+
+    let mut groups = dependency_groups(
+        &replaceable
+            .values()
+            .map(|details| {
+                let tx = details.transaction.as_ref().expect("checked it's there");
+                (
+                    details.txid,
+                    tx.input.iter().map(|txin| txin.previous_output).collect(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    )
+    .into_iter()
+    .filter(|group| {
+        let stuck = group.iter().any(|txid| has_stuck_child.contains(txid));
+        if stuck {
+            eprintln!(
+                "skipping {}: it has an unconfirmed child that can't be replaced",
+                list_txids(group)
+            );
+        }
+        !stuck
+    })
+    .collect::<Vec<_>>();
+    if merge && groups.len() > 1 {
+        groups = vec![groups.concat()];
+    }
+    if groups.is_empty() {
+        return Err(anyhow!(
+            "there are no stuck transactions that can be replaced"
+        ));
+    }
+
+    let mut rows = vec![];
+    for group in groups {
+        let built = match group.as_slice() {
+            [txid] => fee_bump_psbt(&party, *txid, fee.clone()),
+            _ => merged_replacement(
+                &party,
+                &group
+                    .iter()
+                    .map(|txid| replaceable[txid])
+                    .collect::<Vec<_>>(),
+                &prevouts,
+                fee.clone(),
+            ),
+        };
+        let (psbt, old_feerate) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                eprintln!("couldn't replace {}: {}", list_txids(&group), e);
+                continue;
+            }
+        };
+        let (_, new_feerate) = psbt.fee();
+        eprintln!(
+            "replacing {} paying {:.2} sat/vb with one paying {:.2} sat/vb",
+            list_txids(&group),
+            old_feerate.as_sat_vb(),
+            new_feerate.as_sat_vb()
+        );
+        let tx_hex = crate::hex::encode(&encode::serialize(&psbt.clone().extract_tx()));
+        let (_, new_txid) = decide_to_broadcast(
+            wallet.network(),
+            wallet.client(),
+            psbt,
+            yes,
+            print_tx,
+            party.audit_log(),
+            party.bet_db(),
+            "bump-all",
+        )?;
+        if let Some(new_txid) = new_txid {
+            let mut row = vec![
+                Cell::List(
+                    group
+                        .iter()
+                        .map(|txid| Box::new(Cell::string(txid)))
+                        .collect(),
+                ),
+                Cell::string(new_txid),
+                Cell::string(format!("{:.2}", new_feerate.as_sat_vb())),
+            ];
+            match print_tx {
+                true => row.push(Cell::String(tx_hex)),
+                false => carry_over_to_replacement(&party, &group, new_txid)?,
+            }
+            rows.push(row);
+        }
+    }
+
+    let mut header = vec!["replaced", "txid", "feerate"];
+    if print_tx {
+        header.push("tx");
+    }
+    Ok(CmdOutput::table(header, rows))
+}
+
+fn list_txids(txids: &[Txid]) -> String {
+    txids
+        .iter()
+        .map(|txid| txid.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The coins a transaction spends if it's one we can replace by ourselves.
+fn replaceable_inputs<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    details: &TransactionDetails,
+) -> anyhow::Result<Vec<(OutPoint, TxOut)>> {
+    let wallet = party.wallet();
+    let tx = details
+        .transaction
+        .as_ref()
+        .ok_or(anyhow!("the wallet doesn't have the raw transaction"))?;
+    if !psbt_ext::signals_rbf(tx) {
+        return Err(anyhow!("it doesn't signal replace-by-fee"));
+    }
+    if details.fee.is_none() {
+        return Err(anyhow!("its fee isn't known"));
+    }
+    let mut inputs = vec![];
+    for txin in &tx.input {
+        let outpoint = txin.previous_output;
+        let txout = wallet
+            .query_db(|db| db.get_tx(&outpoint.txid, true))?
+            .and_then(|prev| prev.transaction)
+            .and_then(|prev| prev.output.get(outpoint.vout as usize).cloned())
+            .filter(|txout| wallet.is_mine(&txout.script_pubkey).unwrap_or(false))
+            // e.g. a bet's funding or claim transaction
+            .ok_or(anyhow!("it spends coins that aren't only ours"))?;
+        inputs.push((outpoint, txout));
+    }
+    Ok(inputs)
+}
+
+/// Builds one transaction that replaces all of `group` (its transactions parents first). It
+/// spends whatever they spent from outside the group, pays everyone they paid outside the wallet
+/// and sends what's left to one change output.
+fn merged_replacement<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    group: &[&TransactionDetails],
+    prevouts: &HashMap<OutPoint, TxOut>,
+    fee: FeeSpec,
+) -> anyhow::Result<(Psbt, FeeRate)> {
+    let wallet = party.wallet();
+    let txs = group
+        .iter()
+        .map(|details| details.transaction.as_ref().expect("checked it's there"))
+        .collect::<Vec<_>>();
+    let txids = group
+        .iter()
+        .map(|details| details.txid)
+        .collect::<HashSet<_>>();
+    let (inputs, spent_within): (Vec<OutPoint>, Vec<OutPoint>) = txs
+        .iter()
+        .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+        .partition(|outpoint| !txids.contains(&outpoint.txid));
+
+    let mut recipients = Vec::<(Script, u64)>::new();
+    let mut change = None;
+    for (details, tx) in group.iter().zip(&txs) {
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let outpoint = OutPoint {
+                txid: details.txid,
+                vout: vout as u32,
+            };
+            if spent_within.contains(&outpoint) {
+                continue;
+            }
+            let keychain = wallet
+                .query_db(|db| db.get_path_from_script_pubkey(&txout.script_pubkey))?
+                .map(|(keychain, _)| keychain);
+            match keychain {
+                Some(KeychainKind::Internal) => {
+                    change.get_or_insert(txout.script_pubkey.clone());
+                }
+                _ => recipients.push((txout.script_pubkey.clone(), txout.value)),
+            }
+        }
+    }
+    let change = match change {
+        Some(change) => change,
+        None => wallet
+            .get_change_address(AddressIndex::New)?
+            .script_pubkey(),
+    };
+
+    let old_fees = group
+        .iter()
+        .map(|details| details.fee.expect("checked it's known"))
+        .sum::<u64>();
+    let old_feerate = group
+        .iter()
+        .zip(&txs)
+        .map(|(details, tx)| {
+            FeeRate::from_sat_per_vb(
+                details.fee.expect("checked it's known") as f32 * 4.0 / tx.get_weight() as f32,
+            )
+        })
+        .max_by(|a, b| a.as_sat_vb().partial_cmp(&b.as_sat_vb()).unwrap())
+        .expect("groups aren't empty");
+    let fee_spec = fee
+        .with_aliases(&party.settings().fee_aliases)
+        .relative_to(old_feerate)?;
+
+    let build = |fee_absolute: Option<u64>| -> anyhow::Result<Psbt> {
+        let mut builder = wallet.build_tx();
+        builder
+            .manually_selected_only()
+            .enable_rbf()
+            .only_witness_utxo();
+        for outpoint in &inputs {
+            let txout = prevouts[outpoint].clone();
+            let keychain = wallet
+                .query_db(|db| db.get_path_from_script_pubkey(&txout.script_pubkey))?
+                .map(|(keychain, _)| keychain)
+                .unwrap_or(KeychainKind::External);
+            let satisfaction_weight = wallet
+                .get_descriptor_for_keychain(keychain)
+                .max_satisfaction_weight()?;
+            // the coins are spent by transactions in the mempool so BDK only lets us add them as
+            // foreign UTXOs
+            builder.add_foreign_utxo(
+                *outpoint,
+                psbt::Input {
+                    witness_utxo: Some(txout),
+                    ..Default::default()
+                },
+                satisfaction_weight,
+            )?;
+        }
+        for (script_pubkey, value) in &recipients {
+            builder.add_recipient(script_pubkey.clone(), *value);
+        }
+        builder.drain_to(change.clone());
+        match fee_absolute {
+            Some(fee_absolute) => {
+                builder.fee_absolute(fee_absolute);
+            }
+            None => fee_spec.apply_to_builder(wallet.client(), &mut builder)?,
+        }
+        let (mut psbt, _) = builder.finish()?;
+        // the threshold is per send so sends that were each under it can be over it together
+        if let Some(policy) = &party.settings().approval {
+            crate::approval::check(
+                policy,
+                &psbt,
+                |script| *script == change || wallet.is_mine(script).unwrap_or(false),
+                &[],
+            )?;
+            // the signers can't tell change to a change descriptor from a payment
+            crate::approval::allow(psbt.global.unsigned_tx.txid());
+        }
+        let finalized = wallet.sign(
+            &mut psbt,
+            SignOptions {
+                trust_witness_utxo: true,
+                ..Default::default()
+            },
+        )?;
+        if !finalized {
+            return Err(anyhow!(
+                "this wallet couldn't sign the replacement by itself"
+            ));
+        }
+        Ok(psbt)
+    };
+
+    let mut psbt = build(None)?;
+    // a replacement has to pay for everything it replaces plus its own relay at 1 sat/vb
+    let vbytes = (psbt.clone().extract_tx().get_weight() as f32 / 4.0).ceil() as u64;
+    if psbt.fee().0.as_sat() < old_fees + vbytes {
+        psbt = build(Some(old_fees + vbytes))?;
+    }
+    psbt_ext::log_built_tx(&psbt);
+    party.audit_psbt(AuditOperation::Sign, "bump-all", &psbt)?;
+    Ok((psbt, old_feerate))
+}
+
+/// Groups transactions that spend each other's outputs. Each group lists its transactions parents
+/// first.
+fn dependency_groups(txs: &[(Txid, Vec<OutPoint>)]) -> Vec<Vec<Txid>> {
+    let in_set = txs.iter().map(|(txid, _)| *txid).collect::<HashSet<_>>();
+    let parents = |inputs: &[OutPoint]| {
+        let mut parents = inputs
+            .iter()
+            .map(|outpoint| outpoint.txid)
+            .filter(|txid| in_set.contains(txid))
+            .collect::<Vec<_>>();
+        parents.sort();
+        parents.dedup();
+        parents
+    };
+
+    let mut ordered = Vec::<(Txid, Vec<Txid>)>::new();
+    let mut remaining = txs.iter().collect::<Vec<_>>();
+    while !remaining.is_empty() {
+        let before = remaining.len();
+        remaining.retain(|(txid, inputs)| {
+            let parents = parents(inputs);
+            match parents
+                .iter()
+                .all(|parent| ordered.iter().any(|(placed, _)| placed == parent))
+            {
+                true => {
+                    ordered.push((*txid, parents));
+                    false
+                }
+                false => true,
+            }
+        });
+        // transactions can't spend each other in a circle but don't loop forever if they do
+        if remaining.len() == before {
+            break;
+        }
+    }
+
+    let mut group_of = HashMap::<Txid, usize>::new();
+    let mut groups = Vec::<Vec<Txid>>::new();
+    for (txid, parents) in ordered {
+        let mut joined = parents
+            .iter()
+            .map(|parent| group_of[parent])
+            .collect::<Vec<_>>();
+        joined.sort();
+        joined.dedup();
+        let group = match joined.split_first() {
+            Some((first, others)) => {
+                for other in others {
+                    let moved = std::mem::take(&mut groups[*other]);
+                    for moved_txid in &moved {
+                        group_of.insert(*moved_txid, *first);
+                    }
+                    groups[*first].extend(moved);
+                }
+                *first
+            }
+            None => {
+                groups.push(vec![]);
+                groups.len() - 1
+            }
+        };
+        groups[group].push(txid);
+        group_of.insert(txid, group);
+    }
+    groups
+        .into_iter()
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::hashes::Hash;
+
+    fn txid(n: u8) -> Txid {
+        Txid::from_slice(&[n; 32]).unwrap()
+    }
+
+    fn spends(n: u8) -> OutPoint {
+        OutPoint {
+            txid: txid(n),
+            vout: 0,
+        }
+    }
+
+    #[test]
+    fn groups_are_parents_first() {
+        // 1 <- 2 <- 4, 3 alone, 5 spends 3 and 6
+        let txs = vec![
+            (txid(4), vec![spends(2)]),
+            (txid(2), vec![spends(1), spends(100)]),
+            (txid(1), vec![spends(101)]),
+            (txid(3), vec![spends(102)]),
+            (txid(6), vec![spends(103)]),
+            (txid(5), vec![spends(3), spends(6)]),
+        ];
+        let groups = dependency_groups(&txs);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0], vec![txid(1), txid(2), txid(4)]);
+        assert_eq!(groups[1].len(), 3);
+        assert_eq!(groups[1].last(), Some(&txid(5)));
+    }
+}
@@ -0,0 +1,182 @@
+use super::*;
+use crate::{
+    approval::ApprovalRequest, betting::BalanceCategory, cold_storage::ColdStorage, item, psbt_ext,
+};
+use bdk::{blockchain::EsploraBlockchain, SignOptions};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Keep the hot wallet under a ceiling by sweeping the excess to cold storage (see `cold-storage`
+/// in the config)
+pub enum ColdStorageOpt {
+    /// Show the ceiling and how much would be swept now
+    Status,
+    /// Sweep what's above the ceiling to cold storage
+    Sweep {
+        /// Broadcast without asking
+        #[structopt(long, short)]
+        yes: bool,
+    },
+}
+
+pub fn run_cold_storage_cmd(
+    wallet_dir: &PathBuf,
+    opt: ColdStorageOpt,
+) -> anyhow::Result<CmdOutput> {
+    let cold_storage = cold_storage_setting(wallet_dir)?;
+    let party = load_party(wallet_dir)?;
+    party.sync()?;
+    let spendable = party.balance()?.get(BalanceCategory::Spendable).value;
+    let excess = cold_storage.excess(spendable);
+    match opt {
+        ColdStorageOpt::Status => Ok(item! {
+            "destination" => Cell::string(&cold_storage.destination),
+            "ceiling" => Cell::Amount(cold_storage.ceiling),
+            "keep" => Cell::Amount(cold_storage.keep()),
+            "spendable" => Cell::Amount(spendable),
+            "to-sweep" => Cell::Amount(excess.unwrap_or(Amount::ZERO)),
+            "auto" => Cell::string(cold_storage.auto),
+        }),
+        ColdStorageOpt::Sweep { yes } => {
+            let excess = excess.ok_or(anyhow!(
+                "the spendable balance of {} isn't over the ceiling of {} by at least min-sweep ({})",
+                spendable,
+                cold_storage.ceiling,
+                cold_storage.min_sweep
+            ))?;
+            let (result, txid) = sweep_to_cold_storage(&party, &cold_storage, excess, yes)?;
+            Ok(item! {
+                "result" => Cell::string(result),
+                "amount" => Cell::Amount(excess),
+                "txid" => txid.map(Cell::string).unwrap_or(Cell::Empty),
+            })
+        }
+    }
+}
+
+fn cold_storage_setting(wallet_dir: &PathBuf) -> anyhow::Result<ColdStorage> {
+    load_config(wallet_dir)?.cold_storage.ok_or(anyhow!(
+        "cold-storage isn't set in the config e.g. {{ \"destination\": \"<address or descriptor>\", \"ceiling\": 1000000 }}"
+    ))
+}
+
+/// Sweeps (or proposes a sweep of) the hot balance above the ceiling if there's cold storage in the
+/// config. `last_proposed` is what was last proposed so the same sweep isn't proposed over and
+/// over.
+pub(crate) fn check_cold_storage(
+    wallet_dir: &PathBuf,
+    last_proposed: &mut Option<Amount>,
+) -> anyhow::Result<()> {
+    let cold_storage = match load_config(wallet_dir)?.cold_storage {
+        Some(cold_storage) => cold_storage,
+        None => return Ok(()),
+    };
+    let party = load_party(wallet_dir)?;
+    party.sync()?;
+    let spendable = party.balance()?.get(BalanceCategory::Spendable).value;
+    let excess = match cold_storage.excess(spendable) {
+        Some(excess) => excess,
+        None => {
+            *last_proposed = None;
+            return Ok(());
+        }
+    };
+    if cold_storage.auto {
+        let (result, txid) = sweep_to_cold_storage(&party, &cold_storage, excess, true)?;
+        eprintln!(
+            "cold storage: {} {} ({})",
+            result,
+            excess,
+            txid.map(|txid| txid.to_string()).unwrap_or_default()
+        );
+    } else if *last_proposed != Some(excess) {
+        crate::betting::alert(
+            party.settings().alert_command.as_deref(),
+            "cold-storage",
+            &format!(
+                "the spendable balance of {} is over the cold storage ceiling of {} -- run `gun cold-storage sweep` to move {} to cold storage",
+                spendable, cold_storage.ceiling, excess
+            ),
+        );
+        *last_proposed = Some(excess);
+    }
+    Ok(())
+}
+
+fn sweep_to_cold_storage<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    cold_storage: &ColdStorage,
+    amount: Amount,
+    yes: bool,
+) -> anyhow::Result<(&'static str, Option<Txid>)> {
+    let wallet = party.wallet();
+    let settings = party.settings();
+    let destination = cold_storage.next_destination(party.bet_db(), wallet.network())?;
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(destination, amount.as_sat());
+    settings.tx_ordering.apply_to_builder(&mut builder);
+    if settings.rbf.sends {
+        builder.enable_rbf();
+    }
+    for outpoint in party.bet_db().currently_used_utxos(&[])? {
+        builder.add_unspendable(outpoint);
+    }
+    for frozen in party.bet_db().frozen_utxos()? {
+        builder.add_unspendable(frozen);
+    }
+    for outpoint in party.bet_db().coinjoin_outputs()?.keys() {
+        builder.add_unspendable(*outpoint);
+    }
+    FeeSpec::from_str(&cold_storage.fee)?
+        .with_aliases(&settings.fee_aliases)
+        .apply_to_builder(wallet.client(), &mut builder)?;
+    if let Some(spec) = &settings.change_descriptor {
+        builder.drain_to(change_destination(party, spec)?);
+    }
+    let (mut psbt, _) = builder.finish()?;
+    settings.tx_ordering.finish(&mut psbt);
+    psbt_ext::log_built_tx(&psbt);
+    let memo = "cold storage sweep".to_string();
+
+    if let Some(policy) = &settings.approval {
+        if amount > policy.threshold {
+            let bet_db = party.bet_db();
+            let txid = bet_db.insert_pending_psbt(psbt, vec![])?;
+            bet_db.set_pending_approval(
+                txid,
+                Some(ApprovalRequest {
+                    requested_by: "cold storage".to_string(),
+                    requested_at: crate::clock::now(),
+                    outgoing: amount,
+                }),
+            )?;
+            bet_db.set_tx_memo(txid, memo)?;
+            return Ok(("queued", Some(txid)));
+        }
+    }
+
+    wallet.sign(&mut psbt, SignOptions::default())?;
+    party.audit_psbt(AuditOperation::Sign, "cold storage sweep", &psbt)?;
+    if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+        return Err(anyhow!(
+            "the wallet can't sign by itself (is it locked or are some keys elsewhere?)"
+        ));
+    }
+    let (_, txid) = decide_to_broadcast(
+        wallet.network(),
+        wallet.client(),
+        psbt,
+        yes,
+        false,
+        party.audit_log(),
+        party.bet_db(),
+        "cold storage sweep",
+    )?;
+    let txid = match txid {
+        Some(txid) => txid,
+        None => return Ok(("not sent", None)),
+    };
+    party.bet_db().set_tx_memo(txid, memo)?;
+    Ok(("sent", Some(txid)))
+}
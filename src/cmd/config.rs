@@ -0,0 +1,756 @@
+use super::*;
+use crate::{config::WalletKind, item, FeeAlias, Url};
+use bdk::{
+    bitcoin::secp256k1::Secp256k1,
+    descriptor::{get_checksum, IntoWalletDescriptor},
+};
+use serde::de::{self, Visitor};
+use serde_json::Value;
+use structopt::StructOpt;
+
+/// Esplora servers don't estimate fees for targets further away than this many blocks.
+const MAX_FEE_TARGET_BLOCKS: u32 = 1008;
+/// `avoid-change-tolerance`, `dust-change-threshold` and `min-output-value` above this many sats
+/// are probably mistakes (e.g. a value meant to be in sat/vb).
+const SUSPICIOUS_CHANGE_SATS: u64 = 100_000;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Check the wallet's config.json
+pub enum ConfigOpt {
+    /// Find everything that's wrong with config.json at once and suggest how to fix it
+    Validate,
+}
+
+pub fn run_config_cmd(wallet_dir: &PathBuf, opt: ConfigOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        ConfigOpt::Validate => {
+            let config_file = wallet_dir.join("config.json");
+            let json = fs::read_to_string(&config_file)
+                .with_context(|| format!("reading {}", config_file.display()))?;
+            let problems = config_problems(&json);
+            if problems.is_empty() {
+                return Ok(item! { "config" => Cell::string("no problems found") });
+            }
+            let rows = problems
+                .into_iter()
+                .map(|problem| {
+                    vec![
+                        Cell::string(problem.severity.name()),
+                        Cell::String(problem.setting),
+                        Cell::String(problem.problem),
+                        Cell::String(problem.fix),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec!["severity", "setting", "problem", "fix"],
+                rows,
+            ))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// It works but probably not like it was meant to
+    Warning,
+    /// The wallet can't be used until it's fixed
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "ERROR",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigProblem {
+    pub severity: Severity,
+    /// The key in config.json that's wrong
+    pub setting: String,
+    pub problem: String,
+    /// What the user can do about it
+    pub fix: String,
+}
+
+impl ConfigProblem {
+    fn error(setting: &str, problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            setting: setting.to_string(),
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+
+    fn warning(setting: &str, problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(setting, problem, fix)
+        }
+    }
+}
+
+/// Prints what's wrong with config.json to stderr. This runs before every command so it never
+/// fails. A missing or unreadable config is left for the command to complain about.
+pub fn warn_about_config(wallet_dir: &PathBuf) {
+    let problems = match fs::read_to_string(wallet_dir.join("config.json")) {
+        Ok(json) => config_problems(&json),
+        Err(_) => return,
+    };
+    for problem in &problems {
+        eprintln!(
+            "config {}: {} {}",
+            problem.severity.name(),
+            problem.setting,
+            problem.problem
+        );
+    }
+    if !problems.is_empty() {
+        eprintln!("run `gun config validate` to see how to fix this");
+    }
+}
+
+/// Finds everything wrong with the contents of a config.json. Unlike loading it this doesn't stop
+/// at the first setting that can't be parsed.
+pub fn config_problems(json: &str) -> Vec<ConfigProblem> {
+    let mut value = match serde_json::from_str::<Value>(json) {
+        Ok(value) => value,
+        Err(e) => {
+            return vec![ConfigProblem::error(
+                "config.json",
+                format!("isn't valid JSON: {}", e),
+                format!("fix the JSON at line {} column {}", e.line(), e.column()),
+            )]
+        }
+    };
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => {
+            return vec![ConfigProblem::error(
+                "config.json",
+                "isn't a JSON object",
+                "it should look like { \"network\": \"bitcoin\", ... } -- set up the wallet again with `gun init` if it's lost",
+            )]
+        }
+    };
+
+    let mut problems = unknown_settings(object.keys());
+    if let Some(blockchain) = object.get("blockchain") {
+        problems.extend(backend_url_problem(blockchain));
+    }
+
+    let network_fix = "use \"bitcoin\", \"testnet\" or \"regtest\"";
+    let network = match object
+        .get("network")
+        .map(|network| serde_json::from_value::<Network>(network.clone()))
+    {
+        Some(Ok(Network::Signet)) => {
+            problems.push(ConfigProblem::error(
+                "network",
+                "signet isn't supported yet",
+                network_fix,
+            ));
+            None
+        }
+        Some(Ok(network)) => Some(network),
+        Some(Err(e)) => {
+            problems.push(ConfigProblem::error("network", e.to_string(), network_fix));
+            None
+        }
+        None => {
+            problems.push(ConfigProblem::error("network", "is missing", network_fix));
+            None
+        }
+    };
+    // so the rest can still be checked
+    let network = network.unwrap_or_else(|| {
+        object.insert("network".into(), serde_json::json!(Network::Bitcoin));
+        Network::Bitcoin
+    });
+    // what loading the config does so a missing backend isn't a problem
+    let _ = fill_in_default_backend(&mut value);
+
+    match serde_json::from_value::<Config>(value.clone()) {
+        Ok(config) => problems.extend(setting_problems(&config)),
+        Err(_) => problems.extend(parse_problems(&value, network)),
+    }
+    problems
+}
+
+/// Parses each setting on its own with the defaults for the rest so every one that's wrong is
+/// found and not just the first.
+fn parse_problems(value: &Value, network: Network) -> Vec<ConfigProblem> {
+    let template = serde_json::to_value(Config::default_config(network)).unwrap();
+    let object = value.as_object().expect("checked already");
+    let mut problems = vec![];
+    for required in &["kind", "keys"] {
+        if !object.contains_key(*required) {
+            problems.push(ConfigProblem::error(
+                required,
+                "is missing",
+                format!("add it e.g. \"{}\": {}", required, template[*required]),
+            ));
+        }
+    }
+    for (key, setting) in object {
+        let mut attempt = template.clone();
+        attempt[key] = setting.clone();
+        if let Err(e) = serde_json::from_value::<Config>(attempt) {
+            let fix = match key.as_str() {
+                "blockchain" => "only Esplora backends are compiled in e.g. { \"type\": \"esplora\", \"base_url\": \"https://blockstream.info/api\" }. Removing it uses the default for the network.".to_string(),
+                "kind" => "use \"p2wpkh\" or { \"descriptor\": { \"external\": \"wpkh(...)\" } }".to_string(),
+                "keys" => "use \"seed-words-file\"".to_string(),
+                _ => format!("fix {} or remove it to use the default", key),
+            };
+            problems.push(ConfigProblem::error(key, e.to_string(), fix));
+        }
+    }
+    problems
+}
+
+/// Settings that aren't known are ignored when the config is loaded which hides typos.
+fn unknown_settings<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<ConfigProblem> {
+    let known = field_names::<Config>();
+    keys.filter(|key| !known.contains(&key.as_str()))
+        .map(|key| {
+            let closest = known
+                .iter()
+                .map(|name| (edit_distance(key, name), name))
+                .min()
+                .filter(|(distance, _)| *distance <= 3);
+            ConfigProblem::warning(
+                key,
+                "isn't a setting so it's ignored",
+                match closest {
+                    Some((_, name)) => format!("did you mean {}?", name),
+                    None => "remove it".to_string(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Whether the URL of the backend is the kind its `type` can connect to.
+fn backend_url_problem(blockchain: &Value) -> Option<ConfigProblem> {
+    let kind = blockchain.get("type")?.as_str()?;
+    let (url_key, schemes): (&str, &[&str]) = match kind {
+        "esplora" => ("base_url", &["http", "https"]),
+        "electrum" => ("url", &["tcp", "ssl"]),
+        // whether the type is supported is found out parsing it
+        _ => return None,
+    };
+    let url = blockchain.get(url_key)?.as_str()?;
+    let scheme = match Url::parse(url) {
+        Ok(parsed) => parsed.scheme().to_string(),
+        Err(e) => {
+            return Some(ConfigProblem::error(
+                "blockchain",
+                format!("{} {} isn't a URL: {}", url_key, url, e),
+                format!("use a {} URL", schemes.join(" or ")),
+            ))
+        }
+    };
+    if schemes.contains(&scheme.as_str()) {
+        return None;
+    }
+    let fix = match (kind, scheme.as_str()) {
+        ("esplora", "tcp") | ("esplora", "ssl") => "that's the address of an Electrum server -- use the HTTP API of an Esplora server e.g. https://blockstream.info/api".to_string(),
+        _ => format!(
+            "{} backends are reached over {} so use a URL like {}://...",
+            kind,
+            schemes.join(" or "),
+            schemes[schemes.len() - 1]
+        ),
+    };
+    Some(ConfigProblem::error(
+        "blockchain",
+        format!("{} is a {} URL but the backend is {}", url, scheme, kind),
+        fix,
+    ))
+}
+
+/// Looks for settings that parse but can't work or are probably not what was meant.
+fn setting_problems(config: &Config) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+
+    if let WalletKind::Descriptor { external, internal } = &config.kind {
+        problems.extend(descriptor_problems("kind", external, config.network));
+        if let Some(internal) = internal {
+            problems.extend(descriptor_problems("kind", internal, config.network));
+        }
+    }
+    if let Some(change_descriptor) = &config.change_descriptor {
+        problems.extend(descriptor_problems(
+            "change-descriptor",
+            change_descriptor,
+            config.network,
+        ));
+    }
+    if let Some(bet_cosigner) = &config.bet_cosigner {
+        if let Err(e) = crate::betting::BetCosigner::new(bet_cosigner, config.network) {
+            problems.push(ConfigProblem::error(
+                "bet-cosigner",
+                format!("{:#}", e),
+                "use an xpub with its origin e.g. [d34db33f/84'/0'/9']xpub.../* or remove it",
+            ));
+        }
+    }
+
+    if config.price_source.is_some() && !cfg!(feature = "price") {
+        problems.push(ConfigProblem::warning(
+            "price-source",
+            "this gun was built without the price feature so the price is never fetched",
+            "build gun with `--features price` or remove it",
+        ));
+    }
+    if !config.nostr_relays.is_empty() && !cfg!(feature = "nostr") {
+        problems.push(ConfigProblem::warning(
+            "nostr-relays",
+            "this gun was built without the nostr feature so the relays are never used",
+            "build gun with `--features nostr` or remove them",
+        ));
+    }
+
+    if let Some(pinned) = &config.pinned_cert_sha256 {
+        let pinned = normalize_fingerprint(pinned);
+        if pinned.len() != 64 || !pinned.chars().all(|c| c.is_ascii_hexdigit()) {
+            problems.push(ConfigProblem::error(
+                "pinned-cert-sha256",
+                format!("{} isn't a SHA256 fingerprint", pinned),
+                "it should be 64 hex characters -- get it with `gun backend show` or remove it",
+            ));
+        }
+        if backend_url(&config.blockchain).starts_with("http:") {
+            problems.push(ConfigProblem::error(
+                "pinned-cert-sha256",
+                "a certificate is pinned but the backend doesn't use https",
+                "use an https base_url or remove pinned-cert-sha256",
+            ));
+        }
+    }
+
+    let aliases = config.fee_aliases;
+    for alias in FeeAlias::ALL.iter() {
+        let blocks = aliases.blocks(*alias);
+        let setting = format!("fee-aliases.{}", alias.name());
+        if blocks == 0 {
+            problems.push(ConfigProblem::error(
+                &setting,
+                "is 0 blocks but fees can only be estimated for 1 block or more",
+                "use 1 to aim for the next block",
+            ));
+        } else if blocks > MAX_FEE_TARGET_BLOCKS {
+            problems.push(ConfigProblem::warning(
+                &setting,
+                format!(
+                    "is {} blocks but fees are only estimated up to {}",
+                    blocks, MAX_FEE_TARGET_BLOCKS
+                ),
+                format!("use at most {}", MAX_FEE_TARGET_BLOCKS),
+            ));
+        }
+    }
+    if !(aliases.fastest <= aliases.hour && aliases.hour <= aliases.economy) {
+        problems.push(ConfigProblem::warning(
+            "fee-aliases",
+            format!(
+                "fastest is {} blocks, hour {} and economy {} so a faster one can pay less",
+                aliases.fastest, aliases.hour, aliases.economy
+            ),
+            "make fastest no more blocks than hour and hour no more than economy",
+        ));
+    }
+    for (setting, amount) in &[
+        ("avoid-change-tolerance", config.avoid_change_tolerance),
+        ("dust-change-threshold", config.dust_change_threshold),
+        ("min-output-value", config.min_output_value),
+    ] {
+        if let Some(amount) = amount {
+            if amount.as_sat() > SUSPICIOUS_CHANGE_SATS {
+                problems.push(ConfigProblem::warning(
+                    setting,
+                    format!(
+                        "is {} sats which can all go to fees instead of change",
+                        amount.as_sat()
+                    ),
+                    "it's in sats -- use something much smaller like 1000",
+                ));
+            }
+        }
+    }
+
+    if let Some(min_output_value) = config.min_output_value {
+        use bdk::bitcoin::{hashes::Hash, Script, WPubkeyHash};
+        // the smallest dust limit of the outputs gun makes
+        let p2wpkh_dust = crate::dust::dust_limit(&Script::new_v0_wpkh(
+            &WPubkeyHash::from_slice(&[0; 20]).unwrap(),
+        ));
+        if min_output_value < p2wpkh_dust {
+            problems.push(ConfigProblem::warning(
+                "min-output-value",
+                format!(
+                    "is {} sats which is below the dust limit of every kind of output so it does nothing",
+                    min_output_value.as_sat()
+                ),
+                format!(
+                    "remove it or make it more than {} sats",
+                    p2wpkh_dust.as_sat()
+                ),
+            ));
+        }
+    }
+
+    for (setting, url) in &[
+        ("explorer", &config.explorer),
+        ("explorer-url", &config.explorer_url),
+    ] {
+        if let Some(url) = url {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                problems.push(ConfigProblem::error(
+                    setting,
+                    format!("{} isn't an http or https URL", url),
+                    "use a URL like https://mempool.space",
+                ));
+            }
+        }
+    }
+    if let Some(explorer_url) = &config.explorer_url {
+        if !explorer_url.contains("{id}") {
+            problems.push(ConfigProblem::error(
+                "explorer-url",
+                "doesn't have {id} in it so every link goes to the same page",
+                "e.g. http://explorer.onion/{kind}/{id}",
+            ));
+        }
+    }
+
+    for (setting, command) in &[
+        ("external-signer", &config.external_signer),
+        ("alert-command", &config.alert_command),
+    ] {
+        if let Some(command) = command {
+            if command.is_empty() {
+                problems.push(ConfigProblem::error(
+                    setting,
+                    "is an empty command",
+                    "give the program followed by its arguments e.g. [\"my-program\", \"--flag\"] or remove it",
+                ));
+            }
+        }
+    }
+
+    for faucet in &config.faucets {
+        if !faucet.post && !faucet.url.contains("{address}") {
+            problems.push(ConfigProblem::warning(
+                "faucets",
+                format!(
+                    "{}'s url doesn't have {{address}} in it so it isn't told where to send coins",
+                    faucet.name
+                ),
+                "put {address} in the url or set \"post\": true",
+            ));
+        }
+    }
+
+    if let Some(locale) = &config.locale {
+        if let Err(e) = locale.parse::<crate::i18n::Locale>() {
+            problems.push(ConfigProblem::warning(
+                "locale",
+                format!("{} so it's in English", e),
+                "set it to one of the languages gun has or remove it",
+            ));
+        }
+    }
+
+    for rule in &config.proxies {
+        match rule.proxy_url() {
+            Err(e) => problems.push(ConfigProblem::error(
+                "proxies",
+                format!("{:#}", e),
+                "use a url like socks5h://127.0.0.1:9050 or \"none\"",
+            )),
+            Ok(Some(proxy)) if rule.hosts == "onion" && proxy.scheme() == "socks5" => problems
+                .push(ConfigProblem::warning(
+                    "proxies",
+                    "onion services go through a socks5 proxy which can't look up their names",
+                    format!(
+                        "use {} instead",
+                        proxy.as_str().replacen("socks5://", "socks5h://", 1)
+                    ),
+                )),
+            Ok(_) => {}
+        }
+    }
+    let backend = backend_url(&config.blockchain);
+    if let Ok(url) = crate::Url::parse(&backend) {
+        let through_proxy = crate::endpoint::matching_rule(&config.proxies, &url)
+            .map(|rule| matches!(rule.proxy_url(), Ok(Some(_))))
+            .unwrap_or(false);
+        if crate::endpoint::HostKind::of(&url) == Some(crate::endpoint::HostKind::Onion)
+            && !through_proxy
+        {
+            problems.push(ConfigProblem::warning(
+                "blockchain",
+                format!("{} is an onion service but no proxy is set for it", backend),
+                "add { \"hosts\": \"onion\", \"proxy\": \"socks5h://127.0.0.1:9050\" } to proxies",
+            ));
+        }
+    }
+
+    if let Some(claim_to) = &config.claim_to {
+        if let Err(e) = crate::address::check(claim_to, config.network, "the claim-to address") {
+            problems.push(ConfigProblem::error(
+                "claim-to",
+                format!("{:#}", e),
+                format!("use an address for {} or remove it", config.network),
+            ));
+        }
+    }
+
+    if let Some(cold_storage) = &config.cold_storage {
+        if let Err(e) = cold_storage.check_destination(config.network) {
+            problems.push(ConfigProblem::error(
+                "cold-storage.destination",
+                format!("{:#}", e),
+                "give an address or watch-only descriptor of the cold wallet",
+            ));
+        }
+        if let Err(e) = cold_storage.fee.parse::<crate::FeeSpec>() {
+            problems.push(ConfigProblem::error(
+                "cold-storage.fee",
+                format!("{:#}", e),
+                "use a fee like economy or in-blocks:144",
+            ));
+        }
+    }
+
+    let limits = &config.bet_limits;
+    for (setting, percent) in &[
+        ("bet-limits.max-bet-percent", limits.max_bet_percent),
+        ("bet-limits.max-at-risk-percent", limits.max_at_risk_percent),
+    ] {
+        if let Some(percent) = percent.filter(|percent| *percent > 100) {
+            problems.push(ConfigProblem::warning(
+                setting,
+                format!("is {}% so it's the same as 100%", percent),
+                "set it to a percentage from 0 to 100",
+            ));
+        }
+    }
+    if let (Some(bet), Some(at_risk)) = (limits.max_bet_percent, limits.max_at_risk_percent) {
+        if bet > at_risk {
+            problems.push(ConfigProblem::warning(
+                "bet-limits",
+                format!(
+                    "max-bet-percent is {}% but max-at-risk-percent is only {}% so it never matters",
+                    bet, at_risk
+                ),
+                "make max-bet-percent smaller than max-at-risk-percent",
+            ));
+        }
+    }
+
+    let parties = &config.bet_parties;
+    for (setting, empty) in &[
+        (
+            "bet-parties.allow-oracles",
+            parties.allow_oracles.as_ref().map(Vec::is_empty),
+        ),
+        (
+            "bet-parties.allow-counterparties",
+            parties.allow_counterparties.as_ref().map(Vec::is_empty),
+        ),
+    ] {
+        if *empty == Some(true) {
+            problems.push(ConfigProblem::warning(
+                setting,
+                "is empty so no bets can be made",
+                "add who bets can be made with or remove it to allow anyone",
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Checks the checksum of `descriptor` and that it parses for `network`.
+pub fn descriptor_problems(
+    setting: &str,
+    descriptor: &str,
+    network: Network,
+) -> Vec<ConfigProblem> {
+    let mut parts = descriptor.splitn(2, '#');
+    let body = parts.next().unwrap_or("");
+    let expected = match get_checksum(body) {
+        Ok(expected) => expected,
+        Err(e) => {
+            return vec![ConfigProblem::error(
+                setting,
+                format!("{} is invalid: {}", descriptor, e),
+                "fix the descriptor in config.json",
+            )]
+        }
+    };
+    match parts.next() {
+        Some(checksum) if checksum != expected => {
+            return vec![ConfigProblem::error(
+                setting,
+                format!(
+                    "{} has checksum {} but it should be {}",
+                    body, checksum, expected
+                ),
+                "the descriptor was probably mistyped. Copy it again from where it came from",
+            )]
+        }
+        Some(_) => {}
+        None => {
+            return vec![ConfigProblem::warning(
+                setting,
+                format!("{} doesn't have a checksum", body),
+                format!(
+                    "append #{} to it in config.json so typos are caught",
+                    expected
+                ),
+            )]
+        }
+    }
+    match descriptor.into_wallet_descriptor(&Secp256k1::new(), network) {
+        Ok(_) => vec![],
+        Err(e) => vec![ConfigProblem::error(
+            setting,
+            format!("{} can't be used on {}: {}", body, network, e),
+            "check its keys are for the wallet's network (xpub for bitcoin, tpub for the others)",
+        )],
+    }
+}
+
+/// The names serde expects for the fields of `T`. Deserializing a struct asks for them first so
+/// this pretends to be a deserializer to catch them.
+fn field_names<'de, T: serde::Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> serde::Deserializer<'de> for FieldNames<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("only structs have field names"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("got the field names"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// How many characters have to be added, removed or changed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + (a_char != *b_char) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(problems: &[ConfigProblem]) -> Vec<&str> {
+        problems
+            .iter()
+            .map(|problem| problem.setting.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn default_config_has_no_problems() {
+        let json = serde_json::to_string(&Config::default_config(Network::Regtest)).unwrap();
+        assert_eq!(config_problems(&json), vec![]);
+        assert!(field_names::<Config>().contains(&"fee-aliases"));
+    }
+
+    #[test]
+    fn every_problem_is_found_at_once() {
+        let json = r#"{
+            "network": "regtest",
+            "blockchain": { "type": "esplora", "base_url": "ssl://electrum.example:50002" },
+            "kind": "p2wpkh",
+            "keys": "seed-words-file",
+            "fee-aliases": { "fastest": 0 },
+            "coin-select": "biggest-coins-first-please",
+            "tx-ordering": "alphabetical",
+            "fee-alias": { "hour": 3 }
+        }"#;
+        let problems = config_problems(json);
+        assert_eq!(
+            settings(&problems),
+            vec!["fee-alias", "blockchain", "coin-select", "tx-ordering"]
+        );
+        assert_eq!(problems[0].fix, "did you mean fee-aliases?");
+
+        // once it parses the settings themselves are checked
+        let json = json
+            .replace("\"biggest-coins-first-please\"", "\"avoid-reuse\"")
+            .replace("\"alphabetical\"", "\"bip69\"");
+        let fixed = config_problems(&json);
+        assert!(settings(&fixed).contains(&"fee-aliases.fastest"));
+    }
+
+    #[test]
+    fn descriptor_checksums() {
+        let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+        let checksum = get_checksum(descriptor).unwrap();
+        let with_checksum = format!("{}#{}", descriptor, checksum);
+        assert_eq!(
+            descriptor_problems("kind", &with_checksum, Network::Testnet),
+            vec![]
+        );
+        assert_eq!(
+            descriptor_problems("kind", descriptor, Network::Testnet)[0].severity,
+            Severity::Warning
+        );
+        let mistyped = format!("{}#{}", descriptor, "qqqqqqqq");
+        assert_eq!(
+            descriptor_problems("kind", &mistyped, Network::Testnet)[0].severity,
+            Severity::Error
+        );
+        // tpubs aren't for mainnet
+        assert_eq!(
+            descriptor_problems("kind", &with_checksum, Network::Bitcoin).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("fee-alias", "fee-aliases"), 2);
+        assert_eq!(edit_distance("explorer", "explorer"), 0);
+        assert_eq!(edit_distance("rbf", "read-only"), 7);
+    }
+}
@@ -0,0 +1,205 @@
+use super::*;
+use crate::{
+    betting::{ChecksumProblem, RecordChecksum},
+    item,
+};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// How often `gun schedule run --daemon` verifies the database checksums
+const VERIFY_EVERY: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[derive(StructOpt, Debug, Clone)]
+/// Look after the wallet's database
+pub enum DbOpt {
+    /// Rewrite the database so the space taken by removed entries (e.g. pruned bets) is given back
+    Compact,
+    /// Check that the entries whose corruption could cost money (bets, reserved coins and change
+    /// indices) are as gun last wrote them. `gun schedule run --daemon` does this every few hours
+    /// too.
+    Verify,
+}
+
+pub fn run_db_cmd(wallet_dir: &PathBuf, opt: DbOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        DbOpt::Compact => {
+            let db_dir = wallet_dir.join("database.sled");
+            let compacted_dir = wallet_dir.join("database.sled.compacting");
+            let old_dir = wallet_dir.join("database.sled.old");
+
+            if !db_dir.exists() {
+                if old_dir.exists() {
+                    return Err(anyhow!(
+                        "an earlier compaction was interrupted -- move {} back to {} and try again",
+                        old_dir.display(),
+                        db_dir.display()
+                    ));
+                }
+                return Err(anyhow!("there's no database at {}", db_dir.display()));
+            }
+            // left over from a compaction that didn't finish
+            if compacted_dir.exists() {
+                fs::remove_dir_all(&compacted_dir)?;
+            }
+
+            let size_before = dir_size(&db_dir)?;
+            {
+                let database = sled::open(&db_dir).context("opening database.sled")?;
+                let compacted = sled::open(&compacted_dir)
+                    .with_context(|| format!("creating {}", compacted_dir.display()))?;
+                compacted.import(database.export());
+                compacted.flush()?;
+            }
+            fs::rename(&db_dir, &old_dir)?;
+            fs::rename(&compacted_dir, &db_dir)?;
+            fs::remove_dir_all(&old_dir)?;
+            let size_after = dir_size(&db_dir)?;
+
+            Ok(item! {
+                "size-before" => Cell::Int(size_before),
+                "size-after" => Cell::Int(size_after),
+                "reclaimed" => Cell::Int(size_before.saturating_sub(size_after)),
+            })
+        }
+        DbOpt::Verify => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            let report = bet_db.verify_checksums()?;
+            if report.added > 0 {
+                eprintln!(
+                    "{} entries from before checksums were kept have been given one",
+                    report.added
+                );
+            }
+            if !report.problems.is_empty() {
+                for problem in &report.problems {
+                    eprintln!("{}", problem);
+                }
+                eprintln!(
+                    "{}",
+                    restore_suggestion(wallet_dir, &bet_db, &report.problems)
+                );
+                return Err(anyhow!(
+                    "{} entries aren't as gun last wrote them -- don't make bets or claim until they're restored",
+                    report.problems.len()
+                ));
+            }
+            Ok(item! {
+                "checked" => Cell::Int(report.checked as u64),
+                "added" => Cell::Int(report.added as u64),
+            })
+        }
+    }
+}
+
+/// What to do about `problems` going by what's in the latest backup.
+fn restore_suggestion(
+    wallet_dir: &PathBuf,
+    bet_db: &BetDatabase,
+    problems: &[ChecksumProblem],
+) -> String {
+    let last_backup = match last_backup(wallet_dir) {
+        Some(last_backup) => last_backup,
+        None => {
+            return "There's no record of a backup made with `gun backup create` to restore them from."
+                .to_string()
+        }
+    };
+    let restore = format!(
+        "gun backup restore {} {}",
+        last_backup.file.display(),
+        problems
+            .iter()
+            .map(|problem| format!("--entry {}", crate::hex::encode(problem.key())))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let backup = match open_seed_locked_backup(wallet_dir, &last_backup.file) {
+        Ok(Some(backup)) => backup,
+        Ok(None) => {
+            return format!(
+                "The latest backup (from {}) is locked with a passphrase so it hasn't been checked. To restore them from it run:\n  {}",
+                last_backup.created_at, restore
+            )
+        }
+        Err(e) => {
+            return format!(
+                "The latest backup {} can't be opened: {:#}",
+                last_backup.file.display(),
+                e
+            )
+        }
+    };
+
+    let mut lines = vec![format!("The latest backup (from {}):", backup.created_at)];
+    for problem in problems {
+        let written = bet_db
+            .get_entity::<RecordChecksum>(problem.key().to_vec())
+            .ok()
+            .flatten();
+        let status = match (
+            backup
+                .entries
+                .iter()
+                .find(|entry| entry.key == problem.key()),
+            written,
+        ) {
+            (None, _) => "doesn't have",
+            (Some(entry), Some(written)) if written.matches(&entry.value) => {
+                "has as it was last written"
+            }
+            (Some(_), _) => "has an older version of",
+        };
+        lines.push(format!(
+            "  {} entry {}",
+            status,
+            crate::hex::encode(problem.key())
+        ));
+    }
+    lines.push(format!("To restore the ones it has run:\n  {}", restore));
+    lines.join("\n")
+}
+
+/// Verifies the database checksums if it's been [`VERIFY_EVERY`] since `last_verified` and alerts
+/// if any entries have changed.
+pub(crate) fn verify_when_idle(
+    wallet_dir: &PathBuf,
+    last_verified: &mut Option<Instant>,
+) -> anyhow::Result<()> {
+    if matches!(last_verified, Some(last) if last.elapsed() < VERIFY_EVERY) {
+        return Ok(());
+    }
+    *last_verified = Some(Instant::now());
+    let bet_db = load_bet_db(wallet_dir)?;
+    let problems = bet_db.verify_checksums()?.problems;
+    if !problems.is_empty() {
+        crate::betting::alert(
+            load_config(wallet_dir)?.alert_command.as_deref(),
+            "database-corrupted",
+            &format!(
+                "{} database entries aren't as gun last wrote them ({}) -- run `gun db verify` to see which backup has them",
+                problems.len(),
+                problems
+                    .iter()
+                    .map(|problem| problem.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// The total size in bytes of the files under `dir`
+fn dir_size(dir: &PathBuf) -> anyhow::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,153 @@
+use super::*;
+use crate::{
+    amount_ext::FromCliStr,
+    faucet::{FaucetRequests, FaucetResponse},
+    item,
+};
+use bdk::wallet::AddressIndex;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// How often the wallet is synced while waiting for faucet coins.
+const FAUCET_POLL_SECS: u64 = 10;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Things for trying gun out on test networks
+pub enum DevOpt {
+    /// Get coins from the faucets in the config (see `faucets`) sent to the next address and wait
+    /// for them to arrive
+    Faucet {
+        /// Only ask the faucet with this name
+        #[structopt(long)]
+        name: Option<String>,
+        /// How much to ask for (faucets may send a different amount)
+        #[structopt(long, default_value = "100000sat", parse(try_from_str = FromCliStr::from_cli_str))]
+        amount: Amount,
+        /// How many seconds to wait for the coins to show up
+        #[structopt(long, default_value = "600")]
+        timeout: u64,
+        /// Don't wait for the coins to show up
+        #[structopt(long)]
+        no_wait: bool,
+    },
+}
+
+pub fn run_dev_cmd(wallet_dir: &PathBuf, opt: DevOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        DevOpt::Faucet {
+            name,
+            amount,
+            timeout,
+            no_wait,
+        } => run_faucet(wallet_dir, name, amount, timeout, no_wait),
+    }
+}
+
+fn run_faucet(
+    wallet_dir: &PathBuf,
+    name: Option<String>,
+    amount: Amount,
+    timeout: u64,
+    no_wait: bool,
+) -> anyhow::Result<CmdOutput> {
+    let config = load_config(wallet_dir)?;
+    if config.network == Network::Bitcoin {
+        return Err(anyhow!(
+            "faucets are for test networks and this wallet is on mainnet"
+        ));
+    }
+    let faucets = config
+        .faucets
+        .iter()
+        .filter(|faucet| {
+            name.as_ref()
+                .map(|name| *name == faucet.name)
+                .unwrap_or(true)
+        })
+        .collect::<Vec<_>>();
+    if faucets.is_empty() {
+        return Err(match name {
+            Some(name) => anyhow!("there's no faucet called {} in the config", name),
+            None => anyhow!(
+                "there are no faucets in the config -- add some to `faucets` in config.json e.g. [{{ \"name\": \"example\", \"url\": \"https://faucet.example/send?address={{address}}&amount={{amount}}\" }}]"
+            ),
+        });
+    }
+
+    let party = load_party(wallet_dir)?;
+    let wallet = party.wallet();
+    let address = wallet.get_address(AddressIndex::New)?.address;
+    let client = crate::endpoint::http_client()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let mut requests = FaucetRequests::load(wallet_dir)?;
+    let mut problems = vec![];
+    let mut paid = None;
+    for faucet in faucets {
+        let now = crate::chrono::Utc::now().naive_utc();
+        if let Some(next) = requests.too_soon(faucet, now) {
+            problems.push(format!(
+                "{} was asked recently and can be asked again at {}",
+                faucet.name, next
+            ));
+            continue;
+        }
+        let response = faucet.request(&client, &address, amount);
+        if response.is_ok() {
+            // it answered so don't ask it again too soon whatever it said
+            requests.record(faucet, now);
+            requests.save(wallet_dir)?;
+        }
+        match response {
+            Ok(FaucetResponse::Paid(txid)) => {
+                paid = Some((faucet.name.clone(), txid));
+                break;
+            }
+            Ok(FaucetResponse::Dry(reason)) => {
+                problems.push(format!("{} is dry or busy: {}", faucet.name, reason))
+            }
+            Err(e) => problems.push(format!("{:#}", e)),
+        }
+    }
+    let (faucet_name, txid) = paid.ok_or(anyhow!(
+        "none of the faucets sent coins:\n  {}",
+        problems.join("\n  ")
+    ))?;
+    eprintln!("{} is sending coins to {}", faucet_name, address);
+
+    if no_wait {
+        return Ok(item! {
+            "faucet" => Cell::String(faucet_name),
+            "address" => Cell::string(&address),
+            "txid" => txid.map(Cell::string).unwrap_or(Cell::Empty),
+        });
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let script_pubkey = address.script_pubkey();
+    loop {
+        party.sync()?;
+        let received = wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| utxo.txout.script_pubkey == script_pubkey)
+            .collect::<Vec<_>>();
+        if let Some(utxo) = received.first() {
+            return Ok(item! {
+                "faucet" => Cell::String(faucet_name),
+                "address" => Cell::string(&address),
+                "txid" => Cell::string(utxo.outpoint.txid),
+                "value" => Cell::Amount(Amount::from_sat(received.iter().map(|utxo| utxo.txout.value).sum())),
+            });
+        }
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "{} said it sent coins but nothing arrived at {} within {}s. They may still come -- check with `gun -s balance`.",
+                faucet_name,
+                address,
+                timeout
+            ));
+        }
+        std::thread::sleep(Duration::from_secs(FAUCET_POLL_SECS));
+    }
+}
@@ -0,0 +1,471 @@
+use super::*;
+use crate::{
+    betting::{IntegrityProblem, OracleInfo},
+    reqwest, Url,
+};
+use bdk::blockchain::AnyBlockchainConfig;
+use olivia_core::http::RootResponse;
+use olivia_secp256k1::Secp256k1;
+use std::time::{Duration, Instant};
+
+/// Warn if our clock and the backend's differ by more than this many seconds.
+const MAX_CLOCK_SKEW_SECS: i64 = 5 * 60;
+/// Warn if there's less than this much space left where the wallet is.
+const MIN_FREE_DISK_KIB: u64 = 100 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    /// What the user can do about it
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    fn skipped(name: &'static str, why: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Skipped,
+            detail: why.into(),
+            fix: None,
+        }
+    }
+}
+
+/// Looks for everything we know of that can stop the wallet from working and suggests fixes.
+pub fn run_doctor(wallet_dir: &PathBuf) -> anyhow::Result<CmdOutput> {
+    let mut checks = vec![];
+
+    let config = match load_config(wallet_dir) {
+        Ok(config) => {
+            checks.push(Check::ok("config", "config.json is valid"));
+            Some(config)
+        }
+        Err(e) => {
+            checks.push(Check::problem(
+                "config",
+                Status::Fail,
+                format!("{:#}", e),
+                "fix config.json by hand or set up the wallet with `gun init`",
+            ));
+            None
+        }
+    };
+
+    checks.extend(check_database(wallet_dir));
+
+    match &config {
+        Some(config) => {
+            checks.push(check_descriptors(wallet_dir, config));
+            checks.extend(check_backend(config));
+        }
+        None => {
+            for name in &["descriptors", "backend", "clock"] {
+                checks.push(Check::skipped(name, "no valid config"));
+            }
+        }
+    }
+
+    checks.extend(check_oracles(wallet_dir));
+    checks.push(check_disk_space(wallet_dir));
+
+    let rows = checks
+        .into_iter()
+        .map(|check| {
+            vec![
+                Cell::string(check.name),
+                Cell::string(check.status.name()),
+                Cell::String(check.detail),
+                check.fix.map(Cell::String).unwrap_or(Cell::Empty),
+            ]
+        })
+        .collect();
+
+    Ok(CmdOutput::table(
+        vec!["check", "status", "detail", "fix"],
+        rows,
+    ))
+}
+
+fn check_database(wallet_dir: &PathBuf) -> Vec<Check> {
+    let db_file = wallet_dir.join("database.sled");
+    let database = match sled::open(&db_file) {
+        Ok(database) => database,
+        Err(e) => {
+            return vec![Check::problem(
+                "database",
+                Status::Fail,
+                format!("opening {}: {}", db_file.display(), e),
+                "make sure no other gun is running. If the database is corrupted move it away, resync and `gun backup restore` your latest backup",
+            )]
+        }
+    };
+
+    // reading everything is how sled finds out about corruption
+    let mut n_entries = 0;
+    for tree_name in database.tree_names() {
+        let tree = match database.open_tree(&tree_name) {
+            Ok(tree) => tree,
+            Err(e) => {
+                return vec![Check::problem(
+                    "database",
+                    Status::Fail,
+                    format!("opening tree {}: {}", String::from_utf8_lossy(&tree_name), e),
+                    "the database is corrupted -- move it away, resync and `gun backup restore` your latest backup",
+                )]
+            }
+        };
+        for item in tree.iter() {
+            if let Err(e) = item {
+                return vec![Check::problem(
+                    "database",
+                    Status::Fail,
+                    format!("reading tree {}: {}", String::from_utf8_lossy(&tree_name), e),
+                    "the database is corrupted -- move it away, resync and `gun backup restore` your latest backup",
+                )];
+            }
+            n_entries += 1;
+        }
+    }
+    let mut checks = vec![Check::ok(
+        "database",
+        format!("read {} entries without errors", n_entries),
+    )];
+
+    let bet_db = match database.open_tree("bets") {
+        Ok(tree) => BetDatabase::new(tree),
+        Err(e) => {
+            checks.push(Check::problem(
+                "bet-entries",
+                Status::Fail,
+                format!("opening the bets tree: {}", e),
+                "move the database away, resync and `gun backup restore` your latest backup",
+            ));
+            return checks;
+        }
+    };
+    match bet_db.check_integrity() {
+        Ok((n_entries, problems)) if problems.is_empty() => checks.push(Check::ok(
+            "bet-entries",
+            format!("{} entries are consistent", n_entries),
+        )),
+        Ok((_, problems)) => {
+            for problem in problems {
+                let (status, fix) = match &problem {
+                    IntegrityProblem::Invalid { .. } => (
+                        Status::Fail,
+                        "this entry can't be used. Restore it from a backup with `gun backup restore --overwrite`",
+                    ),
+                    IntegrityProblem::BetIdAhead { .. } => (
+                        Status::Fail,
+                        "don't make new bets until this is fixed -- they could replace this one. Restoring a backup fixes the counter",
+                    ),
+                    IntegrityProblem::Orphaned { .. } => (
+                        Status::Warn,
+                        "harmless but the bet it belonged to is gone. Restore it from a backup if you need it",
+                    ),
+                    IntegrityProblem::Unused { .. } => {
+                        (Status::Warn, "harmless -- it is left over from an older gun")
+                    }
+                };
+                checks.push(Check::problem(
+                    "bet-entries",
+                    status,
+                    problem.to_string(),
+                    fix,
+                ));
+            }
+        }
+        Err(e) => checks.push(Check::problem(
+            "bet-entries",
+            Status::Fail,
+            format!("{:#}", e),
+            "the database is corrupted -- move it away, resync and `gun backup restore` your latest backup",
+        )),
+    }
+
+    checks
+}
+
+fn check_descriptors(wallet_dir: &PathBuf, config: &Config) -> Check {
+    if let crate::config::WalletKind::Descriptor { external, internal } = &config.kind {
+        for descriptor in core::iter::once(external).chain(internal) {
+            if let Some(problem) = descriptor_problems("kind", descriptor, config.network)
+                .into_iter()
+                .next()
+            {
+                let status = match problem.severity {
+                    Severity::Warning => Status::Warn,
+                    Severity::Error => Status::Fail,
+                };
+                return Check::problem("descriptors", status, problem.problem, problem.fix);
+            }
+        }
+    }
+
+    // bdk refuses to open a wallet database that was made with other descriptors
+    match load_wallet(wallet_dir) {
+        Ok(_) => Check::ok("descriptors", "checksums match the wallet database"),
+        Err(e) => {
+            let mismatch = e.chain().any(|cause| {
+                matches!(
+                    cause.downcast_ref::<bdk::Error>(),
+                    Some(bdk::Error::ChecksumMismatch)
+                )
+            });
+            if mismatch {
+                Check::problem(
+                    "descriptors",
+                    Status::Fail,
+                    "the wallet database was made for different descriptors or seed words",
+                    "check the seed words and config are the right ones. If they are, move database.sled away and sync again",
+                )
+            } else {
+                Check::problem(
+                    "descriptors",
+                    Status::Fail,
+                    format!("loading the wallet: {:#}", e),
+                    "fix the problem above",
+                )
+            }
+        }
+    }
+}
+
+fn check_backend(config: &Config) -> Vec<Check> {
+    let base_url = match &config.blockchain {
+        AnyBlockchainConfig::Esplora(esplora) => esplora.base_url.trim_end_matches('/').to_string(),
+        #[allow(unreachable_patterns)]
+        _ => {
+            return vec![
+                Check::skipped("backend", "only esplora backends can be checked"),
+                Check::skipped("clock", "only esplora backends can be checked"),
+            ]
+        }
+    };
+    let fix = "check your connection and the backend's base-url in config.json";
+    let client = match crate::endpoint::http_client()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return vec![Check::problem("backend", Status::Fail, e.to_string(), fix)],
+    };
+
+    let started = Instant::now();
+    let response = match client
+        .get(format!("{}/blocks/tip/height", base_url))
+        .send()
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(e) => {
+            return vec![
+                Check::problem(
+                    "backend",
+                    Status::Fail,
+                    format!("{} is unreachable: {}", base_url, e),
+                    fix,
+                ),
+                Check::skipped("clock", "the backend is unreachable"),
+            ]
+        }
+    };
+    let latency = started.elapsed();
+    let server_date = response
+        .headers()
+        .get(reqwest::header::DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| crate::chrono::DateTime::parse_from_rfc2822(date).ok());
+    let tip_height = response.text().unwrap_or_default();
+
+    let mut checks = vec![];
+    let backend = Check::ok(
+        "backend",
+        format!(
+            "{} is at height {} ({}ms)",
+            base_url,
+            tip_height.trim(),
+            latency.as_millis()
+        ),
+    );
+    let backend = match Url::parse(&base_url)
+        .map_err(anyhow::Error::from)
+        .and_then(|url| fetch_cert_fingerprint(&url))
+    {
+        Ok(Some(fingerprint)) => match &config.pinned_cert_sha256 {
+            Some(pinned) if normalize_fingerprint(pinned) != fingerprint => Check::problem(
+                "backend",
+                Status::Fail,
+                format!("the certificate fingerprint {} doesn't match the pinned one", fingerprint),
+                "someone may be intercepting your connection. If the backend renewed its certificate update pinned-cert-sha256",
+            ),
+            _ => backend,
+        },
+        Ok(None) if config.pinned_cert_sha256.is_some() => Check::problem(
+            "backend",
+            Status::Fail,
+            "a certificate is pinned but the backend doesn't use https",
+            "use an https base-url or remove pinned-cert-sha256",
+        ),
+        Ok(None) => backend,
+        Err(e) => Check::problem(
+            "backend",
+            Status::Warn,
+            format!("couldn't get the certificate: {:#}", e),
+            fix,
+        ),
+    };
+    checks.push(backend);
+
+    checks.push(match server_date {
+        _ if crate::clock::is_pinned() => Check::skipped(
+            "clock",
+            format!("it's pinned to {} with --now", crate::clock::now()),
+        ),
+        Some(server_date) => {
+            // the request took some time so the server's clock can be as much as that ahead
+            let skew = crate::chrono::Utc::now().timestamp() - server_date.timestamp();
+            if skew.abs() > MAX_CLOCK_SKEW_SECS + latency.as_secs() as i64 {
+                Check::problem(
+                    "clock",
+                    Status::Warn,
+                    format!("this computer's clock is {}s off the backend's", skew),
+                    "set the time correctly (e.g. turn on NTP) -- bets and offers are checked against it",
+                )
+            } else {
+                Check::ok("clock", format!("within {}s of the backend", skew.abs()))
+            }
+        }
+        None => Check::skipped("clock", "the backend didn't send the time"),
+    });
+
+    checks
+}
+
+fn check_oracles(wallet_dir: &PathBuf) -> Vec<Check> {
+    let bet_db = match load_bet_db(wallet_dir) {
+        Ok(bet_db) => bet_db,
+        Err(e) => return vec![Check::skipped("oracles", format!("{:#}", e))],
+    };
+    let oracles = bet_db
+        .list_entities_print_error::<OracleInfo>()
+        .collect::<Vec<_>>();
+    if oracles.is_empty() {
+        return vec![Check::ok("oracles", "no oracles are trusted")];
+    }
+    let client = match crate::endpoint::http_client()
+        .timeout(Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => return vec![Check::skipped("oracles", e.to_string())],
+    };
+
+    oracles
+        .into_iter()
+        .map(|(oracle_id, oracle_info)| {
+            let root_response = client
+                .get(format!(
+                    "{}://{}",
+                    crate::endpoint::default_scheme(&oracle_id),
+                    oracle_id
+                ))
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|response| response.json::<RootResponse<Secp256k1>>());
+            match root_response {
+                Ok(root_response) if root_response.public_keys == oracle_info.oracle_keys => {
+                    Check::ok("oracles", format!("{} is reachable", oracle_id))
+                }
+                Ok(_) => Check::problem(
+                    "oracles",
+                    Status::Fail,
+                    format!(
+                        "{} is using different keys from the ones we trust",
+                        oracle_id
+                    ),
+                    "don't bet on this oracle until you've found out why from its operator",
+                ),
+                Err(e) => Check::problem(
+                    "oracles",
+                    Status::Warn,
+                    format!("{} is unreachable: {}", oracle_id, e),
+                    "bets on it can't be settled until it comes back. Check your connection",
+                ),
+            }
+        })
+        .collect()
+}
+
+fn check_disk_space(wallet_dir: &PathBuf) -> Check {
+    // there's no portable way to ask for this in std
+    let output = match std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(wallet_dir)
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Check::skipped("disk-space", "couldn't run df"),
+    };
+    let available_kib = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3).map(str::to_string))
+        .and_then(|available| available.parse::<u64>().ok());
+
+    match available_kib {
+        Some(available_kib) if available_kib < MIN_FREE_DISK_KIB => Check::problem(
+            "disk-space",
+            Status::Warn,
+            format!("only {} MiB free", available_kib / 1024),
+            "free up some space -- the database can't be written when the disk is full",
+        ),
+        Some(available_kib) => {
+            Check::ok("disk-space", format!("{} MiB free", available_kib / 1024))
+        }
+        None => Check::skipped("disk-space", "couldn't read the output of df"),
+    }
+}
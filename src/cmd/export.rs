@@ -0,0 +1,163 @@
+use super::*;
+use crate::item;
+use bdk::{bitcoin::Address, KeychainKind};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Export the wallet so other software can use it
+pub enum ExportOpt {
+    /// Write the descriptor, wallet birthday and BIP-329 labels in the formats Sparrow imports.
+    /// Only public keys are exported.
+    Sparrow {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+}
+
+pub fn run_export_cmd(wallet_dir: &PathBuf, opt: ExportOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        ExportOpt::Sparrow { dir } => {
+            let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+            fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+
+            let external = wallet
+                .public_descriptor(KeychainKind::External)?
+                .expect("wallet always has an external descriptor")
+                .to_string();
+            let internal = wallet
+                .public_descriptor(KeychainKind::Internal)?
+                .map(|descriptor| descriptor.to_string());
+            let descriptor = sparrow_descriptor(&external, internal.as_deref())?;
+            let descriptor_file = dir.join("gun-descriptor.txt");
+            fs::write(&descriptor_file, descriptor.as_bytes())?;
+
+            let (birthday_timestamp, birthday_height) = wallet
+                .list_transactions(false)?
+                .into_iter()
+                .filter_map(|tx| tx.confirmation_time)
+                .map(|time| (time.timestamp, time.height))
+                .min()
+                .unwrap_or((crate::clock::timestamp() as u64, 0));
+            let birthday =
+                crate::chrono::NaiveDateTime::from_timestamp(birthday_timestamp as i64, 0);
+            let birthday_file = dir.join("gun-birthday.txt");
+            fs::write(
+                &birthday_file,
+                format!(
+                    "{}\nblock height {}\n",
+                    birthday.date().format("%Y-%m-%d"),
+                    birthday_height
+                ),
+            )?;
+
+            let labels_file = dir.join("gun-labels.jsonl");
+            let labels = bip329_labels(wallet.network(), &bet_db)?
+                .into_iter()
+                .map(|label| serde_json::to_string(&label).unwrap() + "\n")
+                .collect::<String>();
+            fs::write(&labels_file, labels.as_bytes())?;
+
+            eprintln!(
+                "In Sparrow use File > Import Wallet > Output Descriptor with {} then import the labels from {}. Don't scan for transactions before {}.",
+                descriptor_file.display(),
+                labels_file.display(),
+                birthday.date().format("%Y-%m-%d")
+            );
+
+            Ok(item! {
+                "descriptor" => Cell::String(descriptor),
+                "birthday" => Cell::DateTime(birthday_timestamp),
+                "birthday-height" => Cell::Int(birthday_height as u64),
+            })
+        }
+    }
+}
+
+/// Sparrow wants a single descriptor covering receive and change addresses. The checksums on
+/// `external` and `internal` are replaced with ones for what's written.
+fn sparrow_descriptor(external: &str, internal: Option<&str>) -> anyhow::Result<String> {
+    let without_checksum = |descriptor: &str| descriptor.splitn(2, '#').next().unwrap_or("");
+    let external = without_checksum(external);
+    let combined = match internal.map(without_checksum) {
+        Some(internal) if external.replace("/0/*", "/1/*") == internal => {
+            external.replace("/0/*", "/<0;1>/*")
+        }
+        Some(internal) => format!("{}\n{}", external, internal),
+        None => external.to_string(),
+    };
+    let with_checksums = combined
+        .lines()
+        .map(|line| {
+            Ok(format!(
+                "{}#{}",
+                line,
+                bdk::descriptor::get_checksum(line)
+                    .map_err(|e| anyhow!("computing descriptor checksum: {}", e))?
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(with_checksums.join("\n"))
+}
+
+/// Address labels and transaction memos in the BIP-329 format
+pub fn bip329_labels(
+    network: Network,
+    bet_db: &BetDatabase,
+) -> anyhow::Result<Vec<serde_json::Value>> {
+    let mut labels = vec![];
+    for (script, label) in bet_db.address_labels()? {
+        if let Some(address) = Address::from_script(&script, network) {
+            labels.push(serde_json::json!({
+                "type": "addr",
+                "ref": address.to_string(),
+                "label": label,
+            }));
+        }
+    }
+    for (txid, memo) in bet_db.tx_memos()? {
+        labels.push(serde_json::json!({
+            "type": "tx",
+            "ref": txid.to_string(),
+            "label": memo,
+        }));
+    }
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::miniscript::{descriptor::DescriptorPublicKey, Descriptor};
+    use std::str::FromStr;
+
+    const XPUB: &str = "[73c5da0a/84'/0'/0']xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+
+    fn public_descriptor(path: &str) -> String {
+        Descriptor::<DescriptorPublicKey>::from_str(&format!("wpkh({}/{})", XPUB, path))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn sparrow_descriptor_has_one_checksum() {
+        let external = public_descriptor("0/*");
+        let internal = public_descriptor("1/*");
+        assert!(external.contains('#'));
+
+        let combined = sparrow_descriptor(&external, Some(&internal)).unwrap();
+        let body = external
+            .splitn(2, '#')
+            .next()
+            .unwrap()
+            .replace("/0/*", "/<0;1>/*");
+        assert!(body.starts_with("wpkh([73c5da0a/84'/0'/0']xpub"));
+        assert_eq!(
+            combined,
+            format!("{}#{}", body, bdk::descriptor::get_checksum(&body).unwrap())
+        );
+
+        let external_only = sparrow_descriptor(&external, None).unwrap();
+        assert_eq!(external_only, external);
+        assert!(Descriptor::<DescriptorPublicKey>::from_str(&external_only).is_ok());
+    }
+}
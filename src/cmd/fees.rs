@@ -0,0 +1,160 @@
+use super::*;
+use crate::coin_select::{P2WPKH_INPUT_VBYTES, P2WPKH_OUTPUT_VBYTES, TX_OVERHEAD_VBYTES};
+use bdk::{blockchain::AnyBlockchainConfig, FeeRate};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// The confirmation targets (in blocks) to show estimates for.
+const TARGETS: [u32; 8] = [1, 2, 3, 6, 12, 24, 144, 288];
+
+/// Virtual bytes in a block
+const BLOCK_VBYTES: u64 = 1_000_000;
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct FeesOpt {
+    /// The number of p2wpkh inputs in the transaction the fees are worked out for
+    #[structopt(long, default_value = "1")]
+    inputs: usize,
+    /// The number of p2wpkh outputs in the transaction the fees are worked out for
+    #[structopt(long, default_value = "2")]
+    outputs: usize,
+    /// Show how much of the mempool is paying each feerate instead
+    #[structopt(long)]
+    histogram: bool,
+}
+
+pub fn run_fees_cmd(wallet_dir: &PathBuf, opt: FeesOpt) -> anyhow::Result<CmdOutput> {
+    let config = load_config(wallet_dir)?;
+    if opt.histogram {
+        return mempool_histogram(&config);
+    }
+
+    let blockchain = bdk::blockchain::AnyBlockchain::from_config(
+        &crate::pinned_backend::blockchain_config(&config)?,
+    )?;
+    let vbytes = TX_OVERHEAD_VBYTES
+        + opt.inputs as f32 * P2WPKH_INPUT_VBYTES
+        + opt.outputs as f32 * P2WPKH_OUTPUT_VBYTES;
+    let price = match &config.price_source {
+        Some(price_source) => match price_source.fetch() {
+            Ok(price) => Some(price),
+            Err(e) => {
+                eprintln!("couldn't get the price of bitcoin: {:#}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let row = |name: String, feerate: FeeRate| {
+        let fee = Amount::from_sat((feerate.as_sat_vb() * vbytes).ceil() as u64);
+        vec![
+            Cell::String(name),
+            Cell::String(format!("{:.1}", feerate.as_sat_vb())),
+            Cell::Amount(fee),
+            price
+                .as_ref()
+                .map(|price| Cell::String(price.format(fee)))
+                .unwrap_or(Cell::Empty),
+        ]
+    };
+
+    let mut rows = vec![];
+    for target in TARGETS.iter() {
+        let feerate = crate::fee_snapshot::estimate_fee(&blockchain, *target)
+            .with_context(|| format!("estimating the feerate for {} blocks", target))?;
+        rows.push(row(format!("{} blocks", target), feerate));
+    }
+
+    for alias in crate::FeeAlias::ALL.iter() {
+        let blocks = config.fee_aliases.blocks(*alias);
+        let feerate = crate::fee_snapshot::estimate_fee(&blockchain, blocks)
+            .with_context(|| format!("estimating the feerate for {} blocks", blocks))?;
+        rows.push(row(
+            format!("{} ({} blocks)", alias.name(), blocks),
+            feerate,
+        ));
+    }
+
+    let default = FeeSpec::default();
+    if let Some(feerate) = default.feerate(&blockchain)? {
+        rows.push(row(format!("default ({})", default), feerate));
+    }
+
+    eprintln!(
+        "fees are for a transaction with {} input(s) and {} output(s) ({} vbytes)",
+        opt.inputs,
+        opt.outputs,
+        vbytes.ceil()
+    );
+    Ok(CmdOutput::table(
+        vec!["target", "sat/vb", "fee", "fiat"],
+        rows,
+    ))
+}
+
+fn mempool_histogram(config: &Config) -> anyhow::Result<CmdOutput> {
+    let base_url = match crate::pinned_backend::blockchain_config(config)? {
+        AnyBlockchainConfig::Esplora(esplora) => esplora.base_url.trim_end_matches('/').to_string(),
+        #[allow(unreachable_patterns)]
+        _ => return Err(anyhow!("the mempool histogram only works with esplora")),
+    };
+    let histogram = match fetch_histogram(&base_url) {
+        Ok(histogram) => {
+            crate::fee_snapshot::record_histogram(histogram.clone());
+            histogram
+        }
+        Err(e) => match crate::fee_snapshot::FeeSnapshot::load().histogram {
+            Some(cached) => {
+                eprintln!(
+                    "WARNING: couldn't get the mempool from the backend ({:#}) so this is how it was {} ago",
+                    e,
+                    cached.age()
+                );
+                cached.value
+            }
+            None => return Err(e),
+        },
+    };
+
+    // it goes from the highest feerate down so the depth is how far from being mined it is
+    let mut depth = 0;
+    let rows = histogram
+        .into_iter()
+        .map(|(feerate, vsize)| {
+            depth += vsize;
+            vec![
+                Cell::String(format!("{:.1}", feerate)),
+                Cell::Int(vsize),
+                Cell::String(format!("{:.2}", depth as f64 / BLOCK_VBYTES as f64)),
+            ]
+        })
+        .collect();
+
+    Ok(CmdOutput::table(
+        vec!["sat/vb", "vbytes", "depth-blocks"],
+        rows,
+    ))
+}
+
+/// The (sat/vb, vbytes) bands of the backend's mempool from the highest feerate down.
+fn fetch_histogram(base_url: &str) -> anyhow::Result<Vec<(f64, u64)>> {
+    let client = crate::endpoint::http_client()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let response = client
+        .get(format!("{}/mempool", base_url))
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let mempool: serde_json::Value =
+        serde_json::from_str(&response).context("parsing mempool info from backend")?;
+    let histogram = mempool
+        .get("fee_histogram")
+        .and_then(|histogram| histogram.as_array())
+        .ok_or(anyhow!("the backend doesn't provide a fee histogram"))?;
+    Ok(histogram
+        .iter()
+        .filter_map(|band| Some((band.get(0)?.as_f64()?, band.get(1)?.as_u64()?)))
+        .collect())
+}
@@ -1,12 +1,22 @@
-use crate::{cmd, config::Config, item};
+use crate::{
+    cmd,
+    coldcard::ColdcardExport,
+    config::{Config, WalletKind},
+    item,
+    wallet_import::{self, ImportedDescriptors},
+};
 use anyhow::{anyhow, Context};
 use bdk::{
     bitcoin::Network,
+    blockchain::AnyBlockchainConfig,
+    database::MemoryDatabase,
     keys::{
         bip39::{Language, Mnemonic, MnemonicType},
         GeneratableKey, GeneratedKey,
     },
     miniscript::Segwitv0,
+    wallet::AddressIndex,
+    Wallet,
 };
 use cmd::Cell;
 use std::{fs, io, path::PathBuf, str::FromStr};
@@ -16,6 +26,9 @@ use super::CmdOutput;
 
 pub enum NWords {}
 
+/// Electrum's default gap limit
+const IMPORT_STOP_GAP: usize = 20;
+
 #[derive(Clone, Debug, StructOpt)]
 pub struct InitOpt {
     /// The network name (bitcoin|regtest|testnet)
@@ -26,6 +39,27 @@ pub struct InitOpt {
     #[structopt(long, default_value = "12", name = "[12|24]")]
     /// The number of BIP39 seed words to use
     n_words: usize,
+    /// Set up a watch-only wallet from a Coldcard's coldcard-export.json. Transactions are signed
+    /// on the Coldcard with `gun psbt export --coldcard` and `gun psbt import --coldcard`. Seed
+    /// words are still generated for the keys used in bets.
+    #[structopt(long, parse(from_os_str), value_name = "EXPORT_FILE")]
+    coldcard: Option<PathBuf>,
+    /// Use the keys from an unencrypted Electrum wallet file
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "WALLET_FILE",
+        conflicts_with_all = &["coldcard", "from-core-descriptors"]
+    )]
+    from_electrum: Option<PathBuf>,
+    /// Use the descriptors from the JSON output of Bitcoin Core's `listdescriptors`
+    #[structopt(
+        long,
+        parse(from_os_str),
+        value_name = "JSON_FILE",
+        conflicts_with = "coldcard"
+    )]
+    from_core_descriptors: Option<PathBuf>,
 }
 
 pub fn run_init(
@@ -34,8 +68,58 @@ pub fn run_init(
         network,
         n_words,
         from_existing,
+        coldcard,
+        from_electrum,
+        from_core_descriptors,
     }: InitOpt,
 ) -> anyhow::Result<CmdOutput> {
+    let read = |file: &PathBuf| {
+        fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))
+    };
+    let descriptors = match (coldcard, from_electrum, from_core_descriptors) {
+        (Some(export_file), _, _) => {
+            let export = ColdcardExport::from_json(&read(&export_file)?)?;
+            if export.network()? != network {
+                return Err(anyhow!(
+                    "the coldcard export is for {} but you asked for {}",
+                    export.network()?,
+                    network
+                ));
+            }
+            let (external, internal) = export.descriptors()?;
+            check_first_address(&external, network, export.bip84.first.as_deref())?;
+            Some(ImportedDescriptors {
+                external,
+                internal: Some(internal),
+                not_imported: vec![],
+            })
+        }
+        (_, Some(wallet_file), _) => Some(wallet_import::from_electrum(&read(&wallet_file)?)?),
+        (_, _, Some(json_file)) => Some(wallet_import::from_core_descriptors(&read(&json_file)?)?),
+        _ => None,
+    };
+
+    if let Some(descriptors) = &descriptors {
+        // make sure it parses for this network before creating anything
+        Wallet::new_offline(
+            descriptors.external.as_str(),
+            descriptors.internal.as_deref(),
+            network,
+            MemoryDatabase::default(),
+        )
+        .context("the imported descriptors are not valid for this network")?;
+        if descriptors.has_private_keys() {
+            eprintln!("WARNING: the imported wallet has private keys. They will be stored UNENCRYPTED in config.json in {} -- anyone who can read that file can take the coins.", wallet_dir.display());
+        }
+        for descriptor in &descriptors.not_imported {
+            eprintln!(
+                "WARNING: not importing descriptor {} -- coins sent to it won't show up in gun",
+                descriptor
+            );
+        }
+    }
+
+    let from_existing_words = from_existing.is_some();
     let seed_words = match from_existing {
         Some(existing_words_file) => {
             let words = match existing_words_file.as_str() {
@@ -57,15 +141,44 @@ pub fn run_init(
             Mnemonic::validate(&words, Language::English).context("parsing existing seedwords")?;
             words
         }
-        None => {
-            let n_words = MnemonicType::for_word_count(n_words)?;
-            let seed_words: GeneratedKey<_, Segwitv0> =
-                Mnemonic::generate((n_words, Language::English))
-                    .map_err(|_| anyhow!("generating seed phrase failed"))?;
-            seed_words.phrase().into()
-        }
+        None => generate_seed_words(n_words)?,
     };
 
+    let mut config = Config::default_config(network);
+    if let Some(ImportedDescriptors {
+        external, internal, ..
+    }) = descriptors
+    {
+        config.kind = WalletKind::Descriptor { external, internal };
+        // so the whole history of the imported wallet is found on the first sync
+        if let AnyBlockchainConfig::Esplora(esplora) = &mut config.blockchain {
+            esplora.stop_gap = esplora.stop_gap.max(IMPORT_STOP_GAP);
+        }
+    }
+    let sw_file = create_wallet_dir(wallet_dir, &config, &seed_words)?;
+
+    eprintln!("Wrote seeds words to {}", sw_file.display());
+    if from_existing_words {
+        eprintln!("If these seed words were used in other wallets run `gun scan-paths` to look for coins on the derivation paths they use.");
+    }
+    println!("==== BIP39 seed words ====");
+
+    Ok(item! { "seed_words" => Cell::String(seed_words)})
+}
+
+pub(crate) fn generate_seed_words(n_words: usize) -> anyhow::Result<String> {
+    let n_words = MnemonicType::for_word_count(n_words)?;
+    let seed_words: GeneratedKey<_, Segwitv0> = Mnemonic::generate((n_words, Language::English))
+        .map_err(|_| anyhow!("generating seed phrase failed"))?;
+    Ok(seed_words.phrase().into())
+}
+
+/// Makes a new wallet directory with `config` and `seed_words` in it. Returns the seed words file.
+pub(crate) fn create_wallet_dir(
+    wallet_dir: &PathBuf,
+    config: &Config,
+    seed_words: &str,
+) -> anyhow::Result<PathBuf> {
     if wallet_dir.exists() {
         return Err(anyhow!(
             "wallet directory {} already exists -- delete it to create a new wallet",
@@ -75,23 +188,36 @@ pub fn run_init(
 
     std::fs::create_dir(&wallet_dir)?;
 
-    {
-        let mut config_file = wallet_dir.clone();
-        config_file.push("config.json");
-
-        let config = Config::default_config(network);
-        fs::write(
-            config_file,
-            serde_json::to_string_pretty(&config).unwrap().as_bytes(),
-        )?;
-    }
+    let mut config_file = wallet_dir.clone();
+    config_file.push("config.json");
+    fs::write(
+        config_file,
+        serde_json::to_string_pretty(config).unwrap().as_bytes(),
+    )?;
 
     let sw_file = cmd::get_seed_words_file(wallet_dir);
+    fs::write(sw_file.clone(), seed_words)?;
+    Ok(sw_file)
+}
 
-    fs::write(sw_file.clone(), seed_words.clone())?;
-
-    eprintln!("Wrote seeds words to {}", sw_file.display());
-    println!("==== BIP39 seed words ====");
-
-    Ok(item! { "seed_words" => Cell::String(seed_words)})
+fn check_first_address(
+    descriptor: &str,
+    network: Network,
+    expected: Option<&str>,
+) -> anyhow::Result<()> {
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Ok(()),
+    };
+    let wallet = Wallet::new_offline(descriptor, None, network, MemoryDatabase::default())
+        .context("loading coldcard descriptor")?;
+    let first = wallet.get_address(AddressIndex::New)?;
+    if first.address.to_string() != expected {
+        return Err(anyhow!(
+            "the first address derived from the coldcard export is {} but the coldcard says it should be {}",
+            first.address,
+            expected
+        ));
+    }
+    Ok(())
 }
@@ -1,6 +1,33 @@
+mod allowance;
+mod approval;
+mod audit;
+mod backend;
+mod backup;
+mod broadcast_queue;
+mod bump_all;
+mod cold_storage;
+mod config;
+mod db;
+mod dev;
+mod doctor;
+mod export;
+mod fees;
 mod init;
+mod open;
 mod oracle;
+mod psbt;
+mod rotate;
+mod schedule;
+#[cfg(feature = "betting")]
+mod selftest;
+mod send_review;
+mod session;
+mod state;
+mod sweep;
+mod undo;
 mod wallet;
+mod watch;
+mod whoami;
 use anyhow::Context;
 use bdk::{
     bitcoin::{
@@ -13,19 +40,53 @@ use bdk::{
     sled, Wallet,
 };
 
+pub use allowance::*;
+pub use approval::*;
+pub use audit::*;
+pub use backend::*;
+pub use backup::*;
+pub use broadcast_queue::*;
+pub use bump_all::*;
+pub use cold_storage::*;
+pub use config::*;
+pub use db::*;
+pub use dev::*;
+pub use doctor::*;
+pub use export::*;
+pub use fees::*;
 pub use init::*;
+pub use open::*;
+#[cfg(feature = "betting")]
 pub mod bet;
+#[cfg(feature = "betting")]
 pub use bet::*;
 pub use oracle::*;
+pub use psbt::*;
+pub use rotate::*;
+pub use schedule::*;
+#[cfg(feature = "betting")]
+pub use selftest::*;
+pub use send_review::*;
+pub use session::*;
+pub use state::*;
+pub use sweep::*;
 use term_table::{row::Row, Table};
+pub use undo::*;
 pub use wallet::*;
+pub use watch::*;
+pub use whoami::*;
 
 use crate::{
-    betting::{BetDatabase, Party},
+    audit::{AuditLog, AuditOperation},
+    betting::{BetDatabase, Party, PartySettings},
+    broadcast_queue::{BroadcastFailure, QueuedBroadcast},
     chrono::NaiveDateTime,
     config::Config,
+    exit_code::ErrorKind,
+    i18n::{tr, tr_args},
     keychain::Keychain,
     psbt_ext::PsbtFeeRate,
+    tx_size::{format_vbytes, TxSize},
     FeeSpec, ValueChoice,
 };
 use anyhow::anyhow;
@@ -34,11 +95,100 @@ use std::{collections::HashMap, fs, path::PathBuf};
 #[derive(Clone, Debug, structopt::StructOpt)]
 pub struct FeeArgs {
     /// The transaction fee to attach e.g. rate:4.5 (4.5 sats-per-byte), abs:300 (300 sats absolute
-    /// fee), in-blocks:3 (set fee so that it is included in the next three blocks).
+    /// fee), in-blocks:3 (set fee so that it is included in the next three blocks) or one of
+    /// fastest, hour and economy. When canceling bets it can also be relative to the fee the bet
+    /// paid e.g. bump:+25% or bump:+5sat/vb.
     #[structopt(default_value, long)]
     fee: FeeSpec,
 }
 
+impl FeeArgs {
+    /// The fee asked for with any alias (e.g. `hour`) meaning what the config says.
+    pub fn fee_spec(&self, settings: &PartySettings) -> FeeSpec {
+        self.fee.clone().with_aliases(&settings.fee_aliases)
+    }
+}
+
+#[derive(Clone, Debug, structopt::StructOpt)]
+pub struct RbfArgs {
+    /// Signal that the transaction can be replaced to bump its fee (BIP125)
+    #[structopt(long, conflicts_with = "no-rbf")]
+    rbf: bool,
+    /// Don't signal that the transaction can be replaced
+    #[structopt(long)]
+    no_rbf: bool,
+}
+
+impl RbfArgs {
+    /// Whether to signal RBF. `default` is used if neither flag was given.
+    pub fn signal(&self, default: bool) -> bool {
+        match (self.rbf, self.no_rbf) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Clone, Debug, structopt::StructOpt)]
+pub struct QrArgs {
+    /// How big to draw the QR code: small (half blocks) or large (easier to scan from far away)
+    #[structopt(long, default_value = "small")]
+    size: crate::qr::QrSize,
+    /// Draw the dark parts with blocks for terminals with a light background
+    #[structopt(long)]
+    invert: bool,
+}
+
+impl QrArgs {
+    pub fn style(&self) -> crate::qr::QrStyle {
+        crate::qr::QrStyle {
+            size: self.size,
+            invert: self.invert,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, structopt::StructOpt)]
+pub struct PageArgs {
+    /// Show at most this many
+    #[structopt(long)]
+    limit: Option<usize>,
+    /// Skip this many first
+    #[structopt(long, default_value = "0")]
+    offset: usize,
+}
+
+impl PageArgs {
+    /// Only the items of `items` that are on the page.
+    pub fn page<I: Iterator>(&self, items: I) -> std::iter::Take<std::iter::Skip<I>> {
+        items
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+    }
+
+    /// Says where the page is if it isn't everything there is. `total` is how many there are
+    /// altogether.
+    pub fn note_position(&self, total: usize) {
+        let shown = total
+            .saturating_sub(self.offset)
+            .min(self.limit.unwrap_or(usize::MAX));
+        if shown == total {
+            return;
+        }
+        match self.offset + shown < total {
+            true => eprintln!(
+                "showing {} of {} from {}. Use --offset {} for the next ones.",
+                shown,
+                total,
+                self.offset,
+                self.offset + shown
+            ),
+            false => eprintln!("showing {} of {} from {}", shown, total, self.offset),
+        }
+    }
+}
+
 pub enum FeeChoice {
     /// Pay an absolute fee
     Absolute(Amount),
@@ -49,39 +199,100 @@ pub enum FeeChoice {
 }
 
 pub fn load_config(wallet_dir: &PathBuf) -> anyhow::Result<Config> {
+    if let Some(config) = crate::ephemeral::config() {
+        return Ok(config);
+    }
     let mut config_file = wallet_dir.clone();
     config_file.push("config.json");
 
     match config_file.exists() {
         true => {
+            let invalid = |e: serde_json::Error| {
+                ErrorKind::Config.wrap(
+                    anyhow::Error::new(e)
+                        .context(format!("{} isn't a valid config", config_file.display())),
+                )
+            };
             let json_config = fs::read_to_string(config_file.clone())?;
-            Ok(serde_json::from_str::<Config>(&json_config)?)
+            let mut json_config =
+                serde_json::from_str::<serde_json::Value>(&json_config).map_err(invalid)?;
+            let chose_backend = fill_in_default_backend(&mut json_config)?;
+            let config = serde_json::from_value::<Config>(json_config).map_err(invalid)?;
+            if chose_backend {
+                eprintln!(
+                    "No backend is configured so {} will be used (see `gun backend`)",
+                    backend_url(&config.blockchain)
+                );
+                // so the choice is only made and announced once
+                if let Err(e) = write_config(wallet_dir, &config) {
+                    tracing::warn!("couldn't save the backend choice: {}", e);
+                }
+            }
+            Ok(config)
         }
         false => {
-            return Err(anyhow!(
+            return Err(ErrorKind::Config.error(format!(
                 "missing config file at {}",
                 config_file.as_path().display()
-            ))
+            )))
         }
     }
 }
 
+pub fn write_config(wallet_dir: &PathBuf, config: &Config) -> anyhow::Result<()> {
+    if crate::ephemeral::set_config(config) {
+        return Ok(());
+    }
+    fs::write(
+        wallet_dir.join("config.json"),
+        serde_json::to_string_pretty(config).unwrap().as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Reads a passphrase from `env_var` or if it isn't set asks for it on stdin.
+pub fn read_passphrase(env_var: &str, prompt: &str, confirm: bool) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(env_var) {
+        return Ok(passphrase);
+    }
+    let read_line = |prompt: &str| -> anyhow::Result<String> {
+        use std::io::BufRead;
+        let prompt = format!("{}: ", tr(prompt));
+        // it isn't echoed when there's a terminal to read it from
+        if let Ok(passphrase) = rpassword::read_password_from_tty(Some(&prompt)) {
+            return Ok(passphrase);
+        }
+        eprint!("{}", prompt);
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+    };
+    let passphrase = read_line(prompt)?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("the passphrase can't be empty"));
+    }
+    if confirm && read_line("repeat passphrase")? != passphrase {
+        return Err(anyhow!("the passphrases didn't match"));
+    }
+    Ok(passphrase)
+}
+
+/// Asks a yes or no question in the user's language until it gets one of the answers the language
+/// accepts (see [`crate::i18n::Answers`]).
 pub fn read_answer(question: &str) -> bool {
     use std::io::{self, BufRead};
+    let answers = crate::i18n::locale().answers();
     let stdin = io::stdin();
     let mut lines = stdin.lock().lines();
-    println!("{} [y/n]?", question);
+    println!("{} [{}]?", tr(question), answers.hint);
     lines
-        .find_map(
-            |line| match line.unwrap().trim_end().to_lowercase().as_str() {
-                "y" => Some(true),
-                "n" => Some(false),
-                _ => {
-                    println!("[y/n]?");
-                    None
-                }
-            },
-        )
+        .find_map(|line| match answers.parse(&line.unwrap()) {
+            Some(answer) => Some(answer),
+            None => {
+                println!("[{}]?", answers.hint);
+                None
+            }
+        })
         .unwrap_or(false)
 }
 
@@ -91,10 +302,18 @@ pub fn get_seed_words_file(wallet_dir: &PathBuf) -> PathBuf {
     seed_words_file
 }
 
-pub fn load_bet_db(wallet_dir: &PathBuf) -> anyhow::Result<BetDatabase> {
+/// The wallet's database or the temporary one in ephemeral mode.
+fn open_database(wallet_dir: &PathBuf) -> anyhow::Result<sled::Db> {
+    if let Some(database) = crate::ephemeral::database() {
+        return Ok(database);
+    }
     let mut db_file = wallet_dir.clone();
     db_file.push("database.sled");
-    let database = sled::open(db_file.to_str().unwrap())?;
+    sled::open(db_file.to_str().unwrap()).context("opening database.sled")
+}
+
+pub fn load_bet_db(wallet_dir: &PathBuf) -> anyhow::Result<BetDatabase> {
+    let database = open_database(wallet_dir)?;
     let bet_db = BetDatabase::new(database.open_tree("bets")?);
     Ok(bet_db)
 }
@@ -103,10 +322,39 @@ pub fn load_party(
     wallet_dir: &PathBuf,
 ) -> anyhow::Result<Party<bdk::blockchain::EsploraBlockchain, impl bdk::database::BatchDatabase>> {
     let (wallet, bet_db, keychain, config) = load_wallet(wallet_dir).context("loading wallet")?;
-    let party = Party::new(wallet, bet_db, keychain, config.blockchain);
+    let mut settings = config.party_settings();
+    if config.spending_lock.is_some() {
+        settings.spending_lock_dir = Some(wallet_dir.clone());
+    }
+    let audit_log = AuditLog::new(wallet_dir, config.network);
+    let blockchain = crate::pinned_backend::blockchain_config(&config)?;
+    let party = Party::new(wallet, bet_db, keychain, blockchain)
+        .with_settings(settings)
+        .with_audit_log(audit_log);
     Ok(party)
 }
 
+/// The wallet's seed words. If they're locked with the spending passphrase they come from the agent
+/// so the wallet has to be unlocked (see [`crate::session`]).
+pub fn read_seed_words(wallet_dir: &PathBuf) -> anyhow::Result<zeroize::Zeroizing<String>> {
+    if crate::session::locked_seed_file(wallet_dir).exists() {
+        return crate::session::seed_words(wallet_dir);
+    }
+    let seed_words =
+        fs::read_to_string(get_seed_words_file(wallet_dir)).context("loading seed words")?;
+    Ok(zeroize::Zeroizing::new(seed_words))
+}
+
+pub fn keychain_from_seed_words(seed_words: &str) -> anyhow::Result<Keychain> {
+    use bdk::keys::bip39::{Language, Mnemonic, Seed};
+    let mnemonic = Mnemonic::from_phrase(seed_words, Language::English)
+        .map_err(|e| anyhow!("invalid seed words: {}", e))?;
+    let mut seed_bytes = [0u8; 64];
+    let seed = Seed::new(&mnemonic, "");
+    seed_bytes.copy_from_slice(seed.as_bytes());
+    Ok(Keychain::new(seed_bytes))
+}
+
 pub fn load_wallet(
     wallet_dir: &PathBuf,
 ) -> anyhow::Result<(
@@ -115,71 +363,152 @@ pub fn load_wallet(
     Keychain,
     Config,
 )> {
-    use bdk::keys::bip39::{Language, Mnemonic, Seed};
+    use bdk::descriptor::IntoWalletDescriptor;
 
-    if !wallet_dir.exists() {
-        return Err(anyhow!(
+    if !wallet_dir.exists() && !crate::ephemeral::is_enabled() {
+        return Err(ErrorKind::NotFound.error(format!(
             "No wallet found at {}. Run `gun init` to set a new one up or set --gun-dir.",
             wallet_dir.as_path().display()
-        ));
+        )));
     }
 
     let config = load_config(&wallet_dir).context("loading configuration")?;
     let keychain = match config.keys {
+        crate::config::WalletKeys::SeedWordsFile if crate::ephemeral::is_enabled() => {
+            keychain_from_seed_words(&crate::ephemeral::seed_words().unwrap_or_default())
+                .context("parsing the ephemeral wallet's seed words")?
+        }
         crate::config::WalletKeys::SeedWordsFile => {
-            let sw_file = get_seed_words_file(&wallet_dir);
-            let seed_words = fs::read_to_string(sw_file.clone()).context("loading seed words")?;
-            let mnemonic = Mnemonic::from_phrase(&seed_words, Language::English).map_err(|e| {
-                anyhow!(
-                    "parsing seed phrase in '{}' failed: {}",
-                    sw_file.as_path().display(),
-                    e
+            let seed_words = read_seed_words(&wallet_dir)?;
+            keychain_from_seed_words(&seed_words).with_context(|| {
+                format!(
+                    "parsing the seed words of the wallet in '{}'",
+                    wallet_dir.as_path().display()
                 )
-            })?;
-            let mut seed_bytes = [0u8; 64];
-            let seed = Seed::new(&mnemonic, "");
-            seed_bytes.copy_from_slice(seed.as_bytes());
-            Keychain::new(seed_bytes)
+            })?
         }
     };
-    let database = {
-        let mut db_file = wallet_dir.clone();
-        db_file.push("database.sled");
-        sled::open(db_file.to_str().unwrap()).context("opening database.sled")?
-    };
+    let database = open_database(wallet_dir)?;
 
     let wallet = {
         let wallet_db = database
             .open_tree("wallet")
             .context("opening wallet tree")?;
 
-        let (external_descriptor, internal_descriptor) = match config.kind {
+        let secp = bdk::bitcoin::secp256k1::Secp256k1::new();
+        let (external_descriptor, internal_descriptor) = match &config.kind {
             crate::config::WalletKind::P2wpkh => (
                 bdk::template::Bip84(
                     keychain.main_wallet_xprv(config.network),
                     bdk::KeychainKind::External,
-                ),
-                bdk::template::Bip84(
-                    keychain.main_wallet_xprv(config.network),
-                    bdk::KeychainKind::Internal,
+                )
+                .into_wallet_descriptor(&secp, config.network)?,
+                Some(
+                    bdk::template::Bip84(
+                        keychain.main_wallet_xprv(config.network),
+                        bdk::KeychainKind::Internal,
+                    )
+                    .into_wallet_descriptor(&secp, config.network)?,
                 ),
             ),
+            crate::config::WalletKind::Descriptor { external, internal } => (
+                external
+                    .as_str()
+                    .into_wallet_descriptor(&secp, config.network)
+                    .context("parsing external descriptor")?,
+                internal
+                    .as_ref()
+                    .map(|internal| {
+                        internal
+                            .as_str()
+                            .into_wallet_descriptor(&secp, config.network)
+                            .context("parsing internal descriptor")
+                    })
+                    .transpose()?,
+            ),
         };
 
-        let esplora = match AnyBlockchain::from_config(&config.blockchain)? {
+        let esplora = match AnyBlockchain::from_config(&crate::pinned_backend::blockchain_config(
+            &config,
+        )?)? {
             AnyBlockchain::Esplora(esplora) => esplora,
             #[allow(unreachable_patterns)]
             _ => return Err(anyhow!("A the moment only esplora is supported")),
         };
 
-        Wallet::new(
+        let mut wallet = Wallet::new(
             external_descriptor,
-            Some(internal_descriptor),
+            internal_descriptor,
             config.network,
-            wallet_db,
+            wallet_db.clone(),
             esplora,
         )
-        .context("Initializing wallet failed")?
+        .context("Initializing wallet failed")?;
+
+        for plugin_signer in crate::plugin::load_plugin_signers(wallet_dir, &config)
+            .context("loading signer plugins")?
+        {
+            wallet.add_signer(
+                plugin_signer.keychain,
+                plugin_signer.ordering,
+                plugin_signer.signer,
+            );
+        }
+
+        if config.spending_lock.is_some() {
+            // it goes first so none of the real signers get to sign while the wallet is locked
+            for keychain in [bdk::KeychainKind::External, bdk::KeychainKind::Internal] {
+                wallet.add_signer(
+                    keychain,
+                    bdk::signer::SignerOrdering(0),
+                    std::sync::Arc::new(crate::session::SessionSigner {
+                        wallet_dir: wallet_dir.clone(),
+                    }),
+                );
+            }
+        }
+
+        if let Some(policy) = &config.approval {
+            // it goes first too so nothing sending more than the threshold gets signed unless
+            // it was approved
+            for keychain in [bdk::KeychainKind::External, bdk::KeychainKind::Internal] {
+                wallet.add_signer(
+                    keychain,
+                    bdk::signer::SignerOrdering(0),
+                    std::sync::Arc::new(crate::approval::ApprovalSigner {
+                        policy: policy.clone(),
+                        database: wallet_db.clone(),
+                    }),
+                );
+            }
+        }
+
+        if crate::read_only::is_enabled() || config.read_only {
+            crate::read_only::enable();
+            // it goes first so none of the real signers get to sign
+            wallet.add_signer(
+                bdk::KeychainKind::External,
+                bdk::signer::SignerOrdering(0),
+                std::sync::Arc::new(crate::read_only::ReadOnlySigner),
+            );
+            wallet.add_signer(
+                bdk::KeychainKind::Internal,
+                bdk::signer::SignerOrdering(0),
+                std::sync::Arc::new(crate::read_only::ReadOnlySigner),
+            );
+        }
+
+        if let Some(command) = &config.external_signer {
+            let external_signer = crate::external_signer::ExternalSigner::new(command.clone())?;
+            // it goes last so it sees all the other signatures and we can check what it did
+            wallet.add_signer(
+                bdk::KeychainKind::External,
+                bdk::signer::SignerOrdering(usize::MAX),
+                std::sync::Arc::new(external_signer),
+            );
+        }
+
+        wallet
     };
 
     let bet_db = BetDatabase::new(database.open_tree("bets").context("opening bets tree")?);
@@ -188,11 +517,7 @@ pub fn load_wallet(
 }
 
 pub fn load_wallet_db(wallet_dir: &PathBuf) -> anyhow::Result<impl BatchDatabase> {
-    let database = {
-        let mut db_file = wallet_dir.clone();
-        db_file.push("database.sled");
-        sled::open(db_file.to_str().unwrap()).context("opening database.sled")?
-    };
+    let database = open_database(wallet_dir)?;
 
     Ok(database
         .open_tree("wallet")
@@ -221,15 +546,9 @@ impl From<String> for Cell {
     }
 }
 
+/// Formats `amount` in the display unit (see [`crate::amount_ext::set_display_unit`]).
 pub fn format_amount(amount: Amount) -> String {
-    if amount == Amount::ZERO {
-        "0".to_string()
-    } else {
-        let mut string = amount.to_string();
-        string.insert(string.len() - 7, ' ');
-        string.insert(string.len() - 11, ' ');
-        string.trim_end_matches(" BTC").to_string()
-    }
+    crate::amount_ext::display_unit().format(amount)
 }
 
 impl Cell {
@@ -316,13 +635,82 @@ impl CmdOutput {
         })
     }
 
+    /// The main field of an [`CmdOutput::EmphasisedItem`] (e.g. an address or proposal) which is
+    /// what `--copy` copies and `--qr` shows as a QR code.
+    pub fn main_value(&self) -> Option<String> {
+        match self {
+            CmdOutput::EmphasisedItem {
+                main: (_, Cell::String(value)),
+                ..
+            } => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    /// Puts a link to the block explorer under every txid and address in columns and fields named
+    /// after them (e.g. `txid`, `claim-txid` and `address`).
+    pub fn with_explorer_links(self, config: &Config) -> Self {
+        use CmdOutput::*;
+        let link_cell = |name: &str, cell: Cell| -> Cell {
+            let kind = if name.contains("txid") {
+                "tx"
+            } else if name.contains("address") {
+                "address"
+            } else {
+                return cell;
+            };
+            match cell {
+                Cell::String(id) => match config.explorer_link(kind, &id) {
+                    Some(link) => Cell::List(vec![
+                        Box::new(Cell::String(id)),
+                        Box::new(Cell::String(link)),
+                    ]),
+                    None => Cell::String(id),
+                },
+                cell => cell,
+            }
+        };
+        match self {
+            Table(TableData { col_names, rows }) => {
+                let rows = rows
+                    .into_iter()
+                    .map(|row| {
+                        row.into_iter()
+                            .enumerate()
+                            .map(|(i, cell)| link_cell(&col_names[i], cell))
+                            .collect()
+                    })
+                    .collect();
+                Table(TableData { col_names, rows })
+            }
+            Item(item) => Item(
+                item.into_iter()
+                    .map(|(key, cell)| (key, link_cell(key, cell)))
+                    .collect(),
+            ),
+            EmphasisedItem { main, other } => EmphasisedItem {
+                main: (main.0, link_cell(main.0, main.1)),
+                other: other
+                    .into_iter()
+                    .map(|(key, cell)| (key, link_cell(key, cell)))
+                    .collect(),
+            },
+            output => output,
+        }
+    }
+
     pub fn render(self) -> Option<String> {
         use CmdOutput::*;
 
         Some(match self {
             Table(table_data) => {
                 let mut table = term_table::Table::new();
-                table.add_row(Row::new(table_data.col_names.to_vec()));
+                table.add_row(Row::new(
+                    table_data
+                        .col_names
+                        .iter()
+                        .map(|col_name| tr(col_name).to_string()),
+                ));
                 for row in table_data.rows.into_iter() {
                     table.add_row(Row::new(row.into_iter().map(Cell::render)));
                 }
@@ -333,9 +721,9 @@ impl CmdOutput {
                 let mut table = term_table::Table::new();
                 for (key, value) in item {
                     if matches!(value, Cell::Amount(_)) {
-                        table.add_row(Row::new(vec![format!("{} (BTC)", key), value.render()]))
+                        table.add_row(Row::new(vec![format!("{} (BTC)", tr(key)), value.render()]))
                     } else {
-                        table.add_row(Row::new(vec![key.to_string(), value.render()]))
+                        table.add_row(Row::new(vec![tr(key).to_string(), value.render()]))
                     }
                 }
                 table.render()
@@ -424,7 +812,11 @@ impl CmdOutput {
     }
 }
 
-pub fn display_psbt(network: Network, psbt: &Psbt) -> String {
+pub fn display_psbt<B, D: BatchDatabase>(wallet: &Wallet<B, D>, psbt: &Psbt) -> String {
+    render_psbt(wallet.network(), psbt, &TxSize::for_wallet(wallet, psbt))
+}
+
+fn render_psbt(network: Network, psbt: &Psbt, size: &TxSize) -> String {
     let mut table = Table::new();
     let mut header = Some("in".to_string());
     let mut input_total = Amount::ZERO;
@@ -439,6 +831,7 @@ pub fn display_psbt(network: Network, psbt: &Psbt) -> String {
             header.take().unwrap_or("".to_string()),
             input.previous_output.to_string(),
             format_amount(value),
+            format_vbytes(size.input_weights[i]),
         ]));
         input_total += value;
     }
@@ -446,6 +839,7 @@ pub fn display_psbt(network: Network, psbt: &Psbt) -> String {
         "".to_string(),
         "total".into(),
         format_amount(input_total),
+        "".to_string(),
     ]));
 
     let mut output_total = Amount::ZERO;
@@ -460,6 +854,7 @@ pub fn display_psbt(network: Network, psbt: &Psbt) -> String {
             header.take().unwrap_or("".to_string()),
             address,
             format_amount(value),
+            format_vbytes(size.output_weights[i]),
         ]));
         output_total += value;
     }
@@ -468,43 +863,85 @@ pub fn display_psbt(network: Network, psbt: &Psbt) -> String {
         "".to_string(),
         "total".into(),
         format_amount(output_total),
+        "".to_string(),
     ]));
-    let (fee, feerate) = psbt.fee();
+    let (fee, _) = psbt.fee();
 
     table.add_row(Row::new(vec![
-        "fee",
-        &format!("{} sats/vb", feerate.as_sat_vb()),
-        &format_amount(fee),
+        "fee".to_string(),
+        format!("{:.2} sats/vb", fee.as_sat() as f32 / size.vsize() as f32),
+        format_amount(fee),
+        "".to_string(),
+    ]));
+    table.add_row(Row::new(vec![
+        "size".to_string(),
+        size.summary(),
+        "".to_string(),
+        format!("{} overhead", format_vbytes(size.overhead_weight)),
     ]));
 
     table.render()
 }
 
+/// Asks whether to broadcast `psbt` and does so. What is broadcast is recorded in the audit log (if
+/// there is one) under `context` first. If the backend won't take it yet it's put in the broadcast
+/// queue of `bet_db` to be tried again later (see [`crate::broadcast_queue`]).
+#[allow(clippy::too_many_arguments)]
 pub fn decide_to_broadcast(
     network: Network,
     blockchain: &impl bdk::blockchain::Broadcast,
     psbt: Psbt,
     yes: bool,
     print_tx: bool,
+    audit_log: Option<&AuditLog>,
+    bet_db: &BetDatabase,
+    context: &str,
 ) -> anyhow::Result<(CmdOutput, Option<Txid>)> {
     use crate::item;
+    // it's finalized so nothing has to be estimated
+    let size = TxSize::of(&psbt, |_| None);
     if yes
-        || read_answer(&format!(
-            "This is the transaction that will be broadcast. Ok?\n{}",
-            display_psbt(network, &psbt)
+        || read_answer(&tr_args(
+            "This is the transaction that will be broadcast. Ok?\n{tx}",
+            &[("tx", &render_psbt(network, &psbt, &size))],
         ))
     {
-        let tx = psbt.extract_tx();
+        let tx = psbt.clone().extract_tx();
 
         if print_tx {
             Ok((
-                item! { "tx" => Cell::String(crate::hex::encode(&encode::serialize(&tx))) },
+                item! {
+                    "tx" => Cell::String(crate::hex::encode(&encode::serialize(&tx))),
+                    "vsize" => Cell::Int(size.vsize()),
+                    "weight" => Cell::Int(size.weight() as u64),
+                },
                 Some(tx.txid()),
             ))
         } else {
-            use bdk::blockchain::Broadcast;
             let txid = tx.txid();
-            Broadcast::broadcast(blockchain, tx)?;
+            crate::read_only::check("broadcast")?;
+            if let Some(audit_log) = audit_log {
+                audit_log
+                    .record_psbt(AuditOperation::Broadcast, context, &psbt)
+                    .context("recording the broadcast in the audit log")?;
+            }
+            if let Err(e) = crate::read_only::broadcast(blockchain, tx) {
+                if BroadcastFailure::of(&e) != BroadcastFailure::TryLater {
+                    return Err(e);
+                }
+                bet_db.queue_broadcast(psbt, QueuedBroadcast::new(context, &e))?;
+                eprintln!(
+                    "{} couldn't be broadcast ({:#}) so it will be tried again later. See `gun tx queue`.",
+                    txid, e
+                );
+                return Ok((
+                    item! {
+                        "txid" => Cell::string(txid),
+                        "queued" => Cell::string("yes"),
+                    },
+                    Some(txid),
+                ));
+            }
             Ok((item! { "txid" => Cell::string(txid)}, Some(txid)))
         }
     } else {
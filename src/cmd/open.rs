@@ -0,0 +1,46 @@
+use super::*;
+use crate::item;
+use std::str::FromStr;
+
+/// Opens the page of a transaction or address on the block explorer (see `explorer` and
+/// `explorer-url` in the config).
+pub fn run_open(wallet_dir: &PathBuf, id: String) -> anyhow::Result<CmdOutput> {
+    let config = load_config(wallet_dir)?;
+    let kind = if Txid::from_str(&id).is_ok() {
+        "tx"
+    } else if let Ok(address) = Address::from_str(&id) {
+        if !crate::address::on_network(&address, config.network) {
+            return Err(anyhow!(
+                "{} is a {} address but this wallet is on {}",
+                address,
+                address.network,
+                config.network
+            ));
+        }
+        "address"
+    } else {
+        return Err(anyhow!("{} isn't a txid or an address", id));
+    };
+    let link = config.explorer_link(kind, &id).ok_or(anyhow!(
+        "there's no block explorer for {} -- set explorer-url in the config",
+        config.network
+    ))?;
+
+    let opener = if cfg!(target_os = "macos") {
+        vec!["open"]
+    } else if cfg!(target_os = "windows") {
+        vec!["cmd", "/C", "start", ""]
+    } else {
+        vec!["xdg-open"]
+    };
+    let status = std::process::Command::new(opener[0])
+        .args(&opener[1..])
+        .arg(&link)
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("{} exited with {}", opener[0], status),
+        Err(e) => eprintln!("couldn't run {}: {}", opener[0], e),
+    }
+    Ok(item! { "link" => Cell::String(link) })
+}
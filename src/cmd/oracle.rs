@@ -1,10 +1,11 @@
 use crate::{
-    betting::{BetDatabase, OracleInfo},
-    cmd, item, reqwest, Url,
+    betting::{BetDatabase, EquivocationProof, OracleInfo, SeenAttestations},
+    cmd,
+    event_source::EventSources,
+    item,
 };
 use anyhow::anyhow;
-use olivia_core::{http::RootResponse, OracleId};
-use olivia_secp256k1::Secp256k1;
+use olivia_core::OracleId;
 use std::str::FromStr;
 
 use super::{Cell, CmdOutput};
@@ -32,13 +33,22 @@ pub enum OracleOpt {
         /// The oracle's id
         oracle_id: OracleId,
     },
+    /// Print a proof (as JSON) that an oracle attested to two different outcomes of an event.
+    /// This only works if gun has seen both attestations.
+    ProveEquivocation {
+        /// The event's url e.g. https://h00.ooo/random/2021-08-22T00:00:00/heads_tails.winner
+        event: String,
+    },
 }
 
-pub fn run_oralce_cmd(bet_db: BetDatabase, cmd: OracleOpt) -> anyhow::Result<CmdOutput> {
+pub fn run_oralce_cmd(
+    bet_db: BetDatabase,
+    oracle_sources: &EventSources,
+    cmd: OracleOpt,
+) -> anyhow::Result<CmdOutput> {
     match cmd {
         OracleOpt::Add { url, yes } => {
-            let url =
-                Url::from_str(&url).or_else(|_| Url::from_str(&format!("https://{}", url)))?;
+            let url = crate::endpoint::parse_endpoint(&url)?;
             let oracle_id = url
                 .host()
                 .ok_or(anyhow!("orcale url missing host"))?
@@ -46,9 +56,7 @@ pub fn run_oralce_cmd(bet_db: BetDatabase, cmd: OracleOpt) -> anyhow::Result<Cmd
             match bet_db.get_entity::<OracleInfo>(oracle_id.clone())? {
                 Some(_) => eprintln!("oracle {} is already trusted", oracle_id),
                 None => {
-                    let root_response = reqwest::blocking::get(url)?
-                        .error_for_status()?
-                        .json::<RootResponse<Secp256k1>>()?;
+                    let root_response = oracle_sources.for_url(&url).root(&url)?;
                     let oracle_info = OracleInfo {
                         id: oracle_id,
                         oracle_keys: root_response.public_keys,
@@ -56,7 +64,7 @@ pub fn run_oralce_cmd(bet_db: BetDatabase, cmd: OracleOpt) -> anyhow::Result<Cmd
 
                     println!("{}", serde_json::to_string_pretty(&oracle_info).unwrap());
 
-                    if yes || cmd::read_answer(&format!("Trust the oracle displayed above")) {
+                    if yes || cmd::read_answer("Trust the oracle displayed above") {
                         bet_db.insert_oracle_info(oracle_info.clone())?;
                     }
                 }
@@ -104,5 +112,33 @@ pub fn run_oralce_cmd(bet_db: BetDatabase, cmd: OracleOpt) -> anyhow::Result<Cmd
                 "announcement" => Cell::string(oracle_keys.announcement),
             })
         }
+        OracleOpt::ProveEquivocation { event } => {
+            let url = crate::endpoint::parse_endpoint(&event)?;
+            let oracle_id = url
+                .host()
+                .ok_or(anyhow!("event url missing host"))?
+                .to_string();
+            let event_id = olivia_core::EventId::from_str(url.path())
+                .map_err(|e| anyhow!("{} is not a valid event id: {}", url.path(), e))?;
+            let oracle = bet_db
+                .get_entity::<OracleInfo>(oracle_id.clone())?
+                .ok_or(anyhow!("Oracle {} not in database", oracle_id))?;
+            let seen = bet_db
+                .get_entity::<SeenAttestations>(SeenAttestations::key(&oracle_id, &event_id))?
+                .ok_or(anyhow!(
+                    "gun hasn't seen {} attest to {}",
+                    oracle_id,
+                    event_id
+                ))?;
+            let proof =
+                EquivocationProof::from_seen(oracle, event_id.clone(), &seen).ok_or(anyhow!(
+                    "{} has only been seen attesting to {} for {}",
+                    oracle_id,
+                    seen.outcomes().join(", "),
+                    event_id
+                ))?;
+            proof.verify()?;
+            Ok(CmdOutput::Json(serde_json::to_value(&proof)?))
+        }
     }
 }
@@ -0,0 +1,437 @@
+use super::*;
+use crate::{
+    betting::PendingPsbt, coin_select::PolicyCoinSelection, coldcard,
+    external_signer::verify_signed_psbt, item, psbt_policy::PsbtPolicy,
+};
+use bdk::{
+    bitcoin::{consensus::encode, Transaction},
+    SignOptions,
+};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Transactions that are waiting to be signed somewhere else
+pub enum PsbtOpt {
+    /// List transactions waiting for signatures
+    List,
+    /// Export a transaction to be signed
+    Export {
+        /// The transaction to export. Can be left out if only one is waiting.
+        txid: Option<Txid>,
+        /// Write it to a Coldcard's SD card mounted at this directory
+        #[structopt(long, parse(from_os_str), value_name = "SD_CARD_DIR")]
+        coldcard: Option<PathBuf>,
+    },
+    /// Import a signed transaction and broadcast it
+    Import {
+        /// A file containing the signed PSBT (binary or base64). Use "-" for stdin.
+        #[structopt(required_unless = "coldcard")]
+        file: Option<String>,
+        /// Look for the files a Coldcard wrote after signing on the SD card mounted at this
+        /// directory
+        #[structopt(
+            long,
+            parse(from_os_str),
+            value_name = "SD_CARD_DIR",
+            conflicts_with = "file"
+        )]
+        coldcard: Option<PathBuf>,
+        /// Don't prompt for answers just answer yes.
+        #[structopt(long, short)]
+        yes: bool,
+        /// Print the resulting transaction out in hex instead of broadcasting it.
+        #[structopt(long)]
+        print_tx: bool,
+    },
+    /// Forget about a transaction that is waiting for signatures and release its coins
+    Discard { txid: Txid },
+    /// Sign a PSBT made somewhere else (e.g. by a multisig cosigner) if it follows the rules in
+    /// psbt-policy.json and print it
+    Sign {
+        /// A file containing the PSBT (binary or base64). Use "-" for stdin.
+        file: String,
+    },
+}
+
+pub fn run_psbt_cmd(wallet_dir: &PathBuf, opt: PsbtOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        PsbtOpt::List => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            let rows = bet_db
+                .list_entities_print_error::<PendingPsbt>()
+                .map(|(txid, pending)| {
+                    let (fee, feerate) = pending.psbt.fee();
+                    vec![
+                        Cell::string(txid),
+                        Cell::DateTime(pending.created_at.timestamp() as u64),
+                        Cell::Int(pending.psbt.global.unsigned_tx.input.len() as u64),
+                        Cell::Int(pending.psbt.global.unsigned_tx.output.len() as u64),
+                        Cell::Amount(fee),
+                        Cell::string(feerate.as_sat_vb()),
+                        match &pending.approval {
+                            Some(approval) => {
+                                Cell::String(format!("needed (from {})", approval.requested_by))
+                            }
+                            None => Cell::Empty,
+                        },
+                        match &pending.queued {
+                            Some(queued) => Cell::String(format!("{} attempts", queued.attempts)),
+                            None => Cell::Empty,
+                        },
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec![
+                    "txid",
+                    "created",
+                    "inputs",
+                    "outputs",
+                    "fee",
+                    "feerate",
+                    "approval",
+                    "broadcast-queue",
+                ],
+                rows,
+            ))
+        }
+        PsbtOpt::Export { txid, coldcard } => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            let (txid, pending) = get_pending(&bet_db, txid)?;
+            check_not_awaiting_approval(txid, &pending)?;
+            match coldcard {
+                Some(sd_card_dir) => {
+                    let mut path = sd_card_dir.clone();
+                    path.push(format!("{}.psbt", coldcard::file_stem(txid)));
+                    fs::write(&path, encode::serialize(&pending.psbt))
+                        .with_context(|| format!("writing {}", path.display()))?;
+                    eprintln!(
+                        "Sign it on the Coldcard then run `gun psbt import --coldcard {}`",
+                        sd_card_dir.display()
+                    );
+                    Ok(item! { "file" => Cell::string(path.display()) })
+                }
+                None => Ok(item! { "psbt" => Cell::String(pending.psbt.to_string()) }),
+            }
+        }
+        PsbtOpt::Import {
+            file,
+            coldcard,
+            yes,
+            print_tx,
+        } => {
+            let party = load_party(wallet_dir)?;
+            let bet_db = party.bet_db();
+            let wallet = party.wallet();
+            let secp = bdk::bitcoin::secp256k1::Secp256k1::new();
+
+            let (txid, pending, mut signed) = match coldcard {
+                Some(sd_card_dir) => find_coldcard_signed(bet_db, &sd_card_dir)?,
+                None => {
+                    let signed = parse_psbt(&read_psbt_file(file.as_deref().unwrap_or("-"))?)?;
+                    let txid = signed.global.unsigned_tx.txid();
+                    let pending = bet_db
+                        .get_entity::<PendingPsbt>(txid)?
+                        .ok_or(anyhow!("transaction {} isn't waiting for signatures", txid))?;
+                    (txid, pending, signed)
+                }
+            };
+
+            check_not_awaiting_approval(txid, &pending)?;
+            verify_signed_psbt(&pending.psbt, &signed, &secp)?;
+
+            let finalized = wallet.finalize_psbt(&mut signed, SignOptions::default())?;
+            if !finalized {
+                return Err(anyhow!("transaction {} is still missing signatures", txid));
+            }
+
+            let (output, broadcast_txid) = decide_to_broadcast(
+                wallet.network(),
+                wallet.client(),
+                signed,
+                yes,
+                print_tx,
+                party.audit_log(),
+                party.bet_db(),
+                "psbt-import",
+            )?;
+
+            if broadcast_txid.is_some() && !print_tx {
+                bet_db.remove_broadcast_psbt(txid)?;
+                party.record_cost_basis(txid)?;
+                for bet_id in pending.claiming_bets {
+                    if let Err(e) = party.take_next_action(bet_id, false) {
+                        eprintln!(
+                            "error updating state of bet {} after broadcasting claim tx {}: {}",
+                            bet_id, txid, e
+                        );
+                    }
+                }
+            }
+
+            Ok(output)
+        }
+        PsbtOpt::Discard { txid } => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            if bet_db.remove_entity::<PendingPsbt>(txid)?.is_none() {
+                return Err(anyhow!("transaction {} isn't waiting for signatures", txid));
+            }
+            Ok(CmdOutput::None)
+        }
+        PsbtOpt::Sign { file } => {
+            let party = load_party(wallet_dir)?;
+            let wallet = party.wallet();
+            let mut psbt = parse_psbt(&read_psbt_file(&file)?)?;
+            PsbtPolicy::load(wallet_dir)?
+                .check(&psbt, |script| wallet.is_mine(script).unwrap_or(false))?;
+            eprintln!("{}", display_psbt(wallet, &psbt));
+            let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+            party.audit_psbt(AuditOperation::Sign, "psbt sign", &psbt)?;
+            if finalized {
+                eprintln!("The transaction has every signature it needs and can be broadcast.");
+            }
+            Ok(item! {
+                "psbt" => Cell::String(psbt.to_string()),
+                "finalized" => Cell::string(finalized),
+            })
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct FundPsbtOpt {
+    /// A PSBT (base64 or a file containing it) with the outputs to fund e.g. from LND's
+    /// `openchannel --psbt`
+    #[structopt(long, required_unless_one = &["complete", "abort"])]
+    template: Option<String>,
+    /// Broadcast a funded PSBT once the other side is ready
+    #[structopt(long, conflicts_with_all = &["template", "abort"])]
+    complete: Option<Txid>,
+    /// Give up on a funded PSBT and release its coins
+    #[structopt(long, conflicts_with = "template")]
+    abort: Option<Txid>,
+    #[structopt(flatten)]
+    fee_args: FeeArgs,
+    /// Don't prompt for answers just answer yes.
+    #[structopt(long, short)]
+    yes: bool,
+}
+
+/// Funds the outputs of a PSBT made by someone else. The coins used stay reserved until the
+/// funding is completed or aborted so nothing else can double spend them in the meantime.
+pub fn run_fund_psbt(wallet_dir: &PathBuf, opt: FundPsbtOpt) -> anyhow::Result<CmdOutput> {
+    let FundPsbtOpt {
+        template,
+        complete,
+        abort,
+        fee_args,
+        yes,
+    } = opt;
+
+    if let Some(txid) = abort {
+        return run_psbt_cmd(wallet_dir, PsbtOpt::Discard { txid });
+    }
+
+    let party = load_party(wallet_dir)?;
+    let wallet = party.wallet();
+    let bet_db = party.bet_db();
+
+    if let Some(txid) = complete {
+        let pending = bet_db
+            .get_entity::<PendingPsbt>(txid)?
+            .ok_or(anyhow!("{} is not a PSBT we funded", txid))?;
+        let mut psbt = pending.psbt;
+        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+            return Err(anyhow!(
+                "{} is still missing signatures (see `gun psbt export`)",
+                txid
+            ));
+        }
+        let (output, broadcast_txid) = decide_to_broadcast(
+            wallet.network(),
+            wallet.client(),
+            psbt,
+            yes,
+            false,
+            party.audit_log(),
+            party.bet_db(),
+            "fund-psbt",
+        )?;
+        if broadcast_txid.is_some() {
+            bet_db.remove_broadcast_psbt(txid)?;
+        }
+        return Ok(output);
+    }
+
+    let template = template.expect("structopt makes sure one of them is there");
+    let template = match fs::read(&template) {
+        Ok(bytes) => parse_psbt(&bytes)?,
+        Err(_) => parse_psbt(template.as_bytes())?,
+    };
+    if !template.global.unsigned_tx.input.is_empty() {
+        return Err(anyhow!(
+            "the template already has inputs -- only templates with just outputs can be funded"
+        ));
+    }
+
+    let mut builder = wallet
+        .build_tx()
+        .coin_selection(PolicyCoinSelection(party.settings().coin_select));
+    let recipients = template
+        .global
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| txout.script_pubkey.clone())
+        .collect::<Vec<_>>();
+    for txout in &template.global.unsigned_tx.output {
+        builder.add_recipient(txout.script_pubkey.clone(), txout.value);
+    }
+    for outpoint in bet_db
+        .currently_used_utxos(&[])?
+        .into_iter()
+        .chain(bet_db.frozen_utxos()?)
+    {
+        builder.add_unspendable(outpoint);
+    }
+    fee_args
+        .fee_spec(party.settings())
+        .apply_to_builder(wallet.client(), &mut builder)?;
+
+    let (mut psbt, _) = builder.finish()?;
+    let threshold = party.settings().dust_change_threshold;
+    let donated = crate::psbt_ext::fold_dust_change(&mut psbt, threshold, |txout| {
+        !recipients.contains(&txout.script_pubkey)
+            && wallet.is_mine(&txout.script_pubkey).unwrap_or(false)
+    });
+    if donated > Amount::ZERO {
+        eprintln!("{} of dust change was added to the fee", donated);
+    }
+
+    // the template came from somewhere else so its outputs have to follow the policy
+    PsbtPolicy::load(wallet_dir)?.check(&psbt, |script| wallet.is_mine(script).unwrap_or(false))?;
+    let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+    party.audit_psbt(AuditOperation::Sign, "fund-psbt", &psbt)?;
+    eprintln!("{}", display_psbt(wallet, &psbt));
+    let txid = bet_db.insert_pending_psbt(psbt.clone(), vec![])?;
+
+    if finalized {
+        eprintln!(
+            "The coins are reserved until you run `gun fund-psbt --complete {0}` to broadcast it or `gun fund-psbt --abort {0}`.",
+            txid
+        );
+    } else {
+        eprintln!(
+            "This wallet couldn't sign every input. Use `gun psbt export {}` to sign it elsewhere.",
+            txid
+        );
+    }
+
+    Ok(item! {
+        "txid" => Cell::string(txid),
+        "psbt" => Cell::String(psbt.to_string()),
+    })
+}
+
+fn get_pending(bet_db: &BetDatabase, txid: Option<Txid>) -> anyhow::Result<(Txid, PendingPsbt)> {
+    match txid {
+        Some(txid) => Ok((
+            txid,
+            bet_db
+                .get_entity::<PendingPsbt>(txid)?
+                .ok_or(anyhow!("transaction {} isn't waiting for signatures", txid))?,
+        )),
+        None => {
+            let mut pending = bet_db
+                .list_entities::<PendingPsbt>()
+                .collect::<Result<Vec<_>, _>>()?;
+            match pending.len() {
+                0 => Err(anyhow!("there are no transactions waiting for signatures")),
+                1 => Ok(pending.remove(0)),
+                _ => Err(anyhow!(
+                    "there is more than one transaction waiting for signatures so you need to say which one (see `gun psbt list`)"
+                )),
+            }
+        }
+    }
+}
+
+/// Sends waiting for a second approver mustn't be signed anywhere until they've been approved.
+fn check_not_awaiting_approval(txid: Txid, pending: &PendingPsbt) -> anyhow::Result<()> {
+    match &pending.approval {
+        Some(approval) => Err(ErrorKind::Policy.error(format!(
+            "transaction {} is waiting to be approved by someone other than {} (see `gun approval confirm`)",
+            txid,
+            approval.requested_by
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Reads `file` or stdin if it's "-".
+fn read_psbt_file(file: &str) -> anyhow::Result<Vec<u8>> {
+    match file {
+        "-" => {
+            use std::io::Read;
+            let mut bytes = vec![];
+            std::io::stdin().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        file => fs::read(file).with_context(|| format!("reading {}", file)),
+    }
+}
+
+/// PSBT files can be binary or base64.
+fn parse_psbt(bytes: &[u8]) -> anyhow::Result<Psbt> {
+    if bytes.starts_with(b"psbt\xff") {
+        return Ok(encode::deserialize(bytes)?);
+    }
+    let string = std::str::from_utf8(bytes).context("PSBT is neither binary nor base64")?;
+    Ok(Psbt::from_str(string.trim())?)
+}
+
+fn find_coldcard_signed(
+    bet_db: &BetDatabase,
+    sd_card_dir: &PathBuf,
+) -> anyhow::Result<(Txid, PendingPsbt, Psbt)> {
+    for (txid, pending) in bet_db.list_entities_print_error::<PendingPsbt>() {
+        for file_name in coldcard::signed_file_names(txid) {
+            let mut path = sd_card_dir.clone();
+            path.push(&file_name);
+            if !path.exists() {
+                continue;
+            }
+            let bytes = fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            let signed = if file_name.ends_with(".txn") {
+                let hex = std::str::from_utf8(&bytes).context("reading finalized transaction")?;
+                let tx = encode::deserialize::<Transaction>(
+                    &crate::hex::decode(hex.trim()).context("decoding finalized transaction")?,
+                )?;
+                if tx.txid() != txid {
+                    return Err(anyhow!(
+                        "{} has transaction {} but we were expecting {}",
+                        path.display(),
+                        tx.txid(),
+                        txid
+                    ));
+                }
+                // put the witnesses back into the PSBT so they can be checked like any other
+                let mut signed = pending.psbt.clone();
+                for (input, txin) in signed.inputs.iter_mut().zip(tx.input) {
+                    input.final_script_witness = Some(txin.witness);
+                }
+                signed
+            } else {
+                parse_psbt(&bytes)?
+            };
+            eprintln!("importing {}", path.display());
+            return Ok((txid, pending, signed));
+        }
+    }
+
+    Err(anyhow!(
+        "couldn't find any signed transactions from the Coldcard in {}",
+        sd_card_dir.display()
+    ))
+}
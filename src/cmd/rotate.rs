@@ -0,0 +1,469 @@
+use super::*;
+use crate::{
+    approval::ApprovalRequest,
+    betting::{BetId, BetState, Reservation, Rotation},
+    config::WalletKind,
+    item, psbt_ext,
+    tip_watch::TipWatcher,
+};
+use bdk::{blockchain::EsploraBlockchain, wallet::AddressIndex, FeeRate, LocalUtxo, SignOptions};
+use std::{collections::HashSet, str::FromStr};
+use structopt::StructOpt;
+
+/// How long `gun rotate watch` waits between sweeps if there's no new block.
+const WATCH_POLL_SECS: u64 = 60;
+
+/// About how much spending a p2wpkh coin adds to a transaction. Coins worth less than this at the
+/// sweep's feerate are left where they are.
+const P2WPKH_INPUT_VBYTES: f32 = 68.0;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Move everything to a wallet with new seed words (e.g. because the seed words of this one might
+/// have been seen). Run it with the old wallet's --gun-dir.
+pub enum RotateOpt {
+    /// Make the new wallet, copy the labels of the addresses you pay to it and sweep the coins
+    /// into it
+    Start {
+        /// Where to make the new wallet
+        #[structopt(parse(from_os_str))]
+        new_dir: PathBuf,
+        #[structopt(long, default_value = "12", name = "[12|24]")]
+        /// The number of BIP39 seed words to use
+        n_words: usize,
+        /// The fee for the sweeps (see `gun send --fee`)
+        #[structopt(long, default_value = "economy")]
+        fee: String,
+        /// The most coins one sweep spends. Fewer, bigger sweeps pay less in fees.
+        #[structopt(long, default_value = "100")]
+        batch_inputs: usize,
+        /// Broadcast the sweeps without asking
+        #[structopt(long, short)]
+        yes: bool,
+    },
+    /// Show what has been swept and what's still in the old wallet
+    Status,
+    /// Sweep the coins that have come in or been confirmed since the last sweep
+    Sweep {
+        /// Broadcast the sweeps without asking
+        #[structopt(long, short)]
+        yes: bool,
+    },
+    /// Keep sweeping (without asking) until the old wallet is empty. It looks again at every new
+    /// block so bets that pay out after the rotation are swept too.
+    Watch,
+}
+
+/// A sweep made (or queued for approval) by [`sweep_old_wallet`].
+struct Sweep {
+    result: &'static str,
+    coins: usize,
+    amount: Amount,
+    txid: Option<Txid>,
+}
+
+/// What's still in the old wallet.
+#[derive(Default)]
+struct Remaining {
+    /// Coins that can be swept now, biggest first
+    sweepable: Vec<LocalUtxo>,
+    /// In coins that will be swept once they're confirmed or the bet or transaction they're
+    /// reserved for is done
+    waiting: Amount,
+    frozen: Amount,
+    /// In coins worth less than it costs to spend them
+    dust: Amount,
+    /// Bets that could still pay out to the old wallet
+    bets: Vec<BetId>,
+}
+
+impl Remaining {
+    /// Whether there's nothing left that will ever be swept.
+    fn is_done(&self) -> bool {
+        self.sweepable.is_empty() && self.waiting == Amount::ZERO && self.bets.is_empty()
+    }
+
+    fn sweepable_value(&self) -> Amount {
+        Amount::from_sat(self.sweepable.iter().map(|utxo| utxo.txout.value).sum())
+    }
+
+    fn warn(&self) {
+        if self.frozen > Amount::ZERO {
+            eprintln!(
+                "{} is in frozen coins which aren't swept -- unfreeze them with `gun utxo unfreeze` if they should be",
+                self.frozen
+            );
+        }
+        if self.dust > Amount::ZERO {
+            eprintln!(
+                "{} is in coins worth less than the fee to spend them so it's left behind",
+                self.dust
+            );
+        }
+        if !self.bets.is_empty() {
+            eprintln!(
+                "bets {} aren't finished -- what they pay out is swept once it's in the wallet (claim won bets with `gun bet claim` and leave `gun rotate watch` running)",
+                self.bets
+                    .iter()
+                    .map(|bet_id| bet_id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+}
+
+pub fn run_rotate_cmd(wallet_dir: &PathBuf, opt: RotateOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        RotateOpt::Start {
+            new_dir,
+            n_words,
+            fee,
+            batch_inputs,
+            yes,
+        } => {
+            let party = load_party(wallet_dir)?;
+            let bet_db = party.bet_db();
+            if let Some(rotation) = bet_db.get_rotation()? {
+                return Err(anyhow!(
+                    "this wallet is already being moved to {} -- use `gun rotate sweep` to sweep what has come in since",
+                    rotation.to.display()
+                ));
+            }
+            if let FeeSpec::Bump(_) = FeeSpec::from_str(&fee)? {
+                return Err(anyhow!(
+                    "the sweeps don't replace anything so the fee can't be a bump"
+                ));
+            }
+            if batch_inputs == 0 {
+                return Err(anyhow!("--batch-inputs has to be at least 1"));
+            }
+
+            // the new wallet gets all the settings but its keys come from the new seed words
+            let mut config = load_config(wallet_dir)?;
+            config.kind = WalletKind::P2wpkh;
+            let seed_words = generate_seed_words(n_words)?;
+            let sw_file = create_wallet_dir(&new_dir, &config, &seed_words)?;
+            eprintln!("Wrote the new wallet's seed words to {}", sw_file.display());
+            let new_dir = new_dir
+                .canonicalize()
+                .context("finding the new wallet's directory")?;
+
+            let (new_wallet, new_bet_db, _, _) = load_wallet(&new_dir)?;
+            let labels = migrate_labels(&party, &new_bet_db)?;
+            bet_db.set_rotation(Rotation {
+                to: new_dir.clone(),
+                started_at: crate::clock::now(),
+                fee,
+                batch_inputs,
+                sweeps: vec![],
+            })?;
+
+            party.sync()?;
+            let (sweeps, remaining) = sweep_old_wallet(&party, &new_wallet, yes)?;
+            remaining.warn();
+            eprintln!("Leave `gun rotate watch` running (or run `gun rotate sweep` now and then) to sweep anything that comes in later.");
+            println!("==== BIP39 seed words of the new wallet ====");
+            Ok(item! {
+                "seed_words" => Cell::String(seed_words),
+                "new-wallet" => Cell::string(new_dir.display()),
+                "labels-copied" => Cell::Int(labels as u64),
+                "swept" => Cell::Amount(swept_value(&sweeps)),
+                "sweeps" => Cell::String(sweep_txids(&sweeps)),
+            })
+        }
+        RotateOpt::Status => {
+            let party = load_party(wallet_dir)?;
+            let rotation = get_rotation(&party)?;
+            party.sync()?;
+            let remaining = remaining(&party, sweep_feerate(&party, &rotation)?)?;
+            Ok(item! {
+                "new-wallet" => Cell::string(rotation.to.display()),
+                "started" => Cell::DateTime(rotation.started_at.timestamp() as u64),
+                "sweeps" => Cell::String(
+                    rotation
+                        .sweeps
+                        .iter()
+                        .map(|txid| txid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                "sweepable-now" => Cell::Amount(remaining.sweepable_value()),
+                "waiting" => Cell::Amount(remaining.waiting),
+                "frozen" => Cell::Amount(remaining.frozen),
+                "dust" => Cell::Amount(remaining.dust),
+                "bets-in-progress" => Cell::Int(remaining.bets.len() as u64),
+                "empty" => Cell::string(remaining.is_done()),
+            })
+        }
+        RotateOpt::Sweep { yes } => {
+            let (sweeps, remaining) = sweep_from_dir(wallet_dir, yes)?;
+            remaining.warn();
+            Ok(CmdOutput::table(
+                vec!["result", "coins", "amount", "txid"],
+                sweeps
+                    .into_iter()
+                    .map(|sweep| {
+                        vec![
+                            Cell::string(sweep.result),
+                            Cell::Int(sweep.coins as u64),
+                            Cell::Amount(sweep.amount),
+                            sweep.txid.map(Cell::string).unwrap_or(Cell::Empty),
+                        ]
+                    })
+                    .collect(),
+            ))
+        }
+        RotateOpt::Watch => {
+            let poll = std::time::Duration::from_secs(WATCH_POLL_SECS);
+            let mut tip_watcher = TipWatcher::new(&backend_url(
+                &crate::pinned_backend::blockchain_config(&load_config(wallet_dir)?)?,
+            ))?;
+            loop {
+                match sweep_from_dir(wallet_dir, true) {
+                    Ok((sweeps, remaining)) => {
+                        for sweep in &sweeps {
+                            eprintln!(
+                                "rotation: {} {} from {} coins ({})",
+                                sweep.result,
+                                sweep.amount,
+                                sweep.coins,
+                                sweep.txid.map(|txid| txid.to_string()).unwrap_or_default()
+                            );
+                        }
+                        // what was just swept is only gone from the wallet after the next sync
+                        if sweeps.is_empty() && remaining.is_done() {
+                            remaining.warn();
+                            eprintln!("the old wallet is empty");
+                            return Ok(CmdOutput::None);
+                        }
+                    }
+                    Err(e) => eprintln!("couldn't sweep to the new wallet: {}", e),
+                }
+                if let Err(e) = tip_watcher.wait(poll) {
+                    tracing::warn!("couldn't watch for new blocks: {}", e);
+                    std::thread::sleep(poll);
+                }
+            }
+        }
+    }
+}
+
+fn get_rotation<D: BatchDatabase>(party: &Party<EsploraBlockchain, D>) -> anyhow::Result<Rotation> {
+    party.bet_db().get_rotation()?.ok_or(anyhow!(
+        "this wallet isn't being moved to a new one -- start with `gun rotate start <new-dir>`"
+    ))
+}
+
+fn sweep_from_dir(wallet_dir: &PathBuf, yes: bool) -> anyhow::Result<(Vec<Sweep>, Remaining)> {
+    let party = load_party(wallet_dir)?;
+    let rotation = get_rotation(&party)?;
+    let (new_wallet, _, _, _) = load_wallet(&rotation.to).with_context(|| {
+        format!(
+            "loading the new wallet from {} (where `gun rotate start` made it)",
+            rotation.to.display()
+        )
+    })?;
+    party.sync()?;
+    sweep_old_wallet(&party, &new_wallet, yes)
+}
+
+/// Copies the labels of addresses that aren't the old wallet's (i.e. the ones of people we pay)
+/// to the new wallet. Labels of the old wallet's own addresses wouldn't mean anything there.
+fn migrate_labels<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    new_bet_db: &BetDatabase,
+) -> anyhow::Result<usize> {
+    let mut copied = 0;
+    for (script, label) in party.bet_db().address_labels()? {
+        if party.wallet().is_mine(&script)? {
+            continue;
+        }
+        new_bet_db.set_address_label(script, label)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// The feerate the sweeps pay. `None` if the fee is absolute.
+fn sweep_feerate<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    rotation: &Rotation,
+) -> anyhow::Result<Option<FeeRate>> {
+    FeeSpec::from_str(&rotation.fee)?
+        .with_aliases(&party.settings().fee_aliases)
+        .feerate(party.wallet().client())
+}
+
+fn remaining<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    feerate: Option<FeeRate>,
+) -> anyhow::Result<Remaining> {
+    let wallet = party.wallet();
+    let confirmed = wallet
+        .list_transactions(false)?
+        .into_iter()
+        .filter(|tx| tx.confirmation_time.is_some())
+        .map(|tx| tx.txid)
+        .collect::<HashSet<_>>();
+    let reservations = party.bet_db().reservations()?;
+    let mut remaining = Remaining::default();
+    for utxo in wallet.list_unspent()? {
+        let value = Amount::from_sat(utxo.txout.value);
+        let dust = feerate
+            .map(|feerate| value.as_sat() as f32 <= feerate.as_sat_vb() * P2WPKH_INPUT_VBYTES)
+            .unwrap_or(false);
+        match reservations.get(&utxo.outpoint) {
+            Some(Reservation::Frozen) => remaining.frozen += value,
+            Some(_) => remaining.waiting += value,
+            None if !confirmed.contains(&utxo.outpoint.txid) => remaining.waiting += value,
+            None if dust => remaining.dust += value,
+            None => remaining.sweepable.push(utxo),
+        }
+    }
+    // the biggest go first so the most is moved if a sweep fails part of the way through
+    remaining
+        .sweepable
+        .sort_by_key(|utxo| std::cmp::Reverse(utxo.txout.value));
+    for (bet_id, bet_state) in party.bet_db().list_entities_print_error::<BetState>() {
+        if let BetState::Proposed { .. }
+        | BetState::Offered { .. }
+        | BetState::Included { .. }
+        | BetState::Won { .. } = bet_state
+        {
+            remaining.bets.push(bet_id);
+        }
+    }
+    Ok(remaining)
+}
+
+/// Sweeps the coins of the old wallet that can be swept to `new_wallet` with up to the rotation's
+/// `batch_inputs` coins in each transaction. Returns what's left after the sweeps.
+fn sweep_old_wallet<D: BatchDatabase, N: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    new_wallet: &Wallet<EsploraBlockchain, N>,
+    yes: bool,
+) -> anyhow::Result<(Vec<Sweep>, Remaining)> {
+    let wallet = party.wallet();
+    let settings = party.settings();
+    let bet_db = party.bet_db();
+    let mut rotation = get_rotation(party)?;
+    let feerate = sweep_feerate(party, &rotation)?;
+    // every batch pays the same rate rather than asking for an estimate each time
+    let fee = match feerate {
+        Some(feerate) => FeeSpec::Rate(feerate),
+        None => FeeSpec::from_str(&rotation.fee)?,
+    };
+    let mut remaining = remaining(party, feerate)?;
+    let coins = std::mem::take(&mut remaining.sweepable);
+    let mut sweeps = vec![];
+
+    let batches = coins.chunks(rotation.batch_inputs.max(1)).count();
+    for (i, batch) in coins.chunks(rotation.batch_inputs.max(1)).enumerate() {
+        if batches > 1 {
+            eprintln!("sweep {} of {}", i + 1, batches);
+        }
+        let amount = Amount::from_sat(batch.iter().map(|utxo| utxo.txout.value).sum());
+        let mut builder = wallet.build_tx();
+        builder.manually_selected_only();
+        settings.tx_ordering.apply_to_builder(&mut builder);
+        if settings.rbf.sends {
+            builder.enable_rbf();
+        }
+        for utxo in batch {
+            builder.add_utxo(utxo.outpoint)?;
+        }
+        fee.apply_to_builder(wallet.client(), &mut builder)?;
+        builder.drain_to(
+            new_wallet
+                .get_address(AddressIndex::New)?
+                .address
+                .script_pubkey(),
+        );
+        let (mut psbt, _) = builder
+            .finish()
+            .context("building a sweep to the new wallet")?;
+        settings.tx_ordering.finish(&mut psbt);
+        psbt_ext::log_built_tx(&psbt);
+        let memo = "sweep to the new wallet (gun rotate)".to_string();
+
+        if let Some(policy) = &settings.approval {
+            if amount > policy.threshold {
+                let txid = bet_db.insert_pending_psbt(psbt, vec![])?;
+                bet_db.set_pending_approval(
+                    txid,
+                    Some(ApprovalRequest {
+                        requested_by: "rotation".to_string(),
+                        requested_at: crate::clock::now(),
+                        outgoing: amount,
+                    }),
+                )?;
+                bet_db.set_tx_memo(txid, memo)?;
+                remaining.waiting += amount;
+                sweeps.push(Sweep {
+                    result: "queued",
+                    coins: batch.len(),
+                    amount,
+                    txid: Some(txid),
+                });
+                continue;
+            }
+        }
+
+        crate::read_only::check("sign the sweep to the new wallet")?;
+        wallet.sign(&mut psbt, SignOptions::default())?;
+        party.audit_psbt(AuditOperation::Sign, "rotation sweep", &psbt)?;
+        if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+            return Err(anyhow!(
+                "the wallet can't sign by itself (is it locked or are some keys elsewhere?)"
+            ));
+        }
+        let (_, txid) = decide_to_broadcast(
+            wallet.network(),
+            wallet.client(),
+            psbt,
+            yes,
+            false,
+            party.audit_log(),
+            party.bet_db(),
+            "rotation sweep",
+        )?;
+        match txid {
+            Some(txid) => {
+                bet_db.set_tx_memo(txid, memo)?;
+                rotation.sweeps.push(txid);
+                bet_db.set_rotation(rotation.clone())?;
+                sweeps.push(Sweep {
+                    result: "sent",
+                    coins: batch.len(),
+                    amount,
+                    txid: Some(txid),
+                });
+            }
+            None => {
+                remaining.sweepable.extend(batch.iter().cloned());
+                sweeps.push(Sweep {
+                    result: "not sent",
+                    coins: batch.len(),
+                    amount,
+                    txid: None,
+                });
+            }
+        }
+    }
+    Ok((sweeps, remaining))
+}
+
+fn swept_value(sweeps: &[Sweep]) -> Amount {
+    sweeps
+        .iter()
+        .filter(|sweep| sweep.result == "sent")
+        .fold(Amount::ZERO, |total, sweep| total + sweep.amount)
+}
+
+fn sweep_txids(sweeps: &[Sweep]) -> String {
+    sweeps
+        .iter()
+        .filter_map(|sweep| sweep.txid.map(|txid| txid.to_string()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
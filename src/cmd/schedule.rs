@@ -0,0 +1,471 @@
+use super::*;
+use crate::{
+    amount_ext::FromCliStr,
+    approval::ApprovalRequest,
+    item, psbt_ext,
+    schedule::{Interval, ScheduledPayment},
+};
+use bdk::{blockchain::EsploraBlockchain, SignOptions};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// The longest `gun schedule run --daemon` waits between looking for payments that are due. It
+/// also looks whenever polling the tip finds a new block (see [`crate::tip_watch`]) so payments that failed (e.g. because the coins to pay
+/// them weren't confirmed yet) are retried straight away.
+#[cfg(feature = "daemon")]
+const DAEMON_POLL_SECS: u64 = 60;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Pay the same amount to the same address regularly
+pub enum ScheduleOpt {
+    /// Add a recurring payment
+    Add {
+        address: Address,
+        #[structopt(parse(try_from_str = FromCliStr::from_cli_str))]
+        amount: Amount,
+        /// How often to pay e.g. 12h, 1d, 2w or 1m (calendar months)
+        #[structopt(long)]
+        every: Interval,
+        /// The fee for each payment (see `gun send --fee`)
+        #[structopt(long, default_value = "in-blocks:12")]
+        fee: String,
+        /// Sign and broadcast payments below this without asking. Anything else is put in the
+        /// approval queue and has to be confirmed with `gun approval confirm` within the approval
+        /// window.
+        #[structopt(long, parse(try_from_str = FromCliStr::from_cli_str))]
+        auto_below: Option<Amount>,
+        /// A note to put on each payment's transaction
+        #[structopt(long)]
+        memo: Option<String>,
+        /// The day of the first payment (YYYY-MM-DD). It's due straight away otherwise.
+        #[structopt(long)]
+        starting: Option<crate::chrono::NaiveDate>,
+    },
+    /// List the recurring payments and when they're next due
+    List,
+    /// Stop a recurring payment
+    Remove { id: u32 },
+    /// Make the payments that are due, retry the broadcasts that are due (see `gun tx queue`) and
+    /// sweep to cold storage if the hot wallet is over its ceiling. Run it regularly e.g. from
+    /// cron or leave it running with --daemon.
+    Run {
+        /// Keep running and check for payments that are due every minute and at each new block
+        /// (found by polling the backend's tip)
+        #[structopt(long)]
+        daemon: bool,
+        /// Also serve this wallet (e.g. `alice=/home/alice/.gun`) in the same daemon. Each wallet
+        /// has its own loop and wallets on the same backend share the watch for new blocks.
+        #[structopt(long = "wallet", value_name = "NAME=DIR", requires = "daemon")]
+        wallets: Vec<NamedWallet>,
+    },
+}
+
+/// A wallet directory served by the daemon along with the name its messages are shown with.
+#[derive(Debug, Clone)]
+pub struct NamedWallet {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl FromStr for NamedWallet {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        match string.split_once('=') {
+            Some((name, dir)) if !name.is_empty() && !dir.is_empty() => Ok(NamedWallet {
+                name: name.to_string(),
+                dir: PathBuf::from(dir),
+            }),
+            _ => Err(anyhow!(
+                "'{}' should be a name and a wallet directory like alice=/home/alice/.gun",
+                string
+            )),
+        }
+    }
+}
+
+pub fn run_schedule_cmd(wallet_dir: &PathBuf, opt: ScheduleOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        ScheduleOpt::Add {
+            address,
+            amount,
+            every,
+            fee,
+            auto_below,
+            memo,
+            starting,
+        } => {
+            let party = load_party(wallet_dir)?;
+            let address =
+                crate::address::check(&address, party.wallet().network(), "the address to pay")?;
+            if let FeeSpec::Bump(_) = FeeSpec::from_str(&fee)? {
+                return Err(anyhow!(
+                    "scheduled payments don't replace anything so the fee can't be a bump"
+                ));
+            }
+            let auto_below = auto_below.unwrap_or(Amount::ZERO);
+            let policy = party.settings().approval.as_ref();
+            if amount >= auto_below
+                && policy
+                    .map(|policy| policy.approvers.is_empty())
+                    .unwrap_or(true)
+            {
+                return Err(anyhow!(
+                    "payments of {} aren't below --auto-below so they need approving but there's no one to approve them (see `gun approval add-approver`)",
+                    amount
+                ));
+            }
+            if let Some(policy) = policy {
+                if amount < auto_below && amount > policy.threshold {
+                    eprintln!(
+                        "Payments above the approval threshold of {} still need approving.",
+                        policy.threshold
+                    );
+                }
+            }
+            let starts_at = match starting {
+                Some(day) => day.and_hms(0, 0, 0),
+                None => crate::clock::now(),
+            };
+            let id = party.bet_db().add_scheduled_payment(ScheduledPayment {
+                address,
+                amount,
+                every,
+                fee,
+                auto_below,
+                memo,
+                starts_at,
+                payments: 0,
+                last_txid: None,
+            })?;
+            Ok(item! {
+                "id" => Cell::Int(id.into()),
+                "next-due" => Cell::DateTime(starts_at.timestamp() as u64),
+            })
+        }
+        ScheduleOpt::List => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            let rows = bet_db
+                .list_entities_print_error::<ScheduledPayment>()
+                .map(|(id, payment)| {
+                    vec![
+                        Cell::Int(id.into()),
+                        Cell::string(&payment.address),
+                        Cell::Amount(payment.amount),
+                        Cell::string(payment.every),
+                        Cell::DateTime(payment.next_due().timestamp() as u64),
+                        Cell::Amount(payment.auto_below),
+                        Cell::Int(payment.payments.into()),
+                        payment.last_txid.map(Cell::string).unwrap_or(Cell::Empty),
+                        payment.memo.map(Cell::String).unwrap_or(Cell::Empty),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec![
+                    "id",
+                    "address",
+                    "amount",
+                    "every",
+                    "next-due",
+                    "auto-below",
+                    "payments",
+                    "last-txid",
+                    "memo",
+                ],
+                rows,
+            ))
+        }
+        ScheduleOpt::Remove { id } => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            bet_db
+                .remove_entity::<ScheduledPayment>(id)?
+                .ok_or(anyhow!("there's no scheduled payment {}", id))?;
+            Ok(CmdOutput::None)
+        }
+        ScheduleOpt::Run { daemon: false, .. } => {
+            if let Err(e) = retry_broadcasts(wallet_dir, false) {
+                eprintln!("couldn't retry the broadcast queue: {}", e);
+            }
+            let rows = make_due_payments(wallet_dir)?;
+            if let Err(e) = check_cold_storage(wallet_dir, &mut None) {
+                eprintln!("couldn't sweep to cold storage: {}", e);
+            }
+            Ok(CmdOutput::table(
+                vec!["id", "amount", "address", "result", "txid"],
+                rows,
+            ))
+        }
+        ScheduleOpt::Run {
+            daemon: true,
+            wallets,
+        } => run_daemon(wallet_dir, wallets),
+    }
+}
+
+/// Serves `wallet_dir` and `others` until it's killed. Each wallet gets a thread of its own and
+/// each backend a thread that polls its tip and tells the wallets on it about new blocks.
+#[cfg(feature = "daemon")]
+fn run_daemon(wallet_dir: &PathBuf, others: Vec<NamedWallet>) -> anyhow::Result<CmdOutput> {
+    use std::{collections::BTreeMap, sync::mpsc};
+
+    let mut wallets = vec![NamedWallet {
+        name: "default".to_string(),
+        dir: wallet_dir.clone(),
+    }];
+    for other in others {
+        if wallets.iter().any(|wallet| wallet.name == other.name) {
+            return Err(ErrorKind::BadInput.error(format!(
+                "there's more than one wallet called {}",
+                other.name
+            )));
+        }
+        // a decoy session applies to the wallet it was started for
+        let dir = select_wallet_dir(&other.dir);
+        let canonical = dir.canonicalize().with_context(|| {
+            format!(
+                "{} ({}) isn't a wallet directory",
+                other.name,
+                dir.display()
+            )
+        })?;
+        for wallet in &wallets {
+            if wallet.dir.canonicalize().ok().as_ref() == Some(&canonical) {
+                return Err(ErrorKind::BadInput.error(format!(
+                    "{} and {} are the same wallet",
+                    wallet.name, other.name
+                )));
+            }
+        }
+        wallets.push(NamedWallet {
+            name: other.name,
+            dir,
+        });
+    }
+
+    let several = wallets.len() > 1;
+    let mut new_block_senders = BTreeMap::<String, Vec<mpsc::Sender<()>>>::new();
+    let mut servers = vec![];
+    for wallet in wallets {
+        let backend = backend_url(&crate::pinned_backend::blockchain_config(&load_config(
+            &wallet.dir,
+        )?)?);
+        let (sender, new_blocks) = mpsc::channel();
+        new_block_senders.entry(backend).or_default().push(sender);
+        let label = match several {
+            true => format!("{}: ", wallet.name),
+            false => String::new(),
+        };
+        servers.push(std::thread::spawn(move || {
+            serve_wallet(&label, &wallet.dir, new_blocks)
+        }));
+    }
+    for (backend, senders) in new_block_senders {
+        let tip_watcher = crate::tip_watch::TipWatcher::new(&backend)?;
+        std::thread::spawn(move || watch_tip(tip_watcher, senders));
+    }
+    for server in servers {
+        let _ = server.join();
+    }
+    Ok(CmdOutput::None)
+}
+
+#[cfg(not(feature = "daemon"))]
+fn run_daemon(_wallet_dir: &PathBuf, _others: Vec<NamedWallet>) -> anyhow::Result<CmdOutput> {
+    Err(crate::features::not_compiled_in(
+        "daemon",
+        "keep running with --daemon (run `gun schedule run` from cron instead)",
+    ))
+}
+
+/// Makes a wallet's payments, sweeps and checks whenever there's a new block or at least every
+/// [`DAEMON_POLL_SECS`]. Its messages start with `label`.
+#[cfg(feature = "daemon")]
+fn serve_wallet(label: &str, wallet_dir: &PathBuf, new_blocks: std::sync::mpsc::Receiver<()>) {
+    let poll = std::time::Duration::from_secs(DAEMON_POLL_SECS);
+    let mut last_proposed = None;
+    let mut last_verified = None;
+    loop {
+        if let Err(e) = retry_broadcasts(wallet_dir, false) {
+            eprintln!("{}couldn't retry the broadcast queue: {}", label, e);
+        }
+        if let Err(e) = make_due_payments(wallet_dir) {
+            eprintln!("{}couldn't make the scheduled payments: {}", label, e);
+        }
+        if let Err(e) = check_cold_storage(wallet_dir, &mut last_proposed) {
+            eprintln!("{}couldn't sweep to cold storage: {}", label, e);
+        }
+        if let Err(e) = verify_when_idle(wallet_dir, &mut last_verified) {
+            eprintln!("{}couldn't verify the database: {}", label, e);
+        }
+        match new_blocks.recv_timeout(poll) {
+            // blocks that came while this wallet was busy don't need a go each
+            Ok(()) => while new_blocks.try_recv().is_ok() {},
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => std::thread::sleep(poll),
+        }
+    }
+}
+
+/// Tells every wallet in `wallets` when there's a new block.
+#[cfg(feature = "daemon")]
+fn watch_tip(
+    mut tip_watcher: crate::tip_watch::TipWatcher,
+    wallets: Vec<std::sync::mpsc::Sender<()>>,
+) {
+    let poll = std::time::Duration::from_secs(DAEMON_POLL_SECS);
+    loop {
+        match tip_watcher.wait(poll) {
+            Ok(Some(_)) => {
+                for wallet in &wallets {
+                    let _ = wallet.send(());
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!("couldn't watch for new blocks: {}", e);
+                std::thread::sleep(poll);
+            }
+        }
+    }
+}
+
+/// Pays (or queues) every scheduled payment that is due. A payment that fails stays due and is
+/// tried again next time.
+fn make_due_payments(wallet_dir: &PathBuf) -> anyhow::Result<Vec<Vec<Cell>>> {
+    let now = crate::clock::now();
+    // don't sync (or hold the database) when nothing is due
+    let any_due = load_bet_db(wallet_dir)?
+        .list_entities::<ScheduledPayment>()
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|(_, payment)| payment.next_due() <= now);
+    if !any_due {
+        return Ok(vec![]);
+    }
+    let party = load_party(wallet_dir)?;
+    party.sync()?;
+    let mut rows = vec![];
+    for (id, mut payment) in party
+        .bet_db()
+        .list_entities::<ScheduledPayment>()
+        .collect::<Result<Vec<_>, _>>()?
+    {
+        if payment.next_due() > now {
+            continue;
+        }
+        let mut row = vec![
+            Cell::Int(id.into()),
+            Cell::Amount(payment.amount),
+            Cell::string(&payment.address),
+        ];
+        match make_payment(&party, id, &payment) {
+            Ok((result, txid)) => {
+                eprintln!(
+                    "scheduled payment {}: {} {} to {} ({})",
+                    id, result, payment.amount, payment.address, txid
+                );
+                let missed = payment.advance(now);
+                if missed > 0 {
+                    eprintln!(
+                        "scheduled payment {}: {} earlier payment(s) were missed and won't be made",
+                        id, missed
+                    );
+                }
+                payment.last_txid = Some(txid);
+                party.bet_db().set_scheduled_payment(id, payment)?;
+                row.extend([Cell::string(result), Cell::string(txid)]);
+            }
+            Err(e) => {
+                eprintln!("scheduled payment {} failed: {}", id, e);
+                row.extend([Cell::String(format!("failed: {}", e)), Cell::Empty]);
+            }
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn make_payment<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    id: u32,
+    payment: &ScheduledPayment,
+) -> anyhow::Result<(&'static str, Txid)> {
+    let wallet = party.wallet();
+    let settings = party.settings();
+    let mut builder = wallet.build_tx();
+    builder.add_recipient(payment.address.script_pubkey(), payment.amount.as_sat());
+    settings.tx_ordering.apply_to_builder(&mut builder);
+    if settings.rbf.sends {
+        builder.enable_rbf();
+    }
+    for outpoint in party.bet_db().currently_used_utxos(&[])? {
+        builder.add_unspendable(outpoint);
+    }
+    for frozen in party.bet_db().frozen_utxos()? {
+        builder.add_unspendable(frozen);
+    }
+    for outpoint in party.bet_db().coinjoin_outputs()?.keys() {
+        builder.add_unspendable(*outpoint);
+    }
+    FeeSpec::from_str(&payment.fee)?
+        .with_aliases(&settings.fee_aliases)
+        .apply_to_builder(wallet.client(), &mut builder)?;
+    if let Some(spec) = &settings.change_descriptor {
+        builder.drain_to(change_destination(party, spec)?);
+    }
+    let (mut psbt, _) = builder.finish()?;
+    settings.tx_ordering.finish(&mut psbt);
+    psbt_ext::log_built_tx(&psbt);
+    let memo = payment
+        .memo
+        .clone()
+        .unwrap_or_else(|| format!("scheduled payment {}", id));
+
+    let needs_approval = payment.amount >= payment.auto_below
+        || settings
+            .approval
+            .as_ref()
+            .map(|policy| payment.amount > policy.threshold)
+            .unwrap_or(false);
+    if needs_approval {
+        let bet_db = party.bet_db();
+        let txid = bet_db.insert_pending_psbt(psbt, vec![])?;
+        bet_db.set_pending_approval(
+            txid,
+            Some(ApprovalRequest {
+                requested_by: format!("schedule {}", id),
+                requested_at: crate::clock::now(),
+                outgoing: payment.amount,
+            }),
+        )?;
+        bet_db.set_tx_memo(txid, memo)?;
+        return Ok(("queued", txid));
+    }
+
+    wallet.sign(&mut psbt, SignOptions::default())?;
+    party.audit_psbt(
+        AuditOperation::Sign,
+        &format!("scheduled payment {}", id),
+        &psbt,
+    )?;
+    if !wallet.finalize_psbt(&mut psbt, SignOptions::default())? {
+        return Err(anyhow!(
+            "the wallet can't sign by itself (is it locked or are some keys elsewhere?)"
+        ));
+    }
+    let (_, txid) = decide_to_broadcast(
+        wallet.network(),
+        wallet.client(),
+        psbt,
+        true,
+        false,
+        party.audit_log(),
+        party.bet_db(),
+        "scheduled payment",
+    )?;
+    let txid = txid.ok_or(anyhow!("the payment wasn't broadcast"))?;
+    party.bet_db().set_tx_memo(txid, memo)?;
+    party.record_cost_basis(txid)?;
+    Ok(("sent", txid))
+}
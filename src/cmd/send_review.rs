@@ -0,0 +1,578 @@
+//! The summary shown before a send is broadcast.
+//!
+//! Anything that looks like a costly mistake (a huge fee, paying an address that has been paid
+//! before, an amount that looks like a typo) has to be confirmed by typing [`TYPED_CONFIRMATION`]
+//! rather than just answering `y`.
+//!
+//! Amounts are compared with the median of the wallet's earlier sends (not counting bets). One
+//! that's [`UNUSUAL_AMOUNT_MULTIPLE`] times the median or more is a likely typo as is a much
+//! smaller one that would be around the median in another unit (e.g. `5000` sats when 5000 bits
+//! was meant). Set `ignore-unusual-amounts` in the config to turn this off.
+use super::*;
+use crate::betting::{confirmations, BetId, BetState};
+use bdk::{
+    bitcoin::{OutPoint, Script},
+    blockchain::{Blockchain, EsploraBlockchain},
+    FeeRate,
+};
+use std::collections::HashSet;
+use term_table::{row::Row, Table};
+
+/// Fees of at least this percentage of the amount sent need typed confirmation.
+const HIGH_FEE_PERCENT: f32 = 10.0;
+/// Feerates of more than this many times the next block estimate need typed confirmation.
+const HIGH_FEERATE_MULTIPLE: f32 = 2.0;
+/// The confirmation targets (in blocks) the feerate is compared against.
+const FEE_ESTIMATE_TARGETS: [usize; 3] = [1, 3, 6];
+/// Amounts at least this many times the median of earlier sends need typed confirmation.
+const UNUSUAL_AMOUNT_MULTIPLE: u64 = 10;
+/// How many earlier sends it takes before amounts are compared with them.
+const MIN_SENDS_FOR_HISTORY: usize = 3;
+/// How many times one amount unit (BTC, mBTC, bits or sats) is of another.
+const UNIT_FACTORS: [u64; 5] = [100, 1_000, 100_000, 1_000_000, 100_000_000];
+/// What has to be typed to broadcast a high risk send.
+pub const TYPED_CONFIRMATION: &str = "send";
+
+pub struct SendReview {
+    inputs: Vec<ReviewInput>,
+    outputs: Vec<ReviewOutput>,
+    /// What the recipients get
+    amount: Amount,
+    fee: Amount,
+    feerate: FeeRate,
+    /// Feerates the backend estimates for each of `FEE_ESTIMATE_TARGETS`
+    estimates: Vec<(usize, FeeRate)>,
+    rbf: bool,
+    size: TxSize,
+    /// `None` if there weren't enough earlier sends or the checks are turned off
+    history: Option<SendHistory>,
+}
+
+/// The amounts the wallet has sent before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SendHistory {
+    sends: usize,
+    median: Amount,
+}
+
+impl SendHistory {
+    fn new(mut values: Vec<u64>) -> Option<Self> {
+        if values.len() < MIN_SENDS_FOR_HISTORY {
+            return None;
+        }
+        values.sort_unstable();
+        Some(SendHistory {
+            sends: values.len(),
+            median: Amount::from_sat(values[(values.len() - 1) / 2]),
+        })
+    }
+
+    /// Why `value` looks like a typo if it does.
+    fn unusual(&self, value: Amount) -> Option<String> {
+        let (value_sats, median) = (value.as_sat(), self.median.as_sat().max(1));
+        let is_usual = |sats: u64| sats >= median / 3 && sats <= median.saturating_mul(3);
+        let meant = UNIT_FACTORS
+            .iter()
+            .flat_map(|factor| {
+                vec![
+                    value_sats.checked_mul(*factor),
+                    Some(value_sats / factor).filter(|sats| *sats > 0),
+                ]
+            })
+            .flatten()
+            .find(|sats| is_usual(*sats))
+            .map(|sats| {
+                format!(
+                    " -- did you mean {}?",
+                    format_amount(Amount::from_sat(sats))
+                )
+            })
+            .unwrap_or_default();
+        if value_sats >= median.saturating_mul(UNUSUAL_AMOUNT_MULTIPLE) {
+            Some(format!(
+                "{} is {} times the {} you usually send (the median of {} sends){}",
+                format_amount(value),
+                value_sats / median,
+                format_amount(self.median),
+                self.sends,
+                meant
+            ))
+        } else if value_sats.saturating_mul(UNUSUAL_AMOUNT_MULTIPLE) <= median && !meant.is_empty()
+        {
+            Some(format!(
+                "{} is a lot less than the {} you usually send{}",
+                format_amount(value),
+                format_amount(self.median),
+                meant
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+struct ReviewInput {
+    outpoint: OutPoint,
+    value: Amount,
+    label: Option<String>,
+    /// `None` if it isn't known
+    confirmations: Option<u32>,
+}
+
+struct ReviewOutput {
+    destination: String,
+    value: Amount,
+    is_change: bool,
+    is_mine: bool,
+    label: Option<String>,
+    /// Coins have been sent to this script before
+    reused: bool,
+}
+
+pub struct Warning {
+    pub message: String,
+    /// Has to be confirmed by typing rather than `y`
+    pub high_risk: bool,
+}
+
+/// Gathers what the user needs to know about `psbt` before it is broadcast. `recipients` are the
+/// outputs the user asked for. Everything else that is ours is change as is `change` (change sent
+/// outside the wallet's descriptor). `bet_inputs` are the won bets the transaction spends.
+pub fn review_send<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    psbt: &Psbt,
+    recipients: &[Script],
+    change: Option<&Script>,
+    bet_inputs: &[(OutPoint, BetId)],
+) -> anyhow::Result<SendReview> {
+    let wallet = party.wallet();
+    let network = wallet.network();
+    let labels = party.bet_db().address_labels()?;
+    let tip_height = match wallet.client().get_height() {
+        Ok(height) => Some(height),
+        Err(e) => {
+            tracing::warn!("couldn't get the current block height: {}", e);
+            party.bet_db().tip_height()?
+        }
+    };
+
+    let bet_txids = party
+        .bet_db()
+        .list_entities_print_error::<BetState>()
+        .filter_map(|(_, bet_state)| match bet_state {
+            BetState::Offered { bet, .. } => Some(bet.tx().txid()),
+            BetState::Included { bet, .. }
+            | BetState::Won { bet, .. }
+            | BetState::Lost { bet, .. }
+            | BetState::Claimed { bet, .. } => Some(bet.tx().txid()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    let mut seen_scripts = HashSet::new();
+    let mut sent_values = vec![];
+    for tx_details in wallet.list_transactions(true)? {
+        if let Some(tx) = tx_details.transaction {
+            if tx_details.sent > 0 && !bet_txids.contains(&tx_details.txid) {
+                sent_values.extend(
+                    tx.output
+                        .iter()
+                        .filter(|txout| !wallet.is_mine(&txout.script_pubkey).unwrap_or(true))
+                        .map(|txout| txout.value),
+                );
+            }
+            seen_scripts.extend(tx.output.into_iter().map(|txout| txout.script_pubkey));
+        }
+    }
+    let history = match party.settings().ignore_unusual_amounts {
+        true => None,
+        false => SendHistory::new(sent_values),
+    };
+
+    let inputs = psbt
+        .global
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .map(|(txin, psbt_input)| {
+            let outpoint = txin.previous_output;
+            let txout = psbt_input.witness_utxo.as_ref();
+            let bet_id = bet_inputs
+                .iter()
+                .find(|(bet_outpoint, _)| *bet_outpoint == outpoint)
+                .map(|(_, bet_id)| *bet_id);
+            let label = match bet_id {
+                Some(bet_id) => Some(format!("won bet {}", bet_id)),
+                None => txout.and_then(|txout| labels.get(&txout.script_pubkey).cloned()),
+            };
+            let confirmations = match bet_id {
+                Some(_) => None,
+                None => wallet
+                    .query_db(|db| db.get_tx(&outpoint.txid, false))
+                    .unwrap_or(None)
+                    .and_then(|tx| {
+                        let height = tx.confirmation_time.map(|time| time.height);
+                        tip_height.map(|tip_height| confirmations(height, tip_height))
+                    }),
+            };
+            ReviewInput {
+                outpoint,
+                value: Amount::from_sat(txout.map(|txout| txout.value).unwrap_or(0)),
+                label,
+                confirmations,
+            }
+        })
+        .collect();
+
+    let outputs = psbt
+        .global
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|txout| {
+            let script_pubkey = &txout.script_pubkey;
+            // change sent to another descriptor of our seed is ours too
+            let is_mine =
+                Some(script_pubkey) == change || wallet.is_mine(script_pubkey).unwrap_or(false);
+            ReviewOutput {
+                destination: Address::from_script(script_pubkey, network)
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|| script_pubkey.to_string()),
+                value: Amount::from_sat(txout.value),
+                is_change: is_mine && !recipients.contains(script_pubkey),
+                is_mine,
+                label: labels.get(script_pubkey).cloned(),
+                reused: seen_scripts.contains(script_pubkey),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let amount = outputs
+        .iter()
+        .filter(|output| !output.is_change)
+        .fold(Amount::ZERO, |total, output| total + output.value);
+    let (fee, feerate) = psbt.fee();
+
+    let estimates = FEE_ESTIMATE_TARGETS
+        .iter()
+        .filter_map(|target| match wallet.client().estimate_fee(*target) {
+            Ok(estimate) => Some((*target, estimate)),
+            Err(e) => {
+                tracing::warn!("couldn't estimate the feerate for {} blocks: {}", target, e);
+                None
+            }
+        })
+        .collect();
+
+    Ok(SendReview {
+        inputs,
+        outputs,
+        amount,
+        fee,
+        feerate,
+        estimates,
+        rbf: crate::psbt_ext::signals_rbf(&psbt.global.unsigned_tx),
+        size: TxSize::for_wallet(wallet, psbt),
+        history,
+    })
+}
+
+impl SendReview {
+    fn fee_percent(&self) -> Option<f32> {
+        if self.amount == Amount::ZERO {
+            return None;
+        }
+        Some(self.fee.as_sat() as f32 * 100.0 / self.amount.as_sat() as f32)
+    }
+
+    fn estimate(&self, target: usize) -> Option<FeeRate> {
+        self.estimates
+            .iter()
+            .find(|(estimate_target, _)| *estimate_target == target)
+            .map(|(_, estimate)| *estimate)
+    }
+
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = vec![];
+        let mut warn =
+            |high_risk: bool, message: String| warnings.push(Warning { message, high_risk });
+
+        if let Some(fee_percent) = self.fee_percent() {
+            if fee_percent >= HIGH_FEE_PERCENT {
+                warn(
+                    true,
+                    format!("the fee is {:.1}% of the amount being sent", fee_percent),
+                );
+            }
+        }
+        if let Some(next_block) = self.estimate(1) {
+            if self.feerate.as_sat_vb() > next_block.as_sat_vb() * HIGH_FEERATE_MULTIPLE {
+                warn(
+                    true,
+                    format!(
+                        "the feerate is more than {} times the {:.1} sat/vB estimated to get into the next block",
+                        HIGH_FEERATE_MULTIPLE, next_block.as_sat_vb()
+                    ),
+                );
+            }
+        }
+        if let Some(slowest) = FEE_ESTIMATE_TARGETS
+            .last()
+            .and_then(|target| self.estimate(*target).map(|estimate| (target, estimate)))
+        {
+            let (target, estimate) = slowest;
+            if self.feerate.as_sat_vb() < estimate.as_sat_vb() {
+                warn(
+                    false,
+                    format!(
+                        "the feerate is below the {:.1} sat/vB estimated to confirm within {} blocks",
+                        estimate.as_sat_vb(),
+                        target
+                    ),
+                );
+            }
+        }
+        if let Some(history) = &self.history {
+            for output in self
+                .outputs
+                .iter()
+                .filter(|output| !output.is_change && !output.is_mine)
+            {
+                if let Some(why) = history.unusual(output.value) {
+                    warn(true, format!("sending to {}: {}", output.destination, why));
+                }
+            }
+        }
+        for output in self.outputs.iter().filter(|output| output.reused) {
+            if output.is_mine {
+                warn(
+                    false,
+                    format!(
+                        "your address {} has received coins before",
+                        output.destination
+                    ),
+                );
+            } else {
+                warn(
+                    true,
+                    format!(
+                        "{} has been paid before -- reusing addresses harms the privacy of both sides",
+                        output.destination
+                    ),
+                );
+            }
+        }
+        let unconfirmed = self
+            .inputs
+            .iter()
+            .filter(|input| input.confirmations == Some(0))
+            .count();
+        if unconfirmed > 0 {
+            warn(
+                false,
+                format!(
+                    "{} input(s) are unconfirmed so this can't confirm before they do",
+                    unconfirmed
+                ),
+            );
+        }
+        if !self.rbf {
+            warn(
+                false,
+                "RBF is disabled so the fee can't be bumped if it gets stuck".to_string(),
+            );
+        }
+
+        warnings
+    }
+
+    pub fn render(&self) -> String {
+        let mut table = Table::new();
+        let mut header = Some("in");
+        for (input, weight) in self.inputs.iter().zip(&self.size.input_weights) {
+            table.add_row(Row::new(vec![
+                header.take().unwrap_or("").to_string(),
+                input.outpoint.to_string(),
+                format_amount(input.value),
+                input.label.clone().unwrap_or_default(),
+                match input.confirmations {
+                    Some(0) => "unconfirmed".to_string(),
+                    Some(confirmations) => format!("{} confirmations", confirmations),
+                    None => "".to_string(),
+                },
+                format_vbytes(*weight),
+            ]));
+        }
+
+        let mut header = Some("out");
+        for (output, weight) in self.outputs.iter().zip(&self.size.output_weights) {
+            let kind = if output.is_change {
+                "change".to_string()
+            } else {
+                output.label.clone().unwrap_or_default()
+            };
+            table.add_row(Row::new(vec![
+                header.take().unwrap_or("").to_string(),
+                output.destination.clone(),
+                format_amount(output.value),
+                kind,
+                if output.reused {
+                    "used before".to_string()
+                } else {
+                    "".to_string()
+                },
+                format_vbytes(*weight),
+            ]));
+        }
+
+        table.add_row(Row::new(vec![
+            "fee".to_string(),
+            format!("{} sats", self.fee.as_sat()),
+            format_amount(self.fee),
+            self.fee_percent()
+                .map(|fee_percent| format!("{:.2}% of {}", fee_percent, format_amount(self.amount)))
+                .unwrap_or_default(),
+            "".to_string(),
+            "".to_string(),
+        ]));
+        table.add_row(Row::new(vec![
+            "feerate".to_string(),
+            format!("{:.1} sat/vB", self.feerate.as_sat_vb()),
+            "".to_string(),
+            self.estimates
+                .iter()
+                .map(|(target, estimate)| {
+                    format!("{} block(s): {:.1}", target, estimate.as_sat_vb())
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            "".to_string(),
+            "".to_string(),
+        ]));
+        table.add_row(Row::new(vec![
+            "size".to_string(),
+            self.size.summary(),
+            "".to_string(),
+            format!("{} overhead", format_vbytes(self.size.overhead_weight)),
+            "".to_string(),
+            "".to_string(),
+        ]));
+        table.add_row(Row::new(vec![
+            "rbf".to_string(),
+            if self.rbf { "enabled" } else { "disabled" }.to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+        ]));
+
+        let mut rendered = table.render();
+        for warning in self.warnings() {
+            rendered.push_str(&format!(
+                "{} {}\n",
+                if warning.high_risk { "!!" } else { " !" },
+                warning.message
+            ));
+        }
+        rendered
+    }
+
+    /// Shows the review and asks whether to go ahead. High risk sends have to be confirmed by
+    /// typing [`TYPED_CONFIRMATION`].
+    pub fn confirm(&self) -> bool {
+        println!("{}", self.render());
+        if !self.warnings().iter().any(|warning| warning.high_risk) {
+            return read_answer("Broadcast this transaction");
+        }
+
+        use std::io::BufRead;
+        // the word to type stays the same in every language so it can't be mistaken for a yes
+        println!(
+            "{}",
+            tr_args(
+                "This send looks risky (see the !! warnings). Type \"{word}\" to broadcast it anyway:",
+                &[("word", &TYPED_CONFIRMATION)]
+            )
+        );
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line).is_err() {
+            return false;
+        }
+        line.trim() == TYPED_CONFIRMATION
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn review(fee: u64, feerate: f32, reused: bool) -> SendReview {
+        SendReview {
+            inputs: vec![],
+            outputs: vec![ReviewOutput {
+                destination: "bc1qrecipient".into(),
+                value: Amount::from_sat(100_000),
+                is_change: false,
+                is_mine: false,
+                label: None,
+                reused,
+            }],
+            amount: Amount::from_sat(100_000),
+            fee: Amount::from_sat(fee),
+            feerate: FeeRate::from_sat_per_vb(feerate),
+            estimates: vec![
+                (1, FeeRate::from_sat_per_vb(10.0)),
+                (3, FeeRate::from_sat_per_vb(5.0)),
+                (6, FeeRate::from_sat_per_vb(2.0)),
+            ],
+            rbf: true,
+            size: TxSize {
+                overhead_weight: 42,
+                input_weights: vec![],
+                output_weights: vec![124],
+                estimated: false,
+            },
+            history: None,
+        }
+    }
+
+    fn high_risk(review: &SendReview) -> usize {
+        review
+            .warnings()
+            .iter()
+            .filter(|warning| warning.high_risk)
+            .count()
+    }
+
+    #[test]
+    fn high_risk_sends() {
+        assert_eq!(high_risk(&review(1_000, 8.0, false)), 0);
+        assert_eq!(high_risk(&review(10_000, 8.0, false)), 1);
+        assert_eq!(high_risk(&review(1_000, 25.0, false)), 1);
+        assert_eq!(high_risk(&review(1_000, 8.0, true)), 1);
+        assert_eq!(review(1_000, 1.0, false).warnings().len(), 1);
+    }
+
+    #[test]
+    fn unusual_amounts() {
+        assert_eq!(SendHistory::new(vec![10_000, 20_000]), None);
+        let history = SendHistory::new(vec![90_000, 10_000, 100_000, 120_000]).unwrap();
+        assert_eq!(history.median, Amount::from_sat(90_000));
+        assert!(history.unusual(Amount::from_sat(100_000)).is_none());
+        assert!(history.unusual(Amount::from_sat(600_000)).is_none());
+        // 0.9 BTC when 0.9 mBTC was meant
+        let big = history.unusual(Amount::from_sat(90_000_000)).unwrap();
+        assert!(big.contains("1000 times"), "{}", big);
+        assert!(big.contains("did you mean"), "{}", big);
+        // 900 sats when 900 bits was meant
+        let small = history.unusual(Amount::from_sat(900)).unwrap();
+        assert!(small.contains("did you mean"), "{}", small);
+        // small but not like another unit
+        assert!(history.unusual(Amount::from_sat(3)).is_none());
+
+        let mut review = review(1_000, 8.0, false);
+        review.history = Some(history);
+        review.outputs[0].value = Amount::from_sat(90_000_000);
+        assert_eq!(high_risk(&review), 1);
+    }
+}
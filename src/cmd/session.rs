@@ -0,0 +1,346 @@
+use super::*;
+use crate::{
+    item,
+    session::{self, Session, SpendingLock, SPENDING_PASSPHRASE_ENV},
+};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// Starts a spending session that lasts `length` (e.g. `15m`).
+pub fn run_unlock(wallet_dir: &PathBuf, length: String) -> anyhow::Result<CmdOutput> {
+    let config = load_config(wallet_dir)?;
+    let spending_lock = config.spending_lock.ok_or(anyhow!(
+        "this wallet doesn't need unlocking. Use `gun lock --setup` to make it."
+    ))?;
+    let length = session::parse_session_length(&length)?;
+    let until = crate::clock::real_now() + length;
+    session::check_session_end(until, spending_lock.max_session_minutes)?;
+    let passphrase = read_passphrase(SPENDING_PASSPHRASE_ENV, "spending passphrase", false)?;
+    open_seed_words(wallet_dir, &spending_lock, &passphrase)?;
+
+    // the new session replaces any old one
+    session::lock(wallet_dir);
+    let mut agent = Command::new(std::env::current_exe()?);
+    agent
+        .arg("-d")
+        .arg(wallet_dir)
+        .arg("agent")
+        .arg("--until")
+        .arg(until.timestamp().to_string());
+    let mut child = agent
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("starting the agent")?;
+    // the agent checks the passphrase itself so it can't be started without it and works out
+    // whether it's the decoy's so that isn't on its command line
+    {
+        use std::io::Write;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        writeln!(stdin, "{}", passphrase).context("giving the agent the passphrase")?;
+    }
+
+    // the output is the same for the decoy so someone looking on can't tell
+    for _ in 0..50 {
+        if session::current(wallet_dir).is_some() {
+            return Ok(item! { "unlocked-until" => Cell::datetime(until) });
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    Err(anyhow!("the agent didn't start"))
+}
+
+/// Ends the spending session or with `setup` sets the spending passphrase. With `decoy` it sets
+/// the decoy wallet and its passphrase instead.
+pub fn run_lock(
+    wallet_dir: &PathBuf,
+    setup: bool,
+    remove: bool,
+    decoy: Option<PathBuf>,
+) -> anyhow::Result<CmdOutput> {
+    if let Some(decoy_dir) = decoy {
+        set_decoy(wallet_dir, decoy_dir)?;
+    } else if setup || remove {
+        let mut config = load_config(wallet_dir)?;
+        let seed_words = match (&config.spending_lock, setup) {
+            (Some(spending_lock), _) => {
+                let passphrase = read_passphrase(
+                    SPENDING_PASSPHRASE_ENV,
+                    "current spending passphrase",
+                    false,
+                )?;
+                if !spending_lock.has_passphrase(&passphrase) {
+                    return Err(anyhow!("that's not the spending passphrase"));
+                }
+                read_locked_seed_words(wallet_dir, &passphrase)?
+            }
+            (None, false) => return Err(anyhow!("this wallet doesn't have a spending lock")),
+            (None, true) => read_seed_words(wallet_dir)?,
+        };
+        let new_passphrase = match setup {
+            true => Some(Zeroizing::new(read_passphrase(
+                "GUN_NEW_SPENDING_PASSPHRASE",
+                "new spending passphrase",
+                true,
+            )?)),
+            false => None,
+        };
+        config.spending_lock = match &new_passphrase {
+            Some(new_passphrase) => {
+                let new_lock = SpendingLock::new(new_passphrase);
+                Some(SpendingLock {
+                    // changing the passphrase keeps the decoy
+                    decoy_salt: config
+                        .spending_lock
+                        .as_ref()
+                        .map(|old| old.decoy_salt.clone())
+                        .unwrap_or_else(|| new_lock.decoy_salt.clone()),
+                    max_session_minutes: config
+                        .spending_lock
+                        .as_ref()
+                        .map(|old| old.max_session_minutes)
+                        .unwrap_or(new_lock.max_session_minutes),
+                    ..new_lock
+                })
+            }
+            None => None,
+        };
+        write_config(wallet_dir, &config)?;
+        match &new_passphrase {
+            Some(new_passphrase) => lock_seed_words(wallet_dir, &seed_words, new_passphrase)?,
+            None => {
+                fs::write(get_seed_words_file(wallet_dir), seed_words.as_bytes())?;
+                let locked_seed_file = session::locked_seed_file(wallet_dir);
+                if locked_seed_file.exists() {
+                    fs::remove_file(locked_seed_file)?;
+                }
+            }
+        }
+    }
+    if session::lock(wallet_dir) {
+        eprintln!("the spending session has ended");
+    }
+    Ok(CmdOutput::None)
+}
+
+/// Moves the wallet in `decoy_dir` to where the decoy passphrase says the decoy is kept (see
+/// [`SpendingLock::decoy_wallet_dir`]). The config isn't touched.
+fn set_decoy(wallet_dir: &PathBuf, decoy_dir: PathBuf) -> anyhow::Result<()> {
+    let config = load_config(wallet_dir)?;
+    let spending_lock = config.spending_lock.ok_or(anyhow!(
+        "a decoy needs a spending lock. Use `gun lock --setup` first."
+    ))?;
+    let passphrase = read_passphrase(SPENDING_PASSPHRASE_ENV, "spending passphrase", false)?;
+    if !spending_lock.has_passphrase(&passphrase) {
+        return Err(anyhow!("that's not the spending passphrase"));
+    }
+    let decoy_passphrase = read_passphrase("GUN_DECOY_PASSPHRASE", "decoy passphrase", true)?;
+    if spending_lock.has_passphrase(&decoy_passphrase) {
+        return Err(anyhow!(
+            "the decoy passphrase can't be the same as the spending passphrase"
+        ));
+    }
+    move_decoy(wallet_dir, &spending_lock, &decoy_dir, &decoy_passphrase)?;
+    eprintln!(
+        "{} is now the decoy. Unlocking with the decoy passphrase opens it.",
+        decoy_dir.display()
+    );
+    Ok(())
+}
+
+/// Moves the wallet in `decoy_dir` to where `decoy_passphrase` says it's kept and locks its seed
+/// words with the decoy passphrase.
+fn move_decoy(
+    wallet_dir: &PathBuf,
+    spending_lock: &SpendingLock,
+    decoy_dir: &PathBuf,
+    decoy_passphrase: &str,
+) -> anyhow::Result<()> {
+    if decoy_dir.canonicalize().ok() == wallet_dir.canonicalize().ok() {
+        return Err(anyhow!("the decoy needs a wallet directory of its own"));
+    }
+    load_config(&decoy_dir).with_context(|| {
+        format!(
+            "there's no wallet in {} yet. Make one with `gun -d {} init`.",
+            decoy_dir.display(),
+            decoy_dir.display()
+        )
+    })?;
+    if session::locked_seed_file(&decoy_dir).exists() {
+        return Err(anyhow!(
+            "the decoy's seed words are locked. Remove its own spending lock first."
+        ));
+    }
+    let decoy_wallet_dir = spending_lock.decoy_wallet_dir(wallet_dir, decoy_passphrase);
+    if decoy_wallet_dir.exists() {
+        return Err(anyhow!("there's already a decoy with that passphrase"));
+    }
+    std::fs::rename(decoy_dir, &decoy_wallet_dir).with_context(|| {
+        format!(
+            "moving {} into {} (it has to be on the same filesystem)",
+            decoy_dir.display(),
+            wallet_dir.display()
+        )
+    })?;
+    // the decoy's seed words are locked like the real wallet's so nothing but the passphrases
+    // tells them apart and the decoy's can't open the real wallet's
+    let seed_words = read_seed_words(&decoy_wallet_dir)?;
+    lock_seed_words(&decoy_wallet_dir, &seed_words, decoy_passphrase)
+}
+
+/// Fails unless `passphrase` is the spending passphrase or a decoy's. Returns the decoy's wallet
+/// directory if it's a decoy's.
+fn unlocks_decoy(
+    wallet_dir: &PathBuf,
+    spending_lock: &SpendingLock,
+    passphrase: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    if spending_lock.has_passphrase(passphrase) {
+        return Ok(None);
+    }
+    let decoy_wallet_dir = spending_lock.decoy_wallet_dir(wallet_dir, passphrase);
+    match decoy_wallet_dir.join("config.json").exists() {
+        true => Ok(Some(decoy_wallet_dir)),
+        false => Err(anyhow!("that's not the spending passphrase")),
+    }
+}
+
+/// Checks `passphrase` and decrypts the seed words it unlocks, the wallet's own for the spending
+/// passphrase or the decoy's for a decoy passphrase. Returns the decoy's wallet directory too if
+/// it's a decoy's. Seed words still in `seed.txt` from before they were locked get locked here.
+fn open_seed_words(
+    wallet_dir: &PathBuf,
+    spending_lock: &SpendingLock,
+    passphrase: &str,
+) -> anyhow::Result<(Option<PathBuf>, Zeroizing<String>)> {
+    let decoy = unlocks_decoy(wallet_dir, spending_lock, passphrase)?;
+    let seed_dir = decoy.clone().unwrap_or_else(|| wallet_dir.clone());
+    if !session::locked_seed_file(&seed_dir).exists() {
+        let seed_words = read_seed_words(&seed_dir)?;
+        lock_seed_words(&seed_dir, &seed_words, passphrase)?;
+    }
+    Ok((decoy, read_locked_seed_words(&seed_dir, passphrase)?))
+}
+
+/// Decrypts the seed words in `seed.locked` or reads them from `seed.txt` if they were never
+/// locked.
+fn read_locked_seed_words(
+    wallet_dir: &PathBuf,
+    passphrase: &str,
+) -> anyhow::Result<Zeroizing<String>> {
+    let locked_seed_file = session::locked_seed_file(wallet_dir);
+    match locked_seed_file.exists() {
+        true => session::open_seed_words(&fs::read(locked_seed_file)?, passphrase),
+        false => read_seed_words(wallet_dir),
+    }
+}
+
+/// Writes `seed_words` encrypted under `passphrase` to `seed.locked` and deletes `seed.txt`.
+fn lock_seed_words(wallet_dir: &PathBuf, seed_words: &str, passphrase: &str) -> anyhow::Result<()> {
+    fs::write(
+        session::locked_seed_file(wallet_dir),
+        session::seal_seed_words(seed_words, passphrase),
+    )
+    .context("writing the locked seed words")?;
+    let seed_words_file = get_seed_words_file(wallet_dir);
+    if seed_words_file.exists() {
+        fs::remove_file(seed_words_file).context("deleting the unlocked seed words")?;
+    }
+    Ok(())
+}
+
+/// Returns the wallet directory commands should use (see [`session::select_wallet_dir`]).
+pub fn select_wallet_dir(wallet_dir: &PathBuf) -> PathBuf {
+    let has_spending_lock = load_config(wallet_dir)
+        .map(|config| config.spending_lock.is_some())
+        .unwrap_or(false);
+    match has_spending_lock {
+        true => session::select_wallet_dir(wallet_dir),
+        false => wallet_dir.clone(),
+    }
+}
+
+/// What `gun unlock` runs in the background to hold the session open. The passphrase is read from
+/// stdin and checked again here so running `gun agent` by hand doesn't get around `gun unlock`.
+pub fn run_agent(wallet_dir: &PathBuf, until: i64) -> anyhow::Result<CmdOutput> {
+    use std::io::BufRead;
+    let spending_lock = load_config(wallet_dir)?
+        .spending_lock
+        .ok_or(anyhow!("this wallet doesn't have a spending lock"))?;
+    let mut passphrase = String::new();
+    std::io::stdin().lock().read_line(&mut passphrase)?;
+    let passphrase = Zeroizing::new(passphrase);
+    let passphrase = passphrase.trim_end_matches(&['\r', '\n'][..]);
+    let (decoy, seed_words) = open_seed_words(wallet_dir, &spending_lock, passphrase)?;
+    let until = crate::chrono::NaiveDateTime::from_timestamp(until, 0);
+    session::check_session_end(until, spending_lock.max_session_minutes)?;
+    session::serve(
+        &session::socket_path(wallet_dir),
+        &Session { until, decoy },
+        seed_words,
+    )?;
+    Ok(CmdOutput::None)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        cmd::init::{create_wallet_dir, generate_seed_words},
+        config::Config,
+    };
+    use bdk::bitcoin::Network;
+
+    #[test]
+    fn decoy_passphrase_never_opens_the_real_wallet() {
+        let temp_dir = |name: &str| {
+            std::env::temp_dir().join(format!("gun-decoy-test-{}-{}", std::process::id(), name))
+        };
+        let (wallet_dir, decoy_dir) = (temp_dir("wallet"), temp_dir("decoy"));
+        let spending_lock = SpendingLock::new("hunter2");
+        let mut config = Config::default_config(Network::Regtest);
+        config.spending_lock = Some(spending_lock.clone());
+        let real_seed_words = generate_seed_words(12).unwrap();
+        create_wallet_dir(&wallet_dir, &config, &real_seed_words).unwrap();
+        lock_seed_words(&wallet_dir, &real_seed_words, "hunter2").unwrap();
+        let decoy_seed_words = generate_seed_words(12).unwrap();
+        create_wallet_dir(
+            &decoy_dir,
+            &Config::default_config(Network::Regtest),
+            &decoy_seed_words,
+        )
+        .unwrap();
+
+        let moved = move_decoy(&wallet_dir, &spending_lock, &decoy_dir, "correct horse");
+        let decoy_wallet_dir = spending_lock.decoy_wallet_dir(&wallet_dir, "correct horse");
+        let unlocked_with_decoy = open_seed_words(&wallet_dir, &spending_lock, "correct horse");
+        let real_with_decoy_passphrase = read_locked_seed_words(&wallet_dir, "correct horse");
+        let unlocked = open_seed_words(&wallet_dir, &spending_lock, "hunter2");
+        let unlocked_seed_files = [&wallet_dir, &decoy_wallet_dir]
+            .iter()
+            .filter(|dir| get_seed_words_file(dir).exists())
+            .count();
+        fs::remove_dir_all(&wallet_dir).unwrap();
+        let _ = fs::remove_dir_all(&decoy_dir);
+
+        moved.unwrap();
+        assert_eq!(unlocked_seed_files, 0);
+        let real_xprv = keychain_from_seed_words(&real_seed_words)
+            .unwrap()
+            .main_wallet_xprv(Network::Regtest);
+        let (decoy, seed_words) = unlocked_with_decoy.unwrap();
+        assert_eq!(decoy, Some(decoy_wallet_dir));
+        assert_eq!(seed_words.as_str(), decoy_seed_words);
+        assert_ne!(
+            keychain_from_seed_words(&seed_words)
+                .unwrap()
+                .main_wallet_xprv(Network::Regtest),
+            real_xprv
+        );
+        assert!(real_with_decoy_passphrase.is_err());
+        let (decoy, seed_words) = unlocked.unwrap();
+        assert_eq!(decoy, None);
+        assert_eq!(seed_words.as_str(), real_seed_words);
+    }
+}
@@ -0,0 +1,148 @@
+use super::*;
+use crate::{
+    betting::BetState,
+    item,
+    state::{self, BetSnapshot, Snapshot, TxSnapshot, UtxoSnapshot},
+};
+use bdk::{blockchain::Blockchain, database::Database, KeychainKind};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Save what the wallet thinks its state is and compare saved states (e.g. for bug reports)
+pub enum StateOpt {
+    /// Write the wallet's UTXOs, transactions, bets and balance to a file. It has no keys in it.
+    Snapshot {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Show what is different between two snapshots
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+    },
+}
+
+pub fn run_state_cmd(wallet_dir: &PathBuf, opt: StateOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        StateOpt::Snapshot { file } => {
+            let snapshot = take_snapshot(wallet_dir)?;
+            fs::write(&file, serde_json::to_string_pretty(&snapshot).unwrap())
+                .with_context(|| format!("writing {}", file.display()))?;
+            Ok(item! {
+                "file" => Cell::string(file.display()),
+                "utxos" => Cell::Int(snapshot.utxos.len() as u64),
+                "transactions" => Cell::Int(snapshot.transactions.len() as u64),
+                "bets" => Cell::Int(snapshot.bets.len() as u64),
+            })
+        }
+        StateOpt::Diff { a, b } => {
+            let load = |file: &PathBuf| -> anyhow::Result<Snapshot> {
+                serde_json::from_str(
+                    &fs::read_to_string(file)
+                        .with_context(|| format!("reading {}", file.display()))?,
+                )
+                .with_context(|| format!("decoding {}", file.display()))
+            };
+            let (a, b) = (load(&a)?, load(&b)?);
+            if a.network != b.network {
+                return Err(anyhow!(
+                    "one snapshot is of a {} wallet and the other is {}",
+                    a.network,
+                    b.network
+                ));
+            }
+            Ok(CmdOutput::table(
+                vec!["what", "before", "after"],
+                state::diff(&a, &b)
+                    .into_iter()
+                    .map(|difference| {
+                        vec![
+                            Cell::String(difference.path),
+                            difference.before.map(Cell::String).unwrap_or(Cell::Empty),
+                            difference.after.map(Cell::String).unwrap_or(Cell::Empty),
+                        ]
+                    })
+                    .collect(),
+            ))
+        }
+    }
+}
+
+pub(crate) fn take_snapshot(wallet_dir: &PathBuf) -> anyhow::Result<Snapshot> {
+    let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+    let frozen = bet_db.frozen_utxos()?;
+    let in_use = bet_db.currently_used_utxos(&[])?;
+    let coinjoin_outputs = bet_db.coinjoin_outputs()?;
+    let mut memos = bet_db.tx_memos()?;
+
+    let utxos = wallet
+        .list_unspent()?
+        .into_iter()
+        .map(|utxo| {
+            (
+                utxo.outpoint.to_string(),
+                UtxoSnapshot {
+                    value: Amount::from_sat(utxo.txout.value),
+                    keychain: match utxo.keychain {
+                        KeychainKind::Internal => "internal",
+                        KeychainKind::External => "external",
+                    }
+                    .to_string(),
+                    frozen: frozen.contains(&utxo.outpoint),
+                    in_use: in_use.contains(&utxo.outpoint),
+                    coinjoin: coinjoin_outputs.contains_key(&utxo.outpoint),
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let transactions = wallet
+        .list_transactions(false)?
+        .into_iter()
+        .map(|tx| {
+            (
+                tx.txid.to_string(),
+                TxSnapshot {
+                    received: Amount::from_sat(tx.received),
+                    sent: Amount::from_sat(tx.sent),
+                    fee: tx.fee.map(Amount::from_sat),
+                    height: tx.confirmation_time.map(|time| time.height),
+                    memo: memos.remove(&tx.txid),
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let bets = bet_db
+        .list_entities_print_error::<BetState>()
+        .map(|(bet_id, bet_state)| (bet_id, BetSnapshot::from(&bet_state)))
+        .collect();
+
+    let (last_external_index, last_internal_index) = wallet.query_db(|wallet_db| {
+        Ok::<_, anyhow::Error>((
+            wallet_db.get_last_index(KeychainKind::External)?,
+            wallet_db.get_last_index(KeychainKind::Internal)?,
+        ))
+    })?;
+
+    Ok(Snapshot {
+        taken_at: crate::clock::now(),
+        gun_version: env!("CARGO_PKG_VERSION").to_string(),
+        network: wallet.network(),
+        // a snapshot is still useful without a connection to the backend
+        tip_height: wallet.client().get_height().ok(),
+        balance: Amount::from_sat(wallet.get_balance()?),
+        last_external_index,
+        last_internal_index,
+        utxos,
+        transactions,
+        bets,
+        pending_psbts: bet_db
+            .list_entities_print_error::<crate::betting::PendingPsbt>()
+            .map(|(txid, _)| txid.to_string())
+            .collect(),
+    })
+}
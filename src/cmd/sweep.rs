@@ -0,0 +1,648 @@
+use super::*;
+use bdk::{
+    bitcoin::{
+        consensus::encode,
+        secp256k1::Secp256k1,
+        util::{bip32::ExtendedPrivKey, psbt},
+        PrivateKey, Transaction,
+    },
+    blockchain::noop_progress,
+    database::MemoryDatabase,
+    descriptor::{ExtendedDescriptor, IntoWalletDescriptor},
+    miniscript::DescriptorTrait,
+    wallet::AddressIndex,
+    LocalUtxo, SignOptions,
+};
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// The most coins a single sweep transaction spends. More than this are swept with several
+/// transactions to stay well under the standard transaction size.
+const MAX_SWEEP_INPUTS: usize = 200;
+
+/// How often (in addresses) to report progress while scanning a descriptor.
+const SCAN_PROGRESS_INTERVAL: u32 = 100;
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct SweepArgs {
+    #[structopt(flatten)]
+    fee_args: FeeArgs,
+    #[structopt(flatten)]
+    rbf_args: RbfArgs,
+    /// Don't prompt for answers just answer yes.
+    #[structopt(long, short)]
+    yes: bool,
+    /// Print the resulting transaction(s) out in hex instead of broadcasting them.
+    #[structopt(long)]
+    print_tx: bool,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct SweepKeyOpt {
+    /// The private key in WIF (e.g. from a paper wallet)
+    key: String,
+    #[structopt(flatten)]
+    sweep_args: SweepArgs,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct SweepDescriptorOpt {
+    /// The descriptor to sweep. It needs its private keys.
+    descriptor: String,
+    /// The derivation indexes to scan if the descriptor has a wildcard e.g. 0-1000 (the end isn't
+    /// included)
+    #[structopt(long, default_value = "0-1000")]
+    range: IndexRange,
+    #[structopt(flatten)]
+    sweep_args: SweepArgs,
+}
+
+/// Look for coins on the derivation paths other wallets use for the same seed words.
+///
+/// Each path is synced like a wallet (up to the stop gap of the backend config) so coins on any
+/// address that's been used are found. What's found can only be swept into this wallet -- a gun
+/// wallet has one pair of descriptors so the paths can't be kept as extra keychains. Use `gun
+/// whoami` to see which path the wallet itself is on.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ScanPathsOpt {
+    /// Which accounts of each standard to scan
+    #[structopt(long, default_value = "0,1", use_delimiter = true)]
+    accounts: Vec<u32>,
+    /// Only show what's on each path without sweeping it
+    #[structopt(long)]
+    no_sweep: bool,
+    #[structopt(flatten)]
+    sweep_args: SweepArgs,
+}
+
+/// The derivation standards wallets commonly use for BIP39 seed words.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DerivationStandard {
+    /// p2pkh at m/44'
+    Bip44,
+    /// p2wpkh nested in p2sh at m/49'
+    Bip49,
+    /// p2wpkh at m/84' (what gun uses)
+    Bip84,
+    /// p2tr at m/86'
+    Bip86,
+}
+
+impl DerivationStandard {
+    pub const ALL: [DerivationStandard; 4] = [
+        DerivationStandard::Bip44,
+        DerivationStandard::Bip49,
+        DerivationStandard::Bip84,
+        DerivationStandard::Bip86,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DerivationStandard::Bip44 => "BIP44",
+            DerivationStandard::Bip49 => "BIP49",
+            DerivationStandard::Bip84 => "BIP84",
+            DerivationStandard::Bip86 => "BIP86",
+        }
+    }
+
+    fn purpose(&self) -> u32 {
+        match self {
+            DerivationStandard::Bip44 => 44,
+            DerivationStandard::Bip49 => 49,
+            DerivationStandard::Bip84 => 84,
+            DerivationStandard::Bip86 => 86,
+        }
+    }
+
+    pub fn path(&self, network: Network, account: u32) -> String {
+        let coin_type = match network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        format!("m/{}'/{}'/{}'", self.purpose(), coin_type, account)
+    }
+
+    /// The receive and change descriptors (with private keys) of `account`. `None` for taproot
+    /// which the descriptor library gun is built with doesn't support yet.
+    pub fn descriptors(
+        &self,
+        xprv: &ExtendedPrivKey,
+        network: Network,
+        account: u32,
+    ) -> Option<(String, String)> {
+        let path = self.path(network, account);
+        let key = |change: u32| format!("{}{}/{}/*", xprv, &path[1..], change);
+        let wrap = |key: String| match self {
+            DerivationStandard::Bip44 => Some(format!("pkh({})", key)),
+            DerivationStandard::Bip49 => Some(format!("sh(wpkh({}))", key)),
+            DerivationStandard::Bip84 => Some(format!("wpkh({})", key)),
+            DerivationStandard::Bip86 => None,
+        };
+        Some((wrap(key(0))?, wrap(key(1))?))
+    }
+}
+
+/// A range of derivation indexes e.g. `0-1000`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FromStr for IndexRange {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        let (start, end) = string.split_once('-').ok_or(anyhow!(
+            "range should be in the form <start>-<end> e.g. 0-1000"
+        ))?;
+        let start = u32::from_str(start.trim()).context("parsing start of range")?;
+        let end = u32::from_str(end.trim()).context("parsing end of range")?;
+        if start >= end {
+            return Err(anyhow!("range {} is empty", string));
+        }
+        Ok(IndexRange { start, end })
+    }
+}
+
+/// A coin found on one of the addresses being swept
+pub struct FoundCoin {
+    pub utxo: LocalUtxo,
+    pub psbt_input: psbt::Input,
+    pub satisfaction_weight: usize,
+}
+
+/// The descriptors for every kind of address `key` may have received coins on.
+fn key_descriptors(key: &PrivateKey) -> Vec<String> {
+    let compressed = PrivateKey {
+        compressed: true,
+        ..*key
+    };
+    let uncompressed = PrivateKey {
+        compressed: false,
+        ..*key
+    };
+    vec![
+        format!("wpkh({})", compressed.to_wif()),
+        format!("pkh({})", compressed.to_wif()),
+        format!("pkh({})", uncompressed.to_wif()),
+    ]
+}
+
+pub fn run_sweep_key(wallet_dir: &PathBuf, opt: SweepKeyOpt) -> anyhow::Result<CmdOutput> {
+    let SweepKeyOpt { key, sweep_args } = opt;
+    let party = load_party(wallet_dir)?;
+    let network = party.wallet().network();
+    let mut key = PrivateKey::from_wif(key.trim()).context("parsing WIF private key")?;
+    // WIF only tells mainnet keys apart from the rest
+    if (key.network == Network::Bitcoin) != (network == Network::Bitcoin) {
+        return Err(anyhow!(
+            "the key is for {} but this wallet is on {}",
+            key.network,
+            network
+        ));
+    }
+    key.network = network;
+
+    let descriptors = key_descriptors(&key);
+    let mut coins = vec![];
+    for descriptor in &descriptors {
+        let (descriptor, _) = descriptor
+            .as_str()
+            .into_wallet_descriptor(&Secp256k1::new(), network)?;
+        coins.extend(scan_address(&party, descriptor)?);
+    }
+
+    if coins.is_empty() {
+        return Err(anyhow!("no coins were found on any address of the key"));
+    }
+    sweep_coins(&party, coins, &descriptors, sweep_args, None)
+}
+
+pub fn run_sweep_descriptor(
+    wallet_dir: &PathBuf,
+    opt: SweepDescriptorOpt,
+) -> anyhow::Result<CmdOutput> {
+    let SweepDescriptorOpt {
+        descriptor,
+        range,
+        sweep_args,
+    } = opt;
+    let party = load_party(wallet_dir)?;
+    let network = party.wallet().network();
+    let (parsed, keymap) = descriptor
+        .as_str()
+        .into_wallet_descriptor(&Secp256k1::new(), network)
+        .context("parsing descriptor to sweep")?;
+    if keymap.is_empty() {
+        return Err(anyhow!(
+            "the descriptor has no private keys so its coins can't be swept"
+        ));
+    }
+
+    let coins = scan_descriptor(&party, parsed, range)?;
+    if coins.is_empty() {
+        return Err(anyhow!("no coins were found on the descriptor"));
+    }
+    sweep_coins(&party, coins, &[descriptor], sweep_args, None)
+}
+
+pub fn run_scan_paths(wallet_dir: &PathBuf, opt: ScanPathsOpt) -> anyhow::Result<CmdOutput> {
+    let ScanPathsOpt {
+        accounts,
+        no_sweep,
+        sweep_args,
+    } = opt;
+    let party = load_party(wallet_dir)?;
+    let network = party.wallet().network();
+    let uses_seed_words = matches!(
+        load_config(wallet_dir)?.kind,
+        crate::config::WalletKind::P2wpkh
+    );
+    let xprv = party.keychain.main_wallet_xprv(network);
+
+    let mut rows = vec![];
+    for standard in DerivationStandard::ALL.iter() {
+        for account in &accounts {
+            let path = standard.path(network, *account);
+            let label = format!("{} account {} ({})", standard.name(), account, path);
+            let row = |transactions: Cell, value: Cell, swept: Cell| {
+                vec![
+                    Cell::string(standard.name()),
+                    Cell::String(path.clone()),
+                    transactions,
+                    value,
+                    swept,
+                ]
+            };
+            if uses_seed_words && *standard == DerivationStandard::Bip84 && *account == 0 {
+                // it's the wallet itself
+                continue;
+            }
+            let (external, internal) = match standard.descriptors(&xprv, network, *account) {
+                Some(descriptors) => descriptors,
+                None => {
+                    eprintln!("skipping {}: taproot isn't supported yet", label);
+                    rows.push(row(
+                        Cell::Empty,
+                        Cell::Empty,
+                        Cell::string("taproot not supported"),
+                    ));
+                    continue;
+                }
+            };
+            eprintln!("scanning {}...", label);
+            let (transactions, coins) = scan_path(&party, &external, &internal)?;
+            let value = Amount::from_sat(coins.iter().map(|coin| coin.utxo.txout.value).sum());
+            eprintln!(
+                "{} has {} transaction(s) and {} in {} coin(s)",
+                label,
+                transactions,
+                format_amount(value),
+                coins.len()
+            );
+            let swept = if coins.is_empty() || no_sweep {
+                Cell::Empty
+            } else {
+                let txs = sweep_transactions(
+                    &party,
+                    coins,
+                    &[external, internal],
+                    sweep_args.clone(),
+                    None,
+                )?;
+                Cell::List(
+                    txs.iter()
+                        .map(|tx| Box::new(Cell::string(tx.txid())))
+                        .collect(),
+                )
+            };
+            rows.push(row(
+                Cell::Int(transactions as u64),
+                Cell::Amount(value),
+                swept,
+            ));
+        }
+    }
+
+    Ok(CmdOutput::table(
+        vec!["standard", "path", "transactions", "value", "swept"],
+        rows,
+    ))
+}
+
+/// Syncs the receive and change descriptors of a path and returns how many transactions it has
+/// and its coins.
+fn scan_path<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    external: &str,
+    internal: &str,
+) -> anyhow::Result<(usize, Vec<FoundCoin>)> {
+    let network = party.wallet().network();
+    let (parsed, _) = external.into_wallet_descriptor(&Secp256k1::new(), network)?;
+    let satisfaction_weight = parsed.max_satisfaction_weight()?;
+    let scan_wallet = Wallet::new(
+        external,
+        Some(internal),
+        network,
+        MemoryDatabase::default(),
+        party.new_blockchain()?,
+    )?;
+    scan_wallet.sync(noop_progress(), None)?;
+    let transactions = scan_wallet.list_transactions(false)?.len();
+    let coins = scan_wallet
+        .list_unspent()?
+        .into_iter()
+        .map(|utxo| {
+            let psbt_input = scan_wallet.get_psbt_input(utxo.clone(), None, false)?;
+            Ok(FoundCoin {
+                utxo,
+                psbt_input,
+                satisfaction_weight,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok((transactions, coins))
+}
+
+/// Finds the coins on the addresses of `descriptor` in `range` if it has a wildcard.
+pub(crate) fn scan_descriptor<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    parsed: ExtendedDescriptor,
+    range: IndexRange,
+) -> anyhow::Result<Vec<FoundCoin>> {
+    let mut coins = vec![];
+    if parsed.is_deriveable() {
+        let total = range.end - range.start;
+        for index in range.start..range.end {
+            coins.extend(scan_address(party, parsed.derive(index))?);
+            let scanned = index - range.start + 1;
+            if scanned % SCAN_PROGRESS_INTERVAL == 0 || scanned == total {
+                eprintln!(
+                    "scanned {}/{} addresses and found {} coin(s)",
+                    scanned,
+                    total,
+                    coins.len()
+                );
+            }
+        }
+    } else {
+        coins.extend(scan_address(party, parsed)?);
+    }
+    Ok(coins)
+}
+
+/// Finds the coins on the address of a descriptor without a wildcard.
+fn scan_address<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    descriptor: ExtendedDescriptor,
+) -> anyhow::Result<Vec<FoundCoin>> {
+    let satisfaction_weight = descriptor.max_satisfaction_weight()?;
+    let scan_wallet = Wallet::new(
+        descriptor,
+        None,
+        party.wallet().network(),
+        MemoryDatabase::default(),
+        party.new_blockchain()?,
+    )?;
+    scan_wallet.sync(noop_progress(), None)?;
+    let coins = scan_wallet
+        .list_unspent()?
+        .into_iter()
+        .map(|utxo| {
+            let psbt_input = scan_wallet.get_psbt_input(utxo.clone(), None, false)?;
+            Ok(FoundCoin {
+                utxo,
+                psbt_input,
+                satisfaction_weight,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if !coins.is_empty() {
+        let value = coins.iter().map(|coin| coin.utxo.txout.value).sum();
+        eprintln!(
+            "found {} in {} coin(s) on {}",
+            format_amount(Amount::from_sat(value)),
+            coins.len(),
+            scan_wallet.get_address(AddressIndex::Peek(0))?.address
+        );
+    }
+
+    Ok(coins)
+}
+
+/// Sweeps `coins` into the wallet signing with the private keys in `descriptors`. `lock_time` is
+/// for descriptors that can only be spent after a height (see [`crate::allowance`]).
+///
+/// The keys are only used to sign the sweeps and aren't stored anywhere.
+pub(crate) fn sweep_coins<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    coins: Vec<FoundCoin>,
+    descriptors: &[String],
+    sweep_args: SweepArgs,
+    lock_time: Option<u32>,
+) -> anyhow::Result<CmdOutput> {
+    let print_tx = sweep_args.print_tx;
+    let swept = sweep_transactions(party, coins, descriptors, sweep_args, lock_time)?;
+    Ok(sweep_output(&swept, print_tx))
+}
+
+/// Like [`sweep_coins`] but returns the sweeps that were broadcast (or printed).
+fn sweep_transactions<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    coins: Vec<FoundCoin>,
+    descriptors: &[String],
+    sweep_args: SweepArgs,
+    lock_time: Option<u32>,
+) -> anyhow::Result<Vec<Transaction>> {
+    let SweepArgs {
+        fee_args,
+        rbf_args,
+        yes,
+        print_tx,
+    } = sweep_args;
+    let wallet = party.wallet();
+    let network = wallet.network();
+    let signing_wallets = descriptors
+        .iter()
+        .map(|descriptor| {
+            Wallet::new_offline(
+                descriptor.as_str(),
+                None,
+                network,
+                MemoryDatabase::default(),
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut swept = vec![];
+    let batches = coins.chunks(MAX_SWEEP_INPUTS).count();
+    for (i, batch) in coins.chunks(MAX_SWEEP_INPUTS).enumerate() {
+        if batches > 1 {
+            eprintln!("sweep transaction {} of {}", i + 1, batches);
+        }
+        let _span = tracing::info_span!("build_tx", kind = "sweep").entered();
+        let mut builder = wallet.build_tx();
+        builder.manually_selected_only();
+        if rbf_args.signal(party.settings().rbf.sends) {
+            builder.enable_rbf();
+        }
+        if let Some(lock_time) = lock_time {
+            builder.nlocktime(lock_time);
+        }
+        fee_args
+            .fee_spec(party.settings())
+            .apply_to_builder(wallet.client(), &mut builder)?;
+        for coin in batch {
+            builder.add_foreign_utxo(
+                coin.utxo.outpoint,
+                coin.psbt_input.clone(),
+                coin.satisfaction_weight,
+            )?;
+        }
+        builder.drain_to(
+            wallet
+                .get_address(AddressIndex::New)?
+                .address
+                .script_pubkey(),
+        );
+        let (mut psbt, _) = builder.finish().context("building sweep transaction")?;
+        crate::psbt_ext::log_built_tx(&psbt);
+
+        crate::read_only::check("sign the sweep")?;
+        for signing_wallet in &signing_wallets {
+            signing_wallet.sign(
+                &mut psbt,
+                SignOptions {
+                    trust_witness_utxo: true,
+                    assume_height: lock_time,
+                    ..Default::default()
+                },
+            )?;
+        }
+        let finalized = psbt
+            .inputs
+            .iter()
+            .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some());
+        if !finalized {
+            return Err(anyhow!(
+                "couldn't sign every coin being swept with the keys given"
+            ));
+        }
+        party.audit_psbt(AuditOperation::Sign, "sweep", &psbt)?;
+
+        let tx = psbt.clone().extract_tx();
+        let (_, txid) = decide_to_broadcast(
+            network,
+            wallet.client(),
+            psbt,
+            yes,
+            print_tx,
+            party.audit_log(),
+            party.bet_db(),
+            "sweep",
+        )?;
+        if txid.is_some() {
+            swept.push(tx);
+        }
+    }
+
+    Ok(swept)
+}
+
+/// Lists the txids of the sweeps or the transactions themselves if they were only printed.
+fn sweep_output(swept: &[Transaction], print_tx: bool) -> CmdOutput {
+    CmdOutput::List(
+        swept
+            .iter()
+            .map(|tx| {
+                if print_tx {
+                    Cell::String(crate::hex::encode(&encode::serialize(tx)))
+                } else {
+                    Cell::string(tx.txid())
+                }
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::secp256k1::SecretKey;
+
+    #[test]
+    fn key_descriptors_cover_every_address_kind() {
+        let key = PrivateKey {
+            compressed: false,
+            network: Network::Regtest,
+            key: SecretKey::from_slice(&[42u8; 32]).unwrap(),
+        };
+        let addresses = key_descriptors(&key)
+            .into_iter()
+            .map(|descriptor| {
+                Wallet::new_offline(
+                    descriptor.as_str(),
+                    None,
+                    Network::Regtest,
+                    MemoryDatabase::default(),
+                )
+                .unwrap()
+                .get_address(AddressIndex::Peek(0))
+                .unwrap()
+                .address
+                .to_string()
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(addresses.len(), 3);
+    }
+
+    #[test]
+    fn derivation_standard_descriptors() {
+        let xprv = Keychain::new([7u8; 64]).main_wallet_xprv(Network::Regtest);
+        let mut addresses = std::collections::HashSet::new();
+        for standard in DerivationStandard::ALL.iter() {
+            let (external, internal) = match standard.descriptors(&xprv, Network::Regtest, 1) {
+                Some(descriptors) => descriptors,
+                None => {
+                    assert_eq!(*standard, DerivationStandard::Bip86);
+                    continue;
+                }
+            };
+            assert!(external.contains(&format!("/{}'/1'/1'/0/*", standard.purpose())));
+            let wallet = Wallet::new_offline(
+                external.as_str(),
+                Some(internal.as_str()),
+                Network::Regtest,
+                MemoryDatabase::default(),
+            )
+            .unwrap();
+            addresses.insert(
+                wallet
+                    .get_address(AddressIndex::Peek(0))
+                    .unwrap()
+                    .address
+                    .to_string(),
+            );
+        }
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(
+            DerivationStandard::Bip84.path(Network::Bitcoin, 0),
+            "m/84'/0'/0'"
+        );
+    }
+
+    #[test]
+    fn parse_index_range() {
+        assert_eq!(
+            IndexRange::from_str("0-1000").unwrap(),
+            IndexRange {
+                start: 0,
+                end: 1000
+            }
+        );
+        assert!(IndexRange::from_str("5-5").is_err());
+        assert!(IndexRange::from_str("1000").is_err());
+    }
+}
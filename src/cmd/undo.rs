@@ -0,0 +1,62 @@
+use super::*;
+use crate::{audit::AuditOperation, item};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Undo the most recent change that didn't broadcast anything (labels, notes, freezes, tags and
+/// forgotten bets). Broadcasts can't be undone.
+pub struct UndoOpt {
+    /// List what can be undone (most recent first) along with the broadcasts that can't be
+    #[structopt(long)]
+    list: bool,
+}
+
+pub fn run_undo_cmd(wallet_dir: &PathBuf, opt: UndoOpt) -> anyhow::Result<CmdOutput> {
+    let bet_db = load_bet_db(wallet_dir)?;
+    if opt.list {
+        let config = load_config(wallet_dir)?;
+        let mut rows = bet_db
+            .undo_history()?
+            .into_iter()
+            .map(|(_, entry)| {
+                (
+                    entry.done_at,
+                    vec![
+                        Cell::datetime(entry.done_at),
+                        Cell::String(entry.description),
+                        Cell::string("yes"),
+                        Cell::String(entry.command.join(" ")),
+                    ],
+                )
+            })
+            .collect::<Vec<_>>();
+        for entry in AuditLog::new(wallet_dir, config.network).entries()? {
+            let record = entry.record;
+            if record.operation != AuditOperation::Broadcast {
+                continue;
+            }
+            rows.push((
+                record.time,
+                vec![
+                    Cell::datetime(record.time),
+                    Cell::String(format!("broadcast {} ({})", record.txid, record.context)),
+                    Cell::string("no"),
+                    Cell::String(record.command.join(" ")),
+                ],
+            ));
+        }
+        rows.sort_by(|(a, _), (b, _)| b.cmp(a));
+        return Ok(CmdOutput::table(
+            vec!["time", "action", "reversible", "command"],
+            rows.into_iter().map(|(_, row)| row).collect(),
+        ));
+    }
+
+    match bet_db.undo_last()? {
+        Some(entry) => Ok(item! {
+            "undone" => Cell::String(entry.description),
+            "done-at" => Cell::datetime(entry.done_at),
+        }),
+        None => Err(ErrorKind::NotFound.error("there's nothing to undo")),
+    }
+}
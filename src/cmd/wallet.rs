@@ -1,81 +1,46 @@
 use super::*;
-use crate::{amount_ext::FromCliStr, betting::BetState, cmd, item};
+use crate::{
+    amount_ext::FromCliStr,
+    betting::{
+        AddressLabel, BalanceCategory, BetState, FrozenUtxo, MapKey, PendingPsbt, SyncScope, TxMemo,
+    },
+    change_descriptor::ChangeDescriptor,
+    cmd, coin_select,
+    config::WalletKind,
+    item, package,
+    price::CostBasis,
+    psbt_ext,
+};
 use bdk::{
     bitcoin::{Address, OutPoint, Script, Txid},
-    blockchain::EsploraBlockchain,
+    blockchain::{Blockchain, EsploraBlockchain},
     database::Database,
     wallet::{coin_selection::CoinSelectionAlgorithm, tx_builder::TxBuilderContext, AddressIndex},
-    KeychainKind, LocalUtxo, SignOptions, TxBuilder,
+    FeeRate, KeychainKind, LocalUtxo, SignOptions, TxBuilder,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 use structopt::StructOpt;
 
 pub fn run_balance(wallet_dir: PathBuf) -> anyhow::Result<CmdOutput> {
     let party = load_party(&wallet_dir)?;
-
-    let (in_bet, unclaimed) = party
-        .bet_db()
-        .list_entities_print_error::<BetState>()
-        .filter_map(|(_, bet_state)| match bet_state {
-            BetState::Included { bet, .. } => Some((bet.local_value, Amount::ZERO)),
-            BetState::Won { bet, .. } => Some((Amount::ZERO, bet.joint_output_value)),
-            _ => None,
-        })
-        .fold((Amount::ZERO, Amount::ZERO), |cur, next| {
-            (
-                cur.0.checked_add(next.0).unwrap(),
-                cur.1.checked_add(next.1).unwrap(),
-            )
-        });
-
-    let tx_list = party
-        .wallet()
-        .list_transactions(false)?
-        .into_iter()
-        .map(|tx_details| (tx_details.txid, tx_details.confirmation_time.is_some()))
-        .collect::<Vec<_>>();
-    let unspent = party.wallet().list_unspent()?;
-    let currently_used = party.bet_db().currently_used_utxos(&[])?;
-
-    let (confirmed, unconfirmed, in_use) = unspent.into_iter().fold(
-        (Amount::ZERO, Amount::ZERO, Amount::ZERO),
-        |(confirmed, unconfirmed, in_use), local_utxo| {
-            let is_confirmed = tx_list
-                .iter()
-                .find_map(|(txid, is_confirmed)| {
-                    if *txid == local_utxo.outpoint.txid {
-                        Some(*is_confirmed)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(false);
-            let value = Amount::from_sat(local_utxo.txout.value);
-
-            if currently_used
-                .iter()
-                .find(|outpoint| local_utxo.outpoint == **outpoint)
-                .is_some()
-            {
-                (confirmed, unconfirmed, in_use + value)
-            } else if is_confirmed {
-                (confirmed + value, unconfirmed, in_use)
-            } else {
-                match local_utxo.keychain {
-                    KeychainKind::External => (confirmed, unconfirmed + value, in_use),
-                    KeychainKind::Internal => (confirmed + value, unconfirmed, in_use),
-                }
-            }
-        },
-    );
+    let balance = party.balance()?;
+    let value = |category| balance.get(category).value;
+    let confirmed = value(BalanceCategory::Spendable);
+    let unconfirmed = value(BalanceCategory::UnconfirmedIncoming);
+    let unclaimed = value(BalanceCategory::Unclaimed);
 
     Ok(item! {
         "confirmed" => Cell::Amount(confirmed),
         "unconfirmed" => Cell::Amount(unconfirmed),
         "unclaimed" => Cell::Amount(unclaimed),
         "available" => Cell::Amount(confirmed + unconfirmed + unclaimed),
-        "locked" => Cell::Amount(in_bet),
-        "in-use" => Cell::Amount(in_use),
+        "locked" => Cell::Amount(value(BalanceCategory::InBet) + value(BalanceCategory::InBetUnconfirmed)),
+        "in-use" => Cell::Amount(value(BalanceCategory::Reserved)),
+        "in-bet" => Cell::Amount(value(BalanceCategory::InBet)),
+        "in-bet-unconfirmed" => Cell::Amount(value(BalanceCategory::InBetUnconfirmed)),
+        "frozen" => Cell::Amount(value(BalanceCategory::Frozen)),
+        "immature" => Cell::Amount(value(BalanceCategory::Immature)),
+        "total" => Cell::Amount(balance.total()),
     })
 }
 
@@ -86,9 +51,62 @@ pub enum AddressOpt {
     /// First one that hasn't been used.
     LastUnused,
     /// List addresses
-    List,
+    List {
+        /// Only list addresses that have received coins
+        #[structopt(long, conflicts_with = "unused")]
+        used: bool,
+        /// Only list addresses that have never received coins
+        #[structopt(long)]
+        unused: bool,
+        /// Only list addresses that currently hold coins
+        #[structopt(long)]
+        with_balance: bool,
+    },
     /// Show details of an address
     Show { address: Address },
+    /// Attach a label to an address. Leave out the label to remove it.
+    Label {
+        address: Address,
+        label: Option<String>,
+    },
+    /// Work out the receive address at an index from the seed words (not the database) to check
+    /// it against what another device shows
+    Verify {
+        index: u32,
+        /// Don't print a QR code
+        #[structopt(long)]
+        no_qr: bool,
+    },
+    /// Show a QR code to scan with a phone of an address, a BIP21 URI (bitcoin:...), a proposal or
+    /// anything else. Without one it's the last unused address.
+    Qr {
+        data: Option<String>,
+        /// Make a BIP21 URI asking for this much from the address
+        #[structopt(long, parse(try_from_str = FromCliStr::from_cli_str))]
+        amount: Option<Amount>,
+        /// Put this label in the BIP21 URI
+        #[structopt(long)]
+        label: Option<String>,
+        #[structopt(flatten)]
+        qr_args: QrArgs,
+    },
+}
+
+impl AddressOpt {
+    /// What `--sync` syncs before the command runs.
+    pub fn sync_scope(&self) -> SyncScope {
+        match self {
+            // which addresses have been used is only known after a sync
+            AddressOpt::LastUnused
+            | AddressOpt::List { .. }
+            | AddressOpt::Show { .. }
+            | AddressOpt::Qr { data: None, .. } => SyncScope::Wallet,
+            AddressOpt::New
+            | AddressOpt::Label { .. }
+            | AddressOpt::Verify { .. }
+            | AddressOpt::Qr { .. } => SyncScope::Nothing,
+        }
+    }
 }
 
 pub fn get_address(wallet_dir: &PathBuf, addr_opt: AddressOpt) -> anyhow::Result<CmdOutput> {
@@ -104,23 +122,34 @@ pub fn get_address(wallet_dir: &PathBuf, addr_opt: AddressOpt) -> anyhow::Result
         AddressOpt::LastUnused => {
             let (wallet, _, _, _) = load_wallet(wallet_dir)?;
             let address = wallet.get_address(AddressIndex::LastUnused)?;
+            warn_if_used(&wallet, &address.address)?;
             Ok(CmdOutput::EmphasisedItem {
                 main: ("address", Cell::string(address)),
                 other: vec![],
             })
         }
-        AddressOpt::List => {
-            let wallet_db = load_wallet_db(wallet_dir).context("loading wallet db")?;
-            let scripts = wallet_db.iter_script_pubkeys(Some(KeychainKind::External))?;
-            let config = load_config(wallet_dir).context("loading config")?;
-            let index = wallet_db.get_last_index(KeychainKind::External)?;
-            let map = index_utxos(&wallet_db)?;
+        AddressOpt::List {
+            used,
+            unused,
+            with_balance,
+        } => {
+            let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+            let labels = bet_db.address_labels()?;
+            let times_used = address_use_counts(&wallet)?;
+            let (scripts, index, map) = wallet.query_db(|wallet_db| {
+                Ok::<_, anyhow::Error>((
+                    wallet_db.iter_script_pubkeys(Some(KeychainKind::External))?,
+                    wallet_db.get_last_index(KeychainKind::External)?,
+                    index_utxos(wallet_db)?,
+                ))
+            })?;
             let rows = match index {
                 Some(index) => scripts
                     .iter()
                     .take(index as usize + 1)
-                    .map(|script| {
-                        let address = Address::from_script(&script, config.network).unwrap();
+                    .enumerate()
+                    .filter_map(|(i, script)| {
+                        let address = Address::from_script(&script, wallet.network()).unwrap();
                         let value = map
                             .get(script)
                             .map(|utxos| {
@@ -129,12 +158,26 @@ pub fn get_address(wallet_dir: &PathBuf, addr_opt: AddressOpt) -> anyhow::Result
                             .unwrap_or(Amount::ZERO);
 
                         let count = map.get(script).map(Vec::len).unwrap_or(0);
+                        let times_used = times_used.get(script).cloned().unwrap_or(0);
 
-                        vec![
+                        if (used && times_used == 0)
+                            || (unused && times_used > 0)
+                            || (with_balance && value == Amount::ZERO)
+                        {
+                            return None;
+                        }
+
+                        Some(vec![
+                            Cell::Int(i as u64),
                             Cell::String(address.to_string()),
+                            labels
+                                .get(script)
+                                .map(|label| Cell::string(label))
+                                .unwrap_or(Cell::Empty),
+                            Cell::Int(times_used as u64),
                             Cell::Amount(value),
                             Cell::Int(count as u64),
-                        ]
+                        ])
                     })
                     // newest should go first
                     .rev()
@@ -142,10 +185,14 @@ pub fn get_address(wallet_dir: &PathBuf, addr_opt: AddressOpt) -> anyhow::Result
                 None => vec![],
             };
 
-            Ok(CmdOutput::table(vec!["address", "value", "utxos"], rows))
+            Ok(CmdOutput::table(
+                vec!["index", "address", "label", "times-used", "value", "utxos"],
+                rows,
+            ))
         }
         AddressOpt::Show { address } => {
             let (wallet, _, _, _) = load_wallet(wallet_dir)?;
+            warn_if_used(&wallet, &address)?;
             let script_pubkey = address.script_pubkey();
             let output_descriptor = wallet
                 .get_descriptor_for_script_pubkey(&address.script_pubkey())?
@@ -176,7 +223,167 @@ pub fn get_address(wallet_dir: &PathBuf, addr_opt: AddressOpt) -> anyhow::Result
                 "keychain" => keychain,
             })
         }
+        AddressOpt::Label { address, label } => {
+            let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+            let script_pubkey = address.script_pubkey();
+            if !wallet.is_mine(&script_pubkey)? {
+                return Err(anyhow!("{} is not an address of this wallet", address));
+            }
+            bet_db.record_undo(
+                match &label {
+                    Some(label) => format!("label {} \"{}\"", address, label),
+                    None => format!("remove the label of {}", address),
+                },
+                vec![MapKey::AddressLabel(script_pubkey.clone())],
+            )?;
+            match label {
+                Some(label) => bet_db.set_address_label(script_pubkey, label)?,
+                None => {
+                    bet_db.remove_entity::<AddressLabel>(script_pubkey)?;
+                }
+            }
+            Ok(CmdOutput::None)
+        }
+        AddressOpt::Verify { index, no_qr } => {
+            let (wallet, _, keychain, config) = load_wallet(wallet_dir)?;
+            let address = match &config.kind {
+                WalletKind::P2wpkh => keychain.receive_address(config.network, index),
+                // we can't use the seed words but we can still avoid the database
+                WalletKind::Descriptor { external, .. } => {
+                    eprintln!("The coins in this wallet are held by a descriptor rather than the seed words so the address is worked out from the descriptor in the config.");
+                    Wallet::new_offline(
+                        external.as_str(),
+                        None,
+                        config.network,
+                        bdk::database::MemoryDatabase::default(),
+                    )?
+                    .get_address(AddressIndex::Peek(index))?
+                    .address
+                }
+            };
+            let in_database = wallet
+                .query_db(|db| db.get_script_pubkey_from_path(KeychainKind::External, index))?;
+            let matches_database = match in_database {
+                Some(script_pubkey) if script_pubkey != address.script_pubkey() => {
+                    return Err(anyhow!(
+                        "the database has {} at index {} but it should be {}. DO NOT use addresses from this wallet until you find out why.",
+                        Address::from_script(&script_pubkey, config.network)
+                            .map(|address| address.to_string())
+                            .unwrap_or_else(|| script_pubkey.to_string()),
+                        index,
+                        address
+                    ));
+                }
+                Some(_) => Cell::string("yes"),
+                // it hasn't been handed out yet
+                None => Cell::Empty,
+            };
+
+            if !no_qr {
+                eprintln!(
+                    "{}",
+                    crate::qr::render(&address.to_string(), Default::default())?
+                );
+            }
+
+            Ok(CmdOutput::EmphasisedItem {
+                main: ("address", Cell::string(&address)),
+                other: vec![
+                    ("index", Cell::Int(index as u64)),
+                    ("chunked", Cell::String(chunked(&address.to_string()))),
+                    ("matches-database", matches_database),
+                ],
+            })
+        }
+        AddressOpt::Qr {
+            data,
+            amount,
+            label,
+            qr_args,
+        } => {
+            let address = match &data {
+                Some(data) => Address::from_str(data).ok(),
+                None => {
+                    let (wallet, _, _, _) = load_wallet(wallet_dir)?;
+                    let address = wallet.get_address(AddressIndex::LastUnused)?.address;
+                    warn_if_used(&wallet, &address)?;
+                    Some(address)
+                }
+            };
+            let data = match (address, data) {
+                (Some(address), _) if amount.is_some() || label.is_some() => {
+                    crate::qr::bip21_uri(&address, amount, label.as_deref())
+                }
+                (_, _) if amount.is_some() || label.is_some() => {
+                    return Err(anyhow!(
+                        "--amount and --label only go with an address to make a BIP21 URI"
+                    ))
+                }
+                (Some(address), None) => address.to_string(),
+                (_, Some(data)) => data,
+                (None, None) => unreachable!("there's always an address without data"),
+            };
+            eprintln!("{}", crate::qr::render(&data, qr_args.style())?);
+            Ok(CmdOutput::EmphasisedItem {
+                main: ("data", Cell::String(data)),
+                other: vec![],
+            })
+        }
+    }
+}
+
+/// Splits `string` into groups of four characters so it's easier to compare by eye.
+fn chunked(string: &str) -> String {
+    string
+        .chars()
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prints a warning if `address` has already received coins so it isn't handed out again.
+pub fn warn_if_used<D: BatchDatabase>(
+    wallet: &Wallet<EsploraBlockchain, D>,
+    address: &Address,
+) -> anyhow::Result<()> {
+    let times_used = address_use_counts(wallet)?
+        .get(&address.script_pubkey())
+        .cloned()
+        .unwrap_or(0);
+    if times_used > 0 {
+        eprintln!(
+            "WARNING: {} has already received coins {} time(s). Re-using addresses harms your privacy and the privacy of whoever pays you.",
+            address, times_used
+        );
+    }
+    Ok(())
+}
+
+/// How many of the wallet's transactions paid to each of its scripts.
+pub fn address_use_counts<D: BatchDatabase>(
+    wallet: &Wallet<EsploraBlockchain, D>,
+) -> anyhow::Result<HashMap<Script, usize>> {
+    let mut counts = HashMap::new();
+    for tx_details in wallet.list_transactions(true)? {
+        let tx = match tx_details.transaction {
+            Some(tx) => tx,
+            None => continue,
+        };
+        let mut scripts = tx
+            .output
+            .into_iter()
+            .map(|txout| txout.script_pubkey)
+            .filter(|script_pubkey| wallet.is_mine(script_pubkey).unwrap_or(false))
+            .collect::<Vec<_>>();
+        scripts.sort();
+        scripts.dedup();
+        for script_pubkey in scripts {
+            *counts.entry(script_pubkey).or_insert(0) += 1;
+        }
     }
+    Ok(counts)
 }
 
 fn index_utxos(wallet_db: &impl BatchDatabase) -> anyhow::Result<HashMap<Script, Vec<LocalUtxo>>> {
@@ -195,15 +402,30 @@ pub struct SendOpt {
     /// The amount to send with denomination e.g. 0.1BTC
     value: ValueChoice,
     /// The address to send the coins to
-    to: Address,
+    #[structopt(required_unless = "paste")]
+    to: Option<Address>,
+    /// Take the address from the clipboard
+    #[structopt(long, conflicts_with = "to")]
+    paste: bool,
+    /// Try to find coins that pay the amount without needing change by overpaying the fee by at
+    /// most `avoid-change-tolerance` from the config (default 1000sat).
+    #[structopt(long)]
+    avoid_change: bool,
+    /// Like `--avoid-change` but overpaying the fee by at most this much
+    #[structopt(long, value_name = "amount", parse(try_from_str = FromCliStr::from_cli_str))]
+    avoid_change_tolerance: Option<Amount>,
     #[structopt(flatten)]
     spend_opt: SpendOpt,
 }
 
+pub const DEFAULT_AVOID_CHANGE_TOLERANCE_SATS: u64 = 1_000;
+
 #[derive(Clone, Debug, StructOpt)]
 pub struct SpendOpt {
     #[structopt(flatten)]
     fee_args: cmd::FeeArgs,
+    #[structopt(flatten)]
+    rbf_args: cmd::RbfArgs,
     /// Allow spending utxos that are currently being used in a protocol (like a bet).
     #[structopt(long)]
     spend_in_use: bool,
@@ -220,26 +442,77 @@ pub struct SpendOpt {
     /// Print the resulting transaction out in hex instead of broadcasting it.
     #[structopt(long)]
     print_tx: bool,
+    /// How to choose which coins to spend: default or avoid-reuse (spend all coins on an address
+    /// together). Overrides the `coin-select` config setting.
+    #[structopt(long)]
+    coin_select: Option<coin_select::CoinSelectPolicy>,
+    /// A note to remember the transaction by (see `gun tx note`)
+    #[structopt(long)]
+    memo: Option<String>,
+    /// Send change here instead of the wallet's internal addresses. Overrides the
+    /// `change-descriptor` config setting.
+    #[structopt(long, value_name = "DESCRIPTOR|ADDRESS")]
+    change_to: Option<String>,
+    /// Only spend coins received from coinjoins. Without this they're left out so mixed and
+    /// unmixed coins aren't spent together (see `coinjoin` in the config).
+    #[structopt(long)]
+    coinjoin: bool,
+    /// Set when the transaction has no change e.g. `--value all`
+    #[structopt(skip)]
+    drains_wallet: bool,
 }
 
 impl SpendOpt {
     pub fn spend_coins<D: BatchDatabase, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext>(
         self,
         party: &Party<EsploraBlockchain, D>,
-        mut builder: TxBuilder<'_, EsploraBlockchain, D, Cs, Ctx>,
+        builder: TxBuilder<'_, EsploraBlockchain, D, Cs, Ctx>,
+        recipients: &[Script],
+    ) -> anyhow::Result<CmdOutput> {
+        self.spend_coins_with_summary(party, builder, recipients, |_| None)
+    }
+
+    /// Like `spend_coins` but `summary` can add extra information about the transaction to the
+    /// confirmation prompt.
+    ///
+    /// Outputs to our wallet that are not in `recipients` are treated as change and are folded
+    /// into the fee if they are below the dust change threshold.
+    pub fn spend_coins_with_summary<
+        D: BatchDatabase,
+        Cs: CoinSelectionAlgorithm<D>,
+        Ctx: TxBuilderContext,
+        F: FnOnce(&Psbt) -> Option<String>,
+    >(
+        self,
+        party: &Party<EsploraBlockchain, D>,
+        builder: TxBuilder<'_, EsploraBlockchain, D, Cs, Ctx>,
+        recipients: &[Script],
+        summary: F,
     ) -> anyhow::Result<CmdOutput> {
         let SpendOpt {
             fee_args,
+            rbf_args,
             spend_in_use,
             no_spend_unclaimed,
             bump_claiming,
             yes,
             print_tx,
+            coin_select: coin_select_policy,
+            memo,
+            change_to,
+            coinjoin,
+            drains_wallet,
         } = self;
+        let _span = tracing::info_span!("build_tx", kind = "send").entered();
 
-        builder
-            .enable_rbf()
-            .ordering(bdk::wallet::tx_builder::TxOrdering::Bip69Lexicographic);
+        let mut builder = builder.coin_selection(coin_select::PolicyCoinSelection(
+            coin_select_policy.unwrap_or(party.settings().coin_select),
+        ));
+
+        party.settings().tx_ordering.apply_to_builder(&mut builder);
+        if rbf_args.signal(party.settings().rbf.sends) {
+            builder.enable_rbf();
+        }
 
         let in_use = party.bet_db().currently_used_utxos(&[])?;
 
@@ -248,41 +521,206 @@ impl SpendOpt {
                 "note that {} utxos are not availble becuase they are in use",
                 in_use.len()
             );
-            builder.unspendable(in_use);
+            for outpoint in in_use {
+                builder.add_unspendable(outpoint);
+            }
+        }
+
+        for frozen in party.bet_db().frozen_utxos()? {
+            builder.add_unspendable(frozen);
+        }
+
+        let coinjoin_outputs = party.bet_db().coinjoin_outputs()?;
+        for utxo in party.wallet().list_unspent()? {
+            if coinjoin_outputs.contains_key(&utxo.outpoint) != coinjoin {
+                builder.add_unspendable(utxo.outpoint);
+            }
         }
 
-        fee_args
-            .fee
-            .apply_to_builder(party.wallet().client(), &mut builder)?;
+        let fee_spec = fee_args.fee_spec(party.settings());
+        fee_spec.apply_to_builder(party.wallet().client(), &mut builder)?;
 
-        let (mut psbt, claiming_bet_ids) = if !no_spend_unclaimed {
-            party
-                .spend_won_bets(builder, bump_claiming)?
-                .expect("Won't be None since builder we pass in is not manually_selected_only")
+        let change_spec = change_to.or_else(|| party.settings().change_descriptor.clone());
+        let change_script = match change_spec {
+            Some(spec) if !drains_wallet => {
+                let change_script = change_destination(party, &spec)?;
+                builder.drain_to(change_script.clone());
+                Some(change_script)
+            }
+            _ => None,
+        };
+        let is_change = |script: &Script| {
+            Some(script) == change_script.as_ref()
+                || (!recipients.contains(script) && party.wallet().is_mine(script).unwrap_or(false))
+        };
+
+        let won_bets = if !no_spend_unclaimed {
+            party.add_won_bets(&mut builder, bump_claiming, None)?
         } else {
-            let (psbt, _) = builder.finish()?;
-            (psbt, vec![])
+            vec![]
+        };
+
+        let retry = builder.clone();
+        let (mut psbt, _) = builder.finish()?;
+
+        // if we are spending unconfirmed coins of ours the fee has to be enough for their
+        // transactions to confirm too
+        let mut package_note = None;
+        if let Some(target) = fee_spec.feerate(party.wallet().client())? {
+            let ancestors = package::Ancestors::find(party.wallet(), &psbt.global.unsigned_tx)?;
+            if !ancestors.is_empty() {
+                let satisfaction_weight =
+                    crate::tx_size::wallet_satisfaction_weight(party.wallet());
+                let required = ancestors.child_fee_for(
+                    target,
+                    package::estimated_vsize(&psbt, &satisfaction_weight),
+                );
+                let (fee, _) = psbt.fee();
+                if required > fee {
+                    let mut retry = retry;
+                    retry.fee_absolute(required.as_sat());
+                    psbt = retry.finish()?.0;
+                }
+                let (fee, _) = psbt.fee();
+                let package_vsize =
+                    ancestors.vsize + package::estimated_vsize(&psbt, &satisfaction_weight);
+                package_note = Some(format!(
+                    "this spends from {} unconfirmed transaction(s) paying {:.1} sat/vb so the fee is {} to make the package pay {:.1} sat/vb (target {:.1} sat/vb)",
+                    ancestors.txids.len(),
+                    ancestors.feerate().map(|rate| rate.as_sat_vb()).unwrap_or(0.0),
+                    fee,
+                    (ancestors.fee + fee).as_sat() as f32 / package_vsize as f32,
+                    target.as_sat_vb(),
+                ));
+            }
+            if !ancestors.unknown_fee.is_empty() {
+                eprintln!(
+                    "note that {} unconfirmed transaction(s) being spent from weren't made by this wallet so their fee isn't taken into account",
+                    ancestors.unknown_fee.len()
+                );
+            }
+        }
+        party.settings().tx_ordering.finish(&mut psbt);
+        psbt_ext::log_built_tx(&psbt);
+
+        let dust_change_threshold = party.settings().dust_change_threshold;
+        // when draining the wallet there's no change: our outputs are what's being made (e.g. the
+        // outputs of a split)
+        let donated = match drains_wallet {
+            true => Amount::ZERO,
+            false => psbt_ext::fold_dust_change(&mut psbt, dust_change_threshold, |txout| {
+                is_change(&txout.script_pubkey)
+            }),
         };
+        crate::dust::check_min_outputs(&psbt, party.settings().min_output_value, &is_change)?;
+
+        if let Some(policy) = &party.settings().approval {
+            let outgoing = Amount::from_sat(
+                psbt.global
+                    .unsigned_tx
+                    .output
+                    .iter()
+                    .filter(|txout| {
+                        Some(&txout.script_pubkey) != change_script.as_ref()
+                            && !party
+                                .wallet()
+                                .is_mine(&txout.script_pubkey)
+                                .unwrap_or(false)
+                    })
+                    .map(|txout| txout.value)
+                    .sum(),
+            );
+            if outgoing > policy.threshold {
+                if let Some(summary) = summary(&psbt) {
+                    eprintln!("{}", summary);
+                }
+                return cmd::request_approval(
+                    party,
+                    policy,
+                    psbt,
+                    won_bets.into_iter().map(|won| won.bet_id).collect(),
+                    outgoing,
+                    memo,
+                );
+            }
+            // the signers can't tell change to a change descriptor from a payment
+            crate::approval::allow(psbt.global.unsigned_tx.txid());
+        }
 
+        party.sign_won_bets(&mut psbt, &won_bets)?;
         party.wallet().sign(&mut psbt, SignOptions::default())?;
+        party.audit_psbt(AuditOperation::Sign, "send", &psbt)?;
 
         let finalized = party
             .wallet()
             .finalize_psbt(&mut psbt, SignOptions::default())?;
 
-        assert!(finalized, "transaction must be finalized at this point");
+        if donated > Amount::ZERO {
+            eprintln!(
+                "{} of change would have been below the dust change threshold of {} so it was added to the fee instead",
+                donated, dust_change_threshold
+            );
+        }
+
+        if let Some(package_note) = package_note {
+            eprintln!("{}", package_note);
+        }
+
+        if let Some(summary) = summary(&psbt) {
+            eprintln!("{}", summary);
+        }
+
+        if !finalized {
+            // some of the keys are elsewhere (e.g. a hardware wallet)
+            eprintln!("{}", cmd::display_psbt(party.wallet(), &psbt));
+            let txid = party
+                .bet_db()
+                .insert_pending_psbt(psbt, won_bets.into_iter().map(|won| won.bet_id).collect())?;
+            if let Some(memo) = memo {
+                party.bet_db().set_tx_memo(txid, memo)?;
+            }
+            eprintln!(
+                "This wallet couldn't sign the transaction by itself so it has been saved. Use `gun psbt export {}` to get it signed and `gun psbt import` to broadcast it.",
+                txid
+            );
+            return Ok(item! { "txid" => Cell::string(txid) });
+        }
+
+        if !yes {
+            let bet_inputs = won_bets
+                .iter()
+                .map(|won| (won.bet.outpoint(), won.bet_id))
+                .collect::<Vec<_>>();
+            let review = cmd::review_send(
+                party,
+                &psbt,
+                recipients,
+                change_script.as_ref(),
+                &bet_inputs,
+            )?;
+            if !review.confirm() {
+                return Ok(CmdOutput::None);
+            }
+        }
 
         let (output, txid) = cmd::decide_to_broadcast(
             party.wallet().network(),
             party.wallet().client(),
             psbt,
-            yes,
+            true,
             print_tx,
+            party.audit_log(),
+            party.bet_db(),
+            "send",
         )?;
 
         if let Some(txid) = txid {
+            if let Some(memo) = memo {
+                party.bet_db().set_tx_memo(txid, memo)?;
+            }
             if !print_tx {
-                for bet_id in claiming_bet_ids {
+                party.record_cost_basis(txid)?;
+                for bet_id in won_bets.into_iter().map(|won| won.bet_id) {
                     if let Err(e) = party.take_next_action(bet_id, false) {
                         eprintln!(
                             "error updating state of bet {} after broadcasting claim tx {}: {}",
@@ -296,35 +734,245 @@ impl SpendOpt {
         Ok(output)
     }
 }
+
+/// Works out the script for `--change-to` or the `change-descriptor` setting.
+pub(crate) fn change_destination<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    spec: &str,
+) -> anyhow::Result<Script> {
+    let network = party.wallet().network();
+    match Address::from_str(spec) {
+        Ok(address) => {
+            let address = crate::address::check(&address, network, "the change address")?;
+            let script_pubkey = address.script_pubkey();
+            if !party.wallet().is_mine(&script_pubkey)? {
+                return Err(ErrorKind::BadInput.error(format!(
+                    "refusing to send change to {} because it isn't an address of this wallet",
+                    address
+                )));
+            }
+            Ok(script_pubkey)
+        }
+        Err(_) => ChangeDescriptor::new(spec, &party.keychain, network)?
+            .next_script_pubkey(party.bet_db()),
+    }
+}
+
+impl SpendOpt {
+    /// Looks for the coins to pay `amount` to `to` without needing change.
+    fn find_changeless<D: BatchDatabase>(
+        &self,
+        party: &Party<EsploraBlockchain, D>,
+        to: &Address,
+        amount: Amount,
+        tolerance: Amount,
+    ) -> anyhow::Result<Option<coin_select::Changeless>> {
+        use coin_select::*;
+        let mut unavailable = party.bet_db().frozen_utxos()?;
+        if !self.spend_in_use {
+            unavailable.extend(party.bet_db().currently_used_utxos(&[])?);
+        }
+        let coinjoin_outputs = party.bet_db().coinjoin_outputs()?;
+        let wallet = party.wallet();
+
+        let fee_spec = self.fee_args.fee_spec(party.settings());
+        let feerate = fee_spec.feerate(wallet.client())?;
+        let base_fee = match feerate {
+            Some(feerate) => {
+                let output_vbytes = (to.script_pubkey().len() + 9) as f32;
+                Amount::from_sat(
+                    ((TX_OVERHEAD_VBYTES + output_vbytes) * feerate.as_sat_vb()).ceil() as u64,
+                )
+            }
+            None => match fee_spec {
+                FeeSpec::Absolute(fee) => fee,
+                _ => unreachable!("only absolute fees don't have a feerate"),
+            },
+        };
+        let mut satisfaction_weights = HashMap::new();
+        for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+            satisfaction_weights.insert(
+                *keychain,
+                wallet
+                    .get_descriptor_for_keychain(*keychain)
+                    .max_satisfaction_weight()?,
+            );
+        }
+
+        let candidates = wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| !unavailable.contains(&utxo.outpoint))
+            .filter(|utxo| coinjoin_outputs.contains_key(&utxo.outpoint) == self.coinjoin)
+            .map(|utxo| Candidate {
+                outpoint: utxo.outpoint,
+                value: Amount::from_sat(utxo.txout.value),
+                fee: feerate
+                    .map(|feerate| input_fee(satisfaction_weights[&utxo.keychain], feerate))
+                    .unwrap_or(Amount::ZERO),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(find_changeless(&candidates, amount, base_fee, tolerance))
+    }
+}
+
 pub fn run_send(wallet_dir: &PathBuf, send_opt: SendOpt) -> anyhow::Result<CmdOutput> {
     let SendOpt {
         to,
+        paste,
         value,
-        spend_opt,
+        avoid_change,
+        avoid_change_tolerance,
+        mut spend_opt,
     } = send_opt;
+    let to = match (to, paste) {
+        (Some(to), _) => to,
+        (None, true) => crate::clipboard::paste_as::<Address>("an address")?,
+        (None, false) => unreachable!("structopt makes sure there's an address"),
+    };
     let party = load_party(wallet_dir)?;
+    let to = crate::address::check(&to, party.wallet().network(), "the address to send to")?;
+    let config = load_config(wallet_dir)?;
     let mut builder = party.wallet().build_tx();
+    let tolerance = match (avoid_change_tolerance, avoid_change) {
+        (Some(tolerance), _) => Some(tolerance),
+        (None, true) => Some(
+            config
+                .avoid_change_tolerance
+                .unwrap_or(Amount::from_sat(DEFAULT_AVOID_CHANGE_TOLERANCE_SATS)),
+        ),
+        (None, false) => config.avoid_change_tolerance,
+    };
+    let mut changeless = None;
 
     match value {
-        ValueChoice::All => builder.drain_wallet().drain_to(to.script_pubkey()),
-        ValueChoice::Amount(amount) => builder.add_recipient(to.script_pubkey(), amount.as_sat()),
+        ValueChoice::All => {
+            builder.drain_wallet().drain_to(to.script_pubkey());
+            spend_opt.drains_wallet = true;
+        }
+        ValueChoice::Amount(amount) => {
+            builder.add_recipient(to.script_pubkey(), amount.as_sat());
+            if let Some(tolerance) = tolerance {
+                changeless = spend_opt.find_changeless(&party, &to, amount, tolerance)?;
+                match &changeless {
+                    Some(changeless) => {
+                        builder.manually_selected_only();
+                        for outpoint in &changeless.selected {
+                            builder.add_utxo(*outpoint)?;
+                        }
+                        // the excess goes to the fee so no change output is needed
+                        spend_opt.fee_args.fee = FeeSpec::Absolute(changeless.fee);
+                        spend_opt.no_spend_unclaimed = true;
+                    }
+                    None => eprintln!(
+                        "couldn't find coins to send {} without change within a tolerance of {}",
+                        amount, tolerance
+                    ),
+                }
+            }
+        }
     };
 
-    spend_opt.spend_coins(&party, builder)
+    spend_opt.spend_coins_with_summary(&party, builder, &[to.script_pubkey()], |_| {
+        changeless.map(|changeless| {
+            format!(
+                "This transaction has no change output. It uses {} input(s) and pays {} extra to the fee to avoid change (tolerance {}).",
+                changeless.selected.len(),
+                changeless.excess,
+                tolerance.expect("must be set if we found a changeless tx")
+            )
+        })
+    })
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum TransactionOpt {
-    List,
-    Show { txid: Txid },
+    /// List transactions, newest first
+    List {
+        #[structopt(flatten)]
+        page: PageArgs,
+    },
+    Show {
+        txid: Txid,
+    },
+    /// Attach a memo to a transaction. Leave out the memo to remove it.
+    Note {
+        txid: Txid,
+        memo: Option<String>,
+    },
+    /// Replace an unconfirmed transaction with one paying a higher fee (it must signal RBF)
+    Bump {
+        txid: Txid,
+        /// The new fee. Besides the usual fees this can be relative to the old feerate e.g.
+        /// bump:+25% or bump:+5sat/vb.
+        #[structopt(long, default_value = "bump:+25%")]
+        fee: FeeSpec,
+        /// Don't prompt for answers just answer yes.
+        #[structopt(long, short)]
+        yes: bool,
+        /// Print the resulting transaction out in hex instead of broadcasting it.
+        #[structopt(long)]
+        print_tx: bool,
+    },
+    /// Replace all of our unconfirmed transactions that signal RBF with ones paying `fee`.
+    /// Transactions that spend each other's outputs are replaced together by one transaction.
+    BumpAll {
+        /// The new fee e.g. `bump:+25%`, `bump:+5sat/vb`, `10sat/vb` or `fastest`
+        #[structopt(default_value = "bump:+25%")]
+        fee: FeeSpec,
+        /// Replace all of them with a single transaction. This is cheaper but shows they belong
+        /// to the same wallet.
+        #[structopt(long)]
+        merge: bool,
+        /// Don't prompt for answers just answer yes.
+        #[structopt(long, short)]
+        yes: bool,
+        /// Print the resulting transaction(s) out in hex instead of broadcasting them.
+        #[structopt(long)]
+        print_tx: bool,
+    },
+    /// Show the transactions the backend wouldn't take yet. They're tried again by `gun schedule
+    /// run` (and its daemon) with longer and longer waits in between.
+    Queue {
+        /// Try broadcasting all of them now
+        #[structopt(long)]
+        retry: bool,
+        /// Give up on broadcasting this one and free its coins
+        #[structopt(long, conflicts_with = "retry")]
+        remove: Option<Txid>,
+    },
 }
 
 pub fn run_transaction_cmd(wallet_dir: &PathBuf, opt: TransactionOpt) -> anyhow::Result<CmdOutput> {
     use TransactionOpt::*;
-    let (wallet, _, _, _) = load_wallet(wallet_dir)?;
+    if let Bump {
+        txid,
+        fee,
+        yes,
+        print_tx,
+    } = opt
+    {
+        return bump_transaction(wallet_dir, txid, fee, yes, print_tx);
+    }
+    if let BumpAll {
+        fee,
+        merge,
+        yes,
+        print_tx,
+    } = opt
+    {
+        return cmd::run_bump_all(wallet_dir, fee, merge, yes, print_tx);
+    }
+    if let Queue { retry, remove } = opt {
+        return cmd::run_queue_cmd(wallet_dir, retry, remove);
+    }
+    let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+    let memos = bet_db.tx_memos()?;
 
     match opt {
-        List => {
+        List { page } => {
+            // the raw transactions are only looked up for the page being shown
             let mut txns = wallet.list_transactions(false)?;
 
             txns.sort_unstable_by_key(|x| {
@@ -336,9 +984,13 @@ pub fn run_transaction_cmd(wallet_dir: &PathBuf, opt: TransactionOpt) -> anyhow:
                 )
             });
 
-            let rows: Vec<Vec<Cell>> = txns
-                .into_iter()
+            page.note_position(txns.len());
+            let rows: Vec<Vec<Cell>> = page
+                .page(txns.into_iter())
                 .map(|tx| {
+                    let raw = wallet
+                        .query_db(|db| db.get_raw_tx(&tx.txid))
+                        .unwrap_or(None);
                     vec![
                         Cell::String(tx.txid.to_string()),
                         tx.confirmation_time
@@ -351,21 +1003,24 @@ pub fn run_transaction_cmd(wallet_dir: &PathBuf, opt: TransactionOpt) -> anyhow:
                             .unwrap_or(Cell::Empty),
                         Cell::Amount(Amount::from_sat(tx.sent)),
                         Cell::Amount(Amount::from_sat(tx.received)),
+                        raw.as_ref()
+                            .map(|raw| Cell::string(psbt_ext::signals_rbf(raw)))
+                            .unwrap_or(Cell::Empty),
+                        memos.get(&tx.txid).map(Cell::string).unwrap_or(Cell::Empty),
                     ]
                 })
                 .collect();
 
             Ok(CmdOutput::table(
-                vec!["txid", "height", "seen", "sent", "received"],
+                vec!["txid", "height", "seen", "sent", "received", "rbf", "memo"],
                 rows,
             ))
         }
         Show { txid } => {
             let tx = wallet
-                .list_transactions(false)?
-                .into_iter()
-                .find(|tx| tx.txid == txid)
-                .ok_or(anyhow!("Transaction {} not found", txid))?;
+                .query_db(|db| db.get_tx(&txid, true))?
+                .ok_or(ErrorKind::NotFound.error(format!("Transaction {} not found", txid)))?;
+            let cost_basis = bet_db.get_entity::<CostBasis>(txid)?;
 
             Ok(item! {
                 "txid" => Cell::String(tx.txid.to_string()),
@@ -378,37 +1033,270 @@ pub fn run_transaction_cmd(wallet_dir: &PathBuf, opt: TransactionOpt) -> anyhow:
                             .map(|x| Cell::Int(x.height.into()))
                             .unwrap_or(Cell::Empty),
                 "fee" => tx.fee.map(|x| Cell::Amount(Amount::from_sat(x)))
-                    .unwrap_or(Cell::Empty)
+                    .unwrap_or(Cell::Empty),
+                "rbf" => tx.transaction.as_ref()
+                    .map(|raw| Cell::string(psbt_ext::signals_rbf(raw)))
+                    .unwrap_or(Cell::Empty),
+                "memo" => memos.get(&tx.txid).map(Cell::string).unwrap_or(Cell::Empty),
+                "cost-basis" => cost_basis.as_ref().map(Cell::string).unwrap_or(Cell::Empty),
+                "sent-fiat" => cost_basis.as_ref().map(|cost_basis| Cell::String(cost_basis.price.format(Amount::from_sat(tx.sent.saturating_sub(tx.received))))).unwrap_or(Cell::Empty),
             })
         }
+        Note { txid, memo } => {
+            if wallet.query_db(|db| db.get_tx(&txid, false))?.is_none()
+                && bet_db.get_entity::<PendingPsbt>(txid)?.is_none()
+            {
+                return Err(ErrorKind::NotFound.error(format!("Transaction {} not found", txid)));
+            }
+            bet_db.record_undo(
+                match &memo {
+                    Some(memo) => format!("note {} \"{}\"", txid, memo),
+                    None => format!("remove the note on {}", txid),
+                },
+                vec![MapKey::TxMemo(txid)],
+            )?;
+            match memo {
+                Some(memo) => bet_db.set_tx_memo(txid, memo)?,
+                None => {
+                    bet_db.remove_entity::<TxMemo>(txid)?;
+                }
+            }
+            Ok(CmdOutput::None)
+        }
+        Bump { .. } | BumpAll { .. } | Queue { .. } => unreachable!("handled above"),
+    }
+}
+
+fn bump_transaction(
+    wallet_dir: &PathBuf,
+    txid: Txid,
+    fee: FeeSpec,
+    yes: bool,
+    print_tx: bool,
+) -> anyhow::Result<CmdOutput> {
+    let party = load_party(wallet_dir)?;
+    let (psbt, old_feerate) = fee_bump_psbt(&party, txid, fee)?;
+    let wallet = party.wallet();
+    let (_, new_feerate) = psbt.fee();
+    eprintln!(
+        "replacing {} paying {:.2} sat/vb with one paying {:.2} sat/vb",
+        txid,
+        old_feerate.as_sat_vb(),
+        new_feerate.as_sat_vb()
+    );
+
+    let (output, new_txid) = cmd::decide_to_broadcast(
+        wallet.network(),
+        wallet.client(),
+        psbt,
+        yes,
+        print_tx,
+        party.audit_log(),
+        party.bet_db(),
+        "bump",
+    )?;
+    if let Some(new_txid) = new_txid {
+        carry_over_to_replacement(&party, &[txid], new_txid)?;
+    }
+    Ok(output)
+}
+
+/// Builds and signs a replacement for `txid` paying `fee`. Returns it with the old feerate.
+pub(crate) fn fee_bump_psbt<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    txid: Txid,
+    fee: FeeSpec,
+) -> anyhow::Result<(Psbt, FeeRate)> {
+    let wallet = party.wallet();
+    let tx_details = wallet
+        .query_db(|db| db.get_tx(&txid, true))?
+        .ok_or(ErrorKind::NotFound.error(format!("Transaction {} not found", txid)))?;
+    if tx_details.confirmation_time.is_some() {
+        return Err(anyhow!("{} is already confirmed", txid));
+    }
+    let tx = tx_details.transaction.as_ref().ok_or(anyhow!(
+        "the wallet doesn't have the raw transaction of {}",
+        txid
+    ))?;
+    if !psbt_ext::signals_rbf(tx) {
+        return Err(anyhow!(
+            "{} doesn't signal replace-by-fee so it can't be bumped",
+            txid
+        ));
+    }
+    let old_fee = tx_details
+        .fee
+        .ok_or(anyhow!("the fee of {} isn't known", txid))?;
+    let old_feerate = FeeRate::from_sat_per_vb(old_fee as f32 * 4.0 / tx.get_weight() as f32);
+    let fee_spec = fee
+        .with_aliases(&party.settings().fee_aliases)
+        .relative_to(old_feerate)?;
+
+    let mut builder = wallet.build_fee_bump(txid)?;
+    builder.enable_rbf();
+    fee_spec.apply_to_builder(wallet.client(), &mut builder)?;
+    let (mut psbt, _) = builder.finish()?;
+    psbt_ext::log_built_tx(&psbt);
+
+    let finalized = wallet.sign(&mut psbt, SignOptions::default())?;
+    if !finalized {
+        return Err(anyhow!(
+            "this wallet couldn't sign the replacement by itself"
+        ));
+    }
+    party.audit_psbt(AuditOperation::Sign, "bump", &psbt)?;
+    Ok((psbt, old_feerate))
+}
+
+/// Moves the memos and cost basis of the `replaced` transactions to their replacement.
+pub(crate) fn carry_over_to_replacement<D: BatchDatabase>(
+    party: &Party<EsploraBlockchain, D>,
+    replaced: &[Txid],
+    new_txid: Txid,
+) -> anyhow::Result<()> {
+    let mut memos = party.bet_db().tx_memos()?;
+    let memos = replaced
+        .iter()
+        .filter_map(|txid| memos.remove(txid))
+        .collect::<Vec<_>>();
+    if !memos.is_empty() {
+        party.bet_db().set_tx_memo(new_txid, memos.join("; "))?;
+    }
+    // the replacement pays the same people so it keeps the price from when they were first paid
+    for txid in replaced {
+        if let Some(cost_basis) = party.bet_db().get_entity::<CostBasis>(*txid)? {
+            party.bet_db().set_cost_basis(new_txid, cost_basis)?;
+            break;
+        }
     }
+    Ok(())
 }
 
 #[derive(StructOpt, Debug, Clone)]
 /// View Unspent Transaction Outputs (UTxOs)
 pub enum UtxoOpt {
     /// List UTXOs owned by this wallet
-    List,
+    List {
+        /// What to sort the coins by: value, confirmations or address
+        #[structopt(long, default_value = "value")]
+        sort: UtxoSort,
+        /// The fee used to decide whether each coin is economical to spend
+        #[structopt(flatten)]
+        fee_args: FeeArgs,
+        #[structopt(flatten)]
+        page: PageArgs,
+    },
     /// Show details about a particular UTXO
     Show { outpoint: OutPoint },
+    /// Stop a UTXO from being spent by sends, splits and bets
+    Freeze { outpoint: OutPoint },
+    /// Allow a frozen UTXO to be spent again
+    Unfreeze { outpoint: OutPoint },
+    /// Recommend which UTXOs to consolidate now given the feerate you expect to pay when they
+    /// would otherwise be spent
+    Plan {
+        /// The feerate (sat/vb) you expect to pay when spending in the future
+        #[structopt(long)]
+        horizon_rate: f32,
+        /// The fee the consolidation would pay now
+        #[structopt(flatten)]
+        fee_args: FeeArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UtxoSort {
+    Value,
+    Confirmations,
+    Address,
+}
+
+impl FromStr for UtxoSort {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        Ok(match string {
+            "value" => UtxoSort::Value,
+            "confirmations" => UtxoSort::Confirmations,
+            "address" => UtxoSort::Address,
+            _ => {
+                return Err(anyhow!(
+                    "'{}' is not something utxos can be sorted by",
+                    string
+                ))
+            }
+        })
+    }
 }
 
 pub fn run_utxo_cmd(wallet_dir: &PathBuf, opt: UtxoOpt) -> anyhow::Result<CmdOutput> {
     match opt {
-        UtxoOpt::List => {
+        UtxoOpt::List {
+            sort,
+            fee_args,
+            page,
+        } => {
             let party = load_party(&wallet_dir)?;
             let in_use_utxos = party.bet_db().currently_used_utxos(&[])?;
+            let frozen_utxos = party.bet_db().frozen_utxos()?;
+            let coinjoin_outputs = party.bet_db().coinjoin_outputs()?;
+            let labels = party.bet_db().address_labels()?;
             let wallet = party.wallet();
-            let rows = wallet
-                .list_unspent()?
-                .into_iter()
+            let times_used = address_use_counts(wallet)?;
+            let height = wallet
+                .client()
+                .get_height()
+                .map_err(|e| eprintln!("couldn't get the current block height: {}", e))
+                .ok();
+            let feerate = fee_args
+                .fee_spec(party.settings())
+                .feerate(wallet.client())
+                .map_err(|e| eprintln!("couldn't estimate the feerate: {}", e))
+                .ok()
+                .flatten();
+
+            // looking up the transaction is the slow part so it's only done for the coins being
+            // shown unless they are sorted by it
+            let confirmations_of = |utxo: &bdk::LocalUtxo| {
+                let confirmation_height = wallet
+                    .query_db(|db| db.get_tx(&utxo.outpoint.txid, false))
+                    .unwrap_or(None)
+                    .and_then(|tx| tx.confirmation_time)
+                    .map(|time| time.height);
+                match (confirmation_height, height) {
+                    (Some(confirmation_height), Some(height)) => {
+                        Some((height + 1).saturating_sub(confirmation_height))
+                    }
+                    (None, _) => Some(0),
+                    _ => None,
+                }
+            };
+            let mut utxos = wallet.list_unspent()?;
+
+            match sort {
+                UtxoSort::Value => utxos.sort_by_key(|utxo| std::cmp::Reverse(utxo.txout.value)),
+                UtxoSort::Confirmations => {
+                    utxos.sort_by_cached_key(|utxo| std::cmp::Reverse(confirmations_of(utxo)))
+                }
+                UtxoSort::Address => utxos.sort_by_key(|utxo| utxo.txout.script_pubkey.clone()),
+            }
+
+            page.note_position(utxos.len());
+            let rows = page
+                .page(utxos.into_iter())
                 .map(|utxo| {
-                    let tx = wallet
-                        .query_db(|db| db.get_tx(&utxo.outpoint.txid, false))
-                        .unwrap_or(None);
+                    let confirmations = confirmations_of(&utxo);
+                    let script_pubkey = &utxo.txout.script_pubkey;
+                    let spend_cost = feerate.and_then(|feerate| {
+                        let satisfaction_weight = wallet
+                            .get_descriptor_for_keychain(utxo.keychain)
+                            .max_satisfaction_weight()
+                            .ok()?;
+                        Some(coin_select::input_fee(satisfaction_weight, feerate))
+                    });
                     vec![
                         Cell::string(utxo.outpoint),
-                        Address::from_script(&utxo.txout.script_pubkey, wallet.network())
+                        Address::from_script(script_pubkey, wallet.network())
                             .map(|address| Cell::String(address.to_string()))
                             .unwrap_or(Cell::Empty),
                         Cell::Amount(Amount::from_sat(utxo.txout.value)),
@@ -416,13 +1304,33 @@ pub fn run_utxo_cmd(wallet_dir: &PathBuf, opt: UtxoOpt) -> anyhow::Result<CmdOut
                             KeychainKind::Internal => "internal",
                             KeychainKind::External => "external",
                         }),
-                        tx.map(|tx| Cell::String(tx.confirmation_time.is_some().to_string()))
+                        Cell::string(confirmations != Some(0)),
+                        confirmations
+                            .map(|confirmations| Cell::Int(confirmations as u64))
+                            .unwrap_or(Cell::Empty),
+                        Cell::string(times_used.get(script_pubkey).cloned().unwrap_or(0) > 1),
+                        labels
+                            .get(script_pubkey)
+                            .map(|label| Cell::string(label))
                             .unwrap_or(Cell::Empty),
+                        Cell::string(frozen_utxos.contains(&utxo.outpoint)),
+                        Cell::string(coinjoin_outputs.contains_key(&utxo.outpoint)),
                         Cell::string(in_use_utxos.contains(&utxo.outpoint)),
+                        spend_cost.map(Cell::Amount).unwrap_or(Cell::Empty),
+                        spend_cost
+                            .map(|spend_cost| Cell::string(utxo.txout.value > spend_cost.as_sat()))
+                            .unwrap_or(Cell::Empty),
                     ]
                 })
                 .collect();
 
+            if let Some(feerate) = feerate {
+                eprintln!(
+                    "spend costs are for a feerate of {} sat/vB",
+                    feerate.as_sat_vb()
+                );
+            }
+
             // TODO: list won bet utxos
             Ok(CmdOutput::table(
                 vec![
@@ -431,7 +1339,14 @@ pub fn run_utxo_cmd(wallet_dir: &PathBuf, opt: UtxoOpt) -> anyhow::Result<CmdOut
                     "value",
                     "keychain",
                     "confirmed",
+                    "confirmations",
+                    "reused",
+                    "label",
+                    "frozen",
+                    "coinjoin",
                     "in-use",
+                    "spend-cost",
+                    "economical",
                 ],
                 rows,
             ))
@@ -469,6 +1384,16 @@ pub fn run_utxo_cmd(wallet_dir: &PathBuf, opt: UtxoOpt) -> anyhow::Result<CmdOut
                 .currently_used_utxos(&[])?
                 .contains(&utxo.outpoint);
 
+            let frozen = party.bet_db().frozen_utxos()?.contains(&utxo.outpoint);
+            let coinjoin = party
+                .bet_db()
+                .get_entity::<crate::coinjoin::CoinjoinOutput>(utxo.outpoint)?;
+            let label = party
+                .bet_db()
+                .get_entity::<AddressLabel>(script_pubkey.clone())?
+                .map(|address_label| Cell::String(address_label.label))
+                .unwrap_or(Cell::Empty);
+
             // TODO: show utxos that are associated with won bets
             Ok(item! {
                 "outpoint" => Cell::String(utxo.outpoint.to_string()),
@@ -485,18 +1410,155 @@ pub fn run_utxo_cmd(wallet_dir: &PathBuf, opt: UtxoOpt) -> anyhow::Result<CmdOut
                     KeychainKind::Internal => "internal",
                 }.into()),
                 "in-use" => Cell::string(in_use),
+                "frozen" => Cell::string(frozen),
+                "label" => label,
+                "coinjoin-denomination" => coinjoin.as_ref().map(|coinjoin| Cell::Amount(coinjoin.denomination)).unwrap_or(Cell::Empty),
+                "coinjoin-participants" => coinjoin.map(|coinjoin| Cell::Int(coinjoin.participants as u64)).unwrap_or(Cell::Empty),
             })
         }
+        UtxoOpt::Freeze { outpoint } => {
+            let (wallet, bet_db, _, _) = load_wallet(wallet_dir)?;
+            if wallet.query_db(|db| db.get_utxo(&outpoint))?.is_none() {
+                return Err(anyhow!("UTXO {} not in wallet database", outpoint));
+            }
+            bet_db.record_undo(
+                format!("freeze {}", outpoint),
+                vec![MapKey::Frozen(outpoint)],
+            )?;
+            bet_db.freeze_utxo(outpoint)?;
+            Ok(CmdOutput::None)
+        }
+        UtxoOpt::Unfreeze { outpoint } => {
+            let bet_db = load_bet_db(wallet_dir)?;
+            if bet_db.get_entity::<FrozenUtxo>(outpoint)?.is_some() {
+                bet_db.record_undo(
+                    format!("unfreeze {}", outpoint),
+                    vec![MapKey::Frozen(outpoint)],
+                )?;
+            }
+            if bet_db.remove_entity::<FrozenUtxo>(outpoint)?.is_none() {
+                return Err(anyhow!("UTXO {} is not frozen", outpoint));
+            }
+            Ok(CmdOutput::None)
+        }
+        UtxoOpt::Plan {
+            horizon_rate,
+            fee_args,
+        } => {
+            let party = load_party(wallet_dir)?;
+            let wallet = party.wallet();
+            let now = fee_args
+                .fee_spec(party.settings())
+                .feerate(wallet.client())?
+                .ok_or(anyhow!(
+                    "planning needs a feerate rather than an absolute fee"
+                ))?;
+            let horizon = FeeRate::from_sat_per_vb(horizon_rate);
+
+            // coins that sends can't spend can't be consolidated either
+            let in_use = party.bet_db().currently_used_utxos(&[])?;
+            let frozen = party.bet_db().frozen_utxos()?;
+            let mut utxos = wallet
+                .list_unspent()?
+                .into_iter()
+                .filter(|utxo| !in_use.contains(&utxo.outpoint) && !frozen.contains(&utxo.outpoint))
+                .collect::<Vec<_>>();
+            utxos.sort_by_key(|utxo| utxo.txout.value);
+
+            let values = utxos
+                .iter()
+                .map(|utxo| utxo.txout.value)
+                .collect::<Vec<_>>();
+            let plan = coin_select::plan_consolidation(&values, now, horizon);
+            let input_cost = |feerate: FeeRate| {
+                Amount::from_sat(
+                    (coin_select::P2WPKH_INPUT_VBYTES * feerate.as_sat_vb()).ceil() as u64,
+                )
+            };
+
+            let rows = utxos
+                .iter()
+                .enumerate()
+                .map(|(i, utxo)| {
+                    let consolidate = plan
+                        .as_ref()
+                        .map(|plan| plan.consolidate.contains(&i))
+                        .unwrap_or(false);
+                    vec![
+                        Cell::string(utxo.outpoint),
+                        Cell::Amount(Amount::from_sat(utxo.txout.value)),
+                        Cell::Amount(input_cost(now)),
+                        Cell::Amount(input_cost(horizon)),
+                        Cell::string(if consolidate { "consolidate" } else { "keep" }),
+                    ]
+                })
+                .collect();
+
+            match &plan {
+                Some(plan) => eprintln!(
+                    "consolidating {} coin(s) now at {:.1} sat/vb costs {} and saves {} compared to spending them at {:.1} sat/vb",
+                    plan.consolidate.len(),
+                    now.as_sat_vb(),
+                    format_amount(plan.fee),
+                    format_amount(plan.saved),
+                    horizon.as_sat_vb()
+                ),
+                None => eprintln!(
+                    "consolidating now at {:.1} sat/vb wouldn't save anything compared to spending at {:.1} sat/vb",
+                    now.as_sat_vb(),
+                    horizon.as_sat_vb()
+                ),
+            }
+
+            Ok(CmdOutput::table(
+                vec!["outpoint", "value", "cost-now", "cost-later", "action"],
+                rows,
+            ))
+        }
+    }
+}
+
+/// A list of output values and how many of each to create e.g. `0.01x10,0.001x50`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Denominations(pub Vec<(Amount, usize)>);
+
+impl FromStr for Denominations {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        let mut denominations = vec![];
+        for denomination in string.split(',') {
+            let (value, count) = denomination.trim().rsplit_once('x').ok_or(anyhow!(
+                "denomination '{}' should be in the form <value>x<count> e.g. 0.01x10",
+                denomination
+            ))?;
+            // values without a unit are in BTC
+            let value = match value.ends_with(char::is_numeric) {
+                true => Amount::from_str_in(value, bdk::bitcoin::Denomination::Bitcoin)?,
+                false => Amount::from_cli_str(value)?,
+            };
+            let count = usize::from_str(count)
+                .with_context(|| format!("parsing count of denomination '{}'", denomination))?;
+            if value == Amount::ZERO || count == 0 {
+                return Err(anyhow!("denomination '{}' is empty", denomination));
+            }
+            denominations.push((value, count));
+        }
+        Ok(Denominations(denominations))
     }
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct SplitOpt {
     /// The value of each output (best if this divides total)
-    #[structopt(parse(try_from_str = FromCliStr::from_cli_str))]
-    output_size: Amount,
+    #[structopt(parse(try_from_str = FromCliStr::from_cli_str), required_unless = "denominations")]
+    output_size: Option<Amount>,
     /// Number of outputs to create. If omitted it will use the maximum possible.
     n: Option<usize>,
+    /// Split into several denominations instead e.g. 0.01x10,0.001x50 creates ten 0.01 BTC outputs
+    /// and fifty 0.001 BTC outputs.
+    #[structopt(long, conflicts_with = "output-size")]
+    denominations: Option<Denominations>,
     #[structopt(flatten)]
     spend_opt: SpendOpt,
 }
@@ -505,45 +1567,160 @@ pub fn run_split_cmd(wallet_dir: &PathBuf, opt: SplitOpt) -> anyhow::Result<CmdO
     let SplitOpt {
         output_size,
         n,
-        spend_opt,
+        denominations,
+        mut spend_opt,
     } = opt;
     let party = load_party(wallet_dir)?;
     let wallet = party.wallet();
     let mut builder = wallet.build_tx();
 
+    let mut recipients = vec![];
+
+    if let Some(Denominations(denominations)) = denominations {
+        for (value, count) in &denominations {
+            for _ in 0..*count {
+                let script_pubkey = wallet
+                    .get_change_address(AddressIndex::New)?
+                    .address
+                    .script_pubkey();
+                builder.add_recipient(script_pubkey.clone(), value.as_sat());
+                recipients.push(script_pubkey);
+            }
+        }
+
+        return spend_opt.spend_coins_with_summary(&party, builder, &recipients, |psbt| {
+            Some(denomination_fee_summary(psbt, &denominations))
+        });
+    }
+
+    let output_size = output_size.expect("structopt makes sure it is there");
+
     let already_correct = wallet
         .list_unspent()?
         .into_iter()
         .filter(|utxo| utxo.txout.value == output_size.as_sat());
 
-    builder.unspendable(already_correct.map(|utxo| utxo.outpoint).collect());
+    for outpoint in already_correct.map(|utxo| utxo.outpoint) {
+        builder.add_unspendable(outpoint);
+    }
 
     match n {
         Some(n) => {
             for _ in 0..n {
-                builder.add_recipient(
-                    wallet
-                        .get_change_address(AddressIndex::New)?
-                        .address
-                        .script_pubkey(),
-                    output_size.as_sat(),
-                );
+                let script_pubkey = wallet
+                    .get_change_address(AddressIndex::New)?
+                    .address
+                    .script_pubkey();
+                builder.add_recipient(script_pubkey.clone(), output_size.as_sat());
+                recipients.push(script_pubkey);
             }
         }
         None => {
+            let script_pubkey = wallet
+                .get_change_address(AddressIndex::New)?
+                .address
+                .script_pubkey();
+            check_split_size(output_size, party.settings(), &script_pubkey)?;
             builder
                 .drain_wallet()
                 // add one recipient so we at least get one split utxo of the correct size.
-                .add_recipient(
-                    wallet
-                        .get_change_address(AddressIndex::New)?
-                        .address
-                        .script_pubkey(),
-                    output_size.as_sat(),
-                )
+                .add_recipient(script_pubkey.clone(), output_size.as_sat())
                 .split_change(output_size.as_sat(), usize::MAX);
+            recipients.push(script_pubkey);
+            // the rest is split up in the wallet rather than being change
+            spend_opt.drains_wallet = true;
         }
     };
 
-    spend_opt.spend_coins(&party, builder)
+    spend_opt.spend_coins(&party, builder, &recipients)
+}
+
+/// Refuses to split change into outputs that are too small to be worth making. Each split output
+/// could otherwise be refused as too small or (below the dust change threshold) taken for dust.
+fn check_split_size(
+    output_size: Amount,
+    settings: &PartySettings,
+    script_pubkey: &Script,
+) -> anyhow::Result<()> {
+    let min_output_value = crate::dust::min_output_value(settings.min_output_value, script_pubkey);
+    if output_size < min_output_value {
+        return Err(ErrorKind::Policy.error(format!(
+            "can't split into outputs of {} since the smallest output this wallet makes is {}",
+            output_size, min_output_value
+        )));
+    }
+    if output_size < settings.dust_change_threshold {
+        return Err(ErrorKind::Policy.error(format!(
+            "can't split into outputs of {} since they'd be below the dust change threshold of {}",
+            output_size, settings.dust_change_threshold
+        )));
+    }
+    Ok(())
+}
+
+// p2wpkh output: 8 byte value + 1 byte script length + 22 byte script
+const P2WPKH_OUTPUT_VBYTES: u64 = 31;
+
+fn denomination_fee_summary(psbt: &Psbt, denominations: &[(Amount, usize)]) -> String {
+    use term_table::{row::Row, Table};
+    let (_, feerate) = psbt.fee();
+    let mut table = Table::new();
+    table.add_row(Row::new(vec![
+        "denomination",
+        "count",
+        "total",
+        "fee-overhead",
+    ]));
+    for (value, count) in denominations {
+        let vbytes = *count as u64 * P2WPKH_OUTPUT_VBYTES;
+        let overhead = Amount::from_sat((vbytes as f32 * feerate.as_sat_vb()).ceil() as u64);
+        table.add_row(Row::new(vec![
+            format_amount(*value),
+            count.to_string(),
+            format_amount(*value * *count as u64),
+            format!("{} sats", overhead.as_sat()),
+        ]));
+    }
+    table.render()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunked_addresses() {
+        assert_eq!(chunked("bc1qar0srrr7"), "bc1q ar0s rrr7");
+        assert_eq!(chunked("bc1qa"), "bc1q a");
+    }
+
+    #[test]
+    fn parse_denominations() {
+        assert_eq!(
+            Denominations::from_str("0.01x10,0.001x50").unwrap(),
+            Denominations(vec![
+                (Amount::from_sat(1_000_000), 10),
+                (Amount::from_sat(100_000), 50)
+            ])
+        );
+        assert_eq!(
+            Denominations::from_str("5000satx2").unwrap(),
+            Denominations(vec![(Amount::from_sat(5_000), 2)])
+        );
+        assert!(Denominations::from_str("0.01").is_err());
+        assert!(Denominations::from_str("0.01x0").is_err());
+    }
+
+    #[test]
+    fn split_outputs_below_the_dust_change_threshold_are_refused() {
+        let script_pubkey = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+            .unwrap()
+            .script_pubkey();
+        let settings = PartySettings::default();
+        assert!(settings.dust_change_threshold > Amount::from_sat(500));
+        assert!(check_split_size(Amount::from_sat(500), &settings, &script_pubkey).is_err());
+        assert!(
+            check_split_size(settings.dust_change_threshold, &settings, &script_pubkey).is_ok()
+        );
+    }
 }
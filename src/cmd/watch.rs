@@ -0,0 +1,153 @@
+use super::*;
+use crate::{
+    betting::alert,
+    item,
+    watch::{change_alert, WatchExport, WatchList, Watcher},
+};
+use bdk::blockchain::AnyBlockchainConfig;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+/// Keep an eye on bets exported with `gun bet export-watch` from another wallet.
+///
+/// This doesn't need the wallet's keys (or a wallet at all) and can't claim or cancel the bets.
+pub enum WatchOpt {
+    /// Start watching the bets in a file from `gun bet export-watch`
+    Import {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// List the watched bets and what has been seen of them
+    List,
+    /// Look at the chain and the oracles and run the `alert-command` for every bet that has
+    /// changed. Run it regularly e.g. from cron.
+    Check,
+    /// Stop watching bets
+    Remove { ids: Vec<crate::betting::BetId> },
+}
+
+pub fn run_watch_cmd(wallet_dir: &PathBuf, opt: WatchOpt) -> anyhow::Result<CmdOutput> {
+    match opt {
+        WatchOpt::Import { file } => {
+            let export = serde_json::from_str::<WatchExport>(
+                &fs::read_to_string(&file)
+                    .with_context(|| format!("reading {}", file.display()))?,
+            )
+            .with_context(|| format!("decoding {}", file.display()))?;
+
+            if wallet_dir.join("config.json").exists() {
+                let config = load_config(wallet_dir)?;
+                if config.network != export.network {
+                    return Err(anyhow!(
+                        "the bets are on {} but this is set up for {}",
+                        export.network,
+                        config.network
+                    ));
+                }
+            } else {
+                fs::create_dir_all(wallet_dir)
+                    .with_context(|| format!("creating {}", wallet_dir.display()))?;
+                write_config(wallet_dir, &Config::default_config(export.network))?;
+                eprintln!(
+                    "Wrote a config for {} to {}. Set alert-command in it to be told when bets change.",
+                    export.network,
+                    wallet_dir.join("config.json").display()
+                );
+            }
+
+            let mut watch_list = WatchList::load(wallet_dir)?;
+            let n_bets = export.bets.len();
+            let new = watch_list.import(export);
+            watch_list.save(wallet_dir)?;
+            Ok(item! {
+                "new" => Cell::Int(new as u64),
+                "updated" => Cell::Int((n_bets - new) as u64),
+                "watching" => Cell::Int(watch_list.bets.len() as u64),
+            })
+        }
+        WatchOpt::List => {
+            let watch_list = WatchList::load(wallet_dir)?;
+            let rows = watch_list
+                .bets
+                .into_iter()
+                .map(|bet| {
+                    vec![
+                        Cell::Int(bet.bet_id as u64),
+                        Cell::string(bet.seen.state(&bet.my_outcome)),
+                        Cell::string(
+                            bet.event_url()
+                                .map(|url| url.to_string())
+                                .unwrap_or_default(),
+                        ),
+                        bet.expected_outcome_time
+                            .map(Cell::datetime)
+                            .unwrap_or(Cell::Empty),
+                        Cell::String(bet.my_outcome),
+                        Cell::Amount(bet.local_value),
+                        Cell::Amount(bet.joint_output_value),
+                        Cell::string(bet.txid),
+                    ]
+                })
+                .collect();
+            Ok(CmdOutput::table(
+                vec![
+                    "id", "state", "event", "expected", "i-bet", "risk", "pot", "txid",
+                ],
+                rows,
+            ))
+        }
+        WatchOpt::Check => {
+            let config = load_config(wallet_dir)?;
+            let esplora_url = match crate::pinned_backend::blockchain_config(&config)? {
+                AnyBlockchainConfig::Esplora(esplora) => esplora.base_url,
+                _ => {
+                    return Err(anyhow!(
+                        "watching bets needs an esplora server in the blockchain config"
+                    ))
+                }
+            };
+            let watcher = Watcher::new(
+                &esplora_url,
+                config.oracle_sources.clone(),
+                load_bet_db(wallet_dir)?,
+            )?;
+            let mut watch_list = WatchList::load(wallet_dir)?;
+            let mut rows = vec![];
+            for i in 0..watch_list.bets.len() {
+                let bet = &watch_list.bets[i];
+                if let "canceled" | "claimed" = bet.seen.state(&bet.my_outcome) {
+                    continue;
+                }
+                let seen = match watcher.look(bet, &watch_list.oracles) {
+                    Ok(seen) => seen,
+                    Err(e) => {
+                        eprintln!("couldn't check bet {}: {}", bet.bet_id, e);
+                        continue;
+                    }
+                };
+                let message = change_alert(bet, &bet.seen, &seen).map(|(kind, message)| {
+                    alert(config.alert_command.as_deref(), kind, &message);
+                    message
+                });
+                rows.push(vec![
+                    Cell::Int(bet.bet_id as u64),
+                    Cell::string(seen.state(&bet.my_outcome)),
+                    message.map(Cell::String).unwrap_or(Cell::Empty),
+                ]);
+                watch_list.bets[i].seen = seen;
+                // save as we go so a failure later doesn't repeat the alerts
+                watch_list.save(wallet_dir)?;
+            }
+            Ok(CmdOutput::table(vec!["id", "state", "change"], rows))
+        }
+        WatchOpt::Remove { ids } => {
+            let mut watch_list = WatchList::load(wallet_dir)?;
+            let before = watch_list.bets.len();
+            watch_list.bets.retain(|bet| !ids.contains(&bet.bet_id));
+            watch_list.save(wallet_dir)?;
+            Ok(item! {
+                "removed" => Cell::Int((before - watch_list.bets.len()) as u64),
+            })
+        }
+    }
+}
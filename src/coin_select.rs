@@ -0,0 +1,587 @@
+use bdk::{
+    bitcoin::{Amount, OutPoint, Script},
+    database::Database,
+    wallet::coin_selection::{
+        CoinSelectionAlgorithm, CoinSelectionResult, DefaultCoinSelectionAlgorithm,
+    },
+    FeeRate, WeightedUtxo,
+};
+use std::str::FromStr;
+
+/// The virtual size of a transaction with no inputs or outputs (version, locktime, counts and
+/// segwit marker).
+pub const TX_OVERHEAD_VBYTES: f32 = 10.5;
+/// The virtual size of a signed p2wpkh input.
+pub const P2WPKH_INPUT_VBYTES: f32 = 68.0;
+/// The virtual size of a p2wpkh output (8 byte value, 1 byte script length and 22 byte script).
+pub const P2WPKH_OUTPUT_VBYTES: f32 = 31.0;
+
+const MAX_TRIES: usize = 100_000;
+// outpoint (32 + 4) + sequence (4) + script length (1)
+const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4 + 1) * 4;
+
+/// Which coin selection algorithm the wallet uses.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoinSelectPolicy {
+    /// bdk's default (branch and bound)
+    Default,
+    /// Always spend every coin on an address together (see [`AvoidReuse`])
+    AvoidReuse,
+}
+
+impl Default for CoinSelectPolicy {
+    fn default() -> Self {
+        CoinSelectPolicy::Default
+    }
+}
+
+impl FromStr for CoinSelectPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        Ok(match string {
+            "default" => CoinSelectPolicy::Default,
+            "avoid-reuse" => CoinSelectPolicy::AvoidReuse,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "'{}' is not a coin selection policy (expected default or avoid-reuse)",
+                    string
+                ))
+            }
+        })
+    }
+}
+
+/// Selects coins according to a [`CoinSelectPolicy`].
+#[derive(Debug, Clone, Copy)]
+pub struct PolicyCoinSelection(pub CoinSelectPolicy);
+
+impl<D: Database> CoinSelectionAlgorithm<D> for PolicyCoinSelection {
+    fn coin_select(
+        &self,
+        database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+        fee_amount: f32,
+    ) -> Result<CoinSelectionResult, bdk::Error> {
+        match self.0 {
+            CoinSelectPolicy::Default if crate::deterministic::is_enabled() => SeededRandomDraw
+                .coin_select(
+                    database,
+                    required_utxos,
+                    optional_utxos,
+                    fee_rate,
+                    amount_needed,
+                    fee_amount,
+                ),
+            CoinSelectPolicy::Default => DefaultCoinSelectionAlgorithm::default().coin_select(
+                database,
+                required_utxos,
+                optional_utxos,
+                fee_rate,
+                amount_needed,
+                fee_amount,
+            ),
+            CoinSelectPolicy::AvoidReuse => AvoidReuse.coin_select(
+                database,
+                required_utxos,
+                optional_utxos,
+                fee_rate,
+                amount_needed,
+                fee_amount,
+            ),
+        }
+    }
+}
+
+/// Groups coins by address and only ever selects whole groups so that no address is left with a
+/// partial balance that would later link the transactions spending from it.
+///
+/// Coins that are unspendable (frozen or in use by a bet) can't be selected so they are left
+/// behind.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvoidReuse;
+
+impl<D: Database> CoinSelectionAlgorithm<D> for AvoidReuse {
+    fn coin_select(
+        &self,
+        _database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+        mut fee_amount: f32,
+    ) -> Result<CoinSelectionResult, bdk::Error> {
+        let input_fee = |weighted: &WeightedUtxo| {
+            fee_rate.as_sat_vb() * (TXIN_BASE_WEIGHT + weighted.satisfaction_weight) as f32 / 4.0
+        };
+
+        let required_scripts = required_utxos
+            .iter()
+            .map(|weighted| weighted.utxo.txout().script_pubkey.clone())
+            .collect::<Vec<_>>();
+
+        let mut selected = vec![];
+        let mut selected_amount = 0;
+        let mut select = |group: Vec<WeightedUtxo>, fee_amount: &mut f32| {
+            for weighted in group {
+                *fee_amount += input_fee(&weighted);
+                selected_amount += weighted.utxo.txout().value;
+                selected.push(weighted.utxo);
+            }
+            selected_amount
+        };
+
+        let mut total = select(required_utxos, &mut fee_amount);
+        // the rest of the coins on the addresses of the required coins have to go too
+        let (same_address, mut others): (Vec<_>, Vec<_>) = group_by_script(optional_utxos)
+            .into_iter()
+            .partition(|(script, _)| required_scripts.contains(script));
+        for (_, group) in same_address {
+            total = select(group, &mut fee_amount);
+        }
+
+        let effective_value = |group: &[WeightedUtxo]| {
+            group
+                .iter()
+                .map(|weighted| weighted.utxo.txout().value as f32 - input_fee(weighted))
+                .sum::<f32>()
+        };
+        others.retain(|(_, group)| effective_value(group) > 0.0);
+        others.sort_by(|(_, a), (_, b)| {
+            effective_value(b)
+                .partial_cmp(&effective_value(a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut others = others.into_iter();
+        while (total as f32) < amount_needed as f32 + fee_amount.ceil() {
+            match others.next() {
+                Some((_, group)) => total = select(group, &mut fee_amount),
+                None => {
+                    return Err(bdk::Error::InsufficientFunds {
+                        needed: amount_needed + fee_amount.ceil() as u64,
+                        available: total,
+                    })
+                }
+            }
+        }
+
+        Ok(CoinSelectionResult {
+            selected,
+            fee_amount,
+        })
+    }
+}
+
+/// What the default coin selection does when branch and bound fails (pick coins at random until
+/// there's enough) but with the randomness from [`crate::deterministic::rng`] so the same seed
+/// picks the same coins. Only used in deterministic mode.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeededRandomDraw;
+
+impl<D: Database> CoinSelectionAlgorithm<D> for SeededRandomDraw {
+    fn coin_select(
+        &self,
+        _database: &D,
+        required_utxos: Vec<WeightedUtxo>,
+        mut optional_utxos: Vec<WeightedUtxo>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+        mut fee_amount: f32,
+    ) -> Result<CoinSelectionResult, bdk::Error> {
+        let mut rng =
+            crate::deterministic::rng("coin-select").expect("only used in deterministic mode");
+        // the database doesn't promise an order so start from one that only depends on the coins
+        optional_utxos.sort_by_key(|weighted| weighted.utxo.outpoint());
+        crate::deterministic::shuffle(&mut optional_utxos, &mut rng);
+
+        let mut selected = vec![];
+        let mut total = 0;
+        let mut optional = optional_utxos.into_iter();
+        let mut select = |weighted: WeightedUtxo, fee_amount: &mut f32| {
+            *fee_amount += fee_rate.as_sat_vb()
+                * (TXIN_BASE_WEIGHT + weighted.satisfaction_weight) as f32
+                / 4.0;
+            total += weighted.utxo.txout().value;
+            selected.push(weighted.utxo);
+            total
+        };
+        for weighted in required_utxos {
+            select(weighted, &mut fee_amount);
+        }
+        while (total as f32) < amount_needed as f32 + fee_amount.ceil() {
+            match optional.next() {
+                Some(weighted) => {
+                    select(weighted, &mut fee_amount);
+                }
+                None => {
+                    return Err(bdk::Error::InsufficientFunds {
+                        needed: amount_needed + fee_amount.ceil() as u64,
+                        available: total,
+                    })
+                }
+            }
+        }
+
+        Ok(CoinSelectionResult {
+            selected,
+            fee_amount,
+        })
+    }
+}
+
+fn group_by_script(utxos: Vec<WeightedUtxo>) -> Vec<(Script, Vec<WeightedUtxo>)> {
+    let mut groups: Vec<(Script, Vec<WeightedUtxo>)> = vec![];
+    for weighted in utxos {
+        let script_pubkey = weighted.utxo.txout().script_pubkey.clone();
+        match groups
+            .iter_mut()
+            .find(|(script, _)| *script == script_pubkey)
+        {
+            Some((_, group)) => group.push(weighted),
+            None => groups.push((script_pubkey, vec![weighted])),
+        }
+    }
+    groups
+}
+
+/// A candidate coin for selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    /// What spending it adds to the fee (see [`input_fee`])
+    pub fee: Amount,
+}
+
+/// What an input adds to the fee at `feerate` if its scriptSig and witness weigh
+/// `satisfaction_weight` (BDK's `max_satisfaction_weight` for its descriptor).
+pub fn input_fee(satisfaction_weight: usize, feerate: FeeRate) -> Amount {
+    let vbytes = (TXIN_BASE_WEIGHT + satisfaction_weight) as f32 / 4.0;
+    Amount::from_sat((vbytes * feerate.as_sat_vb()).ceil() as u64)
+}
+
+/// The result of a successful search for a changeless input set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changeless {
+    pub selected: Vec<OutPoint>,
+    /// The fee the transaction will pay including the excess
+    pub fee: Amount,
+    /// How much more than the target fee we are paying so that we don't need change
+    pub excess: Amount,
+}
+
+/// Tries to find a set of coins that pays for `target` plus the fee for spending them without
+/// leaving more than `tolerance` over.
+///
+/// `base_fee` is the fee for the transaction without any inputs. Each coin adds its own
+/// [`Candidate::fee`] and coins that cost more than they are worth are never selected.
+pub fn find_changeless(
+    candidates: &[Candidate],
+    target: Amount,
+    base_fee: Amount,
+    tolerance: Amount,
+) -> Option<Changeless> {
+    let mut effective = candidates
+        .iter()
+        .filter(|candidate| candidate.value > candidate.fee)
+        .map(|candidate| (candidate, (candidate.value - candidate.fee).as_sat()))
+        .collect::<Vec<_>>();
+    // trying the big ones first finds solutions with fewer inputs
+    effective.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+
+    let low = (target + base_fee).as_sat();
+    let high = low + tolerance.as_sat();
+    // what the coins from each index on are worth together
+    let mut remaining = vec![0; effective.len() + 1];
+    for i in (0..effective.len()).rev() {
+        remaining[i] = remaining[i + 1] + effective[i].1;
+    }
+    if remaining[0] < low {
+        return None;
+    }
+
+    // A depth first search that tries including each coin before leaving it out. The coins
+    // included so far are the stack so how deep it goes doesn't depend on ours.
+    let mut selection = vec![];
+    let mut sum = 0;
+    let mut index = 0;
+    let mut tries = 0;
+    while sum < low || sum > high {
+        tries += 1;
+        if tries > MAX_TRIES {
+            return None;
+        }
+        if sum > high || index >= effective.len() || sum + remaining[index] < low {
+            // leave out the last coin we included and try the ones after it
+            let last = selection.pop()?;
+            sum -= effective[last].1;
+            index = last + 1;
+        } else {
+            selection.push(index);
+            sum += effective[index].1;
+            index += 1;
+        }
+    }
+
+    let selected = selection
+        .iter()
+        .map(|i| effective[*i].0)
+        .collect::<Vec<_>>();
+    let excess = sum - low;
+    let fee = base_fee.as_sat()
+        + selected
+            .iter()
+            .map(|candidate| candidate.fee.as_sat())
+            .sum::<u64>()
+        + excess;
+
+    Some(Changeless {
+        selected: selected
+            .iter()
+            .map(|candidate| candidate.outpoint)
+            .collect(),
+        fee: Amount::from_sat(fee),
+        excess: Amount::from_sat(excess),
+    })
+}
+
+/// Which coins are worth consolidating now rather than spending later at a different feerate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidationPlan {
+    /// Indexes of the coins to consolidate
+    pub consolidate: Vec<usize>,
+    /// The fee of the consolidation transaction
+    pub fee: Amount,
+    /// How much less is paid in fees overall compared to spending the coins at the horizon feerate
+    pub saved: Amount,
+}
+
+/// Plans consolidating coins worth `values` at the feerate `now` given they would otherwise be
+/// spent at `horizon`. The consolidated coin still has to be spent at `horizon` later.
+///
+/// Returns `None` if there is nothing to be saved.
+pub fn plan_consolidation(
+    values: &[u64],
+    now: FeeRate,
+    horizon: FeeRate,
+) -> Option<ConsolidationPlan> {
+    if now.as_sat_vb() >= horizon.as_sat_vb() {
+        return None;
+    }
+    // coins that cost more than they are worth to spend now are left alone
+    let consolidate = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value as f32 > P2WPKH_INPUT_VBYTES * now.as_sat_vb())
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    if consolidate.len() < 2 {
+        return None;
+    }
+
+    let inputs = consolidate.len() as f32;
+    let fee = (now.as_sat_vb()
+        * (TX_OVERHEAD_VBYTES + P2WPKH_OUTPUT_VBYTES + inputs * P2WPKH_INPUT_VBYTES))
+        .ceil();
+    let later_without = horizon.as_sat_vb() * inputs * P2WPKH_INPUT_VBYTES;
+    let later_with = horizon.as_sat_vb() * P2WPKH_INPUT_VBYTES;
+    let saved = (later_without - later_with - fee).floor();
+    if saved <= 0.0 {
+        return None;
+    }
+
+    Some(ConsolidationPlan {
+        consolidate,
+        fee: Amount::from_sat(fee as u64),
+        saved: Amount::from_sat(saved as u64),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::{
+        bitcoin::{hashes::Hash, TxOut, Txid},
+        database::MemoryDatabase,
+        KeychainKind, LocalUtxo, Utxo,
+    };
+
+    fn candidates(values: &[u64], fee: u64) -> Vec<Candidate> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Candidate {
+                outpoint: OutPoint::new(Txid::from_slice(&[i as u8; 32]).unwrap(), i as u32),
+                value: Amount::from_sat(*value),
+                fee: Amount::from_sat(fee),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_exact_match() {
+        let candidates = candidates(&[50_000, 30_000, 20_150, 7_000], 50);
+        let changeless = find_changeless(
+            &candidates,
+            Amount::from_sat(50_000),
+            Amount::from_sat(50),
+            Amount::from_sat(0),
+        )
+        .unwrap();
+        // 30_000 + 20_150 - 2 * 50 - 50 == 50_000
+        assert_eq!(
+            changeless.selected,
+            vec![candidates[1].outpoint, candidates[2].outpoint]
+        );
+        assert_eq!(changeless.excess, Amount::ZERO);
+        assert_eq!(changeless.fee, Amount::from_sat(150));
+    }
+
+    #[test]
+    fn respects_tolerance() {
+        let candidates = candidates(&[10_000, 25_000], 0);
+        let target = Amount::from_sat(9_000);
+        assert_eq!(
+            find_changeless(&candidates, target, Amount::ZERO, Amount::from_sat(999)),
+            None
+        );
+        let changeless =
+            find_changeless(&candidates, target, Amount::ZERO, Amount::from_sat(1_000)).unwrap();
+        assert_eq!(changeless.selected, vec![candidates[0].outpoint]);
+        assert_eq!(changeless.excess, Amount::from_sat(1_000));
+    }
+
+    #[test]
+    fn never_selects_uneconomical_coins() {
+        let candidates = candidates(&[100, 100, 100], 100);
+        assert_eq!(
+            find_changeless(
+                &candidates,
+                Amount::from_sat(10),
+                Amount::ZERO,
+                Amount::from_sat(1_000)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn each_coin_pays_for_its_own_input() {
+        let mut candidates = candidates(&[10_000, 10_000], 0);
+        // e.g. a p2pkh coin next to a p2wpkh one
+        candidates[0].fee = input_fee(4 * 108, FeeRate::from_sat_per_vb(10.0));
+        candidates[1].fee = input_fee(108, FeeRate::from_sat_per_vb(10.0));
+        let changeless = find_changeless(
+            &candidates,
+            Amount::from_sat(9_000),
+            Amount::ZERO,
+            Amount::from_sat(400),
+        )
+        .unwrap();
+        assert_eq!(changeless.selected, vec![candidates[1].outpoint]);
+        assert_eq!(changeless.fee, Amount::from_sat(1_000));
+    }
+
+    #[test]
+    fn long_searches_give_up_without_running_out_of_stack() {
+        let candidates = candidates(&[1_000; 50_000], 0);
+        // every sum is a multiple of 1000 so this can't be hit but it takes going deep to see it
+        assert_eq!(
+            find_changeless(
+                &candidates,
+                Amount::from_sat(49_999_500),
+                Amount::ZERO,
+                Amount::ZERO
+            ),
+            None
+        );
+    }
+
+    fn outpoint(i: u8) -> OutPoint {
+        OutPoint::new(Txid::from_slice(&[i; 32]).unwrap(), 0)
+    }
+
+    fn weighted(i: u8, value: u64, script: u8) -> WeightedUtxo {
+        WeightedUtxo {
+            satisfaction_weight: 108,
+            utxo: Utxo::Local(LocalUtxo {
+                outpoint: outpoint(i),
+                txout: TxOut {
+                    value,
+                    script_pubkey: Script::from(vec![script]),
+                },
+                keychain: KeychainKind::External,
+            }),
+        }
+    }
+
+    #[test]
+    fn avoid_reuse_spends_whole_addresses() {
+        let optional = vec![
+            weighted(0, 10_000, 1),
+            weighted(1, 50_000, 2),
+            weighted(2, 5_000, 1),
+            weighted(3, 1_000, 2),
+        ];
+        let result = AvoidReuse
+            .coin_select(
+                &MemoryDatabase::default(),
+                vec![],
+                optional,
+                FeeRate::from_sat_per_vb(1.0),
+                20_000,
+                0.0,
+            )
+            .unwrap();
+        let mut selected = result
+            .selected
+            .iter()
+            .map(|utxo| utxo.outpoint())
+            .collect::<Vec<_>>();
+        selected.sort();
+        // the biggest address covers it but its small coin must come along
+        assert_eq!(selected, vec![outpoint(1), outpoint(3)]);
+    }
+
+    #[test]
+    fn avoid_reuse_takes_siblings_of_required_coins() {
+        let result = AvoidReuse
+            .coin_select(
+                &MemoryDatabase::default(),
+                vec![weighted(0, 100_000, 1)],
+                vec![weighted(1, 1_000, 1), weighted(2, 50_000, 2)],
+                FeeRate::from_sat_per_vb(1.0),
+                20_000,
+                0.0,
+            )
+            .unwrap();
+        let mut selected = result
+            .selected
+            .iter()
+            .map(|utxo| utxo.outpoint())
+            .collect::<Vec<_>>();
+        selected.sort();
+        assert_eq!(selected, vec![outpoint(0), outpoint(1)]);
+    }
+
+    #[test]
+    fn consolidate_when_fees_are_cheaper_now() {
+        let now = FeeRate::from_sat_per_vb(2.0);
+        let horizon = FeeRate::from_sat_per_vb(50.0);
+        let plan = plan_consolidation(&[100_000, 100, 50_000], now, horizon).unwrap();
+        // the 100 sat coin isn't worth spending
+        assert_eq!(plan.consolidate, vec![0, 2]);
+        // 2 * (10.5 + 31 + 2 * 68)
+        assert_eq!(plan.fee, Amount::from_sat(355));
+        // 50 * 2 * 68 - 50 * 68 - 355
+        assert_eq!(plan.saved, Amount::from_sat(3_045));
+
+        assert_eq!(plan_consolidation(&[100_000, 50_000], horizon, now), None);
+        assert_eq!(plan_consolidation(&[100_000], now, horizon), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Spotting coins that were received from coinjoins.
+//!
+//! Coinjoins (Wasabi, Whirlpool, JoinMarket) give everyone an output of the same value so they
+//! can be recognised by many inputs and several equal outputs. When we receive one of those equal
+//! outputs during sync it's recorded as a [`CoinjoinOutput`] and depending on the
+//! [`CoinjoinPolicy`] frozen as well. Sends only spend coinjoin outputs together with other
+//! coinjoin outputs (`--coinjoin`) and bets never use them so mixed and unmixed coins don't end up
+//! in the same transaction.
+use bdk::bitcoin::{Amount, Transaction};
+use olivia_core::chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+/// What to do with coins received from a coinjoin.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoinjoinPolicy {
+    /// Don't look for coinjoins
+    Ignore,
+    /// Label them and keep them apart from other coins when spending
+    Label,
+    /// Also freeze them so they can't be spent until `gun utxo unfreeze`
+    Quarantine,
+}
+
+impl Default for CoinjoinPolicy {
+    fn default() -> Self {
+        CoinjoinPolicy::Label
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CoinjoinSettings {
+    #[serde(default)]
+    pub policy: CoinjoinPolicy,
+    /// How many outputs of the same value (and at least as many inputs) make a transaction a
+    /// coinjoin. Payment batches sometimes have two equal outputs so the default is 3.
+    #[serde(default = "default_min_participants")]
+    pub min_participants: usize,
+}
+
+fn default_min_participants() -> usize {
+    3
+}
+
+impl Default for CoinjoinSettings {
+    fn default() -> Self {
+        Self {
+            policy: CoinjoinPolicy::default(),
+            min_participants: default_min_participants(),
+        }
+    }
+}
+
+/// A coin we received from a coinjoin.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CoinjoinOutput {
+    /// The value of the equal outputs
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub denomination: Amount,
+    /// How many outputs had that value
+    pub participants: usize,
+    pub detected_at: NaiveDateTime,
+}
+
+/// The value that most of `tx`'s outputs have and how many have it if `tx` looks like a
+/// coinjoin.
+pub fn detect(tx: &Transaction, min_participants: usize) -> Option<(Amount, usize)> {
+    let mut counts = HashMap::<u64, usize>::new();
+    for txout in &tx.output {
+        *counts.entry(txout.value).or_default() += 1;
+    }
+    let (value, participants) = counts
+        .into_iter()
+        // the larger value wins a tie since that's more likely the denomination than change
+        .max_by_key(|(value, count)| (*count, *value))?;
+    if participants < min_participants.max(2) || tx.input.len() < participants {
+        return None;
+    }
+    Some((Amount::from_sat(value), participants))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{OutPoint, Script, TxIn, TxOut};
+
+    fn tx(inputs: usize, outputs: &[u64]) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..inputs)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        vout: vout as u32,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|value| TxOut {
+                    value: *value,
+                    script_pubkey: Script::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn detect_equal_outputs() {
+        // whirlpool: 5 in, 5 out of the pool's denomination
+        assert_eq!(
+            detect(&tx(5, &[100_000; 5]), 3),
+            Some((Amount::from_sat(100_000), 5))
+        );
+        // joinmarket: equal outputs plus everyone's change
+        assert_eq!(
+            detect(
+                &tx(4, &[500_000, 500_000, 500_000, 123_456, 654_321, 42_000]),
+                3
+            ),
+            Some((Amount::from_sat(500_000), 3))
+        );
+        // a payment with change
+        assert_eq!(detect(&tx(1, &[500_000, 123_456]), 3), None);
+        // a payment batch that happens to have two equal outputs
+        assert_eq!(detect(&tx(2, &[10_000, 10_000, 5_000]), 3), None);
+        // splitting one coin into equal parts isn't a coinjoin
+        assert_eq!(detect(&tx(1, &[10_000; 10]), 3), None);
+    }
+}
@@ -0,0 +1,171 @@
+//! Keeping the hot wallet under a ceiling by sweeping what's above it to cold storage.
+//!
+//! Once the spendable balance goes over [`ColdStorage::ceiling`] (e.g. after winning bets) a
+//! sweep of everything above [`ColdStorage::keep`] to the cold wallet is proposed. `gun schedule
+//! run` makes it straight away if `auto` is set and raises an alert asking for `gun cold-storage
+//! sweep` otherwise. Sweeps over the approval threshold wait in the approval queue like any other
+//! send.
+use crate::betting::BetDatabase;
+use anyhow::Context;
+use bdk::{
+    bitcoin::{Address, Amount, Network, Script},
+    database::{BatchOperations, MemoryDatabase},
+    descriptor::get_checksum,
+    wallet::AddressIndex,
+    KeychainKind, Wallet,
+};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ColdStorage {
+    /// An address or watch-only descriptor of the cold wallet. Each sweep to a descriptor goes to
+    /// its next address.
+    pub destination: String,
+    /// Sweep once the spendable balance is above this
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub ceiling: Amount,
+    /// How much to leave in the hot wallet after a sweep. It's the ceiling if it isn't set.
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub keep: Option<Amount>,
+    /// The fee for sweeps (see `gun send --fee`)
+    #[serde(default = "default_fee")]
+    pub fee: String,
+    /// Sweep without asking
+    #[serde(default)]
+    pub auto: bool,
+    /// Don't sweep less than this because it's not worth the fee
+    #[serde(
+        default = "default_min_sweep",
+        with = "bdk::bitcoin::util::amount::serde::as_sat"
+    )]
+    pub min_sweep: Amount,
+}
+
+fn default_fee() -> String {
+    "economy".to_string()
+}
+
+fn default_min_sweep() -> Amount {
+    Amount::from_sat(100_000)
+}
+
+impl ColdStorage {
+    pub fn keep(&self) -> Amount {
+        self.keep.unwrap_or(self.ceiling).min(self.ceiling)
+    }
+
+    /// How much to sweep when `spendable` can be spent. `None` if it's under the ceiling or the
+    /// sweep would be too small.
+    pub fn excess(&self, spendable: Amount) -> Option<Amount> {
+        if spendable <= self.ceiling {
+            return None;
+        }
+        let excess = spendable - self.keep();
+        if excess < self.min_sweep {
+            return None;
+        }
+        Some(excess)
+    }
+
+    /// Checks the destination without moving on to a new address.
+    pub fn check_destination(&self, network: Network) -> anyhow::Result<()> {
+        match Address::from_str(&self.destination) {
+            Ok(address) => check_address(&address, network).map(|_| ()),
+            Err(_) => script_pubkey_at(&self.destination, network, 0).map(|_| ()),
+        }
+    }
+
+    /// The script to sweep to next. For a descriptor each call moves on to a new address.
+    pub fn next_destination(
+        &self,
+        bet_db: &BetDatabase,
+        network: Network,
+    ) -> anyhow::Result<Script> {
+        match Address::from_str(&self.destination) {
+            Ok(address) => Ok(check_address(&address, network)?.script_pubkey()),
+            Err(_) => {
+                let body = self.destination.splitn(2, '#').next().unwrap_or("");
+                let checksum = get_checksum(body).context("parsing the cold storage descriptor")?;
+                // the index is kept per descriptor so it's shared with change descriptors
+                let index = bet_db.next_change_index(&checksum)?;
+                script_pubkey_at(&self.destination, network, index)
+            }
+        }
+    }
+}
+
+fn check_address(address: &Address, network: Network) -> anyhow::Result<Address> {
+    crate::address::check(address, network, "the cold storage address")
+}
+
+fn script_pubkey_at(descriptor: &str, network: Network, index: u32) -> anyhow::Result<Script> {
+    let mut database = MemoryDatabase::default();
+    if index > 0 {
+        database.set_last_index(KeychainKind::External, index - 1)?;
+    }
+    let wallet = Wallet::new_offline(descriptor, None, network, database).with_context(|| {
+        format!(
+            "the cold storage destination {} isn't an address or descriptor",
+            descriptor
+        )
+    })?;
+    Ok(wallet
+        .get_address(AddressIndex::New)?
+        .address
+        .script_pubkey())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cold_storage(keep: Option<u64>) -> ColdStorage {
+        ColdStorage {
+            destination: "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".into(),
+            ceiling: Amount::from_sat(1_000_000),
+            keep: keep.map(Amount::from_sat),
+            fee: default_fee(),
+            auto: false,
+            min_sweep: default_min_sweep(),
+        }
+    }
+
+    #[test]
+    fn excess_over_the_ceiling() {
+        let sats = Amount::from_sat;
+        let ceiling_only = cold_storage(None);
+        assert_eq!(ceiling_only.excess(sats(900_000)), None);
+        assert_eq!(ceiling_only.excess(sats(1_050_000)), None);
+        assert_eq!(ceiling_only.excess(sats(1_500_000)), Some(sats(500_000)));
+
+        let keep_less = cold_storage(Some(200_000));
+        assert_eq!(keep_less.excess(sats(1_000_000)), None);
+        assert_eq!(keep_less.excess(sats(1_050_000)), Some(sats(850_000)));
+        // keeping more than the ceiling would sweep it straight back over
+        assert_eq!(cold_storage(Some(5_000_000)).keep(), sats(1_000_000));
+    }
+
+    #[test]
+    fn destination_network() {
+        let cold_storage = cold_storage(None);
+        assert!(cold_storage.check_destination(Network::Regtest).is_ok());
+        assert!(cold_storage.check_destination(Network::Bitcoin).is_err());
+        let bet_db = BetDatabase::test_new();
+        let descriptor = ColdStorage {
+            destination: "wpkh(tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)".into(),
+            ..cold_storage
+        };
+        let first = descriptor
+            .next_destination(&bet_db, Network::Regtest)
+            .unwrap();
+        let second = descriptor
+            .next_destination(&bet_db, Network::Regtest)
+            .unwrap();
+        assert_ne!(first, second);
+    }
+}
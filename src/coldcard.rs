@@ -0,0 +1,111 @@
+//! Working with a Coldcard over its SD card.
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{
+    util::bip32::{DerivationPath, ExtendedPubKey},
+    Network, Txid,
+};
+use std::str::FromStr;
+
+/// The contents of the `coldcard-export.json` file the Coldcard writes from "Advanced > MicroSD
+/// Card > Export Wallet > Generic JSON".
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ColdcardExport {
+    pub chain: String,
+    pub xfp: String,
+    pub bip84: ColdcardAccount,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ColdcardAccount {
+    pub deriv: String,
+    pub xpub: String,
+    /// The first receive address so we can check we've derived the same thing
+    pub first: Option<String>,
+}
+
+impl ColdcardExport {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(json).context("parsing coldcard export")
+    }
+
+    pub fn network(&self) -> anyhow::Result<Network> {
+        Ok(match self.chain.as_str() {
+            "BTC" => Network::Bitcoin,
+            "XTN" => Network::Testnet,
+            "XRT" => Network::Regtest,
+            chain => return Err(anyhow!("unknown coldcard chain '{}'", chain)),
+        })
+    }
+
+    /// The native segwit (BIP84) external and internal descriptors with the key origin so the
+    /// Coldcard can recognise its own inputs and change.
+    pub fn descriptors(&self) -> anyhow::Result<(String, String)> {
+        let xpub = ExtendedPubKey::from_str(&self.bip84.xpub).context("parsing coldcard xpub")?;
+        let path = DerivationPath::from_str(&self.bip84.deriv)
+            .context("parsing coldcard derivation path")?;
+        if self.xfp.len() != 8 || !self.xfp.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("'{}' is not a valid key fingerprint", self.xfp));
+        }
+        let origin = format!(
+            "[{}{}]",
+            self.xfp.to_lowercase(),
+            path.to_string().trim_start_matches('m')
+        );
+        Ok((
+            format!("wpkh({}{}/0/*)", origin, xpub),
+            format!("wpkh({}{}/1/*)", origin, xpub),
+        ))
+    }
+}
+
+/// The name (without extension) we give the SD card files for a transaction.
+pub fn file_stem(txid: Txid) -> String {
+    format!("gun-{}", &txid.to_string()[..8])
+}
+
+/// The files the Coldcard may write after signing `<stem>.psbt`. Finalized transactions come back
+/// as `-final.txn` and partially signed ones as `-part.psbt` or `-signed.psbt` depending on the
+/// firmware.
+pub fn signed_file_names(txid: Txid) -> Vec<String> {
+    let stem = file_stem(txid);
+    vec![
+        format!("{}-final.txn", stem),
+        format!("{}-signed.psbt", stem),
+        format!("{}-part.psbt", stem),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{secp256k1::Secp256k1, util::bip32::ExtendedPrivKey};
+
+    #[test]
+    fn descriptors_from_export() {
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &[42u8; 32])
+            .unwrap()
+            .derive_priv(&secp, &path)
+            .unwrap();
+        let xpub = ExtendedPubKey::from_private(&secp, &xprv);
+        let export = ColdcardExport::from_json(&format!(
+            r#"{{
+                "chain": "XTN",
+                "xfp": "0F056943",
+                "account": 0,
+                "bip84": {{
+                    "name": "p2wpkh",
+                    "deriv": "m/84'/1'/0'",
+                    "xpub": "{}"
+                }}
+            }}"#,
+            xpub
+        ))
+        .unwrap();
+        assert_eq!(export.network().unwrap(), Network::Testnet);
+        let (external, internal) = export.descriptors().unwrap();
+        assert_eq!(external, format!("wpkh([0f056943/84'/1'/0']{}/0/*)", xpub));
+        assert_eq!(internal, format!("wpkh([0f056943/84'/1'/0']{}/1/*)", xpub));
+    }
+}
@@ -1,5 +1,25 @@
+use crate::{
+    amount_ext::AmountUnit,
+    approval::ApprovalPolicy,
+    betting::{
+        BetLimits, BetParties, ConfirmationTargets, CounterpartyConfirmations, PartySettings,
+        RbfDefaults,
+    },
+    board::ProposalBoards,
+    coin_select::CoinSelectPolicy,
+    coinjoin::CoinjoinSettings,
+    cold_storage::ColdStorage,
+    endpoint::ProxyRule,
+    event_source::EventSources,
+    faucet::Faucet,
+    notify::NotificationSettings,
+    price::PriceSource,
+    session::SpendingLock,
+    tx_ordering::TxOrderingPolicy,
+    FeeAliases,
+};
 use bdk::{
-    bitcoin::Network,
+    bitcoin::{Address, Amount, Network, Txid},
     blockchain::{esplora::EsploraBlockchainConfig, AnyBlockchainConfig},
 };
 
@@ -14,22 +34,188 @@ pub enum WalletKeys {
 pub enum WalletKind {
     #[serde(rename = "p2wpkh")]
     P2wpkh,
+    /// Coins are held by these descriptors rather than the seed words (e.g. a hardware wallet).
+    /// The seed words are still used for the keys in bets.
+    Descriptor {
+        external: String,
+        internal: Option<String>,
+    },
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub network: Network,
+    /// The backend blocks and transactions come from. If it's left out of config.json the
+    /// [default](Config::default_blockchain) for the network is filled in when it's loaded.
     pub blockchain: AnyBlockchainConfig,
     pub kind: WalletKind,
     pub keys: WalletKeys,
+    /// Names of signer plugins to load signers from (see [`crate::plugin`]).
+    #[serde(default)]
+    pub signers: Vec<String>,
+    /// SHA256 fingerprint of the TLS certificate the backend must present (see
+    /// [`crate::pinned_backend`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_cert_sha256: Option<String>,
+    /// Try to make sends without change by overpaying the fee by at most this much.
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub avoid_change_tolerance: Option<Amount>,
+    /// Change below this value is added to the fee instead of creating an output for it.
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dust_change_threshold: Option<Amount>,
+    /// Sends and splits won't make outputs worth less than this and change below it is added to
+    /// the fee. It can't go below the dust limit of each output type (see [`crate::dust`]).
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_output_value: Option<Amount>,
+    /// The coin selection policy used when `--coin-select` isn't given.
+    #[serde(default)]
+    pub coin_select: CoinSelectPolicy,
+    /// How the inputs and outputs of sends, splits and bets are ordered: random or bip69.
+    #[serde(default)]
+    pub tx_ordering: TxOrderingPolicy,
+    /// A command (program followed by arguments) that is given each PSBT to sign on stdin and
+    /// returns it signed on stdout (see [`crate::external_signer`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_signer: Option<Vec<String>>,
+    /// Warn if the newest block the backend has is older than this many minutes (three times that
+    /// on testnet and never on regtest or signet).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stale_tip_minutes: Option<u32>,
+    /// A command (program followed by arguments) run when something needs the user's attention
+    /// like a reorg. It is given the message as its last argument and the kind of alert in
+    /// `GUN_ALERT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_command: Option<Vec<String>>,
+    /// Automatically replace our cancel transactions while they are unconfirmed so that they pay
+    /// more than the bet transaction they are canceling.
+    #[serde(default)]
+    pub fee_bump_cancels: bool,
+    /// How many confirmations incoming coins, bets and claims need before they are final.
+    #[serde(default)]
+    pub confirmations: ConfirmationTargets,
+    /// Sends above a threshold need a second person to approve them (see `gun approval`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<ApprovalPolicy>,
+    /// Raise the fee of a transaction in the broadcast queue out of its change every this many
+    /// failed attempts (see [`crate::broadcast_queue`]). It's left as it is if this isn't set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub broadcast_bump_after: Option<u32>,
+    /// Whether sends and bet transactions signal replace-by-fee unless `--rbf` or `--no-rbf` is
+    /// given.
+    #[serde(default)]
+    pub rbf: RbfDefaults,
+    /// Send change to this descriptor instead of the wallet's internal one. Its keys have to come
+    /// from the seed words (see [`crate::change_descriptor`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_descriptor: Option<String>,
+    /// Where to get the price of bitcoin from to show amounts in fiat (see [`crate::price`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_source: Option<PriceSource>,
+    /// How many blocks `--fee fastest`, `hour` and `economy` mean
+    #[serde(default)]
+    pub fee_aliases: FeeAliases,
+    /// The block explorer to link to transactions on e.g. `https://mempool.space`. Transactions are
+    /// linked to at `<explorer>/tx/<txid>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer: Option<String>,
+    /// Where to link transactions and addresses to instead of `explorer` (e.g. an onion
+    /// explorer). `{kind}` is replaced with `tx` or `address` and `{id}` with the txid or address
+    /// e.g. `http://explorer.onion/{kind}/{id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer_url: Option<String>,
+    /// Print a link to the explorer under every txid and address (like `--links`)
+    #[serde(default)]
+    pub explorer_links: bool,
+    /// Claim winnings to this address (e.g. a cold wallet) unless the bet was made with
+    /// `--claim-to`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_to: Option<Address>,
+    /// An xpub of an offline key (e.g. `[d34db33f/84'/0'/9']xpub.../*`) that has to sign with
+    /// gun's to claim new bets so winnings can't be taken from this machine alone (see
+    /// [`crate::betting::BetCosigner`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bet_cosigner: Option<String>,
+    /// Where to get announcements and attestations from for oracles that aren't reached over HTTP
+    /// (see [`crate::event_source`]).
+    #[serde(default, skip_serializing_if = "EventSources::is_empty")]
+    pub oracle_sources: EventSources,
+    /// Nostr relays (e.g. `wss://relay.damus.io`) to send and get `gun bet chat` messages through.
+    /// The other side of the bet needs at least one of them too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nostr_relays: Vec<String>,
+    /// How many confirmations the other side's coins need before taking or funding a bet, by the
+    /// size of the bet e.g. `{ "tiers": [{ "above": 100000, "confirmations": 1 }], "refuse": true }`.
+    #[serde(default)]
+    pub counterparty_confirmations: CounterpartyConfirmations,
+    /// Which wallet and bet events to notify about and how (see [`crate::notify`])
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    /// Nothing can be signed without `gun unlock` (see [`crate::session`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spending_lock: Option<SpendingLock>,
+    /// Never sign or broadcast anything (like `--read-only`)
+    #[serde(default)]
+    pub read_only: bool,
+    /// The unit amounts are shown in: `btc` (the default), `mbtc`, `bits` or `sat`. JSON and
+    /// `--tabs` output is always in sats.
+    #[serde(default)]
+    pub display_unit: AmountUnit,
+    /// The language prompts and labels are shown in e.g. `ru`. If it isn't set it comes from
+    /// `LC_ALL`, `LC_MESSAGES` or `LANG` (see [`crate::i18n`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Where `gun bet publish` can advertise proposals by name (see [`crate::board`])
+    #[serde(default, skip_serializing_if = "ProposalBoards::is_empty")]
+    pub proposal_boards: ProposalBoards,
+    /// What to do with coins received from coinjoins e.g. `{ "policy": "quarantine" }` (see
+    /// [`crate::coinjoin`])
+    #[serde(default)]
+    pub coinjoin: CoinjoinSettings,
+    /// How much bets can risk as percentages of the wallet e.g. `{ "max-bet-percent": 10,
+    /// "max-at-risk-percent": 40 }`. Proposals and offers over them are refused unless they're
+    /// made with `--over-limits`.
+    #[serde(default)]
+    pub bet_limits: BetLimits,
+    /// Which oracles and counterparties bets can be made with (see [`BetParties`])
+    #[serde(default)]
+    pub bet_parties: BetParties,
+    /// Sweep what's above a ceiling in the hot wallet to cold storage e.g. `{ "destination":
+    /// "<address or descriptor>", "ceiling": 1000000 }` (see [`crate::cold_storage`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cold_storage: Option<ColdStorage>,
+    /// Don't warn about send amounts that are far from what the wallet usually sends and so look
+    /// like typos
+    #[serde(default)]
+    pub ignore_unusual_amounts: bool,
+    /// Which proxy to connect to each kind of endpoint through e.g. `[{ "hosts": "onion", "proxy":
+    /// "socks5h://127.0.0.1:9050" }]` (see [`crate::endpoint`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub proxies: Vec<ProxyRule>,
+    /// Where `gun dev faucet` can get test coins from (see [`crate::faucet`])
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub faucets: Vec<Faucet>,
 }
 
 impl Config {
-    pub fn default_config(network: Network) -> Config {
+    /// The backend a wallet on `network` uses if its config doesn't have one. Only Esplora is compiled in so it's a public Esplora server except on regtest where it's
+    /// one on localhost.
+    pub fn default_blockchain(network: Network) -> AnyBlockchainConfig {
         use Network::*;
         let concurrency = Some(4);
-        let blockchain = match network {
+        match network {
             Bitcoin => AnyBlockchainConfig::Esplora(EsploraBlockchainConfig {
                 base_url: "https://mempool.space/api".to_string(),
                 concurrency,
@@ -46,13 +232,148 @@ impl Config {
                 stop_gap: 10,
             }),
             Signet => unimplemented!("signet not supported yet!"),
-        };
+        }
+    }
 
+    pub fn default_config(network: Network) -> Config {
         Config {
             network,
-            blockchain,
+            blockchain: Self::default_blockchain(network),
             kind: WalletKind::P2wpkh,
             keys: WalletKeys::SeedWordsFile,
+            signers: vec![],
+            pinned_cert_sha256: None,
+            avoid_change_tolerance: None,
+            dust_change_threshold: None,
+            min_output_value: None,
+            coin_select: CoinSelectPolicy::default(),
+            tx_ordering: TxOrderingPolicy::default(),
+            external_signer: None,
+            stale_tip_minutes: None,
+            alert_command: None,
+            fee_bump_cancels: false,
+            confirmations: ConfirmationTargets::default(),
+            approval: None,
+            broadcast_bump_after: None,
+            rbf: RbfDefaults::default(),
+            change_descriptor: None,
+            price_source: None,
+            fee_aliases: FeeAliases::default(),
+            explorer: None,
+            explorer_url: None,
+            explorer_links: false,
+            claim_to: None,
+            bet_cosigner: None,
+            oracle_sources: EventSources::default(),
+            nostr_relays: vec![],
+            counterparty_confirmations: CounterpartyConfirmations::default(),
+            notifications: NotificationSettings::default(),
+            spending_lock: None,
+            read_only: false,
+            display_unit: AmountUnit::default(),
+            locale: None,
+            proposal_boards: ProposalBoards::default(),
+            coinjoin: CoinjoinSettings::default(),
+            bet_limits: BetLimits::default(),
+            bet_parties: BetParties::default(),
+            cold_storage: None,
+            ignore_unusual_amounts: false,
+            proxies: vec![],
+            faucets: vec![],
+        }
+    }
+
+    /// The block explorer from the config or a public one for the network if it isn't set.
+    pub fn explorer(&self) -> Option<String> {
+        let explorer = match (&self.explorer, self.network) {
+            (Some(explorer), _) => explorer.as_str(),
+            (None, Network::Bitcoin) => "https://mempool.space",
+            (None, Network::Testnet) => "https://mempool.space/testnet",
+            (None, _) => return None,
+        };
+        Some(explorer.trim_end_matches('/').to_string())
+    }
+
+    /// A link to the transaction or address `id` on the block explorer where `kind` is `tx` or
+    /// `address`.
+    pub fn explorer_link(&self, kind: &str, id: &str) -> Option<String> {
+        let template = match &self.explorer_url {
+            Some(template) => template.clone(),
+            None => format!("{}/{{kind}}/{{id}}", self.explorer()?),
+        };
+        Some(template.replace("{kind}", kind).replace("{id}", id))
+    }
+
+    /// A link to `txid` on the block explorer.
+    pub fn explorer_tx_url(&self, txid: Txid) -> Option<String> {
+        self.explorer_link("tx", &txid.to_string())
+    }
+
+    pub fn explorer_address_url(&self, address: &Address) -> Option<String> {
+        self.explorer_link("address", &address.to_string())
+    }
+
+    pub fn party_settings(&self) -> PartySettings {
+        let mut settings = PartySettings {
+            coin_select: self.coin_select,
+            tx_ordering: self.tx_ordering,
+            alert_command: self.alert_command.clone(),
+            fee_bump_cancels: self.fee_bump_cancels,
+            confirmations: self.confirmations,
+            approval: self.approval.clone(),
+            rbf: self.rbf,
+            change_descriptor: self.change_descriptor.clone(),
+            fee_aliases: self.fee_aliases,
+            claim_to: self.claim_to.clone(),
+            bet_cosigner: self.bet_cosigner.clone(),
+            oracle_sources: self.oracle_sources.clone(),
+            counterparty_confirmations: self.counterparty_confirmations.clone(),
+            notifications: self.notifications.clone(),
+            proposal_boards: self.proposal_boards.clone(),
+            price_source: self.price_source.clone(),
+            coinjoin: self.coinjoin,
+            bet_limits: self.bet_limits,
+            bet_parties: self.bet_parties.clone(),
+            ignore_unusual_amounts: self.ignore_unusual_amounts,
+            ..Default::default()
+        };
+        if let Some(stale_tip_minutes) = self.stale_tip_minutes {
+            settings.stale_tip_minutes = stale_tip_minutes;
         }
+        if let Some(threshold) = self.dust_change_threshold {
+            settings.dust_change_threshold = threshold;
+        }
+        if let Some(min_output_value) = self.min_output_value {
+            settings.min_output_value = min_output_value;
+            settings.dust_change_threshold = settings.dust_change_threshold.max(min_output_value);
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn explorer_links() {
+        let txid =
+            Txid::from_str("0767a2ca3e7a2e9d5f0eec0a5bf2e5a1b6b76bbbe1b0e64d5ea3c2e4a9e1f7d3")
+                .unwrap();
+        let mut config = Config::default_config(Network::Bitcoin);
+        assert_eq!(
+            config.explorer_tx_url(txid),
+            Some(format!("https://mempool.space/tx/{}", txid))
+        );
+        config.explorer_url = Some("http://explorer.onion/{kind}/{id}".into());
+        assert_eq!(
+            config.explorer_link("address", "bc1qexample"),
+            Some("http://explorer.onion/address/bc1qexample".into())
+        );
+        assert_eq!(
+            Config::default_config(Network::Regtest).explorer_tx_url(txid),
+            None
+        );
     }
 }
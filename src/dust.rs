@@ -0,0 +1,106 @@
+//! The smallest outputs gun will make.
+//!
+//! Nodes don't relay transactions with outputs below the dust limit of their type so that's the
+//! floor. Some don't want outputs that would cost nearly as much to spend as they're worth either
+//! and set `min-output-value` in the config to raise it. Sends and splits refuse to make outputs
+//! below it and change below it is added to the fee.
+use crate::exit_code::ErrorKind;
+use bdk::bitcoin::{
+    consensus::encode::VarInt, util::psbt::PartiallySignedTransaction as Psbt, Amount, Script,
+};
+
+/// The feerate (sat/vb) Bitcoin Core's dust limit is worked out at.
+const DUST_RELAY_SAT_VB: u64 = 3;
+
+/// The dust limit of outputs with `script_pubkey`: what it costs at 3 sat/vb to create and spend
+/// them e.g. 294 sats for p2wpkh, 330 for p2wsh and p2tr and 546 for p2pkh.
+pub fn dust_limit(script_pubkey: &Script) -> Amount {
+    if script_pubkey.is_provably_unspendable() {
+        return Amount::ZERO;
+    }
+    let script_len = script_pubkey.len();
+    let output_vbytes = 8 + VarInt(script_len as u64).len() + script_len;
+    let spend_vbytes = match script_pubkey.is_witness_program() {
+        // outpoint, empty script_sig, sequence and a (discounted) signature and public key
+        true => 32 + 4 + 1 + 4 + 26,
+        false => 32 + 4 + 1 + 107 + 4,
+    };
+    Amount::from_sat((output_vbytes + spend_vbytes) as u64 * DUST_RELAY_SAT_VB)
+}
+
+/// The smallest output to `script_pubkey` gun will make with `min-output-value` set to
+/// `configured`. It's never below the dust limit.
+pub fn min_output_value(configured: Amount, script_pubkey: &Script) -> Amount {
+    configured.max(dust_limit(script_pubkey))
+}
+
+/// Errors if one of the outputs of `psbt` that isn't change is below [`min_output_value`].
+pub fn check_min_outputs(
+    psbt: &Psbt,
+    configured: Amount,
+    is_change: impl Fn(&Script) -> bool,
+) -> anyhow::Result<()> {
+    for (i, txout) in psbt.global.unsigned_tx.output.iter().enumerate() {
+        let min = min_output_value(configured, &txout.script_pubkey);
+        if txout.value < min.as_sat() && !is_change(&txout.script_pubkey) {
+            return Err(ErrorKind::Policy.error(format!(
+                "output {} is {} which is less than the smallest output this wallet makes of {}{}",
+                i,
+                Amount::from_sat(txout.value),
+                min,
+                match min > dust_limit(&txout.script_pubkey) {
+                    true => " (see min-output-value in the config)",
+                    false => " (the dust limit)",
+                }
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{hashes::Hash, PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash};
+
+    #[test]
+    fn dust_limits_match_bitcoin_core() {
+        let limit = |script: Script| dust_limit(&script).as_sat();
+        assert_eq!(
+            limit(Script::new_v0_wpkh(
+                &WPubkeyHash::from_slice(&[1; 20]).unwrap()
+            )),
+            294
+        );
+        assert_eq!(
+            limit(Script::new_v0_wsh(
+                &WScriptHash::from_slice(&[1; 32]).unwrap()
+            )),
+            330
+        );
+        assert_eq!(
+            limit(Script::new_p2pkh(
+                &PubkeyHash::from_slice(&[1; 20]).unwrap()
+            )),
+            546
+        );
+        assert_eq!(
+            limit(Script::new_p2sh(&ScriptHash::from_slice(&[1; 20]).unwrap())),
+            540
+        );
+        assert_eq!(limit(Script::new_op_return(b"gun")), 0);
+    }
+
+    #[test]
+    fn configured_minimum_is_never_below_dust() {
+        let p2wpkh = Script::new_v0_wpkh(&WPubkeyHash::from_slice(&[1; 20]).unwrap());
+        assert_eq!(
+            min_output_value(Amount::from_sat(100), &p2wpkh),
+            Amount::from_sat(294)
+        );
+        assert_eq!(
+            min_output_value(Amount::from_sat(10_000), &p2wpkh),
+            Amount::from_sat(10_000)
+        );
+    }
+}
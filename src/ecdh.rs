@@ -6,7 +6,7 @@ use sha2::{
         generic_array::{sequence::Split, typenum::U32},
         Digest,
     },
-    Sha512,
+    Sha256, Sha512,
 };
 
 pub fn ecdh(keypair: &KeyPair, remote: &Point<EvenY>) -> (ChaCha20, ChaCha20Rng) {
@@ -20,3 +20,16 @@ pub fn ecdh(keypair: &KeyPair, remote: &Point<EvenY>) -> (ChaCha20, ChaCha20Rng)
 
     (cipher, rng)
 }
+
+/// A key shared by `keypair` and `remote` for `purpose` that has nothing to do with the one
+/// [`ecdh`] gives.
+pub fn shared_key(keypair: &KeyPair, remote: &Point<EvenY>, purpose: &[u8]) -> [u8; 32] {
+    let Y = remote;
+    let x = &keypair.secret_key;
+    let XY = g!(x * Y).mark::<Normal>();
+    Sha256::default()
+        .chain(purpose)
+        .chain(XY.to_xonly().as_bytes())
+        .finalize()
+        .into()
+}
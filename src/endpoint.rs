@@ -0,0 +1,323 @@
+//! Where gun connects to and through what.
+//!
+//! Endpoints (the backend, oracles, proposal boards, faucets and so on) can be written as urls or
+//! as bare hosts e.g. `h00.ooo`, `[2001:db8::1]:3000` or `xyz.onion`. A bare host gets `http` if
+//! it's an onion service or on this machine and `https` otherwise. IPv6 addresses in urls need
+//! brackets but a bare address without a port is put in them by [`parse_endpoint`].
+//!
+//! The `proxies` setting picks a proxy for each endpoint by the kind of host it has e.g. to only go
+//! through Tor for onion services:
+//!
+//! ```json
+//! "proxies": [{ "hosts": "onion", "proxy": "socks5h://127.0.0.1:9050" }]
+//! ```
+//!
+//! The first rule that matches the host is used and `"proxy": "none"` connects directly. Once there
+//! are any rules the endpoints no rule matches connect directly too and `HTTP_PROXY`, `HTTPS_PROXY`
+//! and `ALL_PROXY` are ignored. Onion services have to go through a `socks5h` proxy so the proxy
+//! looks up the name rather than us.
+//!
+//! Everything made with [`http_client`] follows the rules. The backend's client is made inside bdk
+//! so its proxy is given to it through `HTTP_PROXY` and `HTTPS_PROXY` by [`proxy_backend`].
+use crate::Url;
+use anyhow::{anyhow, Context};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::RwLock,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostKind {
+    Onion,
+    Ipv4,
+    Ipv6,
+    /// A name looked up with DNS
+    Dns,
+}
+
+impl HostKind {
+    pub fn of(url: &Url) -> Option<HostKind> {
+        let host = url.host_str()?;
+        Some(if host.starts_with('[') {
+            HostKind::Ipv6
+        } else if host.parse::<Ipv4Addr>().is_ok() {
+            HostKind::Ipv4
+        } else if is_onion(host) {
+            HostKind::Onion
+        } else {
+            HostKind::Dns
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostKind::Onion => "onion",
+            HostKind::Ipv4 => "ipv4",
+            HostKind::Ipv6 => "ipv6",
+            HostKind::Dns => "dns",
+        }
+    }
+}
+
+fn is_onion(domain: &str) -> bool {
+    domain
+        .trim_end_matches('.')
+        .to_lowercase()
+        .ends_with(".onion")
+}
+
+fn is_local(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+/// Parses an endpoint written as a url or a bare host with an optional port and path.
+pub fn parse_endpoint(string: &str) -> anyhow::Result<Url> {
+    let string = string.trim();
+    if string.contains("://") {
+        return Url::parse(string).map_err(|e| {
+            match string.matches(':').count() > 2 && !string.contains('[') {
+                true => anyhow!(
+                    "{} isn't a url: {} (IPv6 addresses need brackets e.g. http://[::1]:3000)",
+                    string,
+                    e
+                ),
+                false => anyhow!("{} isn't a url: {}", string, e),
+            }
+        });
+    }
+    let with_brackets;
+    let bare = match string.parse::<Ipv6Addr>() {
+        Ok(_) => {
+            with_brackets = format!("[{}]", string);
+            &with_brackets
+        }
+        Err(_) if string.matches(':').count() > 1 && !string.starts_with('[') => {
+            return Err(anyhow!(
+                "{} looks like an IPv6 address with a port -- put the address in brackets e.g. [::1]:3000",
+                string
+            ))
+        }
+        Err(_) => string,
+    };
+    let host = match bare.starts_with('[') {
+        true => &bare[..bare.find(']').map(|i| i + 1).unwrap_or_else(|| bare.len())],
+        false => bare.split(|c| c == ':' || c == '/').next().unwrap_or(bare),
+    };
+    Url::parse(&format!("{}://{}", default_scheme(host), bare))
+        .with_context(|| format!("{} isn't a host or url", string))
+}
+
+/// The scheme to use for `host` when it's written without one.
+pub fn default_scheme(host: &str) -> &'static str {
+    if is_onion(host) || is_local(host) {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+/// The url of `event_id` at the oracle `oracle_id`.
+pub fn oracle_event_url(oracle_id: &str, event_id: &impl std::fmt::Display) -> anyhow::Result<Url> {
+    Ok(Url::parse(&format!(
+        "{}://{}{}",
+        default_scheme(oracle_id),
+        oracle_id,
+        event_id
+    ))?)
+}
+
+/// Connects to the endpoints with hosts matching `hosts` through `proxy`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProxyRule {
+    /// `onion`, `ipv4`, `ipv6`, `dns`, `all` or a domain (which matches its subdomains too)
+    pub hosts: String,
+    /// A proxy url e.g. `socks5h://127.0.0.1:9050` or `none` to connect directly
+    pub proxy: String,
+}
+
+impl ProxyRule {
+    pub fn matches(&self, url: &Url) -> bool {
+        let kind = match HostKind::of(url) {
+            Some(kind) => kind,
+            None => return false,
+        };
+        match self.hosts.as_str() {
+            "all" => true,
+            "onion" | "ipv4" | "ipv6" | "dns" => kind.name() == self.hosts,
+            domain => url
+                .host_str()
+                .map(|host| {
+                    let host = host.to_lowercase();
+                    let domain = domain.trim_start_matches('.').to_lowercase();
+                    host == domain || host.ends_with(&format!(".{}", domain))
+                })
+                .unwrap_or(false),
+        }
+    }
+
+    /// `None` if it connects directly.
+    pub fn proxy_url(&self) -> anyhow::Result<Option<Url>> {
+        if self.proxy == "none" {
+            return Ok(None);
+        }
+        let url = Url::parse(&self.proxy)
+            .with_context(|| format!("the proxy {} isn't a url", self.proxy))?;
+        match url.scheme() {
+            "http" | "https" | "socks5" | "socks5h" => Ok(Some(url)),
+            scheme => Err(anyhow!(
+                "the proxy {} is {} but only http, https, socks5 and socks5h proxies work",
+                self.proxy,
+                scheme
+            )),
+        }
+    }
+}
+
+static PROXIES: RwLock<Vec<ProxyRule>> = RwLock::new(Vec::new());
+
+/// Sets the proxy rules from now on. Errors without changing them if a proxy isn't valid so nothing
+/// goes around a proxy that was asked for.
+pub fn set_proxies(rules: Vec<ProxyRule>) -> anyhow::Result<()> {
+    for rule in &rules {
+        rule.proxy_url().context("in the proxies setting")?;
+    }
+    *PROXIES.write().expect("proxy rules lock isn't poisoned") = rules;
+    Ok(())
+}
+
+/// The first of `rules` that matches `url`.
+pub fn matching_rule<'a>(rules: &'a [ProxyRule], url: &Url) -> Option<&'a ProxyRule> {
+    rules.iter().find(|rule| rule.matches(url))
+}
+
+/// The rule for connecting to `url` if any rule matches it.
+pub fn proxy_rule(url: &Url) -> Option<ProxyRule> {
+    matching_rule(
+        &PROXIES.read().expect("proxy rules lock isn't poisoned"),
+        url,
+    )
+    .cloned()
+}
+
+/// The proxy to connect to `url` through. `None` if it connects directly.
+pub fn proxy_for(url: &Url) -> Option<Url> {
+    // set_proxies checked they're all valid
+    proxy_rule(url)?.proxy_url().ok().flatten()
+}
+
+fn has_proxies() -> bool {
+    !PROXIES
+        .read()
+        .expect("proxy rules lock isn't poisoned")
+        .is_empty()
+}
+
+/// A client builder that connects to each url through the proxy the rules pick for it.
+pub fn http_client() -> reqwest::blocking::ClientBuilder {
+    let builder = reqwest::blocking::Client::builder();
+    match has_proxies() {
+        // adding a proxy turns off the ones from the environment
+        true => builder.proxy(reqwest::Proxy::custom(proxy_for)),
+        false => builder,
+    }
+}
+
+/// Has the backend's client (which bdk makes) use the proxy the rules pick for `base_url`. If
+/// there are no rules the proxy environment variables are left the way they are.
+pub fn proxy_backend(base_url: &str) {
+    if !has_proxies() {
+        return;
+    }
+    for var in &[
+        "HTTP_PROXY",
+        "HTTPS_PROXY",
+        "http_proxy",
+        "https_proxy",
+        "ALL_PROXY",
+        "all_proxy",
+    ] {
+        std::env::remove_var(var);
+    }
+    if let Some(proxy) = Url::parse(base_url).ok().and_then(|url| proxy_for(&url)) {
+        std::env::set_var("HTTP_PROXY", proxy.as_str());
+        std::env::set_var("HTTPS_PROXY", proxy.as_str());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bare_endpoints() {
+        let parse = |string: &str| parse_endpoint(string).unwrap().to_string();
+        assert_eq!(parse("h00.ooo"), "https://h00.ooo/");
+        assert_eq!(parse("h00.ooo/api"), "https://h00.ooo/api");
+        assert_eq!(parse("localhost:3000"), "http://localhost:3000/");
+        assert_eq!(parse("[::1]:3000"), "http://[::1]:3000/");
+        assert_eq!(parse("2001:db8::1"), "https://[2001:db8::1]/");
+        assert_eq!(
+            parse("[2001:db8::1]:50002/api"),
+            "https://[2001:db8::1]:50002/api"
+        );
+        assert_eq!(
+            parse("expyuzz4wqqyqhjn.onion:8080"),
+            "http://expyuzz4wqqyqhjn.onion:8080/"
+        );
+        assert_eq!(
+            parse("https://expyuzz4wqqyqhjn.onion"),
+            "https://expyuzz4wqqyqhjn.onion/"
+        );
+        assert!(parse_endpoint("2001:db8::1:3000:abcd:x").is_err());
+        assert!(parse_endpoint("http://2001:db8::1:3000").is_err());
+    }
+
+    #[test]
+    fn host_kinds() {
+        let kind = |string: &str| HostKind::of(&parse_endpoint(string).unwrap());
+        assert_eq!(kind("h00.ooo"), Some(HostKind::Dns));
+        assert_eq!(kind("127.0.0.1"), Some(HostKind::Ipv4));
+        assert_eq!(kind("[::1]:3000"), Some(HostKind::Ipv6));
+        assert_eq!(kind("http://ABC.ONION./api"), Some(HostKind::Onion));
+    }
+
+    #[test]
+    fn first_matching_rule_is_used() {
+        let rules = vec![
+            ProxyRule {
+                hosts: "onion".into(),
+                proxy: "socks5h://127.0.0.1:9050".into(),
+            },
+            ProxyRule {
+                hosts: "h00.ooo".into(),
+                proxy: "none".into(),
+            },
+            ProxyRule {
+                hosts: "all".into(),
+                proxy: "http://proxy.example:8080".into(),
+            },
+        ];
+        let rule = |string: &str| {
+            matching_rule(&rules, &parse_endpoint(string).unwrap())
+                .and_then(|rule| rule.proxy_url().unwrap())
+                .map(|proxy| proxy.to_string())
+        };
+        assert_eq!(rule("abc.onion"), Some("socks5h://127.0.0.1:9050".into()));
+        assert_eq!(rule("https://oracle.h00.ooo/x"), None);
+        assert_eq!(
+            rule("[::1]:3000"),
+            Some("http://proxy.example:8080/".into())
+        );
+        assert!(ProxyRule {
+            hosts: "all".into(),
+            proxy: "ftp://x".into()
+        }
+        .proxy_url()
+        .is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Ephemeral mode (`--ephemeral`) for one-off jobs such as sweeping a paper wallet or looking at
+//! what a seed holds from a live USB.
+//!
+//! The wallet comes from seed words or descriptors given when gun starts and nothing about it is
+//! written to the wallet directory. The config is made up on the spot, the database is a temporary
+//! sled database (kept in `/dev/shm` on Linux and deleted when gun exits) and the files gun
+//! normally keeps next to it (the audit log, watch state and so on) are read and written through
+//! [`read_to_string`], [`write`], [`append`] and [`remove_file`] which keep them in memory in this
+//! mode and go to disk otherwise.
+//!
+//! Commands that only make sense for a wallet that's kept (`init`, `bet`, `backup`, `rotate`...)
+//! refuse to run with [`unsupported`].
+use crate::config::{Config, WalletKind};
+use anyhow::{anyhow, Context};
+use bdk::{
+    keys::bip39::{Language, Mnemonic},
+    sled,
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Seed words or descriptors for the wallet are read from this if it's set and otherwise from
+/// stdin.
+pub const KEYS_ENV: &str = "GUN_EPHEMERAL_KEYS";
+
+/// What the ephemeral wallet is made from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Keys {
+    SeedWords(String),
+    Descriptors {
+        external: String,
+        internal: Option<String>,
+    },
+}
+
+impl Keys {
+    /// Seed words or an external and optionally an internal descriptor separated by whitespace.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let text = text.trim();
+        if Mnemonic::validate(text, Language::English).is_ok() {
+            return Ok(Keys::SeedWords(text.to_string()));
+        }
+        let mut descriptors = text.split_whitespace();
+        match (descriptors.next(), descriptors.next(), descriptors.next()) {
+            (Some(external), internal, None) if external.contains('(') => Ok(Keys::Descriptors {
+                external: external.to_string(),
+                internal: internal.map(str::to_string),
+            }),
+            _ => Err(anyhow!(
+                "expected seed words or one or two descriptors for the ephemeral wallet"
+            )),
+        }
+    }
+}
+
+struct Store {
+    config: Config,
+    seed_words: String,
+    database: sled::Db,
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+fn store() -> std::sync::MutexGuard<'static, Option<Store>> {
+    STORE.lock().expect("ephemeral store lock isn't poisoned")
+}
+
+/// Switches to ephemeral mode with a wallet on `network` made from `keys`.
+pub fn enable(network: bdk::bitcoin::Network, keys: Keys) -> anyhow::Result<()> {
+    let mut config = Config::default_config(network);
+    let seed_words = match keys {
+        Keys::SeedWords(seed_words) => seed_words,
+        Keys::Descriptors { external, internal } => {
+            config.kind = WalletKind::Descriptor { external, internal };
+            // bets are refused so these are never used for anything that has to be recovered
+            crate::cmd::generate_seed_words(12)?
+        }
+    };
+    let database = sled::Config::new()
+        .temporary(true)
+        .open()
+        .context("opening the temporary database")?;
+    *store() = Some(Store {
+        config,
+        seed_words,
+        database,
+        files: BTreeMap::new(),
+    });
+    Ok(())
+}
+
+/// Leaves ephemeral mode and deletes the temporary database. It has to be called before exiting
+/// since statics aren't dropped.
+pub fn disable() {
+    store().take();
+}
+
+pub fn is_enabled() -> bool {
+    store().is_some()
+}
+
+/// The error for trying to `action` (e.g. `make bets`) in ephemeral mode.
+pub fn unsupported(action: &str) -> anyhow::Error {
+    crate::exit_code::ErrorKind::BadInput.error(format!(
+        "can't {} with an --ephemeral wallet since nothing is kept after gun exits",
+        action
+    ))
+}
+
+pub fn config() -> Option<Config> {
+    store().as_ref().map(|store| store.config.clone())
+}
+
+/// Replaces the ephemeral wallet's config. Returns false if we're not in ephemeral mode.
+pub fn set_config(config: &Config) -> bool {
+    match store().as_mut() {
+        Some(store) => {
+            store.config = config.clone();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn seed_words() -> Option<String> {
+    store().as_ref().map(|store| store.seed_words.clone())
+}
+
+/// The temporary database. Every call gets the same one so what one part of a command writes the
+/// next part sees.
+pub fn database() -> Option<sled::Db> {
+    store().as_ref().map(|store| store.database.clone())
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} doesn't exist", path.display()),
+    )
+}
+
+/// Reads a file gun keeps in the wallet directory.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    match store().as_ref() {
+        Some(store) => {
+            let contents = store.files.get(path).ok_or_else(|| not_found(path))?;
+            String::from_utf8(contents.clone())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+        None => fs::read_to_string(path),
+    }
+}
+
+/// Writes a file gun keeps in the wallet directory.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    match store().as_mut() {
+        Some(store) => {
+            store
+                .files
+                .insert(path.to_path_buf(), contents.as_ref().to_vec());
+            Ok(())
+        }
+        None => fs::write(path, contents),
+    }
+}
+
+/// Adds to the end of a file gun keeps in the wallet directory. On disk it's synced before
+/// returning.
+pub fn append(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    match store().as_mut() {
+        Some(store) => {
+            store
+                .files
+                .entry(path.to_path_buf())
+                .or_default()
+                .extend_from_slice(contents.as_ref());
+            Ok(())
+        }
+        None => {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        }
+    }
+}
+
+pub fn remove_file(path: &Path) -> io::Result<()> {
+    match store().as_mut() {
+        Some(store) => store
+            .files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| not_found(path)),
+        None => fs::remove_file(path),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_keys() {
+        let words = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        assert_eq!(
+            Keys::parse(&format!("  {}\n", words)).unwrap(),
+            Keys::SeedWords(words.to_string())
+        );
+        assert_eq!(
+            Keys::parse("wpkh(xpub/0/*) wpkh(xpub/1/*)").unwrap(),
+            Keys::Descriptors {
+                external: "wpkh(xpub/0/*)".into(),
+                internal: Some("wpkh(xpub/1/*)".into())
+            }
+        );
+        assert!(Keys::parse("abandon abandon").is_err());
+        assert!(Keys::parse("wpkh(a) wpkh(b) wpkh(c)").is_err());
+    }
+}
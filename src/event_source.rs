@@ -0,0 +1,319 @@
+//! Where oracle announcements and attestations come from.
+//!
+//! By default they are fetched from the oracle itself over HTTP. The `oracle-sources` config
+//! setting can get them for a particular oracle from somewhere else instead e.g.
+//!
+//! ```json
+//! "oracle-sources": {
+//!     "h00.ooo": { "kind": "nostr", "relays": ["wss://relay.damus.io"] },
+//!     "offline.oracle": { "kind": "file", "dir": "/media/usb/oracle" }
+//! }
+//! ```
+//!
+//! - `nostr` asks each relay in turn for NIP-78 events (kind 30078) whose `d` tag is
+//! `<oracle-id>` for the oracle's keys or `<oracle-id><event-id>` for an event. Their content is
+//! the same JSON the oracle would have returned over HTTP.
+//! - `file` reads that JSON from `<dir>/root.json` for the oracle's keys and `<dir><event-id>.json`
+//! for an event so attestations can be delivered by dropping files into the directory.
+//!
+//! Nothing from a source is trusted. Announcements are checked against the oracle's keys and
+//! attestations against the announcement wherever they came from.
+use crate::{betting::EventResponse, Url};
+use anyhow::{anyhow, Context};
+use olivia_core::EventId;
+use olivia_secp256k1::Secp256k1;
+use std::{collections::BTreeMap, fs, path::PathBuf, str::FromStr, time::Duration};
+
+pub type RootResponse = olivia_core::http::RootResponse<Secp256k1>;
+
+/// The NIP-78 "application-specific data" kind oracle messages are published as.
+pub const NOSTR_KIND: u64 = 30078;
+
+/// Somewhere to get an oracle's messages from.
+///
+/// Oracles and events are always named by their HTTP url (e.g.
+/// `https://h00.ooo/random/2021-08-22T00:00:00/heads_tails.winner`) where the host is the oracle
+/// id and the path is the event id even if they don't come from there.
+pub trait EventSource {
+    /// The oracle's public keys
+    fn root(&self, oracle_url: &Url) -> anyhow::Result<RootResponse>;
+    /// The announcement of the event and its attestation if the oracle has made it yet
+    fn event(&self, event_url: &Url) -> anyhow::Result<EventResponse>;
+    /// Like [`event`](Self::event) but only gets the event if it changed since the request that
+    /// gave `validators`. Only HTTP can tell so other sources always get it.
+    fn event_if_changed(
+        &self,
+        event_url: &Url,
+        _validators: &CacheValidators,
+    ) -> anyhow::Result<Fetched<EventResponse>> {
+        Ok(Fetched::Changed(
+            self.event(event_url)?,
+            CacheValidators::default(),
+        ))
+    }
+}
+
+/// What an HTTP server said identifies the version of a response (`ETag` and `Last-Modified`) so
+/// the next request can ask for it only if it changed.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct CacheValidators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+pub enum Fetched<T> {
+    Changed(T, CacheValidators),
+    /// It's the same as when the validators were given
+    NotModified,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum EventSourceConfig {
+    Http,
+    /// Relay urls e.g. `wss://relay.damus.io`
+    Nostr {
+        relays: Vec<String>,
+    },
+    File {
+        dir: PathBuf,
+    },
+}
+
+impl Default for EventSourceConfig {
+    fn default() -> Self {
+        EventSourceConfig::Http
+    }
+}
+
+impl EventSourceConfig {
+    pub fn source(&self) -> Box<dyn EventSource> {
+        match self {
+            EventSourceConfig::Http => Box::new(Http),
+            EventSourceConfig::Nostr { relays } => Box::new(Nostr {
+                relays: relays.clone(),
+            }),
+            EventSourceConfig::File { dir } => Box::new(FileDrop { dir: dir.clone() }),
+        }
+    }
+}
+
+/// The source for each oracle that doesn't use HTTP.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct EventSources(pub BTreeMap<String, EventSourceConfig>);
+
+impl EventSources {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The source of the oracle at `url`.
+    pub fn for_url(&self, url: &Url) -> Box<dyn EventSource> {
+        url.host_str()
+            .and_then(|oracle_id| self.0.get(oracle_id))
+            .cloned()
+            .unwrap_or_default()
+            .source()
+    }
+}
+
+fn oracle_id(url: &Url) -> anyhow::Result<&str> {
+    url.host_str().ok_or(anyhow!("url {} missing host", url))
+}
+
+fn event_id(url: &Url) -> anyhow::Result<EventId> {
+    EventId::from_str(url.path()).with_context(|| format!("trying to parse the path of {}", url))
+}
+
+pub struct Http;
+
+impl Http {
+    fn client(&self) -> anyhow::Result<reqwest::blocking::Client> {
+        Ok(crate::endpoint::http_client()
+            .timeout(Duration::from_secs(30))
+            .build()?)
+    }
+
+    fn get<T: serde::de::DeserializeOwned>(&self, url: &Url) -> anyhow::Result<T> {
+        let body = self
+            .client()?
+            .get(url.clone())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| format!("while getting {}", url))?;
+        serde_json::from_str(&body)
+            .with_context(|| format!("while decoding the response from {}", url))
+    }
+}
+
+impl EventSource for Http {
+    fn root(&self, oracle_url: &Url) -> anyhow::Result<RootResponse> {
+        self.get(oracle_url)
+    }
+
+    fn event(&self, event_url: &Url) -> anyhow::Result<EventResponse> {
+        self.get(event_url)
+    }
+
+    fn event_if_changed(
+        &self,
+        event_url: &Url,
+        validators: &CacheValidators,
+    ) -> anyhow::Result<Fetched<EventResponse>> {
+        use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+        let mut request = self.client()?.get(event_url.clone());
+        if let Some(etag) = &validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request
+            .send()
+            .with_context(|| format!("while getting {}", event_url))?;
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("while getting {}", event_url))?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = CacheValidators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body = response
+            .text()
+            .with_context(|| format!("while getting {}", event_url))?;
+        let event_response = serde_json::from_str(&body)
+            .with_context(|| format!("while decoding the response from {}", event_url))?;
+        Ok(Fetched::Changed(event_response, validators))
+    }
+}
+
+pub struct FileDrop {
+    pub dir: PathBuf,
+}
+
+impl FileDrop {
+    fn read<T: serde::de::DeserializeOwned>(&self, file: PathBuf) -> anyhow::Result<T> {
+        let contents =
+            fs::read_to_string(&file).with_context(|| format!("reading {}", file.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("decoding {}", file.display()))
+    }
+}
+
+impl EventSource for FileDrop {
+    fn root(&self, _oracle_url: &Url) -> anyhow::Result<RootResponse> {
+        self.read(self.dir.join("root.json"))
+    }
+
+    fn event(&self, event_url: &Url) -> anyhow::Result<EventResponse> {
+        let event_id = event_id(event_url)?;
+        let path = event_id.to_string();
+        self.read(
+            self.dir
+                .join(format!("{}.json", path.trim_start_matches('/'))),
+        )
+    }
+}
+
+pub struct Nostr {
+    pub relays: Vec<String>,
+}
+
+impl Nostr {
+    /// The content of every event with the `d` tag from the first relay that has any.
+    fn query(&self, d_tag: &str) -> anyhow::Result<Vec<String>> {
+        let mut errors = vec![];
+        for relay in &self.relays {
+            match query_relay(relay, d_tag) {
+                Ok(contents) if !contents.is_empty() => return Ok(contents),
+                Ok(_) => errors.push(format!("{} has nothing for {}", relay, d_tag)),
+                Err(e) => errors.push(format!("{}: {}", relay, e)),
+            }
+        }
+        Err(anyhow!(
+            "no relay had {}: {}",
+            d_tag,
+            match errors.is_empty() {
+                true => "no relays are configured".to_string(),
+                false => errors.join(", "),
+            }
+        ))
+    }
+}
+
+fn query_relay(relay: &str, d_tag: &str) -> anyhow::Result<Vec<String>> {
+    let events = crate::nostr::query(
+        relay,
+        serde_json::json!({ "kinds": [NOSTR_KIND], "#d": [d_tag] }),
+    )?;
+    Ok(events.into_iter().map(|event| event.content).collect())
+}
+
+impl EventSource for Nostr {
+    fn root(&self, oracle_url: &Url) -> anyhow::Result<RootResponse> {
+        self.query(oracle_id(oracle_url)?)?
+            .iter()
+            .find_map(|content| serde_json::from_str(content).ok())
+            .ok_or(anyhow!("no valid oracle keys for {} on nostr", oracle_url))
+    }
+
+    fn event(&self, event_url: &Url) -> anyhow::Result<EventResponse> {
+        let d_tag = format!("{}{}", oracle_id(event_url)?, event_id(event_url)?);
+        let mut responses = self
+            .query(&d_tag)?
+            .iter()
+            .filter_map(|content| serde_json::from_str::<EventResponse>(content).ok())
+            .collect::<Vec<_>>();
+        // the oracle may have published the announcement alone before the attestation
+        responses.sort_by_key(|response| response.attestation.is_none());
+        responses
+            .into_iter()
+            .next()
+            .ok_or(anyhow!("no valid announcement for {} on nostr", event_url))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sources_from_config() {
+        let sources: EventSources = serde_json::from_str(
+            r#"{
+                "h00.ooo": { "kind": "nostr", "relays": ["wss://relay.example.com"] },
+                "offline.oracle": { "kind": "file", "dir": "/media/usb/oracle" }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            sources.0.get("offline.oracle"),
+            Some(&EventSourceConfig::File {
+                dir: PathBuf::from("/media/usb/oracle")
+            })
+        );
+        assert_eq!(
+            sources.0.get("h00.ooo"),
+            Some(&EventSourceConfig::Nostr {
+                relays: vec!["wss://relay.example.com".to_string()]
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&EventSourceConfig::Http).unwrap(),
+            serde_json::json!({ "kind": "http" })
+        );
+    }
+}
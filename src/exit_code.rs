@@ -0,0 +1,213 @@
+//! Exit codes that say why a command failed so scripts and bots can tell failures apart.
+//!
+//! | code | kind                 | e.g.                                                          |
+//! |------|----------------------|---------------------------------------------------------------|
+//! | 0    |                      | it worked                                                     |
+//! | 1    | `other`              | anything not below                                            |
+//! | 2    | `bad-input`          | arguments that don't parse or make sense                      |
+//! | 3    | `network`            | the backend, an oracle or a board couldn't be reached         |
+//! | 4    | `insufficient-funds` | not enough spendable coins for a send or bet                  |
+//! | 5    | `policy`             | refused by the PSBT policy, bet limits or the approval queue  |
+//! | 6    | `locked`             | the wallet is locked (see `gun unlock`) or read-only          |
+//! | 7    | `not-found`          | no wallet, bet or transaction with that name or id            |
+//! | 8    | `config`             | the config file is missing or invalid                         |
+//!
+//! These don't change between versions. Errors from gun itself are tagged with their kind where
+//! they're made ([`ErrorKind::error`] and [`ErrorKind::wrap`]) and errors from the libraries we use
+//! are recognised by [`classify`]. With `--json-errors` (or `--json`) the error is written as an
+//! object like [`error_json`] makes.
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    BadInput,
+    Network,
+    InsufficientFunds,
+    Policy,
+    Locked,
+    NotFound,
+    Config,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 8] = [
+        ErrorKind::Other,
+        ErrorKind::BadInput,
+        ErrorKind::Network,
+        ErrorKind::InsufficientFunds,
+        ErrorKind::Policy,
+        ErrorKind::Locked,
+        ErrorKind::NotFound,
+        ErrorKind::Config,
+    ];
+
+    /// What gun exits with when a command fails with this kind of error.
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::BadInput => 2,
+            ErrorKind::Network => 3,
+            ErrorKind::InsufficientFunds => 4,
+            ErrorKind::Policy => 5,
+            ErrorKind::Locked => 6,
+            ErrorKind::NotFound => 7,
+            ErrorKind::Config => 8,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::BadInput => "bad-input",
+            ErrorKind::Network => "network",
+            ErrorKind::InsufficientFunds => "insufficient-funds",
+            ErrorKind::Policy => "policy",
+            ErrorKind::Locked => "locked",
+            ErrorKind::NotFound => "not-found",
+            ErrorKind::Config => "config",
+        }
+    }
+
+    /// An error of this kind that says `message`.
+    pub fn error(self, message: impl fmt::Display) -> anyhow::Error {
+        self.wrap(anyhow::anyhow!("{}", message))
+    }
+
+    /// Makes `error` this kind of error. Its message and causes stay the same.
+    pub fn wrap(self, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(Tagged { kind: self, error })
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// An error with its kind attached. It shows as the error it wraps.
+#[derive(Debug)]
+struct Tagged {
+    kind: ErrorKind,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Tagged {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// The kind of `error`. The outermost tag wins and untagged errors are recognised by what they
+/// are e.g. a `reqwest::Error` is a network error.
+pub fn classify(error: &anyhow::Error) -> ErrorKind {
+    for cause in error.chain() {
+        if let Some(tagged) = cause.downcast_ref::<Tagged>() {
+            return tagged.kind;
+        }
+        if let Some(kind) = library_error_kind(cause) {
+            return kind;
+        }
+    }
+    ErrorKind::Other
+}
+
+fn library_error_kind(cause: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    use std::io::ErrorKind as Io;
+    if let Some(error) = cause.downcast_ref::<bdk::Error>() {
+        return match error {
+            bdk::Error::InsufficientFunds { .. } => Some(ErrorKind::InsufficientFunds),
+            // the session and read-only signers refuse with this
+            bdk::Error::Signer(bdk::signer::SignerError::UserCanceled) => Some(ErrorKind::Locked),
+            bdk::Error::Esplora(_) => Some(ErrorKind::Network),
+            _ => None,
+        };
+    }
+    if cause.downcast_ref::<reqwest::Error>().is_some() {
+        return Some(ErrorKind::Network);
+    }
+    if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+        if let Io::ConnectionRefused
+        | Io::ConnectionReset
+        | Io::ConnectionAborted
+        | Io::NotConnected
+        | Io::AddrNotAvailable
+        | Io::TimedOut = error.kind()
+        {
+            return Some(ErrorKind::Network);
+        }
+    }
+    None
+}
+
+/// `error` as an object for `--json-errors` e.g.
+///
+/// ```json
+/// { "error": "loading wallet", "kind": "config", "code": 8, "causes": ["missing config file at /home/me/.gun/config.json"] }
+/// ```
+pub fn error_json(error: &anyhow::Error) -> serde_json::Value {
+    let kind = classify(error);
+    serde_json::json!({
+        "error": error.to_string(),
+        "kind": kind.name(),
+        "code": kind.code(),
+        "causes": error
+            .chain()
+            .skip(1)
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn codes_are_distinct() {
+        for kind in ErrorKind::ALL.iter() {
+            assert_eq!(
+                ErrorKind::ALL
+                    .iter()
+                    .filter(|other| other.code() == kind.code())
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn tags_survive_context() {
+        let error = Err::<(), _>(ErrorKind::Locked.error("the wallet is locked"))
+            .context("signing")
+            .unwrap_err();
+        assert_eq!(classify(&error), ErrorKind::Locked);
+        assert_eq!(format!("{:#}", error), "signing: the wallet is locked");
+
+        let wrapped = ErrorKind::Config.wrap(anyhow::anyhow!("bad").context("loading config"));
+        assert_eq!(format!("{:#}", wrapped), "loading config: bad");
+        assert_eq!(error_json(&wrapped)["causes"][0], "bad");
+    }
+
+    #[test]
+    fn library_errors() {
+        let insufficient = anyhow::Error::from(bdk::Error::InsufficientFunds {
+            needed: 2,
+            available: 1,
+        })
+        .context("building the transaction");
+        assert_eq!(classify(&insufficient), ErrorKind::InsufficientFunds);
+        let refused =
+            anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused));
+        assert_eq!(classify(&refused), ErrorKind::Network);
+        assert_eq!(classify(&anyhow::anyhow!("what")), ErrorKind::Other);
+    }
+}
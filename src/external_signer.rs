@@ -0,0 +1,374 @@
+//! Signing with an external command (e.g. a script that talks to an HSM).
+//!
+//! The command is given the PSBT in base64 on stdin and must write the signed PSBT in base64 to
+//! stdout within [`TIMEOUT`]. Before gun accepts the result it checks that the transaction wasn't
+//! changed and that every signature the command returned, including those in inputs it finalized,
+//! is valid and signs the whole transaction (`SIGHASH_ALL`).
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{
+        blockdata::script::Instruction,
+        hashes::{hash160, Hash},
+        secp256k1::{self, All, Message, Secp256k1},
+        util::{
+            bip143::SigHashCache,
+            psbt::{self, PartiallySignedTransaction as Psbt},
+        },
+        PublicKey, Script, SigHashType,
+    },
+    signer::{Signer, SignerError, SignerId},
+};
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long the command has to return the signed PSBT e.g. for someone to confirm on a device.
+pub const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone)]
+pub struct ExternalSigner {
+    /// The program followed by its arguments
+    pub command: Vec<String>,
+}
+
+impl ExternalSigner {
+    pub fn new(command: Vec<String>) -> anyhow::Result<Self> {
+        if command.is_empty() {
+            return Err(anyhow!("the external signer command is empty"));
+        }
+        Ok(Self { command })
+    }
+
+    /// Runs the command on `psbt` and returns the verified result.
+    pub fn run(&self, psbt: &Psbt, secp: &Secp256k1<All>) -> anyhow::Result<Psbt> {
+        let mut child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("starting external signer '{}'", self.command[0]))?;
+
+        // it's written while we read so a command that starts answering before it has read all of
+        // the PSBT can't leave us both waiting on each other
+        let mut stdin = child.stdin.take().expect("we asked for stdin to be piped");
+        let unsigned = psbt.to_string();
+        let writer = thread::spawn(move || stdin.write_all(unsigned.as_bytes()));
+        let mut stdout = child
+            .stdout
+            .take()
+            .expect("we asked for stdout to be piped");
+        let reader = thread::spawn(move || {
+            let mut output = vec![];
+            stdout.read_to_end(&mut output).map(|_| output)
+        });
+
+        let deadline = Instant::now() + TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait().context("waiting for external signer")? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(anyhow!(
+                    "external signer '{}' didn't finish within {} seconds",
+                    self.command[0],
+                    TIMEOUT.as_secs()
+                ));
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        if !status.success() {
+            return Err(anyhow!(
+                "external signer '{}' failed with {}",
+                self.command[0],
+                status
+            ));
+        }
+        writer
+            .join()
+            .expect("the writing thread doesn't panic")
+            .context("writing PSBT to external signer")?;
+        let stdout = reader
+            .join()
+            .expect("the reading thread doesn't panic")
+            .context("reading from external signer")?;
+
+        let stdout =
+            String::from_utf8(stdout).context("external signer didn't return a base64 PSBT")?;
+        let signed =
+            Psbt::from_str(stdout.trim()).context("external signer didn't return a valid PSBT")?;
+
+        verify_signed_psbt(psbt, &signed, secp)?;
+
+        Ok(signed)
+    }
+}
+
+impl Signer for ExternalSigner {
+    fn sign(
+        &self,
+        psbt: &mut Psbt,
+        _input_index: Option<usize>,
+        secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        match self.run(psbt, secp) {
+            Ok(signed) => {
+                *psbt = signed;
+                Ok(())
+            }
+            Err(e) => {
+                // SignerError has no variant that can carry our error so print it here
+                tracing::error!("external signer: {:#}", e);
+                Err(SignerError::UserCanceled)
+            }
+        }
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
+        SignerId::PkHash(hash160::Hash::hash(self.command.join(" ").as_bytes()))
+    }
+}
+
+/// Checks that `signed` is `original` with only valid signatures added.
+pub fn verify_signed_psbt(
+    original: &Psbt,
+    signed: &Psbt,
+    secp: &Secp256k1<All>,
+) -> anyhow::Result<()> {
+    if signed.global.unsigned_tx != original.global.unsigned_tx {
+        return Err(anyhow!(
+            "external signer changed the transaction (was {} but returned {})",
+            original.global.unsigned_tx.txid(),
+            signed.global.unsigned_tx.txid()
+        ));
+    }
+
+    if signed.inputs.len() != original.inputs.len() {
+        return Err(anyhow!("external signer changed the number of PSBT inputs"));
+    }
+
+    let mut to_verify = vec![];
+    for (i, (input, original_input)) in signed.inputs.iter().zip(&original.inputs).enumerate() {
+        if input.witness_utxo != original_input.witness_utxo
+            || input.non_witness_utxo != original_input.non_witness_utxo
+        {
+            return Err(anyhow!(
+                "external signer changed the coin being spent by input {}",
+                i
+            ));
+        }
+
+        to_verify.extend(
+            input
+                .partial_sigs
+                .iter()
+                .map(|(public_key, signature)| ToVerify {
+                    index: i,
+                    public_keys: vec![*public_key],
+                    script_code: match &input.witness_script {
+                        Some(witness_script) => witness_script.clone(),
+                        None => Script::new_p2pkh(&public_key.pubkey_hash()),
+                    },
+                    signature: signature.clone(),
+                }),
+        );
+
+        // what we'd broadcast for the inputs it finalized is checked too
+        if input.final_script_witness != original_input.final_script_witness
+            || input.final_script_sig != original_input.final_script_sig
+        {
+            to_verify.extend(final_signatures(i, input)?);
+        }
+    }
+
+    verify_signatures(signed, &to_verify, secp).map_err(|(i, e)| {
+        e.context(format!(
+            "bad signature for input {} from external signer",
+            i
+        ))
+    })
+}
+
+/// Checks the signatures of the inputs of `psbt` that already have a final witness.
+pub fn verify_final_witnesses(psbt: &Psbt, secp: &Secp256k1<All>) -> anyhow::Result<()> {
+    let mut to_verify = vec![];
+    for (i, input) in psbt.inputs.iter().enumerate() {
+        if input.final_script_witness.is_some() {
+            to_verify.extend(final_signatures(i, input)?);
+        }
+    }
+    verify_signatures(psbt, &to_verify, secp)
+        .map_err(|(i, e)| e.context(format!("bad signature for input {}", i)))
+}
+
+/// A signature to check: the input it's for, the keys that could have made it and the script code
+/// it signs.
+struct ToVerify {
+    index: usize,
+    public_keys: Vec<PublicKey>,
+    script_code: Script,
+    signature: Vec<u8>,
+}
+
+/// The signatures in the final script sig and witness of input `index`. Only p2wpkh and p2wsh
+/// inputs (or either nested in p2sh) can be checked so anything else is an error. In a p2wsh
+/// witness every item but the empty ones and the script has to be a signature by one of the keys
+/// in the script.
+fn final_signatures(index: usize, input: &psbt::Input) -> anyhow::Result<Vec<ToVerify>> {
+    let cant_check = |why: &str| anyhow!("input {} can't be checked because {}", index, why);
+    let script_pubkey = &input
+        .witness_utxo
+        .as_ref()
+        .ok_or_else(|| cant_check("it isn't segwit"))?
+        .script_pubkey;
+    let script_sig = input.final_script_sig.clone().unwrap_or_default();
+    let program = if script_pubkey.is_p2sh() {
+        let redeem_script = match script_sig.instructions().collect::<Result<Vec<_>, _>>() {
+            Ok(instructions) => match instructions.as_slice() {
+                [Instruction::PushBytes(redeem_script)] => Script::from(redeem_script.to_vec()),
+                _ => return Err(cant_check("its script sig isn't just the redeem script")),
+            },
+            Err(_) => return Err(cant_check("its script sig is invalid")),
+        };
+        if redeem_script.to_p2sh() != *script_pubkey {
+            return Err(cant_check("its redeem script isn't the one being spent"));
+        }
+        redeem_script
+    } else {
+        if !script_sig.is_empty() {
+            return Err(cant_check("it has a script sig for a native segwit output"));
+        }
+        script_pubkey.clone()
+    };
+    let witness = input
+        .final_script_witness
+        .as_ref()
+        .ok_or_else(|| cant_check("it was finalized without a witness"))?;
+
+    if program.is_v0_p2wpkh() {
+        let (signature, public_key) = match witness.as_slice() {
+            [signature, public_key] => (signature, public_key),
+            _ => return Err(cant_check("its p2wpkh witness isn't a signature and a key")),
+        };
+        let public_key = PublicKey::from_slice(public_key)
+            .with_context(|| format!("input {} has an invalid public key", index))?;
+        if public_key
+            .wpubkey_hash()
+            .map(|hash| Script::new_v0_wpkh(&hash))
+            != Some(program)
+        {
+            return Err(cant_check("its key isn't the one being spent"));
+        }
+        Ok(vec![ToVerify {
+            index,
+            public_keys: vec![public_key],
+            script_code: Script::new_p2pkh(&public_key.pubkey_hash()),
+            signature: signature.clone(),
+        }])
+    } else if program.is_v0_p2wsh() {
+        let (witness_script, items) = witness
+            .split_last()
+            .ok_or_else(|| cant_check("its witness is empty"))?;
+        let witness_script = Script::from(witness_script.clone());
+        if witness_script.to_v0_p2wsh() != program {
+            return Err(cant_check("its witness script isn't the one being spent"));
+        }
+        let public_keys = witness_script
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => PublicKey::from_slice(bytes).ok(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        Ok(items
+            .iter()
+            .filter(|item| !item.is_empty())
+            .map(|signature| ToVerify {
+                index,
+                public_keys: public_keys.clone(),
+                script_code: witness_script.clone(),
+                signature: signature.clone(),
+            })
+            .collect())
+    } else {
+        Err(cant_check("it isn't p2wpkh or p2wsh"))
+    }
+}
+
+/// Checks each of `signatures` against `psbt`. There can be lots (e.g. a claim of many bets) so
+/// they're checked on several threads. The error is for the first bad one.
+fn verify_signatures(
+    psbt: &Psbt,
+    signatures: &[ToVerify],
+    secp: &Secp256k1<All>,
+) -> Result<(), (usize, anyhow::Error)> {
+    let tx = &psbt.global.unsigned_tx;
+    crate::parallel::map_with(
+        signatures,
+        || SigHashCache::new(tx),
+        |sighash_cache, _, to_verify| {
+            verify_input_signature(
+                sighash_cache,
+                &psbt.inputs[to_verify.index],
+                to_verify,
+                secp,
+            )
+            .map_err(|e| (to_verify.index, e))
+        },
+    )
+    .into_iter()
+    .collect()
+}
+
+fn verify_input_signature(
+    sighash_cache: &mut SigHashCache<&bdk::bitcoin::Transaction>,
+    input: &psbt::Input,
+    to_verify: &ToVerify,
+    secp: &Secp256k1<All>,
+) -> anyhow::Result<()> {
+    let (sighash_type, der) = to_verify
+        .signature
+        .split_last()
+        .ok_or(anyhow!("signature is empty"))?;
+    // anything else would let whoever has the transaction change what it pays
+    if !matches!(
+        SigHashType::from_u32_standard(*sighash_type as u32),
+        Ok(SigHashType::All)
+    ) {
+        return Err(anyhow!(
+            "signature has sighash type {} rather than SIGHASH_ALL",
+            sighash_type
+        ));
+    }
+    let value = input
+        .witness_utxo
+        .as_ref()
+        .ok_or(anyhow!("only segwit inputs can be checked"))?
+        .value;
+    let sighash = sighash_cache.signature_hash(
+        to_verify.index,
+        &to_verify.script_code,
+        value,
+        SigHashType::All,
+    );
+    let message = Message::from_slice(&sighash[..]).expect("sighash is 32 bytes");
+    let signature = secp256k1::Signature::from_der(der)?;
+    match to_verify
+        .public_keys
+        .iter()
+        .any(|public_key| secp.verify(&message, &signature, &public_key.key).is_ok())
+    {
+        true => Ok(()),
+        false => Err(anyhow!("signature isn't valid")),
+    }
+}
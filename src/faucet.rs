@@ -0,0 +1,184 @@
+//! Getting testnet coins from faucets for `gun dev faucet`.
+//!
+//! Faucets are listed under `faucets` in the config e.g.
+//!
+//! ```json
+//! "faucets": [
+//!     { "name": "my-faucet", "url": "https://faucet.example/api/send?address={address}&amount={amount}" },
+//!     { "name": "other", "url": "https://other.example/request", "post": true }
+//! ]
+//! ```
+//!
+//! `{address}` and `{amount}` (in sats) are replaced in the url. With `post` the address and
+//! amount are sent as a JSON body instead. Faucets are asked in order until one pays. Each one is
+//! asked at most once every `min-interval-hours` (24 by default) which is remembered in
+//! `faucet-requests.json` in the wallet directory.
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{Address, Amount, Txid};
+use olivia_core::chrono::{Duration, NaiveDateTime};
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Faucet {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub post: bool,
+    #[serde(default = "default_min_interval_hours")]
+    pub min_interval_hours: u32,
+}
+
+fn default_min_interval_hours() -> u32 {
+    24
+}
+
+/// What happened when a faucet was asked for coins.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FaucetResponse {
+    /// It paid and said which transaction (if it did)
+    Paid(Option<Txid>),
+    /// It's out of coins or is refusing requests for now
+    Dry(String),
+}
+
+impl Faucet {
+    pub fn request_url(&self, address: &Address, amount: Amount) -> String {
+        self.url
+            .replace("{address}", &address.to_string())
+            .replace("{amount}", &amount.as_sat().to_string())
+    }
+
+    pub fn request(
+        &self,
+        client: &reqwest::blocking::Client,
+        address: &Address,
+        amount: Amount,
+    ) -> anyhow::Result<FaucetResponse> {
+        let url = self.request_url(address, amount);
+        let request = match self.post {
+            true => client.post(&url).json(&serde_json::json!({
+                "address": address.to_string(),
+                "amount": amount.as_sat(),
+            })),
+            false => client.get(&url),
+        };
+        let response = request
+            .send()
+            .with_context(|| format!("asking {} for coins", self.name))?;
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+        {
+            return Ok(FaucetResponse::Dry(format!("{} ({})", status, body.trim())));
+        }
+        if !status.is_success() {
+            return Err(anyhow!(
+                "{} answered {}: {}",
+                self.name,
+                status,
+                body.trim()
+            ));
+        }
+        Ok(FaucetResponse::Paid(find_txid(&body)))
+    }
+}
+
+/// Finds the txid in what a faucet answered. Some just return it and others put it in JSON.
+fn find_txid(body: &str) -> Option<Txid> {
+    if let Ok(txid) = Txid::from_str(body.trim().trim_matches('"')) {
+        return Some(txid);
+    }
+    let json = serde_json::from_str::<serde_json::Value>(body).ok()?;
+    ["txid", "tx_id", "txId", "hash"]
+        .iter()
+        .find_map(|key| json.get(key)?.as_str())
+        .and_then(|txid| Txid::from_str(txid).ok())
+}
+
+/// When each faucet was last asked for coins.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FaucetRequests(BTreeMap<String, NaiveDateTime>);
+
+impl FaucetRequests {
+    fn path(wallet_dir: &Path) -> std::path::PathBuf {
+        wallet_dir.join("faucet-requests.json")
+    }
+
+    pub fn load(wallet_dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(wallet_dir);
+        match crate::ephemeral::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("reading {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, wallet_dir: &Path) -> anyhow::Result<()> {
+        let path = Self::path(wallet_dir);
+        crate::ephemeral::write(&path, serde_json::to_string_pretty(self).unwrap())
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// When `faucet` can be asked again if it's too soon now.
+    pub fn too_soon(&self, faucet: &Faucet, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let last = self.0.get(&faucet.name)?;
+        let next = *last + Duration::hours(faucet.min_interval_hours.into());
+        if next > now {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    pub fn record(&mut self, faucet: &Faucet, now: NaiveDateTime) {
+        self.0.insert(faucet.name.clone(), now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn faucet() -> Faucet {
+        Faucet {
+            name: "test".into(),
+            url: "https://faucet.test/send?to={address}&sats={amount}".into(),
+            post: false,
+            min_interval_hours: 24,
+        }
+    }
+
+    #[test]
+    fn request_url_and_txid() {
+        let address = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        assert_eq!(
+            faucet().request_url(&address, Amount::from_sat(10_000)),
+            "https://faucet.test/send?to=tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx&sats=10000"
+        );
+
+        let txid = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+        assert_eq!(find_txid(txid), Txid::from_str(txid).ok());
+        assert_eq!(
+            find_txid(&format!("{{\"txid\": \"{}\", \"amount\": 1000}}", txid)),
+            Txid::from_str(txid).ok()
+        );
+        assert_eq!(find_txid("thanks!"), None);
+    }
+
+    #[test]
+    fn one_request_per_interval() {
+        let faucet = faucet();
+        let mut requests = FaucetRequests::default();
+        let now = NaiveDateTime::from_timestamp(1_600_000_000, 0);
+        assert_eq!(requests.too_soon(&faucet, now), None);
+        requests.record(&faucet, now);
+        assert_eq!(
+            requests.too_soon(&faucet, now + Duration::hours(1)),
+            Some(now + Duration::hours(24))
+        );
+        assert_eq!(requests.too_soon(&faucet, now + Duration::hours(24)), None);
+    }
+}
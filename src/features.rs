@@ -0,0 +1,73 @@
+//! The entry points of gun that can be switched off with cargo features e.g.
+//! `cargo build --no-default-features` for a gun whose commands are only the wallet's. It's the
+//! commands that go, not the code behind them (see below).
+//!
+//! | feature   | what it adds                                                          |
+//! |-----------|-----------------------------------------------------------------------|
+//! | `betting` | the `gun bet` commands and `gun selftest`                             |
+//! | `daemon`  | `gun schedule run --daemon`                                           |
+//! | `price`   | getting the price of bitcoin from the `price-source` in the config    |
+//! | `nostr`   | sending and getting events through nostr relays (chat and the boards) |
+//!
+//! They're all on by default. Commands that need a feature that was left out fail with an error
+//! saying which one rather than not existing.
+//!
+//! This doesn't make the binary much smaller. The wallet is built on the betting code (the party
+//! and its database), and the config and the bet database keep price, schedule and board types,
+//! so all of it is compiled in either way. Only `nostr` leaves out a dependency (`tungstenite`).
+//! Leaving the modules themselves out needs those types moved out of them first.
+use crate::exit_code::ErrorKind;
+
+/// Every optional feature and whether this binary was built with it.
+pub const FEATURES: [(&str, bool); 4] = [
+    ("betting", cfg!(feature = "betting")),
+    ("daemon", cfg!(feature = "daemon")),
+    ("price", cfg!(feature = "price")),
+    ("nostr", cfg!(feature = "nostr")),
+];
+
+/// The commands each feature adds.
+const COMMANDS: [(&str, &str); 2] = [("bet", "betting"), ("selftest", "betting")];
+
+/// The features this binary was built with.
+pub fn compiled_in() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| *feature)
+        .collect()
+}
+
+/// The error for trying to `action` without `feature`.
+pub fn not_compiled_in(feature: &str, action: &str) -> anyhow::Error {
+    ErrorKind::BadInput.error(format!(
+        "this gun was built without the {} feature so it can't {}. Build it with `--features {}` to do that.",
+        feature, action, feature
+    ))
+}
+
+/// The error for running `command` if it's one of ours that this binary was built without.
+pub fn missing_command(command: &str) -> Option<anyhow::Error> {
+    let (_, feature) = COMMANDS.iter().find(|(name, _)| *name == command)?;
+    match FEATURES
+        .iter()
+        .any(|(name, enabled)| name == feature && *enabled)
+    {
+        true => None,
+        false => Some(not_compiled_in(feature, &format!("run `gun {}`", command))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands_belong_to_features() {
+        for (_, feature) in COMMANDS.iter() {
+            assert!(FEATURES.iter().any(|(name, _)| name == feature));
+        }
+        assert!(missing_command("balance").is_none());
+        assert_eq!(missing_command("bet").is_some(), !cfg!(feature = "betting"));
+    }
+}
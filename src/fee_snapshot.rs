@@ -0,0 +1,186 @@
+//! The last fee estimates and mempool histogram we got from the backend so fees can still be worked
+//! out when it can't be reached.
+//!
+//! Every estimate a [`crate::FeeSpec`] gets goes through [`estimate_fee`] which records it in
+//! `fee-snapshot.json` in the wallet directory. When the backend can't give one the recorded
+//! estimate is used instead with a warning saying how old it is. `gun fees --histogram` does the
+//! same with the histogram.
+use crate::chrono::{NaiveDateTime, Utc};
+use bdk::{blockchain::Blockchain, FeeRate};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+pub const FEE_SNAPSHOT_FILE: &str = "fee-snapshot.json";
+
+static SNAPSHOT_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Keeps the snapshot in `wallet_dir` from now on. Until this is called nothing is recorded.
+pub fn keep_in(wallet_dir: &Path) {
+    *SNAPSHOT_FILE.lock().unwrap() = Some(wallet_dir.join(FEE_SNAPSHOT_FILE));
+}
+
+fn snapshot_file() -> Option<PathBuf> {
+    SNAPSHOT_FILE.lock().unwrap().clone()
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Seen<T> {
+    pub value: T,
+    pub seen_at: NaiveDateTime,
+}
+
+impl<T> Seen<T> {
+    fn now(value: T) -> Self {
+        Seen {
+            value,
+            seen_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// How long ago it was seen e.g. `3 hours`
+    pub fn age(&self) -> String {
+        describe_age((Utc::now().naive_utc() - self.seen_at).num_seconds())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FeeSnapshot {
+    /// sat/vb for each confirmation target (in blocks)
+    #[serde(default)]
+    pub estimates: BTreeMap<u32, Seen<f32>>,
+    /// The mempool's (sat/vb, vbytes) bands from the highest feerate down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Seen<Vec<(f64, u64)>>>,
+}
+
+impl FeeSnapshot {
+    /// The recorded snapshot. One that can't be read is treated as empty since it's only a cache.
+    pub fn load() -> Self {
+        let path = match snapshot_file() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+        match crate::ephemeral::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                tracing::warn!("ignoring {} since it isn't valid: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) {
+        if let Some(path) = snapshot_file() {
+            let json = serde_json::to_string_pretty(self).unwrap();
+            if let Err(e) = crate::ephemeral::write(&path, json) {
+                tracing::warn!(
+                    "couldn't record the fee estimates in {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// The recorded estimate to use for `target` blocks and the target it was for. That's the
+    /// nearest target that isn't slower so it pays at least enough or if there isn't one the
+    /// nearest slower one.
+    pub fn estimate_for(&self, target: u32) -> Option<(u32, &Seen<f32>)> {
+        self.estimates
+            .range(..=target)
+            .next_back()
+            .or_else(|| self.estimates.range(target..).next())
+            .map(|(target, estimate)| (*target, estimate))
+    }
+}
+
+/// The feerate to get confirmed in `target` blocks from `blockchain` or if it can't give one the
+/// last one it gave.
+pub fn estimate_fee<B: Blockchain>(blockchain: &B, target: u32) -> anyhow::Result<FeeRate> {
+    match blockchain.estimate_fee(target as usize) {
+        Ok(feerate) => {
+            if snapshot_file().is_some() {
+                let mut snapshot = FeeSnapshot::load();
+                snapshot
+                    .estimates
+                    .insert(target, Seen::now(feerate.as_sat_vb()));
+                snapshot.save();
+            }
+            Ok(feerate)
+        }
+        Err(e) => {
+            let snapshot = FeeSnapshot::load();
+            let (cached_target, estimate) = match snapshot.estimate_for(target) {
+                Some(cached) => cached,
+                None => return Err(e.into()),
+            };
+            eprintln!(
+                "WARNING: couldn't get a fee estimate from the backend ({}) so the one from {} ago is used: {:.1} sat/vb for {} blocks. It may be out of date.",
+                e,
+                estimate.age(),
+                estimate.value,
+                cached_target
+            );
+            Ok(FeeRate::from_sat_per_vb(estimate.value))
+        }
+    }
+}
+
+pub fn record_histogram(bands: Vec<(f64, u64)>) {
+    let mut snapshot = FeeSnapshot::load();
+    snapshot.histogram = Some(Seen::now(bands));
+    snapshot.save();
+}
+
+fn describe_age(seconds: i64) -> String {
+    let plural = |n: i64, unit: &str| match n {
+        1 => format!("1 {}", unit),
+        n => format!("{} {}s", n, unit),
+    };
+    match seconds.max(0) {
+        seconds if seconds < 60 => "less than a minute".to_string(),
+        seconds if seconds < 60 * 60 => plural(seconds / 60, "minute"),
+        seconds if seconds < 2 * 24 * 60 * 60 => plural(seconds / (60 * 60), "hour"),
+        seconds => plural(seconds / (24 * 60 * 60), "day"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_a_target_that_is_no_slower() {
+        let seen_at = Utc::now().naive_utc();
+        let mut snapshot = FeeSnapshot::default();
+        assert_eq!(snapshot.estimate_for(6), None);
+        for (target, sat_vb) in [(1, 20.0), (6, 10.0), (144, 2.0)] {
+            snapshot.estimates.insert(
+                target,
+                Seen {
+                    value: sat_vb,
+                    seen_at,
+                },
+            );
+        }
+        let target_for = |blocks| snapshot.estimate_for(blocks).unwrap().0;
+        assert_eq!(target_for(6), 6);
+        assert_eq!(target_for(12), 6);
+        assert_eq!(target_for(1000), 144);
+        assert_eq!(snapshot.estimates.remove(&1).unwrap().value, 20.0);
+        assert_eq!(snapshot.estimate_for(0).unwrap().0, 6);
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!(describe_age(30), "less than a minute");
+        assert_eq!(describe_age(61), "1 minute");
+        assert_eq!(describe_age(3 * 60 * 60), "3 hours");
+        assert_eq!(describe_age(3 * 24 * 60 * 60), "3 days");
+    }
+}
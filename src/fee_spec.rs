@@ -15,6 +15,91 @@ pub enum FeeSpec {
     Absolute(Amount),
     Rate(FeeRate),
     Height(u32),
+    /// A friendly name for a number of blocks (see [`FeeAliases`])
+    Alias(FeeAlias, u32),
+    /// An increase over the feerate of the transaction being replaced
+    Bump(FeeBump),
+}
+
+/// The lowest feerate increase (in sats per vbyte) nodes relay a replacement for (BIP125 rule 4)
+pub const INCREMENTAL_RELAY_FEERATE_SAT_VB: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeBump {
+    /// e.g. `bump:+25%`
+    Percent(f32),
+    /// e.g. `bump:+5sat/vb`
+    Rate(FeeRate),
+}
+
+impl FeeBump {
+    /// The feerate for a replacement of a transaction paying `original`.
+    pub fn apply(&self, original: FeeRate) -> anyhow::Result<FeeRate> {
+        let original = original.as_sat_vb();
+        let increase = match self {
+            FeeBump::Percent(percent) => original * percent / 100.0,
+            FeeBump::Rate(rate) => rate.as_sat_vb(),
+        };
+        if increase < INCREMENTAL_RELAY_FEERATE_SAT_VB {
+            return Err(anyhow!(
+                "bumping {:.2} sat/vb by {} only adds {:.2} sat/vb but replacements have to add at least {} sat/vb",
+                original,
+                FeeSpec::Bump(*self),
+                increase,
+                INCREMENTAL_RELAY_FEERATE_SAT_VB
+            ));
+        }
+        Ok(FeeRate::from_sat_per_vb(original + increase))
+    }
+}
+
+/// Names for confirmation targets that can be used instead of `in-blocks:<n>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeAlias {
+    Fastest,
+    Hour,
+    Economy,
+}
+
+impl FeeAlias {
+    pub const ALL: [FeeAlias; 3] = [FeeAlias::Fastest, FeeAlias::Hour, FeeAlias::Economy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeeAlias::Fastest => "fastest",
+            FeeAlias::Hour => "hour",
+            FeeAlias::Economy => "economy",
+        }
+    }
+}
+
+/// How many blocks each [`FeeAlias`] means. Set with `fee-aliases` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct FeeAliases {
+    pub fastest: u32,
+    pub hour: u32,
+    pub economy: u32,
+}
+
+impl Default for FeeAliases {
+    fn default() -> Self {
+        Self {
+            fastest: 1,
+            hour: 6,
+            economy: 144,
+        }
+    }
+}
+
+impl FeeAliases {
+    pub fn blocks(&self, alias: FeeAlias) -> u32 {
+        match alias {
+            FeeAlias::Fastest => self.fastest,
+            FeeAlias::Hour => self.hour,
+            FeeAlias::Economy => self.economy,
+        }
+    }
 }
 
 impl Default for FeeSpec {
@@ -24,6 +109,43 @@ impl Default for FeeSpec {
 }
 
 impl FeeSpec {
+    /// The feerate this spec asks for. Absolute fees don't have one.
+    pub fn feerate<B: Blockchain>(&self, blockchain: &B) -> anyhow::Result<Option<FeeRate>> {
+        use FeeSpec::*;
+        Ok(match self {
+            Absolute(_) => None,
+            Rate(rate) => Some(*rate),
+            Height(height) | Alias(_, height) => {
+                Some(crate::fee_snapshot::estimate_fee(blockchain, *height)?)
+            }
+            Bump(_) => return Err(self.bump_without_original()),
+        })
+    }
+
+    /// Resolves a [`FeeSpec::Bump`] against the feerate of the transaction being replaced. Other
+    /// specs are returned as they are.
+    pub fn relative_to(self, original: FeeRate) -> anyhow::Result<Self> {
+        Ok(match self {
+            FeeSpec::Bump(bump) => FeeSpec::Rate(bump.apply(original)?),
+            fee_spec => fee_spec,
+        })
+    }
+
+    fn bump_without_original(&self) -> anyhow::Error {
+        anyhow!(
+            "{} is relative to the transaction being replaced so it can only be used when bumping or canceling one",
+            self
+        )
+    }
+
+    /// Makes an alias mean what `aliases` says rather than the default number of blocks.
+    pub fn with_aliases(self, aliases: &FeeAliases) -> Self {
+        match self {
+            FeeSpec::Alias(alias, _) => FeeSpec::Alias(alias, aliases.blocks(alias)),
+            fee_spec => fee_spec,
+        }
+    }
+
     pub fn apply_to_builder<
         B: Blockchain,
         D: BatchDatabase,
@@ -42,10 +164,11 @@ impl FeeSpec {
             Rate(rate) => {
                 builder.fee_rate(*rate);
             }
-            Height(height) => {
-                let feerate = blockchain.estimate_fee(*height as usize)?;
+            Height(height) | Alias(_, height) => {
+                let feerate = crate::fee_snapshot::estimate_fee(blockchain, *height)?;
                 builder.fee_rate(feerate);
             }
+            Bump(_) => return Err(self.bump_without_original()),
         }
         Ok(())
     }
@@ -74,7 +197,30 @@ impl FromStr for FeeSpec {
             return Ok(FeeSpec::Height(in_blocks));
         }
 
-        return Err(anyhow!("{} is not a valid fee specification"));
+        if let Some(bump) = string.strip_prefix("bump:") {
+            let bump = bump.strip_prefix('+').unwrap_or(bump);
+            if let Some(percent) = bump.strip_suffix('%') {
+                return Ok(FeeSpec::Bump(FeeBump::Percent(f32::from_str(percent)?)));
+            }
+            let rate = bump
+                .strip_suffix("sat/vb")
+                .or_else(|| bump.strip_suffix("sat/vB"))
+                .ok_or(anyhow!(
+                    "fee bump '{}' should be a percentage (bump:+25%) or a feerate (bump:+5sat/vb)",
+                    string
+                ))?;
+            return Ok(FeeSpec::Bump(FeeBump::Rate(FeeRate::from_sat_per_vb(
+                f32::from_str(rate)?,
+            ))));
+        }
+
+        for alias in FeeAlias::ALL.iter() {
+            if string == alias.name() {
+                return Ok(FeeSpec::Alias(*alias, FeeAliases::default().blocks(*alias)));
+            }
+        }
+
+        return Err(anyhow!("{} is not a valid fee specification", string));
     }
 }
 
@@ -84,6 +230,9 @@ impl core::fmt::Display for FeeSpec {
             FeeSpec::Rate(rate) => write!(f, "rate:{}", rate.as_sat_vb()),
             FeeSpec::Absolute(abs) => write!(f, "abs:{}", abs),
             FeeSpec::Height(height) => write!(f, "in-blocks:{}", height),
+            FeeSpec::Alias(alias, _) => write!(f, "{}", alias.name()),
+            FeeSpec::Bump(FeeBump::Percent(percent)) => write!(f, "bump:+{}%", percent),
+            FeeSpec::Bump(FeeBump::Rate(rate)) => write!(f, "bump:+{}sat/vb", rate.as_sat_vb()),
         }
     }
 }
@@ -110,5 +259,66 @@ mod test {
             FeeSpec::from_str("in-blocks:5").unwrap(),
             FeeSpec::Height(5)
         );
+        assert_eq!(
+            FeeSpec::from_str("economy").unwrap(),
+            FeeSpec::Alias(FeeAlias::Economy, 144)
+        );
+        assert!(FeeSpec::from_str("slowest").is_err());
+    }
+
+    #[test]
+    fn parse_fee_bumps() {
+        assert_eq!(
+            FeeSpec::from_str("bump:+25%").unwrap(),
+            FeeSpec::Bump(FeeBump::Percent(25.0))
+        );
+        assert_eq!(
+            FeeSpec::from_str("bump:+5sat/vb").unwrap(),
+            FeeSpec::Bump(FeeBump::Rate(FeeRate::from_sat_per_vb(5.0)))
+        );
+        assert!(FeeSpec::from_str("bump:+5").is_err());
+        for string in ["bump:+25%", "bump:+2.5sat/vb"] {
+            assert_eq!(FeeSpec::from_str(string).unwrap().to_string(), string);
+        }
+    }
+
+    #[test]
+    fn fee_bumps_need_the_incremental_relay_fee() {
+        let original = FeeRate::from_sat_per_vb(10.0);
+        assert_eq!(
+            FeeSpec::from_str("bump:+25%")
+                .unwrap()
+                .relative_to(original)
+                .unwrap(),
+            FeeSpec::Rate(FeeRate::from_sat_per_vb(12.5))
+        );
+        assert!(FeeSpec::from_str("bump:+5%")
+            .unwrap()
+            .relative_to(original)
+            .is_err());
+        assert!(FeeSpec::from_str("bump:+0.5sat/vb")
+            .unwrap()
+            .relative_to(original)
+            .is_err());
+        assert_eq!(
+            FeeSpec::Height(3).relative_to(original).unwrap(),
+            FeeSpec::Height(3)
+        );
+    }
+
+    #[test]
+    fn fee_aliases_round_trip() {
+        let aliases = FeeAliases {
+            hour: 4,
+            ..Default::default()
+        };
+        for string in ["fastest", "hour", "economy", "in-blocks:6"] {
+            let fee_spec = FeeSpec::from_str(string).unwrap().with_aliases(&aliases);
+            assert_eq!(fee_spec.to_string(), string);
+        }
+        assert_eq!(
+            FeeSpec::from_str("hour").unwrap().with_aliases(&aliases),
+            FeeSpec::Alias(FeeAlias::Hour, 4)
+        );
     }
 }
@@ -0,0 +1,321 @@
+//! Showing what gun says in the user's language.
+//!
+//! The locale comes from `locale` in the config or else `LC_ALL`, `LC_MESSAGES` or `LANG` and is
+//! English if gun doesn't have the language. Strings are looked up by their English text (like
+//! gettext) so anything without a translation is just shown in English. Placeholders are written
+//! `{name}` and filled in by [`tr_args`].
+//!
+//! Only what's shown to people is translated: prompts and the labels of tables and items. `--json`
+//! and `--tabs` output stays in English so scripts don't break when the locale changes.
+//!
+//! Answers to prompts are checked against [`Answers`] for the locale. Each locale's "yes" and "no"
+//! answers never overlap and English `yes` and `no` always work. A locale leaves out answers that
+//! could be typed by mistake on another keyboard layout -- on a Russian layout the `y` key types
+//! `н` (the first letter of "нет") so Russian doesn't take `y`, `n` or single letters at all.
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Ru,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::En
+    }
+}
+
+impl Locale {
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::Ru];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Ru => "ru",
+        }
+    }
+
+    /// The locale named by a tag like `ru`, `ru-RU` or `ru_RU.UTF-8`. `C` and `POSIX` are English.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag
+            .split(|c| c == '_' || c == '-' || c == '.' || c == '@')
+            .next()?
+            .to_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::En),
+            "ru" => Some(Locale::Ru),
+            _ => None,
+        }
+    }
+
+    /// The locale from the environment the way other programs pick it.
+    pub fn from_env() -> Option<Locale> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::from_tag(&value))
+    }
+
+    fn catalog(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::En => &[],
+            Locale::Ru => RU,
+        }
+    }
+
+    pub fn answers(&self) -> Answers {
+        match self {
+            Locale::En => Answers {
+                hint: "y/n",
+                yes: &["y", "yes"],
+                no: &["n", "no"],
+            },
+            Locale::Ru => Answers {
+                hint: "да/нет",
+                yes: &["да", "yes"],
+                no: &["нет", "no"],
+            },
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for Locale {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        Locale::from_tag(string).ok_or(anyhow::anyhow!(
+            "gun doesn't speak {} (it has {})",
+            string,
+            Locale::ALL
+                .iter()
+                .map(Locale::code)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+}
+
+/// What a yes or no question accepts in a locale.
+#[derive(Clone, Copy, Debug)]
+pub struct Answers {
+    /// Shown after the question
+    pub hint: &'static str,
+    pub yes: &'static [&'static str],
+    pub no: &'static [&'static str],
+}
+
+impl Answers {
+    /// `None` if it isn't one of the answers (so the question should be asked again).
+    pub fn parse(&self, answer: &str) -> Option<bool> {
+        let answer = answer.trim().to_lowercase();
+        if self.yes.contains(&answer.as_str()) {
+            Some(true)
+        } else if self.no.contains(&answer.as_str()) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+static LOCALE: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the language from now on.
+pub fn set_locale(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed)
+}
+
+pub fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        1 => Locale::Ru,
+        _ => Locale::En,
+    }
+}
+
+/// `english` in the current language.
+pub fn tr(english: &str) -> &str {
+    translate(locale(), english)
+}
+
+fn translate(locale: Locale, english: &str) -> &str {
+    locale
+        .catalog()
+        .iter()
+        .find(|(from, _)| *from == english)
+        .map(|(_, to)| *to)
+        .unwrap_or(english)
+}
+
+/// `english` in the current language with each `{name}` replaced by its value in `args`.
+pub fn tr_args(english: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    fill(tr(english), args)
+}
+
+fn fill(template: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    args.iter()
+        .fold(template.to_string(), |string, (name, value)| {
+            string.replace(&format!("{{{}}}", name), &value.to_string())
+        })
+}
+
+const RU: &[(&str, &str)] = &[
+    // prompts
+    ("Broadcast this transaction", "Отправить эту транзакцию в сеть"),
+    (
+        "This send looks risky (see the !! warnings). Type \"{word}\" to broadcast it anyway:",
+        "Эта отправка выглядит рискованной (см. предупреждения !!). Чтобы всё равно отправить её в сеть, введите \"{word}\":",
+    ),
+    (
+        "This is the transaction that will be broadcast. Ok?\n{tx}",
+        "Эта транзакция будет отправлена в сеть. Продолжить?\n{tx}",
+    ),
+    ("Trust the oracle displayed above", "Доверять оракулу, показанному выше"),
+    ("You are proposing a bet on the {event}.", "Вы предлагаете пари на событие: {event}."),
+    (
+        "\nThe outcome is expected to be known at {time} UTC (in {left}).",
+        "\nИсход ожидается в {time} UTC (через {left}).",
+    ),
+    (" Ok?", " Продолжить?"),
+    (
+        "You are making {count} proposals from {file}. Ok?",
+        "Из {file} будет создано предложений: {count}. Продолжить?",
+    ),
+    ("You are betting that {outcome}", "Вы ставите на то, что {outcome}"),
+    ("Do you want to take this bet?", "Принять это пари?"),
+    (
+        "You should only forget a proposal if you are you confident no one will make an offer to it.\nIf you're not sure it's better to cancel it properly using `gun bet cancel`.\nAre you sure you want to forget your proposed bet {id}",
+        "Забывайте предложение, только если уверены, что на него никто не сделает встречное предложение.\nЕсли не уверены, лучше отменить его как следует с помощью `gun bet cancel`.\nВы уверены, что хотите забыть своё предложение пари {id}",
+    ),
+    (
+        "Forgetting an offer can lead to loss of funds if it has been seen by the proposer. Are you sure you want to forget bet {id}",
+        "Если предложивший пари уже видел ваше встречное предложение, забыв его, можно потерять средства. Вы уверены, что хотите забыть пари {id}",
+    ),
+    ("repeat passphrase", "повторите парольную фразу"),
+    ("backup passphrase", "парольная фраза резервной копии"),
+    ("spending passphrase", "парольная фраза для трат"),
+    ("current spending passphrase", "текущая парольная фраза для трат"),
+    ("new spending passphrase", "новая парольная фраза для трат"),
+    ("decoy passphrase", "парольная фраза приманки"),
+    ("approver passphrase", "парольная фраза утверждающего"),
+    // labels
+    ("address", "адрес"),
+    ("amount", "сумма"),
+    ("balance", "баланс"),
+    ("bet", "пари"),
+    ("bets", "пари"),
+    ("change", "сдача"),
+    ("confirmations", "подтверждения"),
+    ("error", "ошибка"),
+    ("event", "событие"),
+    ("event-id", "id-события"),
+    ("fee", "комиссия"),
+    ("feerate", "ставка-комиссии"),
+    ("fix", "как-исправить"),
+    ("height", "высота"),
+    ("label", "метка"),
+    ("message", "сообщение"),
+    ("network", "сеть"),
+    ("oracle", "оракул"),
+    ("outcome", "исход"),
+    ("outcome-time", "время-исхода"),
+    ("payout", "выплата"),
+    ("problem", "проблема"),
+    ("proposal", "предложение"),
+    ("ratio", "соотношение"),
+    ("result", "результат"),
+    ("reward", "выигрыш"),
+    ("risk", "риск"),
+    ("setting", "настройка"),
+    ("severity", "важность"),
+    ("size", "размер"),
+    ("state", "состояние"),
+    ("status", "статус"),
+    ("time", "время"),
+    ("value", "сумма"),
+    ("winner", "победитель"),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locale_from_tag() {
+        assert_eq!(Locale::from_tag("ru_RU.UTF-8"), Some(Locale::Ru));
+        assert_eq!(Locale::from_tag("RU-ru"), Some(Locale::Ru));
+        assert_eq!(Locale::from_tag("en_GB"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("C"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("xx_XX"), None);
+        assert!(Locale::from_str("de").is_err());
+    }
+
+    #[test]
+    fn translations_keep_their_placeholders() {
+        let placeholders = |string: &str| {
+            let mut found = string
+                .match_indices('{')
+                .filter_map(|(i, _)| Some(&string[i..=i + string[i..].find('}')?]))
+                .collect::<Vec<_>>();
+            found.sort();
+            found
+        };
+        for locale in Locale::ALL.iter() {
+            for (english, translated) in locale.catalog() {
+                assert_eq!(
+                    placeholders(english),
+                    placeholders(translated),
+                    "{} translation of {:?}",
+                    locale,
+                    english
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn answers_are_unambiguous() {
+        for locale in Locale::ALL.iter() {
+            let answers = locale.answers();
+            assert!(
+                answers.yes.iter().all(|yes| !answers.no.contains(yes)),
+                "{} has an answer that means yes and no",
+                locale
+            );
+            assert_eq!(answers.parse("yes"), Some(true));
+            assert_eq!(answers.parse(" NO\n"), Some(false));
+        }
+        let ru = Locale::Ru.answers();
+        assert_eq!(ru.parse("да"), Some(true));
+        assert_eq!(ru.parse("Нет"), Some(false));
+        // the keys these are on mean the opposite on the other layout
+        for typo in &["y", "n", "н", "д", "lf", "ytn"] {
+            assert_eq!(ru.parse(typo), None, "{}", typo);
+        }
+    }
+
+    #[test]
+    fn translate_and_fill() {
+        assert_eq!(translate(Locale::Ru, "balance"), "баланс");
+        assert_eq!(translate(Locale::Ru, "not translated"), "not translated");
+        assert_eq!(translate(Locale::En, "balance"), "balance");
+        assert_eq!(
+            fill(
+                translate(Locale::Ru, "You are betting that {outcome}"),
+                &[("outcome", &"it rains")]
+            ),
+            "Вы ставите на то, что it rains"
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use crate::betting::Proposal;
 use bdk::bitcoin::{
     hashes::{sha512, Hash, HashEngine, Hmac, HmacEngine},
-    util::bip32::ExtendedPrivKey,
-    Network,
+    secp256k1::Secp256k1,
+    util::bip32::{ChildNumber, ExtendedPrivKey, ExtendedPubKey},
+    Address, Network,
 };
 use olivia_secp256k1::schnorr_fun::fun::{marker::*, Point, Scalar, G};
 
@@ -54,10 +55,40 @@ impl Keychain {
         }
     }
 
+    /// The secret that encrypts backups (see [`crate::backup`]).
+    pub fn backup_secret(&self) -> [u8; 64] {
+        let mut hmac = HmacEngine::<sha512::Hash>::new(b"gun-backup");
+        hmac.input(&self.seed[..]);
+        Hmac::from_engine(hmac).into_inner()
+    }
+
     pub fn main_wallet_xprv(&self, network: Network) -> ExtendedPrivKey {
         ExtendedPrivKey::new_master(network, &self.seed).unwrap()
     }
 
+    /// The BIP84 receive address at `index` worked out straight from the seed without going through
+    /// BDK or the database. Used to check what the wallet hands out hasn't been tampered with.
+    pub fn receive_address(&self, network: Network, index: u32) -> Address {
+        let secp = Secp256k1::new();
+        let coin_type = match network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let path = [
+            ChildNumber::Hardened { index: 84 },
+            ChildNumber::Hardened { index: coin_type },
+            ChildNumber::Hardened { index: 0 },
+            ChildNumber::Normal { index: 0 },
+            ChildNumber::Normal { index },
+        ];
+        let xprv = self
+            .main_wallet_xprv(network)
+            .derive_priv(&secp, &path)
+            .expect("derivation can't fail");
+        let xpub = ExtendedPubKey::from_private(&secp, &xprv);
+        Address::p2wpkh(&xpub.public_key, network).expect("key is compressed")
+    }
+
     pub fn get_key_for_proposal(&self, proposal: &Proposal) -> KeyPair {
         let mut proposal = proposal.clone();
         proposal.public_key = crate::placeholder_point();
@@ -78,3 +109,34 @@ impl Keychain {
         KeyPair::from_slice(&res[..]).expect("computationally unreachable")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::{
+        database::MemoryDatabase, template::Bip84, wallet::AddressIndex, KeychainKind, Wallet,
+    };
+
+    #[test]
+    fn receive_address_matches_bdk() {
+        let keychain = Keychain::new([42u8; 64]);
+        for network in [Network::Bitcoin, Network::Regtest] {
+            let wallet = Wallet::new_offline(
+                Bip84(keychain.main_wallet_xprv(network), KeychainKind::External),
+                None,
+                network,
+                MemoryDatabase::default(),
+            )
+            .unwrap();
+            for index in [0, 1, 57] {
+                assert_eq!(
+                    keychain.receive_address(network, index),
+                    wallet
+                        .get_address(AddressIndex::Peek(index))
+                        .unwrap()
+                        .address
+                );
+            }
+        }
+    }
+}
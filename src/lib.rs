@@ -3,16 +3,61 @@
 use std::str::FromStr;
 
 use bdk::bitcoin::Amount;
+pub mod address;
+pub mod allowance;
 pub mod amount_ext;
+pub mod approval;
+pub mod audit;
+pub mod backup;
 pub mod betting;
+pub mod board;
+pub mod broadcast_queue;
 mod change;
+pub mod change_descriptor;
+pub mod clipboard;
+pub mod clock;
 pub mod cmd;
+pub mod coin_select;
+pub mod coinjoin;
+pub mod cold_storage;
+pub mod coldcard;
 pub mod config;
+pub mod deterministic;
+pub mod dust;
 pub mod ecdh;
 pub mod encode;
+pub mod endpoint;
+pub mod ephemeral;
+pub mod event_source;
+pub mod exit_code;
+pub mod external_signer;
+pub mod faucet;
+pub mod features;
+pub mod fee_snapshot;
 mod fee_spec;
+pub mod i18n;
 pub mod keychain;
+pub mod logging;
+pub mod nostr;
+pub mod notify;
+pub mod oracle_poll;
+pub mod package;
+pub mod parallel;
+pub mod pinned_backend;
+pub mod plugin;
+pub mod price;
 pub mod psbt_ext;
+pub mod psbt_policy;
+pub mod qr;
+pub mod read_only;
+pub mod schedule;
+pub mod session;
+pub mod state;
+pub mod tip_watch;
+pub mod tx_ordering;
+pub mod tx_size;
+pub mod wallet_import;
+pub mod watch;
 pub use fee_spec::*;
 pub use reqwest;
 
@@ -52,7 +97,7 @@ pub(crate) fn placeholder_point(
 }
 
 pub fn format_dt_diff_till_now(dt: chrono::NaiveDateTime) -> String {
-    let now = chrono::Utc::now().naive_utc();
+    let now = crate::clock::now();
     let diff = dt - now;
     if diff.abs() < chrono::Duration::hours(1) {
         format!("{}m", diff.num_minutes())
@@ -0,0 +1,138 @@
+//! Logging with [`tracing`] so failed syncs, transactions and bet claims can be debugged after the
+//! fact.
+//!
+//! Log lines go to stderr and optionally to a file that is rotated every day. Everything written
+//! goes through [`redact`] on the way out. That is a last line of defence -- secrets should be
+//! wrapped in [`Secret`] before they get anywhere near a log macro.
+use std::{borrow::Cow, io, path::Path};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*};
+
+/// What replaces secrets in the logs.
+pub const REDACTED: &str = "[redacted]";
+
+/// Key prefixes of extended private keys on every network we support.
+const XPRV_PREFIXES: [&str; 2] = ["xprv", "tprv"];
+
+/// Sets up logging. `verbosity` is the number of times `-v` was given. The returned guard must be
+/// kept until the program exits or the end of the log file may be lost.
+pub fn init(verbosity: u8, log_file: Option<&Path>) -> anyhow::Result<Option<WorkerGuard>> {
+    let stderr_level = match verbosity {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let stderr_layer = fmt::layer()
+        .with_writer(|| Redacting(io::stderr()))
+        .without_time()
+        .with_target(false)
+        .with_filter(stderr_level);
+
+    let (file_layer, guard) = match log_file {
+        Some(log_file) => {
+            let dir = match log_file.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let file_name = log_file
+                .file_name()
+                .ok_or(anyhow::anyhow!("{} is not a file", log_file.display()))?;
+            std::fs::create_dir_all(dir)?;
+            let (writer, guard) =
+                tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, file_name));
+            // the file is for after the fact so it always gets at least the debug messages
+            let layer = fmt::layer()
+                .with_ansi(false)
+                .with_writer(move || Redacting(writer.clone()))
+                .with_filter(std::cmp::max(LevelFilter::DEBUG, stderr_level));
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .try_init()?;
+
+    Ok(guard)
+}
+
+/// Wraps a value so its `Display` and `Debug` don't show it.
+pub struct Secret<T>(pub T);
+
+impl<T> core::fmt::Display for Secret<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl<T> core::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Removes anything that looks like an extended private key from `text`.
+pub fn redact(text: &str) -> Cow<str> {
+    let is_xprv = |word: &str| {
+        XPRV_PREFIXES.iter().any(|prefix| word.starts_with(prefix))
+            && word.len() >= 100
+            && word.chars().all(|c| c.is_ascii_alphanumeric())
+    };
+    if !text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .any(is_xprv)
+    {
+        return Cow::Borrowed(text);
+    }
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+        redacted.push_str(if is_xprv(&word) { REDACTED } else { &word });
+        word.clear();
+        redacted.push(c);
+    }
+    redacted.push_str(if is_xprv(&word) { REDACTED } else { &word });
+    Cow::Owned(redacted)
+}
+
+/// Passes what is written through [`redact`]. tracing writes each event in one go so keys are never
+/// split between writes.
+struct Redacting<W>(W);
+
+impl<W: io::Write> io::Write for Redacting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redacts_extended_private_keys() {
+        let xprv = "tprv8ZgxMBicQKsPd3G66kPkZEuJZgUK9QXJRYCwnCtYLJjEZmw8xFjCxGoyx533AL83XFcSQeuVmVeJbZai5RTBxDp71Abd2FPSyQumRL79BKw";
+        let line = format!("descriptor wpkh({}/84'/1'/0'/0/*) loaded", xprv);
+        assert_eq!(
+            redact(&line),
+            format!("descriptor wpkh({}/84'/1'/0'/0/*) loaded", REDACTED)
+        );
+        let txid = "txid 3b8e6f2e2c1ad8e5a31c69b0b6bb4a3e5b1b52b6ab6b2e5a2b0a0c8c6d6a2e1f";
+        assert!(matches!(redact(txid), Cow::Borrowed(_)));
+        assert_eq!(format!("{}", Secret("hunter2")), REDACTED);
+    }
+}
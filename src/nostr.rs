@@ -0,0 +1,211 @@
+//! Just enough of nostr (NIP-01) to publish events to relays and fetch them back.
+//!
+//! Our keys are BIP340 keys already so the keys from [`crate::keychain`] are used as they are.
+use crate::keychain::KeyPair;
+use anyhow::anyhow;
+use olivia_secp256k1::schnorr_fun::{
+    fun::{marker::*, Point},
+    nonce::Deterministic,
+    Message, MessageKind, Schnorr, Signature,
+};
+use sha2::{Digest, Sha256};
+use std::{convert::TryInto, str::FromStr};
+#[cfg(feature = "nostr")]
+use tungstenite::Message as WsMessage;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u64,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+fn schnorr() -> Schnorr<Sha256, Deterministic<Sha256>> {
+    Schnorr::new(Deterministic::<Sha256>::default(), MessageKind::Prehashed)
+}
+
+pub fn public_key_hex(public_key: &Point<EvenY>) -> String {
+    crate::hex::encode(&public_key.to_xonly().as_bytes()[..])
+}
+
+fn event_id(
+    pubkey: &str,
+    created_at: u64,
+    kind: u64,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = serde_json::json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    Sha256::digest(serialized.as_bytes()).into()
+}
+
+impl Event {
+    pub fn sign(
+        keypair: &KeyPair,
+        created_at: u64,
+        kind: u64,
+        tags: Vec<Vec<String>>,
+        content: String,
+    ) -> Self {
+        let pubkey = public_key_hex(&keypair.public_key);
+        let id = event_id(&pubkey, created_at, kind, &tags, &content);
+        let schnorr = schnorr();
+        let signing_keypair = schnorr.new_keypair(keypair.secret_key.clone());
+        let sig = schnorr.sign(&signing_keypair, Message::<Public>::raw(&id[..]));
+        Event {
+            id: crate::hex::encode(&id[..]),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: crate::hex::encode(&sig.to_bytes()[..]),
+        }
+    }
+
+    /// Checks the id is right and `pubkey` signed it.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        if crate::hex::encode(&id[..]) != self.id {
+            return Err(anyhow!("event {} has the wrong id", self.id));
+        }
+        let public_key = Point::<EvenY>::from_str(&self.pubkey)
+            .map_err(|_| anyhow!("event {} has an invalid pubkey", self.id))?;
+        let sig = crate::hex::decode(&self.sig)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .and_then(|bytes: [u8; 64]| Signature::from_bytes(bytes))
+            .ok_or(anyhow!("event {} has an invalid signature", self.id))?;
+        if !schnorr().verify(&public_key, Message::<Public>::raw(&id[..]), &sig) {
+            return Err(anyhow!("event {} has an invalid signature", self.id));
+        }
+        Ok(())
+    }
+
+    /// The value of the first tag named `name`.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|tag| tag.get(0).map(String::as_str) == Some(name))
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+}
+
+/// Sends `event` to `relay` and waits for it to be accepted.
+#[cfg(not(feature = "nostr"))]
+pub fn publish(_relay: &str, _event: &Event) -> anyhow::Result<()> {
+    Err(crate::features::not_compiled_in(
+        "nostr",
+        "publish to nostr relays",
+    ))
+}
+
+/// Every event `relay` has stored that matches `filter` (see NIP-01).
+#[cfg(not(feature = "nostr"))]
+pub fn query(_relay: &str, _filter: serde_json::Value) -> anyhow::Result<Vec<Event>> {
+    Err(crate::features::not_compiled_in(
+        "nostr",
+        "query nostr relays",
+    ))
+}
+
+/// Sends `event` to `relay` and waits for it to be accepted.
+#[cfg(feature = "nostr")]
+pub fn publish(relay: &str, event: &Event) -> anyhow::Result<()> {
+    crate::read_only::check("publish to nostr")?;
+    let (mut socket, _) = tungstenite::connect(relay)?;
+    socket.write_message(WsMessage::Text(
+        serde_json::json!(["EVENT", event]).to_string(),
+    ))?;
+    let result = loop {
+        let message = match socket.read_message()? {
+            WsMessage::Text(text) => serde_json::from_str::<serde_json::Value>(&text)?,
+            WsMessage::Close(_) => break Err(anyhow!("{} closed the connection", relay)),
+            _ => continue,
+        };
+        match message[0].as_str() {
+            // NIP-20
+            Some("OK") if message[1].as_str() == Some(&event.id) => match message[2].as_bool() {
+                Some(true) => break Ok(()),
+                _ => break Err(anyhow!("{} rejected the event: {}", relay, message[3])),
+            },
+            Some("NOTICE") => tracing::warn!(%relay, notice = %message[1], "relay notice"),
+            _ => {}
+        }
+    };
+    let _ = socket.close(None);
+    result
+}
+
+/// Every event `relay` has stored that matches `filter` (see NIP-01).
+#[cfg(feature = "nostr")]
+pub fn query(relay: &str, filter: serde_json::Value) -> anyhow::Result<Vec<Event>> {
+    let (mut socket, _) = tungstenite::connect(relay)?;
+    socket.write_message(WsMessage::Text(
+        serde_json::json!(["REQ", "gun", filter]).to_string(),
+    ))?;
+
+    let mut events = vec![];
+    loop {
+        let message = match socket.read_message()? {
+            WsMessage::Text(text) => serde_json::from_str::<serde_json::Value>(&text)?,
+            WsMessage::Close(_) => break,
+            _ => continue,
+        };
+        match message[0].as_str() {
+            Some("EVENT") => match serde_json::from_value::<Event>(message[2].clone()) {
+                Ok(event) => events.push(event),
+                Err(e) => tracing::debug!(%relay, "ignoring invalid event: {}", e),
+            },
+            // the relay has sent everything it has stored
+            Some("EOSE") => break,
+            Some("NOTICE") => tracing::warn!(%relay, notice = %message[1], "relay notice"),
+            _ => {}
+        }
+    }
+    let _ = socket.write_message(WsMessage::Text(
+        serde_json::json!(["CLOSE", "gun"]).to_string(),
+    ));
+    let _ = socket.close(None);
+    Ok(events)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use olivia_secp256k1::schnorr_fun::fun::{s, G};
+
+    #[test]
+    fn signed_events_verify() {
+        let keypair = KeyPair::from_slice(&[42u8; 32]).unwrap();
+        let event = Event::sign(
+            &keypair,
+            1_600_000_000,
+            4,
+            vec![vec!["p".into(), public_key_hex(&keypair.public_key)]],
+            "hello".into(),
+        );
+        assert!(event.verify().is_ok());
+        assert_eq!(event.tag("p"), Some(event.pubkey.as_str()));
+
+        let mut tampered = event.clone();
+        tampered.content = "goodbye".into();
+        assert!(tampered.verify().is_err());
+
+        let other = Point::<EvenY>::from_scalar_mul(G, &mut s!(7));
+        let mut wrong_key = event;
+        wrong_key.pubkey = public_key_hex(&other);
+        assert!(wrong_key.verify().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Notifications about everyday things happening to the wallet and its bets like coins arriving
+//! or an oracle attesting. They're off unless `notifications` in the config has `enabled` set.
+//!
+//! When there's a desktop to show them on they are shown with `notify-send` (or `osascript` on
+//! macOS). Otherwise (or with `desktop` off) they're given to the `alert-command` like alerts are
+//! with the kind of notification in `GUN_ALERT`.
+use anyhow::anyhow;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationKind {
+    /// Coins have arrived in the wallet
+    IncomingPayment,
+    /// A transaction or bet of ours has been confirmed
+    Confirmation,
+    /// Someone took one of our offers
+    OfferTaken,
+    /// The oracle has attested to the outcome of a bet
+    Attestation,
+    /// The transaction claiming a bet we won has been confirmed
+    ClaimConfirmed,
+}
+
+impl NotificationKind {
+    pub const ALL: [NotificationKind; 5] = [
+        NotificationKind::IncomingPayment,
+        NotificationKind::Confirmation,
+        NotificationKind::OfferTaken,
+        NotificationKind::Attestation,
+        NotificationKind::ClaimConfirmed,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        use NotificationKind::*;
+        match self {
+            IncomingPayment => "incoming-payment",
+            Confirmation => "confirmation",
+            OfferTaken => "offer-taken",
+            Attestation => "attestation",
+            ClaimConfirmed => "claim-confirmed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct NotificationSettings {
+    pub enabled: bool,
+    /// Show them on the desktop if there is one
+    pub desktop: bool,
+    /// Which kinds to notify about
+    pub kinds: Vec<NotificationKind>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            desktop: true,
+            kinds: NotificationKind::ALL.to_vec(),
+        }
+    }
+}
+
+impl NotificationSettings {
+    pub fn wants(&self, kind: NotificationKind) -> bool {
+        self.enabled && self.kinds.contains(&kind)
+    }
+}
+
+/// Shows `message` on the desktop or gives it to `alert_command` if that can't be done.
+pub fn notify(
+    settings: &NotificationSettings,
+    alert_command: Option<&[String]>,
+    kind: NotificationKind,
+    message: &str,
+) {
+    if !settings.wants(kind) {
+        return;
+    }
+    tracing::info!(kind = kind.name(), "{}", message);
+    if settings.desktop {
+        match desktop_notification("gun", message) {
+            Ok(()) => return,
+            Err(e) => tracing::debug!("couldn't show a desktop notification: {}", e),
+        }
+    }
+    crate::betting::run_alert_command(alert_command, kind.name(), message);
+}
+
+fn desktop_notification(title: &str, message: &str) -> anyhow::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("osascript");
+        // AppleScript strings are escaped the same way as Rust's debug strings
+        command.arg("-e").arg(format!(
+            "display notification {:?} with title {:?}",
+            message, title
+        ));
+        command
+    } else if cfg!(unix) {
+        if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+            return Err(anyhow!("there's no desktop"));
+        }
+        let mut command = std::process::Command::new("notify-send");
+        command.arg(title).arg(message);
+        command
+    } else {
+        return Err(anyhow!("desktop notifications aren't supported here"));
+    };
+    let status = command.status()?;
+    if !status.success() {
+        return Err(anyhow!("{:?} exited with {}", command, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_wanted_kinds() {
+        let settings: NotificationSettings =
+            serde_json::from_str(r#"{ "enabled": true, "kinds": ["attestation"] }"#).unwrap();
+        assert!(settings.desktop);
+        assert!(settings.wants(NotificationKind::Attestation));
+        assert!(!settings.wants(NotificationKind::IncomingPayment));
+        assert!(!NotificationSettings::default().wants(NotificationKind::Attestation));
+    }
+}
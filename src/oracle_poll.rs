@@ -0,0 +1,208 @@
+//! Polling oracles for attestations without hammering them.
+//!
+//! Claiming and watching bets ask the oracle about the same events over and over. The last
+//! response for each event url is kept in the database with the `ETag` and `Last-Modified` the
+//! oracle sent so asking again is a conditional request the oracle can answer with `304 Not
+//! Modified`. Once there's an attestation the oracle isn't asked again at all. When asking fails
+//! the event isn't asked about again for a while, doubling each time it fails in a row. Requests
+//! to the same host are spaced out by at least [`MIN_HOST_INTERVAL_MILLIS`] whichever event
+//! they're for.
+use crate::{
+    betting::{BetDatabase, EventResponse},
+    event_source::{CacheValidators, EventSources, Fetched},
+    Url,
+};
+use olivia_core::chrono::{Duration, NaiveDateTime, Utc};
+
+/// How long to wait between requests to the same host.
+pub const MIN_HOST_INTERVAL_MILLIS: i64 = 1_000;
+/// How long to wait after the first failure. It doubles with each failure after that.
+const FIRST_BACKOFF_SECS: i64 = 30;
+/// The longest to wait after failures.
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+
+/// What we know from asking about an event url.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OraclePoll {
+    #[serde(default)]
+    pub validators: CacheValidators,
+    /// The last response the oracle gave
+    pub response: Option<EventResponse>,
+    pub last_polled: NaiveDateTime,
+    /// How many times in a row asking has failed
+    #[serde(default)]
+    pub failures: u32,
+    /// Don't ask again before this because of the failures
+    #[serde(default)]
+    pub retry_after: Option<NaiveDateTime>,
+}
+
+/// When a host was last sent a request.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OracleHostPoll {
+    pub last_request: NaiveDateTime,
+}
+
+/// How long to wait before asking again after `failures` failures in a row.
+pub fn backoff(failures: u32) -> Duration {
+    if failures == 0 {
+        return Duration::zero();
+    }
+    let secs = FIRST_BACKOFF_SECS.saturating_mul(1i64 << (failures - 1).min(30));
+    Duration::seconds(secs.min(MAX_BACKOFF_SECS))
+}
+
+pub struct OraclePoller<'a> {
+    bet_db: &'a BetDatabase,
+    sources: &'a EventSources,
+}
+
+impl<'a> OraclePoller<'a> {
+    pub fn new(bet_db: &'a BetDatabase, sources: &'a EventSources) -> Self {
+        OraclePoller { bet_db, sources }
+    }
+
+    /// The oracle's latest response for the event at `event_url`. Returns `None` if it's backing
+    /// off from failures and there's nothing from before. Errors are recorded and returned.
+    pub fn poll(&self, event_url: &Url) -> anyhow::Result<Option<EventResponse>> {
+        let key = event_url.to_string();
+        let previous = self.bet_db.get_entity::<OraclePoll>(key.clone())?;
+        let cached = previous.as_ref().and_then(|poll| poll.response.clone());
+        if let Some(response) = &cached {
+            if response.attestation.is_some() {
+                // attestations don't change
+                return Ok(cached);
+            }
+        }
+        let now = Utc::now().naive_utc();
+        if let Some(retry_after) = previous.as_ref().and_then(|poll| poll.retry_after) {
+            if retry_after > now {
+                tracing::debug!(%event_url, %retry_after, "not asking the oracle while backing off");
+                return Ok(cached);
+            }
+        }
+
+        self.wait_for_host(event_url)?;
+        let validators = previous
+            .as_ref()
+            .map(|poll| poll.validators.clone())
+            .unwrap_or_default();
+        let fetched = self
+            .sources
+            .for_url(event_url)
+            .event_if_changed(event_url, &validators);
+        let now = Utc::now().naive_utc();
+        match fetched {
+            Ok(Fetched::NotModified) if cached.is_some() => {
+                self.bet_db.set_oracle_poll(
+                    key,
+                    OraclePoll {
+                        validators,
+                        response: cached.clone(),
+                        last_polled: now,
+                        failures: 0,
+                        retry_after: None,
+                    },
+                )?;
+                Ok(cached)
+            }
+            Ok(Fetched::NotModified) => {
+                // we lost what it was so ask again without the validators
+                let event_response = self.sources.for_url(event_url).event(event_url)?;
+                self.bet_db.set_oracle_poll(
+                    key,
+                    OraclePoll {
+                        validators: CacheValidators::default(),
+                        response: Some(event_response.clone()),
+                        last_polled: now,
+                        failures: 0,
+                        retry_after: None,
+                    },
+                )?;
+                Ok(Some(event_response))
+            }
+            Ok(Fetched::Changed(event_response, validators)) => {
+                self.bet_db.set_oracle_poll(
+                    key,
+                    OraclePoll {
+                        validators,
+                        response: Some(event_response.clone()),
+                        last_polled: now,
+                        failures: 0,
+                        retry_after: None,
+                    },
+                )?;
+                Ok(Some(event_response))
+            }
+            Err(e) => {
+                let failures = previous.as_ref().map(|poll| poll.failures).unwrap_or(0) + 1;
+                let retry_after = now + backoff(failures);
+                tracing::warn!(%event_url, failures, %retry_after, "asking the oracle failed: {:#}", e);
+                self.bet_db.set_oracle_poll(
+                    key,
+                    OraclePoll {
+                        validators,
+                        response: cached,
+                        last_polled: now,
+                        failures,
+                        retry_after: Some(retry_after),
+                    },
+                )?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sleeps until the host of `url` can be sent another request and records that it's being
+    /// sent one now.
+    fn wait_for_host(&self, url: &Url) -> anyhow::Result<()> {
+        let host = match url.host_str() {
+            Some(host) => host.to_string(),
+            None => return Ok(()),
+        };
+        if let Some(last) = self.bet_db.get_entity::<OracleHostPoll>(host.clone())? {
+            let next = last.last_request + Duration::milliseconds(MIN_HOST_INTERVAL_MILLIS);
+            // a negative wait means it's already been long enough
+            if let Ok(wait) = (next - Utc::now().naive_utc()).to_std() {
+                std::thread::sleep(wait);
+            }
+        }
+        self.bet_db.set_oracle_host_poll(
+            host,
+            OracleHostPoll {
+                last_request: Utc::now().naive_utc(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        assert_eq!(backoff(0), Duration::zero());
+        assert_eq!(backoff(1), Duration::seconds(30));
+        assert_eq!(backoff(2), Duration::seconds(60));
+        assert_eq!(backoff(5), Duration::seconds(480));
+        assert_eq!(backoff(20), Duration::hours(6));
+        assert_eq!(backoff(u32::MAX), Duration::hours(6));
+    }
+
+    #[test]
+    fn host_requests_are_recorded() {
+        let bet_db = BetDatabase::test_new();
+        let sources = EventSources::default();
+        let poller = OraclePoller::new(&bet_db, &sources);
+        let url =
+            Url::parse("https://h00.ooo/random/2021-01-01T00:00:00/heads_tails.winner").unwrap();
+        poller.wait_for_host(&url).unwrap();
+        assert!(bet_db
+            .get_entity::<OracleHostPoll>("h00.ooo".to_string())
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,162 @@
+//! Fees for transactions that spend from our own unconfirmed transactions.
+//!
+//! Miners look at the feerate of a transaction together with its unconfirmed ancestors (the
+//! "package") so a child of a low fee parent has to pay extra for both to confirm in time.
+use crate::tx_size::TxSize;
+use bdk::{
+    bitcoin::{util::psbt::PartiallySignedTransaction as Psbt, Amount, Transaction, TxOut, Txid},
+    database::BatchDatabase,
+    FeeRate, Wallet,
+};
+use std::collections::HashSet;
+
+/// The unconfirmed transactions of ours that a transaction spends from (directly or not).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ancestors {
+    pub txids: Vec<Txid>,
+    pub fee: Amount,
+    pub vsize: u64,
+    /// Unconfirmed ancestors we don't know the fee of (e.g. someone paying us). They are left out
+    /// of `fee` and `vsize`.
+    pub unknown_fee: Vec<Txid>,
+}
+
+impl Ancestors {
+    pub fn find<B, D: BatchDatabase>(
+        wallet: &Wallet<B, D>,
+        tx: &Transaction,
+    ) -> anyhow::Result<Self> {
+        let mut ancestors = Ancestors::default();
+        let mut seen = HashSet::new();
+        let mut to_visit = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output.txid)
+            .collect::<Vec<_>>();
+
+        while let Some(txid) = to_visit.pop() {
+            if !seen.insert(txid) {
+                continue;
+            }
+            let tx_details = match wallet.query_db(|db| db.get_tx(&txid, true))? {
+                Some(tx_details) if tx_details.confirmation_time.is_none() => tx_details,
+                // confirmed or not ours (e.g. a bet output)
+                _ => continue,
+            };
+            let parent = match &tx_details.transaction {
+                Some(parent) => parent,
+                None => continue,
+            };
+            match tx_details.fee {
+                Some(fee) => {
+                    ancestors.txids.push(txid);
+                    ancestors.fee += Amount::from_sat(fee);
+                    ancestors.vsize += vsize(parent.get_weight());
+                }
+                None => ancestors.unknown_fee.push(txid),
+            }
+            to_visit.extend(parent.input.iter().map(|txin| txin.previous_output.txid));
+        }
+
+        Ok(ancestors)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txids.is_empty()
+    }
+
+    pub fn feerate(&self) -> Option<FeeRate> {
+        if self.vsize == 0 {
+            return None;
+        }
+        Some(FeeRate::from_sat_per_vb(
+            self.fee.as_sat() as f32 / self.vsize as f32,
+        ))
+    }
+
+    /// The fee a child of `child_vsize` has to pay for it and the ancestors to pay `target`
+    /// together.
+    pub fn child_fee_for(&self, target: FeeRate, child_vsize: u64) -> Amount {
+        let package_fee = (target.as_sat_vb() * (self.vsize + child_vsize) as f32).ceil() as u64;
+        Amount::from_sat(package_fee.saturating_sub(self.fee.as_sat()))
+    }
+}
+
+fn vsize(weight: usize) -> u64 {
+    ((weight + 3) / 4) as u64
+}
+
+/// The vsize `psbt` will have once its inputs are signed. `satisfaction_weight` says how much
+/// signing each coin adds (see [`TxSize::of`] and [`crate::tx_size::wallet_satisfaction_weight`]).
+pub fn estimated_vsize(psbt: &Psbt, satisfaction_weight: impl Fn(&TxOut) -> Option<usize>) -> u64 {
+    TxSize::of(psbt, satisfaction_weight).vsize()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn child_pays_for_parent() {
+        let ancestors = Ancestors {
+            txids: vec![],
+            fee: Amount::from_sat(200),
+            vsize: 200,
+            unknown_fee: vec![],
+        };
+        assert_eq!(ancestors.feerate(), Some(FeeRate::from_sat_per_vb(1.0)));
+        // (200 + 100) * 10 - 200
+        assert_eq!(
+            ancestors.child_fee_for(FeeRate::from_sat_per_vb(10.0), 100),
+            Amount::from_sat(2_800)
+        );
+        // parents that already pay more than the target don't make the child pay less than nothing
+        assert_eq!(
+            ancestors.child_fee_for(FeeRate::from_sat_per_vb(0.5), 100),
+            Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn estimate_uses_the_coins_own_satisfaction() {
+        use bdk::{
+            bitcoin::{util::psbt, OutPoint, Script, TxIn},
+            miniscript::{descriptor::DescriptorPublicKey, Descriptor},
+        };
+        use std::str::FromStr;
+        // a 2 of 2 p2wsh coin needs two signatures and the script in its witness
+        let multisig = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(multi(2,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5))",
+        )
+        .unwrap();
+        let weight = multisig.max_satisfaction_weight().unwrap();
+        let txout = TxOut {
+            value: 100_000,
+            script_pubkey: Script::new(),
+        };
+        let psbt = Psbt {
+            global: psbt::Global::from_unsigned_tx(Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: OutPoint::default(),
+                    ..Default::default()
+                }],
+                output: vec![TxOut {
+                    value: 90_000,
+                    script_pubkey: Script::new(),
+                }],
+            })
+            .unwrap(),
+            inputs: vec![psbt::Input {
+                witness_utxo: Some(txout),
+                ..Default::default()
+            }],
+            outputs: vec![Default::default()],
+        };
+        let as_p2wpkh = estimated_vsize(&psbt, |_| None);
+        let as_multisig = estimated_vsize(&psbt, |_| Some(weight));
+        assert!(weight > 4 + 1 + 73 + 34);
+        assert!(as_multisig > as_p2wpkh);
+    }
+}
@@ -0,0 +1,103 @@
+//! Doing the same thing to each input of a big transaction (signing or checking signatures) on
+//! several threads at once.
+//!
+//! Each thread works through its own run of inputs with its own state (e.g. a `SigHashCache`) so
+//! nothing is shared while they work. Transactions with only a few inputs are done on the calling
+//! thread since starting threads would take longer than the work.
+use std::thread;
+
+/// Below this many items per thread it's not worth starting another thread.
+pub const MIN_ITEMS_PER_WORKER: usize = 8;
+
+/// How many threads [`map_with`] uses for `n_items`.
+pub fn workers(n_items: usize) -> usize {
+    let available = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    available.min(n_items / MIN_ITEMS_PER_WORKER).max(1)
+}
+
+/// `f` applied to each of `items` with the results in the same order. Each thread starts with its
+/// own state from `init`.
+pub fn map_with<T, S, R>(
+    items: &[T],
+    init: impl Fn() -> S + Sync,
+    f: impl Fn(&mut S, usize, &T) -> R + Sync,
+) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    map_with_workers(workers(items.len()), items, init, f)
+}
+
+/// Like [`map_with`] but with exactly `workers` threads (e.g. to compare it with one).
+pub fn map_with_workers<T, S, R>(
+    workers: usize,
+    items: &[T],
+    init: impl Fn() -> S + Sync,
+    f: impl Fn(&mut S, usize, &T) -> R + Sync,
+) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let run = |offset: usize, chunk: &[T]| {
+        let mut state = init();
+        chunk
+            .iter()
+            .enumerate()
+            .map(|(i, item)| f(&mut state, offset + i, item))
+            .collect::<Vec<_>>()
+    };
+    if workers <= 1 || items.len() <= 1 {
+        return run(0, items);
+    }
+    let chunk_size = (items.len() + workers - 1) / workers;
+    thread::scope(|scope| {
+        let handles = items
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let run = &run;
+                scope.spawn(move || run(i * chunk_size, chunk))
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| match handle.join() {
+                Ok(results) => results,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn results_stay_in_order() {
+        let items = (0..100u32).collect::<Vec<_>>();
+        for workers in 1..6 {
+            let results = map_with_workers(
+                workers,
+                &items,
+                || 0u32,
+                |seen, index, item| {
+                    *seen += 1;
+                    (index, *item * 2)
+                },
+            );
+            assert_eq!(
+                results,
+                items
+                    .iter()
+                    .map(|item| (*item as usize, *item * 2))
+                    .collect::<Vec<_>>()
+            );
+        }
+        assert!(map_with(&[] as &[u32], || (), |_, _, item| *item).is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Pinning the backend's TLS certificate (`pinned-cert-sha256` in the config).
+//!
+//! bdk makes the backend's HTTP client itself so it can't be given a certificate verifier of ours.
+//! Instead, when a certificate is pinned, everything talks to the backend through a relay that gun
+//! runs on localhost. The relay makes every connection to the backend itself and checks the
+//! certificate presented on that connection against the pin before it sends anything. The checked
+//! connection is the one the request goes over. The pin is the only check the certificate gets so
+//! personal servers with self-signed certificates work too.
+//!
+//! Only Esplora backends can be pinned since they're the only ones gun is built with.
+use crate::{config::Config, hex, Url};
+use anyhow::{anyhow, Context};
+use bdk::blockchain::AnyBlockchainConfig;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+/// How long connecting to the backend and each read and write can take
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The relays running in this process: the backend url, the pin and where the relay listens
+static RELAYS: Mutex<Vec<(String, String, SocketAddr)>> = Mutex::new(Vec::new());
+
+/// Normalizes a certificate fingerprint so it can be written as `AB:CD:..` or `abcd..`.
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// `config.blockchain` with the backend reached through the relay if a certificate is pinned.
+/// Everything that makes a bdk blockchain from the config goes through here.
+pub fn blockchain_config(config: &Config) -> anyhow::Result<AnyBlockchainConfig> {
+    let mut blockchain = config.blockchain.clone();
+    match &mut blockchain {
+        AnyBlockchainConfig::Esplora(esplora) => {
+            esplora.base_url = base_url(&esplora.base_url, config.pinned_cert_sha256.as_deref())?
+        }
+        #[allow(unreachable_patterns)]
+        _ => {}
+    }
+    Ok(blockchain)
+}
+
+/// The url to reach the Esplora server at `base_url` at: the relay's if `pinned` is the
+/// fingerprint of its certificate, otherwise `base_url` itself.
+pub fn base_url(base_url: &str, pinned: Option<&str>) -> anyhow::Result<String> {
+    let pinned = match pinned {
+        Some(pinned) => normalize_fingerprint(pinned),
+        None => return Ok(base_url.to_string()),
+    };
+    let url = Url::parse(base_url).with_context(|| format!("parsing backend url {}", base_url))?;
+    if url.scheme() != "https" {
+        return Err(anyhow!(
+            "a certificate is pinned in the config but the backend {} doesn't use https",
+            url
+        ));
+    }
+    if crate::endpoint::proxy_for(&url).is_some() {
+        return Err(anyhow!(
+            "a certificate is pinned in the config but the backend {} is reached through a proxy so it can't be checked",
+            url
+        ));
+    }
+    let relay = relay(&url, &pinned)?;
+    Ok(format!(
+        "http://{}{}",
+        relay,
+        url.path().trim_end_matches('/')
+    ))
+}
+
+/// Connects to `host` on `port` giving up on each of its addresses after [`TIMEOUT`].
+pub fn connect(host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("looking up {}", host))?
+    {
+        match TcpStream::connect_timeout(&addr, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => anyhow::Error::new(e).context(format!("connecting to {}:{}", host, port)),
+        None => anyhow!("{} has no addresses", host),
+    })
+}
+
+/// Makes a TLS connection to `host` that accepts whatever certificate it presents and returns it
+/// with the SHA256 fingerprint of the certificate. Checking the fingerprint is up to the caller.
+pub fn tls_connect(
+    host: &str,
+    port: u16,
+) -> anyhow::Result<(native_tls::TlsStream<TcpStream>, String)> {
+    let stream = connect(host, port)?;
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()?;
+    let tls_stream = connector
+        .connect(host, stream)
+        .map_err(|e| anyhow!("TLS handshake with {} failed: {}", host, e))?;
+    let certificate = tls_stream
+        .peer_certificate()?
+        .ok_or(anyhow!("{} did not present a certificate", host))?;
+    let fingerprint = hex::encode(&Sha256::digest(&certificate.to_der()?));
+    Ok((tls_stream, fingerprint))
+}
+
+/// Where the relay to `url` checking its certificate against `pinned` listens. It's started the
+/// first time it's asked for.
+fn relay(url: &Url, pinned: &str) -> anyhow::Result<SocketAddr> {
+    let mut relays = RELAYS.lock().expect("relays lock isn't poisoned");
+    if let Some((_, _, relay)) = relays
+        .iter()
+        .find(|(relay_url, relay_pin, _)| relay_url == url.as_str() && relay_pin == pinned)
+    {
+        return Ok(*relay);
+    }
+    let backend = Backend {
+        // IPv6 addresses are in brackets in urls
+        host: url
+            .host_str()
+            .ok_or(anyhow!("url {} missing host", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port: url.port_or_known_default().unwrap_or(443),
+        host_header: match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        },
+        pinned: pinned.to_string(),
+    };
+    let listener = TcpListener::bind(("127.0.0.1", 0)).context("starting the backend relay")?;
+    let relay = listener.local_addr()?;
+    thread::spawn(move || {
+        for local in listener.incoming().flatten() {
+            let backend = backend.clone();
+            thread::spawn(move || {
+                if let Err(e) = backend.relay_request(local) {
+                    tracing::error!("backend relay: {:#}", e);
+                }
+            });
+        }
+    });
+    relays.push((url.to_string(), pinned.to_string(), relay));
+    Ok(relay)
+}
+
+#[derive(Clone, Debug)]
+struct Backend {
+    host: String,
+    port: u16,
+    host_header: String,
+    pinned: String,
+}
+
+impl Backend {
+    /// Passes one request from `local` to the backend and the response back. Every request gets a
+    /// connection of its own (`Connection: close`) so every one of them has its certificate
+    /// checked.
+    fn relay_request(&self, mut local: TcpStream) -> anyhow::Result<()> {
+        local.set_read_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(local.try_clone()?);
+        let mut head = vec![];
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                // the client closed a connection it didn't use
+                return Ok(());
+            }
+            let lower = line.to_ascii_lowercase();
+            if line.trim_end().is_empty() {
+                head.extend_from_slice(b"Connection: close\r\n\r\n");
+                break;
+            } else if lower.starts_with("host:") {
+                line = format!("Host: {}\r\n", self.host_header);
+            } else if lower.starts_with("connection:") {
+                continue;
+            } else if lower.starts_with("transfer-encoding:") {
+                return Err(anyhow!(
+                    "requests with a Transfer-Encoding can't be relayed"
+                ));
+            } else if let Some(length) = lower.strip_prefix("content-length:") {
+                content_length = length
+                    .trim()
+                    .parse::<usize>()
+                    .context("invalid Content-Length")?;
+            }
+            head.extend_from_slice(line.as_bytes());
+        }
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body)?;
+
+        let (mut backend, fingerprint) = tls_connect(&self.host, self.port)?;
+        if fingerprint != self.pinned {
+            // nothing has been sent to it
+            write!(
+                local,
+                "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )?;
+            return Err(anyhow!(
+                "the certificate of {} has fingerprint {} which does not match the pinned fingerprint {}. Someone may be intercepting your connection!",
+                self.host,
+                fingerprint,
+                self.pinned
+            ));
+        }
+        backend.write_all(&head)?;
+        backend.write_all(&body)?;
+        backend.flush()?;
+        match io::copy(&mut backend, &mut local) {
+            // servers often close without a TLS close_notify once they've answered
+            Err(e) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_fingerprints() {
+        assert_eq!(normalize_fingerprint("AB:cd:01"), "abcd01");
+        assert_eq!(normalize_fingerprint(" abcd01\n"), "abcd01");
+    }
+
+    #[test]
+    fn unpinned_backends_are_reached_directly() {
+        assert_eq!(
+            base_url("https://mempool.space/api", None).unwrap(),
+            "https://mempool.space/api"
+        );
+        assert!(base_url("http://localhost:3000", Some("abcd")).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Extension points for third parties.
+//!
+//! There are two kinds of plugins:
+//!
+//! 1. External commands: any executable named `gun-<name>` on your `$PATH` can be run as `gun
+//! <name>`. It gets the wallet directory through the `GUN_DIR` environment variable.
+//! 2. Signer plugins: in-process implementations of [`SignerPlugin`] registered with
+//! [`register_signer_plugin`]. Plugins named in the `signers` list of the config are asked for a
+//! signer when the wallet is loaded.
+use crate::config::Config;
+use anyhow::anyhow;
+use bdk::{
+    signer::{Signer, SignerOrdering},
+    KeychainKind,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+pub const EXTERNAL_COMMAND_PREFIX: &str = "gun-";
+
+/// A source of signers that can be plugged into the wallet.
+pub trait SignerPlugin: Send + Sync {
+    /// The name used to refer to this plugin in the config.
+    fn name(&self) -> &str;
+    /// Create the signers this plugin provides for the wallet at `wallet_dir`.
+    fn signers(&self, wallet_dir: &Path, config: &Config) -> anyhow::Result<Vec<PluginSigner>>;
+}
+
+/// A signer provided by a plugin along with where it should be added to the wallet.
+pub struct PluginSigner {
+    pub keychain: KeychainKind,
+    pub ordering: SignerOrdering,
+    pub signer: Arc<dyn Signer>,
+}
+
+static SIGNER_PLUGINS: Mutex<Vec<Arc<dyn SignerPlugin>>> = Mutex::new(Vec::new());
+
+/// Make a signer plugin available to be selected in the config.
+pub fn register_signer_plugin(plugin: impl SignerPlugin + 'static) {
+    SIGNER_PLUGINS.lock().unwrap().push(Arc::new(plugin));
+}
+
+pub fn get_signer_plugin(name: &str) -> Option<Arc<dyn SignerPlugin>> {
+    SIGNER_PLUGINS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|plugin| plugin.name() == name)
+        .cloned()
+}
+
+/// Collects the signers from each of the plugins selected in the config.
+pub fn load_plugin_signers(
+    wallet_dir: &Path,
+    config: &Config,
+) -> anyhow::Result<Vec<PluginSigner>> {
+    let mut signers = vec![];
+    for name in &config.signers {
+        let plugin = get_signer_plugin(name).ok_or(anyhow!(
+            "signer plugin '{}' is in the config but isn't available in this build of gun",
+            name
+        ))?;
+        signers.extend(plugin.signers(wallet_dir, config)?);
+    }
+    Ok(signers)
+}
+
+/// Looks for `gun-<name>` in `$PATH`.
+pub fn find_external_command(name: &str) -> Option<PathBuf> {
+    let file_name = format!(
+        "{}{}{}",
+        EXTERNAL_COMMAND_PREFIX,
+        name,
+        std::env::consts::EXE_SUFFIX
+    );
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(&file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Runs an external command returning its exit code.
+pub fn run_external_command(wallet_dir: &Path, args: &[String]) -> anyhow::Result<i32> {
+    let name = args.get(0).ok_or(anyhow!("missing command name"))?;
+    let command = find_external_command(name).ok_or(anyhow!(
+        "'{}' is not a gun command and no '{}{}' was found in $PATH",
+        name,
+        EXTERNAL_COMMAND_PREFIX,
+        name
+    ))?;
+    let status = std::process::Command::new(&command)
+        .args(&args[1..])
+        .env("GUN_DIR", wallet_dir)
+        .status()
+        .map_err(|e| anyhow!("failed to run {}: {}", command.display(), e))?;
+
+    Ok(status.code().unwrap_or(1))
+}
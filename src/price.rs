@@ -0,0 +1,167 @@
+//! The price of bitcoin in a fiat currency so amounts can also be shown in fiat.
+//!
+//! There is no default source. The `price-source` config setting names a url that returns JSON and
+//! where to find the price in it e.g.
+//!
+//! ```json
+//! "price-source": {
+//!     "url": "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=usd",
+//!     "pointer": "/bitcoin/usd",
+//!     "currency": "USD"
+//! }
+//! ```
+//!
+//! With a source set the price is recorded as the [`CostBasis`] of each bet when it's funded and of
+//! each send when it's broadcast.
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::Amount;
+use olivia_core::chrono::NaiveDateTime;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PriceSource {
+    pub url: String,
+    /// A JSON pointer (RFC 6901) to the price of one bitcoin in the response
+    pub pointer: String,
+    pub currency: String,
+}
+
+impl PriceSource {
+    /// Gets the current price of one bitcoin.
+    #[cfg(not(feature = "price"))]
+    pub fn fetch(&self) -> anyhow::Result<Price> {
+        Err(crate::features::not_compiled_in(
+            "price",
+            "get the price of bitcoin",
+        ))
+    }
+
+    /// Gets the current price of one bitcoin.
+    #[cfg(feature = "price")]
+    pub fn fetch(&self) -> anyhow::Result<Price> {
+        let client = crate::endpoint::http_client()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let response = client
+            .get(&self.url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| format!("fetching price from {}", self.url))?;
+        let response: serde_json::Value = serde_json::from_str(&response)
+            .with_context(|| format!("price source {} didn't return JSON", self.url))?;
+        let value = response.pointer(&self.pointer).ok_or(anyhow!(
+            "price source response has nothing at {}",
+            self.pointer
+        ))?;
+        // some sources return the price as a string
+        let per_btc = value
+            .as_f64()
+            .or_else(|| value.as_str().and_then(|value| value.parse().ok()))
+            .ok_or(anyhow!(
+                "price source returned {} which isn't a number",
+                value
+            ))?;
+        Ok(Price {
+            per_btc,
+            currency: self.currency.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Price {
+    pub per_btc: f64,
+    pub currency: String,
+}
+
+impl Price {
+    pub fn to_fiat(&self, amount: Amount) -> f64 {
+        amount.as_btc() * self.per_btc
+    }
+
+    /// Formats `amount` in fiat e.g. `1.23 USD`.
+    pub fn format(&self, amount: Amount) -> String {
+        format!("{:.2} {}", self.to_fiat(amount), self.currency)
+    }
+}
+
+/// The price when a bet was funded or a send was made so gains and losses can be worked out in
+/// fiat later.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CostBasis {
+    #[serde(flatten)]
+    pub price: Price,
+    pub recorded_at: NaiveDateTime,
+}
+
+impl CostBasis {
+    /// What `amount` was worth when the cost basis was recorded.
+    pub fn value(&self, amount: Amount) -> f64 {
+        self.price.to_fiat(amount)
+    }
+
+    /// How much more (or less if negative) `amount` is worth at `now` than when the cost basis was
+    /// recorded. `None` if the prices are in different currencies.
+    pub fn gain(&self, amount: Amount, now: &Price) -> Option<f64> {
+        if now.currency != self.price.currency {
+            return None;
+        }
+        Some(now.to_fiat(amount) - self.value(amount))
+    }
+}
+
+/// e.g. `50000.00 USD/BTC`
+impl std::fmt::Display for CostBasis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2} {}/BTC", self.price.per_btc, self.price.currency)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn convert_to_fiat() {
+        let price = Price {
+            per_btc: 50_000.0,
+            currency: "USD".into(),
+        };
+        assert_eq!(price.format(Amount::from_sat(2_000)), "1.00 USD");
+        assert_eq!(price.format(Amount::ONE_BTC), "50000.00 USD");
+    }
+
+    #[test]
+    fn gain_since_cost_basis() {
+        let cost_basis = CostBasis {
+            price: Price {
+                per_btc: 40_000.0,
+                currency: "USD".into(),
+            },
+            recorded_at: NaiveDateTime::from_timestamp(1_600_000_000, 0),
+        };
+        let now = Price {
+            per_btc: 50_000.0,
+            currency: "USD".into(),
+        };
+        assert_eq!(cost_basis.to_string(), "40000.00 USD/BTC");
+        assert_eq!(
+            cost_basis.gain(Amount::from_sat(1_000_000), &now),
+            Some(100.0)
+        );
+        assert_eq!(
+            cost_basis.gain(
+                Amount::ONE_BTC,
+                &Price {
+                    per_btc: 40_000.0,
+                    currency: "EUR".into()
+                }
+            ),
+            None
+        );
+    }
+}
@@ -1,5 +1,10 @@
+use anyhow::anyhow;
 use bdk::{
-    bitcoin::{util::psbt::PartiallySignedTransaction as Psbt, Amount},
+    bitcoin::{
+        secp256k1::{self, All, Message, Secp256k1, SecretKey},
+        util::{bip143::SigHashCache, psbt::PartiallySignedTransaction as Psbt},
+        Amount, PublicKey, SigHashType, Transaction, TxOut,
+    },
     FeeRate,
 };
 
@@ -22,3 +27,238 @@ impl PsbtFeeRate for Psbt {
         (Amount::from_sat(fee), feerate)
     }
 }
+
+/// Logs what went into a transaction we just built.
+pub fn log_built_tx(psbt: &Psbt) {
+    let tx = &psbt.global.unsigned_tx;
+    tracing::debug!(
+        txid = %tx.txid(),
+        inputs = ?tx.input.iter().map(|txin| txin.previous_output).collect::<Vec<_>>(),
+        outputs = tx.output.len(),
+        "built transaction"
+    );
+}
+
+/// Removes change outputs worth less than `threshold` so that their value goes to the fee instead.
+/// This must be done before the PSBT is signed.
+///
+/// Returns how much was added to the fee.
+pub fn fold_dust_change(
+    psbt: &mut Psbt,
+    threshold: Amount,
+    mut is_change: impl FnMut(&TxOut) -> bool,
+) -> Amount {
+    let mut donated = Amount::ZERO;
+    let mut i = 0;
+    while i < psbt.global.unsigned_tx.output.len() {
+        let txout = &psbt.global.unsigned_tx.output[i];
+        let is_last = psbt.global.unsigned_tx.output.len() == 1;
+        if !is_last && txout.value < threshold.as_sat() && is_change(txout) {
+            donated += Amount::from_sat(txout.value);
+            psbt.global.unsigned_tx.output.remove(i);
+            psbt.outputs.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    donated
+}
+
+/// Signs the input at each index in `keys` with its key (`SIGHASH_ALL`). The inputs have to be
+/// segwit ones with a `witness_utxo` and `witness_script`. The signatures are put in
+/// `partial_sigs` for finalizing.
+///
+/// Unlike signing with a wallet each input is only looked at by the key that signs it and the
+/// sighash midstate is worked out once per thread rather than once per input. Transactions with
+/// many inputs (e.g. claims of lots of bets) are signed on several threads.
+pub fn sign_inputs(
+    psbt: &mut Psbt,
+    keys: &[(usize, SecretKey)],
+    secp: &Secp256k1<All>,
+) -> anyhow::Result<()> {
+    let tx = &psbt.global.unsigned_tx;
+    let inputs = &psbt.inputs;
+    let signatures = crate::parallel::map_with(
+        keys,
+        || SigHashCache::new(tx),
+        |sighash_cache, _, (index, secret_key)| -> anyhow::Result<_> {
+            let input = inputs
+                .get(*index)
+                .ok_or(anyhow!("there is no input {} to sign", index))?;
+            let value = input
+                .witness_utxo
+                .as_ref()
+                .ok_or(anyhow!("input {} has no witness_utxo", index))?
+                .value;
+            let witness_script = input
+                .witness_script
+                .as_ref()
+                .ok_or(anyhow!("input {} has no witness_script", index))?;
+            let sighash =
+                sighash_cache.signature_hash(*index, witness_script, value, SigHashType::All);
+            let signature = secp.sign(
+                &Message::from_slice(&sighash[..]).expect("sighash is 32 bytes"),
+                secret_key,
+            );
+            let mut signature = signature.serialize_der().to_vec();
+            signature.push(SigHashType::All.as_u32() as u8);
+            let public_key = PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(secp, secret_key),
+            };
+            Ok((*index, public_key, signature))
+        },
+    );
+    for signature in signatures {
+        let (index, public_key, signature) = signature?;
+        psbt.inputs[index]
+            .partial_sigs
+            .insert(public_key, signature);
+    }
+    Ok(())
+}
+
+/// The nSequence BDK gives inputs when RBF is enabled.
+pub const RBF_SEQUENCE: u32 = 0xFFFF_FFFD;
+/// The nSequence BDK gives inputs when RBF isn't enabled (and there's no lock time).
+pub const FINAL_SEQUENCE: u32 = 0xFFFF_FFFF;
+
+/// Whether `tx` signals that it can be replaced (BIP125).
+pub fn signals_rbf(tx: &Transaction) -> bool {
+    tx.input.iter().any(|txin| txin.sequence < 0xFFFF_FFFE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{
+        blockdata::{opcodes, script::Builder},
+        OutPoint, Script, Transaction, TxIn,
+    };
+
+    #[test]
+    fn fold_only_small_change() {
+        let change = Script::from(vec![0x51]);
+        let recipient = Script::from(vec![0x52]);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    value: 600,
+                    script_pubkey: recipient.clone(),
+                },
+                TxOut {
+                    value: 700,
+                    script_pubkey: change.clone(),
+                },
+                TxOut {
+                    value: 5_000,
+                    script_pubkey: change.clone(),
+                },
+            ],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        let donated = fold_dust_change(&mut psbt, Amount::from_sat(1_000), |txout| {
+            txout.script_pubkey == change
+        });
+        assert_eq!(donated, Amount::from_sat(700));
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(
+            psbt.global
+                .unsigned_tx
+                .output
+                .iter()
+                .map(|txout| txout.value)
+                .collect::<Vec<_>>(),
+            vec![600, 5_000]
+        );
+    }
+
+    #[test]
+    fn signed_inputs_verify() {
+        let secp = Secp256k1::new();
+        let keys = (1..=20u8)
+            .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+            .collect::<Vec<_>>();
+        let witness_scripts = keys
+            .iter()
+            .map(|secret_key| {
+                let public_key = PublicKey {
+                    compressed: true,
+                    key: secp256k1::PublicKey::from_secret_key(&secp, secret_key),
+                };
+                Builder::new()
+                    .push_key(&public_key)
+                    .push_opcode(opcodes::all::OP_CHECKSIG)
+                    .into_script()
+            })
+            .collect::<Vec<_>>();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..keys.len())
+                .map(|i| TxIn {
+                    previous_output: OutPoint::new(Default::default(), i as u32),
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: Script::from(vec![0x51]),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, witness_script) in psbt.inputs.iter_mut().zip(&witness_scripts) {
+            input.witness_utxo = Some(TxOut {
+                value: 10_000,
+                script_pubkey: witness_script.to_v0_p2wsh(),
+            });
+            input.witness_script = Some(witness_script.clone());
+        }
+        let original = psbt.clone();
+        let to_sign = keys.iter().cloned().enumerate().collect::<Vec<_>>();
+        sign_inputs(&mut psbt, &to_sign, &secp).unwrap();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| input.partial_sigs.len() == 1));
+        crate::external_signer::verify_signed_psbt(&original, &psbt, &secp).unwrap();
+
+        // the signatures commit to the transaction
+        let mut changed = original.clone();
+        changed.global.unsigned_tx.output[0].value = 2_000;
+        let mut signed_changed = changed.clone();
+        sign_inputs(&mut signed_changed, &to_sign, &secp).unwrap();
+        signed_changed.global.unsigned_tx = psbt.global.unsigned_tx.clone();
+        assert!(
+            crate::external_signer::verify_signed_psbt(&original, &signed_changed, &secp).is_err()
+        );
+        assert!(sign_inputs(&mut changed, &[(keys.len(), keys[0])], &secp).is_err());
+
+        // a valid signature that doesn't commit to the outputs isn't good enough
+        let mut signed_none = original.clone();
+        let sighash = SigHashCache::new(&original.global.unsigned_tx).signature_hash(
+            0,
+            &witness_scripts[0],
+            10_000,
+            SigHashType::None,
+        );
+        let mut signature = secp
+            .sign(&Message::from_slice(&sighash[..]).unwrap(), &keys[0])
+            .serialize_der()
+            .to_vec();
+        signature.push(SigHashType::None.as_u32() as u8);
+        signed_none.inputs[0].partial_sigs.insert(
+            PublicKey {
+                compressed: true,
+                key: secp256k1::PublicKey::from_secret_key(&secp, &keys[0]),
+            },
+            signature,
+        );
+        assert!(
+            crate::external_signer::verify_signed_psbt(&original, &signed_none, &secp).is_err()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Rules a PSBT that gun didn't build has to follow before gun will sign it.
+//!
+//! The rules are read from `psbt-policy.json` in the wallet directory e.g.
+//!
+//! ```json
+//! {
+//!   "allowed-destinations": ["bc1q..."],
+//!   "max-fee": 20000,
+//!   "max-feerate": 50.0,
+//!   "allow-foreign-inputs": false
+//! }
+//! ```
+//!
+//! Without the file only the default applies: every input has to be the wallet's.
+use crate::package;
+use anyhow::Context;
+use bdk::bitcoin::{
+    util::psbt::PartiallySignedTransaction as Psbt, Address, Amount, Script, TxOut,
+};
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PsbtPolicy {
+    /// Outputs that aren't the wallet's have to pay one of these. Anywhere is allowed if it isn't
+    /// set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_destinations: Option<Vec<Address>>,
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_fee: Option<Amount>,
+    /// In sats per vbyte
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_feerate: Option<f32>,
+    /// Sign even if some inputs belong to someone else (e.g. a coinjoin or payjoin)
+    #[serde(default)]
+    pub allow_foreign_inputs: bool,
+}
+
+impl PsbtPolicy {
+    pub fn path(wallet_dir: &Path) -> std::path::PathBuf {
+        wallet_dir.join("psbt-policy.json")
+    }
+
+    pub fn load(wallet_dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(wallet_dir);
+        match crate::ephemeral::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("reading {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    /// Everything about `psbt` that breaks the policy. `is_mine` says whether a script belongs to
+    /// the wallet.
+    pub fn violations(&self, psbt: &Psbt, is_mine: impl Fn(&Script) -> bool) -> Vec<String> {
+        let tx = &psbt.global.unsigned_tx;
+        let mut violations = vec![];
+        let spent = psbt
+            .inputs
+            .iter()
+            .zip(&tx.input)
+            .map(|(input, txin)| {
+                input.witness_utxo.clone().or_else(|| {
+                    input
+                        .non_witness_utxo
+                        .as_ref()
+                        .and_then(|prev_tx| prev_tx.output.get(txin.previous_output.vout as usize))
+                        .cloned()
+                })
+            })
+            .collect::<Vec<Option<TxOut>>>();
+
+        if !self.allow_foreign_inputs {
+            for (txin, spent) in tx.input.iter().zip(&spent) {
+                match spent {
+                    Some(txout) if is_mine(&txout.script_pubkey) => {}
+                    Some(_) => violations
+                        .push(format!("input {} isn't the wallet's", txin.previous_output)),
+                    None => violations.push(format!(
+                        "input {} doesn't say what it spends so it can't be checked",
+                        txin.previous_output
+                    )),
+                }
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_destinations {
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if is_mine(&txout.script_pubkey)
+                    || allowed
+                        .iter()
+                        .any(|address| address.script_pubkey() == txout.script_pubkey)
+                {
+                    continue;
+                }
+                violations.push(format!(
+                    "output {} pays {} to a destination that isn't allowed ({})",
+                    vout,
+                    Amount::from_sat(txout.value),
+                    txout.script_pubkey.asm()
+                ));
+            }
+        }
+
+        if self.max_fee.is_some() || self.max_feerate.is_some() {
+            let input_value = spent
+                .iter()
+                .map(|txout| txout.as_ref().map(|txout| txout.value))
+                .sum::<Option<u64>>();
+            let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+            match input_value.and_then(|input_value| input_value.checked_sub(output_value)) {
+                Some(fee) => {
+                    let fee = Amount::from_sat(fee);
+                    let feerate =
+                        fee.as_sat() as f32 / package::estimated_vsize(psbt, |_| None) as f32;
+                    if let Some(max_fee) = self.max_fee.filter(|max_fee| fee > *max_fee) {
+                        violations.push(format!("the fee of {} is more than {}", fee, max_fee));
+                    }
+                    if let Some(max_feerate) = self
+                        .max_feerate
+                        .filter(|max_feerate| feerate > *max_feerate)
+                    {
+                        violations.push(format!(
+                            "the fee rate of {:.1} sat/vb is more than {:.1} sat/vb",
+                            feerate, max_feerate
+                        ));
+                    }
+                }
+                None => violations
+                    .push("the fee can't be worked out so it can't be checked".to_string()),
+            }
+        }
+
+        violations
+    }
+
+    /// Returns an error listing everything wrong with `psbt` if it breaks the policy.
+    pub fn check(&self, psbt: &Psbt, is_mine: impl Fn(&Script) -> bool) -> anyhow::Result<()> {
+        let violations = self.violations(psbt, is_mine);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(crate::exit_code::ErrorKind::Policy.error(format!(
+            "refusing to sign {} because it breaks the PSBT policy:\n  {}",
+            psbt.global.unsigned_tx.txid(),
+            violations.join("\n  ")
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{
+        hashes::Hash, util::psbt::Input, Network, OutPoint, Transaction, TxIn, WPubkeyHash,
+    };
+
+    fn script(n: u8) -> Script {
+        Script::new_v0_wpkh(&WPubkeyHash::from_slice(&[n; 20]).unwrap())
+    }
+
+    fn psbt(inputs: &[(u8, u64)], outputs: &[(u8, u64)]) -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..inputs.len())
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        vout: vout as u32,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|(owner, value)| TxOut {
+                    value: *value,
+                    script_pubkey: script(*owner),
+                })
+                .collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (input, (owner, value)) in psbt.inputs.iter_mut().zip(inputs) {
+            *input = Input {
+                witness_utxo: Some(TxOut {
+                    value: *value,
+                    script_pubkey: script(*owner),
+                }),
+                ..Default::default()
+            };
+        }
+        psbt
+    }
+
+    // scripts made from 0 are the wallet's
+    fn is_mine(script_pubkey: &Script) -> bool {
+        *script_pubkey == script(0)
+    }
+
+    #[test]
+    fn default_policy_only_refuses_foreign_inputs() {
+        let policy = PsbtPolicy::default();
+        assert!(policy
+            .violations(&psbt(&[(0, 100_000)], &[(1, 50_000), (0, 40_000)]), is_mine)
+            .is_empty());
+        let violations = policy.violations(
+            &psbt(&[(0, 100_000), (2, 100_000)], &[(1, 190_000)]),
+            is_mine,
+        );
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("isn't the wallet's"));
+
+        let mut missing_utxo = psbt(&[(0, 100_000)], &[(1, 90_000)]);
+        missing_utxo.inputs[0].witness_utxo = None;
+        assert_eq!(policy.violations(&missing_utxo, is_mine).len(), 1);
+    }
+
+    #[test]
+    fn destinations_and_fees() {
+        let allowed = Address::from_script(&script(1), Network::Regtest).unwrap();
+        let policy = PsbtPolicy {
+            allowed_destinations: Some(vec![allowed]),
+            max_fee: Some(Amount::from_sat(5_000)),
+            max_feerate: None,
+            allow_foreign_inputs: false,
+        };
+        // change back to the wallet is always fine
+        assert!(policy
+            .violations(&psbt(&[(0, 100_000)], &[(1, 50_000), (0, 46_000)]), is_mine)
+            .is_empty());
+        let violations =
+            policy.violations(&psbt(&[(0, 100_000)], &[(3, 50_000), (0, 40_000)]), is_mine);
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+        assert!(violations[0].contains("isn't allowed"));
+        assert!(violations[1].contains("the fee of"));
+
+        let policy = PsbtPolicy {
+            max_feerate: Some(10.0),
+            ..PsbtPolicy::default()
+        };
+        assert!(policy
+            .violations(&psbt(&[(0, 100_000)], &[(1, 99_000)]), is_mine)
+            .is_empty());
+        assert_eq!(
+            policy
+                .violations(&psbt(&[(0, 100_000)], &[(1, 90_000)]), is_mine)
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! QR codes drawn in the terminal so addresses, payment URIs and proposals can be scanned with a
+//! phone.
+//!
+//! By default light modules are drawn with full blocks which suits the usual light text on a dark
+//! background. On a light background use `invert`.
+use anyhow::anyhow;
+use bdk::bitcoin::{util::address::Payload, Address, Amount, Denomination};
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QrSize {
+    /// Two modules to a character (half blocks)
+    Small,
+    /// Each module two characters wide so it's square in most fonts
+    Large,
+}
+
+impl Default for QrSize {
+    fn default() -> Self {
+        QrSize::Small
+    }
+}
+
+impl FromStr for QrSize {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        match string {
+            "small" => Ok(QrSize::Small),
+            "large" => Ok(QrSize::Large),
+            _ => Err(anyhow!("{} isn't a QR size -- use small or large", string)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QrStyle {
+    pub size: QrSize,
+    /// Draw dark modules with blocks for terminals with a light background
+    pub invert: bool,
+}
+
+/// What to put in the QR code for `string`. Bech32 addresses (on their own or in a BIP21 URI) are
+/// upper cased because QR codes fit upper case letters and digits in much less space.
+pub fn qr_data(string: &str) -> String {
+    let is_bech32 = |address: &str| {
+        Address::from_str(address)
+            .map(|address| matches!(address.payload, Payload::WitnessProgram { .. }))
+            .unwrap_or(false)
+    };
+    let scheme = "bitcoin:";
+    if string
+        .get(..scheme.len())
+        .map(|start| start.eq_ignore_ascii_case(scheme))
+        .unwrap_or(false)
+    {
+        // the query is case sensitive (e.g. the label) so only the address is changed
+        let rest = &string[scheme.len()..];
+        let prefix_len = scheme.len() + rest.find('?').unwrap_or_else(|| rest.len());
+        if is_bech32(&string[scheme.len()..prefix_len]) {
+            return format!(
+                "{}{}",
+                string[..prefix_len].to_uppercase(),
+                &string[prefix_len..]
+            );
+        }
+        return string.to_string();
+    }
+    if is_bech32(string) {
+        return string.to_uppercase();
+    }
+    string.to_string()
+}
+
+/// A BIP21 URI paying `address`.
+pub fn bip21_uri(address: &Address, amount: Option<Amount>, label: Option<&str>) -> String {
+    let mut params = vec![];
+    if let Some(amount) = amount {
+        let btc = amount.to_string_in(Denomination::Bitcoin);
+        let btc = btc.trim_end_matches('0').trim_end_matches('.');
+        params.push(format!("amount={}", btc));
+    }
+    if let Some(label) = label {
+        params.push(format!("label={}", percent_encode(label)));
+    }
+    match params.is_empty() {
+        true => format!("bitcoin:{}", address),
+        false => format!("bitcoin:{}?{}", address, params.join("&")),
+    }
+}
+
+fn percent_encode(string: &str) -> String {
+    string
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Draws `string` (after [`qr_data`]) as a QR code for the terminal.
+pub fn render(string: &str, style: QrStyle) -> anyhow::Result<String> {
+    let code = QrCode::new(qr_data(string).as_bytes())
+        .map_err(|e| anyhow!("{} can't be put in a QR code: {}", shorten(string), e))?;
+    let (dark, light) = match style.invert {
+        false => (false, true),
+        true => (true, false),
+    };
+    Ok(match style.size {
+        QrSize::Small => {
+            let pixel = |filled: bool| match filled {
+                true => Dense1x2::Dark,
+                false => Dense1x2::Light,
+            };
+            code.render::<Dense1x2>()
+                .dark_color(pixel(dark))
+                .light_color(pixel(light))
+                .quiet_zone(true)
+                .build()
+        }
+        QrSize::Large => {
+            let pixel = |filled: bool| match filled {
+                true => '█',
+                false => ' ',
+            };
+            code.render::<char>()
+                .dark_color(pixel(dark))
+                .light_color(pixel(light))
+                .module_dimensions(2, 1)
+                .quiet_zone(true)
+                .build()
+        }
+    })
+}
+
+fn shorten(string: &str) -> String {
+    match string.char_indices().nth(20) {
+        Some((i, _)) => format!("{}...", &string[..i]),
+        None => string.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    #[test]
+    fn bech32_is_upper_cased() {
+        assert_eq!(qr_data(ADDRESS), ADDRESS.to_uppercase());
+        assert_eq!(
+            qr_data(&format!("bitcoin:{}?label=Rent", ADDRESS)),
+            format!("BITCOIN:{}?label=Rent", ADDRESS.to_uppercase())
+        );
+        let legacy = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+        assert_eq!(qr_data(legacy), legacy);
+        assert_eq!(qr_data("not an address"), "not an address");
+    }
+
+    #[test]
+    fn bip21() {
+        let address = Address::from_str(ADDRESS).unwrap();
+        assert_eq!(
+            bip21_uri(&address, None, None),
+            format!("bitcoin:{}", ADDRESS)
+        );
+        assert_eq!(
+            bip21_uri(
+                &address,
+                Some(Amount::from_sat(150_000)),
+                Some("rent & food")
+            ),
+            format!("bitcoin:{}?amount=0.0015&label=rent%20%26%20food", ADDRESS)
+        );
+        assert_eq!(
+            bip21_uri(&address, Some(Amount::from_sat(100_000_000)), None),
+            format!("bitcoin:{}?amount=1", ADDRESS)
+        );
+    }
+
+    #[test]
+    fn sizes_and_invert() {
+        let small = render(ADDRESS, QrStyle::default()).unwrap();
+        let large = render(
+            ADDRESS,
+            QrStyle {
+                size: QrSize::Large,
+                invert: false,
+            },
+        )
+        .unwrap();
+        let width = |qr: &str| qr.lines().next().unwrap().chars().count();
+        assert_eq!(width(&large), width(&small) * 2);
+        assert!(large.lines().count() > small.lines().count());
+        let inverted = render(
+            ADDRESS,
+            QrStyle {
+                size: QrSize::Large,
+                invert: true,
+            },
+        )
+        .unwrap();
+        // the quiet zone is light so it's blocks unless inverted
+        assert!(large.starts_with('█'));
+        assert!(inverted.starts_with(' '));
+    }
+}
@@ -0,0 +1,75 @@
+//! Read-only mode (`--read-only`, `GUN_READ_ONLY` or `read-only` in the config) for wallets on
+//! shared machines and in demos.
+//!
+//! Nothing is signed or broadcast in it. Rather than each command checking for itself, the wallet
+//! is loaded with a [`ReadOnlySigner`] that refuses before any of the real signers get a turn and
+//! every broadcast goes through [`broadcast`]. Commands that only look at things, decode strings or
+//! do dry runs work as usual.
+use anyhow::anyhow;
+use bdk::{
+    bitcoin::{
+        hashes::{hash160, Hash},
+        secp256k1::{All, Secp256k1},
+        util::psbt::PartiallySignedTransaction as Psbt,
+        Transaction,
+    },
+    blockchain::Broadcast,
+    signer::{Signer, SignerError, SignerId},
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    READ_ONLY.store(true, Ordering::Relaxed)
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Fails if we're in read-only mode. `action` is what would have been done e.g. `broadcast`.
+pub fn check(action: &str) -> anyhow::Result<()> {
+    if is_enabled() {
+        return Err(crate::exit_code::ErrorKind::Locked.error(format!(
+            "can't {} because gun is in read-only mode (see --read-only and `read-only` in the config)",
+            action
+        )));
+    }
+    Ok(())
+}
+
+/// Broadcasts `tx` unless we're in read-only mode. Everything that broadcasts goes through here.
+pub fn broadcast(blockchain: &impl Broadcast, tx: Transaction) -> anyhow::Result<()> {
+    check(&format!("broadcast {}", tx.txid()))?;
+    Broadcast::broadcast(blockchain, tx)?;
+    Ok(())
+}
+
+/// A signer that goes first and refuses to let the wallet sign anything.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadOnlySigner;
+
+impl Signer for ReadOnlySigner {
+    fn sign(
+        &self,
+        psbt: &mut Psbt,
+        _input_index: Option<usize>,
+        _secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        // SignerError has no variant that can carry our error so print it here
+        tracing::error!(
+            "refusing to sign {} in read-only mode",
+            psbt.global.unsigned_tx.txid()
+        );
+        Err(SignerError::UserCanceled)
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
+        SignerId::PkHash(hash160::Hash::hash(b"gun-read-only"))
+    }
+}
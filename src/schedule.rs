@@ -0,0 +1,194 @@
+//! Recurring payments made by `gun schedule run`.
+//!
+//! A [`ScheduledPayment`] pays the same amount to the same address every [`Interval`]. Due dates
+//! are always worked out from when the schedule started rather than from the last payment so a
+//! payment on the 31st stays on the last day of the month instead of drifting to the 28th after
+//! February.
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{Address, Amount, Txid};
+use olivia_core::chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// How often a scheduled payment is made.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Interval {
+    Hours(u32),
+    Days(u32),
+    Weeks(u32),
+    /// Calendar months. The day is moved back to the end of shorter months.
+    Months(u32),
+}
+
+impl Interval {
+    /// When the `n`th payment after the one at `start` is due.
+    pub fn nth_after(&self, start: NaiveDateTime, n: u32) -> NaiveDateTime {
+        match *self {
+            Interval::Hours(hours) => start + Duration::hours(hours as i64 * n as i64),
+            Interval::Days(days) => start + Duration::days(days as i64 * n as i64),
+            Interval::Weeks(weeks) => start + Duration::weeks(weeks as i64 * n as i64),
+            Interval::Months(months) => add_months(start, months * n),
+        }
+    }
+}
+
+fn add_months(time: NaiveDateTime, months: u32) -> NaiveDateTime {
+    let month0 = time.year() * 12 + time.month0() as i32 + months as i32;
+    let (year, month) = (month0.div_euclid(12), month0.rem_euclid(12) as u32 + 1);
+    let last_day = (28..=31)
+        .rev()
+        .find(|day| NaiveDate::from_ymd_opt(year, month, *day).is_some())
+        .expect("every month has at least 28 days");
+    NaiveDate::from_ymd(year, month, time.day().min(last_day)).and_time(time.time())
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(interval: &str) -> Result<Self, Self::Err> {
+        let split = interval
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or(anyhow!("{} is missing a unit (h, d, w or m)", interval))?;
+        let (n, unit) = interval.split_at(split);
+        let n = n
+            .parse::<u32>()
+            .with_context(|| format!("{} is not a valid interval", interval))?;
+        if n == 0 {
+            return Err(anyhow!("the interval can't be zero"));
+        }
+        Ok(match unit {
+            "h" => Interval::Hours(n),
+            "d" => Interval::Days(n),
+            "w" => Interval::Weeks(n),
+            "m" => Interval::Months(n),
+            unit => {
+                return Err(anyhow!(
+                    "{} is not a unit of time -- use h (hours), d (days), w (weeks) or m (months)",
+                    unit
+                ))
+            }
+        })
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interval::Hours(n) => write!(f, "{}h", n),
+            Interval::Days(n) => write!(f, "{}d", n),
+            Interval::Weeks(n) => write!(f, "{}w", n),
+            Interval::Months(n) => write!(f, "{}m", n),
+        }
+    }
+}
+
+impl TryFrom<String> for Interval {
+    type Error = anyhow::Error;
+
+    fn try_from(interval: String) -> Result<Self, Self::Error> {
+        Interval::from_str(&interval)
+    }
+}
+
+impl From<Interval> for String {
+    fn from(interval: Interval) -> Self {
+        interval.to_string()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ScheduledPayment {
+    pub address: Address,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+    pub every: Interval,
+    /// The fee as it would be given to `--fee` e.g. `in-blocks:12`
+    pub fee: String,
+    /// Payments below this are signed and broadcast without asking. The rest wait in the approval
+    /// queue.
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub auto_below: Amount,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// When the first payment was due
+    pub starts_at: NaiveDateTime,
+    /// How many due dates have passed (paid, queued or skipped)
+    pub payments: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_txid: Option<Txid>,
+}
+
+impl ScheduledPayment {
+    pub fn next_due(&self) -> NaiveDateTime {
+        self.every.nth_after(self.starts_at, self.payments)
+    }
+
+    /// Moves on to the first due date after `now` and returns how many were missed on the way
+    /// (not counting the one being paid now).
+    pub fn advance(&mut self, now: NaiveDateTime) -> u32 {
+        let mut missed = 0;
+        self.payments += 1;
+        while self.next_due() <= now {
+            self.payments += 1;
+            missed += 1;
+        }
+        missed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{Network, Script};
+
+    fn time(year: i32, month: u32, day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(year, month, day).and_hms(9, 30, 0)
+    }
+
+    #[test]
+    fn months_stay_at_the_end_of_the_month() {
+        let every = Interval::from_str("1m").unwrap();
+        let start = time(2023, 1, 31);
+        assert_eq!(every.nth_after(start, 1), time(2023, 2, 28));
+        assert_eq!(every.nth_after(start, 2), time(2023, 3, 31));
+        assert_eq!(every.nth_after(start, 13), time(2024, 2, 29));
+        assert_eq!(
+            Interval::Months(3).nth_after(time(2023, 11, 30), 1),
+            time(2024, 2, 29)
+        );
+        assert_eq!(
+            Interval::from_str("2w").unwrap().nth_after(start, 1),
+            time(2023, 2, 14)
+        );
+    }
+
+    #[test]
+    fn parse_intervals() {
+        for interval in ["12h", "1d", "2w", "1m"] {
+            assert_eq!(Interval::from_str(interval).unwrap().to_string(), interval);
+        }
+        assert!(Interval::from_str("0d").is_err());
+        assert!(Interval::from_str("5").is_err());
+        assert!(Interval::from_str("1y").is_err());
+    }
+
+    #[test]
+    fn missed_payments_are_skipped() {
+        let mut payment = ScheduledPayment {
+            address: Address::p2wsh(&Script::new(), Network::Regtest),
+            amount: Amount::from_sat(10_000),
+            every: Interval::Days(1),
+            fee: "in-blocks:12".into(),
+            auto_below: Amount::ZERO,
+            memo: None,
+            starts_at: time(2023, 1, 1),
+            payments: 0,
+            last_txid: None,
+        };
+        assert_eq!(payment.advance(time(2023, 1, 1)), 0);
+        assert_eq!(payment.next_due(), time(2023, 1, 2));
+        assert_eq!(payment.advance(time(2023, 1, 5)), 3);
+        assert_eq!(payment.next_due(), time(2023, 1, 6));
+    }
+}
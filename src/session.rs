@@ -0,0 +1,440 @@
+//! Spending sessions. With a `spending-lock` in the config nothing can be signed until `gun unlock`
+//! has been given the spending passphrase. Unlocking starts an agent process that listens on
+//! `agent.sock` in the wallet directory until the session times out or `gun lock` ends it. The
+//! agent is handed the passphrase on stdin and checks it itself before it listens. Signing, by the
+//! wallet or of claims, asks the agent whether the session is still open.
+//!
+//! The seed words are encrypted under the passphrase in `seed.locked` (see [`seal_seed_words`])
+//! instead of being kept in `seed.txt`. The agent decrypts them when it starts and hands them to
+//! the commands that load the wallet over the socket so while the wallet is locked nothing that
+//! needs the wallet's keys works, not even `gun balance`. The agent's copy is zeroized when the
+//! session ends. Whether the seed words are locked goes by whether `seed.locked` is there, not by
+//! the config, so taking the spending lock out of the config doesn't get around it. A salted PBKDF2
+//! hash of the passphrase is kept in the config too (like approvers' passphrases, see
+//! [`crate::approval`]) to check it before anything is decrypted.
+//!
+//! A spending lock can also have a decoy: a second, small wallet with a passphrase of its own.
+//! Unlocking with the decoy passphrase opens a session for the decoy and every command then runs
+//! against the decoy's directory instead (see [`select_wallet_dir`]) while the real wallet stays
+//! locked. The decoy's seed words are locked with the decoy passphrase the same way so the agent
+//! of a decoy session only ever has the decoy's seed words and can't hand over the real ones. Nothing in the config says whether there's a decoy. Its directory is named after a
+//! PBKDF2 of the decoy passphrase (see [`SpendingLock::decoy_wallet_dir`]) and the agent tells the
+//! other commands about the decoy session over the socket rather than on its command line.
+use crate::{
+    backup::{
+        check_passphrase, hash_passphrase, passphrase_salt, pbkdf2_sha512, BackupKeys,
+        PASSPHRASE_ROUNDS,
+    },
+    chrono::{Duration, NaiveDateTime},
+    hex,
+};
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{
+        hashes::{hash160, Hash},
+        secp256k1::{All, Secp256k1},
+        util::psbt::PartiallySignedTransaction as Psbt,
+    },
+    signer::{Signer, SignerError, SignerId},
+};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+pub const SPENDING_PASSPHRASE_ENV: &str = "GUN_SPENDING_PASSPHRASE";
+pub const DEFAULT_SESSION: &str = "15m";
+/// Where a wallet with a spending lock keeps its encrypted seed words
+pub const LOCKED_SEED_FILE: &str = "seed.locked";
+const LOCKED_SEED_MAGIC: &[u8; 7] = b"gunseed";
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SpendingLock {
+    /// hex
+    pub salt: String,
+    /// hex of the PBKDF2-HMAC-SHA512 of the passphrase
+    pub passphrase_hash: String,
+    /// The longest `gun unlock --for` can be in minutes
+    #[serde(default = "default_max_session_minutes")]
+    pub max_session_minutes: u32,
+    /// hex. Every spending lock has one whether or not there's a decoy.
+    #[serde(default)]
+    pub decoy_salt: String,
+}
+
+fn default_max_session_minutes() -> u32 {
+    8 * 60
+}
+
+impl SpendingLock {
+    pub fn new(passphrase: &str) -> Self {
+        let (salt, passphrase_hash) = hash_passphrase(passphrase);
+        Self {
+            salt,
+            passphrase_hash,
+            max_session_minutes: default_max_session_minutes(),
+            decoy_salt: hex::encode(&passphrase_salt()),
+        }
+    }
+
+    pub fn has_passphrase(&self, passphrase: &str) -> bool {
+        check_passphrase(passphrase, &self.salt, &self.passphrase_hash)
+    }
+
+    /// Where the decoy wallet unlocked by `passphrase` is kept in the real wallet's directory. It
+    /// can only be found with the decoy passphrase.
+    pub fn decoy_wallet_dir(&self, wallet_dir: &Path, passphrase: &str) -> PathBuf {
+        let salt = hex::decode(&self.decoy_salt).unwrap_or_default();
+        let key = pbkdf2_sha512(passphrase, &salt, PASSPHRASE_ROUNDS);
+        wallet_dir.join(hex::encode(&key[..8]))
+    }
+}
+
+/// An open spending session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub until: NaiveDateTime,
+    /// The decoy's wallet directory if it was opened with the decoy passphrase
+    pub decoy: Option<PathBuf>,
+}
+
+/// Parses how long a session lasts e.g. `90s`, `15m` or `2h`.
+pub fn parse_session_length(length: &str) -> anyhow::Result<Duration> {
+    let split = length
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or(anyhow!("{} is missing a unit (s, m or h)", length))?;
+    let (n, unit) = length.split_at(split);
+    let n = n
+        .parse::<i64>()
+        .with_context(|| format!("{} is not a valid length of time", length))?;
+    Ok(match unit {
+        "s" => Duration::seconds(n),
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        unit => return Err(anyhow!("{} is not a unit of time -- use s, m or h", unit)),
+    })
+}
+
+/// Fails if a session ending at `until` would last longer than `max_session_minutes`. It goes by
+/// the system clock like the agent does so pinning the clock can't make a session longer.
+pub fn check_session_end(until: NaiveDateTime, max_session_minutes: u32) -> anyhow::Result<()> {
+    if until > crate::clock::real_now() + Duration::minutes(max_session_minutes as i64) {
+        return Err(anyhow!(
+            "sessions can't last longer than {} minutes (see max-session-minutes in spending-lock)",
+            max_session_minutes
+        ));
+    }
+    Ok(())
+}
+
+pub fn socket_path(wallet_dir: &Path) -> PathBuf {
+    wallet_dir.join("agent.sock")
+}
+
+pub fn locked_seed_file(wallet_dir: &Path) -> PathBuf {
+    wallet_dir.join(LOCKED_SEED_FILE)
+}
+
+/// Encrypts `seed_words` under `passphrase` for [`LOCKED_SEED_FILE`] the way backups with seed
+/// words in them are (see [`crate::backup`]) with a salt of its own.
+pub fn seal_seed_words(seed_words: &str, passphrase: &str) -> Vec<u8> {
+    let salt = passphrase_salt();
+    let keys = BackupKeys::from_passphrase(passphrase, &salt, PASSPHRASE_ROUNDS);
+    let mut ciphertext = seed_words.as_bytes().to_vec();
+    let tag = keys.seal_in_place(&mut ciphertext);
+    [&LOCKED_SEED_MAGIC[..], &salt, &tag, &ciphertext].concat()
+}
+
+/// Decrypts what [`seal_seed_words`] encrypted.
+pub fn open_seed_words(sealed: &[u8], passphrase: &str) -> anyhow::Result<Zeroizing<String>> {
+    let corrupted = || anyhow!("the locked seed words are corrupted");
+    let rest = sealed
+        .strip_prefix(&LOCKED_SEED_MAGIC[..])
+        .filter(|rest| rest.len() >= 48)
+        .ok_or_else(corrupted)?;
+    let mut salt = [0u8; 16];
+    salt.copy_from_slice(&rest[..16]);
+    let mut tag = [0u8; 32];
+    tag.copy_from_slice(&rest[16..48]);
+    let plaintext = BackupKeys::from_passphrase(passphrase, &salt, PASSPHRASE_ROUNDS)
+        .open(&tag, &rest[48..])
+        .ok_or(anyhow!("the seed words aren't locked with that passphrase"))?;
+    Ok(Zeroizing::new(
+        String::from_utf8(plaintext).map_err(|_| corrupted())?,
+    ))
+}
+
+#[cfg(unix)]
+mod agent {
+    use super::*;
+    use std::{
+        fs,
+        io::{BufRead, BufReader, ErrorKind, Write},
+        os::unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+        },
+        thread,
+        time::Duration as StdDuration,
+    };
+
+    /// Runs the agent until the session ends or until it's told to lock. `seed_words` are zeroized
+    /// when it returns.
+    pub fn serve(
+        socket: &Path,
+        session: &Session,
+        seed_words: Zeroizing<String>,
+    ) -> anyhow::Result<()> {
+        let _ = fs::remove_file(socket);
+        let listener = UnixListener::bind(socket)
+            .with_context(|| format!("listening on {}", socket.display()))?;
+        fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+        listener.set_nonblocking(true)?;
+        while crate::clock::real_now() < session.until {
+            match listener.accept() {
+                Ok((stream, _)) => match handle(stream, session, &seed_words) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("agent: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(StdDuration::from_millis(200))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let _ = fs::remove_file(socket);
+        Ok(())
+    }
+
+    /// Answers one request and returns whether the session should end.
+    fn handle(stream: UnixStream, session: &Session, seed_words: &str) -> anyhow::Result<bool> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(StdDuration::from_secs(5)))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let mut stream = &stream;
+        match line.trim() {
+            "status" => {
+                match &session.decoy {
+                    Some(decoy_dir) => writeln!(
+                        stream,
+                        "unlocked-until {} decoy {}",
+                        session.until.timestamp(),
+                        decoy_dir.display()
+                    )?,
+                    None => writeln!(stream, "unlocked-until {}", session.until.timestamp())?,
+                }
+                Ok(false)
+            }
+            "seed-words" => {
+                writeln!(stream, "seed-words {}", seed_words)?;
+                Ok(false)
+            }
+            "lock" => {
+                writeln!(stream, "locked")?;
+                Ok(true)
+            }
+            request => {
+                writeln!(stream, "error unknown request {}", request)?;
+                Ok(false)
+            }
+        }
+    }
+
+    pub fn request(socket: &Path, request: &str) -> Option<String> {
+        let mut stream = UnixStream::connect(socket).ok()?;
+        stream
+            .set_read_timeout(Some(StdDuration::from_secs(5)))
+            .ok()?;
+        writeln!(stream, "{}", request).ok()?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line).ok()?;
+        Some(line.trim().to_string())
+    }
+}
+
+#[cfg(not(unix))]
+mod agent {
+    use super::*;
+
+    pub fn serve(
+        _socket: &Path,
+        _session: &Session,
+        _seed_words: Zeroizing<String>,
+    ) -> anyhow::Result<()> {
+        Err(anyhow!(
+            "spending sessions need unix sockets which this system doesn't have"
+        ))
+    }
+
+    pub fn request(_socket: &Path, _request: &str) -> Option<String> {
+        None
+    }
+}
+
+pub use agent::serve;
+
+/// The open session for the wallet in `wallet_dir` or `None` if it's locked.
+pub fn current(wallet_dir: &Path) -> Option<Session> {
+    let response = agent::request(&socket_path(wallet_dir), "status")?;
+    let mut words = response.strip_prefix("unlocked-until ")?.splitn(2, ' ');
+    let timestamp = words.next()?.parse::<i64>().ok()?;
+    let until = NaiveDateTime::from_timestamp(timestamp, 0);
+    if until <= crate::clock::real_now() {
+        return None;
+    }
+    Some(Session {
+        until,
+        decoy: words
+            .next()
+            .and_then(|rest| rest.strip_prefix("decoy "))
+            .map(PathBuf::from),
+    })
+}
+
+/// When the session for the real wallet in `wallet_dir` ends or `None` if it's locked. A decoy
+/// session doesn't count.
+pub fn unlocked_until(wallet_dir: &Path) -> Option<NaiveDateTime> {
+    current(wallet_dir)
+        .filter(|session| session.decoy.is_none())
+        .map(|session| session.until)
+}
+
+/// Which wallet directory commands should use: the decoy's if a decoy session is open, otherwise
+/// `wallet_dir` itself. Everything that works out which wallet to use goes through here.
+pub fn select_wallet_dir(wallet_dir: &Path) -> PathBuf {
+    match current(wallet_dir) {
+        Some(Session {
+            decoy: Some(decoy_dir),
+            ..
+        }) => decoy_dir,
+        _ => wallet_dir.to_path_buf(),
+    }
+}
+
+/// The seed words of the wallet in `wallet_dir` from the agent. `wallet_dir` can be the wallet with
+/// the spending lock or a decoy in it but the agent only has the seed words of the one that was
+/// unlocked.
+pub fn seed_words(wallet_dir: &Path) -> anyhow::Result<Zeroizing<String>> {
+    let session_dir = match current(wallet_dir) {
+        Some(Session { decoy: None, .. }) => Some(wallet_dir),
+        _ => wallet_dir.parent().filter(|parent| {
+            matches!(current(parent), Some(Session { decoy: Some(decoy_dir), .. }) if decoy_dir == wallet_dir)
+        }),
+    };
+    let response = session_dir
+        .and_then(|session_dir| agent::request(&socket_path(session_dir), "seed-words"))
+        .map(Zeroizing::new);
+    match response
+        .as_ref()
+        .and_then(|response| response.strip_prefix("seed-words "))
+    {
+        Some(seed_words) => Ok(Zeroizing::new(seed_words.to_string())),
+        None => Err(crate::exit_code::ErrorKind::Locked.error(
+            "the seed words are locked with the spending passphrase. Run `gun unlock` first.",
+        )),
+    }
+}
+
+/// Ends the session and returns whether there was one.
+pub fn lock(wallet_dir: &Path) -> bool {
+    agent::request(&socket_path(wallet_dir), "lock").is_some()
+}
+
+/// Fails unless there's an open session. `action` is what needs it e.g. `sign the sweep`.
+pub fn check(wallet_dir: &Path, action: &str) -> anyhow::Result<()> {
+    if unlocked_until(wallet_dir).is_none() {
+        return Err(crate::exit_code::ErrorKind::Locked.error(format!(
+            "can't {} because the wallet is locked. Run `gun unlock` first.",
+            action
+        )));
+    }
+    Ok(())
+}
+
+/// A signer that goes first and refuses to let the wallet sign unless there's an open session.
+#[derive(Clone, Debug)]
+pub struct SessionSigner {
+    pub wallet_dir: PathBuf,
+}
+
+impl Signer for SessionSigner {
+    fn sign(
+        &self,
+        _psbt: &mut Psbt,
+        _input_index: Option<usize>,
+        _secp: &Secp256k1<All>,
+    ) -> Result<(), SignerError> {
+        match check(&self.wallet_dir, "sign") {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                // SignerError has no variant that can carry our error so print it here
+                tracing::error!("{}", e);
+                Err(SignerError::UserCanceled)
+            }
+        }
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn id(&self, _secp: &Secp256k1<All>) -> SignerId {
+        SignerId::PkHash(hash160::Hash::hash(b"gun-session"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spending_lock_and_session_length() {
+        let lock = SpendingLock::new("hunter2");
+        assert!(lock.has_passphrase("hunter2"));
+        assert!(!lock.has_passphrase("hunter3"));
+        assert_eq!(parse_session_length("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_session_length("2h").unwrap(), Duration::hours(2));
+        assert!(parse_session_length("15").is_err());
+        assert!(parse_session_length("15d").is_err());
+
+        let wallet_dir = Path::new("/home/satoshi/.gun");
+        let decoy_dir = lock.decoy_wallet_dir(wallet_dir, "correct horse");
+        assert_eq!(decoy_dir.parent(), Some(wallet_dir));
+        assert_eq!(
+            lock.decoy_wallet_dir(wallet_dir, "correct horse"),
+            decoy_dir
+        );
+        assert_ne!(
+            lock.decoy_wallet_dir(wallet_dir, "battery staple"),
+            decoy_dir
+        );
+        assert_ne!(
+            SpendingLock::new("hunter2").decoy_wallet_dir(wallet_dir, "correct horse"),
+            decoy_dir,
+            "each spending lock has its own decoy salt"
+        );
+    }
+
+    #[test]
+    fn locked_seed_words() {
+        let seed_words = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let sealed = seal_seed_words(seed_words, "hunter2");
+        assert!(!String::from_utf8_lossy(&sealed).contains("abandon"));
+        assert_eq!(
+            open_seed_words(&sealed, "hunter2").unwrap().as_str(),
+            seed_words
+        );
+        assert!(open_seed_words(&sealed, "hunter3").is_err());
+        assert_ne!(
+            seal_seed_words(seed_words, "hunter2"),
+            sealed,
+            "each gets a salt of its own"
+        );
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open_seed_words(&tampered, "hunter2").is_err());
+        assert!(open_seed_words(&sealed[..40], "hunter2").is_err());
+    }
+}
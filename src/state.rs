@@ -0,0 +1,245 @@
+//! Snapshots of what the wallet thinks its state is so two of them can be compared e.g. before
+//! and after a balance changed unexpectedly.
+//!
+//! A snapshot only has things derived from the wallet and bet databases. It has no keys or
+//! secrets (the secret keys of won bets are left out) so it can go in a bug report.
+use crate::betting::{BetId, BetState};
+use bdk::bitcoin::{Amount, Network};
+use olivia_core::chrono::NaiveDateTime;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Snapshot {
+    pub taken_at: NaiveDateTime,
+    pub gun_version: String,
+    pub network: Network,
+    pub tip_height: Option<u32>,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub balance: Amount,
+    pub last_external_index: Option<u32>,
+    pub last_internal_index: Option<u32>,
+    pub utxos: BTreeMap<String, UtxoSnapshot>,
+    pub transactions: BTreeMap<String, TxSnapshot>,
+    pub bets: BTreeMap<BetId, BetSnapshot>,
+    pub pending_psbts: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UtxoSnapshot {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub value: Amount,
+    pub keychain: String,
+    pub frozen: bool,
+    pub in_use: bool,
+    pub coinjoin: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TxSnapshot {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub received: Amount,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub sent: Amount,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub fee: Option<Amount>,
+    pub height: Option<u32>,
+    pub memo: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BetSnapshot {
+    pub state: String,
+    pub oracle: String,
+    pub event_id: String,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub local_value: Amount,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub joint_output_value: Option<Amount>,
+    /// The bet's output once there is a bet transaction
+    pub outpoint: Option<String>,
+    pub reserved_utxos: Vec<String>,
+    /// The claim or cancel transaction
+    pub spent_by: Option<String>,
+    pub height: Option<u32>,
+    pub tags: Vec<String>,
+}
+
+impl From<&BetState> for BetSnapshot {
+    fn from(bet_state: &BetState) -> Self {
+        use crate::betting::BetOrProp;
+        let (spent_by, height) = match bet_state {
+            BetState::Included { height, .. } => (None, *height),
+            BetState::Claimed { txid, height, .. } => (Some(txid.to_string()), *height),
+            BetState::Canceled {
+                cancel_txid,
+                height,
+                ..
+            } => (Some(cancel_txid.to_string()), *height),
+            _ => (None, None),
+        };
+        let reserved_utxos = bet_state
+            .reserved_utxos()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let state = bet_state.name().to_string();
+        match bet_state.clone().into_bet_or_prop() {
+            BetOrProp::Proposal(local_proposal) => BetSnapshot {
+                state,
+                oracle: local_proposal.proposal.oracle.clone(),
+                event_id: local_proposal.proposal.event_id.to_string(),
+                local_value: local_proposal.proposal.value,
+                joint_output_value: None,
+                outpoint: None,
+                reserved_utxos,
+                spent_by,
+                height,
+                tags: local_proposal.tags,
+            },
+            BetOrProp::Bet(bet)
+            | BetOrProp::OfferedBet {
+                bet: crate::betting::OfferedBet(bet),
+                ..
+            } => BetSnapshot {
+                state,
+                oracle: bet.oracle_id.to_string(),
+                event_id: bet.oracle_event.event.id.to_string(),
+                local_value: bet.local_value,
+                joint_output_value: Some(bet.joint_output_value),
+                outpoint: Some(bet.outpoint().to_string()),
+                reserved_utxos,
+                spent_by,
+                height,
+                tags: bet.tags,
+            },
+        }
+    }
+}
+
+/// One thing that is different between two snapshots.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Difference {
+    /// Where it is e.g. `utxos.<outpoint>.frozen`
+    pub path: String,
+    /// What it was in the first snapshot (`None` if it wasn't there)
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// Everything that differs between snapshots `a` and `b`. Something only one of them has (e.g.
+/// a new UTXO) is one difference rather than one per field.
+pub fn diff(a: &Snapshot, b: &Snapshot) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_values(
+        "",
+        Some(&serde_json::to_value(a).expect("snapshots serialize")),
+        Some(&serde_json::to_value(b).expect("snapshots serialize")),
+        &mut differences,
+    );
+    differences
+        .into_iter()
+        .filter(|difference| difference.path != "taken-at")
+        .collect()
+}
+
+fn diff_values(path: &str, a: Option<&Value>, b: Option<&Value>, out: &mut Vec<Difference>) {
+    if let (Some(Value::Object(a)), Some(Value::Object(b))) = (a, b) {
+        let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let path = match path {
+                "" => key.clone(),
+                path => format!("{}.{}", path, key),
+            };
+            diff_values(&path, a.get(key), b.get(key), out);
+        }
+        return;
+    }
+    if a != b {
+        let render = |value: Option<&Value>| match value {
+            Some(Value::String(string)) => Some(string.clone()),
+            Some(value) => Some(value.to_string()),
+            None => None,
+        };
+        out.push(Difference {
+            path: path.to_string(),
+            before: render(a),
+            after: render(b),
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut utxos = BTreeMap::new();
+        utxos.insert(
+            "aa:0".to_string(),
+            UtxoSnapshot {
+                value: Amount::from_sat(10_000),
+                keychain: "external".into(),
+                frozen: false,
+                in_use: false,
+                coinjoin: false,
+            },
+        );
+        Snapshot {
+            taken_at: NaiveDateTime::from_timestamp(0, 0),
+            gun_version: "0.2.0".into(),
+            network: Network::Regtest,
+            tip_height: Some(100),
+            balance: Amount::from_sat(10_000),
+            last_external_index: Some(0),
+            last_internal_index: None,
+            utxos,
+            transactions: BTreeMap::new(),
+            bets: BTreeMap::new(),
+            pending_psbts: vec![],
+        }
+    }
+
+    #[test]
+    fn diff_shows_changed_and_new_things() {
+        let a = snapshot();
+        assert_eq!(diff(&a, &a), vec![]);
+
+        let mut b = snapshot();
+        b.taken_at = NaiveDateTime::from_timestamp(60, 0);
+        b.balance = Amount::from_sat(15_000);
+        b.utxos.get_mut("aa:0").unwrap().frozen = true;
+        b.utxos.insert(
+            "bb:1".to_string(),
+            UtxoSnapshot {
+                value: Amount::from_sat(5_000),
+                keychain: "internal".into(),
+                frozen: false,
+                in_use: false,
+                coinjoin: false,
+            },
+        );
+        let differences = diff(&a, &b);
+        assert_eq!(
+            differences
+                .iter()
+                .map(|difference| difference.path.as_str())
+                .collect::<Vec<_>>(),
+            vec!["balance", "utxos.aa:0.frozen", "utxos.bb:1"]
+        );
+        assert_eq!(differences[0].before.as_deref(), Some("10000"));
+        assert_eq!(differences[0].after.as_deref(), Some("15000"));
+        assert_eq!(differences[2].before, None);
+        assert!(differences[2]
+            .after
+            .as_ref()
+            .unwrap()
+            .contains("\"value\":5000"));
+    }
+}
@@ -0,0 +1,68 @@
+//! Finding out about new blocks without syncing the whole wallet (for things that keep running
+//! like `gun schedule run --daemon`).
+//!
+//! This polls. Only the Esplora client is compiled in and Esplora can't push anything so this asks
+//! for the hash of the tip every [`TIP_POLL_INTERVAL`]. It's one small request and the connection
+//! is kept alive between them, which is much less than a sync that looks up every script of the
+//! wallet. There are no Electrum header or script subscriptions and nothing about the mempool is
+//! pushed: once a block is seen the wallet still syncs the usual way. The client is made by
+//! [`crate::endpoint::http_client`] so it goes through the same proxies as everything else.
+use anyhow::Context;
+use std::time::{Duration, Instant};
+
+/// How often the tip is asked for while waiting.
+const TIP_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct TipWatcher {
+    client: reqwest::blocking::Client,
+    base_url: String,
+    tip: Option<String>,
+}
+
+impl TipWatcher {
+    pub fn new(base_url: &str) -> anyhow::Result<Self> {
+        let client = crate::endpoint::http_client()
+            .timeout(Duration::from_secs(10))
+            .tcp_keepalive(Some(Duration::from_secs(30)))
+            .pool_idle_timeout(None)
+            .build()?;
+        Ok(TipWatcher {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            tip: None,
+        })
+    }
+
+    fn fetch_tip(&self) -> anyhow::Result<String> {
+        let tip = self
+            .client
+            .get(format!("{}/blocks/tip/hash", self.base_url))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .with_context(|| format!("asking {} for the tip", self.base_url))?;
+        Ok(tip.trim().to_string())
+    }
+
+    /// Waits for a block we haven't seen for at most `timeout`. Returns the hash of the new tip
+    /// or `None` if there wasn't one in time. The first call only learns what the tip is.
+    pub fn wait(&mut self, timeout: Duration) -> anyhow::Result<Option<String>> {
+        let deadline = Instant::now() + timeout;
+        if self.tip.is_none() {
+            self.tip = Some(self.fetch_tip()?);
+        }
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(TIP_POLL_INTERVAL.min(deadline - now));
+            let tip = self.fetch_tip()?;
+            if self.tip.as_ref() != Some(&tip) {
+                tracing::debug!(tip = %tip, "new block");
+                self.tip = Some(tip.clone());
+                return Ok(Some(tip));
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! How the inputs and outputs of the transactions we make are ordered (`tx-ordering` in the
+//! config).
+//!
+//! Random ordering is the default because BIP69 ordering is itself a fingerprint now that few
+//! wallets use it. Bet transactions are built by both sides so they can't use an RNG only one side
+//! has. Instead they start from BIP69 order and are shuffled with the RNG from the ECDH of the
+//! two parties' keys which both sides can reproduce (see [`shuffle_psbt`]).
+use crate::rand_core::RngCore;
+use bdk::{
+    bitcoin::util::psbt::PartiallySignedTransaction as Psbt,
+    database::BatchDatabase,
+    wallet::{coin_selection::CoinSelectionAlgorithm, tx_builder::TxBuilderContext},
+    TxBuilder,
+};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TxOrderingPolicy {
+    /// Shuffle inputs and outputs
+    Random,
+    /// Sort inputs and outputs as BIP69 says
+    Bip69,
+}
+
+impl Default for TxOrderingPolicy {
+    fn default() -> Self {
+        TxOrderingPolicy::Random
+    }
+}
+
+impl FromStr for TxOrderingPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(string: &str) -> anyhow::Result<Self> {
+        Ok(match string {
+            "random" => TxOrderingPolicy::Random,
+            "bip69" => TxOrderingPolicy::Bip69,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "'{}' is not a transaction ordering (expected random or bip69)",
+                    string
+                ))
+            }
+        })
+    }
+}
+
+impl TxOrderingPolicy {
+    /// Sets the ordering on `builder`. Call [`finish`](Self::finish) on what it builds.
+    pub fn apply_to_builder<
+        B,
+        D: BatchDatabase,
+        Cs: CoinSelectionAlgorithm<D>,
+        Ctx: TxBuilderContext,
+    >(
+        self,
+        builder: &mut TxBuilder<'_, B, D, Cs, Ctx>,
+    ) {
+        use bdk::wallet::tx_builder::TxOrdering;
+        builder.ordering(match self {
+            // in deterministic mode the shuffle has to come from its RNG so start from something
+            // that doesn't depend on the order coins were selected in
+            TxOrderingPolicy::Random if crate::deterministic::is_enabled() => {
+                TxOrdering::Bip69Lexicographic
+            }
+            TxOrderingPolicy::Random => TxOrdering::Shuffle,
+            TxOrderingPolicy::Bip69 => TxOrdering::Bip69Lexicographic,
+        });
+    }
+
+    /// Does the part of the ordering the builder couldn't (the shuffle in deterministic mode).
+    pub fn finish(self, psbt: &mut Psbt) {
+        if let TxOrderingPolicy::Random = self {
+            if let Some(mut rng) = crate::deterministic::rng("tx-ordering") {
+                shuffle_psbt(psbt, &mut rng);
+            }
+        }
+    }
+}
+
+/// Shuffles the inputs and outputs of `psbt` (keeping each PSBT input and output with its
+/// transaction input and output). The same starting order and RNG always give the same result.
+pub fn shuffle_psbt(psbt: &mut Psbt, rng: &mut impl RngCore) {
+    let tx = &mut psbt.global.unsigned_tx;
+    let mut inputs = tx
+        .input
+        .drain(..)
+        .zip(psbt.inputs.drain(..))
+        .collect::<Vec<_>>();
+    crate::deterministic::shuffle(&mut inputs, rng);
+    let (txins, psbt_inputs) = inputs.into_iter().unzip();
+    tx.input = txins;
+    psbt.inputs = psbt_inputs;
+
+    let mut outputs = tx
+        .output
+        .drain(..)
+        .zip(psbt.outputs.drain(..))
+        .collect::<Vec<_>>();
+    crate::deterministic::shuffle(&mut outputs, rng);
+    let (txouts, psbt_outputs) = outputs.into_iter().unzip();
+    tx.output = txouts;
+    psbt.outputs = psbt_outputs;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rand_core::SeedableRng;
+    use bdk::bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+    use chacha20::ChaCha20Rng;
+
+    #[test]
+    fn psbt_inputs_and_outputs_move_with_the_transaction() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..5)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        vout,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            output: (0..5)
+                .map(|value| TxOut {
+                    value,
+                    script_pubkey: Script::new(),
+                })
+                .collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for (i, (input, output)) in psbt.inputs.iter_mut().zip(&mut psbt.outputs).enumerate() {
+            input.redeem_script = Some(Script::from(vec![i as u8]));
+            output.redeem_script = Some(Script::from(vec![i as u8]));
+        }
+        let mut shuffled = psbt.clone();
+        shuffle_psbt(&mut shuffled, &mut ChaCha20Rng::from_seed([7u8; 32]));
+        let mut again = psbt.clone();
+        shuffle_psbt(&mut again, &mut ChaCha20Rng::from_seed([7u8; 32]));
+        assert_eq!(shuffled, again);
+        assert_ne!(shuffled.global.unsigned_tx, psbt.global.unsigned_tx);
+
+        for (txout, output) in shuffled
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(&shuffled.outputs)
+        {
+            assert_eq!(
+                output.redeem_script,
+                Some(Script::from(vec![txout.value as u8]))
+            );
+        }
+        for (txin, input) in shuffled
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(&shuffled.inputs)
+        {
+            assert_eq!(
+                input.redeem_script,
+                Some(Script::from(vec![txin.previous_output.vout as u8]))
+            );
+        }
+    }
+}
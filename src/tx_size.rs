@@ -0,0 +1,238 @@
+//! How big a transaction is (or will be once it's signed) and how much of that each input and
+//! output is responsible for.
+//!
+//! Sizes are in weight units (WU). A vbyte is four of them and feerates are per vbyte. Inputs that
+//! are finalized are measured. The witness of an input that isn't signed yet is estimated with
+//! BDK's `max_satisfaction_weight` for the descriptor it belongs to so an estimate is never less
+//! than what the signed transaction will weigh.
+use bdk::{
+    bitcoin::{
+        util::psbt::{Input, PartiallySignedTransaction as Psbt},
+        TxOut, VarInt,
+    },
+    database::{BatchDatabase, Database},
+    Wallet,
+};
+
+/// What BDK's `max_satisfaction_weight` gives a p2wpkh descriptor. Used for unsigned inputs whose
+/// descriptor we don't know.
+const P2WPKH_SATISFACTION_WEIGHT: usize = 4 + 1 + 73 + 34;
+/// The previous outpoint and nSequence of an input.
+const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4) * 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxSize {
+    /// The version, lock time, input and output counts and the segwit marker and flag
+    pub overhead_weight: usize,
+    pub input_weights: Vec<usize>,
+    pub output_weights: Vec<usize>,
+    /// Some inputs aren't finalized so their weight is an upper bound
+    pub estimated: bool,
+}
+
+impl TxSize {
+    /// Works out the size of `psbt`. `satisfaction_weight` is asked how much the scriptSig and
+    /// witness of an input that spends a coin will weigh when it's not finalized yet.
+    pub fn of(psbt: &Psbt, satisfaction_weight: impl Fn(&TxOut) -> Option<usize>) -> Self {
+        let tx = &psbt.global.unsigned_tx;
+        let mut estimated = false;
+        let mut segwit = false;
+        let input_weights = tx
+            .input
+            .iter()
+            .zip(&psbt.inputs)
+            .map(|(txin, input)| {
+                if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                    estimated = true;
+                    // we don't know if it will have a witness but assuming so is the upper bound
+                    segwit = true;
+                    let satisfaction = spent_txout(input, txin.previous_output.vout)
+                        .and_then(|txout| satisfaction_weight(&txout))
+                        .unwrap_or(P2WPKH_SATISFACTION_WEIGHT);
+                    return TXIN_BASE_WEIGHT + satisfaction;
+                }
+                let script_sig_len = input
+                    .final_script_sig
+                    .as_ref()
+                    .map(|script_sig| script_sig.len())
+                    .unwrap_or(0);
+                let witness_weight = match &input.final_script_witness {
+                    Some(witness) => {
+                        segwit = true;
+                        VarInt(witness.len() as u64).len()
+                            + witness
+                                .iter()
+                                .map(|item| VarInt(item.len() as u64).len() + item.len())
+                                .sum::<usize>()
+                    }
+                    None => 0,
+                };
+                TXIN_BASE_WEIGHT
+                    + (VarInt(script_sig_len as u64).len() + script_sig_len) * 4
+                    + witness_weight
+            })
+            .collect::<Vec<_>>();
+
+        // in a segwit transaction inputs without a witness still have an empty one
+        let input_weights = input_weights
+            .into_iter()
+            .zip(&psbt.inputs)
+            .map(|(weight, input)| {
+                let empty_witness = segwit
+                    && input.final_script_witness.is_none()
+                    && input.final_script_sig.is_some();
+                weight + empty_witness as usize
+            })
+            .collect();
+
+        let output_weights = tx
+            .output
+            .iter()
+            .map(|txout| {
+                let script_len = txout.script_pubkey.len();
+                (8 + VarInt(script_len as u64).len() + script_len) * 4
+            })
+            .collect();
+
+        let overhead_weight =
+            (4 + 4 + VarInt(tx.input.len() as u64).len() + VarInt(tx.output.len() as u64).len())
+                * 4
+                + if segwit { 2 } else { 0 };
+
+        TxSize {
+            overhead_weight,
+            input_weights,
+            output_weights,
+            estimated,
+        }
+    }
+
+    /// Like [`of`](Self::of) but the witnesses of the wallet's own unsigned inputs are estimated
+    /// from the wallet's descriptors.
+    pub fn for_wallet<B, D: BatchDatabase>(wallet: &Wallet<B, D>, psbt: &Psbt) -> Self {
+        Self::of(psbt, wallet_satisfaction_weight(wallet))
+    }
+
+    pub fn weight(&self) -> usize {
+        self.overhead_weight
+            + self.input_weights.iter().sum::<usize>()
+            + self.output_weights.iter().sum::<usize>()
+    }
+
+    pub fn vsize(&self) -> u64 {
+        ((self.weight() + 3) / 4) as u64
+    }
+
+    /// e.g. `141 vB (562 WU)` with a `~` in front if it's an estimate.
+    pub fn summary(&self) -> String {
+        format!(
+            "{}{} vB ({} WU)",
+            if self.estimated { "~" } else { "" },
+            self.vsize(),
+            self.weight()
+        )
+    }
+}
+
+/// Formats the vbytes an input or output adds to a transaction. These aren't whole numbers
+/// because only the total is rounded up.
+pub fn format_vbytes(weight: usize) -> String {
+    format!("{:.2} vB", weight as f32 / 4.0)
+}
+
+fn spent_txout(input: &Input, vout: u32) -> Option<TxOut> {
+    input.witness_utxo.clone().or_else(|| {
+        input
+            .non_witness_utxo
+            .as_ref()
+            .and_then(|prev_tx| prev_tx.output.get(vout as usize))
+            .cloned()
+    })
+}
+
+/// How much the scriptSig and witness spending a coin of `wallet` will weigh:
+/// `max_satisfaction_weight` of the descriptor the coin belongs to or `None` if it isn't the
+/// wallet's.
+pub fn wallet_satisfaction_weight<'a, B, D: BatchDatabase>(
+    wallet: &'a Wallet<B, D>,
+) -> impl Fn(&TxOut) -> Option<usize> + 'a {
+    move |txout| {
+        let (keychain, _) = wallet
+            .query_db(|db| db.get_path_from_script_pubkey(&txout.script_pubkey))
+            .ok()??;
+        wallet
+            .get_descriptor_for_keychain(keychain)
+            .max_satisfaction_weight()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{hashes::Hash, OutPoint, Script, Transaction, TxIn, WPubkeyHash};
+
+    fn p2wpkh(n: u8) -> Script {
+        Script::new_v0_wpkh(&WPubkeyHash::from_slice(&[n; 20]).unwrap())
+    }
+
+    fn psbt() -> Psbt {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint {
+                        vout,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            output: (0..2)
+                .map(|n| TxOut {
+                    value: 10_000,
+                    script_pubkey: p2wpkh(n),
+                })
+                .collect(),
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        for input in &mut psbt.inputs {
+            input.witness_utxo = Some(TxOut {
+                value: 20_000,
+                script_pubkey: p2wpkh(9),
+            });
+        }
+        psbt
+    }
+
+    #[test]
+    fn finalized_size_is_exact() {
+        let mut psbt = psbt();
+        for input in &mut psbt.inputs {
+            input.final_script_witness = Some(vec![vec![1; 72], vec![2; 33]]);
+        }
+        let size = TxSize::of(&psbt, |_| None);
+        assert!(!size.estimated);
+        assert_eq!(size.weight(), psbt.extract_tx().get_weight());
+        // a p2wpkh output is 31 bytes
+        assert_eq!(size.output_weights, vec![124, 124]);
+    }
+
+    #[test]
+    fn unsigned_size_is_an_upper_bound() {
+        let unsigned = psbt();
+        let size = TxSize::of(&unsigned, |_| None);
+        assert!(size.estimated);
+        assert!(size.summary().starts_with('~'));
+
+        let mut signed = unsigned.clone();
+        for input in &mut signed.inputs {
+            input.final_script_witness = Some(vec![vec![1; 72], vec![2; 33]]);
+        }
+        assert!(size.weight() >= signed.extract_tx().get_weight());
+
+        let bigger = TxSize::of(&unsigned, |_| Some(P2WPKH_SATISFACTION_WEIGHT + 100));
+        assert_eq!(bigger.weight(), size.weight() + 200);
+    }
+}
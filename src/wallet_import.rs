@@ -0,0 +1,305 @@
+//! Turning other wallets' exports into descriptors gun can use.
+//!
+//! Wallets made from an old (pre 2.0) Electrum seed can't be imported. Their addresses come from
+//! adding a hash of the index to the master public key which isn't BIP32 so no descriptor can
+//! describe them and gun only follows descriptors.
+use anyhow::{anyhow, Context};
+use bdk::{
+    bitcoin::{
+        secp256k1::Secp256k1,
+        util::{base58, bip32::DerivationPath},
+    },
+    miniscript::{descriptor::DescriptorPublicKey, Descriptor},
+};
+use std::str::FromStr;
+
+/// The descriptors for a wallet's receiving and change addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedDescriptors {
+    pub external: String,
+    pub internal: Option<String>,
+    /// Descriptors in what was imported from that gun won't follow
+    pub not_imported: Vec<String>,
+}
+
+impl ImportedDescriptors {
+    /// Whether the descriptors hold private keys. They're written to config.json as they are.
+    pub fn has_private_keys(&self) -> bool {
+        let secp = Secp256k1::signing_only();
+        std::iter::once(&self.external)
+            .chain(self.internal.as_ref())
+            .any(|descriptor| {
+                Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)
+                    .map(|(_, keymap)| !keymap.is_empty())
+                    .unwrap_or(false)
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScriptType {
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+}
+
+impl ScriptType {
+    fn wrap(self, key: &str) -> String {
+        match self {
+            ScriptType::P2pkh => format!("pkh({})", key),
+            ScriptType::P2shP2wpkh => format!("sh(wpkh({}))", key),
+            ScriptType::P2wpkh => format!("wpkh({})", key),
+        }
+    }
+}
+
+// (prefix, version bytes, is it private, script type, is it mainnet)
+#[rustfmt::skip]
+const SLIP132_VERSIONS: [(&str, [u8; 4], bool, ScriptType, bool); 12] = [
+    ("xpub", [0x04, 0x88, 0xb2, 0x1e], false, ScriptType::P2pkh, true),
+    ("xprv", [0x04, 0x88, 0xad, 0xe4], true, ScriptType::P2pkh, true),
+    ("ypub", [0x04, 0x9d, 0x7c, 0xb2], false, ScriptType::P2shP2wpkh, true),
+    ("yprv", [0x04, 0x9d, 0x78, 0x78], true, ScriptType::P2shP2wpkh, true),
+    ("zpub", [0x04, 0xb2, 0x47, 0x46], false, ScriptType::P2wpkh, true),
+    ("zprv", [0x04, 0xb2, 0x43, 0x0c], true, ScriptType::P2wpkh, true),
+    ("tpub", [0x04, 0x35, 0x87, 0xcf], false, ScriptType::P2pkh, false),
+    ("tprv", [0x04, 0x35, 0x83, 0x94], true, ScriptType::P2pkh, false),
+    ("upub", [0x04, 0x4a, 0x52, 0x62], false, ScriptType::P2shP2wpkh, false),
+    ("uprv", [0x04, 0x4a, 0x4e, 0x28], true, ScriptType::P2shP2wpkh, false),
+    ("vpub", [0x04, 0x5f, 0x1c, 0xf6], false, ScriptType::P2wpkh, false),
+    ("vprv", [0x04, 0x5f, 0x18, 0xbc], true, ScriptType::P2wpkh, false),
+];
+
+/// Converts a SLIP-132 extended key (zpub, yprv, vpub...) into the plain xpub/xprv/tpub/tprv form
+/// descriptors use and tells us what kind of script it was for.
+fn normalize_extended_key(key: &str) -> anyhow::Result<(String, ScriptType)> {
+    let (_, _, is_private, script_type, is_mainnet) = SLIP132_VERSIONS
+        .iter()
+        .find(|(prefix, ..)| key.starts_with(prefix))
+        .ok_or(anyhow!("'{}' is not an extended key gun understands", key))?;
+    let mut data = base58::from_check(key).context("decoding extended key")?;
+    if data.len() != 78 {
+        return Err(anyhow!("extended key has the wrong length"));
+    }
+    let plain_prefix = match (is_private, is_mainnet) {
+        (false, true) => "xpub",
+        (true, true) => "xprv",
+        (false, false) => "tpub",
+        (true, false) => "tprv",
+    };
+    let (_, version, ..) = SLIP132_VERSIONS
+        .iter()
+        .find(|(prefix, ..)| *prefix == plain_prefix)
+        .expect("plain prefixes are in the list");
+    data[..4].copy_from_slice(&version[..]);
+    Ok((base58::check_encode_slice(&data), *script_type))
+}
+
+#[derive(serde::Deserialize)]
+struct ElectrumWallet {
+    wallet_type: String,
+    keystore: Option<ElectrumKeystore>,
+}
+
+#[derive(serde::Deserialize)]
+struct ElectrumKeystore {
+    #[serde(rename = "type")]
+    kind: String,
+    xpub: Option<String>,
+    xprv: Option<String>,
+    root_fingerprint: Option<String>,
+    derivation: Option<String>,
+}
+
+/// Reads an (unencrypted) Electrum wallet file.
+///
+/// If the file has the private key the descriptors will hold it too so gun can sign.
+pub fn from_electrum(contents: &str) -> anyhow::Result<ImportedDescriptors> {
+    let wallet: ElectrumWallet = serde_json::from_str(contents).map_err(|_| {
+        anyhow!("couldn't read the Electrum wallet file -- if it is encrypted remove the password in Electrum first")
+    })?;
+    if wallet.wallet_type != "standard" {
+        return Err(anyhow!(
+            "only standard Electrum wallets can be imported not '{}'",
+            wallet.wallet_type
+        ));
+    }
+    let keystore = wallet
+        .keystore
+        .ok_or(anyhow!("Electrum wallet file has no keystore"))?;
+    match keystore.kind.as_str() {
+        "bip32" => {}
+        "old" => {
+            return Err(anyhow!(
+                "this wallet uses an old (pre 2.0) Electrum seed which gun can't follow since its addresses aren't derived with BIP32 -- send the funds to a new Electrum wallet first and import that one"
+            ))
+        }
+        kind => return Err(anyhow!("Electrum '{}' keystores are not supported", kind)),
+    }
+
+    let key = keystore
+        .xprv
+        .or(keystore.xpub)
+        .ok_or(anyhow!("Electrum keystore has no extended key"))?;
+    let (key, script_type) = normalize_extended_key(&key)?;
+    let origin = match (keystore.root_fingerprint, keystore.derivation) {
+        (Some(fingerprint), Some(derivation)) => {
+            let path = DerivationPath::from_str(&derivation)
+                .context("parsing Electrum derivation path")?;
+            format!(
+                "[{}{}]",
+                fingerprint.to_lowercase(),
+                path.to_string().trim_start_matches('m')
+            )
+        }
+        _ => String::new(),
+    };
+
+    Ok(ImportedDescriptors {
+        external: script_type.wrap(&format!("{}{}/0/*", origin, key)),
+        internal: Some(script_type.wrap(&format!("{}{}/1/*", origin, key))),
+        not_imported: vec![],
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct CoreDescriptors {
+    descriptors: Vec<CoreDescriptor>,
+}
+
+#[derive(serde::Deserialize)]
+struct CoreDescriptor {
+    desc: String,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    internal: Option<bool>,
+}
+
+/// Reads the output of Bitcoin Core's `listdescriptors` (with or without private keys).
+///
+/// gun only follows one pair of descriptors so if Core has several active ones we take the
+/// native segwit pair. The rest, active or not, are in [`ImportedDescriptors::not_imported`].
+pub fn from_core_descriptors(json: &str) -> anyhow::Result<ImportedDescriptors> {
+    let dump: CoreDescriptors =
+        serde_json::from_str(json).context("parsing Bitcoin Core listdescriptors output")?;
+    let (active, inactive): (Vec<_>, Vec<_>) = dump
+        .descriptors
+        .into_iter()
+        .partition(|descriptor| descriptor.active);
+
+    let pick = |internal: bool| {
+        active
+            .iter()
+            .filter(|descriptor| descriptor.internal.unwrap_or(false) == internal)
+            .max_by_key(|descriptor| descriptor.desc.starts_with("wpkh("))
+    };
+
+    let external = pick(false).ok_or(anyhow!(
+        "there are no active receiving descriptors in the Bitcoin Core dump"
+    ))?;
+    let internal = pick(true);
+
+    let not_imported = active
+        .iter()
+        .chain(inactive.iter())
+        .map(|descriptor| &descriptor.desc)
+        .filter(|desc| {
+            **desc != external.desc && Some(*desc) != internal.map(|internal| &internal.desc)
+        })
+        .cloned()
+        .collect();
+
+    Ok(ImportedDescriptors {
+        external: external.desc.clone(),
+        internal: internal.map(|internal| internal.desc.clone()),
+        not_imported,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::{
+        secp256k1::Secp256k1,
+        util::bip32::{ExtendedPrivKey, ExtendedPubKey},
+        Network,
+    };
+
+    fn xpub() -> ExtendedPubKey {
+        let secp = Secp256k1::new();
+        let xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &[7u8; 32]).unwrap();
+        ExtendedPubKey::from_private(&secp, &xprv)
+    }
+
+    fn with_version(key: &str, version: [u8; 4]) -> String {
+        let mut data = base58::from_check(key).unwrap();
+        data[..4].copy_from_slice(&version);
+        base58::check_encode_slice(&data)
+    }
+
+    #[test]
+    fn electrum_segwit_wallet() {
+        let xpub = xpub().to_string();
+        let zpub = with_version(&xpub, [0x04, 0xb2, 0x47, 0x46]);
+        let wallet = format!(
+            r#"{{
+                "wallet_type": "standard",
+                "seed_type": "segwit",
+                "keystore": {{
+                    "type": "bip32",
+                    "xpub": "{}",
+                    "root_fingerprint": "ABCD1234",
+                    "derivation": "m/0'"
+                }}
+            }}"#,
+            zpub
+        );
+        let imported = from_electrum(&wallet).unwrap();
+        assert_eq!(
+            imported.external,
+            format!("wpkh([abcd1234/0']{}/0/*)", xpub)
+        );
+        assert_eq!(
+            imported.internal,
+            Some(format!("wpkh([abcd1234/0']{}/1/*)", xpub))
+        );
+    }
+
+    #[test]
+    fn knows_when_it_has_private_keys() {
+        let xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &[7u8; 32]).unwrap();
+        let with_xprv = ImportedDescriptors {
+            external: format!("wpkh({}/0/*)", xprv),
+            internal: Some(format!("wpkh({}/1/*)", xprv)),
+            not_imported: vec![],
+        };
+        let with_xpub = ImportedDescriptors {
+            external: format!("wpkh({}/0/*)", xpub()),
+            internal: None,
+            not_imported: vec![],
+        };
+        assert!(with_xprv.has_private_keys());
+        assert!(!with_xpub.has_private_keys());
+    }
+
+    #[test]
+    fn core_prefers_native_segwit() {
+        let dump = r#"{
+            "wallet_name": "test",
+            "descriptors": [
+                { "desc": "pkh(xpubA/0/*)#aaaa", "active": true, "internal": false },
+                { "desc": "wpkh(xpubB/0/*)#bbbb", "active": true, "internal": false },
+                { "desc": "wpkh(xpubB/1/*)#cccc", "active": true, "internal": true },
+                { "desc": "wpkh(xpubC/0/*)#dddd", "active": false, "internal": false }
+            ]
+        }"#;
+        let imported = from_core_descriptors(dump).unwrap();
+        assert_eq!(imported.external, "wpkh(xpubB/0/*)#bbbb");
+        assert_eq!(imported.internal, Some("wpkh(xpubB/1/*)#cccc".to_string()));
+        assert_eq!(
+            imported.not_imported,
+            vec!["pkh(xpubA/0/*)#aaaa", "wpkh(xpubC/0/*)#dddd"]
+        );
+    }
+}
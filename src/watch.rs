@@ -0,0 +1,376 @@
+//! Keeping an eye on bets from a machine that doesn't have the wallet's keys.
+//!
+//! `gun bet export-watch` writes the public parts of the wallet's bets (and the oracles they use)
+//! to a file. `gun watch import` on another machine keeps them in `watch.json` and `gun watch
+//! check` (e.g. from cron) looks at the chain and the oracles and runs the `alert-command` when
+//! something happens to a bet. Nothing it has can be used to claim or cancel a bet.
+use crate::{
+    betting::{verify_response, BetDatabase, BetId, BetState, OfferedBet, OracleInfo},
+    chrono::NaiveDateTime,
+    event_source::EventSources,
+    oracle_poll::OraclePoller,
+    Url,
+};
+use anyhow::{anyhow, Context};
+use bdk::bitcoin::{Amount, Network, OutPoint, Txid};
+use olivia_core::{EventId, OracleId};
+use std::{path::Path, str::FromStr, time::Duration};
+
+/// What `gun bet export-watch` writes.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchExport {
+    pub network: Network,
+    pub oracles: Vec<OracleInfo>,
+    pub bets: Vec<WatchedBet>,
+}
+
+/// The public parts of a bet.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchedBet {
+    pub bet_id: BetId,
+    pub oracle_id: OracleId,
+    pub event_id: EventId,
+    pub expected_outcome_time: Option<NaiveDateTime>,
+    /// The outcome the wallet wins on
+    pub my_outcome: String,
+    pub txid: Txid,
+    pub vout: u32,
+    pub inputs: Vec<OutPoint>,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub local_value: Amount,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub joint_output_value: Amount,
+    /// What `gun watch check` last saw
+    #[serde(default)]
+    pub seen: Seen,
+}
+
+impl WatchedBet {
+    /// Bets that are already finished (or haven't got a transaction yet) aren't worth watching.
+    pub fn from_bet_state(bet_id: BetId, bet_state: &BetState) -> Option<Self> {
+        let bet = match bet_state {
+            BetState::Offered {
+                bet: OfferedBet(bet),
+                ..
+            }
+            | BetState::Included { bet, .. }
+            | BetState::Won { bet, .. } => bet,
+            _ => return None,
+        };
+        Some(WatchedBet {
+            bet_id,
+            oracle_id: bet.oracle_id.clone(),
+            event_id: bet.oracle_event.event.id.clone(),
+            expected_outcome_time: bet.oracle_event.event.expected_outcome_time,
+            my_outcome: bet.my_outcome().outcome_string(),
+            txid: bet.tx().txid(),
+            vout: bet.vout,
+            inputs: bet.input_outpoints(),
+            local_value: bet.local_value,
+            joint_output_value: bet.joint_output_value,
+            seen: Seen::default(),
+        })
+    }
+
+    pub fn event_url(&self) -> anyhow::Result<Url> {
+        crate::endpoint::oracle_event_url(&self.oracle_id, &self.event_id)
+    }
+}
+
+/// What has happened to a watched bet.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Seen {
+    /// `Some(None)` means it's in the mempool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bet_tx: Option<Option<u32>>,
+    /// A transaction other than the bet that spent one of its inputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canceled_by: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
+    /// The transaction that spent the bet's output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claimed_by: Option<Txid>,
+}
+
+impl Seen {
+    pub fn state(&self, my_outcome: &str) -> &'static str {
+        match (self, self.outcome.as_deref()) {
+            (
+                Seen {
+                    canceled_by: Some(_),
+                    ..
+                },
+                _,
+            ) => "canceled",
+            (
+                Seen {
+                    claimed_by: Some(_),
+                    ..
+                },
+                _,
+            ) => "claimed",
+            (_, Some(outcome)) if outcome == my_outcome => "won",
+            (_, Some(_)) => "lost",
+            (
+                Seen {
+                    bet_tx: Some(_), ..
+                },
+                _,
+            ) => "included",
+            _ => "offered",
+        }
+    }
+}
+
+/// The watched bets kept in `watch.json` in the wallet directory.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchList {
+    pub oracles: Vec<OracleInfo>,
+    pub bets: Vec<WatchedBet>,
+}
+
+impl WatchList {
+    pub fn path(wallet_dir: &Path) -> std::path::PathBuf {
+        wallet_dir.join("watch.json")
+    }
+
+    pub fn load(wallet_dir: &Path) -> anyhow::Result<Self> {
+        let path = Self::path(wallet_dir);
+        match crate::ephemeral::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("reading {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    pub fn save(&self, wallet_dir: &Path) -> anyhow::Result<()> {
+        let path = Self::path(wallet_dir);
+        crate::ephemeral::write(&path, serde_json::to_string_pretty(self).unwrap())
+            .with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// Adds the bets and oracles in `export` replacing any with the same id but keeping what has
+    /// been seen of them. Returns how many bets were new.
+    pub fn import(&mut self, export: WatchExport) -> usize {
+        for oracle in export.oracles {
+            self.oracles.retain(|existing| existing.id != oracle.id);
+            self.oracles.push(oracle);
+        }
+        let mut new = 0;
+        for mut bet in export.bets {
+            match self
+                .bets
+                .iter_mut()
+                .find(|existing| existing.bet_id == bet.bet_id && existing.txid == bet.txid)
+            {
+                Some(existing) => {
+                    bet.seen = existing.seen.clone();
+                    *existing = bet;
+                }
+                None => {
+                    self.bets.retain(|existing| existing.bet_id != bet.bet_id);
+                    self.bets.push(bet);
+                    new += 1;
+                }
+            }
+        }
+        self.bets.sort_by_key(|bet| bet.bet_id);
+        new
+    }
+}
+
+/// Looks at the chain through an esplora server and at the oracles. How the oracles were polled is
+/// kept in `bet_db` so they aren't asked more than they need to be between checks.
+pub struct Watcher {
+    esplora_url: String,
+    client: reqwest::blocking::Client,
+    oracle_sources: EventSources,
+    bet_db: BetDatabase,
+}
+
+impl Watcher {
+    pub fn new(
+        esplora_url: &str,
+        oracle_sources: EventSources,
+        bet_db: BetDatabase,
+    ) -> anyhow::Result<Self> {
+        Ok(Watcher {
+            esplora_url: esplora_url.trim_end_matches('/').to_string(),
+            client: crate::endpoint::http_client()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+            oracle_sources,
+            bet_db,
+        })
+    }
+
+    /// `None` if the server doesn't know about it.
+    fn get(&self, path: &str) -> anyhow::Result<Option<serde_json::Value>> {
+        let url = format!("{}/{}", self.esplora_url, path);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .with_context(|| format!("while getting {}", url))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body = response.error_for_status()?.text()?;
+        Ok(Some(serde_json::from_str(&body).with_context(|| {
+            format!("while decoding the response from {}", url)
+        })?))
+    }
+
+    fn spent_by(&self, outpoint: OutPoint) -> anyhow::Result<Option<Txid>> {
+        let outspend = self.get(&format!("tx/{}/outspend/{}", outpoint.txid, outpoint.vout))?;
+        Ok(outspend
+            .and_then(|outspend| outspend["txid"].as_str().map(str::to_string))
+            .map(|txid| Txid::from_str(&txid))
+            .transpose()?)
+    }
+
+    /// What can be seen of `bet` now.
+    pub fn look(&self, bet: &WatchedBet, oracles: &[OracleInfo]) -> anyhow::Result<Seen> {
+        let mut seen = bet.seen.clone();
+        seen.bet_tx = self.get(&format!("tx/{}/status", bet.txid))?.map(|status| {
+            status["block_height"]
+                .as_u64()
+                .filter(|_| status["confirmed"].as_bool() == Some(true))
+                .map(|height| height as u32)
+        });
+
+        if seen.bet_tx.is_none() {
+            for input in &bet.inputs {
+                if let Some(txid) = self.spent_by(*input)? {
+                    if txid != bet.txid {
+                        seen.canceled_by = Some(txid);
+                    }
+                }
+            }
+            return Ok(seen);
+        }
+        seen.canceled_by = None;
+
+        if seen.outcome.is_none() {
+            let oracle = oracles
+                .iter()
+                .find(|oracle| oracle.id == bet.oracle_id)
+                .ok_or(anyhow!("oracle {} wasn't exported", bet.oracle_id))?;
+            let event_url = bet.event_url()?;
+            let response =
+                OraclePoller::new(&self.bet_db, &self.oracle_sources).poll(&event_url)?;
+            if let Some(response) = response.filter(|response| response.attestation.is_some()) {
+                verify_response(oracle, &bet.event_id, &response)?;
+                seen.outcome = response.attestation.map(|attestation| attestation.outcome);
+            }
+        }
+
+        seen.claimed_by = self.spent_by(OutPoint {
+            txid: bet.txid,
+            vout: bet.vout,
+        })?;
+        Ok(seen)
+    }
+}
+
+/// The alert to raise (kind and message) when what's seen of `bet` goes from `before` to `after`.
+pub fn change_alert(
+    bet: &WatchedBet,
+    before: &Seen,
+    after: &Seen,
+) -> Option<(&'static str, String)> {
+    let (from, to) = (before.state(&bet.my_outcome), after.state(&bet.my_outcome));
+    if from == to {
+        return None;
+    }
+    let message = match to {
+        "included" => format!("bet {} is in the chain", bet.bet_id),
+        "canceled" => format!(
+            "bet {} was canceled by {}",
+            bet.bet_id,
+            after.canceled_by.unwrap()
+        ),
+        "won" => format!(
+            "bet {} was won ({} to claim) -- claim it from the wallet with `gun bet claim`",
+            bet.bet_id, bet.joint_output_value
+        ),
+        "lost" => format!("bet {} was lost ({})", bet.bet_id, bet.local_value),
+        "claimed" => format!(
+            "the output of bet {} was spent by {}",
+            bet.bet_id,
+            after.claimed_by.unwrap()
+        ),
+        _ => format!("bet {} went from {} to {}", bet.bet_id, from, to),
+    };
+    Some(("watch", message))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bdk::bitcoin::hashes::Hash;
+
+    fn watched_bet(bet_id: BetId) -> WatchedBet {
+        WatchedBet {
+            bet_id,
+            oracle_id: "h00.ooo".into(),
+            event_id: EventId::from_str("/random/2020-09-25T08:00:00/heads_tails.winner").unwrap(),
+            expected_outcome_time: None,
+            my_outcome: "heads".into(),
+            txid: Txid::from_slice(&[bet_id as u8; 32]).unwrap(),
+            vout: 0,
+            inputs: vec![],
+            local_value: Amount::from_sat(10_000),
+            joint_output_value: Amount::from_sat(20_000),
+            seen: Seen::default(),
+        }
+    }
+
+    #[test]
+    fn importing_keeps_what_was_seen() {
+        let mut watch_list = WatchList::default();
+        let export = WatchExport {
+            network: Network::Regtest,
+            oracles: vec![],
+            bets: vec![watched_bet(1), watched_bet(2)],
+        };
+        assert_eq!(watch_list.import(export.clone()), 2);
+        watch_list.bets[0].seen.bet_tx = Some(Some(100));
+        assert_eq!(watch_list.import(export), 0);
+        assert_eq!(watch_list.bets[0].seen.bet_tx, Some(Some(100)));
+        assert_eq!(watch_list.bets.len(), 2);
+    }
+
+    #[test]
+    fn alerts_on_state_changes() {
+        let bet = watched_bet(1);
+        let offered = Seen::default();
+        let included = Seen {
+            bet_tx: Some(None),
+            ..Seen::default()
+        };
+        let won = Seen {
+            outcome: Some("heads".into()),
+            ..included.clone()
+        };
+        assert_eq!(change_alert(&bet, &offered, &offered), None);
+        assert!(change_alert(&bet, &offered, &included).is_some());
+        assert_eq!(won.state(&bet.my_outcome), "won");
+        assert!(change_alert(&bet, &included, &won)
+            .unwrap()
+            .1
+            .contains("gun bet claim"));
+        // a confirmation isn't worth an alert
+        let confirmed = Seen {
+            bet_tx: Some(Some(100)),
+            ..Seen::default()
+        };
+        assert_eq!(change_alert(&bet, &included, &confirmed), None);
+    }
+}
@@ -255,14 +255,18 @@ pub fn test_happy_path() {
 
     loser.learn_outcome(loser_id, attestation).unwrap();
     assert!(
-        loser.claim(FeeSpec::default(), false).unwrap().is_none(),
+        loser
+            .claim(FeeSpec::default(), false, true)
+            .unwrap()
+            .is_empty(),
         "loser should not have claim tx"
     );
     wait_for_state!(loser, loser_id, "lost");
 
     let (_, winner_claim_psbt) = winner
-        .claim(FeeSpec::default(), false)
+        .claim(FeeSpec::default(), false, true)
         .unwrap()
+        .pop()
         .expect("winner should return a tx here");
 
     let winner_claim_tx = winner_claim_psbt.extract_tx();
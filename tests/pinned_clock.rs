@@ -0,0 +1,19 @@
+//! Pinning the clock (`--now` or `GUN_NOW`) is process wide so this is a test binary of its own.
+use bdk::bitcoin::Amount;
+use gun_wallet::{approval::ApprovalPolicy, chrono::Duration, clock, session};
+
+#[test]
+pub fn pinned_clock_does_not_move_security_checks() {
+    let real_now = clock::real_now();
+    clock::pin(real_now + Duration::days(10 * 365));
+
+    // a session that only looks short from the pinned time is refused
+    assert!(session::check_session_end(clock::now() + Duration::minutes(10), 60).is_err());
+    assert!(session::check_session_end(real_now + Duration::minutes(10), 60).is_ok());
+
+    // pinning the clock back doesn't bring an expired approval back
+    let policy = ApprovalPolicy::new(Amount::from_sat(100_000));
+    let requested_at = real_now - Duration::minutes(policy.window_minutes as i64 + 1);
+    clock::pin(requested_at);
+    assert!(policy.is_expired(requested_at));
+}
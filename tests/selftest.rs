@@ -0,0 +1,36 @@
+#![cfg(feature = "betting")]
+use bdk::testutils::blockchain_tests::TestClient;
+use gun_wallet::cmd::{run_selftest, SelftestOpt};
+use structopt::StructOpt;
+
+#[test]
+pub fn selftest_passes() {
+    let test_client = TestClient::default();
+    let esplora_url = format!(
+        "http://{}",
+        test_client.electrsd.esplora_url.as_ref().unwrap()
+    );
+    let dir = std::env::temp_dir().join(format!("gun-selftest-test-{}", std::process::id()));
+    let opt = SelftestOpt::from_iter_safe(&[
+        "selftest".to_string(),
+        "--esplora".to_string(),
+        esplora_url,
+        "--bitcoind".to_string(),
+        test_client.bitcoind.rpc_url(),
+        "--bitcoind-cookie".to_string(),
+        test_client
+            .bitcoind
+            .params
+            .cookie_file
+            .display()
+            .to_string(),
+        "--dir".to_string(),
+        dir.display().to_string(),
+    ])
+    .unwrap();
+    run_selftest(opt).unwrap();
+    assert!(
+        !dir.exists(),
+        "the wallets should be removed when it passes"
+    );
+}